@@ -1,6 +1,14 @@
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHasher, SaltString},
+    Argon2,
+};
+
 fn main() {
     let password = rpassword::prompt_password("Enter password to be hashed: ")
         .expect("Unable to read password");
-    let password_hash = bcrypt::hash(&password, 10).expect("Unable to hash password");
+    let salt = SaltString::generate(&mut OsRng);
+    let password_hash = Argon2::default()
+        .hash_password(password.as_bytes(), &salt)
+        .expect("Unable to hash password");
     println!("Password Hash:\n{}", password_hash);
 }
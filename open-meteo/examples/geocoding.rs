@@ -0,0 +1,13 @@
+use open_meteo::geocoding::{search, SearchParameters};
+
+#[tokio::main]
+async fn main() {
+    let client = reqwest::Client::new();
+
+    let parameters = SearchParameters::builder()
+        .name("Aoraki Mount Cook")
+        .count(5)
+        .build();
+    let locations = search(&client, &parameters).await.unwrap();
+    println!("{:#?}", &locations)
+}
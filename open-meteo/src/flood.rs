@@ -0,0 +1,180 @@
+//! Client for the [Open-Meteo Flood API](https://open-meteo.com/en/docs/flood-api), which provides
+//! forecasts of the daily river discharge from the
+//! [GloFAS](https://www.globalfloods.eu/) model, see [`obtain_flood_forecast()`].
+
+use chrono::NaiveDate;
+use reqwest::Method;
+use serde::{ser::SerializeMap, Deserialize, Serialize};
+
+use crate::{check_response, Error};
+
+/// Base url of the public Open-Meteo flood API.
+pub const DEFAULT_FLOOD_BASE_URL: &str = "https://flood-api.open-meteo.com";
+
+/// A daily river discharge variable, see [`FloodParameters::daily`]. The statistics are
+/// calculated over the members of the ensemble forecast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FloodVariable {
+    /// Daily river discharge of the control forecast (m³/s).
+    RiverDischarge,
+    /// Mean of the ensemble (m³/s).
+    RiverDischargeMean,
+    /// Median of the ensemble (m³/s).
+    RiverDischargeMedian,
+    /// Maximum of the ensemble (m³/s).
+    RiverDischargeMax,
+    /// Minimum of the ensemble (m³/s).
+    RiverDischargeMin,
+    /// 25th percentile of the ensemble (m³/s).
+    RiverDischargeP25,
+    /// 75th percentile of the ensemble (m³/s).
+    RiverDischargeP75,
+}
+
+/// Parameters for a flood forecast request, see [`obtain_flood_forecast()`].
+#[derive(Debug, PartialEq, buildstructor::Builder)]
+pub struct FloodParameters {
+    /// Geographical WGS84 latitude of the location, which should be on a river.
+    pub latitude: f32,
+    /// Geographical WGS84 longitude of the location, which should be on a river.
+    pub longitude: f32,
+    /// The daily variables which should be returned.
+    pub daily: Vec<FloodVariable>,
+    /// Number of days to forecast (up to 210), 92 by default.
+    pub forecast_days: Option<u8>,
+    /// If set, past days are also returned.
+    pub past_days: Option<u8>,
+}
+
+impl Serialize for FloodParameters {
+    fn serialize<S>(&self, serializer: S) -> Result<S::Ok, S::Error>
+    where
+        S: serde::Serializer,
+    {
+        let mut map = serializer.serialize_map(None)?;
+        map.serialize_entry("latitude", &self.latitude)?;
+        map.serialize_entry("longitude", &self.longitude)?;
+        for variable in &self.daily {
+            map.serialize_entry("daily", variable)?;
+        }
+        self.forecast_days
+            .map(|v| map.serialize_entry("forecast_days", &v))
+            .transpose()?;
+        self.past_days
+            .map(|v| map.serialize_entry("past_days", &v))
+            .transpose()?;
+        map.end()
+    }
+}
+
+/// The daily values of a [`FloodForecast`], each variable is only present if it was requested.
+/// Values are `None` where the model has no data.
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct FloodDaily {
+    /// The date of each value.
+    pub time: Vec<NaiveDate>,
+    /// See [`FloodVariable::RiverDischarge`].
+    pub river_discharge: Option<Vec<Option<f32>>>,
+    /// See [`FloodVariable::RiverDischargeMean`].
+    pub river_discharge_mean: Option<Vec<Option<f32>>>,
+    /// See [`FloodVariable::RiverDischargeMedian`].
+    pub river_discharge_median: Option<Vec<Option<f32>>>,
+    /// See [`FloodVariable::RiverDischargeMax`].
+    pub river_discharge_max: Option<Vec<Option<f32>>>,
+    /// See [`FloodVariable::RiverDischargeMin`].
+    pub river_discharge_min: Option<Vec<Option<f32>>>,
+    /// See [`FloodVariable::RiverDischargeP25`].
+    pub river_discharge_p25: Option<Vec<Option<f32>>>,
+    /// See [`FloodVariable::RiverDischargeP75`].
+    pub river_discharge_p75: Option<Vec<Option<f32>>>,
+}
+
+/// A river discharge forecast, see [`obtain_flood_forecast()`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct FloodForecast {
+    /// Geographical WGS84 latitude of the center of the river grid-cell which was used, which may
+    /// be a few kilometres from the requested location.
+    pub latitude: f32,
+    /// Geographical WGS84 longitude of the center of the river grid-cell which was used.
+    pub longitude: f32,
+    /// The daily values.
+    pub daily: FloodDaily,
+}
+
+/// Obtain the river discharge forecast from the public Open-Meteo flood API.
+pub async fn obtain_flood_forecast(
+    client: &reqwest::Client,
+    parameters: &FloodParameters,
+) -> Result<FloodForecast, Error> {
+    obtain_flood_forecast_from(client, DEFAULT_FLOOD_BASE_URL, parameters).await
+}
+
+/// Obtain the river discharge forecast from the Open-Meteo flood API hosted at `base_url` (e.g.
+/// [`DEFAULT_FLOOD_BASE_URL`]).
+pub async fn obtain_flood_forecast_from(
+    client: &reqwest::Client,
+    base_url: &str,
+    parameters: &FloodParameters,
+) -> Result<FloodForecast, Error> {
+    let query = serde_urlencoded::to_string(parameters)?;
+    let url = format!("{}/v1/flood?{}", base_url.trim_end_matches('/'), query);
+    tracing::trace!("GET {}", url);
+
+    let response = check_response(client.request(Method::GET, url).send().await?).await?;
+    Ok(serde_json::from_slice(&response.bytes().await?)?)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use serde_json::json;
+
+    use super::{FloodForecast, FloodParameters, FloodVariable};
+
+    #[test]
+    fn flood_parameters_serialize() {
+        let parameters = FloodParameters::builder()
+            .latitude(-43.5)
+            .longitude(170.25)
+            .daily_entry(FloodVariable::RiverDischarge)
+            .daily_entry(FloodVariable::RiverDischargeMax)
+            .forecast_days(7)
+            .build();
+        assert_eq!(
+            "latitude=-43.5&longitude=170.25&daily=river_discharge&daily=river_discharge_max\
+            &forecast_days=7",
+            serde_urlencoded::to_string(&parameters).unwrap()
+        );
+    }
+
+    #[test]
+    fn flood_forecast_deserialize() {
+        let forecast_json = json!({
+            "latitude": -43.525,
+            "longitude": 170.275,
+            "generationtime_ms": 0.3,
+            "utc_offset_seconds": 0,
+            "timezone": "GMT",
+            "timezone_abbreviation": "GMT",
+            "daily_units": {
+                "time": "iso8601",
+                "river_discharge": "m³/s",
+            },
+            "daily": {
+                "time": ["2022-12-03", "2022-12-04"],
+                "river_discharge": [42.5, null],
+            }
+        });
+        let forecast: FloodForecast = serde_json::from_value(forecast_json).unwrap();
+        assert_eq!(
+            vec![
+                NaiveDate::from_ymd(2022, 12, 3),
+                NaiveDate::from_ymd(2022, 12, 4)
+            ],
+            forecast.daily.time
+        );
+        assert_eq!(Some(vec![Some(42.5), None]), forecast.daily.river_discharge);
+        assert_eq!(None, forecast.daily.river_discharge_max);
+    }
+}
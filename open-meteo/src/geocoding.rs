@@ -0,0 +1,152 @@
+//! Client for the [Open-Meteo Geocoding API](https://open-meteo.com/en/docs/geocoding-api), which
+//! searches for locations by name, see [`search()`].
+
+use reqwest::Method;
+use serde::{Deserialize, Serialize};
+
+use crate::{check_response, Error};
+
+/// Base url of the public Open-Meteo geocoding API.
+pub const DEFAULT_GEOCODING_BASE_URL: &str = "https://geocoding-api.open-meteo.com";
+
+/// Parameters for a location search, see [`search()`].
+#[derive(Debug, PartialEq, Eq, Serialize, buildstructor::Builder)]
+pub struct SearchParameters {
+    /// The name (or the start of the name) of the location to search for. Names shorter than 2
+    /// characters return no results, names of 2 characters only return exact matches.
+    pub name: String,
+    /// The maximum number of results to return (up to 100), 10 by default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub count: Option<u8>,
+    /// ISO 639-1 code of the language used for the returned names (e.g. `fr`), English by
+    /// default.
+    #[serde(skip_serializing_if = "Option::is_none")]
+    pub language: Option<String>,
+}
+
+/// A location found by [`search()`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Location {
+    /// Unique id of the location (from [GeoNames](https://www.geonames.org/)).
+    pub id: u64,
+    /// Name of the location, in the requested [`SearchParameters::language`] if available.
+    pub name: String,
+    /// Geographical WGS84 latitude of the location.
+    pub latitude: f32,
+    /// Geographical WGS84 longitude of the location.
+    pub longitude: f32,
+    /// Elevation of the location in metres above mean sea level.
+    pub elevation: Option<f32>,
+    /// Time zone of the location.
+    pub timezone: Option<chrono_tz::Tz>,
+    /// Name of the country that the location is in.
+    pub country: Option<String>,
+    /// ISO 3166-1 alpha2 code of the country that the location is in.
+    pub country_code: Option<String>,
+    /// Name of the first level administrative area (e.g. the state) that the location is in.
+    pub admin1: Option<String>,
+    /// Number of inhabitants of the location.
+    pub population: Option<u64>,
+}
+
+/// Response of the search endpoint, which omits `results` if there are none.
+#[derive(Deserialize)]
+struct SearchResults {
+    #[serde(default)]
+    results: Vec<Location>,
+}
+
+/// Search for locations matching the [`SearchParameters::name`] using the public Open-Meteo
+/// geocoding API, ordered by relevance.
+pub async fn search(
+    client: &reqwest::Client,
+    parameters: &SearchParameters,
+) -> Result<Vec<Location>, Error> {
+    search_from(client, DEFAULT_GEOCODING_BASE_URL, parameters).await
+}
+
+/// Search for locations using the Open-Meteo geocoding API hosted at `base_url` (e.g.
+/// [`DEFAULT_GEOCODING_BASE_URL`]), see [`search()`].
+pub async fn search_from(
+    client: &reqwest::Client,
+    base_url: &str,
+    parameters: &SearchParameters,
+) -> Result<Vec<Location>, Error> {
+    let query = serde_urlencoded::to_string(parameters)?;
+    let url = format!("{}/v1/search?{}", base_url.trim_end_matches('/'), query);
+    tracing::trace!("GET {}", url);
+
+    let response = check_response(client.request(Method::GET, url).send().await?).await?;
+    let results: SearchResults = serde_json::from_slice(&response.bytes().await?)?;
+    Ok(results.results)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono_tz::Tz;
+    use serde_json::json;
+
+    use super::{Location, SearchParameters, SearchResults};
+
+    #[test]
+    fn search_parameters_serialize() {
+        let parameters = SearchParameters::builder().name("Mount Cook").build();
+        assert_eq!(
+            "name=Mount+Cook",
+            serde_urlencoded::to_string(&parameters).unwrap()
+        );
+
+        let parameters = SearchParameters::builder()
+            .name("Zürich")
+            .count(1)
+            .language("de")
+            .build();
+        assert_eq!(
+            "name=Z%C3%BCrich&count=1&language=de",
+            serde_urlencoded::to_string(&parameters).unwrap()
+        );
+    }
+
+    #[test]
+    fn search_results_deserialize() {
+        let results_json = json!({
+            "results": [{
+                "id": 2950159,
+                "name": "Berlin",
+                "latitude": 52.52437,
+                "longitude": 13.41053,
+                "elevation": 74.0,
+                "feature_code": "PPLC",
+                "country_code": "DE",
+                "admin1_id": 2950157,
+                "timezone": "Europe/Berlin",
+                "population": 3426354,
+                "postcodes": ["10967", "13347"],
+                "country_id": 2921044,
+                "country": "Deutschland",
+                "admin1": "Berlin"
+            }],
+            "generationtime_ms": 0.8
+        });
+        let results: SearchResults = serde_json::from_value(results_json).unwrap();
+        assert_eq!(
+            vec![Location {
+                id: 2950159,
+                name: "Berlin".to_owned(),
+                latitude: 52.52437,
+                longitude: 13.41053,
+                elevation: Some(74.0),
+                timezone: Some(Tz::Europe__Berlin),
+                country: Some("Deutschland".to_owned()),
+                country_code: Some("DE".to_owned()),
+                admin1: Some("Berlin".to_owned()),
+                population: Some(3426354),
+            }],
+            results.results
+        );
+
+        let results: SearchResults =
+            serde_json::from_value(json!({"generationtime_ms": 0.2})).unwrap();
+        assert!(results.results.is_empty());
+    }
+}
@@ -4,9 +4,15 @@ use std::{
     hash::Hash,
 };
 
+pub mod flood;
+pub mod geocoding;
 pub mod level;
+pub mod model;
+pub mod units;
 
 use chrono::NaiveDateTime;
+#[cfg(feature = "client")]
+use futures_util::TryStreamExt;
 use level::{Level, LevelField, LevelVariable};
 use once_cell::sync::Lazy;
 use reqwest::{Method, StatusCode};
@@ -17,9 +23,12 @@ use serde::{
 };
 use strum::IntoEnumIterator;
 use strum_macros::EnumIter;
+#[cfg(feature = "client")]
+use tokio_util::io::{StreamReader, SyncIoBridge};
+use units::{Celsius, KmPerHour, Metres, Millimetres, Quantity, WattsPerSquareMetre};
 
 /// WMO Weather interpretation code (WW)
-#[derive(EnumIter, Clone, Copy, Debug)]
+#[derive(EnumIter, Clone, Copy, Debug, PartialEq, Eq)]
 pub enum WeatherCode {
     /// Code: 0
     ClearSky = 0,
@@ -96,6 +105,39 @@ impl WeatherCode {
     pub fn code(&self) -> u8 {
         *self as u8
     }
+
+    /// An emoji which depicts the weather, for making formatted forecasts easier to scan at a
+    /// glance. Codes which only differ in intensity share the same emoji.
+    pub fn emoji(&self) -> &'static str {
+        match self {
+            WeatherCode::ClearSky => "☀️",
+            WeatherCode::MainlyClear => "🌤️",
+            WeatherCode::PartlyCloudy => "⛅",
+            WeatherCode::Overcast => "☁️",
+            WeatherCode::Fog | WeatherCode::FogDepositingRime => "🌫️",
+            WeatherCode::DrizzleLight
+            | WeatherCode::DrizzleModerate
+            | WeatherCode::DrizzleDense
+            | WeatherCode::RainSlight
+            | WeatherCode::RainModerate
+            | WeatherCode::RainHeavy => "🌧️",
+            WeatherCode::DrizzleFreezingLight
+            | WeatherCode::DrizzleFreezingDense
+            | WeatherCode::RainFreezingLight
+            | WeatherCode::RainFreezingHeavy => "🧊",
+            WeatherCode::SnowSlight
+            | WeatherCode::SnowModerate
+            | WeatherCode::SnowHeavy
+            | WeatherCode::SnowGrains
+            | WeatherCode::SnowShowersSlight
+            | WeatherCode::SnowShowersHeavy => "🌨️",
+            WeatherCode::RainShowersSlight
+            | WeatherCode::RainShowersModerate
+            | WeatherCode::RainShowersViolent => "🌦️",
+            WeatherCode::ThunderstormSlightOrModerate => "⛈️",
+            WeatherCode::ThunderstormHailSlight | WeatherCode::ThunderstormHailHeavy => "🌩️",
+        }
+    }
 }
 
 impl<'de> Deserialize<'de> for WeatherCode {
@@ -227,6 +269,8 @@ pub enum HourlyVariable {
     CloudCoverMid,
     /// Requests [Hourly::cloud_cover_high].
     CloudCoverHigh,
+    /// Requests [Hourly::visibility].
+    Visibility,
     /// Requests [Hourly::wind_speed].
     WindSpeed(GroundLevel),
     /// Requests [Hourly::wind_direction].
@@ -236,17 +280,33 @@ pub enum HourlyVariable {
     // TODO: more fields
     /// Requests [Hourly::precipitation].
     Precipitation,
+    /// Requests [Hourly::precipitation_probability].
+    PrecipitationProbability,
     // TODO: more fields
     /// Requests [Hourly::weather_code].
     WeatherCode,
     /// Requests [Hourly::snow_depth].
     SnowDepth,
+    /// Requests [Hourly::uv_index].
+    UvIndex,
     /// Requests [Hourly::freezing_level_height].
     FreezingLevelHeight,
+    /// Requests [Hourly::shortwave_radiation].
+    ShortwaveRadiation,
+    /// Requests [Hourly::direct_radiation].
+    DirectRadiation,
+    /// Requests [Hourly::diffuse_radiation].
+    DiffuseRadiation,
     /// Requests [Hourly::pressure_temperature],
     PressureTemperature(PressureLevel),
     /// Requests [Hourly::pressure_geopotential_height],
     PressureGeopotentialHeight(PressureLevel),
+    /// Requests [Hourly::pressure_dewpoint],
+    PressureDewpoint(PressureLevel),
+    /// Requests [Hourly::pressure_wind_speed],
+    PressureWindSpeed(PressureLevel),
+    /// Requests [Hourly::pressure_wind_direction],
+    PressureWindDirection(PressureLevel),
 }
 
 static HOURLY_ENUMERATED: Lazy<Vec<HourlyVariable>> = Lazy::new(|| {
@@ -262,6 +322,7 @@ static HOURLY_ENUMERATED: Lazy<Vec<HourlyVariable>> = Lazy::new(|| {
         HourlyVariable::CloudCoverLow,
         HourlyVariable::CloudCoverMid,
         HourlyVariable::CloudCoverHigh,
+        HourlyVariable::Visibility,
     ];
 
     e.extend(
@@ -280,9 +341,14 @@ static HOURLY_ENUMERATED: Lazy<Vec<HourlyVariable>> = Lazy::new(|| {
     e.extend_from_slice(&[
         HourlyVariable::WindGusts10m,
         HourlyVariable::Precipitation,
+        HourlyVariable::PrecipitationProbability,
         HourlyVariable::WeatherCode,
         HourlyVariable::SnowDepth,
+        HourlyVariable::UvIndex,
         HourlyVariable::FreezingLevelHeight,
+        HourlyVariable::ShortwaveRadiation,
+        HourlyVariable::DirectRadiation,
+        HourlyVariable::DiffuseRadiation,
     ]);
 
     e.extend(
@@ -299,6 +365,27 @@ static HOURLY_ENUMERATED: Lazy<Vec<HourlyVariable>> = Lazy::new(|| {
             .map(HourlyVariable::PressureGeopotentialHeight),
     );
 
+    e.extend(
+        PressureLevel::enumerate()
+            .iter()
+            .cloned()
+            .map(HourlyVariable::PressureDewpoint),
+    );
+
+    e.extend(
+        PressureLevel::enumerate()
+            .iter()
+            .cloned()
+            .map(HourlyVariable::PressureWindSpeed),
+    );
+
+    e.extend(
+        PressureLevel::enumerate()
+            .iter()
+            .cloned()
+            .map(HourlyVariable::PressureWindDirection),
+    );
+
     e
 });
 
@@ -334,17 +421,50 @@ impl HourlyVariable {
             HourlyVariable::CloudCoverLow => "cloud_cover_low",
             HourlyVariable::CloudCoverMid => "cloud_cover_mid",
             HourlyVariable::CloudCoverHigh => "cloud_cover_high",
+            HourlyVariable::Visibility => "visibility",
             HourlyVariable::WindSpeed(level) => WindSpeedField::name(level),
             HourlyVariable::WindDirection(level) => WindDirectionField::name(level),
             HourlyVariable::WindGusts10m => "windgusts_10m",
             HourlyVariable::Precipitation => "precipitation",
+            HourlyVariable::PrecipitationProbability => "precipitation_probability",
             HourlyVariable::WeatherCode => "weathercode",
             HourlyVariable::SnowDepth => "snow_depth",
+            HourlyVariable::UvIndex => "uv_index",
             HourlyVariable::FreezingLevelHeight => "freezinglevel_height",
+            HourlyVariable::ShortwaveRadiation => "shortwave_radiation",
+            HourlyVariable::DirectRadiation => "direct_radiation",
+            HourlyVariable::DiffuseRadiation => "diffuse_radiation",
             HourlyVariable::PressureTemperature(level) => PressureTemperatureField::name(level),
             HourlyVariable::PressureGeopotentialHeight(level) => {
                 PressureGeopotentialHeightField::name(level)
             }
+            HourlyVariable::PressureDewpoint(level) => PressureDewpointField::name(level),
+            HourlyVariable::PressureWindSpeed(level) => PressureWindSpeedField::name(level),
+            HourlyVariable::PressureWindDirection(level) => PressureWindDirectionField::name(level),
+        }
+    }
+
+    /// The unit of the values for this variable in [Hourly], as it is expected to appear in
+    /// [Forecast::hourly_units]. `None` if the values are not a [Quantity].
+    pub fn unit(&self) -> Option<&'static str> {
+        match self {
+            HourlyVariable::Temperature2m
+            | HourlyVariable::Dewpoint2m
+            | HourlyVariable::ApparentTemperature
+            | HourlyVariable::PressureTemperature(_)
+            | HourlyVariable::PressureDewpoint(_) => Some(Celsius::UNIT),
+            HourlyVariable::WindSpeed(_)
+            | HourlyVariable::WindGusts10m
+            | HourlyVariable::PressureWindSpeed(_) => Some(KmPerHour::UNIT),
+            HourlyVariable::Precipitation => Some(Millimetres::UNIT),
+            HourlyVariable::SnowDepth
+            | HourlyVariable::Visibility
+            | HourlyVariable::FreezingLevelHeight
+            | HourlyVariable::PressureGeopotentialHeight(_) => Some(Metres::UNIT),
+            HourlyVariable::ShortwaveRadiation
+            | HourlyVariable::DirectRadiation
+            | HourlyVariable::DiffuseRadiation => Some(WattsPerSquareMetre::UNIT),
+            _ => None,
         }
     }
 
@@ -473,7 +593,7 @@ impl LevelField<GroundLevel> for WindSpeedField {
 }
 
 /// Speed of the wind.
-pub type WindSpeed = LevelVariable<GroundLevel, WindSpeedField, Vec<f32>>;
+pub type WindSpeed = LevelVariable<GroundLevel, WindSpeedField, Vec<KmPerHour>>;
 
 #[derive(EnumIter, Debug, Copy, Clone, PartialEq, Eq, Hash)]
 pub enum PressureLevel {
@@ -534,7 +654,7 @@ impl Level for PressureLevel {
     }
 }
 
-pub type PressureTemperature = LevelVariable<PressureLevel, PressureTemperatureField, Vec<f32>>;
+pub type PressureTemperature = LevelVariable<PressureLevel, PressureTemperatureField, Vec<Celsius>>;
 
 #[derive(Debug)]
 pub struct PressureTemperatureField;
@@ -554,7 +674,7 @@ impl LevelField<PressureLevel> for PressureTemperatureField {
 }
 
 pub type PressureGeopotentialHeight =
-    LevelVariable<PressureLevel, PressureGeopotentialHeightField, Vec<f32>>;
+    LevelVariable<PressureLevel, PressureGeopotentialHeightField, Vec<Metres>>;
 
 pub struct PressureGeopotentialHeightField;
 
@@ -573,6 +693,61 @@ impl LevelField<PressureLevel> for PressureGeopotentialHeightField {
     }
 }
 
+pub type PressureDewpoint = LevelVariable<PressureLevel, PressureDewpointField, Vec<Celsius>>;
+
+pub struct PressureDewpointField;
+
+static PRESSURE_DEWPOINT_FIELD_NAMES: Lazy<HashMap<PressureLevel, String>> = Lazy::new(|| {
+    PressureLevel::enumerate()
+        .iter()
+        .cloned()
+        .map(|level| (level, format!("dewpoint_{}hPa", level as u32)))
+        .collect()
+});
+
+impl LevelField<PressureLevel> for PressureDewpointField {
+    fn name(level: &PressureLevel) -> &'static str {
+        PRESSURE_DEWPOINT_FIELD_NAMES.get(level).unwrap()
+    }
+}
+
+pub type PressureWindSpeed = LevelVariable<PressureLevel, PressureWindSpeedField, Vec<KmPerHour>>;
+
+pub struct PressureWindSpeedField;
+
+static PRESSURE_WIND_SPEED_FIELD_NAMES: Lazy<HashMap<PressureLevel, String>> = Lazy::new(|| {
+    PressureLevel::enumerate()
+        .iter()
+        .cloned()
+        .map(|level| (level, format!("windspeed_{}hPa", level as u32)))
+        .collect()
+});
+
+impl LevelField<PressureLevel> for PressureWindSpeedField {
+    fn name(level: &PressureLevel) -> &'static str {
+        PRESSURE_WIND_SPEED_FIELD_NAMES.get(level).unwrap()
+    }
+}
+
+pub type PressureWindDirection = LevelVariable<PressureLevel, PressureWindDirectionField, Vec<f32>>;
+
+pub struct PressureWindDirectionField;
+
+static PRESSURE_WIND_DIRECTION_FIELD_NAMES: Lazy<HashMap<PressureLevel, String>> =
+    Lazy::new(|| {
+        PressureLevel::enumerate()
+            .iter()
+            .cloned()
+            .map(|level| (level, format!("winddirection_{}hPa", level as u32)))
+            .collect()
+    });
+
+impl LevelField<PressureLevel> for PressureWindDirectionField {
+    fn name(level: &PressureLevel) -> &'static str {
+        PRESSURE_WIND_DIRECTION_FIELD_NAMES.get(level).unwrap()
+    }
+}
+
 #[derive(Debug, Clone, Default)]
 pub struct Hourly {
     /// The times for the values in this struct's fields.
@@ -582,7 +757,7 @@ pub struct Hourly {
     ///
     /// + Valid time: `Instant`
     /// + Unit: `°C (°F)`
-    pub temperature_2m: Option<Vec<f32>>,
+    pub temperature_2m: Option<Vec<Celsius>>,
     /// Relative humidity at 2 meters above ground.
     ///
     /// + Valid time: `Instant`
@@ -593,13 +768,13 @@ pub struct Hourly {
     ///
     /// + Valid time: `Instant`
     /// + Unit: `°C (°F)`
-    pub dewpoint_2m: Option<Vec<f32>>,
+    pub dewpoint_2m: Option<Vec<Celsius>>,
     /// Apparent temperature is the perceived feels-like temperature combining wind chill factor,
     /// relative humidity and solar radiation.
     ///
     /// + Valid time: `Instant`
     /// + Unit: `°C (°F)`
-    pub apparent_temperature: Option<Vec<f32>>,
+    pub apparent_temperature: Option<Vec<Celsius>>,
     /// Atmospheric air pressure reduced to mean sea level (msl) Typically pressure on mean sea
     /// level is used in meteorology.
     ///
@@ -636,6 +811,11 @@ pub struct Hourly {
     /// + Valid time: `Instant`
     /// + Unit: `%`
     pub cloud_cover_high: Option<Vec<f32>>,
+    /// Viewing distance in meters. Influenced by low clouds, humidity and aerosols.
+    ///
+    /// + Valid time: `Instant`
+    /// + Unit: `meters`
+    pub visibility: Option<Vec<Metres>>,
     /// Wind speed at different heights above ground. Wind speed at 10 meters is the standard level.
     ///
     /// + Valid time: `Instant`
@@ -650,13 +830,18 @@ pub struct Hourly {
     ///
     /// + Valid time: `Preceding hour mean`
     /// + Unit: `km/h`
-    pub wind_gusts_10m: Option<Vec<f32>>,
+    pub wind_gusts_10m: Option<Vec<KmPerHour>>,
     // TODO: more fields
     /// Total precipitation (rain, showers, snow) sum of the preceding hour.
     ///
     /// + Valid time: `Preceding hour sum`
     /// + Unit: `mm (inch)`
-    pub precipitation: Option<Vec<f32>>,
+    pub precipitation: Option<Vec<Millimetres>>,
+    /// Probability of precipitation with more than 0.1 mm of the preceding hour.
+    ///
+    /// + Valid time: `Preceding hour probability`
+    /// + Unit: `%`
+    pub precipitation_probability: Option<Vec<f32>>,
     // TODO: more fields
     /// Weather condition.
     ///
@@ -666,12 +851,33 @@ pub struct Hourly {
     ///
     /// + Valid time: `Instant`
     /// + Unit: `meters`
-    pub snow_depth: Option<Vec<f32>>,
+    pub snow_depth: Option<Vec<Metres>>,
+    /// UV index, taking cloud cover into account.
+    ///
+    /// + Valid time: `Instant`
+    pub uv_index: Option<Vec<f32>>,
     /// Altitude above sea level of the 0°C level.
     ///
     /// + Valid time: `Instant`
     /// + Unit: `meters`
-    pub freezing_level_height: Option<Vec<f32>>,
+    pub freezing_level_height: Option<Vec<Metres>>,
+    /// Global horizontal irradiance (the sum of [Hourly::direct_radiation] and
+    /// [Hourly::diffuse_radiation]) as a mean of the preceding hour.
+    ///
+    /// + Valid time: `Preceding hour mean`
+    /// + Unit: `W/m²`
+    pub shortwave_radiation: Option<Vec<WattsPerSquareMetre>>,
+    /// Direct solar radiation on the horizontal plane as a mean of the preceding hour.
+    ///
+    /// + Valid time: `Preceding hour mean`
+    /// + Unit: `W/m²`
+    pub direct_radiation: Option<Vec<WattsPerSquareMetre>>,
+    /// Diffuse solar radiation (scattered by the atmosphere and clouds) as a mean of the
+    /// preceding hour.
+    ///
+    /// + Valid time: `Preceding hour mean`
+    /// + Unit: `W/m²`
+    pub diffuse_radiation: Option<Vec<WattsPerSquareMetre>>,
     /// Air temperature at the specified pressure level. Air temperatures decrease linearly with
     /// pressure.
     ///
@@ -685,6 +891,21 @@ pub struct Hourly {
     /// + Valid time: `Instant`
     /// + Unit: `meter`
     pub pressure_geopotential_height: PressureGeopotentialHeight,
+    /// Dew point temperature at the specified pressure level.
+    ///
+    /// + Valid time: `Instant`
+    /// + Unit: `°C (°F)`
+    pub pressure_dewpoint: PressureDewpoint,
+    /// Wind speed at the specified pressure level.
+    ///
+    /// + Valid time: `Instant`
+    /// + Unit: `km/h (mph, m/s, knots)`
+    pub pressure_wind_speed: PressureWindSpeed,
+    /// Wind direction at the specified pressure level.
+    ///
+    /// + Valid time: `Instant`
+    /// + Unit: `°`
+    pub pressure_wind_direction: PressureWindDirection,
 }
 
 impl<'de> Deserialize<'de> for Hourly {
@@ -744,6 +965,9 @@ impl<'de> Deserialize<'de> for Hourly {
                 let mut pressure_temperature_fields: HashMap<String, Vec<f32>> = HashMap::new();
                 let mut pressure_geopotential_height_fields: HashMap<String, Vec<f32>> =
                     HashMap::new();
+                let mut pressure_dewpoint_fields: HashMap<String, Vec<f32>> = HashMap::new();
+                let mut pressure_wind_speed_fields: HashMap<String, Vec<f32>> = HashMap::new();
+                let mut pressure_wind_direction_fields: HashMap<String, Vec<f32>> = HashMap::new();
 
                 while let Some(key) = map.next_key::<String>()? {
                     if let Some(hv) = HourlyVariable::from_serde_name(&key) {
@@ -785,6 +1009,9 @@ impl<'de> Deserialize<'de> for Hourly {
                             HourlyVariable::CloudCoverHigh => {
                                 hourly.cloud_cover_high = map.next_value()?;
                             }
+                            HourlyVariable::Visibility => {
+                                hourly.visibility = map.next_value()?;
+                            }
                             HourlyVariable::WindSpeed(_) => {
                                 wind_speed_fields.insert(key.to_owned(), map.next_value()?);
                             }
@@ -797,15 +1024,30 @@ impl<'de> Deserialize<'de> for Hourly {
                             HourlyVariable::Precipitation => {
                                 hourly.precipitation = map.next_value()?;
                             }
+                            HourlyVariable::PrecipitationProbability => {
+                                hourly.precipitation_probability = map.next_value()?;
+                            }
                             HourlyVariable::WeatherCode => {
                                 hourly.weather_code = map.next_value()?;
                             }
                             HourlyVariable::SnowDepth => {
                                 hourly.snow_depth = map.next_value()?;
                             }
+                            HourlyVariable::UvIndex => {
+                                hourly.uv_index = map.next_value()?;
+                            }
                             HourlyVariable::FreezingLevelHeight => {
                                 hourly.freezing_level_height = map.next_value()?;
                             }
+                            HourlyVariable::ShortwaveRadiation => {
+                                hourly.shortwave_radiation = map.next_value()?;
+                            }
+                            HourlyVariable::DirectRadiation => {
+                                hourly.direct_radiation = map.next_value()?;
+                            }
+                            HourlyVariable::DiffuseRadiation => {
+                                hourly.diffuse_radiation = map.next_value()?;
+                            }
                             HourlyVariable::PressureTemperature(_) => {
                                 pressure_temperature_fields
                                     .insert(key.to_owned(), map.next_value()?);
@@ -814,6 +1056,17 @@ impl<'de> Deserialize<'de> for Hourly {
                                 pressure_geopotential_height_fields
                                     .insert(key.to_owned(), map.next_value()?);
                             }
+                            HourlyVariable::PressureDewpoint(_) => {
+                                pressure_dewpoint_fields.insert(key.to_owned(), map.next_value()?);
+                            }
+                            HourlyVariable::PressureWindSpeed(_) => {
+                                pressure_wind_speed_fields
+                                    .insert(key.to_owned(), map.next_value()?);
+                            }
+                            HourlyVariable::PressureWindDirection(_) => {
+                                pressure_wind_direction_fields
+                                    .insert(key.to_owned(), map.next_value()?);
+                            }
                         }
                     } else {
                         return Err(serde::de::Error::unknown_field(
@@ -832,6 +1085,13 @@ impl<'de> Deserialize<'de> for Hourly {
                 hourly.pressure_geopotential_height = PressureGeopotentialHeight::deserialize(
                     pressure_geopotential_height_fields.into_deserializer(),
                 )?;
+                hourly.pressure_dewpoint =
+                    PressureDewpoint::deserialize(pressure_dewpoint_fields.into_deserializer())?;
+                hourly.pressure_wind_speed =
+                    PressureWindSpeed::deserialize(pressure_wind_speed_fields.into_deserializer())?;
+                hourly.pressure_wind_direction = PressureWindDirection::deserialize(
+                    pressure_wind_direction_fields.into_deserializer(),
+                )?;
 
                 Ok(hourly)
             }
@@ -902,6 +1162,20 @@ impl Default for TimeFormat {
     }
 }
 
+/// Preference for how the grid-cell used for the forecast is selected for the requested
+/// coordinates.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum CellSelection {
+    /// Prefer a grid-cell on land with a similar elevation to the requested coordinates.
+    #[default]
+    Land,
+    /// Prefer a grid-cell on sea.
+    Sea,
+    /// Select the nearest grid-cell.
+    Nearest,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum TimeZone {
     /// The position coordinates will be automatically resolved to the local time zone.
@@ -929,6 +1203,9 @@ impl Serialize for TimeZone {
     }
 }
 
+/// Format of [ForecastParameters::start_hour] and [ForecastParameters::end_hour].
+const HOUR_FORMAT: &str = "%Y-%m-%dT%H:%M";
+
 #[derive(Debug, PartialEq, buildstructor::Builder)]
 pub struct ForecastParameters {
     /// Geographical WGS84 latitude of the location.
@@ -957,6 +1234,18 @@ pub struct ForecastParameters {
     /// The time interval to get weather data. Must be specified in conjunction with
     /// [ForecastParameters::start_date].
     pub end_date: Option<chrono::NaiveDate>,
+    /// The first hour (in the requested [ForecastParameters::timezone]) to get hourly weather
+    /// data for. Must be within the available range, which includes
+    /// [ForecastParameters::past_days].
+    pub start_hour: Option<NaiveDateTime>,
+    /// The last hour (in the requested [ForecastParameters::timezone]) to get hourly weather data
+    /// for.
+    pub end_hour: Option<NaiveDateTime>,
+    /// The elevation used for statistical downscaling. By default a 90 meter digital elevation
+    /// model is used.
+    pub elevation: Option<f32>,
+    /// How the grid-cell is selected for the coordinates, [CellSelection::Land] by default.
+    pub cell_selection: Option<CellSelection>,
 }
 
 impl Serialize for ForecastParameters {
@@ -990,6 +1279,18 @@ impl Serialize for ForecastParameters {
         self.end_date
             .map(|v| map.serialize_entry("end_date", &v))
             .transpose()?;
+        self.start_hour
+            .map(|v| map.serialize_entry("start_hour", &v.format(HOUR_FORMAT).to_string()))
+            .transpose()?;
+        self.end_hour
+            .map(|v| map.serialize_entry("end_hour", &v.format(HOUR_FORMAT).to_string()))
+            .transpose()?;
+        self.elevation
+            .map(|v| map.serialize_entry("elevation", &v))
+            .transpose()?;
+        self.cell_selection
+            .map(|v| map.serialize_entry("cell_selection", &v))
+            .transpose()?;
         map.end()
     }
 }
@@ -1038,11 +1339,94 @@ pub enum Error {
     #[error("Error while performing request")]
     Reqwest(#[from] reqwest::Error),
     #[error("Response status unsuccessful, code: {code}, reason: {reason}")]
-    ResponseStatusNotSuccessful { code: StatusCode, reason: String },
+    ResponseStatusNotSuccessful { code: StatusCode, reason: ApiError },
     #[error("Error while parsing json")]
     SerdeJson(#[from] serde_json::Error),
     #[error("Error while seriazizing url query parameters")]
     SerdeUrlencoded(#[from] serde_urlencoded::ser::Error),
+    #[error("Error while reading response")]
+    Io(#[from] std::io::Error),
+    #[error("Unexpected unit {actual:?} for {variable:?}, expected {expected:?}")]
+    UnexpectedUnit {
+        variable: HourlyVariable,
+        expected: &'static str,
+        actual: String,
+    },
+}
+
+impl Forecast {
+    /// Check that the units listed in [Forecast::hourly_units] match the units of the
+    /// [Quantity] used for each variable in [Hourly] (see [HourlyVariable::unit()]).
+    pub fn check_units(&self) -> Result<(), Error> {
+        for (variable, actual) in self.hourly_units.iter().flatten() {
+            if let Some(expected) = variable.unit() {
+                if actual != expected {
+                    return Err(Error::UnexpectedUnit {
+                        variable: *variable,
+                        expected,
+                        actual: actual.clone(),
+                    });
+                }
+            }
+        }
+        Ok(())
+    }
+}
+
+impl Error {
+    /// The reason given by the API for rejecting the request, if it was rejected.
+    pub fn api_error(&self) -> Option<&ApiError> {
+        match self {
+            Error::ResponseStatusNotSuccessful { reason, .. } => Some(reason),
+            _ => None,
+        }
+    }
+}
+
+/// Reason given by the Open-Meteo API for rejecting a request, parsed from the `reason` in its
+/// error payload. Each variant contains the original reason.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum ApiError {
+    /// A parameter of the request was invalid (e.g. an unsupported hourly variable).
+    InvalidParameter(String),
+    /// The requested latitude or longitude was outside of the valid range.
+    CoordinatesOutOfRange(String),
+    /// Too many requests have been made, and the request should be retried later.
+    RateLimited(String),
+    /// The request was rejected for some other reason.
+    Other(String),
+}
+
+impl ApiError {
+    /// Classify the `reason` given in an error payload for a response with status `code`.
+    pub fn parse(code: StatusCode, reason: String) -> Self {
+        let lowercase = reason.to_lowercase();
+        if code == StatusCode::TOO_MANY_REQUESTS || lowercase.contains("limit exceeded") {
+            Self::RateLimited(reason)
+        } else if lowercase.contains("latitude") || lowercase.contains("longitude") {
+            Self::CoordinatesOutOfRange(reason)
+        } else if code == StatusCode::BAD_REQUEST {
+            Self::InvalidParameter(reason)
+        } else {
+            Self::Other(reason)
+        }
+    }
+
+    /// The original reason given by the API.
+    pub fn reason(&self) -> &str {
+        match self {
+            Self::InvalidParameter(reason)
+            | Self::CoordinatesOutOfRange(reason)
+            | Self::RateLimited(reason)
+            | Self::Other(reason) => reason,
+        }
+    }
+}
+
+impl std::fmt::Display for ApiError {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.write_str(self.reason())
+    }
 }
 
 #[derive(Deserialize)]
@@ -1050,46 +1434,107 @@ struct ErrorMessage {
     reason: String,
 }
 
+/// Base url of the public Open-Meteo API.
+pub const DEFAULT_BASE_URL: &str = "https://api.open-meteo.com";
+
 pub async fn obtain_forecast_json(
     client: &reqwest::Client,
     parameters: &ForecastParameters,
 ) -> Result<String, Error> {
+    obtain_forecast_json_from(client, DEFAULT_BASE_URL, parameters).await
+}
+
+/// Send the forecast request to the Open-Meteo API hosted at `base_url`, and check that the
+/// response was successful.
+async fn forecast_response(
+    client: &reqwest::Client,
+    base_url: &str,
+    parameters: &ForecastParameters,
+) -> Result<reqwest::Response, Error> {
     let query = serde_urlencoded::to_string(parameters)?;
-    let url = format!("https://api.open-meteo.com/v1/forecast?{}", query);
+    let url = format!("{}/v1/forecast?{}", base_url.trim_end_matches('/'), query);
     tracing::trace!("GET {}", url);
 
     let response = client.request(Method::GET, url).send().await?;
+    check_response(response).await
+}
 
+/// Check that a `response` from an Open-Meteo API was successful, otherwise return the reason
+/// given in its error payload.
+pub(crate) async fn check_response(
+    response: reqwest::Response,
+) -> Result<reqwest::Response, Error> {
     if response.status().is_success() {
-        response.text().await.map_err(Error::from)
+        Ok(response)
     } else {
+        let code = response.status();
+        let reason = response
+            .json::<ErrorMessage>()
+            .await
+            .map(|message| message.reason)
+            .unwrap_or_default();
         Err(Error::ResponseStatusNotSuccessful {
-            code: response.status(),
-            reason: response
-                .json::<ErrorMessage>()
-                .await
-                .map(|message| message.reason)
-                .unwrap_or_default(),
+            code,
+            reason: ApiError::parse(code, reason),
         })
     }
 }
 
+/// Obtain the forecast json from the Open-Meteo API hosted at `base_url` (e.g.
+/// [`DEFAULT_BASE_URL`]).
+pub async fn obtain_forecast_json_from(
+    client: &reqwest::Client,
+    base_url: &str,
+    parameters: &ForecastParameters,
+) -> Result<String, Error> {
+    forecast_response(client, base_url, parameters)
+        .await?
+        .text()
+        .await
+        .map_err(Error::from)
+}
+
+#[cfg(feature = "client")]
 pub async fn obtain_forecast(
     client: &reqwest::Client,
     parameters: &ForecastParameters,
 ) -> Result<Forecast, Error> {
-    obtain_forecast_json(client, parameters)
-        .await
-        .and_then(|json| Ok(serde_json::from_str(&json)?))
+    obtain_forecast_from(client, DEFAULT_BASE_URL, parameters).await
+}
+
+/// Obtain the forecast from the Open-Meteo API hosted at `base_url` (e.g. [`DEFAULT_BASE_URL`]).
+#[cfg(feature = "client")]
+pub async fn obtain_forecast_from(
+    client: &reqwest::Client,
+    base_url: &str,
+    parameters: &ForecastParameters,
+) -> Result<Forecast, Error> {
+    let response = forecast_response(client, base_url, parameters).await?;
+
+    // Deserialize the body as it is received, rather than buffering what can be a large response.
+    let body = response.bytes_stream().map_err(std::io::Error::other);
+    let reader = SyncIoBridge::new(StreamReader::new(body));
+    let forecast: Forecast = tokio::task::spawn_blocking(move || {
+        serde_json::from_reader(std::io::BufReader::new(reader))
+    })
+    .await
+    .map_err(|error| Error::Io(std::io::Error::other(error)))??;
+
+    forecast.check_units()?;
+    Ok(forecast)
 }
 
 #[cfg(test)]
 mod test {
     use chrono::NaiveDate;
     use chrono_tz::Tz;
+    use reqwest::StatusCode;
     use serde_json::json;
 
-    use crate::{Forecast, GroundLevel, HourlyVariable};
+    use crate::{
+        units::{KmPerHour, Metres},
+        ApiError, CellSelection, Error, Forecast, ForecastParameters, GroundLevel, HourlyVariable,
+    };
 
     use super::TimeZone;
 
@@ -1105,6 +1550,24 @@ mod test {
         assert_eq!(json!("Pacific/Auckland"), timezone_auckland);
     }
 
+    #[test]
+    fn forecast_parameters_serialize() {
+        let parameters = ForecastParameters::builder()
+            .latitude(-43.5)
+            .longitude(170.25)
+            .past_days(1)
+            .start_hour(NaiveDate::from_ymd(2022, 11, 2).and_hms(21, 0, 0))
+            .end_hour(NaiveDate::from_ymd(2022, 11, 4).and_hms(9, 0, 0))
+            .elevation(2216.0)
+            .cell_selection(CellSelection::Nearest)
+            .build();
+        assert_eq!(
+            "latitude=-43.5&longitude=170.25&past_days=1&start_hour=2022-11-02T21%3A00\
+            &end_hour=2022-11-04T09%3A00&elevation=2216.0&cell_selection=nearest",
+            serde_urlencoded::to_string(&parameters).unwrap()
+        );
+    }
+
     #[test]
     fn forecast_deserialize() {
         let forecast_json = json!({
@@ -1165,11 +1628,11 @@ mod test {
             hourly.time
         );
         assert_eq!(
-            &vec![0.0, 10.0],
+            &vec![KmPerHour(0.0), KmPerHour(10.0)],
             hourly.wind_speed.value(&GroundLevel::L10).unwrap()
         );
         assert_eq!(
-            &vec![10.0, 20.0],
+            &vec![KmPerHour(10.0), KmPerHour(20.0)],
             hourly.wind_speed.value(&GroundLevel::L80).unwrap()
         );
         assert_eq!(
@@ -1180,7 +1643,10 @@ mod test {
             &vec![210.0, 310.0],
             hourly.wind_direction.value(&GroundLevel::L80).unwrap()
         );
-        assert_eq!(vec![2000.0, 1980.0], hourly.freezing_level_height.unwrap());
+        assert_eq!(
+            vec![Metres(2000.0), Metres(1980.0)],
+            hourly.freezing_level_height.unwrap()
+        );
         let expected_hourly_units = vec![
             (HourlyVariable::FreezingLevelHeight, "m"),
             (HourlyVariable::Time, "iso8601"),
@@ -1195,4 +1661,77 @@ mod test {
         assert_eq!("NZDT", forecast.timezone_abbreviation);
         assert_eq!(46800, forecast.utc_offset_seconds);
     }
+
+    #[test]
+    fn forecast_check_units() {
+        let forecast_json = |precipitation_unit: &str| {
+            json!({
+              "elevation": 1050.0,
+              "generationtime_ms": 0.5849599838256836,
+              "hourly_units": {
+                "freezinglevel_height": "m",
+                "precipitation": precipitation_unit,
+                "time": "iso8601",
+                "winddirection_10m": "°",
+              },
+              "latitude": -43.375,
+              "longitude": 170.25,
+              "timezone": "Pacific/Auckland",
+              "timezone_abbreviation": "NZDT",
+              "utc_offset_seconds": 46800,
+            })
+        };
+
+        let forecast: Forecast = serde_json::from_value(forecast_json("mm")).unwrap();
+        forecast.check_units().unwrap();
+
+        let forecast: Forecast = serde_json::from_value(forecast_json("inch")).unwrap();
+        match forecast.check_units().unwrap_err() {
+            Error::UnexpectedUnit {
+                variable,
+                expected,
+                actual,
+            } => {
+                assert_eq!(HourlyVariable::Precipitation, variable);
+                assert_eq!("mm", expected);
+                assert_eq!("inch", actual);
+            }
+            error => panic!("Unexpected error: {}", error),
+        }
+    }
+
+    #[test]
+    fn api_error_parse() {
+        assert_eq!(
+            ApiError::CoordinatesOutOfRange(
+                "Latitude must be in range of -90 to 90°. Given: 100.0.".to_string()
+            ),
+            ApiError::parse(
+                StatusCode::BAD_REQUEST,
+                "Latitude must be in range of -90 to 90°. Given: 100.0.".to_string()
+            )
+        );
+        assert_eq!(
+            ApiError::InvalidParameter(
+                "Cannot initialize ForecastVariable from invalid String value foo".to_string()
+            ),
+            ApiError::parse(
+                StatusCode::BAD_REQUEST,
+                "Cannot initialize ForecastVariable from invalid String value foo".to_string()
+            )
+        );
+        assert_eq!(
+            ApiError::RateLimited(
+                "Minutely API request limit exceeded. Please try again in one minute.".to_string()
+            ),
+            ApiError::parse(
+                StatusCode::TOO_MANY_REQUESTS,
+                "Minutely API request limit exceeded. Please try again in one minute.".to_string()
+            )
+        );
+        assert_eq!(
+            ApiError::Other(String::new()),
+            ApiError::parse(StatusCode::INTERNAL_SERVER_ERROR, String::new())
+        );
+    }
 }
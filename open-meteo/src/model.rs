@@ -0,0 +1,77 @@
+//! Metadata about the weather models which provide the data of the Open-Meteo API, such as when
+//! the latest run of a model was initialised, see [`obtain_model_metadata()`].
+
+use chrono::{DateTime, Utc};
+use reqwest::Method;
+use serde::Deserialize;
+
+use crate::{check_response, Error, DEFAULT_BASE_URL};
+
+/// Metadata about a weather model, see [`obtain_model_metadata()`].
+#[derive(Debug, Clone, PartialEq, Eq, Deserialize)]
+pub struct ModelMetadata {
+    /// When the latest run of the model was initialised, i.e. its reference time (e.g. `06Z`).
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_run_initialisation_time: DateTime<Utc>,
+    /// When the data of the latest run of the model became available in the API.
+    #[serde(with = "chrono::serde::ts_seconds")]
+    pub last_run_availability_time: DateTime<Utc>,
+}
+
+/// Obtain the metadata of the `model` (e.g. `ncep_gfs013`) from the public Open-Meteo API.
+pub async fn obtain_model_metadata(
+    client: &reqwest::Client,
+    model: &str,
+) -> Result<ModelMetadata, Error> {
+    obtain_model_metadata_from(client, DEFAULT_BASE_URL, model).await
+}
+
+/// Obtain the metadata of the `model` from the Open-Meteo API hosted at `base_url` (e.g.
+/// [`DEFAULT_BASE_URL`]).
+pub async fn obtain_model_metadata_from(
+    client: &reqwest::Client,
+    base_url: &str,
+    model: &str,
+) -> Result<ModelMetadata, Error> {
+    let url = format!(
+        "{}/data/{}/static/meta.json",
+        base_url.trim_end_matches('/'),
+        model
+    );
+    tracing::trace!("GET {}", url);
+
+    let response = check_response(client.request(Method::GET, url).send().await?).await?;
+    Ok(serde_json::from_slice(&response.bytes().await?)?)
+}
+
+#[cfg(test)]
+mod test {
+    use serde_json::json;
+
+    use super::ModelMetadata;
+
+    #[test]
+    fn model_metadata_deserialize() {
+        let metadata: ModelMetadata = serde_json::from_value(json!({
+            "crs_wkt": "GEOGCRS[\"Reduced Gaussian Grid\"]",
+            "last_run_availability_time": 1670058300,
+            "last_run_initialisation_time": 1670047200,
+            "last_run_modification_time": 1670058000,
+            "temporal_resolution_seconds": 3600,
+            "update_interval_seconds": 21600
+        }))
+        .unwrap();
+        assert_eq!(
+            "2022-12-03T06:00:00Z"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .unwrap(),
+            metadata.last_run_initialisation_time
+        );
+        assert_eq!(
+            "2022-12-03T09:05:00Z"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .unwrap(),
+            metadata.last_run_availability_time
+        );
+    }
+}
@@ -0,0 +1,104 @@
+//! Unit-aware quantities for the values in [`crate::Hourly`], so that values with different units
+//! cannot be mixed up.
+//!
+//! The units of the values in a response are listed in [`crate::Forecast::hourly_units`], which
+//! are checked against the [`Quantity::UNIT`] of each variable using
+//! [`crate::Forecast::check_units()`].
+
+use std::{
+    fmt::Display,
+    ops::{Add, AddAssign},
+};
+
+use serde::{Deserialize, Serialize};
+
+/// A value with a unit.
+pub trait Quantity: Copy {
+    /// The unit of this quantity, as it appears in [`crate::Forecast::hourly_units`].
+    const UNIT: &'static str;
+
+    /// The value of this quantity in [`Quantity::UNIT`].
+    fn value(self) -> f32;
+}
+
+macro_rules! quantity {
+    ($(#[$meta:meta])* $name:ident, $unit:literal) => {
+        $(#[$meta])*
+        #[derive(Debug, Clone, Copy, Default, PartialEq, PartialOrd, Serialize, Deserialize)]
+        #[serde(transparent)]
+        pub struct $name(pub f32);
+
+        impl Quantity for $name {
+            const UNIT: &'static str = $unit;
+
+            fn value(self) -> f32 {
+                self.0
+            }
+        }
+
+        impl Add for $name {
+            type Output = Self;
+
+            fn add(self, rhs: Self) -> Self::Output {
+                Self(self.0 + rhs.0)
+            }
+        }
+
+        impl AddAssign for $name {
+            fn add_assign(&mut self, rhs: Self) {
+                self.0 += rhs.0;
+            }
+        }
+
+        impl Display for $name {
+            fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+                self.0.fmt(f)?;
+                f.write_str($unit)
+            }
+        }
+    };
+}
+
+quantity!(
+    /// Temperature in degrees Celsius.
+    Celsius,
+    "°C"
+);
+quantity!(
+    /// Speed in kilometres per hour.
+    KmPerHour,
+    "km/h"
+);
+quantity!(
+    /// Length (e.g. of precipitation) in millimetres.
+    Millimetres,
+    "mm"
+);
+quantity!(
+    /// Length (e.g. of height) in metres.
+    Metres,
+    "m"
+);
+quantity!(
+    /// Irradiance (e.g. of solar radiation) in watts per square metre.
+    WattsPerSquareMetre,
+    "W/m²"
+);
+
+#[cfg(test)]
+mod test {
+    use super::{KmPerHour, Millimetres, Quantity};
+
+    #[test]
+    fn test_quantity() {
+        let mut precipitation = Millimetres(0.5);
+        precipitation += Millimetres(1.0);
+        assert_eq!(Millimetres(1.5), precipitation);
+        assert_eq!("1.5mm", precipitation.to_string());
+        assert_eq!("km/h", KmPerHour::UNIT);
+        assert_eq!(10.0, KmPerHour(10.0).value());
+
+        let speeds: Vec<KmPerHour> = serde_json::from_str("[0.0, 10.5]").unwrap();
+        assert_eq!(vec![KmPerHour(0.0), KmPerHour(10.5)], speeds);
+    }
+}
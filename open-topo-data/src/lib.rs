@@ -76,6 +76,10 @@ pub enum Error {
     Reqwest(#[from] reqwest::Error),
     #[error("No results in response")]
     NoResults,
+    #[error("Unsuccessful response status {status}: {message}")]
+    ResponseStatus { status: String, message: String },
+    #[error("Daily request limit of {0} exceeded")]
+    DailyLimitExceeded(u32),
 }
 
 #[derive(Debug, PartialEq)]
@@ -85,23 +89,37 @@ pub struct Parameters {
     pub dataset: Dataset,
 }
 
+/// Base url of the public Open Topo Data API.
+pub const DEFAULT_BASE_URL: &str = "https://api.opentopodata.org";
+
 pub async fn obtain_elevation(
     client: &reqwest::Client,
     parameters: &Parameters,
+) -> Result<f32, Error> {
+    obtain_elevation_from(client, DEFAULT_BASE_URL, None, parameters).await
+}
+
+/// Obtain the elevation from the Open Topo Data API hosted at `base_url` (e.g.
+/// [`DEFAULT_BASE_URL`]). If specified, the `api_key` is sent as a bearer token in the
+/// `Authorization` header, for self-hosted servers behind an authenticating proxy.
+pub async fn obtain_elevation_from(
+    client: &reqwest::Client,
+    base_url: &str,
+    api_key: Option<&str>,
+    parameters: &Parameters,
 ) -> Result<f32, Error> {
     let url = format!(
-        "https://api.opentopodata.org/v1/{}?locations={},{}",
+        "{}/v1/{}?locations={},{}",
+        base_url.trim_end_matches('/'),
         serde_json::to_value(&parameters.dataset)?.as_str().unwrap(),
         parameters.latitude,
         parameters.longitude,
     );
-    let results: ObtainResults = client
-        .get(url)
-        .send()
-        .await?
-        .error_for_status()?
-        .json()
-        .await?;
+    let mut request = client.get(url);
+    if let Some(api_key) = api_key {
+        request = request.bearer_auth(api_key);
+    }
+    let results: ObtainResults = request.send().await?.error_for_status()?.json().await?;
     Ok(results.results.get(0).ok_or(Error::NoResults)?.elevation)
 }
 
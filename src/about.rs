@@ -0,0 +1,68 @@
+//! Information about the service, sent in reply to the
+//! [`Command::About`](crate::request::Command::About) command.
+
+/// Link to the documentation for users of the service.
+pub const DOCUMENTATION_URL: &str = "https://kellpossible.github.io/email-weather";
+
+/// Version of the service.
+const VERSION: &str = env!("CARGO_PKG_VERSION");
+
+/// The message sent in reply to [`Command::About`](crate::request::Command::About). A `short` message fits within the 160
+/// character limit of an inReach reply. `quota_remaining` is the number of requests the service
+/// can still process today, `None` if it is unlimited (see [`crate::quota::Quotas::remaining()`]).
+#[must_use]
+pub fn message(short: bool, quota_remaining: Option<u32>) -> String {
+    if short {
+        let quota = match quota_remaining {
+            Some(remaining) => format!("{} today", remaining),
+            None => "unlimited".to_owned(),
+        };
+        format!(
+            "email-weather v{} Data: Open-Meteo.com (CC BY 4.0), MET Norway, Open Topo Data. \
+            Quota: {}. Help: {}",
+            VERSION,
+            quota,
+            DOCUMENTATION_URL.trim_start_matches("https://")
+        )
+    } else {
+        let quota = match quota_remaining {
+            Some(remaining) => format!("{remaining} requests remaining today (UTC)"),
+            None => "unlimited".to_owned(),
+        };
+        format!(
+            "email-weather v{VERSION}\n\
+            \n\
+            Forecast data:\n\
+            + Weather data by Open-Meteo.com (https://open-meteo.com), licensed CC BY 4.0\n\
+            + MET Norway (https://api.met.no) when Open-Meteo is unavailable\n\
+            + Terrain elevation by Open Topo Data (https://www.opentopodata.org) using the \
+            Mapzen dataset\n\
+            \n\
+            Quota: {quota}\n\
+            \n\
+            Documentation: {DOCUMENTATION_URL}"
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{message, DOCUMENTATION_URL};
+
+    #[test]
+    fn test_short_message_fits_inreach() {
+        for quota_remaining in [None, Some(u32::MAX)] {
+            let short = message(true, quota_remaining);
+            assert!(short.len() <= 160, "{} characters: {short}", short.len());
+        }
+    }
+
+    #[test]
+    fn test_long_message() {
+        let long = message(false, Some(42));
+        assert!(long.contains("42 requests remaining"));
+        assert!(long.contains(env!("CARGO_PKG_VERSION")));
+        assert!(long.contains("Open-Meteo"));
+        assert!(long.contains(DOCUMENTATION_URL));
+    }
+}
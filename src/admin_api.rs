@@ -0,0 +1,172 @@
+//! JSON API for operators to inspect recent activity of the service (e.g. to answer "did my
+//! message arrive?" support questions), see [`admin_api()`].
+
+use std::sync::Arc;
+
+use axum::{
+    extract::Query,
+    middleware,
+    response::IntoResponse,
+    routing::{get, post},
+    Json, Router,
+};
+use reqwest::StatusCode;
+use serde::Deserialize;
+
+use crate::{
+    analytics::Analytics,
+    auth::{require_session, AdminAuth},
+    changes::PreviousForecasts,
+    confirmation::SenderConfirmations,
+    encryption::PublicKeys,
+    feed::Feeds,
+    forget,
+};
+
+/// Number of records returned when no limit is specified.
+const DEFAULT_LIMIT: u32 = 50;
+/// Maximum number of records that can be requested.
+const MAX_LIMIT: u32 = 500;
+
+/// Query parameters for the admin API routes.
+#[derive(Debug, Deserialize)]
+struct RecentQuery {
+    /// Maximum number of records to return, see [`DEFAULT_LIMIT`] and [`MAX_LIMIT`].
+    limit: Option<u32>,
+    /// Only return records for this request id.
+    request_id: Option<String>,
+}
+
+/// Query parameters for `GET /admin/audit`.
+#[derive(Debug, Deserialize)]
+struct AuditQuery {
+    /// Maximum number of entries to return, see [`DEFAULT_LIMIT`] and [`MAX_LIMIT`].
+    limit: Option<u32>,
+}
+
+/// Body of `POST /admin/forget`.
+#[derive(Debug, Deserialize)]
+struct ForgetBody {
+    /// Email address or inReach device id of the sender to forget.
+    sender: String,
+}
+
+impl RecentQuery {
+    fn limit(&self) -> u32 {
+        self.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT)
+    }
+}
+
+fn json_response<T: serde::Serialize>(result: eyre::Result<T>) -> axum::response::Response {
+    match result {
+        Ok(records) => Json(records).into_response(),
+        Err(error) => {
+            tracing::error!("{:?}", error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+/// Routes for the admin API, which require an admin session (see [`AdminAuth`]):
+///
+/// + `GET /requests?limit=..&request_id=..` responds with the most recently processed requests
+///   recorded in `analytics`, newest first.
+/// + `GET /replies?limit=..&request_id=..` responds with the most recently sent (or failed)
+///   replies recorded in `analytics`, newest first.
+///
+/// + `GET /audit?limit=..` responds with the most recent admin actions recorded in the audit log
+///   (see [`AdminAuth::with_audit_log()`]), newest first.
+///
+/// + `POST /forget` with a JSON body `{"sender": ".."}` erases the data stored about the sender
+///   (an email address or inReach device id), see [`forget::forget()`], and responds with the
+///   number of records erased.
+///
+/// `limit` defaults to 50 (maximum 500), and `request_id` optionally selects the records for a
+/// single request.
+pub fn admin_api(
+    analytics: &'static Analytics,
+    confirmations: Option<&'static SenderConfirmations>,
+    feeds: Option<&'static Feeds>,
+    previous_forecasts: Option<&'static PreviousForecasts>,
+    public_keys: Option<&'static PublicKeys>,
+    auth: Arc<AdminAuth>,
+) -> Router {
+    Router::new()
+        .route(
+            "/requests",
+            get(move |Query(query): Query<RecentQuery>| async move {
+                let result = tokio::task::spawn_blocking(move || {
+                    analytics.recent_requests(query.limit(), query.request_id.as_deref())
+                })
+                .await
+                .map_err(eyre::Error::from)
+                .and_then(|result| result);
+                json_response(result)
+            }),
+        )
+        .route(
+            "/replies",
+            get(move |Query(query): Query<RecentQuery>| async move {
+                let result = tokio::task::spawn_blocking(move || {
+                    analytics.recent_replies(query.limit(), query.request_id.as_deref())
+                })
+                .await
+                .map_err(eyre::Error::from)
+                .and_then(|result| result);
+                json_response(result)
+            }),
+        )
+        .route(
+            "/audit",
+            get({
+                let auth = auth.clone();
+                move |Query(query): Query<AuditQuery>| async move {
+                    let limit = query.limit.unwrap_or(DEFAULT_LIMIT).min(MAX_LIMIT) as usize;
+                    let result = tokio::task::spawn_blocking(move || match auth.audit_log() {
+                        Some(audit_log) => audit_log.recent(limit),
+                        None => Ok(Vec::new()),
+                    })
+                    .await
+                    .map_err(eyre::Error::from)
+                    .and_then(|result| result);
+                    json_response(result)
+                }
+            }),
+        )
+        .route(
+            "/forget",
+            post(move |Json(body): Json<ForgetBody>| async move {
+                let result = tokio::task::spawn_blocking(move || {
+                    forget::forget(
+                        &body.sender,
+                        Some(analytics),
+                        confirmations,
+                        feeds,
+                        previous_forecasts,
+                        public_keys,
+                    )
+                })
+                .await
+                .map_err(eyre::Error::from)
+                .and_then(|result| result);
+                json_response(result)
+            }),
+        )
+        .route_layer(middleware::from_fn_with_state(auth, require_session))
+}
+
+#[cfg(test)]
+mod test {
+    use super::{RecentQuery, DEFAULT_LIMIT, MAX_LIMIT};
+
+    #[test]
+    fn test_recent_query_limit() {
+        let query = |limit| RecentQuery {
+            limit,
+            request_id: None,
+        };
+        assert_eq!(DEFAULT_LIMIT, query(None).limit());
+        assert_eq!(10, query(Some(10)).limit());
+        assert_eq!(MAX_LIMIT, query(Some(10_000)).limit());
+    }
+}
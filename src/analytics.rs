@@ -0,0 +1,632 @@
+//! Anonymized analytics of forecast requests and replies, persisted to a local SQLite database,
+//! see [`Analytics`].
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use rusqlite::{params, Connection};
+use serde::Serialize;
+
+use crate::{correlation::RequestId, gis::Position, store::Store};
+
+/// The kind of device which sent a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum DeviceKind {
+    /// An inReach satellite communicator.
+    Inreach,
+    /// A plain email client.
+    Plain,
+}
+
+impl DeviceKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Inreach => "inreach",
+            Self::Plain => "plain",
+        }
+    }
+}
+
+/// The format requested for the reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum FormatKind {
+    /// No format was requested, the default format was used.
+    Default,
+    /// The short format was requested.
+    Short,
+    /// The long format was requested.
+    Long,
+}
+
+impl FormatKind {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Default => "default",
+            Self::Short => "short",
+            Self::Long => "long",
+        }
+    }
+}
+
+/// The outcome of processing a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Outcome {
+    /// A forecast was sent in the reply.
+    Success,
+    /// No forecast position was specified.
+    NoPosition,
+    /// An external service was temporarily unavailable.
+    Unavailable,
+    /// An unexpected error occurred.
+    Error,
+    /// The sender has not confirmed their address, see [`crate::confirmation`].
+    Unconfirmed,
+    /// The request exceeded a quota, see [`crate::quota`].
+    QuotaExceeded,
+    /// The process queue was saturated, see [`crate::back_pressure`].
+    Overloaded,
+    /// The request was a duplicate of one answered recently, see [`crate::duplicate`].
+    Duplicate,
+}
+
+impl Outcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Success => "success",
+            Self::NoPosition => "no_position",
+            Self::Unavailable => "unavailable",
+            Self::Error => "error",
+            Self::Unconfirmed => "unconfirmed",
+            Self::QuotaExceeded => "quota_exceeded",
+            Self::Overloaded => "overloaded",
+            Self::Duplicate => "duplicate",
+        }
+    }
+}
+
+/// The outcome of sending a reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReplyOutcome {
+    /// The reply was sent.
+    Sent,
+    /// The reply could not be sent and was discarded.
+    Failed,
+}
+
+impl ReplyOutcome {
+    fn as_str(self) -> &'static str {
+        match self {
+            Self::Sent => "sent",
+            Self::Failed => "failed",
+        }
+    }
+}
+
+/// A processed request. It contains no personal information, and the position is rounded (see
+/// [`RequestEvent::new()`]) so that it cannot be used to locate the user precisely. The sender is
+/// only stored as a hash (see [`RequestEvent::with_sender()`]), so that their requests can be
+/// erased using [`Analytics::forget()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct RequestEvent {
+    /// Id of the request, used to match it with its reply and log entries.
+    pub request_id: RequestId,
+    /// When processing the request started.
+    pub timestamp: DateTime<Utc>,
+    /// Position of the requested forecast, rounded to 0.1 degrees.
+    pub position: Option<(f32, f32)>,
+    /// The kind of device which sent the request.
+    pub device: DeviceKind,
+    /// The format requested for the reply.
+    pub format: FormatKind,
+    /// How long it took to process the request.
+    pub duration: chrono::Duration,
+    /// The outcome of processing the request.
+    pub outcome: Outcome,
+    /// Hash of the sender's identity, see [`crate::forget::sender_hash()`].
+    pub sender: Option<String>,
+}
+
+/// Round a coordinate to 0.1 degrees (approximately 11km).
+fn round_coordinate(value: f32) -> f32 {
+    (value * 10.0).round() / 10.0
+}
+
+impl RequestEvent {
+    /// Construct a new [`RequestEvent`], rounding the `position`.
+    #[must_use]
+    pub fn new(
+        request_id: RequestId,
+        timestamp: DateTime<Utc>,
+        position: Option<Position>,
+        device: DeviceKind,
+        format: FormatKind,
+        duration: chrono::Duration,
+        outcome: Outcome,
+    ) -> Self {
+        Self {
+            request_id,
+            timestamp,
+            position: position.map(|position| {
+                (
+                    round_coordinate(position.latitude),
+                    round_coordinate(position.longitude),
+                )
+            }),
+            device,
+            format,
+            duration,
+            outcome,
+            sender: None,
+        }
+    }
+
+    /// Set the hash of the sender's identity, see [`crate::forget::sender_hash()`].
+    #[must_use]
+    pub fn with_sender(mut self, sender: String) -> Self {
+        self.sender = Some(sender);
+        self
+    }
+}
+
+/// A reply which was sent (or failed to send). Like [`RequestEvent`] it contains no personal
+/// information.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ReplyEvent {
+    /// Id of the request that this is the reply to.
+    pub request_id: RequestId,
+    /// When sending the reply finished.
+    pub timestamp: DateTime<Utc>,
+    /// The kind of device that the reply was sent to.
+    pub device: DeviceKind,
+    /// Number of attempts made to send the reply.
+    pub attempts: u32,
+    /// The outcome of sending the reply.
+    pub outcome: ReplyOutcome,
+}
+
+/// A recorded [`RequestEvent`], see [`Analytics::recent_requests()`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct RequestRecord {
+    /// Id of the request, `None` for requests recorded before request ids were stored.
+    pub request_id: Option<String>,
+    /// When processing the request started.
+    pub timestamp: DateTime<Utc>,
+    /// Latitude of the requested forecast, rounded to 0.1 degrees.
+    pub latitude: Option<f32>,
+    /// Longitude of the requested forecast, rounded to 0.1 degrees.
+    pub longitude: Option<f32>,
+    /// The kind of device which sent the request.
+    pub device: String,
+    /// The format requested for the reply.
+    pub format: String,
+    /// How long it took to process the request in milliseconds.
+    pub duration_ms: u64,
+    /// The outcome of processing the request.
+    pub outcome: String,
+}
+
+/// A recorded [`ReplyEvent`], see [`Analytics::recent_replies()`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct ReplyRecord {
+    /// Id of the request that this is the reply to.
+    pub request_id: String,
+    /// When sending the reply finished.
+    pub timestamp: DateTime<Utc>,
+    /// The kind of device that the reply was sent to.
+    pub device: String,
+    /// Number of attempts made to send the reply.
+    pub attempts: u32,
+    /// The outcome of sending the reply.
+    pub outcome: String,
+}
+
+/// Number of records erased by [`Analytics::forget()`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize)]
+pub struct Forgotten {
+    /// Number of requests erased.
+    pub requests: u64,
+    /// Number of replies erased.
+    pub replies: u64,
+    /// Number of sender confirmations erased, see [`crate::confirmation::SenderConfirmations`].
+    pub confirmations: u64,
+    /// Number of feed subscriptions and entries erased, see [`crate::feed::Feeds`].
+    pub feeds: u64,
+    /// Number of previous forecasts erased, see [`crate::changes::PreviousForecasts`].
+    pub previous_forecasts: u64,
+    /// Number of public keys erased, see [`crate::encryption::PublicKeys`].
+    pub public_keys: u64,
+}
+
+/// Simple statistics about the recorded requests, see [`Analytics::stats()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct AnalyticsStats {
+    /// Total number of requests.
+    pub total: u64,
+    /// Mean time taken to process a request in milliseconds, `None` if there are no requests.
+    pub mean_duration_ms: Option<f64>,
+    /// Number of requests for each outcome, ordered by the most frequent.
+    pub by_outcome: Vec<(String, u64)>,
+    /// Number of requests for each kind of device, ordered by the most frequent.
+    pub by_device: Vec<(String, u64)>,
+    /// Number of requests for each format, ordered by the most frequent.
+    pub by_format: Vec<(String, u64)>,
+}
+
+fn to_count(count: i64) -> u64 {
+    u64::try_from(count).unwrap_or_default()
+}
+
+fn parse_timestamp(timestamp: &str) -> rusqlite::Result<DateTime<Utc>> {
+    DateTime::parse_from_rfc3339(timestamp)
+        .map(|timestamp| timestamp.with_timezone(&Utc))
+        .map_err(|error| {
+            rusqlite::Error::FromSqlConversionFailure(
+                0,
+                rusqlite::types::Type::Text,
+                Box::new(error),
+            )
+        })
+}
+
+/// Records [`RequestEvent`]s and [`ReplyEvent`]s in a SQLite database.
+pub struct Analytics {
+    store: Store,
+}
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS requests (
+    id INTEGER PRIMARY KEY,
+    timestamp TEXT NOT NULL,
+    latitude REAL,
+    longitude REAL,
+    device TEXT NOT NULL,
+    format TEXT NOT NULL,
+    duration_ms INTEGER NOT NULL,
+    outcome TEXT NOT NULL,
+    request_id TEXT,
+    sender TEXT
+);
+CREATE TABLE IF NOT EXISTS replies (
+    id INTEGER PRIMARY KEY,
+    request_id TEXT NOT NULL,
+    timestamp TEXT NOT NULL,
+    device TEXT NOT NULL,
+    attempts INTEGER NOT NULL,
+    outcome TEXT NOT NULL
+);";
+
+impl Analytics {
+    /// Open (or create) the analytics database at `path`.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        Self::from_store(Store::open("analytics", path, SCHEMA)?)
+    }
+
+    /// Open an analytics database which is stored in memory, and lost when it is dropped.
+    pub fn open_in_memory() -> eyre::Result<Self> {
+        Self::from_store(Store::open_in_memory("analytics", SCHEMA)?)
+    }
+
+    /// Add the columns which are missing from databases created by previous versions.
+    fn from_store(store: Store) -> eyre::Result<Self> {
+        Ok(Self {
+            store: store.migrate("requests", &[("request_id", "TEXT"), ("sender", "TEXT")])?,
+        })
+    }
+
+    /// The database that the analytics are stored in.
+    #[cfg(test)]
+    pub(crate) fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Insert `event` into the database.
+    pub fn insert(&self, event: &RequestEvent) -> eyre::Result<()> {
+        self.store
+            .connection()
+            .execute(
+                "INSERT INTO requests \
+                (timestamp, latitude, longitude, device, format, duration_ms, outcome, \
+                request_id, sender) \
+                VALUES (?1, ?2, ?3, ?4, ?5, ?6, ?7, ?8, ?9)",
+                params![
+                    event.timestamp.to_rfc3339(),
+                    event.position.map(|(latitude, _)| latitude),
+                    event.position.map(|(_, longitude)| longitude),
+                    event.device.as_str(),
+                    event.format.as_str(),
+                    event.duration.num_milliseconds(),
+                    event.outcome.as_str(),
+                    event.request_id.to_string(),
+                    event.sender,
+                ],
+            )
+            .wrap_err("Unable to insert request event")?;
+        Ok(())
+    }
+
+    /// Insert reply `event` into the database.
+    pub fn insert_reply(&self, event: &ReplyEvent) -> eyre::Result<()> {
+        self.store
+            .connection()
+            .execute(
+                "INSERT INTO replies (request_id, timestamp, device, attempts, outcome) \
+                VALUES (?1, ?2, ?3, ?4, ?5)",
+                params![
+                    event.request_id.to_string(),
+                    event.timestamp.to_rfc3339(),
+                    event.device.as_str(),
+                    event.attempts,
+                    event.outcome.as_str(),
+                ],
+            )
+            .wrap_err("Unable to insert reply event")?;
+        Ok(())
+    }
+
+    /// Record `event` in the background, so that writing to the database does not hold up
+    /// processing. Failures are logged.
+    pub fn record(&'static self, event: RequestEvent) {
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = self.insert(&event) {
+                tracing::error!("Error recording analytics: {:?}", error);
+            }
+        });
+    }
+
+    /// Record reply `event` in the background, see [`Analytics::record()`].
+    pub fn record_reply(&'static self, event: ReplyEvent) {
+        tokio::task::spawn_blocking(move || {
+            if let Err(error) = self.insert_reply(&event) {
+                tracing::error!("Error recording reply analytics: {:?}", error);
+            }
+        });
+    }
+
+    /// Erase the requests recorded for `sender` (see [`crate::forget::sender_hash()`]), and their
+    /// replies.
+    pub fn forget(&self, sender: &str) -> eyre::Result<Forgotten> {
+        let mut connection = self.store.connection();
+        let transaction = connection.transaction()?;
+        let replies = transaction
+            .execute(
+                "DELETE FROM replies WHERE request_id IN \
+                (SELECT request_id FROM requests WHERE sender = ?1)",
+                [sender],
+            )
+            .wrap_err("Unable to erase replies")?;
+        let requests = transaction
+            .execute("DELETE FROM requests WHERE sender = ?1", [sender])
+            .wrap_err("Unable to erase requests")?;
+        transaction.commit()?;
+        Ok(Forgotten {
+            requests: requests as u64,
+            replies: replies as u64,
+            ..Forgotten::default()
+        })
+    }
+
+    /// The most recent (up to `limit`) recorded requests, newest first, optionally only those
+    /// with the specified `request_id`.
+    pub fn recent_requests(
+        &self,
+        limit: u32,
+        request_id: Option<&str>,
+    ) -> eyre::Result<Vec<RequestRecord>> {
+        let connection = self.store.connection();
+        let mut statement = connection.prepare(
+            "SELECT request_id, timestamp, latitude, longitude, device, format, duration_ms, \
+            outcome FROM requests WHERE ?1 IS NULL OR request_id = ?1 \
+            ORDER BY timestamp DESC, id DESC LIMIT ?2",
+        )?;
+        let records = statement
+            .query_map(params![request_id, limit], |row| {
+                Ok(RequestRecord {
+                    request_id: row.get(0)?,
+                    timestamp: parse_timestamp(&row.get::<_, String>(1)?)?,
+                    latitude: row.get(2)?,
+                    longitude: row.get(3)?,
+                    device: row.get(4)?,
+                    format: row.get(5)?,
+                    duration_ms: to_count(row.get(6)?),
+                    outcome: row.get(7)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("Unable to query recent requests")?;
+        Ok(records)
+    }
+
+    /// The most recent (up to `limit`) recorded replies, newest first, optionally only those
+    /// with the specified `request_id`.
+    pub fn recent_replies(
+        &self,
+        limit: u32,
+        request_id: Option<&str>,
+    ) -> eyre::Result<Vec<ReplyRecord>> {
+        let connection = self.store.connection();
+        let mut statement = connection.prepare(
+            "SELECT request_id, timestamp, device, attempts, outcome FROM replies \
+            WHERE ?1 IS NULL OR request_id = ?1 ORDER BY timestamp DESC, id DESC LIMIT ?2",
+        )?;
+        let records = statement
+            .query_map(params![request_id, limit], |row| {
+                Ok(ReplyRecord {
+                    request_id: row.get(0)?,
+                    timestamp: parse_timestamp(&row.get::<_, String>(1)?)?,
+                    device: row.get(2)?,
+                    attempts: row.get(3)?,
+                    outcome: row.get(4)?,
+                })
+            })?
+            .collect::<Result<Vec<_>, _>>()
+            .wrap_err("Unable to query recent replies")?;
+        Ok(records)
+    }
+
+    fn count_by(connection: &Connection, column: &str) -> eyre::Result<Vec<(String, u64)>> {
+        let mut statement = connection.prepare(&format!(
+            "SELECT {column}, COUNT(*) AS count FROM requests GROUP BY {column} \
+            ORDER BY count DESC, {column}"
+        ))?;
+        let counts = statement
+            .query_map([], |row| Ok((row.get(0)?, row.get::<_, i64>(1)?)))?
+            .map(|result| result.map(|(value, count)| (value, to_count(count))))
+            .collect::<Result<Vec<(String, u64)>, _>>()?;
+        Ok(counts)
+    }
+
+    /// Obtain statistics about the recorded requests.
+    pub fn stats(&self) -> eyre::Result<AnalyticsStats> {
+        let connection = self.store.connection();
+        let (total, mean_duration_ms): (i64, Option<f64>) = connection
+            .query_row(
+                "SELECT COUNT(*), AVG(duration_ms) FROM requests",
+                [],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .wrap_err("Unable to query analytics totals")?;
+        Ok(AnalyticsStats {
+            total: to_count(total),
+            mean_duration_ms,
+            by_outcome: Self::count_by(&connection, "outcome")?,
+            by_device: Self::count_by(&connection, "device")?,
+            by_format: Self::count_by(&connection, "format")?,
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::{correlation::RequestId, gis::Position, store::Store};
+
+    use crate::time::test_now as now;
+
+    use super::{
+        Analytics, DeviceKind, FormatKind, Outcome, ReplyEvent, ReplyOutcome, RequestEvent, SCHEMA,
+    };
+
+    #[test]
+    fn test_request_event_rounds_position() {
+        let event = RequestEvent::new(
+            RequestId::new(),
+            now(),
+            Some(Position::new(-43.513832, 170.33975)),
+            DeviceKind::Inreach,
+            FormatKind::Default,
+            Duration::milliseconds(100),
+            Outcome::Success,
+        );
+        assert_eq!(Some((-43.5, 170.3)), event.position);
+    }
+
+    #[test]
+    fn test_analytics_stats() {
+        let analytics = Analytics::open_in_memory().unwrap();
+        assert_eq!(0, analytics.stats().unwrap().total);
+
+        for (device, duration, outcome) in [
+            (DeviceKind::Inreach, 100, Outcome::Success),
+            (DeviceKind::Plain, 200, Outcome::Success),
+            (DeviceKind::Plain, 300, Outcome::NoPosition),
+        ] {
+            analytics
+                .insert(&RequestEvent::new(
+                    RequestId::new(),
+                    now(),
+                    None,
+                    device,
+                    FormatKind::Short,
+                    Duration::milliseconds(duration),
+                    outcome,
+                ))
+                .unwrap();
+        }
+
+        let stats = analytics.stats().unwrap();
+        assert_eq!(3, stats.total);
+        assert_eq!(Some(200.0), stats.mean_duration_ms);
+        assert_eq!(
+            vec![("success".to_owned(), 2), ("no_position".to_owned(), 1)],
+            stats.by_outcome
+        );
+        assert_eq!(
+            vec![("plain".to_owned(), 2), ("inreach".to_owned(), 1)],
+            stats.by_device
+        );
+        assert_eq!(vec![("short".to_owned(), 3)], stats.by_format);
+    }
+
+    #[test]
+    fn test_analytics_recent() {
+        let analytics = Analytics::open_in_memory().unwrap();
+        let request_ids = [RequestId::new(), RequestId::new()];
+        for (i, request_id) in request_ids.iter().enumerate() {
+            let timestamp = now() + Duration::minutes(i64::try_from(i).unwrap());
+            analytics
+                .insert(&RequestEvent::new(
+                    *request_id,
+                    timestamp,
+                    Some(Position::new(-43.5, 170.3)),
+                    DeviceKind::Plain,
+                    FormatKind::Long,
+                    Duration::milliseconds(100),
+                    Outcome::Success,
+                ))
+                .unwrap();
+            analytics
+                .insert_reply(&ReplyEvent {
+                    request_id: *request_id,
+                    timestamp,
+                    device: DeviceKind::Plain,
+                    attempts: 1,
+                    outcome: ReplyOutcome::Sent,
+                })
+                .unwrap();
+        }
+
+        let requests = analytics.recent_requests(10, None).unwrap();
+        assert_eq!(2, requests.len());
+        assert_eq!(Some(request_ids[1].to_string()), requests[0].request_id);
+        assert_eq!(Some(-43.5), requests[0].latitude);
+        assert_eq!("long", requests[0].format);
+
+        let requests = analytics.recent_requests(1, None).unwrap();
+        assert_eq!(1, requests.len());
+
+        let request_id = request_ids[0].to_string();
+        let replies = analytics.recent_replies(10, Some(&request_id)).unwrap();
+        assert_eq!(1, replies.len());
+        assert_eq!(request_id, replies[0].request_id);
+        assert_eq!(now(), replies[0].timestamp);
+        assert_eq!("sent", replies[0].outcome);
+    }
+
+    #[test]
+    fn test_analytics_migrate() {
+        let connection = rusqlite::Connection::open_in_memory().unwrap();
+        connection
+            .execute_batch(
+                "CREATE TABLE requests (
+                    id INTEGER PRIMARY KEY,
+                    timestamp TEXT NOT NULL,
+                    latitude REAL,
+                    longitude REAL,
+                    device TEXT NOT NULL,
+                    format TEXT NOT NULL,
+                    duration_ms INTEGER NOT NULL,
+                    outcome TEXT NOT NULL
+                );
+                INSERT INTO requests (timestamp, device, format, duration_ms, outcome)
+                VALUES ('2022-12-03T08:00:00+00:00', 'plain', 'short', 100, 'success');",
+            )
+            .unwrap();
+        let analytics =
+            Analytics::from_store(Store::from_connection("analytics", connection, SCHEMA).unwrap())
+                .unwrap();
+        let requests = analytics.recent_requests(10, None).unwrap();
+        assert_eq!(1, requests.len());
+        assert_eq!(None, requests[0].request_id);
+    }
+}
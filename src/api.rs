@@ -0,0 +1,363 @@
+//! Public REST API for obtaining forecasts over http, see [`forecast_api()`], and decoding
+//! forecasts in the short format, see [`decode_api()`].
+//!
+//! Browser based tools hosted on other origins can call the API when their origin is permitted
+//! with [`cors_layer()`], and access to the API can be restricted to holders of [`ApiTokens`].
+
+use std::sync::Arc;
+
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderValue, Method, Request},
+    middleware::Next,
+    response::{IntoResponse, Response},
+    routing::{get, post},
+    Json, Router,
+};
+use hmac::{Hmac, Mac};
+use rand::RngCore;
+use reqwest::StatusCode;
+use serde::Deserialize;
+use sha2::Sha256;
+use tower_http::cors::{AllowOrigin, CorsLayer};
+
+use crate::{
+    forecast_service,
+    format::{
+        decode_short, DecodedForecast, FormatDetail, FormatForecastOptions, LongFormatDetail,
+        LongFormatStyle,
+    },
+    gis::Position,
+    process::{build_forecast, ForecastWindow, FormatForecast},
+    profile::ForecastVariable,
+    time, topo_data_service,
+};
+
+/// Maximum number of days that can be requested, limited by what Open-Meteo provides.
+const MAX_DAYS: u8 = 16;
+
+/// Format of the forecast returned by the API.
+#[derive(Debug, Clone, Copy, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    /// See [`FormatDetail::Short`].
+    Short,
+    /// See [`FormatDetail::Long`], rendered as plain text.
+    Long,
+}
+
+impl From<Format> for FormatForecastOptions {
+    fn from(format: Format) -> Self {
+        let detail = match format {
+            Format::Short => FormatDetail::default(),
+            Format::Long => FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+        };
+        Self {
+            detail,
+            ..Self::default()
+        }
+    }
+}
+
+/// Query parameters for `GET /api/forecast`.
+#[derive(Debug, Deserialize)]
+struct ForecastQuery {
+    lat: f32,
+    lon: f32,
+    format: Option<Format>,
+    days: Option<u8>,
+}
+
+#[derive(Debug, thiserror::Error)]
+enum ForecastApiError {
+    #[error("{0}")]
+    BadRequest(String),
+    #[error("Internal server error")]
+    InternalServerError(#[from] eyre::Error),
+}
+
+impl IntoResponse for ForecastApiError {
+    fn into_response(self) -> axum::response::Response {
+        match self {
+            ForecastApiError::BadRequest(message) => {
+                (StatusCode::BAD_REQUEST, message).into_response()
+            }
+            ForecastApiError::InternalServerError(error) => {
+                tracing::error!("{:?}", error);
+                (StatusCode::INTERNAL_SERVER_ERROR, "Internal server error").into_response()
+            }
+        }
+    }
+}
+
+/// Validate that `latitude` and `longitude` (in decimal degrees) are within range, returns a
+/// message describing the problem if they are not.
+pub fn validate_position(latitude: f32, longitude: f32) -> Result<(), String> {
+    if !(-90.0..=90.0).contains(&latitude) {
+        return Err(format!(
+            "Invalid latitude {latitude}. It needs to be in the range [-90.0, 90.0]"
+        ));
+    }
+    if !(-180.0..=180.0).contains(&longitude) {
+        return Err(format!(
+            "Invalid longitude {longitude}. It needs to be in the range [-180.0, 180.0]"
+        ));
+    }
+    Ok(())
+}
+
+fn validate_query(query: &ForecastQuery) -> Result<(), ForecastApiError> {
+    validate_position(query.lat, query.lon).map_err(ForecastApiError::BadRequest)?;
+    if let Some(days) = query.days {
+        if days == 0 || days > MAX_DAYS {
+            return Err(ForecastApiError::BadRequest(format!(
+                "Invalid days {}. It needs to be in the range [1, {}]",
+                days, MAX_DAYS
+            )));
+        }
+    }
+    Ok(())
+}
+
+async fn forecast(
+    query: ForecastQuery,
+    time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    topo_data_service: &dyn topo_data_service::Port,
+) -> Result<String, ForecastApiError> {
+    validate_query(&query)?;
+
+    let format: FormatForecastOptions = query.format.unwrap_or(Format::Short).into();
+    let forecast_output = build_forecast(
+        time,
+        forecast_service,
+        Some(topo_data_service),
+        Position::new(query.lat, query.lon),
+        ForecastVariable::DEFAULT,
+        ForecastWindow {
+            past_days: None,
+            days: query.days,
+        },
+        format.mode,
+        Vec::new(),
+    )
+    .await?;
+
+    Ok(forecast_output.format(&format))
+}
+
+/// Routes for the public forecast API:
+///
+/// + `GET /forecast?lat=..&lon=..&format=short|long&days=..` responds with the forecast for the
+///   specified position as plain text, in the same format used for email replies. `format`
+///   defaults to `short`, and `days` defaults to all available days.
+pub fn forecast_api(
+    time: &'static dyn time::Port,
+    forecast_service: forecast_service::Failover,
+    topo_data_service: topo_data_service::Failover,
+) -> Router {
+    let forecast_service = Arc::new(forecast_service);
+    let topo_data_service = Arc::new(topo_data_service);
+
+    Router::new().route(
+        "/forecast",
+        get(move |Query(query): Query<ForecastQuery>| {
+            let forecast_service = forecast_service.clone();
+            let topo_data_service = topo_data_service.clone();
+            async move { forecast(query, time, &*forecast_service, &*topo_data_service).await }
+        }),
+    )
+}
+
+/// Routes for decoding forecasts:
+///
+/// + `POST /decode` with a plain text body containing a forecast in the short format (e.g. pasted
+///   from an inReach message) responds with the decoded forecast as JSON, with each value
+///   labelled and converted back to its units, see [`decode_short()`].
+pub fn decode_api() -> Router {
+    Router::new().route("/decode", post(decode))
+}
+
+async fn decode(message: String) -> Json<DecodedForecast> {
+    Json(decode_short(&message))
+}
+
+/// Layer which permits browsers to call the API from pages hosted on `origins` (e.g.
+/// `https://tools.example.org`), or from any origin if `origins` contains `*`. Returns an error if
+/// an origin is not of the form `scheme://host[:port]`.
+pub fn cors_layer(origins: &[String]) -> eyre::Result<CorsLayer> {
+    let allow_origin = if origins.iter().any(|origin| origin == "*") {
+        AllowOrigin::any()
+    } else {
+        let origins = origins
+            .iter()
+            .map(|origin| {
+                let url = url::Url::parse(origin)
+                    .map_err(|error| eyre::eyre!("Invalid CORS origin {:?}: {}", origin, error))?;
+                if url.origin().ascii_serialization() != *origin {
+                    return Err(eyre::eyre!(
+                        "Invalid CORS origin {:?}, expected scheme://host[:port]",
+                        origin
+                    ));
+                }
+                Ok(HeaderValue::from_str(origin)?)
+            })
+            .collect::<eyre::Result<Vec<HeaderValue>>>()?;
+        AllowOrigin::list(origins)
+    };
+    Ok(CorsLayer::new()
+        .allow_origin(allow_origin)
+        .allow_methods([Method::GET, Method::POST])
+        .allow_headers([header::AUTHORIZATION, header::CONTENT_TYPE]))
+}
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Bearer tokens which are permitted to use the API, see [`require_api_token()`]. Tokens are
+/// compared using a MAC keyed with a value generated when the application starts, so that the
+/// comparison takes constant time.
+pub struct ApiTokens {
+    key: [u8; 32],
+    macs: Vec<Vec<u8>>,
+}
+
+impl ApiTokens {
+    /// Construct [`ApiTokens`] from `tokens` separated by commas or newlines (e.g. the contents of
+    /// the `API_TOKENS` secret). Surrounding whitespace and empty tokens are ignored.
+    #[must_use]
+    pub fn new(tokens: &str) -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        let mut api_tokens = Self {
+            key,
+            macs: Vec::new(),
+        };
+        api_tokens.macs = tokens
+            .split([',', '\n'])
+            .map(str::trim)
+            .filter(|token| !token.is_empty())
+            .map(|token| {
+                let mut mac = api_tokens.mac();
+                mac.update(token.as_bytes());
+                mac.finalize().into_bytes().to_vec()
+            })
+            .collect();
+        api_tokens
+    }
+
+    /// Returns `true` if no tokens were provided, in which case no requests are permitted.
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.macs.is_empty()
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any size")
+    }
+
+    /// Returns `true` if `token` is one of the permitted tokens.
+    #[must_use]
+    pub fn is_valid(&self, token: &str) -> bool {
+        self.macs.iter().any(|expected| {
+            let mut mac = self.mac();
+            mac.update(token.as_bytes());
+            mac.verify_slice(expected).is_ok()
+        })
+    }
+}
+
+/// Middleware which requires an `Authorization: Bearer <token>` header with one of the
+/// [`ApiTokens`], otherwise responds with `401 Unauthorized`.
+pub async fn require_api_token<B>(
+    State(tokens): State<Arc<ApiTokens>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let authorized = request
+        .headers()
+        .get(header::AUTHORIZATION)
+        .and_then(|value| value.to_str().ok())
+        .and_then(|value| value.strip_prefix("Bearer "))
+        .map_or(false, |token| tokens.is_valid(token.trim()));
+
+    if authorized {
+        next.run(request).await
+    } else {
+        (
+            StatusCode::UNAUTHORIZED,
+            [(header::WWW_AUTHENTICATE, "Bearer")],
+            "A valid API token is required",
+        )
+            .into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        cors_layer, decode, validate_position, validate_query, ApiTokens, ForecastQuery, Format,
+    };
+
+    fn query(lat: f32, lon: f32, days: Option<u8>) -> ForecastQuery {
+        ForecastQuery {
+            lat,
+            lon,
+            format: Some(Format::Short),
+            days,
+        }
+    }
+
+    #[test]
+    fn test_validate_query() {
+        assert!(validate_query(&query(-43.5, 170.3, None)).is_ok());
+        assert!(validate_query(&query(-43.5, 170.3, Some(3))).is_ok());
+        assert!(validate_query(&query(-91.0, 170.3, None)).is_err());
+        assert!(validate_query(&query(-43.5, 181.0, None)).is_err());
+        assert!(validate_query(&query(-43.5, 170.3, Some(0))).is_err());
+        assert!(validate_query(&query(-43.5, 170.3, Some(17))).is_err());
+        assert!(validate_position(90.0, -180.0).is_ok());
+        assert!(validate_position(f32::NAN, 170.3).is_err());
+    }
+
+    #[test]
+    fn test_api_tokens() {
+        let tokens = ApiTokens::new("first, second\nthird\n\n");
+        assert!(tokens.is_valid("first"));
+        assert!(tokens.is_valid("second"));
+        assert!(tokens.is_valid("third"));
+        assert!(!tokens.is_valid("fourth"));
+        assert!(!tokens.is_valid(""));
+        assert!(ApiTokens::new(" \n").is_empty());
+    }
+
+    #[test]
+    fn test_cors_layer() {
+        let origins =
+            |origins: &[&str]| -> Vec<String> { origins.iter().map(ToString::to_string).collect() };
+        assert!(cors_layer(&origins(&[])).is_ok());
+        assert!(cors_layer(&origins(&["*"])).is_ok());
+        assert!(cors_layer(&origins(&[
+            "https://tools.example.org",
+            "http://localhost:8080"
+        ]))
+        .is_ok());
+        assert!(cors_layer(&origins(&["https://tools.example.org/"])).is_err());
+        assert!(cors_layer(&origins(&["tools.example.org"])).is_err());
+    }
+
+    #[tokio::test]
+    async fn test_decode_api() {
+        let decoded = decode("TzGMT FE33 TE34\n04T03 C3 F7 W1g2@3 P0".to_owned())
+            .await
+            .0;
+        let decoded = serde_json::to_value(decoded).unwrap();
+        assert_eq!(33.0, decoded["header"]["forecast_elevation"]);
+        let row = &decoded["rows"][0];
+        assert_eq!(4, row["day"]);
+        assert_eq!(3, row["hour"]);
+        assert_eq!("wind", row["parameters"][2]["parameter"]);
+        assert_eq!(20.0, row["parameters"][2]["gusts_kmh"]);
+    }
+}
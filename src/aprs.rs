@@ -0,0 +1,554 @@
+//! An optional transport for amateur radio operators, which listens on
+//! [APRS-IS](http://www.aprs-is.net/) for messages addressed to
+//! [`AprsOptions::callsign`] containing a forecast request (e.g. `-43.5,170.3 FLY`), and replies
+//! with the short format forecast as APRS messages, see [`serve_aprs()`].
+
+use std::collections::VecDeque;
+
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::TcpStream,
+    sync::watch,
+};
+
+use crate::{
+    aviation_weather,
+    circuit_breaker::CircuitBreakerPort,
+    flood,
+    forecast::{ForecastError, ForecastService},
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions, ShortFormatDetail},
+    options::DynamicOptions,
+    quota::Quotas,
+    request::ForecastRequest,
+    task::{run_retry_log_errors, TaskContext},
+    topo_data_service,
+};
+
+/// Maximum length of the text of an APRS message.
+pub const MAX_MESSAGE_LENGTH: usize = 67;
+
+/// Destination (tocall) of the packets sent, from the range reserved for experimental software.
+const TOCALL: &str = "APZEWX";
+
+/// Number of recently received message ids remembered, so that messages which are retransmitted
+/// (because the acknowledgement was lost) are only processed once.
+const RECENT_MESSAGES: usize = 64;
+
+/// The connection is considered to be lost if nothing (including the server's periodic comments)
+/// has been received for this long.
+const READ_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(120);
+
+/// Options for the APRS-IS transport, specified in [`crate::options::Options::aprs`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct AprsOptions {
+    /// Callsign (optionally with an SSID) that requests are sent to, and replies are sent from,
+    /// e.g. `ZL4ABC-10`. This must be the licensed callsign of the operator of the service.
+    pub callsign: String,
+    /// Address (`host:port`) of the APRS-IS server.
+    ///
+    /// Default is `rotate.aprs2.net:14580`.
+    #[serde(default = "default_server")]
+    pub server: String,
+    /// Maximum number of APRS messages sent in reply to each request, each containing up to
+    /// [`MAX_MESSAGE_LENGTH`] characters of the forecast.
+    ///
+    /// Default is `3`.
+    #[serde(default = "default_max_messages")]
+    pub max_messages: usize,
+}
+
+fn default_server() -> String {
+    "rotate.aprs2.net:14580".to_owned()
+}
+
+fn default_max_messages() -> usize {
+    3
+}
+
+/// Calculate the APRS-IS passcode for `callsign`, which is required to send packets. The SSID is
+/// ignored.
+#[must_use]
+pub fn passcode(callsign: &str) -> u16 {
+    let call = callsign
+        .split('-')
+        .next()
+        .unwrap_or_default()
+        .to_uppercase();
+    let mut hash: u16 = 0x73e2;
+    for (i, byte) in call.bytes().enumerate() {
+        if i % 2 == 0 {
+            hash ^= u16::from(byte) << 8;
+        } else {
+            hash ^= u16::from(byte);
+        }
+    }
+    hash & 0x7fff
+}
+
+/// An APRS message received from APRS-IS.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Message {
+    /// Callsign of the sender.
+    pub source: String,
+    /// Callsign that the message is addressed to.
+    pub addressee: String,
+    /// Text of the message.
+    pub text: String,
+    /// Message id, which needs to be acknowledged, `None` if the message does not require an
+    /// acknowledgement.
+    pub id: Option<String>,
+}
+
+impl Message {
+    /// Parse a message from a packet in the APRS-IS (TNC2) format, e.g.
+    /// `ZL4XYZ-7>APRS,TCPIP*::ZL4ABC-10:-43.5,170.3{42`. `None` if the packet is not a message.
+    #[must_use]
+    pub fn parse(packet: &str) -> Option<Self> {
+        let (header, info) = packet.trim_end().split_once(':')?;
+        let (source, _path) = header.split_once('>')?;
+        let info = info.strip_prefix(':')?;
+        let addressee = info.get(..9)?.trim_end();
+        let text = info.get(9..)?.strip_prefix(':')?;
+        // The id may be followed by the reply-ack of the sender, e.g. `{MM}AA`.
+        let (text, id) = match text.rsplit_once('{') {
+            Some((text, id)) => {
+                let id = id.split('}').next().unwrap_or_default();
+                (text, (!id.is_empty()).then(|| id.to_owned()))
+            }
+            None => (text, None),
+        };
+        Some(Self {
+            source: source.to_owned(),
+            addressee: addressee.to_owned(),
+            text: text.to_owned(),
+            id,
+        })
+    }
+
+    /// Whether the message acknowledges or rejects a message that was sent.
+    #[must_use]
+    pub fn is_ack(&self) -> bool {
+        self.id.is_none() && (self.text.starts_with("ack") || self.text.starts_with("rej"))
+    }
+}
+
+/// Split `text` into the text of APRS messages of up to [`MAX_MESSAGE_LENGTH`] characters,
+/// preferring to split between words. Characters which are not allowed in messages are replaced
+/// with spaces.
+#[must_use]
+pub fn split_messages(text: &str) -> Vec<String> {
+    let text: String = text
+        .chars()
+        .map(|c| {
+            if c.is_ascii_graphic() && !matches!(c, '|' | '~' | '{') {
+                c
+            } else {
+                ' '
+            }
+        })
+        .collect();
+    let mut messages: Vec<String> = Vec::new();
+    let mut current = String::new();
+    for word in text.split_whitespace() {
+        for part in word.as_bytes().chunks(MAX_MESSAGE_LENGTH) {
+            let part = std::str::from_utf8(part).expect("text is ascii");
+            if !current.is_empty() && current.len() + 1 + part.len() > MAX_MESSAGE_LENGTH {
+                messages.push(std::mem::take(&mut current));
+            }
+            if !current.is_empty() {
+                current.push(' ');
+            }
+            current.push_str(part);
+        }
+    }
+    if !current.is_empty() {
+        messages.push(current);
+    }
+    messages
+}
+
+/// Responds to the messages addressed to [`AprsOptions::callsign`], see [`Station::handle()`].
+#[derive(Debug)]
+pub struct Station {
+    callsign: String,
+    max_messages: usize,
+    recent: VecDeque<(String, String)>,
+}
+
+impl Station {
+    /// Construct a new [`Station`].
+    #[must_use]
+    pub fn new(options: &AprsOptions) -> Self {
+        Self {
+            callsign: options.callsign.to_uppercase(),
+            max_messages: options.max_messages.max(1),
+            recent: VecDeque::with_capacity(RECENT_MESSAGES),
+        }
+    }
+
+    /// The line sent to log in to the APRS-IS server, which subscribes to the messages addressed
+    /// to the station.
+    #[must_use]
+    pub fn login(&self) -> String {
+        format!(
+            "user {} pass {} vers email-weather {} filter g/{}\r\n",
+            self.callsign,
+            passcode(&self.callsign),
+            env!("CARGO_PKG_VERSION"),
+            self.callsign
+        )
+    }
+
+    /// A packet containing a message with `text` addressed to `addressee`.
+    fn packet(&self, addressee: &str, text: &str) -> String {
+        format!(
+            "{}>{},TCPIP*::{:<9}:{}\r\n",
+            self.callsign, TOCALL, addressee, text
+        )
+    }
+
+    /// Whether the message has been processed recently, otherwise remember it.
+    fn is_duplicate(&mut self, message: &Message) -> bool {
+        let key = match &message.id {
+            Some(id) => (message.source.clone(), id.clone()),
+            None => return false,
+        };
+        if self.recent.contains(&key) {
+            return true;
+        }
+        if self.recent.len() == RECENT_MESSAGES {
+            self.recent.pop_front();
+        }
+        self.recent.push_back(key);
+        false
+    }
+
+    /// Restrict the format to the short format, within the length of the messages sent in reply.
+    fn transform_format(&self, mut format: FormatForecastOptions) -> FormatForecastOptions {
+        let max_length = self.max_messages * MAX_MESSAGE_LENGTH;
+        let length_limit = match &format.detail {
+            FormatDetail::Short(short) => short
+                .length_limit
+                .map_or(max_length, |limit| limit.min(max_length)),
+            FormatDetail::Long(_) => max_length,
+        };
+        format.detail = FormatDetail::Short(ShortFormatDetail {
+            length_limit: Some(length_limit),
+        });
+        format
+    }
+
+    /// Handle a `packet` received at `now`, returning the packets to send in reply: an
+    /// acknowledgement (if required) followed by the forecast for the request in the message.
+    pub async fn handle(
+        &mut self,
+        packet: &str,
+        service: &ForecastService<'_>,
+        quotas: &Quotas,
+        now: DateTime<Utc>,
+    ) -> Vec<String> {
+        let message = match Message::parse(packet) {
+            Some(message)
+                if message.addressee.eq_ignore_ascii_case(&self.callsign) && !message.is_ack() =>
+            {
+                message
+            }
+            _ => return Vec::new(),
+        };
+        let mut packets = Vec::new();
+        if let Some(id) = &message.id {
+            packets.push(self.packet(&message.source, &format!("ack{}", id)));
+        }
+        if self.is_duplicate(&message) {
+            tracing::debug!("Ignoring duplicate message from {}", message.source);
+            return packets;
+        }
+        tracing::info!("Received APRS message from {}", message.source);
+
+        let reply = if let Err(error) = quotas.check(None, now) {
+            tracing::warn!("Request refused: {}", error);
+            format!("{}, please try again later", error)
+        } else {
+            let (request, errors) = ForecastRequest::parse(&message.text);
+            let errors = errors
+                .into_iter()
+                .map(|error| format!("Error parsing request: {}", error))
+                .collect();
+            match service
+                .forecast_with(&request, None, errors, |format| {
+                    self.transform_format(format)
+                })
+                .await
+            {
+                Ok(forecast) => forecast.message,
+                Err(ForecastError::NoPosition) => {
+                    "Include a position in the request, e.g. -43.5,170.3".to_owned()
+                }
+                Err(error) => {
+                    tracing::error!("Error obtaining forecast for APRS message: {:?}", error);
+                    "Forecast is unavailable, please try again later".to_owned()
+                }
+            }
+        };
+        packets.extend(
+            split_messages(&reply)
+                .into_iter()
+                .take(self.max_messages)
+                .map(|text| self.packet(&message.source, &text)),
+        );
+        packets
+    }
+}
+
+/// Connect to the APRS-IS server and respond to messages until the connection is lost.
+async fn serve_aprs_impl(
+    options: &AprsOptions,
+    forecast_service: &dyn forecast_service::Port,
+    topo_data_service: &dyn topo_data_service::Port,
+    aviation_weather: &dyn aviation_weather::Port,
+    flood: &dyn flood::Port,
+    options_rx: &watch::Receiver<DynamicOptions>,
+    context: &TaskContext,
+) -> eyre::Result<()> {
+    let stream = TcpStream::connect(&options.server)
+        .await
+        .wrap_err_with(|| format!("Error connecting to APRS-IS server {}", options.server))?;
+    let (reader, mut writer) = stream.into_split();
+    let mut reader = BufReader::new(reader);
+    let mut station = Station::new(options);
+    writer
+        .write_all(station.login().as_bytes())
+        .await
+        .wrap_err("Error logging in to APRS-IS server")?;
+    tracing::info!("Connected to APRS-IS server {}", options.server);
+
+    let mut buffer = Vec::new();
+    loop {
+        buffer.clear();
+        let read = tokio::time::timeout(READ_TIMEOUT, reader.read_until(b'\n', &mut buffer))
+            .await
+            .map_err(|_| {
+                eyre::eyre!(
+                    "Nothing received from APRS-IS server for {:?}",
+                    READ_TIMEOUT
+                )
+            })?
+            .wrap_err("Error reading from APRS-IS server")?;
+        if read == 0 {
+            return Err(eyre::eyre!("APRS-IS server closed the connection"));
+        }
+        let line = String::from_utf8_lossy(&buffer);
+        if let Some(comment) = line.strip_prefix('#') {
+            if comment.contains("logresp") && comment.contains(" unverified") {
+                return Err(eyre::eyre!(
+                    "APRS-IS server did not verify the login for {}",
+                    options.callsign
+                ));
+            }
+            tracing::trace!("APRS-IS server: {}", comment.trim());
+            continue;
+        }
+
+        let dynamic_options = options_rx.borrow().clone();
+        let service = ForecastService::new(
+            context.time,
+            forecast_service,
+            Some(topo_data_service),
+            &dynamic_options,
+        )
+        .with_aviation_weather(Some(aviation_weather))
+        .with_flood(Some(flood));
+        let packets = station
+            .handle(&line, &service, context.quotas, context.time.utc_now())
+            .await;
+        if packets.len() > 1 {
+            context
+                .status
+                .record_forecast_processed(context.time.utc_now());
+        }
+        for packet in packets {
+            writer
+                .write_all(packet.as_bytes())
+                .await
+                .wrap_err("Error sending packet to APRS-IS server")?;
+        }
+    }
+}
+
+/// Task which listens on APRS-IS for messages addressed to [`AprsOptions::callsign`], and replies
+/// with the forecast for the request contained in each message. Reconnects (with backoff) if the
+/// connection is lost.
+pub async fn serve_aprs(
+    shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    options: &'static AprsOptions,
+    forecast_service: forecast_service::Failover,
+    topo_data_service: topo_data_service::Failover,
+    aviation_weather: aviation_weather::Gateway,
+    flood: flood::Gateway,
+    options_rx: watch::Receiver<DynamicOptions>,
+    context: TaskContext,
+) {
+    tracing::debug!("Starting APRS-IS job");
+    run_retry_log_errors(
+        move || {
+            let forecast_service = forecast_service.clone();
+            let topo_data_service = topo_data_service.clone();
+            let aviation_weather = aviation_weather.clone();
+            let flood = flood.clone();
+            let options_rx = options_rx.clone();
+            async move {
+                let forecast_service = CircuitBreakerPort::new(
+                    forecast_service,
+                    &context.circuit_breakers.forecast,
+                    context.time,
+                );
+                let topo_data_service = CircuitBreakerPort::new(
+                    topo_data_service,
+                    &context.circuit_breakers.topo_data,
+                    context.time,
+                );
+                serve_aprs_impl(
+                    options,
+                    &forecast_service,
+                    &topo_data_service,
+                    &aviation_weather,
+                    &flood,
+                    &options_rx,
+                    &context,
+                )
+                .await
+            }
+        },
+        shutdown_rx,
+        context.backoff,
+        context.time,
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod test {
+    use once_cell::sync::Lazy;
+    use open_meteo::Forecast;
+    use tokio::sync::watch;
+
+    use crate::{
+        forecast::ForecastService,
+        forecast_service::{self, ObtainedForecast},
+        options::DynamicOptions,
+        quota::Quotas,
+    };
+
+    use super::{passcode, split_messages, AprsOptions, Message, Station, MAX_MESSAGE_LENGTH};
+
+    static FORECAST_MT_COOK: Lazy<Forecast> = Lazy::new(|| {
+        serde_json::from_str(&std::fs::read_to_string("fixtures/forecast_mt_cook.json").unwrap())
+            .unwrap()
+    });
+
+    #[test]
+    fn test_passcode() {
+        assert_eq!(13023, passcode("N0CALL"));
+        assert_eq!(13023, passcode("n0call-10"));
+    }
+
+    #[test]
+    fn test_parse_message() {
+        let message =
+            Message::parse("ZL4XYZ-7>APRS,TCPIP*,qAC,T2TEST::EWX      :-43.5,170.3{42\r\n")
+                .unwrap();
+        assert_eq!(
+            Message {
+                source: "ZL4XYZ-7".to_owned(),
+                addressee: "EWX".to_owned(),
+                text: "-43.5,170.3".to_owned(),
+                id: Some("42".to_owned()),
+            },
+            message
+        );
+        assert!(!message.is_ack());
+
+        let message = Message::parse("ZL4XYZ-7>APRS::EWX      :-43.5,170.3 FLY{AB}CD").unwrap();
+        assert_eq!("-43.5,170.3 FLY", message.text);
+        assert_eq!(Some("AB"), message.id.as_deref());
+
+        let message = Message::parse("ZL4XYZ-7>APRS::EWX      :ack42").unwrap();
+        assert!(message.is_ack());
+
+        // Position report.
+        assert!(Message::parse("ZL4XYZ-7>APRS:!4330.00S/17018.00E>").is_none());
+    }
+
+    #[test]
+    fn test_split_messages() {
+        assert_eq!(
+            vec!["03T21 C2 W1@32 P0 04T00 C5"],
+            split_messages("03T21 C2 W1@32 P0\n04T00 C5|~")
+        );
+
+        let words = "ABCDEFGHIJ ".repeat(10);
+        let messages = split_messages(&words);
+        assert_eq!(2, messages.len());
+        assert_eq!(words[..65], messages[0]);
+        assert_eq!(words[66..109], messages[1]);
+
+        let word = "A".repeat(70);
+        assert_eq!(
+            vec![word[..MAX_MESSAGE_LENGTH].to_owned(), "AAA".to_owned()],
+            split_messages(&word)
+        );
+    }
+
+    #[tokio::test]
+    async fn test_handle() {
+        let mut time = crate::time::MockPort::new();
+        time.expect_utc_now()
+            .returning(|| "2022-12-03T08:00:00Z".parse().unwrap());
+        let mut forecast_service = forecast_service::MockPort::new();
+        forecast_service
+            .expect_obtain_forecast()
+            .times(1)
+            .returning(|_| Ok(ObtainedForecast::fixture(FORECAST_MT_COOK.clone())));
+        let options = DynamicOptions::default();
+        let service = ForecastService::new(&time, &forecast_service, None, &options);
+        let quotas = Quotas::new(watch::channel(DynamicOptions::default()).1);
+        let mut station = Station::new(&AprsOptions {
+            callsign: "ewx".to_owned(),
+            server: String::new(),
+            max_messages: 2,
+        });
+        let now = "2022-12-03T08:00:00Z".parse().unwrap();
+        let packet = "ZL4XYZ-7>APRS,TCPIP*::EWX      :-43.5,170.3 ML{42";
+
+        let packets = station.handle(packet, &service, &quotas, now).await;
+        assert_eq!(3, packets.len(), "{:?}", packets);
+        assert_eq!("EWX>APZEWX,TCPIP*::ZL4XYZ-7 :ack42\r\n", packets[0]);
+        for packet in &packets[1..] {
+            let text = packet
+                .strip_prefix("EWX>APZEWX,TCPIP*::ZL4XYZ-7 :")
+                .unwrap()
+                .trim_end();
+            assert!(text.len() <= MAX_MESSAGE_LENGTH, "{}", text);
+        }
+
+        // Retransmitted message is acknowledged but not processed again.
+        let packets = station.handle(packet, &service, &quotas, now).await;
+        assert_eq!(vec!["EWX>APZEWX,TCPIP*::ZL4XYZ-7 :ack42\r\n"], packets);
+
+        // Messages addressed to other stations are ignored.
+        let packets = station
+            .handle(
+                "ZL4XYZ-7>APRS::OTHER    :-43.5,170.3{43",
+                &service,
+                &quotas,
+                now,
+            )
+            .await;
+        assert!(packets.is_empty());
+    }
+}
@@ -0,0 +1,261 @@
+//! Archiving the raw RFC822 messages which are received, so that they can later be reinjected into
+//! the process queue with the `replay` subcommand (see [`replay()`]), e.g. to debug a parser
+//! regression against real-world emails, see [`EmailArchive`].
+
+use std::path::{Path, PathBuf};
+
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
+use eyre::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+use crate::{
+    correlation::{Queued, RequestId},
+    fs,
+    options::{DynamicOptions, Options},
+    queue::QueueCipher,
+    receive::accept_message,
+};
+
+/// Format of the time at the start of the name of each archived message, which sorts
+/// chronologically.
+const TIMESTAMP_FORMAT: &str = "%Y%m%dT%H%M%S%.3fZ";
+
+/// Options for archiving received emails, see [`Options::email_archive`].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct EmailArchiveOptions {
+    /// Archived messages received more than this many days ago are removed.
+    ///
+    /// Default is `Some(30)`.
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: Option<u64>,
+    /// The oldest archived messages are removed until there are at most this many.
+    ///
+    /// Default is `Some(1000)`.
+    #[serde(default = "default_max_messages")]
+    pub max_messages: Option<usize>,
+}
+
+fn default_max_age_days() -> Option<u64> {
+    Some(30)
+}
+
+fn default_max_messages() -> Option<usize> {
+    Some(1000)
+}
+
+impl Default for EmailArchiveOptions {
+    fn default() -> Self {
+        Self {
+            max_age_days: default_max_age_days(),
+            max_messages: default_max_messages(),
+        }
+    }
+}
+
+impl Options {
+    /// The directory in which received messages are archived.
+    #[must_use]
+    pub fn email_archive_dir(&self) -> PathBuf {
+        self.data_dir.join("archive")
+    }
+}
+
+/// Archives the raw RFC822 messages received by [`crate::receive::receive_emails()`] as `.eml`
+/// files in a directory, removing old messages according to [`EmailArchiveOptions`].
+#[derive(Debug)]
+pub struct EmailArchive {
+    dir: PathBuf,
+    options: EmailArchiveOptions,
+}
+
+impl EmailArchive {
+    /// Construct a new [`EmailArchive`] which archives messages in `dir` (created if it does not
+    /// exist).
+    pub fn new(dir: &Path, options: EmailArchiveOptions) -> eyre::Result<Self> {
+        fs::create_dir_if_not_exists(dir)?;
+        Ok(Self {
+            dir: dir.to_owned(),
+            options,
+        })
+    }
+
+    /// Archive `rfc822_body`, which was `received_at` the specified time, then remove any archived
+    /// messages which violate the retention options. Returns the path of the archived message.
+    pub async fn archive(
+        &self,
+        rfc822_body: &[u8],
+        received_at: DateTime<Utc>,
+    ) -> eyre::Result<PathBuf> {
+        let file_name = format!(
+            "{}-{}.eml",
+            received_at.format(TIMESTAMP_FORMAT),
+            Uuid::new_v4()
+        );
+        let path = self.dir.join(file_name);
+        tokio::fs::write(&path, rfc822_body)
+            .await
+            .wrap_err_with(|| format!("Error archiving message to {:?}", path))?;
+        self.prune(received_at).await?;
+        Ok(path)
+    }
+
+    /// Remove the archived messages selected by [`select_pruned()`].
+    async fn prune(&self, now: DateTime<Utc>) -> eyre::Result<()> {
+        let mut entries = tokio::fs::read_dir(&self.dir)
+            .await
+            .wrap_err_with(|| format!("Error reading archive directory {:?}", self.dir))?;
+        let mut file_names = Vec::new();
+        while let Some(entry) = entries.next_entry().await? {
+            if let Some(file_name) = entry.file_name().to_str() {
+                if file_name.ends_with(".eml") {
+                    file_names.push(file_name.to_owned());
+                }
+            }
+        }
+
+        for file_name in select_pruned(file_names, &self.options, now) {
+            let path = self.dir.join(file_name);
+            tokio::fs::remove_file(&path)
+                .await
+                .wrap_err_with(|| format!("Error removing archived message {:?}", path))?;
+            tracing::debug!("Removed archived message {:?}", path);
+        }
+        Ok(())
+    }
+}
+
+/// The time at which the archived message with `file_name` was received.
+fn received_at(file_name: &str) -> Option<DateTime<Utc>> {
+    let (timestamp, _) = file_name.split_once('-')?;
+    NaiveDateTime::parse_from_str(timestamp, TIMESTAMP_FORMAT)
+        .ok()
+        .map(|timestamp| Utc.from_utc_datetime(&timestamp))
+}
+
+/// Select the names of the archived messages which should be removed according to `options`.
+fn select_pruned(
+    mut file_names: Vec<String>,
+    options: &EmailArchiveOptions,
+    now: DateTime<Utc>,
+) -> Vec<String> {
+    // Newest messages first.
+    file_names.sort_unstable_by(|a, b| b.cmp(a));
+
+    let max_age_secs = options.max_age_days.map(|days| days * 24 * 60 * 60);
+    file_names
+        .into_iter()
+        .enumerate()
+        .filter(|(i, file_name)| {
+            let too_many = options.max_messages.map_or(false, |max| *i >= max);
+            let too_old = max_age_secs.map_or(false, |max_age_secs| {
+                received_at(file_name).map_or(false, |received_at| {
+                    u64::try_from((now - received_at).num_seconds())
+                        .map_or(false, |age_secs| age_secs > max_age_secs)
+                })
+            });
+            too_many || too_old
+        })
+        .map(|(_, file_name)| file_name)
+        .collect()
+}
+
+/// Reinject the message stored in the RFC822 file at `path` (e.g. a message archived by
+/// [`EmailArchive`]) into the process queue (encrypted using `queue_cipher` if specified), as
+/// though it had just been received. Returns the [`RequestId`] of the queued request, or an error
+/// if the message was rejected.
+pub async fn replay(
+    path: &Path,
+    options: &Options,
+    queue_cipher: Option<&'static QueueCipher>,
+) -> eyre::Result<RequestId> {
+    let rfc822_body = tokio::fs::read(path)
+        .await
+        .wrap_err_with(|| format!("Error reading message from {:?}", path))?;
+    let email = accept_message(&rfc822_body, &DynamicOptions::from(options))
+        .map_err(eyre::Error::from)
+        .wrap_err_with(|| format!("Message {:?} was not accepted", path))?;
+
+    let request_id = RequestId::new();
+    let email_data =
+        serde_json::to_vec(&Queued::new(request_id, &email).with_queued_at(Utc::now()))
+            .wrap_err("Error serializing email data to json bytes")?;
+    options
+        .queue
+        .open_sender(&options.data_dir, "process")?
+        .with_cipher(queue_cipher)
+        .send(&email_data)
+        .await
+        .wrap_err("Error submitting email data to process queue")?;
+    Ok(request_id)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::time::test_now as now;
+
+    use super::{received_at, select_pruned, EmailArchive, EmailArchiveOptions};
+
+    #[test]
+    fn test_select_pruned() {
+        let file_names: Vec<String> = [
+            "20221203T095900.000Z-b.eml",
+            "20221101T100000.000Z-a.eml",
+            "20221203T095800.500Z-c.eml",
+            "20221203T095700.000Z-d.eml",
+        ]
+        .into_iter()
+        .map(ToOwned::to_owned)
+        .collect();
+        assert_eq!(
+            Some("2022-12-03T09:58:00.500Z".parse().unwrap()),
+            received_at(&file_names[2])
+        );
+
+        let options = EmailArchiveOptions {
+            max_age_days: Some(30),
+            max_messages: None,
+        };
+        assert_eq!(
+            vec!["20221101T100000.000Z-a.eml"],
+            select_pruned(file_names.clone(), &options, now())
+        );
+
+        let options = EmailArchiveOptions {
+            max_age_days: None,
+            max_messages: Some(2),
+        };
+        assert_eq!(
+            vec!["20221203T095700.000Z-d.eml", "20221101T100000.000Z-a.eml"],
+            select_pruned(file_names, &options, now())
+        );
+    }
+
+    #[tokio::test]
+    async fn test_archive() {
+        let dir = std::env::temp_dir().join("email-weather-test-email-archive");
+        let _ = std::fs::remove_dir_all(&dir);
+        let archive = EmailArchive::new(
+            &dir,
+            EmailArchiveOptions {
+                max_age_days: Some(1),
+                max_messages: None,
+            },
+        )
+        .unwrap();
+
+        let old = archive
+            .archive(b"old", now() - Duration::days(2))
+            .await
+            .unwrap();
+        assert_eq!(b"old".as_slice(), std::fs::read(&old).unwrap());
+        let new = archive.archive(b"new", now()).await.unwrap();
+        assert!(!old.exists());
+        assert_eq!(b"new".as_slice(), std::fs::read(&new).unwrap());
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
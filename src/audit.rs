@@ -0,0 +1,180 @@
+//! An append-only log of the actions taken using the admin interface (e.g. viewing and downloading
+//! logs, or erasing a sender's data), so that operators can review who accessed what, see
+//! [`AuditLog`].
+//!
+//! Each action is appended to the file as a line of JSON (see [`AuditEntry`]). The file is kept
+//! separately from the application logs, so it is not removed by the log retention.
+
+use std::{
+    fs::{File, OpenOptions},
+    io::{BufRead, BufReader, Write},
+    net::IpAddr,
+    path::{Path, PathBuf},
+    sync::Mutex,
+};
+
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use serde::{Deserialize, Serialize};
+
+/// An action taken using the admin interface.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(tag = "action", rename_all = "snake_case")]
+pub enum AuditAction {
+    /// Successful login.
+    Login,
+    /// Failed login attempt (invalid username or password).
+    LoginFailed,
+    /// Logout, ending the session.
+    Logout,
+    /// A request made using an admin session (e.g. `GET /logs/bundle.tar.gz`).
+    Request {
+        /// The http method.
+        method: String,
+        /// Path (and query) of the request.
+        path: String,
+        /// Status code of the response.
+        status: u16,
+    },
+}
+
+impl std::fmt::Display for AuditAction {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            AuditAction::Login => f.write_str("login"),
+            AuditAction::LoginFailed => f.write_str("failed login"),
+            AuditAction::Logout => f.write_str("logout"),
+            AuditAction::Request {
+                method,
+                path,
+                status,
+            } => write!(f, "{} {} ({})", method, path, status),
+        }
+    }
+}
+
+/// An entry in the [`AuditLog`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct AuditEntry {
+    /// When the action was taken.
+    pub timestamp: DateTime<Utc>,
+    /// IP address of the client, if known.
+    pub ip: Option<IpAddr>,
+    /// The action taken.
+    #[serde(flatten)]
+    pub action: AuditAction,
+}
+
+/// Append-only log of admin actions, stored as lines of JSON in a file, see the
+/// [module documentation](self).
+pub struct AuditLog {
+    path: PathBuf,
+    file: Mutex<File>,
+}
+
+impl AuditLog {
+    /// Open (or create) the audit log file at `path`, entries are appended to the end of the
+    /// existing file.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        let file = OpenOptions::new()
+            .create(true)
+            .append(true)
+            .open(path)
+            .wrap_err_with(|| format!("Unable to open audit log {:?}", path))?;
+        Ok(Self {
+            path: path.to_owned(),
+            file: Mutex::new(file),
+        })
+    }
+
+    /// Append `action` taken by the client at `ip` to the log. Errors are logged rather than
+    /// returned, so that they do not prevent the action.
+    pub fn record(&self, ip: Option<IpAddr>, action: AuditAction) {
+        let entry = AuditEntry {
+            timestamp: Utc::now(),
+            ip,
+            action,
+        };
+        if let Err(error) = self.append(&entry) {
+            tracing::error!("Error recording {:?} in audit log: {:?}", entry, error);
+        }
+    }
+
+    fn append(&self, entry: &AuditEntry) -> eyre::Result<()> {
+        let mut line = serde_json::to_string(entry)?;
+        line.push('\n');
+        let mut file = self.file.lock().expect("mutex is poisoned");
+        file.write_all(line.as_bytes())?;
+        file.flush()?;
+        Ok(())
+    }
+
+    /// The most recent `limit` entries in the log, newest first. Lines which cannot be parsed
+    /// are skipped.
+    pub fn recent(&self, limit: usize) -> eyre::Result<Vec<AuditEntry>> {
+        let file = File::open(&self.path)
+            .wrap_err_with(|| format!("Unable to open audit log {:?}", self.path))?;
+        let lines = BufReader::new(file)
+            .lines()
+            .collect::<Result<Vec<String>, _>>()
+            .wrap_err("Error reading audit log")?;
+        Ok(lines
+            .iter()
+            .rev()
+            .filter_map(|line| match serde_json::from_str(line) {
+                Ok(entry) => Some(entry),
+                Err(error) => {
+                    tracing::warn!("Skipping invalid audit log entry {:?}: {}", line, error);
+                    None
+                }
+            })
+            .take(limit)
+            .collect())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr};
+
+    use super::{AuditAction, AuditLog};
+
+    #[test]
+    fn test_record_recent() {
+        let dir = std::env::temp_dir().join("email-weather-test-audit-log");
+        std::fs::create_dir_all(&dir).unwrap();
+        let path = dir.join("audit.jsonl");
+        let _ = std::fs::remove_file(&path);
+        let ip = Some(IpAddr::V4(Ipv4Addr::LOCALHOST));
+
+        let audit_log = AuditLog::open(&path).unwrap();
+        audit_log.record(ip, AuditAction::Login);
+        audit_log.record(
+            ip,
+            AuditAction::Request {
+                method: "GET".to_owned(),
+                path: "/logs/bundle.tar.gz".to_owned(),
+                status: 200,
+            },
+        );
+        drop(audit_log);
+
+        // Entries are appended to the existing file.
+        let audit_log = AuditLog::open(&path).unwrap();
+        audit_log.record(None, AuditAction::Logout);
+        let entries = audit_log.recent(2).unwrap();
+        assert_eq!(2, entries.len());
+        assert_eq!(AuditAction::Logout, entries[0].action);
+        assert_eq!(None, entries[0].ip);
+        assert_eq!(
+            "GET /logs/bundle.tar.gz (200)",
+            entries[1].action.to_string()
+        );
+        assert_eq!(ip, entries[1].ip);
+
+        let contents = std::fs::read_to_string(&path).unwrap();
+        assert_eq!(3, contents.lines().count());
+        assert!(contents.contains(r#""action":"login""#), "{contents}");
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,557 @@
+//! Session based authentication for the admin interface, see [`AdminAuth`].
+
+use std::{
+    collections::HashMap,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant, SystemTime},
+};
+
+use argon2::{
+    password_hash::{rand_core::OsRng, PasswordHash, PasswordHasher, SaltString},
+    Argon2, PasswordVerifier,
+};
+use axum::{
+    extract::{Query, State},
+    http::{header, HeaderMap, Request},
+    middleware::Next,
+    response::{Html, IntoResponse, Redirect, Response},
+    routing::{get, post},
+    Form, Router,
+};
+use hmac::{Hmac, Mac};
+use html_builder::Html5;
+use rand::RngCore;
+use reqwest::StatusCode;
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+use sha2::Sha256;
+
+use crate::{
+    audit::{AuditAction, AuditLog},
+    client_ip::{client_ip, ClientIp},
+    html,
+};
+
+/// Name of the cookie used to store the admin session.
+const SESSION_COOKIE: &str = "email_weather_session";
+/// How long an admin session remains valid after logging in.
+const SESSION_DURATION: Duration = Duration::from_secs(12 * 60 * 60);
+/// Number of consecutive failed login attempts from an IP address before it is locked out.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+/// How long an IP address is locked out for after [`MAX_FAILED_ATTEMPTS`].
+const LOCKOUT_DURATION: Duration = Duration::from_secs(15 * 60);
+/// The only user permitted to access the admin interface.
+const ADMIN_USERNAME: &str = "admin";
+
+type HmacSha256 = Hmac<Sha256>;
+
+/// Verify `password` against `password_hash`. Argon2 hashes (in PHC string format, e.g.
+/// `$argon2id$...`) are preferred, bcrypt hashes (e.g. `$2b$...`) are still accepted so that
+/// existing deployments continue to work.
+pub fn verify_password(password: &str, password_hash: &str) -> bool {
+    if password_hash.starts_with("$argon2") {
+        match PasswordHash::new(password_hash) {
+            Ok(hash) => Argon2::default()
+                .verify_password(password.as_bytes(), &hash)
+                .is_ok(),
+            Err(error) => {
+                tracing::error!("Invalid argon2 admin password hash: {}", error);
+                false
+            }
+        }
+    } else {
+        tracing::warn!(
+            "Admin password hash is not an argon2 hash, bcrypt hashes are deprecated, please \
+            generate a new hash using argon2id"
+        );
+        bcrypt::verify(password, password_hash).unwrap_or(false)
+    }
+}
+
+/// Algorithm used to hash the admin password, see [`hash_password()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PasswordHashAlgorithm {
+    /// Argon2id, in PHC string format (e.g. `$argon2id$...`), which is preferred.
+    Argon2id,
+    /// bcrypt (e.g. `$2b$...`), which is deprecated, but still accepted by [`verify_password()`].
+    Bcrypt,
+}
+
+impl std::str::FromStr for PasswordHashAlgorithm {
+    type Err = eyre::Error;
+
+    fn from_str(s: &str) -> Result<Self, Self::Err> {
+        match s {
+            "argon2id" => Ok(Self::Argon2id),
+            "bcrypt" => Ok(Self::Bcrypt),
+            _ => Err(eyre::eyre!(
+                "Unknown password hash algorithm {:?}, expected one of: argon2id, bcrypt",
+                s
+            )),
+        }
+    }
+}
+
+/// Hash `password` using `algorithm` with a random salt, in the format expected for the
+/// `ADMIN_PASSWORD_HASH` secret (see [`crate::secrets::Secrets::admin_password_hash`]).
+pub fn hash_password(password: &str, algorithm: PasswordHashAlgorithm) -> eyre::Result<String> {
+    match algorithm {
+        PasswordHashAlgorithm::Argon2id => {
+            let salt = SaltString::generate(&mut OsRng);
+            Ok(Argon2::default()
+                .hash_password(password.as_bytes(), &salt)
+                .map_err(|error| eyre::eyre!("Error hashing password using argon2id: {}", error))?
+                .to_string())
+        }
+        PasswordHashAlgorithm::Bcrypt => Ok(bcrypt::hash(password, bcrypt::DEFAULT_COST)?),
+    }
+}
+
+#[derive(Debug, Default)]
+struct FailedAttempts {
+    count: u32,
+    locked_until: Option<Instant>,
+}
+
+/// Authentication state for the admin interface. Sessions are stored in a cookie signed using a
+/// key generated when the application starts, so sessions are invalidated by a restart.
+pub struct AdminAuth {
+    admin_password_hash: &'static SecretString,
+    key: [u8; 32],
+    secure_cookie: bool,
+    failed_attempts: Mutex<HashMap<IpAddr, FailedAttempts>>,
+    audit_log: Option<AuditLog>,
+}
+
+impl AdminAuth {
+    /// Construct a new [`AdminAuth`].
+    ///
+    /// + `admin_password_hash` is the `admin` user password hashed using argon2 or bcrypt.
+    /// + `secure_cookie` sets the `Secure` attribute on the session cookie, which should be used
+    ///   when the admin interface is served over https.
+    pub fn new(admin_password_hash: &'static SecretString, secure_cookie: bool) -> Self {
+        let mut key = [0u8; 32];
+        rand::thread_rng().fill_bytes(&mut key);
+        Self {
+            admin_password_hash,
+            key,
+            secure_cookie,
+            failed_attempts: Mutex::new(HashMap::new()),
+            audit_log: None,
+        }
+    }
+
+    /// Record logins, logouts and the requests made using an admin session in `audit_log`.
+    #[must_use]
+    pub fn with_audit_log(mut self, audit_log: AuditLog) -> Self {
+        self.audit_log = Some(audit_log);
+        self
+    }
+
+    /// The log of admin actions, if enabled using [`AdminAuth::with_audit_log()`].
+    pub fn audit_log(&self) -> Option<&AuditLog> {
+        self.audit_log.as_ref()
+    }
+
+    fn audit(&self, ip: Option<IpAddr>, action: AuditAction) {
+        if let Some(audit_log) = &self.audit_log {
+            audit_log.record(ip, action);
+        }
+    }
+
+    fn mac(&self) -> HmacSha256 {
+        HmacSha256::new_from_slice(&self.key).expect("HMAC accepts keys of any size")
+    }
+
+    /// Create a session token which expires at `expires` (seconds since the unix epoch).
+    fn session_token(&self, expires: u64) -> String {
+        let mut mac = self.mac();
+        mac.update(format!("{}.{}", ADMIN_USERNAME, expires).as_bytes());
+        let signature = base64::encode_config(mac.finalize().into_bytes(), base64::URL_SAFE_NO_PAD);
+        format!("{}.{}", expires, signature)
+    }
+
+    /// Returns `true` if `token` was created by [`AdminAuth::session_token()`] and has not expired.
+    fn is_valid_session(&self, token: &str, now: SystemTime) -> bool {
+        let (expires, signature) = match token.split_once('.') {
+            Some(parts) => parts,
+            None => return false,
+        };
+        let expires: u64 = match expires.parse() {
+            Ok(expires) => expires,
+            Err(_) => return false,
+        };
+        let signature = match base64::decode_config(signature, base64::URL_SAFE_NO_PAD) {
+            Ok(signature) => signature,
+            Err(_) => return false,
+        };
+
+        let mut mac = self.mac();
+        mac.update(format!("{}.{}", ADMIN_USERNAME, expires).as_bytes());
+        if mac.verify_slice(&signature).is_err() {
+            return false;
+        }
+
+        unix_seconds(now) < expires
+    }
+
+    /// Returns the time until `ip` is permitted to attempt to login again, if it is locked out.
+    fn locked_out(&self, ip: IpAddr, now: Instant) -> Option<Duration> {
+        let failed_attempts = self.failed_attempts.lock().expect("mutex is poisoned");
+        failed_attempts
+            .get(&ip)
+            .and_then(|attempts| attempts.locked_until)
+            .and_then(|locked_until| locked_until.checked_duration_since(now))
+            .filter(|remaining| !remaining.is_zero())
+    }
+
+    fn record_failure(&self, ip: IpAddr, now: Instant) {
+        let mut failed_attempts = self.failed_attempts.lock().expect("mutex is poisoned");
+        let attempts = failed_attempts.entry(ip).or_default();
+        if attempts
+            .locked_until
+            .map_or(false, |locked_until| locked_until <= now)
+        {
+            *attempts = FailedAttempts::default();
+        }
+        attempts.count += 1;
+        if attempts.count >= MAX_FAILED_ATTEMPTS {
+            tracing::warn!(
+                "Locking out {} from admin login after {} failed attempts",
+                ip,
+                attempts.count
+            );
+            attempts.locked_until = Some(now + LOCKOUT_DURATION);
+        }
+    }
+
+    fn record_success(&self, ip: IpAddr) {
+        let mut failed_attempts = self.failed_attempts.lock().expect("mutex is poisoned");
+        failed_attempts.remove(&ip);
+    }
+
+    fn session_cookie(&self, value: &str, max_age: Duration) -> String {
+        let secure = if self.secure_cookie { "; Secure" } else { "" };
+        format!(
+            "{}={}; Path=/; Max-Age={}; HttpOnly; SameSite=Strict{}",
+            SESSION_COOKIE,
+            value,
+            max_age.as_secs(),
+            secure
+        )
+    }
+}
+
+fn unix_seconds(time: SystemTime) -> u64 {
+    time.duration_since(SystemTime::UNIX_EPOCH)
+        .map_or(0, |duration| duration.as_secs())
+}
+
+fn session_cookie_value(headers: &HeaderMap) -> Option<&str> {
+    headers
+        .get_all(header::COOKIE)
+        .iter()
+        .filter_map(|value| value.to_str().ok())
+        .flat_map(|value| value.split(';'))
+        .filter_map(|cookie| cookie.trim().split_once('='))
+        .find(|(name, _)| *name == SESSION_COOKIE)
+        .map(|(_, value)| value)
+}
+
+/// Only permit redirecting to paths on this server after login. Browsers treat `\` as `/` and
+/// ignore tabs and newlines in urls, so these are rejected to prevent redirecting to another host
+/// (e.g. with `/\example.com` or `/\t/example.com`).
+fn sanitize_next(next: Option<&str>) -> &str {
+    match next {
+        Some(next)
+            if next.starts_with('/')
+                && !matches!(next.chars().nth(1), Some('/' | '\\'))
+                && !next.chars().any(|c| c == '\\' || c.is_control()) =>
+        {
+            next
+        }
+        _ => "/logs/",
+    }
+}
+
+/// Middleware which requires a valid admin session, otherwise redirects to the login page.
+/// Authorized requests are recorded in the audit log (if enabled, see
+/// [`AdminAuth::with_audit_log()`]).
+pub async fn require_session<B>(
+    State(auth): State<Arc<AdminAuth>>,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    let authorized = session_cookie_value(request.headers()).map_or(false, |token| {
+        auth.is_valid_session(token, SystemTime::now())
+    });
+
+    if authorized {
+        let ip = client_ip(request.headers(), request.extensions());
+        let method = request.method().to_string();
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or_else(|| request.uri().path().to_owned(), ToString::to_string);
+        let response = next.run(request).await;
+        auth.audit(
+            ip,
+            AuditAction::Request {
+                method,
+                path,
+                status: response.status().as_u16(),
+            },
+        );
+        response
+    } else {
+        let path = request
+            .uri()
+            .path_and_query()
+            .map_or("/logs/", |path| path.as_str());
+        let login = format!("/admin/login?next={}", urlencoding::encode(path));
+        Redirect::to(&login).into_response()
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginQuery {
+    next: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LoginForm {
+    username: String,
+    password: SecretString,
+    next: Option<String>,
+}
+
+fn login_page(next: &str, error: Option<&str>) -> eyre::Result<Html<String>> {
+    use std::fmt::Write;
+    let mut buf = html_builder::Buffer::new();
+    let mut html = buf.html();
+    write!(html.head().title(), "email-weather admin login")?;
+    let mut body = html.body();
+
+    if let Some(error) = error {
+        let mut p = body.p();
+        write!(p, "{}", html::Escaped(error))?;
+    }
+
+    let mut form = body
+        .form()
+        .attr(r#"method="post""#)
+        .attr(r#"action="/admin/login""#);
+    let next_attr = html::attribute("value", next);
+    form.input()
+        .attr(r#"type="hidden""#)
+        .attr(r#"name="next""#)
+        .attr(&next_attr);
+    {
+        let mut label = form.label();
+        write!(label, "Username ")?;
+        label
+            .input()
+            .attr(r#"type="text""#)
+            .attr(r#"name="username""#);
+    }
+    {
+        let mut label = form.label();
+        write!(label, " Password ")?;
+        label
+            .input()
+            .attr(r#"type="password""#)
+            .attr(r#"name="password""#);
+    }
+    form.input()
+        .attr(r#"type="submit""#)
+        .attr(r#"value="Login""#);
+
+    Ok(Html::from(buf.finish()))
+}
+
+fn login_page_response(status: StatusCode, next: &str, error: Option<&str>) -> Response {
+    match login_page(next, error) {
+        Ok(html) => (status, html).into_response(),
+        Err(error) => {
+            tracing::error!("{:?}", error);
+            StatusCode::INTERNAL_SERVER_ERROR.into_response()
+        }
+    }
+}
+
+async fn login(auth: &AdminAuth, ip: IpAddr, form: LoginForm) -> Response {
+    let next = sanitize_next(form.next.as_deref()).to_string();
+
+    if let Some(remaining) = auth.locked_out(ip, Instant::now()) {
+        return login_page_response(
+            StatusCode::TOO_MANY_REQUESTS,
+            &next,
+            Some(&format!(
+                "Too many failed login attempts, try again in {} minutes",
+                remaining.as_secs() / 60 + 1
+            )),
+        );
+    }
+
+    let password_hash = auth.admin_password_hash;
+    let password_matches = tokio::task::spawn_blocking(move || {
+        verify_password(form.password.expose_secret(), password_hash.expose_secret())
+    })
+    .await
+    .unwrap_or(false);
+
+    if form.username == ADMIN_USERNAME && password_matches {
+        auth.record_success(ip);
+        auth.audit(Some(ip), AuditAction::Login);
+        tracing::info!("Successful admin login from {}", ip);
+        let expires = unix_seconds(SystemTime::now() + SESSION_DURATION);
+        let cookie = auth.session_cookie(&auth.session_token(expires), SESSION_DURATION);
+        ([(header::SET_COOKIE, cookie)], Redirect::to(&next)).into_response()
+    } else {
+        auth.record_failure(ip, Instant::now());
+        auth.audit(Some(ip), AuditAction::LoginFailed);
+        tracing::warn!("Failed admin login from {}", ip);
+        login_page_response(
+            StatusCode::UNAUTHORIZED,
+            &next,
+            Some("Invalid username or password"),
+        )
+    }
+}
+
+async fn logout(auth: &AdminAuth, ip: IpAddr) -> Response {
+    auth.audit(Some(ip), AuditAction::Logout);
+    let cookie = auth.session_cookie("", Duration::ZERO);
+    ([(header::SET_COOKIE, cookie)], Redirect::to("/admin/login")).into_response()
+}
+
+/// Routes for logging in and out of the admin interface:
+///
+/// + `GET /login` the login page.
+/// + `POST /login` submit the login form.
+/// + `POST /logout` end the admin session.
+pub fn admin_routes(auth: Arc<AdminAuth>) -> Router {
+    let login_auth = auth.clone();
+    Router::new()
+        .route(
+            "/login",
+            get(|Query(query): Query<LoginQuery>| async move {
+                login_page_response(StatusCode::OK, sanitize_next(query.next.as_deref()), None)
+            })
+            .post(move |ClientIp(ip): ClientIp, Form(form): Form<LoginForm>| {
+                let auth = login_auth.clone();
+                async move { login(&auth, ip, form).await }
+            }),
+        )
+        .route(
+            "/logout",
+            post(move |ClientIp(ip): ClientIp| {
+                let auth = auth.clone();
+                async move { logout(&auth, ip).await }
+            }),
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use std::{
+        net::{IpAddr, Ipv4Addr},
+        time::{Duration, Instant, SystemTime},
+    };
+
+    use argon2::{
+        password_hash::{PasswordHasher, SaltString},
+        Argon2,
+    };
+    use secrecy::SecretString;
+
+    use super::{
+        hash_password, sanitize_next, unix_seconds, verify_password, AdminAuth,
+        PasswordHashAlgorithm, LOCKOUT_DURATION, MAX_FAILED_ATTEMPTS,
+    };
+
+    fn auth() -> AdminAuth {
+        let hash: &'static SecretString = Box::leak(Box::new(SecretString::new(String::new())));
+        AdminAuth::new(hash, false)
+    }
+
+    #[test]
+    fn test_verify_password_argon2() {
+        let salt = SaltString::new("c29tZXNhbHQ").unwrap();
+        let hash = Argon2::default()
+            .hash_password(b"password", &salt)
+            .unwrap()
+            .to_string();
+        assert!(verify_password("password", &hash));
+        assert!(!verify_password("wrong", &hash));
+    }
+
+    #[test]
+    fn test_verify_password_bcrypt() {
+        let hash = bcrypt::hash("password", 4).unwrap();
+        assert!(verify_password("password", &hash));
+        assert!(!verify_password("wrong", &hash));
+    }
+
+    #[test]
+    fn test_hash_password() {
+        let hash = hash_password("password", PasswordHashAlgorithm::Argon2id).unwrap();
+        assert!(hash.starts_with("$argon2id$"), "{hash}");
+        assert!(verify_password("password", &hash));
+        assert!(!verify_password("wrong", &hash));
+
+        let hash = hash_password("password", PasswordHashAlgorithm::Bcrypt).unwrap();
+        assert!(hash.starts_with("$2"), "{hash}");
+        assert!(verify_password("password", &hash));
+
+        assert!("scrypt".parse::<PasswordHashAlgorithm>().is_err());
+    }
+
+    #[test]
+    fn test_session_token() {
+        let auth = auth();
+        let now = SystemTime::now();
+        let token = auth.session_token(unix_seconds(now) + 60);
+        assert!(auth.is_valid_session(&token, now));
+        assert!(!auth.is_valid_session(&token, now + Duration::from_secs(120)));
+
+        let (expires, signature) = token.split_once('.').unwrap();
+        let tampered = format!("{}.{}", expires.parse::<u64>().unwrap() + 1000, signature);
+        assert!(!auth.is_valid_session(&tampered, now));
+        assert!(!auth.is_valid_session("garbage", now));
+
+        // Tokens from a different key are not valid.
+        assert!(!self::auth().is_valid_session(&token, now));
+    }
+
+    #[test]
+    fn test_lockout() {
+        let auth = auth();
+        let ip = IpAddr::V4(Ipv4Addr::LOCALHOST);
+        let now = Instant::now();
+        for _ in 0..MAX_FAILED_ATTEMPTS - 1 {
+            auth.record_failure(ip, now);
+        }
+        assert!(auth.locked_out(ip, now).is_none());
+        auth.record_failure(ip, now);
+        assert!(auth.locked_out(ip, now).is_some());
+        assert!(auth.locked_out(ip, now + LOCKOUT_DURATION).is_none());
+
+        auth.record_success(ip);
+        assert!(auth.locked_out(ip, now).is_none());
+    }
+
+    #[test]
+    fn test_sanitize_next() {
+        assert_eq!("/logs/abc", sanitize_next(Some("/logs/abc")));
+        assert_eq!("/logs/", sanitize_next(Some("//example.com")));
+        assert_eq!("/logs/", sanitize_next(Some("https://example.com")));
+        assert_eq!("/logs/", sanitize_next(Some("/\\evil.example")));
+        assert_eq!("/logs/", sanitize_next(Some("/\t/evil.example")));
+        assert_eq!("/logs/", sanitize_next(Some("/\n/evil.example")));
+        assert_eq!("/logs/", sanitize_next(Some("/logs/\\abc")));
+        assert_eq!("/logs/", sanitize_next(Some("logs/abc")));
+        assert_eq!("/logs/", sanitize_next(None));
+    }
+}
@@ -0,0 +1,395 @@
+//! Lookup of the latest METAR (observation) and TAF (terminal aerodrome forecast) reports for an
+//! aviation weather station, requested using `METAR` (for the nearest station to the request
+//! position) or e.g. `METAR NZMC` (for a particular station), see [`lookup()`].
+//!
+//! Reports are obtained from the [NOAA Aviation Weather Center](https://aviationweather.gov/data/api/)
+//! data API (the successor to ADDS), and included in the reply verbatim.
+
+use async_trait::async_trait;
+use eyre::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format::{FormatDetail, FormatForecastOptions},
+    gis::Position,
+    process::newline,
+};
+
+/// Options for the aviation weather service, specified in [`crate::options::Options`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct AviationWeatherOptions {
+    /// Base url of the Aviation Weather Center data API.
+    ///
+    /// Default is the public API `https://aviationweather.gov`.
+    #[serde(default = "default_base_url")]
+    pub base_url: url::Url,
+    /// Distance (in degrees of latitude and longitude) from the request position that is searched
+    /// for the nearest station.
+    ///
+    /// Default is `1.0`.
+    #[serde(default = "default_search_radius_degrees")]
+    pub search_radius_degrees: f32,
+}
+
+fn default_base_url() -> url::Url {
+    "https://aviationweather.gov"
+        .parse()
+        .expect("Unable to parse url")
+}
+
+fn default_search_radius_degrees() -> f32 {
+    1.0
+}
+
+impl Default for AviationWeatherOptions {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+            search_radius_degrees: default_search_radius_degrees(),
+        }
+    }
+}
+
+/// The kind of report, see [`Port::obtain_report()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ReportKind {
+    /// Routine observation.
+    Metar,
+    /// Terminal aerodrome forecast.
+    Taf,
+}
+
+impl ReportKind {
+    /// Name of the report in the API and in the reply.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Self::Metar => "METAR",
+            Self::Taf => "TAF",
+        }
+    }
+}
+
+/// A station which produces METAR reports, see [`Port::stations_near()`].
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct Station {
+    /// ICAO identifier of the station, e.g. `NZMC`.
+    #[serde(rename = "icaoId")]
+    pub icao_id: String,
+    /// Latitude of the station.
+    pub lat: f32,
+    /// Longitude of the station.
+    pub lon: f32,
+}
+
+/// Trait used to allow mocking the aviation weather service.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait Port: Send + Sync {
+    /// Obtain the latest raw report of the `kind` for the `station`, `None` if the station has
+    /// no current report of that kind.
+    async fn obtain_report(&self, kind: ReportKind, station: &str) -> eyre::Result<Option<String>>;
+    /// Obtain the stations producing METAR reports within
+    /// [`AviationWeatherOptions::search_radius_degrees`] of `position`.
+    async fn stations_near(&self, position: Position) -> eyre::Result<Vec<Station>>;
+}
+
+/// Concrete implementation of [`Port`] using the Aviation Weather Center data API.
+#[derive(Clone)]
+pub struct Gateway {
+    http_client: reqwest::Client,
+    options: AviationWeatherOptions,
+}
+
+impl Gateway {
+    /// Construct a new [`Gateway`].
+    #[must_use]
+    pub fn new(http_client: reqwest::Client, options: AviationWeatherOptions) -> Self {
+        Self {
+            http_client,
+            options,
+        }
+    }
+
+    fn url(&self, path: &str) -> String {
+        format!(
+            "{}/api/data/{}",
+            self.options.base_url.as_str().trim_end_matches('/'),
+            path
+        )
+    }
+}
+
+#[derive(Deserialize)]
+struct StationInfo {
+    #[serde(flatten)]
+    station: Station,
+    #[serde(rename = "siteType", default)]
+    site_type: Vec<String>,
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn obtain_report(&self, kind: ReportKind, station: &str) -> eyre::Result<Option<String>> {
+        let report = self
+            .http_client
+            .get(self.url(&kind.name().to_lowercase()))
+            .query(&[("ids", station), ("format", "raw")])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err_with(|| format!("Error requesting {} for {}", kind.name(), station))?
+            .text()
+            .await
+            .wrap_err_with(|| format!("Error reading {} for {}", kind.name(), station))?;
+        let report = report.trim();
+        Ok((!report.is_empty()).then(|| report.to_owned()))
+    }
+
+    async fn stations_near(&self, position: Position) -> eyre::Result<Vec<Station>> {
+        let radius = self.options.search_radius_degrees;
+        let bbox = format!(
+            "{},{},{},{}",
+            position.latitude - radius,
+            position.longitude - radius,
+            position.latitude + radius,
+            position.longitude + radius
+        );
+        let stations: Vec<StationInfo> = self
+            .http_client
+            .get(self.url("stationinfo"))
+            .query(&[("bbox", bbox.as_str()), ("format", "json")])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err("Error requesting stations")?
+            .json()
+            .await
+            .wrap_err("Error parsing stations")?;
+        Ok(stations
+            .into_iter()
+            .filter(|info| info.site_type.iter().any(|site_type| site_type == "METAR"))
+            .map(|info| info.station)
+            .collect())
+    }
+}
+
+/// The latest reports for a station, see [`lookup()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct StationReports {
+    /// ICAO identifier of the station.
+    pub station: String,
+    /// The latest METAR, `None` if unavailable.
+    pub metar: Option<String>,
+    /// The latest TAF, `None` if unavailable.
+    pub taf: Option<String>,
+}
+
+impl StationReports {
+    /// Format the reports as a block to be appended to the forecast. The reports are included
+    /// verbatim, so reports which would exceed the length limit of a short format are omitted.
+    #[must_use]
+    pub fn format(&self, options: &FormatForecastOptions) -> String {
+        let length_limit = match &options.detail {
+            FormatDetail::Short(short) => short.length_limit,
+            FormatDetail::Long(_) => None,
+        };
+        let separator = newline(&options.detail);
+        let mut output = String::new();
+        for (kind, report) in [
+            (ReportKind::Metar, &self.metar),
+            (ReportKind::Taf, &self.taf),
+        ] {
+            let line = match report {
+                // Reports from some sources omit the report type.
+                Some(report) if report.starts_with(kind.name()) => report.clone(),
+                Some(report) => format!("{} {}", kind.name(), report),
+                None => format!("{} {} unavailable", kind.name(), self.station),
+            };
+            let separator = if output.is_empty() { "" } else { separator };
+            if let Some(length_limit) = length_limit {
+                if output.len() + separator.len() + line.len() > length_limit {
+                    break;
+                }
+            }
+            output.push_str(separator);
+            output.push_str(&line);
+        }
+        output
+    }
+}
+
+/// Lookup the latest reports for `station`, or the nearest station to `position` if `None`.
+pub async fn lookup(
+    port: &dyn Port,
+    station: Option<&str>,
+    position: Option<Position>,
+) -> eyre::Result<StationReports> {
+    let station = match station {
+        Some(station) => station.to_owned(),
+        None => {
+            let position = position
+                .ok_or_else(|| eyre::eyre!("A station or position is required for METAR"))?;
+            nearest_station(port.stations_near(position).await?, position)
+                .ok_or_else(|| eyre::eyre!("No METAR station found near {:?}", position))?
+        }
+    };
+    Ok(StationReports {
+        metar: port.obtain_report(ReportKind::Metar, &station).await?,
+        taf: port.obtain_report(ReportKind::Taf, &station).await?,
+        station,
+    })
+}
+
+/// The identifier of the station in `stations` nearest to `position`.
+fn nearest_station(stations: Vec<Station>, position: Position) -> Option<String> {
+    // Longitude is scaled so that distances are comparable away from the equator.
+    let scale = position.latitude.to_radians().cos();
+    let distance = |station: &Station| {
+        let latitude = station.lat - position.latitude;
+        let longitude = (station.lon - position.longitude) * scale;
+        latitude * latitude + longitude * longitude
+    };
+    stations
+        .into_iter()
+        .min_by(|a, b| distance(a).total_cmp(&distance(b)))
+        .map(|station| station.icao_id)
+}
+
+#[cfg(test)]
+mod test {
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use crate::{
+        format::{FormatDetail, FormatForecastOptions, ShortFormatDetail},
+        gis::Position,
+    };
+
+    use super::{
+        lookup, AviationWeatherOptions, Gateway, MockPort, Port, ReportKind, Station,
+        StationReports,
+    };
+
+    const METAR: &str = "NZMC 030800Z AUTO 27010KT 9999 NCD 12/03 Q1012";
+    const TAF: &str = "TAF NZMC 030500Z 0306/0318 27012KT 9999 FEW040";
+
+    #[tokio::test]
+    async fn test_gateway() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/api/data/metar"))
+            .and(matchers::query_param("ids", "NZMC"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(format!("{METAR}\n")))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/api/data/taf"))
+            .respond_with(ResponseTemplate::new(200).set_body_string(""))
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/api/data/stationinfo"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!([
+                { "icaoId": "NZMC", "lat": -43.76, "lon": 170.13, "siteType": ["METAR", "TAF"] },
+                { "icaoId": "NZXX", "lat": -43.5, "lon": 170.3, "siteType": ["RAOB"] },
+            ])))
+            .mount(&server)
+            .await;
+
+        let gateway = Gateway::new(
+            reqwest::Client::new(),
+            AviationWeatherOptions {
+                base_url: server.uri().parse().unwrap(),
+                ..AviationWeatherOptions::default()
+            },
+        );
+        assert_eq!(
+            Some(METAR.to_owned()),
+            gateway
+                .obtain_report(ReportKind::Metar, "NZMC")
+                .await
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            gateway
+                .obtain_report(ReportKind::Taf, "NZMC")
+                .await
+                .unwrap()
+        );
+        let stations = gateway
+            .stations_near(Position::new(-43.513832, 170.33975))
+            .await
+            .unwrap();
+        assert_eq!(
+            vec!["NZMC"],
+            stations.iter().map(|s| &s.icao_id).collect::<Vec<_>>()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_lookup_nearest() {
+        let mut port = MockPort::new();
+        port.expect_stations_near().returning(|_| {
+            Ok(vec![
+                Station {
+                    icao_id: "NZWF".to_owned(),
+                    lat: -44.72,
+                    lon: 169.25,
+                },
+                Station {
+                    icao_id: "NZMC".to_owned(),
+                    lat: -43.76,
+                    lon: 170.13,
+                },
+            ])
+        });
+        port.expect_obtain_report().returning(|kind, station| {
+            assert_eq!("NZMC", station);
+            Ok(match kind {
+                ReportKind::Metar => Some(METAR.to_owned()),
+                ReportKind::Taf => Some(TAF.to_owned()),
+            })
+        });
+
+        let reports = lookup(&port, None, Some(Position::new(-43.513832, 170.33975)))
+            .await
+            .unwrap();
+        assert_eq!(
+            StationReports {
+                station: "NZMC".to_owned(),
+                metar: Some(METAR.to_owned()),
+                taf: Some(TAF.to_owned()),
+            },
+            reports
+        );
+
+        assert!(lookup(&port, None, None).await.is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let reports = StationReports {
+            station: "NZMC".to_owned(),
+            metar: Some(METAR.to_owned()),
+            taf: None,
+        };
+        assert_eq!(
+            format!("METAR {METAR}\nTAF NZMC unavailable"),
+            reports.format(&FormatForecastOptions::default())
+        );
+
+        let reports = StationReports {
+            taf: Some(TAF.to_owned()),
+            ..reports
+        };
+        let short = reports.format(&FormatForecastOptions {
+            detail: FormatDetail::Short(ShortFormatDetail {
+                length_limit: Some(60),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert_eq!(format!("METAR {METAR}"), short);
+    }
+}
@@ -0,0 +1,80 @@
+//! Back-pressure applied when more requests are waiting in the process queue than can be
+//! processed in a reasonable time (e.g. while the forecast service is slow), so that the time
+//! taken to reply does not grow without bound, see [`BackPressureOptions`].
+
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// What to do with requests while the process queue is saturated, see
+/// [`BackPressureOptions::overload`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum Overload {
+    /// Reply to requests taken from the queue with a short message asking the sender to try again
+    /// later, instead of a forecast, until the queue has drained.
+    Reply,
+    /// Stop fetching new emails from the inbox until the queue has drained. They remain unread in
+    /// the inbox, and are received once there is room in the queue.
+    Defer,
+}
+
+/// Options for applying back-pressure when the process queue is saturated, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct BackPressureOptions {
+    /// Maximum number of requests waiting in the process queue. Requests which were queued before
+    /// the service started are not counted.
+    ///
+    /// Default is `None` (unlimited).
+    #[serde(default)]
+    pub max_queued_requests: Option<u64>,
+    /// What to do with requests while the queue is saturated, see [`Overload`].
+    ///
+    /// Default is [`Overload::Reply`].
+    #[serde(default = "default_overload")]
+    pub overload: Overload,
+}
+
+fn default_overload() -> Overload {
+    Overload::Reply
+}
+
+impl Default for BackPressureOptions {
+    fn default() -> Self {
+        Self {
+            max_queued_requests: None,
+            overload: default_overload(),
+        }
+    }
+}
+
+impl BackPressureOptions {
+    /// Returns `true` if the process queue with `pending` requests is saturated, and `overload`
+    /// should be applied.
+    #[must_use]
+    pub fn is_overloaded(&self, pending: u64, overload: Overload) -> bool {
+        self.overload == overload
+            && self
+                .max_queued_requests
+                .map_or(false, |max_queued_requests| pending > max_queued_requests)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{BackPressureOptions, Overload};
+
+    #[test]
+    fn test_is_overloaded() {
+        let options = BackPressureOptions::default();
+        assert!(!options.is_overloaded(u64::MAX, Overload::Reply));
+
+        let options = BackPressureOptions {
+            max_queued_requests: Some(10),
+            overload: Overload::Reply,
+        };
+        assert!(!options.is_overloaded(10, Overload::Reply));
+        assert!(options.is_overloaded(11, Overload::Reply));
+        assert!(!options.is_overloaded(11, Overload::Defer));
+    }
+}
@@ -0,0 +1,320 @@
+//! Record/replay of responses from the external forecast and elevation services, so that tests can
+//! run against realistic payloads without network access, see [`Cassette`].
+//!
+//! Tests using a cassette replay the responses recorded in `fixtures/cassettes/`. Run them with
+//! the `RECORD_FIXTURES` environment variable set to obtain responses from the real services and
+//! record them instead, e.g. after adding a new forecast variable:
+//!
+//! ```bash
+//! RECORD_FIXTURES=1 cargo test
+//! ```
+
+use std::{
+    collections::BTreeMap,
+    path::{Path, PathBuf},
+    sync::{Mutex, MutexGuard},
+};
+
+use async_trait::async_trait;
+use open_meteo::{Forecast, ForecastParameters};
+
+use crate::{
+    forecast_service::{self, ObtainedForecast, Provider},
+    topo_data_service,
+};
+
+/// Whether a [`Cassette`] records or replays responses.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Mode {
+    /// Obtain responses from the real services and record them.
+    Record,
+    /// Replay previously recorded responses.
+    Replay,
+}
+
+impl Mode {
+    /// [`Mode::Record`] if the `RECORD_FIXTURES` environment variable is set, otherwise
+    /// [`Mode::Replay`].
+    pub(crate) fn from_env() -> Self {
+        if std::env::var("RECORD_FIXTURES").is_ok() {
+            Self::Record
+        } else {
+            Self::Replay
+        }
+    }
+}
+
+/// Implementation of [`forecast_service::Port`] and [`topo_data_service::Port`] which either
+/// records the responses from the real services into a json file, or replays them from it. The
+/// responses are keyed by the parameters of the request.
+pub(crate) struct Cassette {
+    path: PathBuf,
+    mode: Mode,
+    forecast_service: forecast_service::Gateway,
+    topo_data_service: topo_data_service::Gateway,
+    responses: Mutex<BTreeMap<String, serde_json::Value>>,
+}
+
+impl Cassette {
+    /// Open the cassette `fixtures/cassettes/{name}.json`, in the mode selected by
+    /// [`Mode::from_env()`], using the public APIs when recording.
+    pub(crate) fn open(name: &str) -> Self {
+        let http_client = reqwest::Client::new();
+        Self::open_path(
+            Path::new("fixtures/cassettes").join(format!("{}.json", name)),
+            Mode::from_env(),
+            forecast_service::Gateway::new(http_client.clone()),
+            topo_data_service::Gateway::new(http_client),
+        )
+    }
+
+    /// Open the cassette at `path` in `mode`, using the specified gateways when recording.
+    pub(crate) fn open_path(
+        path: PathBuf,
+        mode: Mode,
+        forecast_service: forecast_service::Gateway,
+        topo_data_service: topo_data_service::Gateway,
+    ) -> Self {
+        let responses = match mode {
+            Mode::Record => BTreeMap::new(),
+            Mode::Replay => {
+                let json = std::fs::read_to_string(&path).unwrap_or_else(|error| {
+                    panic!(
+                        "Unable to read cassette {:?} ({}), run with RECORD_FIXTURES=1 to record it",
+                        path, error
+                    )
+                });
+                serde_json::from_str(&json)
+                    .unwrap_or_else(|error| panic!("Invalid cassette {:?}: {}", path, error))
+            }
+        };
+        Self {
+            path,
+            mode,
+            forecast_service,
+            topo_data_service,
+            responses: Mutex::new(responses),
+        }
+    }
+
+    fn responses(&self) -> MutexGuard<'_, BTreeMap<String, serde_json::Value>> {
+        self.responses.lock().expect("cassette mutex is poisoned")
+    }
+
+    /// Obtain the replayed response for `key`.
+    fn replay(&self, key: &str) -> serde_json::Value {
+        self.responses().get(key).cloned().unwrap_or_else(|| {
+            panic!(
+                "No response recorded for {:?} in cassette {:?}, run with RECORD_FIXTURES=1 to \
+                record it",
+                key, self.path
+            )
+        })
+    }
+
+    /// Record the `response` for `key`, and save the cassette.
+    fn record(&self, key: String, response: serde_json::Value) {
+        let mut responses = self.responses();
+        responses.insert(key, response);
+        if let Some(parent) = self.path.parent() {
+            std::fs::create_dir_all(parent).unwrap();
+        }
+        std::fs::write(
+            &self.path,
+            serde_json::to_string_pretty(&*responses).unwrap() + "\n",
+        )
+        .unwrap();
+    }
+}
+
+/// Key for a forecast request, see [`forecast_service::parameters_key()`].
+fn forecast_key(parameters: &ForecastParameters) -> String {
+    format!(
+        "open-meteo forecast?{}",
+        forecast_service::parameters_key(parameters).unwrap()
+    )
+}
+
+/// Key for an elevation request.
+fn elevation_key(parameters: &open_topo_data::Parameters) -> String {
+    format!(
+        "opentopodata {}?locations={},{}",
+        serde_json::to_value(&parameters.dataset)
+            .unwrap()
+            .as_str()
+            .unwrap_or_default(),
+        parameters.latitude,
+        parameters.longitude
+    )
+}
+
+#[async_trait]
+impl forecast_service::Port for Cassette {
+    async fn obtain_forecast(
+        &self,
+        parameters: &ForecastParameters,
+    ) -> Result<ObtainedForecast, forecast_service::Error> {
+        let key = forecast_key(parameters);
+        let response = match self.mode {
+            Mode::Record => {
+                let json = self
+                    .forecast_service
+                    .obtain_forecast_json(parameters)
+                    .await?;
+                let response: serde_json::Value =
+                    serde_json::from_str(&json).map_err(open_meteo::Error::from)?;
+                self.record(key, response.clone());
+                response
+            }
+            Mode::Replay => self.replay(&key),
+        };
+        let forecast: Forecast =
+            serde_json::from_value(response).map_err(open_meteo::Error::from)?;
+        Ok(ObtainedForecast::new(
+            forecast,
+            Provider::OpenMeteo.name(),
+            chrono::Utc::now(),
+        ))
+    }
+}
+
+#[async_trait]
+impl topo_data_service::Port for Cassette {
+    async fn obtain_elevation(
+        &self,
+        parameters: &open_topo_data::Parameters,
+    ) -> Result<f32, open_topo_data::Error> {
+        let key = elevation_key(parameters);
+        let response = match self.mode {
+            Mode::Record => {
+                let elevation = self.topo_data_service.obtain_elevation(parameters).await?;
+                let response = serde_json::json!(elevation);
+                self.record(key, response.clone());
+                response
+            }
+            Mode::Replay => self.replay(&key),
+        };
+        Ok(serde_json::from_value(response)?)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::path::PathBuf;
+
+    use open_meteo::{Forecast, ForecastParameters, HourlyVariable};
+    use open_topo_data::Dataset;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use crate::{forecast_service, forecast_service::Port as _, topo_data_service};
+
+    use super::{Cassette, Mode};
+
+    fn forecast_parameters() -> ForecastParameters {
+        ForecastParameters::builder()
+            .latitude(-43.513832)
+            .longitude(170.33975)
+            .hourly_entry(HourlyVariable::FreezingLevelHeight)
+            .hourly_entry(HourlyVariable::WeatherCode)
+            .hourly_entry(HourlyVariable::Precipitation)
+            .build()
+    }
+
+    fn elevation_parameters() -> open_topo_data::Parameters {
+        open_topo_data::Parameters {
+            latitude: -43.513832,
+            longitude: 170.33975,
+            dataset: Dataset::NzDem,
+        }
+    }
+
+    fn open(path: PathBuf, mode: Mode, server: &MockServer) -> Cassette {
+        let http_client = reqwest::Client::new();
+        Cassette::open_path(
+            path,
+            mode,
+            forecast_service::Gateway::with_base_url(http_client.clone(), server.uri()),
+            topo_data_service::Gateway::with_base_url(http_client, server.uri()),
+        )
+    }
+
+    /// Test that responses recorded from the services can be replayed without them.
+    #[tokio::test]
+    async fn test_record_replay() {
+        let path = std::env::temp_dir().join(format!("cassette-{}.json", uuid::Uuid::new_v4()));
+
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v1/forecast"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                std::fs::read("fixtures/forecast_mt_cook.json").unwrap(),
+                "application/json",
+            ))
+            .expect(1)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v1/nzdem8m"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{
+                    "dataset": "nzdem8m",
+                    "elevation": 2216.0,
+                    "location": { "lat": -43.513832, "lng": 170.33975 },
+                }],
+                "status": "OK",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let recording = open(path.clone(), Mode::Record, &server);
+        let recorded_forecast = recording
+            .obtain_forecast(&forecast_parameters())
+            .await
+            .unwrap()
+            .forecast;
+        let recorded_elevation =
+            topo_data_service::Port::obtain_elevation(&recording, &elevation_parameters())
+                .await
+                .unwrap();
+        server.verify().await;
+        server.reset().await;
+
+        // The server no longer responds, so these can only succeed by replaying.
+        let replaying = open(path.clone(), Mode::Replay, &server);
+        let replayed_forecast = replaying
+            .obtain_forecast(&forecast_parameters())
+            .await
+            .unwrap()
+            .forecast;
+        let replayed_elevation =
+            topo_data_service::Port::obtain_elevation(&replaying, &elevation_parameters())
+                .await
+                .unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        // The units are a `HashMap`, so their debug output isn't ordered consistently.
+        assert_eq!(
+            recorded_forecast.hourly_units,
+            replayed_forecast.hourly_units
+        );
+        assert_eq!(
+            format!(
+                "{:?}",
+                Forecast {
+                    hourly_units: None,
+                    ..recorded_forecast
+                }
+            ),
+            format!(
+                "{:?}",
+                Forecast {
+                    hourly_units: None,
+                    ..replayed_forecast
+                }
+            )
+        );
+        assert_eq!(2216.0, recorded_elevation);
+        assert_eq!(recorded_elevation, replayed_elevation);
+    }
+}
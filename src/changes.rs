@@ -0,0 +1,604 @@
+//! Changes in a forecast since the previous forecast sent to the same sender for the same
+//! position, so that senders who request forecasts repeatedly (e.g. every evening during a trip)
+//! can see at a glance what has changed since their last report, see [`PreviousForecasts`] and
+//! [`ForecastChanges`].
+//!
+//! Each forecast is reduced to a [`ForecastDigest`] of the most important values for each (local)
+//! day, which is compared with the digest of the previous forecast. Only the days which are
+//! complete in both forecasts are compared, and only changes which are large enough to matter are
+//! reported:
+//!
+//! + **Freezing level**, the maximum of the day changed by at least
+//!   [`FREEZING_LEVEL_THRESHOLD_M`].
+//! + **Precipitation**, the total of the day is now (or is no longer) at least
+//!   [`PRECIPITATION_EXPECTED_MM`], or changed by at least [`PRECIPITATION_THRESHOLD_MM`].
+//! + **Weather**, the most significant weather of the day changed, ignoring changes between
+//!   [`Weather::Clear`] and [`Weather::Cloudy`].
+
+use std::path::Path;
+
+use chrono::{DateTime, NaiveDate, Utc};
+use eyre::Context;
+use rusqlite::{params, OptionalExtension};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format::{FormatDetail, FormatForecastOptions},
+    gis::Position,
+    process::newline,
+    store::Store,
+    subject::Weather,
+};
+
+/// Minimum change (metres) in the maximum freezing level of a day which is reported.
+pub const FREEZING_LEVEL_THRESHOLD_M: f32 = 200.0;
+
+/// Total precipitation (mm) of a day at or above which precipitation is expected.
+pub const PRECIPITATION_EXPECTED_MM: f32 = 1.0;
+
+/// Minimum change (mm) in the total precipitation of a day which is reported, when precipitation
+/// is expected in both forecasts.
+pub const PRECIPITATION_THRESHOLD_MM: f32 = 5.0;
+
+/// Size (degrees of latitude and longitude) of the cells that positions are rounded to, so that
+/// forecasts for nearby positions (e.g. a camp moved a short distance) are compared.
+const CELL_DEGREES: f32 = 0.1;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS previous_forecasts (
+    sender TEXT NOT NULL,
+    cell TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    digest TEXT NOT NULL,
+    PRIMARY KEY (sender, cell)
+);";
+
+/// The most important values of a single (local) day of a forecast, see [`ForecastDigest`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct DayDigest {
+    /// The (local) date.
+    pub date: NaiveDate,
+    /// Maximum freezing level (metres above sea level), `None` if it is not in the forecast.
+    pub freezing_level: Option<f32>,
+    /// Total precipitation (mm), `None` if it is not in the forecast.
+    pub precipitation: Option<f32>,
+    /// The most significant weather, `None` if it is not in the forecast.
+    pub weather: Option<Weather>,
+}
+
+/// The most important values of each (local) day of a forecast, which are compared with the
+/// previous forecast, see [`ForecastChanges::between()`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize, Deserialize)]
+pub struct ForecastDigest {
+    /// The days of the forecast in chronological order.
+    pub days: Vec<DayDigest>,
+}
+
+impl ForecastDigest {
+    /// Add the values of a row of the forecast on `date` (rows are expected to be added in
+    /// chronological order). `precipitation` is the amount accumulated since the previous row.
+    pub fn push(
+        &mut self,
+        date: NaiveDate,
+        freezing_level: Option<f32>,
+        precipitation: Option<f32>,
+        weather: Option<Weather>,
+    ) {
+        let day = match self.days.last_mut() {
+            Some(day) if day.date == date => day,
+            _ => {
+                self.days.push(DayDigest {
+                    date,
+                    freezing_level: None,
+                    precipitation: None,
+                    weather: None,
+                });
+                self.days.last_mut().expect("day was just pushed")
+            }
+        };
+        if let Some(freezing_level) = freezing_level {
+            day.freezing_level = Some(
+                day.freezing_level
+                    .map_or(freezing_level, |previous| previous.max(freezing_level)),
+            );
+        }
+        if let Some(precipitation) = precipitation {
+            day.precipitation = Some(day.precipitation.unwrap_or(0.0) + precipitation);
+        }
+        if let Some(weather) = weather {
+            day.weather = Some(
+                day.weather
+                    .map_or(weather, |previous| previous.max(weather)),
+            );
+        }
+    }
+
+    /// Whether the digest contains no days (e.g. the digest of a sounding).
+    #[must_use]
+    pub fn is_empty(&self) -> bool {
+        self.days.is_empty()
+    }
+
+    /// The days which are covered completely by the forecast, the first and last days usually
+    /// only contain some of the hours of the day.
+    fn complete_days(&self) -> &[DayDigest] {
+        if self.days.len() > 2 {
+            &self.days[1..(self.days.len() - 1)]
+        } else {
+            &[]
+        }
+    }
+}
+
+/// A change in the forecast of a day, see the [module documentation](self).
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Change {
+    /// The maximum freezing level (metres) changed.
+    FreezingLevel {
+        /// The (local) date of the day.
+        date: NaiveDate,
+        /// Value in the previous forecast.
+        previous: f32,
+        /// Value in the current forecast.
+        current: f32,
+    },
+    /// The total precipitation (mm) changed.
+    Precipitation {
+        /// The (local) date of the day.
+        date: NaiveDate,
+        /// Value in the previous forecast.
+        previous: f32,
+        /// Value in the current forecast.
+        current: f32,
+    },
+    /// The most significant weather changed.
+    Weather {
+        /// The (local) date of the day.
+        date: NaiveDate,
+        /// Value in the previous forecast.
+        previous: Weather,
+        /// Value in the current forecast.
+        current: Weather,
+    },
+}
+
+impl Change {
+    /// Format the change for the short format, e.g. `03 F+3` for the freezing level rising by
+    /// 300m.
+    fn format_short(&self) -> String {
+        match self {
+            Change::FreezingLevel {
+                date,
+                previous,
+                current,
+            } => format!(
+                "{} F{:+.0}",
+                date.format("%d"),
+                ((current - previous) / 100.0).round()
+            ),
+            Change::Precipitation {
+                date,
+                previous,
+                current,
+            } => format!(
+                "{} P{:.0}>{:.0}",
+                date.format("%d"),
+                previous.round(),
+                current.round()
+            ),
+            Change::Weather {
+                date,
+                previous,
+                current,
+            } => format!(
+                "{} {}>{}",
+                date.format("%d"),
+                previous.label(),
+                current.label()
+            ),
+        }
+    }
+
+    /// Format the change for the long format, e.g. `Sat 3 Dec: freezing level up 300m to 2100m`.
+    fn format_long(&self) -> String {
+        match self {
+            Change::FreezingLevel {
+                date,
+                previous,
+                current,
+            } => format!(
+                "{}: freezing level {} {:.0}m to {:.0}m",
+                date.format("%a %-d %b"),
+                if current > previous { "up" } else { "down" },
+                (current - previous).abs().round(),
+                current.round()
+            ),
+            Change::Precipitation {
+                date,
+                previous,
+                current,
+            } => {
+                let date = date.format("%a %-d %b");
+                if *previous < PRECIPITATION_EXPECTED_MM {
+                    format!(
+                        "{date}: precipitation now expected ({:.0}mm)",
+                        current.round()
+                    )
+                } else if *current < PRECIPITATION_EXPECTED_MM {
+                    format!(
+                        "{date}: precipitation no longer expected (was {:.0}mm)",
+                        previous.round()
+                    )
+                } else {
+                    format!(
+                        "{date}: precipitation {} from {:.0}mm to {:.0}mm",
+                        if current > previous { "up" } else { "down" },
+                        previous.round(),
+                        current.round()
+                    )
+                }
+            }
+            Change::Weather {
+                date,
+                previous,
+                current,
+            } => format!(
+                "{}: {} (was {})",
+                date.format("%a %-d %b"),
+                current.label().to_lowercase(),
+                previous.label().to_lowercase()
+            ),
+        }
+    }
+}
+
+/// The changes between the previous forecast and the current forecast, see the
+/// [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForecastChanges {
+    /// When the previous forecast was sent.
+    pub since: DateTime<Utc>,
+    /// The changes in chronological order.
+    pub changes: Vec<Change>,
+}
+
+impl ForecastChanges {
+    /// The changes from the `previous` forecast (sent at `since`) to the `current` forecast.
+    #[must_use]
+    pub fn between(
+        previous: &ForecastDigest,
+        current: &ForecastDigest,
+        since: DateTime<Utc>,
+    ) -> Self {
+        let mut changes = Vec::new();
+        for day in current.complete_days() {
+            let previous_day = match previous
+                .complete_days()
+                .iter()
+                .find(|previous_day| previous_day.date == day.date)
+            {
+                Some(previous_day) => previous_day,
+                None => continue,
+            };
+            let date = day.date;
+            if let (Some(previous), Some(current)) =
+                (previous_day.freezing_level, day.freezing_level)
+            {
+                if (current - previous).abs() >= FREEZING_LEVEL_THRESHOLD_M {
+                    changes.push(Change::FreezingLevel {
+                        date,
+                        previous,
+                        current,
+                    });
+                }
+            }
+            if let (Some(previous), Some(current)) = (previous_day.precipitation, day.precipitation)
+            {
+                let expected_changed = (previous >= PRECIPITATION_EXPECTED_MM)
+                    != (current >= PRECIPITATION_EXPECTED_MM);
+                if expected_changed || (current - previous).abs() >= PRECIPITATION_THRESHOLD_MM {
+                    changes.push(Change::Precipitation {
+                        date,
+                        previous,
+                        current,
+                    });
+                }
+            }
+            if let (Some(previous), Some(current)) = (previous_day.weather, day.weather) {
+                if previous != current && previous.max(current) > Weather::Cloudy {
+                    changes.push(Change::Weather {
+                        date,
+                        previous,
+                        current,
+                    });
+                }
+            }
+        }
+        Self { since, changes }
+    }
+
+    /// Format the changes as a block to be appended to the forecast, using the detail in
+    /// `options`. `None` if there is nothing to include in the short format.
+    #[must_use]
+    pub fn format(&self, options: &FormatForecastOptions) -> Option<String> {
+        let newline = newline(&options.detail);
+        match &options.detail {
+            FormatDetail::Short(_) => {
+                if self.changes.is_empty() {
+                    return None;
+                }
+                Some(
+                    std::iter::once("CHG".to_owned())
+                        .chain(self.changes.iter().map(Change::format_short))
+                        .collect::<Vec<_>>()
+                        .join(newline),
+                )
+            }
+            FormatDetail::Long(_) => {
+                let since = self.since.format("%Y-%m-%d %H:%M UTC");
+                if self.changes.is_empty() {
+                    return Some(format!(
+                        "No significant changes since the previous forecast ({since})"
+                    ));
+                }
+                Some(
+                    std::iter::once(format!("Changes since the previous forecast ({since}):"))
+                        .chain(
+                            self.changes
+                                .iter()
+                                .map(|change| format!("+ {}", change.format_long())),
+                        )
+                        .collect::<Vec<_>>()
+                        .join(newline),
+                )
+            }
+        }
+    }
+}
+
+/// The cell that `position` is rounded to, see [`CELL_DEGREES`].
+fn cell(position: Position) -> String {
+    let round = |degrees: f32| (degrees / CELL_DEGREES).round() * CELL_DEGREES;
+    format!(
+        "{:.1},{:.1}",
+        round(position.latitude),
+        round(position.longitude)
+    )
+}
+
+fn parse_time(time: &str) -> eyre::Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(time)
+        .wrap_err("Invalid previous forecast time")?
+        .with_timezone(&Utc))
+}
+
+/// Records the digest of the latest forecast sent to each sender for each position (rounded to a
+/// cell of [`CELL_DEGREES`]) in a SQLite database. Senders are identified by the hash of their
+/// address, see [`crate::forget::sender_hash()`].
+pub struct PreviousForecasts {
+    store: Store,
+}
+
+impl PreviousForecasts {
+    /// Open (or create) the database at `path`.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open("previous forecasts", path, SCHEMA)?,
+        })
+    }
+
+    /// Open a database which is stored in memory, and lost when it is dropped.
+    pub fn open_in_memory() -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open_in_memory("previous forecasts", SCHEMA)?,
+        })
+    }
+
+    /// The database that the previous forecasts are stored in.
+    #[cfg(test)]
+    pub(crate) fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Record the `digest` of the forecast sent to `sender` for `position` at `now`, replacing the
+    /// previous forecast. Returns the previous forecast and the time it was sent, if there was
+    /// one.
+    pub fn replace(
+        &self,
+        sender: &str,
+        position: Position,
+        digest: &ForecastDigest,
+        now: DateTime<Utc>,
+    ) -> eyre::Result<Option<(ForecastDigest, DateTime<Utc>)>> {
+        let cell = cell(position);
+        let mut connection = self.store.connection();
+        let transaction = connection.transaction()?;
+        let previous: Option<(String, String)> = transaction
+            .query_row(
+                "SELECT digest, created_at FROM previous_forecasts WHERE sender = ?1 AND cell = ?2",
+                params![sender, cell],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .wrap_err("Unable to query previous forecast")?;
+        transaction
+            .execute(
+                "INSERT OR REPLACE INTO previous_forecasts (sender, cell, created_at, digest) \
+                VALUES (?1, ?2, ?3, ?4)",
+                params![
+                    sender,
+                    cell,
+                    now.to_rfc3339(),
+                    serde_json::to_string(digest)?
+                ],
+            )
+            .wrap_err("Unable to record previous forecast")?;
+        transaction.commit()?;
+
+        previous
+            .map(|(digest, created_at)| {
+                Ok((
+                    serde_json::from_str(&digest).wrap_err("Invalid previous forecast digest")?,
+                    parse_time(&created_at)?,
+                ))
+            })
+            .transpose()
+    }
+
+    /// Erase the previous forecasts of `sender`, returns the number of records erased.
+    pub fn forget(&self, sender: &str) -> eyre::Result<u64> {
+        self.store.forget(sender, &["previous_forecasts"])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use crate::{
+        format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle},
+        gis::Position,
+        subject::Weather,
+        time::test_now as now,
+    };
+
+    use super::{Change, ForecastChanges, ForecastDigest, PreviousForecasts};
+
+    fn date(day: u32) -> NaiveDate {
+        NaiveDate::from_ymd_opt(2022, 12, day).unwrap()
+    }
+
+    /// A digest with two rows for each of the `days`, with the freezing level, the precipitation
+    /// and the weather of each row.
+    fn digest(days: &[(u32, f32, f32, Weather)]) -> ForecastDigest {
+        let mut digest = ForecastDigest::default();
+        for (day, freezing_level, precipitation, weather) in days {
+            for _ in 0..2 {
+                digest.push(
+                    date(*day),
+                    Some(*freezing_level),
+                    Some(*precipitation / 2.0),
+                    Some(*weather),
+                );
+            }
+        }
+        digest
+    }
+
+    #[test]
+    fn test_changes_between() {
+        let previous = digest(&[
+            (2, 1500.0, 0.0, Weather::Clear),
+            (3, 1800.0, 0.0, Weather::Clear),
+            (4, 2000.0, 12.0, Weather::Rain),
+            (5, 2000.0, 6.0, Weather::Rain),
+        ]);
+        assert_eq!(2, previous.days.len() - previous.complete_days().len());
+        assert_eq!(Some(12.0), previous.days[2].precipitation);
+
+        let current = digest(&[
+            (3, 1000.0, 0.0, Weather::Cloudy),
+            (4, 2300.0, 10.0, Weather::Snow),
+            (5, 2100.0, 0.5, Weather::Cloudy),
+            (6, 2000.0, 6.0, Weather::Rain),
+        ]);
+        let changes = ForecastChanges::between(&previous, &current, now());
+        // The 3rd is incomplete in the current forecast, and the 5th in the previous forecast.
+        assert_eq!(
+            vec![
+                Change::FreezingLevel {
+                    date: date(4),
+                    previous: 2000.0,
+                    current: 2300.0,
+                },
+                Change::Weather {
+                    date: date(4),
+                    previous: Weather::Rain,
+                    current: Weather::Snow,
+                },
+            ],
+            changes.changes
+        );
+        assert_eq!(
+            Some("CHG\n04 F+3\n04 Rain>Snow".to_owned()),
+            changes.format(&FormatForecastOptions::default())
+        );
+    }
+
+    #[test]
+    fn test_format() {
+        let changes = ForecastChanges {
+            since: now(),
+            changes: vec![
+                Change::FreezingLevel {
+                    date: date(3),
+                    previous: 1800.0,
+                    current: 1500.0,
+                },
+                Change::Precipitation {
+                    date: date(3),
+                    previous: 0.0,
+                    current: 12.4,
+                },
+                Change::Precipitation {
+                    date: date(4),
+                    previous: 6.0,
+                    current: 0.0,
+                },
+            ],
+        };
+        let long_options = FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        };
+        assert_eq!(
+            "Changes since the previous forecast (2022-12-03 08:00 UTC):\n\
+            + Sat 3 Dec: freezing level down 300m to 1500m\n\
+            + Sat 3 Dec: precipitation now expected (12mm)\n\
+            + Sun 4 Dec: precipitation no longer expected (was 6mm)",
+            changes.format(&long_options).unwrap()
+        );
+        assert_eq!(
+            "CHG\n03 F-3\n03 P0>12\n04 P6>0",
+            changes.format(&FormatForecastOptions::default()).unwrap()
+        );
+
+        let unchanged = ForecastChanges {
+            since: now(),
+            changes: Vec::new(),
+        };
+        assert_eq!(None, unchanged.format(&FormatForecastOptions::default()));
+        assert_eq!(
+            "No significant changes since the previous forecast (2022-12-03 08:00 UTC)",
+            unchanged.format(&long_options).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_previous_forecasts() {
+        let previous_forecasts = PreviousForecasts::open_in_memory().unwrap();
+        let first = digest(&[(3, 1800.0, 0.0, Weather::Clear)]);
+        let second = digest(&[(3, 2000.0, 0.0, Weather::Clear)]);
+        let position = Position::new(-43.51, 170.34);
+        assert_eq!(
+            None,
+            previous_forecasts
+                .replace("sha256:aaaa", position, &first, now())
+                .unwrap()
+        );
+        // A nearby position in the same cell.
+        assert_eq!(
+            Some((first, now())),
+            previous_forecasts
+                .replace("sha256:aaaa", Position::new(-43.53, 170.32), &second, now())
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            previous_forecasts
+                .replace("sha256:bbbb", position, &second, now())
+                .unwrap()
+        );
+    }
+}
@@ -0,0 +1,310 @@
+//! Validation of the application configuration, see [`check_config()`].
+
+use std::{borrow::Cow, fmt::Display, path::Path, time::Duration};
+
+use secrecy::ExposeSecret;
+
+use crate::{
+    api::cors_layer,
+    options::Options,
+    queue::{self, QueueCipher},
+    redact::init_identity_key,
+    retry::ExponentialBackoff,
+    secrets::Secrets,
+};
+
+/// The outcome of a single [`Check`].
+#[derive(Debug)]
+pub enum Outcome {
+    /// The check passed.
+    Pass,
+    /// The check found a problem which may not prevent the application from running.
+    Warning(String),
+    /// The check found a problem which will prevent the application from running correctly.
+    Error(String),
+}
+
+/// A single configuration check that was performed.
+#[derive(Debug)]
+pub struct Check {
+    /// Name of what was checked.
+    pub name: Cow<'static, str>,
+    /// The outcome of the check.
+    pub outcome: Outcome,
+}
+
+/// Report produced by [`check_config()`].
+#[derive(Debug, Default)]
+pub struct Report {
+    /// Checks that were performed.
+    pub checks: Vec<Check>,
+}
+
+impl Report {
+    fn push(&mut self, name: impl Into<Cow<'static, str>>, outcome: Outcome) {
+        self.checks.push(Check {
+            name: name.into(),
+            outcome,
+        });
+    }
+
+    /// Returns `true` if none of the checks produced an [`Outcome::Error`].
+    #[must_use]
+    pub fn is_ok(&self) -> bool {
+        !self
+            .checks
+            .iter()
+            .any(|check| matches!(check.outcome, Outcome::Error(_)))
+    }
+}
+
+impl Display for Report {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        for check in &self.checks {
+            match &check.outcome {
+                Outcome::Pass => writeln!(f, "[PASS] {}", check.name)?,
+                Outcome::Warning(message) => writeln!(f, "[WARN] {}: {}", check.name, message)?,
+                Outcome::Error(message) => writeln!(f, "[FAIL] {}: {}", check.name, message)?,
+            }
+        }
+        Ok(())
+    }
+}
+
+/// Check that a file can be written in an existing directory.
+fn check_writable(dir: &Path) -> eyre::Result<()> {
+    let test_path = dir.join(".email-weather-check-config");
+    std::fs::write(&test_path, b"check")?;
+    std::fs::remove_file(&test_path)?;
+    Ok(())
+}
+
+/// Check that a directory is writable without creating it. If the directory does not exist, its
+/// parent must exist and be writable so that the directory can be created when the application
+/// starts.
+fn check_dir_writable(path: &Path) -> Outcome {
+    let display = path.display();
+    if path.is_dir() {
+        return match check_writable(path) {
+            Ok(()) => Outcome::Pass,
+            Err(error) => Outcome::Error(format!("\"{display}\" is not writable: {error}")),
+        };
+    }
+    if path.exists() {
+        return Outcome::Error(format!("\"{display}\" is not a directory"));
+    }
+
+    let parent = match path.parent() {
+        Some(parent) if parent.as_os_str().is_empty() => Path::new("."),
+        Some(parent) => parent,
+        None => return Outcome::Error(format!("\"{display}\" does not exist")),
+    };
+    let parent_display = parent.display();
+    if !parent.is_dir() {
+        return Outcome::Error(format!(
+            "\"{display}\" does not exist, and cannot be created because its parent \
+            \"{parent_display}\" does not exist"
+        ));
+    }
+    match check_writable(parent) {
+        Ok(()) => Outcome::Warning(format!(
+            "\"{display}\" does not exist, it will be created when the application starts"
+        )),
+        Err(error) => Outcome::Error(format!(
+            "\"{display}\" does not exist, and cannot be created because its parent \
+            \"{parent_display}\" is not writable: {error}"
+        )),
+    }
+}
+
+/// Validate the [`Options`] and the [`Secrets`] that they reference, checking that:
+///
+/// + `data_dir` and `secrets_dir` are writable (or can be created, without creating them).
+/// + `base_url` is reachable (a warning if not, because it may be served by this application).
+/// + The TLS certificate and private key (if specified) are readable.
+/// + Secrets can be loaded, and the client secret is parsable.
+/// + The admin password hash is a well formed argon2 (or deprecated bcrypt) hash.
+/// + The Redis queue `leader_ttl_secs` (if used) is longer than `poll_interval_secs`.
+pub async fn check_config(options: &Options, http_client: reqwest::Client) -> Report {
+    let mut report = Report::default();
+
+    for (name, path) in [
+        ("data_dir", &options.data_dir),
+        ("secrets_dir", &options.secrets_dir),
+    ] {
+        report.push(format!("{name} is writable"), check_dir_writable(path));
+    }
+
+    let outcome = match http_client
+        .get(options.base_url.clone())
+        .timeout(Duration::from_secs(10))
+        .send()
+        .await
+    {
+        Ok(_) => Outcome::Pass,
+        Err(error) => Outcome::Warning(format!(
+            "{} is not reachable (this is expected if it is served by this application and \
+            the application is not running): {}",
+            options.base_url, error
+        )),
+    };
+    report.push("base_url is reachable", outcome);
+
+    if options.tls.is_some() && options.acme.is_some() {
+        report.push(
+            "tls and acme are exclusive",
+            Outcome::Error("Only one of the tls and acme options may be specified".to_string()),
+        );
+    }
+
+    if options.acme.is_some() && options.base_url.host_str().is_none() {
+        report.push(
+            "base_url has a hostname for acme",
+            Outcome::Error(format!("{} has no hostname", options.base_url)),
+        );
+    }
+
+    for (name, backoff) in [
+        ("task_backoff", &options.task_backoff),
+        ("reply_backoff", &options.reply_backoff),
+    ] {
+        let outcome = match ExponentialBackoff::from_options(backoff) {
+            Ok(_) => Outcome::Pass,
+            Err(error) => Outcome::Error(error.to_string()),
+        };
+        report.push(format!("{} is valid", name), outcome);
+    }
+
+    if let queue::Backend::Redis(redis) = &options.queue {
+        let outcome = if redis.leader_ttl_secs > options.poll_interval_secs {
+            Outcome::Pass
+        } else {
+            Outcome::Error(format!(
+                "leader_ttl_secs ({}) must be longer than poll_interval_secs ({}), otherwise the \
+                leader lock expires before it is renewed",
+                redis.leader_ttl_secs, options.poll_interval_secs
+            ))
+        };
+        report.push("queue leader_ttl_secs is valid", outcome);
+    }
+
+    let outcome = match cors_layer(&options.api_cors_origins) {
+        Ok(_) => Outcome::Pass,
+        Err(error) => Outcome::Error(error.to_string()),
+    };
+    report.push("api_cors_origins are valid", outcome);
+
+    if let Some(tls) = &options.tls {
+        for (name, path) in [
+            ("tls certificate", &tls.certificate_path),
+            ("tls private key", &tls.private_key_path),
+        ] {
+            let outcome = match std::fs::metadata(path) {
+                Ok(_) => Outcome::Pass,
+                Err(error) => Outcome::Error(format!("{:?} is not readable: {}", path, error)),
+            };
+            report.push(format!("{} is readable", name), outcome);
+        }
+    }
+
+    if !options.secrets_dir.is_dir() {
+        return report;
+    }
+
+    // Loaded without side effects, this command only validates the configuration.
+    let secrets =
+        match Secrets::load(&options.secrets_dir, &options.secrets_backend, http_client).await {
+            Ok(secrets) => {
+                report.push("secrets can be loaded", Outcome::Pass);
+                secrets
+            }
+            Err(error) => {
+                report.push(
+                    "secrets can be loaded",
+                    Outcome::Error(format!("{:?}", error)),
+                );
+                return report;
+            }
+        };
+
+    let outcome = if secrets.oauth_secrets.client_secret.is_some() {
+        Outcome::Pass
+    } else {
+        Outcome::Error("CLIENT_SECRET secret has not been provided".to_string())
+    };
+    report.push("client secret is present", outcome);
+
+    let outcome = match &secrets.admin_password_hash {
+        Some(hash) if hash.expose_secret().starts_with("$argon2") => {
+            match argon2::password_hash::PasswordHash::new(hash.expose_secret()) {
+                Ok(_) => Outcome::Pass,
+                Err(error) => Outcome::Error(format!("Invalid argon2 hash: {}", error)),
+            }
+        }
+        Some(hash) => match hash.expose_secret().parse::<bcrypt::HashParts>() {
+            Ok(_) => Outcome::Warning(
+                "bcrypt hashes are deprecated, please generate a new argon2id hash".to_string(),
+            ),
+            Err(error) => Outcome::Error(format!("Invalid bcrypt hash: {}", error)),
+        },
+        None => Outcome::Warning(
+            "ADMIN_PASSWORD_HASH secret has not been provided, admin interface will be disabled"
+                .to_string(),
+        ),
+    };
+    report.push("admin password hash is well formed", outcome);
+
+    let outcome = match init_identity_key(&secrets.identity_key) {
+        Ok(()) => Outcome::Pass,
+        Err(error) => Outcome::Error(error.to_string()),
+    };
+    report.push("identity key is valid", outcome);
+
+    if let Some(queue_key) = &secrets.queue_key {
+        let outcome = match QueueCipher::new(queue_key) {
+            Ok(_) => Outcome::Pass,
+            Err(error) => Outcome::Error(error.to_string()),
+        };
+        report.push("queue key is valid", outcome);
+    }
+
+    report
+}
+
+#[cfg(test)]
+mod test {
+    use super::{check_dir_writable, Outcome, Report};
+
+    #[test]
+    fn test_report_is_ok() {
+        let mut report = Report::default();
+        report.push("pass", Outcome::Pass);
+        report.push("warning", Outcome::Warning("warning".to_string()));
+        assert!(report.is_ok());
+        report.push("error", Outcome::Error("error".to_string()));
+        assert!(!report.is_ok());
+    }
+
+    #[test]
+    fn test_check_dir_writable() {
+        let dir = std::env::temp_dir().join("email-weather-test-check-dir-writable");
+        let _ = std::fs::remove_dir_all(&dir);
+
+        let missing = dir.join("missing");
+        assert!(matches!(check_dir_writable(&missing), Outcome::Error(_)));
+        assert!(!dir.exists());
+
+        std::fs::create_dir(&dir).unwrap();
+        assert!(matches!(check_dir_writable(&missing), Outcome::Warning(_)));
+        assert!(!missing.exists());
+
+        assert!(matches!(check_dir_writable(&dir), Outcome::Pass));
+        assert!(std::fs::read_dir(&dir).unwrap().next().is_none());
+
+        let file = dir.join("file");
+        std::fs::write(&file, b"file").unwrap();
+        assert!(matches!(check_dir_writable(&file), Outcome::Error(_)));
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,249 @@
+//! Circuit breakers for external services, see [`CircuitBreaker`].
+
+use std::{future::Future, sync::Mutex};
+
+use chrono::{DateTime, Duration, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::time;
+
+/// Options for [`CircuitBreaker`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct CircuitBreakerOptions {
+    /// Number of consecutive failures before the circuit is opened.
+    ///
+    /// Default is `5`.
+    #[serde(default = "default_failure_threshold")]
+    pub failure_threshold: u32,
+    /// How long (in seconds) the circuit remains open before a request is permitted to test
+    /// whether the service has recovered.
+    ///
+    /// Default is `300`.
+    #[serde(default = "default_reset_timeout_secs")]
+    pub reset_timeout_secs: u32,
+}
+
+fn default_failure_threshold() -> u32 {
+    5
+}
+
+fn default_reset_timeout_secs() -> u32 {
+    300
+}
+
+impl Default for CircuitBreakerOptions {
+    fn default() -> Self {
+        Self {
+            failure_threshold: default_failure_threshold(),
+            reset_timeout_secs: default_reset_timeout_secs(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+enum State {
+    /// Requests are permitted.
+    Closed { consecutive_failures: u32 },
+    /// Requests are not permitted until the specified time.
+    Open { until: DateTime<Utc> },
+    /// A single request is permitted to test whether the service has recovered.
+    HalfOpen,
+}
+
+/// Error returned by [`CircuitBreaker::check()`] when the circuit is open.
+#[derive(Debug, thiserror::Error)]
+#[error("{name} is temporarily unavailable")]
+pub struct CircuitOpen {
+    /// Name of the service which is unavailable.
+    pub name: &'static str,
+}
+
+/// Tracks failures of requests to an external service. After
+/// [`CircuitBreakerOptions::failure_threshold`] consecutive failures the circuit is opened, and
+/// requests should not be made to the service (see [`CircuitBreaker::check()`]) until
+/// [`CircuitBreakerOptions::reset_timeout_secs`] has elapsed. Then a single request is permitted,
+/// if it succeeds the circuit is closed, otherwise it is opened again.
+#[derive(Debug)]
+pub struct CircuitBreaker {
+    name: &'static str,
+    options: CircuitBreakerOptions,
+    state: Mutex<State>,
+}
+
+impl CircuitBreaker {
+    /// Create a new closed [`CircuitBreaker`] for the service named `name`.
+    #[must_use]
+    pub fn new(name: &'static str, options: CircuitBreakerOptions) -> Self {
+        Self {
+            name,
+            options,
+            state: Mutex::new(State::Closed {
+                consecutive_failures: 0,
+            }),
+        }
+    }
+
+    /// Check whether a request to the service is permitted at `now`.
+    pub fn check(&self, now: DateTime<Utc>) -> Result<(), CircuitOpen> {
+        let mut state = self
+            .state
+            .lock()
+            .expect("circuit breaker mutex is poisoned");
+        match *state {
+            State::Closed { .. } | State::HalfOpen => Ok(()),
+            State::Open { until } if now >= until => {
+                tracing::info!("Circuit for {} is half open, testing service", self.name);
+                *state = State::HalfOpen;
+                Ok(())
+            }
+            State::Open { .. } => Err(CircuitOpen { name: self.name }),
+        }
+    }
+
+    /// Record a successful request, closing the circuit.
+    pub fn record_success(&self) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("circuit breaker mutex is poisoned");
+        if !matches!(*state, State::Closed { .. }) {
+            tracing::info!("Circuit for {} is closed", self.name);
+        }
+        *state = State::Closed {
+            consecutive_failures: 0,
+        };
+    }
+
+    /// Record a failed request at `now`, which may open the circuit.
+    pub fn record_failure(&self, now: DateTime<Utc>) {
+        let mut state = self
+            .state
+            .lock()
+            .expect("circuit breaker mutex is poisoned");
+        let open = match *state {
+            State::Closed {
+                consecutive_failures,
+            } => {
+                let consecutive_failures = consecutive_failures + 1;
+                *state = State::Closed {
+                    consecutive_failures,
+                };
+                consecutive_failures >= self.options.failure_threshold
+            }
+            State::HalfOpen => true,
+            State::Open { .. } => false,
+        };
+
+        if open {
+            tracing::warn!(
+                "Circuit for {} is open for {} seconds",
+                self.name,
+                self.options.reset_timeout_secs
+            );
+            *state = State::Open {
+                until: now + Duration::seconds(i64::from(self.options.reset_timeout_secs)),
+            };
+        }
+    }
+
+    /// Record the outcome of a request made at `now`.
+    pub fn record<T, E>(&self, result: &Result<T, E>, now: DateTime<Utc>) {
+        match result {
+            Ok(_) => self.record_success(),
+            Err(_) => self.record_failure(now),
+        }
+    }
+}
+
+/// Decorator for a service port (e.g. [`crate::forecast_service::Port`] or
+/// [`crate::topo_data_service::Port`]) which records the outcome of each request in a
+/// [`CircuitBreaker`]. Each port implements its trait for this decorator using
+/// [`CircuitBreakerPort::request()`].
+pub struct CircuitBreakerPort<'a, P> {
+    inner: P,
+    breaker: &'a CircuitBreaker,
+    time: &'a dyn time::Port,
+}
+
+impl<'a, P> CircuitBreakerPort<'a, P> {
+    /// Construct a new [`CircuitBreakerPort`].
+    #[must_use]
+    pub fn new(inner: P, breaker: &'a CircuitBreaker, time: &'a dyn time::Port) -> Self {
+        Self {
+            inner,
+            breaker,
+            time,
+        }
+    }
+
+    /// Make a `request` to the decorated port, recording its outcome.
+    pub async fn request<'b, T, E, F>(&'b self, request: impl FnOnce(&'b P) -> F) -> Result<T, E>
+    where
+        F: Future<Output = Result<T, E>>,
+    {
+        let result = request(&self.inner).await;
+        self.breaker.record(&result, self.time.utc_now());
+        result
+    }
+}
+
+/// Circuit breakers for each of the external services used by the application's tasks.
+#[derive(Debug)]
+pub struct CircuitBreakers {
+    /// Circuit breaker for [`crate::forecast_service`].
+    pub forecast: CircuitBreaker,
+    /// Circuit breaker for [`crate::topo_data_service`].
+    pub topo_data: CircuitBreaker,
+    /// Circuit breaker for replies sent using the inReach web interface.
+    pub inreach: CircuitBreaker,
+}
+
+impl CircuitBreakers {
+    /// Create new closed [`CircuitBreakers`].
+    #[must_use]
+    pub fn new(options: CircuitBreakerOptions) -> Self {
+        Self {
+            forecast: CircuitBreaker::new("Forecast service", options),
+            topo_data: CircuitBreaker::new("Terrain elevation service", options),
+            inreach: CircuitBreaker::new("inReach reply service", options),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::time::test_now as now;
+
+    use super::{CircuitBreaker, CircuitBreakerOptions};
+
+    #[test]
+    fn test_circuit_breaker() {
+        let breaker = CircuitBreaker::new(
+            "test",
+            CircuitBreakerOptions {
+                failure_threshold: 2,
+                reset_timeout_secs: 60,
+            },
+        );
+        assert!(breaker.check(now()).is_ok());
+        breaker.record_failure(now());
+        assert!(breaker.check(now()).is_ok());
+        breaker.record_failure(now());
+        assert!(breaker.check(now()).is_err());
+        assert!(breaker.check(now() + Duration::seconds(59)).is_err());
+
+        // Half open, a single failure opens the circuit again.
+        assert!(breaker.check(now() + Duration::seconds(60)).is_ok());
+        breaker.record_failure(now() + Duration::seconds(60));
+        assert!(breaker.check(now() + Duration::seconds(61)).is_err());
+
+        // Half open, a success closes the circuit.
+        assert!(breaker.check(now() + Duration::seconds(120)).is_ok());
+        breaker.record_success();
+        breaker.record_failure(now() + Duration::seconds(121));
+        assert!(breaker.check(now() + Duration::seconds(121)).is_ok());
+    }
+}
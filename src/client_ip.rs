@@ -0,0 +1,128 @@
+//! Determine the IP address of the client making an http request, see [`ClientIp`].
+
+use std::net::{IpAddr, SocketAddr};
+
+use async_trait::async_trait;
+use axum::{
+    extract::{ConnectInfo, FromRequestParts},
+    http::{request::Parts, Extensions, HeaderMap, StatusCode},
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Where the client IP address of http requests is obtained from, specified in
+/// [`crate::options::Options`]. Headers are only trusted when the service is configured to run
+/// behind a proxy which sets them, otherwise clients could choose their own address.
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum ClientIpSource {
+    /// The address of the peer of the connection. Behind a proxy, this is the address of the proxy.
+    #[default]
+    Peer,
+    /// The `Fly-Client-IP` header, set by the [fly.io](https://fly.io/) proxy.
+    FlyClientIp,
+    /// The last address in the `X-Forwarded-For` header, which is the address of the client that
+    /// connected to the proxy (earlier addresses are set by the client, and cannot be trusted).
+    XForwardedFor,
+}
+
+const FLY_CLIENT_IP: &str = "fly-client-ip";
+const X_FORWARDED_FOR: &str = "x-forwarded-for";
+
+impl ClientIpSource {
+    /// The client IP address of a request with the specified `headers` and `extensions`. Falls
+    /// back to the address of the peer if the header is missing or invalid. Requires the router to
+    /// be served using `into_make_service_with_connect_info::<SocketAddr>()`.
+    #[must_use]
+    pub fn client_ip(self, headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+        let header_ip = match self {
+            Self::Peer => None,
+            Self::FlyClientIp => headers
+                .get(FLY_CLIENT_IP)
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.trim().parse().ok()),
+            Self::XForwardedFor => headers
+                .get_all(X_FORWARDED_FOR)
+                .iter()
+                .next_back()
+                .and_then(|value| value.to_str().ok())
+                .and_then(|value| value.rsplit(',').next())
+                .and_then(|value| value.trim().parse().ok()),
+        };
+        header_ip.or_else(|| {
+            extensions
+                .get::<ConnectInfo<SocketAddr>>()
+                .map(|ConnectInfo(address)| address.ip())
+        })
+    }
+}
+
+/// Extractor for the IP address of the client making the request, obtained using the
+/// [`ClientIpSource`] added to the router as an [`axum::Extension`] (or
+/// [`ClientIpSource::Peer`] if there is none).
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct ClientIp(pub IpAddr);
+
+#[async_trait]
+impl<S: Send + Sync> FromRequestParts<S> for ClientIp {
+    type Rejection = (StatusCode, &'static str);
+
+    async fn from_request_parts(parts: &mut Parts, _state: &S) -> Result<Self, Self::Rejection> {
+        client_ip(&parts.headers, &parts.extensions)
+            .map(Self)
+            .ok_or((
+                StatusCode::INTERNAL_SERVER_ERROR,
+                "Unable to determine the client IP address",
+            ))
+    }
+}
+
+/// The client IP address of a request with the specified `headers` and `extensions`, see
+/// [`ClientIp`].
+#[must_use]
+pub fn client_ip(headers: &HeaderMap, extensions: &Extensions) -> Option<IpAddr> {
+    extensions
+        .get::<ClientIpSource>()
+        .copied()
+        .unwrap_or_default()
+        .client_ip(headers, extensions)
+}
+
+#[cfg(test)]
+mod test {
+    use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+
+    use axum::{
+        extract::ConnectInfo,
+        http::{Extensions, HeaderMap, HeaderValue},
+    };
+
+    use super::ClientIpSource;
+
+    #[test]
+    fn test_client_ip() {
+        let peer = IpAddr::V4(Ipv4Addr::new(10, 0, 0, 1));
+        let mut extensions = Extensions::new();
+        extensions.insert(ConnectInfo(SocketAddr::new(peer, 8080)));
+
+        let mut headers = HeaderMap::new();
+        headers.insert("fly-client-ip", HeaderValue::from_static("203.0.113.1"));
+        headers.append("x-forwarded-for", HeaderValue::from_static("192.0.2.1"));
+        headers.append(
+            "x-forwarded-for",
+            HeaderValue::from_static("192.0.2.2, 203.0.113.2"),
+        );
+
+        let ip = |source: ClientIpSource, headers: &HeaderMap| {
+            source.client_ip(headers, &extensions).unwrap().to_string()
+        };
+        assert_eq!("10.0.0.1", ip(ClientIpSource::Peer, &headers));
+        assert_eq!("203.0.113.1", ip(ClientIpSource::FlyClientIp, &headers));
+        assert_eq!("203.0.113.2", ip(ClientIpSource::XForwardedFor, &headers));
+
+        let mut headers = HeaderMap::new();
+        assert_eq!("10.0.0.1", ip(ClientIpSource::FlyClientIp, &headers));
+        headers.insert("fly-client-ip", HeaderValue::from_static("invalid"));
+        assert_eq!("10.0.0.1", ip(ClientIpSource::FlyClientIp, &headers));
+        assert_eq!("10.0.0.1", ip(ClientIpSource::XForwardedFor, &headers));
+    }
+}
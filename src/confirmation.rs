@@ -0,0 +1,259 @@
+//! Confirmation of the address of plain email senders (double opt-in), so that the service can't
+//! be used to send replies to addresses which didn't request them, see [`SenderConfirmations`].
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use rand::Rng;
+use rusqlite::{params, OptionalExtension};
+
+use crate::store::Store;
+
+/// A confirmation request is not sent again to a sender until this much time has passed since the
+/// previous one, so that forged requests can't be used to flood an address with confirmation
+/// requests.
+const RESEND_INTERVAL: chrono::Duration = chrono::Duration::hours(1);
+
+/// After this many incorrect codes, further codes are ignored until the confirmation request is
+/// sent again (see [`RESEND_INTERVAL`]) with a new code, so that the code can't be guessed.
+const MAX_FAILED_ATTEMPTS: u32 = 5;
+
+/// Reply sent when a sender confirms their address.
+pub const CONFIRMED_MESSAGE: &str =
+    "Thank you, your address has been confirmed. You can now send forecast requests.";
+
+/// Reply sent to a sender who needs to confirm their address using `code`.
+#[must_use]
+pub fn required_message(code: u32) -> String {
+    format!(
+        "To prevent this service from being used to send unsolicited email, please confirm your \
+        address by replying with:\n\
+        \n\
+        CONFIRM {code}\n\
+        \n\
+        Your request has not been processed, please send it again once your address has been \
+        confirmed."
+    )
+}
+
+/// Whether a sender has confirmed their address, see [`SenderConfirmations::check()`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Confirmation {
+    /// The sender has previously confirmed their address.
+    Confirmed,
+    /// The sender confirmed their address with this message.
+    NewlyConfirmed,
+    /// The sender needs to confirm their address by replying with `code`. `send` is `false` when
+    /// a confirmation request was sent recently (see [`RESEND_INTERVAL`]), and no reply should be
+    /// sent.
+    Required {
+        /// Code that the sender needs to reply with.
+        code: u32,
+        /// Whether to send the confirmation request.
+        send: bool,
+    },
+}
+
+/// Generate a new six digit confirmation code.
+pub(crate) fn generate_code() -> u32 {
+    rand::thread_rng().gen_range(100_000..1_000_000)
+}
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS senders (
+    sender TEXT PRIMARY KEY,
+    code INTEGER,
+    requested_at TEXT NOT NULL,
+    confirmed_at TEXT,
+    failed_attempts INTEGER NOT NULL DEFAULT 0
+);";
+
+/// Records which senders have confirmed their address in a SQLite database. Senders are
+/// identified by the hash of their address, see [`crate::forget::sender_hash()`].
+pub struct SenderConfirmations {
+    store: Store,
+}
+
+impl SenderConfirmations {
+    /// Open (or create) the database at `path`.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open("sender confirmations", path, SCHEMA)?,
+        })
+    }
+
+    /// Open a database which is stored in memory, and lost when it is dropped.
+    pub fn open_in_memory() -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open_in_memory("sender confirmations", SCHEMA)?,
+        })
+    }
+
+    /// The database that the sender confirmations are stored in.
+    #[cfg(test)]
+    pub(crate) fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Check whether `sender` has confirmed their address, confirming it if they replied with the
+    /// correct `code`, otherwise generating a code for them to confirm with. Incorrect codes are
+    /// counted, see [`MAX_FAILED_ATTEMPTS`].
+    pub fn check(
+        &self,
+        sender: &str,
+        code: Option<u32>,
+        now: DateTime<Utc>,
+    ) -> eyre::Result<Confirmation> {
+        let connection = self.store.connection();
+        let row: Option<(Option<u32>, String, Option<String>, u32)> = connection
+            .query_row(
+                "SELECT code, requested_at, confirmed_at, failed_attempts FROM senders \
+                WHERE sender = ?1",
+                [sender],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?, row.get(3)?)),
+            )
+            .optional()
+            .wrap_err("Unable to query sender confirmation")?;
+
+        match row {
+            Some((_, _, Some(_), _)) => Ok(Confirmation::Confirmed),
+            Some((Some(expected), requested_at, None, mut failed_attempts)) => {
+                let locked = failed_attempts >= MAX_FAILED_ATTEMPTS;
+                if let Some(code) = code.filter(|_| !locked) {
+                    if code == expected {
+                        connection.execute(
+                            "UPDATE senders SET code = NULL, confirmed_at = ?2 WHERE sender = ?1",
+                            params![sender, now.to_rfc3339()],
+                        )?;
+                        return Ok(Confirmation::NewlyConfirmed);
+                    }
+                    failed_attempts += 1;
+                    connection.execute(
+                        "UPDATE senders SET failed_attempts = ?2 WHERE sender = ?1",
+                        params![sender, failed_attempts],
+                    )?;
+                }
+                let requested_at = DateTime::parse_from_rfc3339(&requested_at)
+                    .wrap_err("Invalid confirmation request time")?
+                    .with_timezone(&Utc);
+                let send = now - requested_at >= RESEND_INTERVAL;
+                if !send {
+                    return Ok(Confirmation::Required {
+                        code: expected,
+                        send,
+                    });
+                }
+                // The code is replaced if it may have been guessed, and the attempts are counted
+                // again for the new confirmation request.
+                let code = if failed_attempts >= MAX_FAILED_ATTEMPTS {
+                    generate_code()
+                } else {
+                    expected
+                };
+                connection.execute(
+                    "UPDATE senders SET code = ?2, requested_at = ?3, failed_attempts = 0 \
+                    WHERE sender = ?1",
+                    params![sender, code, now.to_rfc3339()],
+                )?;
+                Ok(Confirmation::Required { code, send })
+            }
+            _ => {
+                let code = generate_code();
+                connection.execute(
+                    "INSERT OR REPLACE INTO senders (sender, code, requested_at) \
+                    VALUES (?1, ?2, ?3)",
+                    params![sender, code, now.to_rfc3339()],
+                )?;
+                Ok(Confirmation::Required { code, send: true })
+            }
+        }
+    }
+
+    /// Erase the confirmation for `sender`, returns the number of records erased.
+    pub fn forget(&self, sender: &str) -> eyre::Result<u64> {
+        self.store.forget(sender, &["senders"])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::time::test_now as now;
+
+    use super::{Confirmation, SenderConfirmations, MAX_FAILED_ATTEMPTS};
+
+    #[test]
+    fn test_check() {
+        let confirmations = SenderConfirmations::open_in_memory().unwrap();
+        let code = match confirmations.check("sha256:aaaa", None, now()).unwrap() {
+            Confirmation::Required { code, send: true } => code,
+            confirmation => panic!("Unexpected {:?}", confirmation),
+        };
+        assert!((100_000..1_000_000).contains(&code));
+
+        // Not sent again until the resend interval has elapsed.
+        assert_eq!(
+            Confirmation::Required { code, send: false },
+            confirmations
+                .check("sha256:aaaa", Some(code + 1), now() + Duration::minutes(5))
+                .unwrap()
+        );
+        assert_eq!(
+            Confirmation::Required { code, send: true },
+            confirmations
+                .check("sha256:aaaa", None, now() + Duration::hours(2))
+                .unwrap()
+        );
+
+        assert_eq!(
+            Confirmation::NewlyConfirmed,
+            confirmations
+                .check("sha256:aaaa", Some(code), now())
+                .unwrap()
+        );
+        assert_eq!(
+            Confirmation::Confirmed,
+            confirmations.check("sha256:aaaa", None, now()).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_check_failed_attempts() {
+        let confirmations = SenderConfirmations::open_in_memory().unwrap();
+        let code = match confirmations.check("sha256:aaaa", None, now()).unwrap() {
+            Confirmation::Required { code, .. } => code,
+            confirmation => panic!("Unexpected {:?}", confirmation),
+        };
+        let wrong_code = if code == 100_000 { 100_001 } else { 100_000 };
+        for _ in 0..MAX_FAILED_ATTEMPTS {
+            assert_eq!(
+                Confirmation::Required { code, send: false },
+                confirmations
+                    .check("sha256:aaaa", Some(wrong_code), now())
+                    .unwrap()
+            );
+        }
+
+        // The correct code is ignored after too many incorrect codes.
+        assert_eq!(
+            Confirmation::Required { code, send: false },
+            confirmations
+                .check("sha256:aaaa", Some(code), now())
+                .unwrap()
+        );
+
+        // A new code is sent once the resend interval has elapsed.
+        let later = now() + Duration::hours(2);
+        let new_code = match confirmations.check("sha256:aaaa", None, later).unwrap() {
+            Confirmation::Required { code, send: true } => code,
+            confirmation => panic!("Unexpected {:?}", confirmation),
+        };
+        assert_eq!(
+            Confirmation::NewlyConfirmed,
+            confirmations
+                .check("sha256:aaaa", Some(new_code), later)
+                .unwrap()
+        );
+    }
+}
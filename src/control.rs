@@ -0,0 +1,73 @@
+//! Controls which allow operators to wake the service's tasks early (e.g. while debugging),
+//! instead of waiting for them to finish sleeping, see [`Controls`].
+
+use std::sync::Arc;
+
+use axum::{middleware, response::Redirect, routing::post, Router};
+use tokio::sync::{futures::Notified, Notify};
+
+use crate::auth::{require_session, AdminAuth};
+
+/// Wakes the tasks which are sleeping between polls of the inbox, or between retries of replies.
+#[derive(Debug, Default)]
+pub struct Controls {
+    poll: Notify,
+    flush: Notify,
+}
+
+impl Controls {
+    /// Construct a new [`Controls`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Poll the inbox now, instead of waiting for [`crate::options::DynamicOptions::poll_interval`]
+    /// to elapse. If the inbox is currently being polled, it is polled again immediately after.
+    pub fn poll_now(&self) {
+        tracing::info!("Inbox poll requested");
+        self.poll.notify_one();
+    }
+
+    /// Completes when [`Controls::poll_now()`] is called.
+    pub fn poll_requested(&self) -> Notified<'_> {
+        self.poll.notified()
+    }
+
+    /// Retry the replies which are waiting to be retried now, instead of waiting for their backoff
+    /// to elapse, so that the queues are processed.
+    pub fn flush_queues(&self) {
+        tracing::info!("Queue flush requested");
+        self.flush.notify_waiters();
+    }
+
+    /// Completes when [`Controls::flush_queues()`] is called.
+    pub fn flush_requested(&self) -> Notified<'_> {
+        self.flush.notified()
+    }
+}
+
+/// Routes for operating the [`Controls`], which require an admin session (see [`AdminAuth`]).
+/// Both redirect to the logs index page, where they are presented as buttons:
+///
+/// + `POST /poll` polls the inbox now, see [`Controls::poll_now()`].
+/// + `POST /flush` retries the replies waiting to be retried now, see
+///   [`Controls::flush_queues()`].
+pub fn control_routes(controls: &'static Controls, auth: Arc<AdminAuth>) -> Router {
+    Router::new()
+        .route(
+            "/poll",
+            post(move || async move {
+                controls.poll_now();
+                Redirect::to("/logs/")
+            }),
+        )
+        .route(
+            "/flush",
+            post(move || async move {
+                controls.flush_queues();
+                Redirect::to("/logs/")
+            }),
+        )
+        .route_layer(middleware::from_fn_with_state(auth, require_session))
+}
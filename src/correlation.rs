@@ -0,0 +1,121 @@
+//! Correlation of log entries and replies with the email request that caused them, see
+//! [`RequestId`].
+
+use std::fmt::Display;
+
+use chrono::{DateTime, Utc};
+use lettre::message::header::{Header, HeaderName, HeaderValue};
+use serde::{Deserialize, Serialize};
+use uuid::Uuid;
+
+/// Identifier generated when an email is received, which follows the request through the
+/// processing and reply queues. It is included in log entries for the request, and in the reply,
+/// so that a report from a user can be matched to the logs.
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Hash, Serialize, Deserialize)]
+#[serde(transparent)]
+pub struct RequestId(Uuid);
+
+impl RequestId {
+    /// Generate a new random [`RequestId`].
+    #[must_use]
+    pub fn new() -> Self {
+        Self(Uuid::new_v4())
+    }
+}
+
+/// Generates a new random [`RequestId`], used for payloads that were queued before request ids
+/// were introduced.
+impl Default for RequestId {
+    fn default() -> Self {
+        Self::new()
+    }
+}
+
+impl Display for RequestId {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        self.0.fmt(f)
+    }
+}
+
+/// A payload stored in a queue, along with the [`RequestId`] of the request that it belongs to.
+#[derive(Debug, Serialize, Deserialize)]
+pub struct Queued<T> {
+    /// Id of the request that this payload belongs to.
+    #[serde(default)]
+    pub request_id: RequestId,
+    /// When the payload was placed in the queue, used to measure the time it spent in the queue,
+    /// see [`crate::latency`]. `None` for payloads queued before this was recorded.
+    #[serde(default)]
+    pub queued_at: Option<DateTime<Utc>>,
+    /// The queued payload.
+    #[serde(flatten)]
+    pub payload: T,
+}
+
+impl<T> Queued<T> {
+    /// Construct a new [`Queued`].
+    pub fn new(request_id: RequestId, payload: T) -> Self {
+        Self {
+            request_id,
+            queued_at: None,
+            payload,
+        }
+    }
+
+    /// Record when the payload was placed in the queue.
+    #[must_use]
+    pub fn with_queued_at(mut self, queued_at: DateTime<Utc>) -> Self {
+        self.queued_at = Some(queued_at);
+        self
+    }
+}
+
+/// `X-Request-Id` email header, used to include the [`RequestId`] in plain email replies.
+#[derive(Clone, Debug)]
+pub struct XRequestId(pub RequestId);
+
+impl Header for XRequestId {
+    fn name() -> HeaderName {
+        HeaderName::new_from_ascii_str("X-Request-Id")
+    }
+
+    fn parse(s: &str) -> Result<Self, Box<dyn std::error::Error + Send + Sync>> {
+        Ok(Self(RequestId(s.trim().parse()?)))
+    }
+
+    fn display(&self) -> HeaderValue {
+        HeaderValue::new(Self::name(), self.0.to_string())
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{TimeZone, Utc};
+    use serde::{Deserialize, Serialize};
+
+    use super::{Queued, RequestId};
+
+    #[derive(Debug, PartialEq, Serialize, Deserialize)]
+    enum Payload {
+        Message(String),
+    }
+
+    #[test]
+    fn test_queued_roundtrip() {
+        let queued = Queued::new(RequestId::new(), Payload::Message("hello".to_string()))
+            .with_queued_at(Utc.with_ymd_and_hms(2022, 12, 3, 4, 5, 6).unwrap());
+        let json = serde_json::to_string(&queued).unwrap();
+        let deserialized: Queued<Payload> = serde_json::from_str(&json).unwrap();
+        assert_eq!(queued.request_id, deserialized.request_id);
+        assert_eq!(queued.queued_at, deserialized.queued_at);
+        assert_eq!(queued.payload, deserialized.payload);
+    }
+
+    #[test]
+    fn test_queued_without_request_id() {
+        let deserialized: Queued<Payload> =
+            serde_json::from_str(r#"{"Message": "hello"}"#).unwrap();
+        assert_eq!(Payload::Message("hello".to_string()), deserialized.payload);
+        assert_eq!(None, deserialized.queued_at);
+    }
+}
@@ -0,0 +1,103 @@
+//! Suppression of duplicate requests, which senders commonly send when they think that their first
+//! request failed (e.g. because the reply is slow to arrive on a satellite device), so that the
+//! forecast is only obtained and sent once, see [`Duplicates`].
+//!
+//! A request is a duplicate if a forecast was sent for an identical request (the same position,
+//! options and additional requests) from the same sender within the window
+//! [`DynamicOptions::duplicate_window`](crate::options::DynamicOptions::duplicate_window).
+
+use std::collections::HashMap;
+
+use chrono::{DateTime, Duration, Utc};
+
+use crate::receive::{Received, ReceivedKind};
+
+/// Identifies the contents of a request, ignoring everything about the email which carried it
+/// (e.g. the message id), so that identical requests have the same key.
+#[must_use]
+pub fn request_key(received: &ReceivedKind) -> Option<String> {
+    serde_json::to_string(&(
+        received.position(),
+        received.forecast_request(),
+        received.additional_requests(),
+    ))
+    .ok()
+}
+
+/// The time at which a forecast was last sent for each request (identified by the sender and the
+/// [`request_key()`]). The times are stored in memory, so they are forgotten when the service is
+/// restarted.
+#[derive(Debug, Default)]
+pub struct Duplicates {
+    answered: HashMap<(String, String), DateTime<Utc>>,
+}
+
+impl Duplicates {
+    /// If the request with `key` from `sender` is a duplicate of a request answered within
+    /// `window` before `now`, returns the time at which that request was answered. Requests
+    /// answered before the window are forgotten.
+    pub fn find(
+        &mut self,
+        sender: &str,
+        key: &str,
+        window: Duration,
+        now: DateTime<Utc>,
+    ) -> Option<DateTime<Utc>> {
+        self.answered
+            .retain(|_, answered_at| now - *answered_at <= window);
+        self.answered
+            .get(&(sender.to_owned(), key.to_owned()))
+            .copied()
+    }
+
+    /// Record that the request with `key` from `sender` was answered at `now`.
+    pub fn record(&mut self, sender: String, key: String, now: DateTime<Utc>) {
+        self.answered.insert((sender, key), now);
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::{
+        gis::Position, inreach, receive::ReceivedKind, request::ParsedForecastRequest,
+        time::test_now as now,
+    };
+
+    use super::{request_key, Duplicates};
+
+    fn received(request: &str) -> ReceivedKind {
+        ReceivedKind::Inreach(inreach::email::Received {
+            from_name: "Luke Frisken".to_owned(),
+            referral_url: "https://example.com/textmessage/txtmsg?extId=1"
+                .parse()
+                .unwrap(),
+            mapshare_url: None,
+            device_id: None,
+            sent_at: None,
+            position: Position::new(-43.5, 170.3),
+            forecast_request: ParsedForecastRequest::parse(request),
+        })
+    }
+
+    #[test]
+    fn test_find_record() {
+        let window = Duration::minutes(5);
+        let key = request_key(&received("-43.5,170.3 ML")).unwrap();
+        assert_eq!(key, request_key(&received("-43.5,170.3 ML")).unwrap());
+        assert_ne!(key, request_key(&received("-43.5,170.3 MS")).unwrap());
+
+        let mut duplicates = Duplicates::default();
+        assert_eq!(None, duplicates.find("sender", &key, window, now()));
+        duplicates.record("sender".to_owned(), key.clone(), now());
+
+        let later = now() + Duration::minutes(3);
+        assert_eq!(Some(now()), duplicates.find("sender", &key, window, later));
+        assert_eq!(None, duplicates.find("other", &key, window, later));
+
+        let expired = now() + Duration::minutes(6);
+        assert_eq!(None, duplicates.find("sender", &key, window, expired));
+        assert_eq!(None, duplicates.find("sender", &key, window, later));
+    }
+}
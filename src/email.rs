@@ -94,6 +94,16 @@ impl<'a> TryFrom<&mail_parser::Addr<'a>> for Account {
         Ok(Self(lettre::message::Mailbox { name, email }))
     }
 }
+impl schemars::JsonSchema for Account {
+    fn schema_name() -> String {
+        "Account".to_string()
+    }
+
+    fn json_schema(gen: &mut schemars::gen::SchemaGenerator) -> schemars::schema::Schema {
+        String::json_schema(gen)
+    }
+}
+
 impl Display for Account {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         self.0.fmt(f)
@@ -0,0 +1,438 @@
+//! Encryption of plain email replies using [OpenPGP](https://www.rfc-editor.org/rfc/rfc4880), for
+//! senders who don't want their forecasts (and the positions they reveal) to be readable by the
+//! email providers and satellite gateways that relay them, see [`PublicKeys`].
+//!
+//! A sender registers their public key by sending the
+//! [`Command::Key`](crate::request::Command::Key) command with the ASCII armored key pasted below
+//! it, or attached to the email. `KEY OFF` removes the key. Because the `From` header of an email
+//! can be forged, the change is only made once the sender replies with the code sent to their
+//! address using [`Command::KeyConfirm`](crate::request::Command::KeyConfirm). Every reply sent to
+//! them afterwards (starting with the reply to `KEY <code>`) is encrypted to the key, and sent as
+//! a [PGP/MIME](https://www.rfc-editor.org/rfc/rfc3156) message, including the html version and
+//! attachments. Keys are erased along with the rest of the sender's data, see [`crate::forget`].
+
+use std::path::Path;
+
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use lettre::message::{header::ContentType, MultiPart, SinglePart};
+use pgp::{
+    crypto::sym::SymmetricKeyAlgorithm,
+    types::{KeyTrait, PublicKeyTrait},
+    Deserializable, Message, SignedPublicKey,
+};
+use rusqlite::{params, OptionalExtension};
+
+use crate::{confirmation::generate_code, reply, store::Store};
+
+const BEGIN_PUBLIC_KEY: &str = "-----BEGIN PGP PUBLIC KEY BLOCK-----";
+const END_PUBLIC_KEY: &str = "-----END PGP PUBLIC KEY BLOCK-----";
+
+/// A change to a sender's public key is discarded if it is not confirmed within this time.
+const PENDING_EXPIRY: chrono::Duration = chrono::Duration::hours(24);
+
+/// Reply sent when a sender removes their key using `KEY OFF`.
+pub const REMOVED_MESSAGE: &str =
+    "Your public key has been removed, replies will no longer be encrypted.";
+
+/// Reply sent when a sender sends `KEY` without a public key.
+pub const MISSING_KEY_MESSAGE: &str = "To encrypt your replies, send KEY with your ASCII armored \
+    PGP public key pasted below it, or attached to the email.";
+
+/// Sent instead of a reply which could not be encrypted, rather than sending it unencrypted.
+pub const ENCRYPTION_FAILED_MESSAGE: &str = "Your reply could not be encrypted to your public \
+    key, so it has not been sent. Send KEY with a new public key, or KEY OFF to receive \
+    unencrypted replies.";
+
+/// Reply sent when a sender confirms a change to their key with an incorrect or expired code.
+pub const INVALID_CODE_MESSAGE: &str = "The code is incorrect or has expired, your public key \
+    has not been changed. Send KEY or KEY OFF again to receive a new code.";
+
+/// Reply sent to a sender who needs to confirm the registration of the public key with
+/// `fingerprint` (or the removal of their key if it is `None`) using `code`.
+#[must_use]
+pub fn confirm_message(fingerprint: Option<&str>, code: u32) -> String {
+    let change = match fingerprint {
+        Some(fingerprint) => format!("register your public key {fingerprint}"),
+        None => "remove your public key".to_owned(),
+    };
+    format!("To {change}, reply with KEY {code} within 24 hours.")
+}
+
+/// Reply sent when a sender registers the public key with `fingerprint`.
+#[must_use]
+pub fn registered_message(fingerprint: &str) -> String {
+    format!(
+        "Your public key {fingerprint} has been registered, this and all future replies will be \
+        encrypted to it. Send KEY OFF to receive unencrypted replies."
+    )
+}
+
+/// The first ASCII armored public key block in `text`, if there is one.
+#[must_use]
+pub fn extract_public_key(text: &str) -> Option<&str> {
+    let start = text.find(BEGIN_PUBLIC_KEY)?;
+    let end = text[start..].find(END_PUBLIC_KEY)? + start + END_PUBLIC_KEY.len();
+    Some(&text[start..end])
+}
+
+/// Parse and verify the ASCII `armored` public key, which is required to have a key which can be
+/// used for encryption.
+fn parse_public_key(armored: &str) -> eyre::Result<SignedPublicKey> {
+    let (key, _headers) =
+        SignedPublicKey::from_string(armored).wrap_err("Invalid PGP public key")?;
+    key.verify()
+        .wrap_err("PGP public key could not be verified")?;
+    if !key.is_encryption_key()
+        && !key
+            .public_subkeys
+            .iter()
+            .any(|subkey| subkey.is_encryption_key())
+    {
+        return Err(eyre::eyre!("PGP public key cannot be used for encryption"));
+    }
+    Ok(key)
+}
+
+/// The fingerprint (in hexadecimal) of the ASCII `armored` public key, or an error if it is not a
+/// valid key which can be used for encryption.
+pub fn fingerprint(armored: &str) -> eyre::Result<String> {
+    let key = parse_public_key(armored)?;
+    Ok(key
+        .fingerprint()
+        .iter()
+        .map(|byte| format!("{byte:02X}"))
+        .collect())
+}
+
+fn encrypt_to(key: &impl PublicKeyTrait, data: &[u8]) -> eyre::Result<String> {
+    Message::new_literal_bytes("", data)
+        .encrypt_to_keys(
+            &mut rand::thread_rng(),
+            SymmetricKeyAlgorithm::AES256,
+            &[key],
+        )
+        .wrap_err("Error encrypting message")?
+        .to_armored_string(None)
+        .wrap_err("Error armoring encrypted message")
+}
+
+/// Encrypt `data` to the ASCII `armored` public key, preferring its encryption subkey. Returns the
+/// ASCII armored encrypted message.
+pub fn encrypt(armored: &str, data: &[u8]) -> eyre::Result<String> {
+    let key = parse_public_key(armored)?;
+    match key
+        .public_subkeys
+        .iter()
+        .find(|subkey| subkey.is_encryption_key())
+    {
+        Some(subkey) => encrypt_to(subkey, data),
+        None => encrypt_to(&key, data),
+    }
+}
+
+/// Encrypt the message, html message and attachments of the `plain` reply to the ASCII `armored`
+/// public key, see [`reply::Plain::encrypted`]. The subject from the template (which may contain
+/// the position and weather) is also removed.
+pub fn encrypt_reply(plain: &mut reply::Plain, armored: &str) -> eyre::Result<()> {
+    let body = plain.multipart(plain.html_message.clone())?.formatted();
+    plain.encrypted = Some(encrypt(armored, &body)?);
+    plain.plain_message = "This message is encrypted".to_owned();
+    plain.html_message = None;
+    plain.attachments = Vec::new();
+    plain.reply_subject = None;
+    Ok(())
+}
+
+/// The PGP/MIME body for the ASCII `armored` encrypted message.
+pub(crate) fn mime_body(armored: &str) -> eyre::Result<MultiPart> {
+    Ok(MultiPart::encrypted("application/pgp-encrypted".to_owned())
+        .singlepart(
+            SinglePart::builder()
+                .header(ContentType::parse("application/pgp-encrypted")?)
+                .body("Version: 1".to_owned()),
+        )
+        .singlepart(
+            SinglePart::builder()
+                .header(ContentType::parse(
+                    "application/octet-stream; name=\"encrypted.asc\"",
+                )?)
+                .body(armored.to_owned()),
+        ))
+}
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS keys (
+    sender TEXT PRIMARY KEY,
+    key TEXT NOT NULL,
+    registered_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS pending_keys (
+    sender TEXT PRIMARY KEY,
+    key TEXT,
+    code INTEGER NOT NULL,
+    requested_at TEXT NOT NULL
+);";
+
+/// A change to a sender's public key, made once it is confirmed, see [`PublicKeys::confirm()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum KeyChange {
+    /// Register the ASCII armored public key.
+    Register(String),
+    /// Remove the registered key.
+    Remove,
+}
+
+/// Records the public keys registered by senders in a SQLite database. Senders are identified by
+/// the hash of their address, see [`crate::forget::sender_hash()`].
+pub struct PublicKeys {
+    store: Store,
+}
+
+impl PublicKeys {
+    /// Open (or create) the database at `path`.
+    pub fn open(path: &Path) -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open("public keys", path, SCHEMA)?,
+        })
+    }
+
+    /// Open a database which is stored in memory, and lost when it is dropped.
+    pub fn open_in_memory() -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open_in_memory("public keys", SCHEMA)?,
+        })
+    }
+
+    /// The database that the public keys are stored in.
+    #[cfg(test)]
+    pub(crate) fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// Register the ASCII armored public `key` of `sender`, replacing any previous key.
+    pub fn register(&self, sender: &str, key: &str, now: DateTime<Utc>) -> eyre::Result<()> {
+        self.store
+            .connection()
+            .execute(
+                "INSERT OR REPLACE INTO keys (sender, key, registered_at) VALUES (?1, ?2, ?3)",
+                params![sender, key, now.to_rfc3339()],
+            )
+            .wrap_err("Unable to register public key")?;
+        Ok(())
+    }
+
+    /// Record the `change` to the key of `sender`, which is made once they confirm it with the
+    /// returned code (see [`PublicKeys::confirm()`]), replacing any previous unconfirmed change.
+    pub fn request(
+        &self,
+        sender: &str,
+        change: &KeyChange,
+        now: DateTime<Utc>,
+    ) -> eyre::Result<u32> {
+        let code = generate_code();
+        let key = match change {
+            KeyChange::Register(key) => Some(key.as_str()),
+            KeyChange::Remove => None,
+        };
+        self.store
+            .connection()
+            .execute(
+                "INSERT OR REPLACE INTO pending_keys (sender, key, code, requested_at) \
+                VALUES (?1, ?2, ?3, ?4)",
+                params![sender, key, code, now.to_rfc3339()],
+            )
+            .wrap_err("Unable to record public key change")?;
+        Ok(code)
+    }
+
+    /// Make the change to the key of `sender` requested with [`PublicKeys::request()`], if `code`
+    /// is correct and the change hasn't expired. The change is discarded after a single
+    /// incorrect code, so that the code can't be guessed. Returns the change which was made.
+    pub fn confirm(
+        &self,
+        sender: &str,
+        code: u32,
+        now: DateTime<Utc>,
+    ) -> eyre::Result<Option<KeyChange>> {
+        let mut connection = self.store.connection();
+        let transaction = connection.transaction()?;
+        let pending: Option<(Option<String>, u32, String)> = transaction
+            .query_row(
+                "SELECT key, code, requested_at FROM pending_keys WHERE sender = ?1",
+                [sender],
+                |row| Ok((row.get(0)?, row.get(1)?, row.get(2)?)),
+            )
+            .optional()
+            .wrap_err("Unable to query public key change")?;
+        transaction
+            .execute("DELETE FROM pending_keys WHERE sender = ?1", [sender])
+            .wrap_err("Unable to erase public key change")?;
+
+        let change = match pending {
+            Some((key, pending_code, requested_at)) if pending_code == code => {
+                let requested_at = DateTime::parse_from_rfc3339(&requested_at)
+                    .wrap_err("Invalid public key change time")?;
+                let change = match key {
+                    Some(key) => KeyChange::Register(key),
+                    None => KeyChange::Remove,
+                };
+                (now.signed_duration_since(requested_at) < PENDING_EXPIRY).then_some(change)
+            }
+            _ => None,
+        };
+        match &change {
+            Some(KeyChange::Register(key)) => {
+                transaction
+                    .execute(
+                        "INSERT OR REPLACE INTO keys (sender, key, registered_at) \
+                        VALUES (?1, ?2, ?3)",
+                        params![sender, key, now.to_rfc3339()],
+                    )
+                    .wrap_err("Unable to register public key")?;
+            }
+            Some(KeyChange::Remove) => {
+                transaction
+                    .execute("DELETE FROM keys WHERE sender = ?1", [sender])
+                    .wrap_err("Unable to remove public key")?;
+            }
+            None => {}
+        }
+        transaction.commit()?;
+        Ok(change)
+    }
+
+    /// The public key registered by `sender`, `None` if they haven't registered one.
+    pub fn key(&self, sender: &str) -> eyre::Result<Option<String>> {
+        self.store
+            .connection()
+            .query_row("SELECT key FROM keys WHERE sender = ?1", [sender], |row| {
+                row.get(0)
+            })
+            .optional()
+            .wrap_err("Unable to query public key")
+    }
+
+    /// Erase the public key of `sender` and any unconfirmed change to it, returns the number of
+    /// records erased.
+    pub fn forget(&self, sender: &str) -> eyre::Result<u64> {
+        self.store.forget(sender, &["keys", "pending_keys"])
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+    use once_cell::sync::Lazy;
+
+    use crate::time::test_now as now;
+
+    use super::{encrypt, extract_public_key, fingerprint, KeyChange, PublicKeys};
+
+    static PUBLIC_KEY: Lazy<String> =
+        Lazy::new(|| std::fs::read_to_string("fixtures/pgp_public_key.asc").unwrap());
+
+    #[test]
+    fn test_extract_public_key() {
+        let text = format!("KEY\n\n{}\n\nSent from my phone", PUBLIC_KEY.trim());
+        assert_eq!(Some(PUBLIC_KEY.trim()), extract_public_key(&text));
+        assert_eq!(None, extract_public_key("KEY"));
+        assert_eq!(
+            None,
+            extract_public_key("-----BEGIN PGP PUBLIC KEY BLOCK-----\ntruncated")
+        );
+    }
+
+    #[test]
+    fn test_encrypt() {
+        assert_eq!(
+            "79CB2D0C5DEFA581FD7B05ACDE1BA7BB01478152",
+            fingerprint(&PUBLIC_KEY).unwrap()
+        );
+        assert!(fingerprint("not a key").is_err());
+
+        let encrypted = encrypt(&PUBLIC_KEY, b"03T21 <T5>").unwrap();
+        assert!(
+            encrypted.starts_with("-----BEGIN PGP MESSAGE-----"),
+            "{encrypted}"
+        );
+        assert!(!encrypted.contains("<T5>"));
+    }
+
+    #[test]
+    fn test_public_keys() {
+        let public_keys = PublicKeys::open_in_memory().unwrap();
+        assert_eq!(None, public_keys.key("sha256:aaaa").unwrap());
+        public_keys.register("sha256:aaaa", "first", now()).unwrap();
+        public_keys
+            .register("sha256:aaaa", "second", now())
+            .unwrap();
+        assert_eq!(
+            Some("second".to_owned()),
+            public_keys.key("sha256:aaaa").unwrap()
+        );
+        assert_eq!(None, public_keys.key("sha256:bbbb").unwrap());
+    }
+
+    #[test]
+    fn test_public_keys_confirm() {
+        let public_keys = PublicKeys::open_in_memory().unwrap();
+        let register = KeyChange::Register("first".to_owned());
+        let code = public_keys
+            .request("sha256:aaaa", &register, now())
+            .unwrap();
+        assert_eq!(None, public_keys.key("sha256:aaaa").unwrap());
+        assert_eq!(
+            None,
+            public_keys.confirm("sha256:bbbb", code, now()).unwrap()
+        );
+        assert_eq!(
+            Some(register),
+            public_keys.confirm("sha256:aaaa", code, now()).unwrap()
+        );
+        assert_eq!(
+            Some("first".to_owned()),
+            public_keys.key("sha256:aaaa").unwrap()
+        );
+        // The code can only be used once.
+        assert_eq!(
+            None,
+            public_keys.confirm("sha256:aaaa", code, now()).unwrap()
+        );
+
+        // The change is discarded after an incorrect code.
+        let code = public_keys
+            .request("sha256:aaaa", &KeyChange::Remove, now())
+            .unwrap();
+        let wrong_code = if code == 100_000 { 100_001 } else { 100_000 };
+        assert_eq!(
+            None,
+            public_keys
+                .confirm("sha256:aaaa", wrong_code, now())
+                .unwrap()
+        );
+        assert_eq!(
+            None,
+            public_keys.confirm("sha256:aaaa", code, now()).unwrap()
+        );
+
+        // The change expires.
+        let code = public_keys
+            .request("sha256:aaaa", &KeyChange::Remove, now())
+            .unwrap();
+        assert_eq!(
+            None,
+            public_keys
+                .confirm("sha256:aaaa", code, now() + Duration::hours(25))
+                .unwrap()
+        );
+
+        let code = public_keys
+            .request("sha256:aaaa", &KeyChange::Remove, now())
+            .unwrap();
+        assert_eq!(
+            Some(KeyChange::Remove),
+            public_keys.confirm("sha256:aaaa", code, now()).unwrap()
+        );
+        assert_eq!(None, public_keys.key("sha256:aaaa").unwrap());
+    }
+}
@@ -0,0 +1,341 @@
+//! An opt-in [Atom](https://datatracker.ietf.org/doc/html/rfc4287) feed of the forecasts generated
+//! for a sender, so that they can be archived or consumed outside of email, see [`Feeds`].
+//!
+//! A sender subscribes using the [`Command::Feed`](crate::request::Command::Feed) command, and
+//! is sent the url of their feed, which is served by [`feed_routes()`]. The url contains a random
+//! token which is the only authentication required to read the feed, so it should be kept
+//! private. Feeds are erased along with the rest of the sender's data, see [`crate::forget`].
+
+use std::path::Path;
+
+use axum::{
+    extract::Path as UrlPath,
+    http::{header, StatusCode},
+    response::IntoResponse,
+    routing::get,
+    Router,
+};
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use rand::Rng;
+use rusqlite::{params, OptionalExtension};
+
+use crate::{html::escape, store::Store};
+
+/// Maximum number of entries kept in a sender's feed, older entries are erased.
+pub const MAX_ENTRIES: u32 = 50;
+
+const SCHEMA: &str = "CREATE TABLE IF NOT EXISTS subscribers (
+    sender TEXT PRIMARY KEY,
+    token TEXT UNIQUE NOT NULL,
+    subscribed_at TEXT NOT NULL
+);
+CREATE TABLE IF NOT EXISTS entries (
+    id INTEGER PRIMARY KEY AUTOINCREMENT,
+    sender TEXT NOT NULL,
+    created_at TEXT NOT NULL,
+    message TEXT NOT NULL
+);
+CREATE INDEX IF NOT EXISTS entries_sender ON entries (sender);";
+
+/// A forecast recorded in a sender's feed.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FeedEntry {
+    /// Unique id of the entry.
+    pub id: i64,
+    /// When the forecast was generated.
+    pub created_at: DateTime<Utc>,
+    /// The (plain text) forecast message.
+    pub message: String,
+}
+
+/// A sender's feed, see [`Feeds::feed()`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Feed {
+    /// When the sender subscribed to the feed.
+    pub subscribed_at: DateTime<Utc>,
+    /// The entries in the feed, newest first.
+    pub entries: Vec<FeedEntry>,
+}
+
+fn parse_time(time: &str) -> eyre::Result<DateTime<Utc>> {
+    Ok(DateTime::parse_from_rfc3339(time)
+        .wrap_err("Invalid feed time")?
+        .with_timezone(&Utc))
+}
+
+/// Records the forecasts generated for subscribed senders in a SQLite database. Senders are
+/// identified by the hash of their address, see [`crate::forget::sender_hash()`].
+pub struct Feeds {
+    store: Store,
+    base_url: url::Url,
+}
+
+impl Feeds {
+    /// Open (or create) the database at `path`. Feeds are served relative to `base_url` (see
+    /// [`Feeds::url()`]).
+    pub fn open(path: &Path, base_url: url::Url) -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open("feeds", path, SCHEMA)?,
+            base_url,
+        })
+    }
+
+    /// Open a database which is stored in memory, and lost when it is dropped.
+    pub fn open_in_memory(base_url: url::Url) -> eyre::Result<Self> {
+        Ok(Self {
+            store: Store::open_in_memory("feeds", SCHEMA)?,
+            base_url,
+        })
+    }
+
+    /// The database that the feeds are stored in.
+    #[cfg(test)]
+    pub(crate) fn store(&self) -> &Store {
+        &self.store
+    }
+
+    /// The url of the feed with the specified `token`.
+    pub fn url(&self, token: &str) -> eyre::Result<url::Url> {
+        Ok(self.base_url.join("feed/")?.join(token)?)
+    }
+
+    /// Subscribe `sender` to their feed, returns the token identifying the feed. Subscribing again
+    /// returns the existing token.
+    pub fn subscribe(&self, sender: &str, now: DateTime<Utc>) -> eyre::Result<String> {
+        let connection = self.store.connection();
+        let existing: Option<String> = connection
+            .query_row(
+                "SELECT token FROM subscribers WHERE sender = ?1",
+                [sender],
+                |row| row.get(0),
+            )
+            .optional()
+            .wrap_err("Unable to query feed subscriber")?;
+        if let Some(token) = existing {
+            return Ok(token);
+        }
+        let token = format!("{:032x}", rand::thread_rng().gen::<u128>());
+        connection
+            .execute(
+                "INSERT INTO subscribers (sender, token, subscribed_at) VALUES (?1, ?2, ?3)",
+                params![sender, token, now.to_rfc3339()],
+            )
+            .wrap_err("Unable to subscribe to feed")?;
+        Ok(token)
+    }
+
+    /// Record the forecast `message` in the feed of `sender`, if they are subscribed. Only the
+    /// newest [`MAX_ENTRIES`] are kept. Returns whether the message was recorded.
+    pub fn record(&self, sender: &str, message: &str, now: DateTime<Utc>) -> eyre::Result<bool> {
+        let mut connection = self.store.connection();
+        let transaction = connection.transaction()?;
+        let recorded = transaction
+            .execute(
+                "INSERT INTO entries (sender, created_at, message) \
+                SELECT ?1, ?2, ?3 WHERE EXISTS (SELECT 1 FROM subscribers WHERE sender = ?1)",
+                params![sender, now.to_rfc3339(), message],
+            )
+            .wrap_err("Unable to record feed entry")?;
+        if recorded > 0 {
+            transaction
+                .execute(
+                    "DELETE FROM entries WHERE sender = ?1 AND id NOT IN \
+                    (SELECT id FROM entries WHERE sender = ?1 ORDER BY id DESC LIMIT ?2)",
+                    params![sender, MAX_ENTRIES],
+                )
+                .wrap_err("Unable to erase old feed entries")?;
+        }
+        transaction.commit()?;
+        Ok(recorded > 0)
+    }
+
+    /// The feed identified by `token`, `None` if there is no such feed.
+    pub fn feed(&self, token: &str) -> eyre::Result<Option<Feed>> {
+        let connection = self.store.connection();
+        let subscriber: Option<(String, String)> = connection
+            .query_row(
+                "SELECT sender, subscribed_at FROM subscribers WHERE token = ?1",
+                [token],
+                |row| Ok((row.get(0)?, row.get(1)?)),
+            )
+            .optional()
+            .wrap_err("Unable to query feed subscriber")?;
+        let (sender, subscribed_at) = match subscriber {
+            Some(subscriber) => subscriber,
+            None => return Ok(None),
+        };
+
+        let mut statement = connection.prepare(
+            "SELECT id, created_at, message FROM entries WHERE sender = ?1 ORDER BY id DESC",
+        )?;
+        let entries = statement
+            .query_map([sender], |row| {
+                Ok((row.get(0)?, row.get::<_, String>(1)?, row.get(2)?))
+            })?
+            .map(|row| {
+                let (id, created_at, message) = row.wrap_err("Unable to query feed entry")?;
+                Ok(FeedEntry {
+                    id,
+                    created_at: parse_time(&created_at)?,
+                    message,
+                })
+            })
+            .collect::<eyre::Result<Vec<FeedEntry>>>()?;
+        Ok(Some(Feed {
+            subscribed_at: parse_time(&subscribed_at)?,
+            entries,
+        }))
+    }
+
+    /// Erase the feed of `sender`, returns the number of records erased.
+    pub fn forget(&self, sender: &str) -> eyre::Result<u64> {
+        self.store.forget(sender, &["entries", "subscribers"])
+    }
+}
+
+/// Render `feed`, which is served at `url`, as an Atom feed.
+#[must_use]
+pub fn render_atom(feed: &Feed, url: &url::Url) -> String {
+    let url = escape(url.as_str());
+    let updated = feed
+        .entries
+        .first()
+        .map_or(feed.subscribed_at, |entry| entry.created_at);
+    let mut output = format!(
+        "<?xml version=\"1.0\" encoding=\"utf-8\"?>\n\
+        <feed xmlns=\"http://www.w3.org/2005/Atom\">\n\
+        <id>{url}</id>\n\
+        <title>Weather Forecasts</title>\n\
+        <link rel=\"self\" href=\"{url}\"/>\n\
+        <author><name>email-weather</name></author>\n\
+        <updated>{}</updated>\n",
+        updated.to_rfc3339()
+    );
+    for entry in &feed.entries {
+        let title = entry.message.lines().next().unwrap_or_default();
+        output.push_str(&format!(
+            "<entry>\n\
+            <id>{url}#{}</id>\n\
+            <title>{}</title>\n\
+            <updated>{}</updated>\n\
+            <content type=\"text\">{}</content>\n\
+            </entry>\n",
+            entry.id,
+            escape(title),
+            entry.created_at.to_rfc3339(),
+            escape(&entry.message),
+        ));
+    }
+    output.push_str("</feed>\n");
+    output
+}
+
+/// The message sent in reply to [`Command::Feed`](crate::request::Command::Feed), with the `url`
+/// of the sender's feed. A `short` message fits within the 160 character limit of an inReach
+/// reply.
+#[must_use]
+pub fn subscribed_message(url: &url::Url, short: bool) -> String {
+    if short {
+        format!("Forecast feed: {url}")
+    } else {
+        format!(
+            "Your forecasts will now be published in an Atom feed at:\n\
+            \n\
+            {url}\n\
+            \n\
+            Anyone with this link can read your forecasts, so please keep it private. Send \
+            FORGET ME to erase the feed."
+        )
+    }
+}
+
+/// Routes for serving the feeds in `feeds`:
+///
+/// + `GET /{token}` responds with the Atom feed identified by `token` (see
+///   [`Feeds::subscribe()`]), or `404 Not Found` if there is no such feed.
+pub fn feed_routes(feeds: &'static Feeds) -> Router {
+    Router::new().route(
+        "/:token",
+        get(move |UrlPath(token): UrlPath<String>| async move {
+            let result = tokio::task::spawn_blocking(move || {
+                let feed = feeds.feed(&token)?;
+                feed.map(|feed| Ok(render_atom(&feed, &feeds.url(&token)?)))
+                    .transpose()
+            })
+            .await
+            .map_err(eyre::Error::from)
+            .and_then(|result| result);
+            match result {
+                Ok(Some(atom)) => {
+                    ([(header::CONTENT_TYPE, "application/atom+xml")], atom).into_response()
+                }
+                Ok(None) => StatusCode::NOT_FOUND.into_response(),
+                Err(error) => {
+                    tracing::error!("{:?}", error);
+                    StatusCode::INTERNAL_SERVER_ERROR.into_response()
+                }
+            }
+        }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::time::test_now as now;
+
+    use super::{render_atom, subscribed_message, Feeds, MAX_ENTRIES};
+
+    fn feeds() -> Feeds {
+        Feeds::open_in_memory("https://example.com/".parse().unwrap()).unwrap()
+    }
+
+    #[test]
+    fn test_feed() {
+        let feeds = feeds();
+        assert!(!feeds
+            .record("sha256:aaaa", "Not subscribed", now())
+            .unwrap());
+
+        let token = feeds.subscribe("sha256:aaaa", now()).unwrap();
+        assert_eq!(32, token.len());
+        assert_eq!(token, feeds.subscribe("sha256:aaaa", now()).unwrap());
+        assert_ne!(token, feeds.subscribe("sha256:bbbb", now()).unwrap());
+
+        for i in 0..(MAX_ENTRIES + 2) {
+            let time = now() + Duration::hours(i64::from(i));
+            assert!(feeds
+                .record("sha256:aaaa", &format!("Forecast {i}"), time)
+                .unwrap());
+        }
+        let feed = feeds.feed(&token).unwrap().unwrap();
+        assert_eq!(MAX_ENTRIES as usize, feed.entries.len());
+        assert_eq!(
+            format!("Forecast {}", MAX_ENTRIES + 1),
+            feed.entries[0].message
+        );
+        assert_eq!(None, feeds.feed("unknown").unwrap());
+    }
+
+    #[test]
+    fn test_render_atom() {
+        let feeds = feeds();
+        let token = feeds.subscribe("sha256:aaaa", now()).unwrap();
+        feeds
+            .record("sha256:aaaa", "03T21 <T5>\nW10", now())
+            .unwrap();
+        let url = feeds.url(&token).unwrap();
+        assert_eq!(format!("https://example.com/feed/{token}"), url.as_str());
+        let atom = render_atom(&feeds.feed(&token).unwrap().unwrap(), &url);
+        assert!(atom.contains(&format!("<id>{url}</id>")), "{atom}");
+        assert!(atom.contains("<title>03T21 &lt;T5&gt;</title>"), "{atom}");
+        assert!(
+            atom.contains("<updated>2022-12-03T08:00:00+00:00</updated>"),
+            "{atom}"
+        );
+
+        assert!(subscribed_message(&url, true).len() <= 160);
+    }
+}
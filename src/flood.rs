@@ -0,0 +1,353 @@
+//! A forecast of the daily river discharge, for packrafters and canyoners to judge the expected
+//! river levels, requested using `FLOOD` (for the next [`DEFAULT_FLOOD_DAYS`]) or e.g. `FLOOD=14`
+//! (for the next 14 days), see [`build_river_discharge()`].
+//!
+//! The discharge is obtained from the [Open-Meteo Flood API](https://open-meteo.com/en/docs/flood-api)
+//! for the river nearest to the request position (within the GloFAS model's 5km grid), see
+//! [`open_meteo::flood`].
+
+use async_trait::async_trait;
+use chrono::NaiveDate;
+use eyre::Context;
+use open_meteo::flood::{FloodForecast, FloodParameters, FloodVariable};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    format::{FormatDetail, FormatForecastOptions},
+    gis::Position,
+    process::{format_table, newline},
+};
+
+/// Number of days forecast if not specified in the request.
+pub const DEFAULT_FLOOD_DAYS: u8 = 7;
+
+/// Options for the flood service, specified in [`crate::options::Options`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct FloodOptions {
+    /// Base url of the Open-Meteo Flood API.
+    ///
+    /// Default is the public API `https://flood-api.open-meteo.com`.
+    #[serde(default = "default_base_url")]
+    pub base_url: url::Url,
+}
+
+fn default_base_url() -> url::Url {
+    open_meteo::flood::DEFAULT_FLOOD_BASE_URL
+        .parse()
+        .expect("Unable to parse url")
+}
+
+impl Default for FloodOptions {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+        }
+    }
+}
+
+/// Trait used to allow mocking the flood service.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait Port: Send + Sync {
+    /// Obtain the forecast of the river discharge at `position` for the next number of `days`.
+    async fn obtain_flood_forecast(
+        &self,
+        position: Position,
+        days: u8,
+    ) -> eyre::Result<FloodForecast>;
+}
+
+/// Concrete implementation of [`Port`] using the Open-Meteo Flood API.
+#[derive(Clone)]
+pub struct Gateway {
+    http_client: reqwest::Client,
+    options: FloodOptions,
+}
+
+impl Gateway {
+    /// Construct a new [`Gateway`].
+    #[must_use]
+    pub fn new(http_client: reqwest::Client, options: FloodOptions) -> Self {
+        Self {
+            http_client,
+            options,
+        }
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn obtain_flood_forecast(
+        &self,
+        position: Position,
+        days: u8,
+    ) -> eyre::Result<FloodForecast> {
+        let parameters = FloodParameters::builder()
+            .latitude(position.latitude)
+            .longitude(position.longitude)
+            .daily_entry(FloodVariable::RiverDischarge)
+            .daily_entry(FloodVariable::RiverDischargeMedian)
+            .daily_entry(FloodVariable::RiverDischargeMax)
+            .forecast_days(days)
+            .build();
+        open_meteo::flood::obtain_flood_forecast_from(
+            &self.http_client,
+            self.options.base_url.as_str(),
+            &parameters,
+        )
+        .await
+        .wrap_err("Error obtaining flood forecast")
+    }
+}
+
+/// The forecast river discharge for a single day.
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiverDischargeDay {
+    /// The date.
+    pub date: NaiveDate,
+    /// Discharge of the control forecast (m³/s).
+    pub discharge: f32,
+    /// Median discharge of the ensemble (m³/s), if available.
+    pub median: Option<f32>,
+    /// Maximum discharge of the ensemble (m³/s), if available, which indicates how high the
+    /// river could rise.
+    pub max: Option<f32>,
+}
+
+/// The forecast of the river discharge, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct RiverDischarge {
+    /// Position of the river grid cell used for the forecast.
+    pub position: Position,
+    /// The forecast for each day.
+    pub days: Vec<RiverDischargeDay>,
+}
+
+impl RiverDischarge {
+    /// Summarise the `forecast`, omitting days without a value for the discharge (e.g. when the
+    /// position is not near a river).
+    pub fn from_forecast(forecast: &FloodForecast) -> eyre::Result<Self> {
+        let daily = &forecast.daily;
+        let discharge = daily
+            .river_discharge
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("Expected river discharge to be present"))?;
+        let value = |values: &Option<Vec<Option<f32>>>, i: usize| {
+            values
+                .as_ref()
+                .and_then(|values| values.get(i).copied().flatten())
+        };
+        let days = daily
+            .time
+            .iter()
+            .zip(discharge)
+            .enumerate()
+            .filter_map(|(i, (date, discharge))| {
+                Some(RiverDischargeDay {
+                    date: *date,
+                    discharge: (*discharge)?,
+                    median: value(&daily.river_discharge_median, i),
+                    max: value(&daily.river_discharge_max, i),
+                })
+            })
+            .collect();
+        Ok(Self {
+            position: Position::new(forecast.latitude, forecast.longitude),
+            days,
+        })
+    }
+
+    /// Format the forecast as a block to be appended to the forecast, using the detail in
+    /// `options`.
+    #[must_use]
+    pub fn format(&self, options: &FormatForecastOptions) -> String {
+        match &options.detail {
+            FormatDetail::Short(_) => {
+                let lines = self.days.iter().map(|day| {
+                    let mut line = format!(
+                        "{} Q{}",
+                        day.date.format("%d"),
+                        format_discharge(day.discharge)
+                    );
+                    if let Some(max) = day.max {
+                        line.push_str(&format!("/{}", format_discharge(max)));
+                    }
+                    line
+                });
+                std::iter::once("FLOOD m3/s".to_owned())
+                    .chain(lines)
+                    .collect::<Vec<_>>()
+                    .join(newline(&options.detail))
+            }
+            FormatDetail::Long(long) => {
+                let mut output = format!(
+                    "River discharge at {:.3},{:.3}",
+                    self.position.latitude, self.position.longitude
+                );
+                output.push_str(newline(&options.detail));
+                let columns = vec![
+                    "Date".to_owned(),
+                    "Discharge".to_owned(),
+                    "Median".to_owned(),
+                    "Maximum".to_owned(),
+                ];
+                let cell = |value: Option<f32>| {
+                    value.map_or_else(String::new, |value| {
+                        format!("{} m³/s", format_discharge(value))
+                    })
+                };
+                let records = self
+                    .days
+                    .iter()
+                    .map(|day| {
+                        vec![
+                            day.date.to_string(),
+                            cell(Some(day.discharge)),
+                            cell(day.median),
+                            cell(day.max),
+                        ]
+                    })
+                    .collect();
+                output.push_str(&format_table(long.style.as_ref(), columns, records));
+                output
+            }
+        }
+    }
+}
+
+/// Format a discharge (m³/s), with one decimal place for small rivers.
+fn format_discharge(discharge: f32) -> String {
+    if discharge < 10.0 {
+        format!("{:.1}", discharge)
+    } else {
+        format!("{:.0}", discharge)
+    }
+}
+
+/// Obtain the forecast of the river discharge at `position` for the next number of `days`, see the
+/// [module documentation](self).
+pub async fn build_river_discharge(
+    port: &dyn Port,
+    position: Position,
+    days: u8,
+) -> eyre::Result<RiverDischarge> {
+    let forecast = port.obtain_flood_forecast(position, days).await?;
+    let river_discharge = RiverDischarge::from_forecast(&forecast)?;
+    if river_discharge.days.is_empty() {
+        return Err(eyre::eyre!("No river discharge found near {:?}", position));
+    }
+    Ok(river_discharge)
+}
+
+#[cfg(test)]
+mod test {
+    use open_meteo::flood::{FloodDaily, FloodForecast};
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use crate::{
+        format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle},
+        gis::Position,
+    };
+
+    use super::{build_river_discharge, FloodOptions, Gateway, MockPort, Port, RiverDischarge};
+
+    fn forecast() -> FloodForecast {
+        FloodForecast {
+            latitude: -43.525,
+            longitude: 170.275,
+            daily: FloodDaily {
+                time: vec![
+                    "2022-12-03".parse().unwrap(),
+                    "2022-12-04".parse().unwrap(),
+                    "2022-12-05".parse().unwrap(),
+                ],
+                river_discharge: Some(vec![Some(42.4), Some(5.3), None]),
+                river_discharge_max: Some(vec![Some(60.0), None, Some(10.0)]),
+                ..FloodDaily::default()
+            },
+        }
+    }
+
+    #[tokio::test]
+    async fn test_gateway() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v1/flood"))
+            .and(matchers::query_param("forecast_days", "7"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "latitude": -43.525,
+                "longitude": 170.275,
+                "daily": {
+                    "time": ["2022-12-03"],
+                    "river_discharge": [42.4],
+                    "river_discharge_median": [40.0],
+                    "river_discharge_max": [60.0],
+                }
+            })))
+            .mount(&server)
+            .await;
+
+        let gateway = Gateway::new(
+            reqwest::Client::new(),
+            FloodOptions {
+                base_url: server.uri().parse().unwrap(),
+            },
+        );
+        let forecast = gateway
+            .obtain_flood_forecast(Position::new(-43.513832, 170.33975), 7)
+            .await
+            .unwrap();
+        assert_eq!(
+            Some(vec![Some(40.0)]),
+            forecast.daily.river_discharge_median
+        );
+    }
+
+    #[tokio::test]
+    async fn test_build_river_discharge() {
+        let mut port = MockPort::new();
+        port.expect_obtain_flood_forecast()
+            .returning(|_, _| Ok(forecast()));
+        let river_discharge = build_river_discharge(&port, Position::new(-43.5, 170.3), 7)
+            .await
+            .unwrap();
+        assert_eq!(2, river_discharge.days.len());
+
+        let mut port = MockPort::new();
+        port.expect_obtain_flood_forecast().returning(|_, _| {
+            Ok(FloodForecast {
+                daily: FloodDaily {
+                    river_discharge: Some(vec![None, None, None]),
+                    ..forecast().daily
+                },
+                ..forecast()
+            })
+        });
+        assert!(build_river_discharge(&port, Position::new(-43.5, 170.3), 7)
+            .await
+            .is_err());
+    }
+
+    #[test]
+    fn test_format() {
+        let river_discharge = RiverDischarge::from_forecast(&forecast()).unwrap();
+        assert_eq!(
+            "FLOOD m3/s\n03 Q42/60\n04 Q5.3",
+            river_discharge.format(&FormatForecastOptions::default())
+        );
+
+        let long = river_discharge.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(
+            long.contains("River discharge at -43.525,170.275"),
+            "{long}"
+        );
+        assert!(long.contains("42 m³/s"), "{long}");
+        assert!(long.contains("60 m³/s"), "{long}");
+    }
+}
@@ -0,0 +1,506 @@
+//! Analysis of the thermals and stability of the atmosphere for free-flight pilots (paragliding
+//! and hang gliding), requested using `FLY`, see [`build_flying()`].
+//!
+//! The analysis uses the surface temperature and dewpoint at [`THERMAL_HOUR`] (local time) each
+//! day, along with the temperature and geopotential height at each of the
+//! [`FLYING_PRESSURE_LEVELS`]:
+//!
+//! + **Lapse rates** of the environment between the surface and 700hPa, and between 700hPa and
+//!   500hPa.
+//! + **Thermal top**, the height where a parcel of air rising from the surface along the dry
+//!   adiabat becomes cooler than the environment.
+//! + **Cloud base**, the lifting condensation level, estimated from the spread between the
+//!   surface temperature and dewpoint. Only included if it is below the thermal top (i.e.
+//!   cumulus are expected to form).
+//! + **Lifted index**, the difference between the environment and a parcel of air lifted from the
+//!   surface to 500hPa. Negative values indicate instability.
+
+use chrono::{NaiveDate, NaiveDateTime, Timelike};
+use eyre::Context;
+use open_meteo::{HourlyVariable, PressureLevel, TimeZone};
+
+use crate::{
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions},
+    gis::Position,
+    process::{format_table, newline},
+    time,
+};
+
+/// The local hour of the day at which the analysis is performed, near the peak of daytime
+/// heating.
+pub const THERMAL_HOUR: u32 = 14;
+
+/// Number of days included in the analysis, starting from the current day.
+const FLYING_DAYS: i64 = 3;
+
+/// The pressure levels used for the analysis, from the surface upwards.
+pub const FLYING_PRESSURE_LEVELS: &[PressureLevel] = &[
+    PressureLevel::L1000,
+    PressureLevel::L925,
+    PressureLevel::L850,
+    PressureLevel::L700,
+    PressureLevel::L600,
+    PressureLevel::L500,
+    PressureLevel::L400,
+    PressureLevel::L300,
+];
+
+/// Cooling of a rising parcel of dry air (°C per km).
+const DRY_ADIABATIC_LAPSE_RATE: f32 = 9.8;
+
+/// Approximate cooling of a rising parcel of saturated air in the lower and middle troposphere
+/// (°C per km).
+const MOIST_ADIABATIC_LAPSE_RATE: f32 = 6.0;
+
+/// Metres that the lifting condensation level rises for each °C of spread between the
+/// temperature and dewpoint.
+const LCL_METRES_PER_DEGREE: f32 = 125.0;
+
+/// The height of the thermal top, see [`FlyingDay::thermal_top`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum ThermalTop {
+    /// Thermals reach this height (metres above sea level).
+    At(f32),
+    /// Thermals reach above the highest available pressure level, at this height (metres above
+    /// sea level).
+    Above(f32),
+}
+
+/// Stability of the atmosphere, classified using the lifted index.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stability {
+    /// Lifted index above 0.
+    Stable,
+    /// Lifted index between -3 and 0.
+    MarginallyUnstable,
+    /// Lifted index between -6 and -3.
+    Unstable,
+    /// Lifted index below -6, thunderstorms are likely if there is enough moisture.
+    VeryUnstable,
+}
+
+impl Stability {
+    /// Classify the `lifted_index`.
+    #[must_use]
+    pub fn from_lifted_index(lifted_index: f32) -> Self {
+        if lifted_index > 0.0 {
+            Self::Stable
+        } else if lifted_index > -3.0 {
+            Self::MarginallyUnstable
+        } else if lifted_index > -6.0 {
+            Self::Unstable
+        } else {
+            Self::VeryUnstable
+        }
+    }
+
+    /// Description used in the long format.
+    #[must_use]
+    pub fn description(self) -> &'static str {
+        match self {
+            Self::Stable => "stable",
+            Self::MarginallyUnstable => "marginally unstable",
+            Self::Unstable => "unstable",
+            Self::VeryUnstable => "very unstable",
+        }
+    }
+}
+
+/// The temperature and geopotential height at a pressure level.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Level {
+    /// The pressure level.
+    pub pressure: PressureLevel,
+    /// Geopotential height (metres above sea level).
+    pub height: f32,
+    /// Temperature (°C).
+    pub temperature: f32,
+}
+
+/// Analysis for a single day, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlyingDay {
+    /// The (local) date.
+    pub date: NaiveDate,
+    /// Lapse rate (°C per km) between the surface and 700hPa. `None` if 700hPa is below the
+    /// surface.
+    pub lapse_rate_low: Option<f32>,
+    /// Lapse rate (°C per km) between 700hPa and 500hPa.
+    pub lapse_rate_mid: Option<f32>,
+    /// Height that thermals reach.
+    pub thermal_top: ThermalTop,
+    /// Height of the cloud base (metres above sea level), if cumulus are expected to form below
+    /// the thermal top.
+    pub cloud_base: Option<f32>,
+    /// Lifted index (°C) at 500hPa, `None` if 500hPa is unavailable.
+    pub lifted_index: Option<f32>,
+}
+
+impl FlyingDay {
+    /// Analyse the atmosphere on `date` above a surface at `surface_elevation` (metres) with the
+    /// `temperature` and `dewpoint` (°C), using the pressure `levels` (in any order, levels below
+    /// the surface are ignored).
+    #[must_use]
+    pub fn analyse(
+        date: NaiveDate,
+        surface_elevation: f32,
+        temperature: f32,
+        dewpoint: f32,
+        levels: &[Level],
+    ) -> Self {
+        let mut levels: Vec<Level> = levels
+            .iter()
+            .copied()
+            .filter(|level| level.height > surface_elevation)
+            .collect();
+        levels.sort_by(|a, b| a.height.total_cmp(&b.height));
+        let level =
+            |pressure: PressureLevel| levels.iter().find(|level| level.pressure == pressure);
+        let lapse_rate = |(lower_height, lower_temperature): (f32, f32), upper: &Level| {
+            (lower_temperature - upper.temperature) / ((upper.height - lower_height) / 1000.0)
+        };
+
+        let l700 = level(PressureLevel::L700);
+        let l500 = level(PressureLevel::L500);
+        let lapse_rate_low = l700.map(|l700| lapse_rate((surface_elevation, temperature), l700));
+        let lapse_rate_mid = l700
+            .zip(l500)
+            .map(|(l700, l500)| lapse_rate((l700.height, l700.temperature), l500));
+
+        // Dry adiabatic parcel temperature at `height`.
+        let parcel = |height: f32| {
+            temperature - DRY_ADIABATIC_LAPSE_RATE * (height - surface_elevation) / 1000.0
+        };
+        let mut thermal_top = ThermalTop::At(surface_elevation);
+        let mut previous = (surface_elevation, 0.0_f32);
+        for level in &levels {
+            let excess = parcel(level.height) - level.temperature;
+            if excess < 0.0 {
+                // Interpolate the height at which the excess reaches zero.
+                let (previous_height, previous_excess) = previous;
+                thermal_top = ThermalTop::At(
+                    previous_height
+                        + (level.height - previous_height) * previous_excess
+                            / (previous_excess - excess),
+                );
+                break;
+            }
+            thermal_top = ThermalTop::Above(level.height);
+            previous = (level.height, excess);
+        }
+
+        let lcl = surface_elevation + LCL_METRES_PER_DEGREE * (temperature - dewpoint).max(0.0);
+        let cloud_base = match thermal_top {
+            ThermalTop::At(top) => (lcl < top).then_some(lcl),
+            ThermalTop::Above(_) => Some(lcl),
+        };
+
+        let lifted_index = l500.map(|l500| {
+            let parcel_500 = if lcl < l500.height {
+                parcel(lcl) - MOIST_ADIABATIC_LAPSE_RATE * (l500.height - lcl) / 1000.0
+            } else {
+                parcel(l500.height)
+            };
+            l500.temperature - parcel_500
+        });
+
+        Self {
+            date,
+            lapse_rate_low,
+            lapse_rate_mid,
+            thermal_top,
+            cloud_base,
+            lifted_index,
+        }
+    }
+}
+
+/// The analysis for each day of the forecast, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct FlyingForecast {
+    /// Analysis for each day.
+    pub days: Vec<FlyingDay>,
+}
+
+impl FlyingForecast {
+    /// Format the analysis as a block to be appended to the forecast, using the detail in
+    /// `options`.
+    #[must_use]
+    pub fn format(&self, options: &FormatForecastOptions) -> String {
+        let optional = |value: Option<f32>| {
+            value.map_or_else(|| "-".to_owned(), |value| format!("{:.0}", value.round()))
+        };
+        match &options.detail {
+            FormatDetail::Short(_) => {
+                let lines = self.days.iter().map(|day| {
+                    let thermal_top = match day.thermal_top {
+                        ThermalTop::At(top) => format!("{:.0}", (top / 100.0).round()),
+                        ThermalTop::Above(top) => format!(">{:.0}", (top / 100.0).round()),
+                    };
+                    let cloud_base = day
+                        .cloud_base
+                        .map(|base| format!(" CB{:.0}", (base / 100.0).round()))
+                        .unwrap_or_default();
+                    format!(
+                        "{} TT{}{} LR{}/{} LI{}",
+                        day.date.format("%d"),
+                        thermal_top,
+                        cloud_base,
+                        optional(day.lapse_rate_low),
+                        optional(day.lapse_rate_mid),
+                        optional(day.lifted_index),
+                    )
+                });
+                std::iter::once("FLY".to_owned())
+                    .chain(lines)
+                    .collect::<Vec<_>>()
+                    .join(newline(&options.detail))
+            }
+            FormatDetail::Long(long) => {
+                let mut output = format!("Flying conditions at {THERMAL_HOUR}:00");
+                output.push_str(newline(&options.detail));
+                let columns = [
+                    "Date",
+                    "Thermal Top",
+                    "Cloud Base",
+                    "Lapse Rate (surface-700hPa)",
+                    "Lapse Rate (700-500hPa)",
+                    "Lifted Index",
+                ]
+                .into_iter()
+                .map(ToString::to_string)
+                .collect();
+                let lapse_rate = |value: Option<f32>| {
+                    value.map_or_else(
+                        || "unavailable".to_owned(),
+                        |value| format!("{:.1}°C/km", value),
+                    )
+                };
+                let records = self
+                    .days
+                    .iter()
+                    .map(|day| {
+                        vec![
+                            day.date.to_string(),
+                            match day.thermal_top {
+                                ThermalTop::At(top) => format!("{:.0}m", top.round()),
+                                ThermalTop::Above(top) => format!("above {:.0}m", top.round()),
+                            },
+                            day.cloud_base.map_or_else(
+                                || "none".to_owned(),
+                                |base| format!("{:.0}m", base.round()),
+                            ),
+                            lapse_rate(day.lapse_rate_low),
+                            lapse_rate(day.lapse_rate_mid),
+                            day.lifted_index.map_or_else(
+                                || "unavailable".to_owned(),
+                                |index| {
+                                    format!(
+                                        "{} ({})",
+                                        optional(Some(index)),
+                                        Stability::from_lifted_index(index).description()
+                                    )
+                                },
+                            ),
+                        ]
+                    })
+                    .collect();
+                output.push_str(&format_table(long.style.as_ref(), columns, records));
+                output
+            }
+        }
+    }
+}
+
+/// Obtain the forecast required for the analysis at `position`, and analyse each of the days at
+/// [`THERMAL_HOUR`], see the [module documentation](self).
+pub async fn build_flying(
+    time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    position: Position,
+) -> eyre::Result<FlyingForecast> {
+    let today = time.utc_now().naive_utc().date();
+    let mut parameters = open_meteo::ForecastParameters::builder()
+        .latitude(position.latitude)
+        .longitude(position.longitude)
+        .timezone(TimeZone::Auto)
+        .build();
+    parameters.hourly = [HourlyVariable::Temperature2m, HourlyVariable::Dewpoint2m]
+        .into_iter()
+        .chain(FLYING_PRESSURE_LEVELS.iter().flat_map(|level| {
+            [
+                HourlyVariable::PressureGeopotentialHeight(*level),
+                HourlyVariable::PressureTemperature(*level),
+            ]
+        }))
+        .collect();
+    parameters.start_date = Some(today);
+    parameters.end_date = Some(today + chrono::Duration::days(FLYING_DAYS - 1));
+
+    let forecast = forecast_service
+        .obtain_forecast(&parameters)
+        .await
+        .wrap_err("Error obtaining forecast for flying conditions")?
+        .forecast;
+    let hourly = forecast
+        .hourly
+        .ok_or_else(|| eyre::eyre!("Expected hourly forecast to be present"))?;
+    let temperature = hourly
+        .temperature_2m
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected temperature to be present"))?;
+    let dewpoint = hourly
+        .dewpoint_2m
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected dewpoint to be present"))?;
+
+    let days = hourly
+        .time
+        .iter()
+        .enumerate()
+        .filter(|(_, time): &(usize, &NaiveDateTime)| {
+            time.hour() == THERMAL_HOUR && time.minute() == 0
+        })
+        .filter_map(|(i, time)| {
+            let levels: Vec<Level> = FLYING_PRESSURE_LEVELS
+                .iter()
+                .filter_map(|level| {
+                    Some(Level {
+                        pressure: *level,
+                        height: hourly.pressure_geopotential_height.value(level)?.get(i)?.0,
+                        temperature: hourly.pressure_temperature.value(level)?.get(i)?.0,
+                    })
+                })
+                .collect();
+            Some(FlyingDay::analyse(
+                time.date(),
+                forecast.elevation,
+                temperature.get(i)?.0,
+                dewpoint.get(i)?.0,
+                &levels,
+            ))
+        })
+        .collect::<Vec<_>>();
+
+    if days.is_empty() {
+        return Err(eyre::eyre!(
+            "Forecast is missing the times for flying conditions"
+        ));
+    }
+    Ok(FlyingForecast { days })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+    use open_meteo::PressureLevel;
+
+    use crate::format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle};
+
+    use super::{FlyingDay, FlyingForecast, Level, Stability, ThermalTop};
+
+    fn levels() -> Vec<Level> {
+        let level = |pressure, height, temperature| Level {
+            pressure,
+            height,
+            temperature,
+        };
+        vec![
+            level(PressureLevel::L1000, 100.0, 20.0),
+            level(PressureLevel::L850, 1500.0, 12.0),
+            level(PressureLevel::L700, 3000.0, -1.0),
+            level(PressureLevel::L600, 4200.0, -5.0),
+            level(PressureLevel::L500, 5600.0, -15.0),
+        ]
+    }
+
+    fn day() -> FlyingDay {
+        FlyingDay::analyse(
+            NaiveDate::from_ymd_opt(2022, 12, 3).unwrap(),
+            1000.0,
+            20.0,
+            8.0,
+            &levels(),
+        )
+    }
+
+    #[test]
+    fn test_analyse() {
+        let day = day();
+        // (20 - -1) / 2km
+        assert_eq!(Some(10.5), day.lapse_rate_low);
+        // (-1 - -15) / 2.6km
+        assert_eq!(
+            Some(5.4),
+            day.lapse_rate_mid.map(|rate| (rate * 10.0).round() / 10.0)
+        );
+
+        // The parcel is 3.1°C warmer at 1500m, 1.4°C warmer at 3000m, and 6.36°C cooler at
+        // 4200m.
+        match day.thermal_top {
+            ThermalTop::At(top) => assert_eq!(3216.0, top.round()),
+            ThermalTop::Above(_) => panic!("Expected thermal top, found {:?}", day.thermal_top),
+        }
+
+        // 1000m + 125m * 12°C
+        assert_eq!(Some(2500.0), day.cloud_base);
+
+        // Parcel at 2500m is 5.3°C, and -13.3°C at 5600m (saturated above the cloud base).
+        let lifted_index = day.lifted_index.unwrap();
+        assert_eq!(-1.7, (lifted_index * 10.0).round() / 10.0);
+        assert_eq!(
+            Stability::MarginallyUnstable,
+            Stability::from_lifted_index(lifted_index)
+        );
+        assert_eq!(Stability::Stable, Stability::from_lifted_index(1.7));
+        assert_eq!(Stability::VeryUnstable, Stability::from_lifted_index(-7.0));
+    }
+
+    #[test]
+    fn test_analyse_stable() {
+        // A strong inversion above the surface.
+        let day = FlyingDay::analyse(
+            NaiveDate::from_ymd_opt(2022, 12, 3).unwrap(),
+            100.0,
+            5.0,
+            4.0,
+            &[
+                Level {
+                    pressure: PressureLevel::L925,
+                    height: 800.0,
+                    temperature: 8.0,
+                },
+                Level {
+                    pressure: PressureLevel::L850,
+                    height: 1500.0,
+                    temperature: 4.0,
+                },
+            ],
+        );
+        assert_eq!(ThermalTop::At(100.0), day.thermal_top);
+        assert_eq!(None, day.cloud_base);
+        assert_eq!(None, day.lapse_rate_low);
+        assert_eq!(None, day.lifted_index);
+    }
+
+    #[test]
+    fn test_format() {
+        let forecast = FlyingForecast { days: vec![day()] };
+        assert_eq!(
+            "FLY\n03 TT32 CB25 LR11/5 LI-2",
+            forecast.format(&FormatForecastOptions::default())
+        );
+
+        let long = forecast.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(long.contains("Flying conditions at 14:00"), "{long}");
+        assert!(long.contains("3216m"), "{long}");
+        assert!(long.contains("10.5°C/km"), "{long}");
+        assert!(long.contains("-2 (marginally unstable)"), "{long}");
+    }
+}
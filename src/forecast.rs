@@ -0,0 +1,670 @@
+//! High level API for obtaining a formatted forecast for a [`ForecastRequest`], see
+//! [`ForecastService`]. This is the same pipeline used to reply to emails, without the email
+//! plumbing, so that it can be reused by other frontends.
+
+use chrono::{DateTime, Utc};
+
+use crate::{
+    aviation_weather::{self, StationReports},
+    changes::{ForecastChanges, ForecastDigest, PreviousForecasts},
+    circuit_breaker::CircuitOpen,
+    flood::{self, build_river_discharge, RiverDischarge, DEFAULT_FLOOD_DAYS},
+    flying::{build_flying, FlyingForecast},
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions, LongFormatStyle, ShortFormatDetail},
+    gis::Position,
+    html,
+    latency::{Latency, Stage},
+    options::DynamicOptions,
+    past_weather::{build_past_weather, PastWeather, DEFAULT_PAST_WEATHER_HOURS},
+    process::{build_forecast, newline, ForecastWindow, FormatForecast},
+    profile::{ForecastProfile, ForecastVariable},
+    request::ForecastRequest,
+    snow_outlook::{build_snow_outlook, SnowOutlook},
+    solar::{build_solar_outlook, SolarOutlook},
+    subject::ForecastSummary,
+    time, topo_data_service,
+};
+
+/// Error produced by [`ForecastService::forecast()`].
+#[derive(Debug, thiserror::Error)]
+pub enum ForecastError {
+    /// The request did not specify a position for the forecast.
+    #[error("No forecast position specified")]
+    NoPosition,
+    /// An external service is temporarily unavailable.
+    #[error(transparent)]
+    Unavailable(#[from] CircuitOpen),
+    /// The forecast service rejected the request, for a reason which can be explained to the user.
+    #[error("Forecast service rejected the request: {0}")]
+    Rejected(open_meteo::ApiError),
+    /// An unexpected error occurred.
+    #[error(transparent)]
+    Unexpected(#[from] eyre::Error),
+}
+
+/// A formatted forecast, see [`ForecastService::forecast()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct FormattedForecast {
+    /// The forecast formatted as plain text.
+    pub message: String,
+    /// The forecast formatted as html, if the requested format uses
+    /// [`LongFormatStyle::Html`].
+    pub html_message: Option<String>,
+    /// Summary of the forecast, used to render the subject of the reply.
+    pub summary: Option<ForecastSummary>,
+}
+
+impl FormattedForecast {
+    /// Combine the `forecasts` for multiple requests into a single forecast, with a section for
+    /// each request. The html version is only included if at least one of the forecasts has one,
+    /// and the plain text of the others is included in a `<pre>` element. The summary of the
+    /// first forecast is used.
+    #[must_use]
+    pub fn combine(mut forecasts: Vec<Self>) -> Self {
+        if forecasts.len() == 1 {
+            return forecasts.remove(0);
+        }
+        let total = forecasts.len();
+        let heading = |i: usize| format!("Request {} of {}", i + 1, total);
+
+        let message = forecasts
+            .iter()
+            .enumerate()
+            .map(|(i, forecast)| format!("{}\n{}", heading(i), forecast.message))
+            .collect::<Vec<_>>()
+            .join("\n\n");
+        let html_message = forecasts
+            .iter()
+            .any(|forecast| forecast.html_message.is_some())
+            .then(|| {
+                forecasts
+                    .iter()
+                    .enumerate()
+                    .map(|(i, forecast)| {
+                        let body = match &forecast.html_message {
+                            Some(html_message) => html_message.clone(),
+                            None => format!("<pre>{}</pre>", html::escape(&forecast.message)),
+                        };
+                        format!("<h3>{}</h3>{}", heading(i), body)
+                    })
+                    .collect::<String>()
+            });
+
+        Self {
+            message,
+            html_message,
+            summary: forecasts
+                .into_iter()
+                .next()
+                .and_then(|forecast| forecast.summary),
+        }
+    }
+}
+
+/// Obtains forecasts using the specified services, and formats them for a [`ForecastRequest`]
+/// using the profiles and default format from [`DynamicOptions`].
+pub struct ForecastService<'a> {
+    time: &'a dyn time::Port,
+    forecast_service: &'a dyn forecast_service::Port,
+    topo_data_service: Option<&'a dyn topo_data_service::Port>,
+    aviation_weather: Option<&'a dyn aviation_weather::Port>,
+    flood: Option<&'a dyn flood::Port>,
+    latency: Option<(&'a Latency, &'a dyn time::Port)>,
+    previous_forecasts: Option<(&'static PreviousForecasts, &'a str)>,
+    options: &'a DynamicOptions,
+}
+
+impl<'a> ForecastService<'a> {
+    /// Construct a new [`ForecastService`]. If `topo_data_service` is `None` the terrain elevation
+    /// is not included in forecasts.
+    #[must_use]
+    pub fn new(
+        time: &'a dyn time::Port,
+        forecast_service: &'a dyn forecast_service::Port,
+        topo_data_service: Option<&'a dyn topo_data_service::Port>,
+        options: &'a DynamicOptions,
+    ) -> Self {
+        Self {
+            time,
+            forecast_service,
+            topo_data_service,
+            aviation_weather: None,
+            flood: None,
+            latency: None,
+            previous_forecasts: None,
+            options,
+        }
+    }
+
+    /// Use `aviation_weather` to obtain the reports for requests which include `METAR`. If it is
+    /// not specified, these requests include an error instead.
+    #[must_use]
+    pub fn with_aviation_weather(
+        mut self,
+        aviation_weather: Option<&'a dyn aviation_weather::Port>,
+    ) -> Self {
+        self.aviation_weather = aviation_weather;
+        self
+    }
+
+    /// Use `flood` to obtain the river discharge for requests which include `FLOOD`. If it is not
+    /// specified, these requests include an error instead.
+    #[must_use]
+    pub fn with_flood(mut self, flood: Option<&'a dyn flood::Port>) -> Self {
+        self.flood = flood;
+        self
+    }
+
+    /// Record the time spent obtaining and formatting forecasts in `latency`, measured using
+    /// `clock` (which unlike the time used for the forecast, is not fixed at the time that a
+    /// message was sent).
+    #[must_use]
+    pub fn with_latency(mut self, latency: &'a Latency, clock: &'a dyn time::Port) -> Self {
+        self.latency = Some((latency, clock));
+        self
+    }
+
+    /// Include the changes since the previous forecast sent to `sender` (see
+    /// [`crate::forget::sender_hash()`]) for the same position, and record each forecast as the
+    /// previous forecast in `previous_forecasts`, see [`crate::changes`].
+    #[must_use]
+    pub fn with_previous_forecasts(
+        mut self,
+        previous_forecasts: &'static PreviousForecasts,
+        sender: &'a str,
+    ) -> Self {
+        self.previous_forecasts = Some((previous_forecasts, sender));
+        self
+    }
+
+    /// Record `digest` as the previous forecast of `sender` at `position`, and return the changes
+    /// since the forecast that it replaces (if there was one). Errors are logged instead of
+    /// failing the forecast.
+    async fn changes(
+        &self,
+        previous_forecasts: &'static PreviousForecasts,
+        sender: &str,
+        position: Position,
+        digest: ForecastDigest,
+    ) -> Option<ForecastChanges> {
+        if digest.is_empty() {
+            return None;
+        }
+        let sender = sender.to_owned();
+        let now = self.time.utc_now();
+        let result = tokio::task::spawn_blocking(move || -> eyre::Result<_> {
+            let previous = previous_forecasts.replace(&sender, position, &digest, now)?;
+            Ok(previous
+                .map(|(previous, since)| ForecastChanges::between(&previous, &digest, since)))
+        })
+        .await
+        .map_err(eyre::Error::from)
+        .and_then(|result| result);
+        match result {
+            Ok(changes) => changes,
+            Err(error) => {
+                tracing::error!("Error comparing with the previous forecast: {:?}", error);
+                None
+            }
+        }
+    }
+
+    /// The current time if latency is being recorded, see [`ForecastService::with_latency()`].
+    fn latency_started(&self) -> Option<DateTime<Utc>> {
+        self.latency.map(|(_, clock)| clock.utc_now())
+    }
+
+    /// Record the time since `started` (see [`ForecastService::latency_started()`]) in `stage`.
+    fn record_latency(&self, stage: Stage, started: Option<DateTime<Utc>>) {
+        if let (Some((latency, clock)), Some(started)) = (self.latency, started) {
+            latency.record(stage, clock.utc_now() - started);
+        }
+    }
+
+    /// Obtain the forecast for `request`, formatted using the format requested, or otherwise the
+    /// format of the selected profile, or the default format.
+    pub async fn forecast(
+        &self,
+        request: &ForecastRequest,
+    ) -> Result<FormattedForecast, ForecastError> {
+        self.forecast_with(request, None, Vec::new(), |format| format)
+            .await
+    }
+
+    /// Obtain the forecast for `request` (see [`ForecastService::forecast()`]), using
+    /// `fallback_position` if the request does not specify a position. The `errors` are included
+    /// in the forecast, and `transform_format` is applied to the selected format (e.g. to impose
+    /// limits of the device that the reply is being sent to).
+    pub(crate) async fn forecast_with(
+        &self,
+        request: &ForecastRequest,
+        fallback_position: Option<Position>,
+        mut errors: Vec<String>,
+        transform_format: impl FnOnce(FormatForecastOptions) -> FormatForecastOptions,
+    ) -> Result<FormattedForecast, ForecastError> {
+        let profile: Option<&ForecastProfile> = match request
+            .profile
+            .as_ref()
+            .or(self.options.default_profile.as_ref())
+        {
+            Some(name) => {
+                let profile = self.options.profile(name);
+                if profile.is_none() {
+                    tracing::warn!("Unknown forecast profile {:?}", name);
+                    errors.push(format!("Unknown profile: {}", name));
+                }
+                profile
+            }
+            None => None,
+        };
+        let variables: &[ForecastVariable] =
+            profile.map_or(ForecastVariable::DEFAULT, |profile| &profile.variables);
+
+        let format = request.format.clone().unwrap_or_else(|| {
+            profile
+                .and_then(|profile| profile.format.as_ref())
+                .unwrap_or(&self.options.default_format)
+                .clone()
+        });
+        let format = transform_format(format);
+
+        let position = request
+            .position
+            .or(fallback_position)
+            .ok_or(ForecastError::NoPosition)?;
+        let forecast_started = self.latency_started();
+        let past_weather: Option<PastWeather> = match request.past_weather {
+            Some(past_weather_request) => {
+                let hours = past_weather_request
+                    .hours
+                    .unwrap_or(DEFAULT_PAST_WEATHER_HOURS);
+                match build_past_weather(self.time, self.forecast_service, position, hours).await {
+                    Ok(past_weather) => Some(past_weather),
+                    Err(error) => {
+                        tracing::error!("Error building past weather: {:?}", error);
+                        errors.push("Past weather is unavailable".to_owned());
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let flying: Option<FlyingForecast> = if request.fly {
+            match build_flying(self.time, self.forecast_service, position).await {
+                Ok(flying) => Some(flying),
+                Err(error) => {
+                    tracing::error!("Error building flying conditions: {:?}", error);
+                    errors.push("Flying conditions are unavailable".to_owned());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let snow_outlook: Option<SnowOutlook> = match request.snow_outlook {
+            Some(snow_outlook_request) => {
+                let elevation = snow_outlook_request.elevation.map(f32::from);
+                match build_snow_outlook(self.time, self.forecast_service, position, elevation)
+                    .await
+                {
+                    Ok(snow_outlook) => Some(snow_outlook),
+                    Err(error) => {
+                        tracing::error!("Error building snow outlook: {:?}", error);
+                        errors.push("Snow outlook is unavailable".to_owned());
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let solar_outlook: Option<SolarOutlook> = if request.solar {
+            match build_solar_outlook(self.time, self.forecast_service, position).await {
+                Ok(solar_outlook) => Some(solar_outlook),
+                Err(error) => {
+                    tracing::error!("Error building solar outlook: {:?}", error);
+                    errors.push("Solar outlook is unavailable".to_owned());
+                    None
+                }
+            }
+        } else {
+            None
+        };
+        let station_reports: Option<StationReports> = match &request.metar {
+            Some(metar) => {
+                let reports = match self.aviation_weather {
+                    Some(aviation_weather) => {
+                        aviation_weather::lookup(
+                            aviation_weather,
+                            metar.station.as_deref(),
+                            Some(position),
+                        )
+                        .await
+                    }
+                    None => Err(eyre::eyre!("Aviation weather service is not available")),
+                };
+                match reports {
+                    Ok(reports) => Some(reports),
+                    Err(error) => {
+                        tracing::error!("Error obtaining METAR: {:?}", error);
+                        errors.push("METAR is unavailable".to_owned());
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let river_discharge: Option<RiverDischarge> = match request.flood {
+            Some(flood_request) => {
+                let days = flood_request.days.unwrap_or(DEFAULT_FLOOD_DAYS);
+                let river_discharge = match self.flood {
+                    Some(flood) => build_river_discharge(flood, position, days).await,
+                    None => Err(eyre::eyre!("Flood service is not available")),
+                };
+                match river_discharge {
+                    Ok(river_discharge) => Some(river_discharge),
+                    Err(error) => {
+                        tracing::error!("Error obtaining river discharge: {:?}", error);
+                        errors.push("River discharge is unavailable".to_owned());
+                        None
+                    }
+                }
+            }
+            None => None,
+        };
+        let forecast_output = build_forecast(
+            self.time,
+            self.forecast_service,
+            self.topo_data_service,
+            position,
+            variables,
+            ForecastWindow {
+                past_days: request.past_days,
+                days: profile.and_then(|profile| profile.days),
+            },
+            format.mode,
+            errors,
+        )
+        .await
+        .map_err(|error| {
+            match error
+                .downcast_ref::<forecast_service::Error>()
+                .and_then(forecast_service::Error::api_error)
+            {
+                Some(api_error) => ForecastError::Rejected(api_error.clone()),
+                None => ForecastError::Unexpected(error),
+            }
+        })?;
+        self.record_latency(Stage::Forecast, forecast_started);
+        let changes: Option<ForecastChanges> = match self.previous_forecasts {
+            Some((previous_forecasts, sender)) => {
+                self.changes(
+                    previous_forecasts,
+                    sender,
+                    position,
+                    forecast_output.digest(),
+                )
+                .await
+            }
+            None => None,
+        };
+        let format_started = self.latency_started();
+
+        // The changes since the previous forecast, past weather, flying conditions, snow and solar
+        // outlooks, METAR and river discharge (if requested) follow the forecast, which is
+        // shortened to make room for them within the length limit.
+        let format_forecast = |format: &FormatForecastOptions| {
+            let separator = newline(&format.detail).repeat(2);
+            let blocks: String = changes
+                .iter()
+                .filter_map(|changes| changes.format(format))
+                .chain(
+                    past_weather
+                        .iter()
+                        .map(|past_weather| past_weather.format(format)),
+                )
+                .chain(flying.iter().map(|flying| flying.format(format)))
+                .chain(snow_outlook.iter().map(|outlook| outlook.format(format)))
+                .chain(solar_outlook.iter().map(|outlook| outlook.format(format)))
+                .chain(station_reports.iter().map(|reports| reports.format(format)))
+                .chain(river_discharge.iter().map(|river| river.format(format)))
+                .map(|block| separator.clone() + &block)
+                .collect();
+            let mut forecast_format = format.clone();
+            if let FormatDetail::Short(ShortFormatDetail {
+                length_limit: Some(length_limit),
+            }) = &mut forecast_format.detail
+            {
+                *length_limit = length_limit.saturating_sub(blocks.len());
+            }
+            forecast_output.format(&forecast_format) + &blocks
+        };
+        let message: String = format_forecast(&format);
+        let summary = Some(forecast_output.summary(position));
+        let formatted = match &format.detail {
+            FormatDetail::Long(long) if long.style == Some(LongFormatStyle::Html) => {
+                let mut plain_long = long.clone();
+                let mut plain_format = format.clone();
+                plain_long.style = Some(LongFormatStyle::PlainText);
+                plain_format.detail = FormatDetail::Long(plain_long);
+
+                FormattedForecast {
+                    message: format_forecast(&plain_format),
+                    html_message: Some(message),
+                    summary,
+                }
+            }
+            _ => FormattedForecast {
+                message,
+                html_message: None,
+                summary,
+            },
+        };
+        self.record_latency(Stage::Format, format_started);
+
+        Ok(formatted)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use once_cell::sync::Lazy;
+    use open_meteo::Forecast;
+
+    use crate::{
+        cassette::Cassette,
+        changes::PreviousForecasts,
+        forecast_service::{self, ObtainedForecast},
+        format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle},
+        gis::Position,
+        options::DynamicOptions,
+        request::ForecastRequest,
+    };
+
+    use super::{ForecastError, ForecastService, FormattedForecast};
+
+    static FORECAST_MT_COOK: Lazy<Forecast> = Lazy::new(|| {
+        serde_json::from_str(&std::fs::read_to_string("fixtures/forecast_mt_cook.json").unwrap())
+            .unwrap()
+    });
+
+    fn time() -> crate::time::MockPort {
+        let mut time = crate::time::MockPort::new();
+        time.expect_utc_now()
+            .returning(|| "2022-12-03T08:00:00Z".parse().unwrap());
+        time
+    }
+
+    #[tokio::test]
+    async fn test_forecast_html() {
+        let mut forecast_service = forecast_service::MockPort::new();
+        forecast_service
+            .expect_obtain_forecast()
+            .return_once(|_| Ok(ObtainedForecast::fixture(FORECAST_MT_COOK.clone())));
+        let time = time();
+        let options = DynamicOptions::default();
+        let service = ForecastService::new(&time, &forecast_service, None, &options);
+
+        let forecast = service
+            .forecast(&ForecastRequest {
+                position: Some(Position::new(-43.513832, 170.33975)),
+                format: Some(FormatForecastOptions {
+                    detail: FormatDetail::Long(LongFormatDetail {
+                        style: Some(LongFormatStyle::Html),
+                    }),
+                    ..FormatForecastOptions::default()
+                }),
+                profile: None,
+                past_days: None,
+                grib: false,
+                fly: false,
+                snow_outlook: None,
+                metar: None,
+                past_weather: None,
+                flood: None,
+                solar: false,
+            })
+            .await
+            .unwrap();
+
+        assert!(forecast.html_message.unwrap().contains("<table"));
+        assert!(!forecast.message.contains("<table"));
+    }
+
+    /// Test that the changes since the previous forecast are included once there is one.
+    #[tokio::test]
+    async fn test_forecast_previous_forecasts() {
+        let mut forecast_service = forecast_service::MockPort::new();
+        forecast_service
+            .expect_obtain_forecast()
+            .returning(|_| Ok(ObtainedForecast::fixture(FORECAST_MT_COOK.clone())));
+        let time = time();
+        let options = DynamicOptions::default();
+        let previous_forecasts: &'static PreviousForecasts =
+            Box::leak(Box::new(PreviousForecasts::open_in_memory().unwrap()));
+        let service = ForecastService::new(&time, &forecast_service, None, &options)
+            .with_previous_forecasts(previous_forecasts, "sha256:aaaa");
+        let request = ForecastRequest {
+            position: Some(Position::new(-43.513832, 170.33975)),
+            format: Some(FormatForecastOptions {
+                detail: FormatDetail::Long(LongFormatDetail {
+                    style: Some(LongFormatStyle::PlainText),
+                }),
+                ..FormatForecastOptions::default()
+            }),
+            ..ForecastRequest::default()
+        };
+
+        let first = service.forecast(&request).await.unwrap();
+        assert!(
+            !first.message.contains("previous forecast"),
+            "{}",
+            first.message
+        );
+        // The forecast is identical, so nothing has changed.
+        let second = service.forecast(&request).await.unwrap();
+        assert!(
+            second
+                .message
+                .contains("No significant changes since the previous forecast"),
+            "{}",
+            second.message
+        );
+    }
+
+    /// Test that variables which are not provided (e.g. by a fallback provider) are omitted.
+    #[tokio::test]
+    async fn test_forecast_unavailable_variable() {
+        let mut forecast = FORECAST_MT_COOK.clone();
+        forecast.hourly.as_mut().unwrap().freezing_level_height = None;
+        let mut forecast_service = forecast_service::MockPort::new();
+        forecast_service
+            .expect_obtain_forecast()
+            .return_once(|_| Ok(ObtainedForecast::fixture(forecast)));
+        let time = time();
+        let options = DynamicOptions::default();
+        let service = ForecastService::new(&time, &forecast_service, None, &options);
+
+        let forecast = service
+            .forecast(&ForecastRequest {
+                position: Some(Position::new(-43.513832, 170.33975)),
+                ..ForecastRequest::default()
+            })
+            .await
+            .unwrap();
+
+        // The error is only flagged in the short format header.
+        assert!(
+            forecast.message.starts_with("Tz+13:00 FE0 E\n"),
+            "{}",
+            forecast.message
+        );
+        assert!(forecast.message.contains("03T21 C2 W1@32 P0\n"));
+    }
+
+    /// Test obtaining a forecast using responses recorded from the real services.
+    #[tokio::test]
+    async fn test_forecast_cassette() {
+        let cassette = Cassette::open("mt_cook");
+        let time = time();
+        let options = DynamicOptions::default();
+        let service = ForecastService::new(&time, &cassette, Some(&cassette), &options);
+
+        let forecast = service
+            .forecast(&ForecastRequest {
+                position: Some(Position::new(-43.513832, 170.33975)),
+                ..ForecastRequest::default()
+            })
+            .await
+            .unwrap();
+
+        assert!(forecast
+            .message
+            .starts_with("Tz+13:00 FE0 TE2216\n03T21 C2 F28 W1@32 P0\n"));
+        assert_eq!(None, forecast.html_message);
+    }
+
+    #[tokio::test]
+    async fn test_forecast_no_position() {
+        let forecast_service = forecast_service::MockPort::new();
+        let time = time();
+        let options = DynamicOptions::default();
+        let service = ForecastService::new(&time, &forecast_service, None, &options);
+
+        let error = service
+            .forecast(&ForecastRequest::default())
+            .await
+            .unwrap_err();
+        assert!(matches!(error, ForecastError::NoPosition));
+    }
+
+    #[test]
+    fn test_combine() {
+        let single = FormattedForecast {
+            message: "short".to_owned(),
+            html_message: None,
+            summary: None,
+        };
+        assert_eq!(single, FormattedForecast::combine(vec![single.clone()]));
+
+        let combined = FormattedForecast::combine(vec![
+            single,
+            FormattedForecast {
+                message: "long".to_owned(),
+                html_message: Some("<table></table>".to_owned()),
+                summary: None,
+            },
+        ]);
+        assert_eq!(
+            "Request 1 of 2\nshort\n\nRequest 2 of 2\nlong",
+            combined.message
+        );
+        assert_eq!(
+            Some(
+                "<h3>Request 1 of 2</h3><pre>short</pre><h3>Request 2 of 2</h3><table></table>"
+                    .to_owned()
+            ),
+            combined.html_message
+        );
+    }
+}
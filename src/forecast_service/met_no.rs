@@ -0,0 +1,419 @@
+//! [`Port`] implementation for the
+//! [MET Norway Locationforecast](https://api.met.no/weatherapi/locationforecast/2.0/documentation)
+//! API, which converts its forecasts to [`open_meteo::Forecast`].
+
+use std::collections::HashMap;
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use open_meteo::{
+    units::{Celsius, KmPerHour, Millimetres},
+    Forecast, ForecastParameters, GroundLevel, Hourly, WeatherCode, WindDirection, WindSpeed,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::time;
+
+use super::{Error, ForecastSource, ObtainedForecast, Port};
+
+const PROVIDER: &str = "MET Norway";
+
+/// Options for [`Gateway`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Options {
+    /// Base url of the MET Norway API.
+    ///
+    /// Default is `https://api.met.no`.
+    #[serde(default = "default_base_url")]
+    pub base_url: url::Url,
+    /// `User-Agent` sent with requests, which is required by the
+    /// [terms of service](https://api.met.no/doc/TermsOfService) to identify the application and
+    /// include contact information.
+    ///
+    /// Default is `email-weather github.com/kellpossible/email-weather`.
+    #[serde(default = "default_user_agent")]
+    pub user_agent: String,
+}
+
+fn default_base_url() -> url::Url {
+    "https://api.met.no".parse().expect("Unable to parse url")
+}
+
+fn default_user_agent() -> String {
+    "email-weather github.com/kellpossible/email-weather".to_string()
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+            user_agent: default_user_agent(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct Response {
+    geometry: Geometry,
+    properties: Properties,
+}
+
+#[derive(Deserialize)]
+struct Geometry {
+    /// Longitude, latitude and altitude.
+    coordinates: Vec<f32>,
+}
+
+#[derive(Deserialize)]
+struct Properties {
+    meta: Option<Meta>,
+    timeseries: Vec<TimeStep>,
+}
+
+#[derive(Deserialize)]
+struct Meta {
+    /// When the forecast was last updated.
+    updated_at: DateTime<Utc>,
+}
+
+#[derive(Deserialize)]
+struct TimeStep {
+    time: DateTime<Utc>,
+    data: TimeStepData,
+}
+
+#[derive(Deserialize)]
+struct TimeStepData {
+    instant: Instant,
+    next_1_hours: Option<NextHours>,
+}
+
+#[derive(Deserialize)]
+struct Instant {
+    details: InstantDetails,
+}
+
+#[derive(Deserialize)]
+struct InstantDetails {
+    /// Air pressure at sea level in hPa.
+    air_pressure_at_sea_level: Option<f32>,
+    air_temperature: Option<f32>,
+    /// Relative humidity in %.
+    relative_humidity: Option<f32>,
+    wind_from_direction: Option<f32>,
+    /// Wind speed in m/s.
+    wind_speed: Option<f32>,
+}
+
+#[derive(Deserialize)]
+struct NextHours {
+    summary: Summary,
+    #[serde(default)]
+    details: NextHoursDetails,
+}
+
+#[derive(Deserialize)]
+struct Summary {
+    symbol_code: String,
+}
+
+#[derive(Default, Deserialize)]
+struct NextHoursDetails {
+    precipitation_amount: Option<f32>,
+}
+
+/// Convert a MET Norway weather symbol code (e.g. `lightrainshowers_day`) to the closest
+/// [`WeatherCode`].
+fn weather_code(symbol_code: &str) -> Option<WeatherCode> {
+    let symbol = symbol_code.split('_').next().unwrap_or_default();
+    if symbol.contains("thunder") {
+        return Some(WeatherCode::ThunderstormSlightOrModerate);
+    }
+    Some(match symbol {
+        "clearsky" => WeatherCode::ClearSky,
+        "fair" => WeatherCode::MainlyClear,
+        "partlycloudy" => WeatherCode::PartlyCloudy,
+        "cloudy" => WeatherCode::Overcast,
+        "fog" => WeatherCode::Fog,
+        "lightrain" => WeatherCode::RainSlight,
+        "rain" => WeatherCode::RainModerate,
+        "heavyrain" => WeatherCode::RainHeavy,
+        "lightrainshowers" => WeatherCode::RainShowersSlight,
+        "rainshowers" => WeatherCode::RainShowersModerate,
+        "heavyrainshowers" => WeatherCode::RainShowersViolent,
+        "lightsleet" | "sleet" | "lightsleetshowers" | "sleetshowers" => {
+            WeatherCode::RainFreezingLight
+        }
+        "heavysleet" | "heavysleetshowers" => WeatherCode::RainFreezingHeavy,
+        "lightsnow" => WeatherCode::SnowSlight,
+        "snow" => WeatherCode::SnowModerate,
+        "heavysnow" => WeatherCode::SnowHeavy,
+        "lightsnowshowers" | "snowshowers" => WeatherCode::SnowShowersSlight,
+        "heavysnowshowers" => WeatherCode::SnowShowersHeavy,
+        _ => return None,
+    })
+}
+
+/// Collect `value` for each of the `steps`, or `None` if it is missing for any of them.
+fn collect<T>(steps: &[TimeStep], value: impl Fn(&TimeStep) -> Option<T>) -> Option<Vec<T>> {
+    steps.iter().map(value).collect()
+}
+
+/// The summary of the hour following `step`.
+fn next_1_hours(step: &TimeStep) -> Option<&NextHours> {
+    step.data.next_1_hours.as_ref()
+}
+
+/// Convert the hourly part of the `response` (MET Norway provides hourly time steps for the first
+/// few days, followed by 6 hourly steps) to a [`Forecast`] in UTC.
+fn to_forecast(response: Response, parameters: &ForecastParameters) -> Forecast {
+    let timeseries = response.properties.timeseries;
+    let hourly_len = timeseries
+        .windows(2)
+        .take_while(|steps| steps[1].time - steps[0].time == chrono::Duration::hours(1))
+        .count()
+        + 1;
+    let steps: Vec<TimeStep> = timeseries
+        .into_iter()
+        .take(hourly_len)
+        .filter(|step| step.data.next_1_hours.is_some())
+        .collect();
+
+    let mut hourly = Hourly {
+        time: steps.iter().map(|step| step.time.naive_utc()).collect(),
+        ..Hourly::default()
+    };
+    hourly.temperature_2m = collect(&steps, |step| {
+        step.data.instant.details.air_temperature.map(Celsius)
+    });
+    hourly.precipitation = collect(&steps, |step| {
+        next_1_hours(step)?
+            .details
+            .precipitation_amount
+            .map(Millimetres)
+    });
+    hourly.pressure_msl = collect(&steps, |step| {
+        step.data.instant.details.air_pressure_at_sea_level
+    });
+    hourly.relative_humidity_2m =
+        collect(&steps, |step| step.data.instant.details.relative_humidity);
+    hourly.weather_code = collect(&steps, |step| {
+        weather_code(&next_1_hours(step)?.summary.symbol_code)
+    });
+    if let Some(speed) = collect(&steps, |step| {
+        step.data
+            .instant
+            .details
+            .wind_speed
+            .map(|speed| KmPerHour(speed * 3.6))
+    }) {
+        hourly.wind_speed = WindSpeed::new(HashMap::from([(GroundLevel::L10, speed)]));
+    }
+    if let Some(direction) = collect(&steps, |step| step.data.instant.details.wind_from_direction) {
+        hourly.wind_direction = WindDirection::new(HashMap::from([(GroundLevel::L10, direction)]));
+    }
+
+    let coordinates = &response.geometry.coordinates;
+    Forecast {
+        latitude: coordinates.get(1).copied().unwrap_or(parameters.latitude),
+        longitude: coordinates.first().copied().unwrap_or(parameters.longitude),
+        elevation: coordinates
+            .get(2)
+            .copied()
+            .or(parameters.elevation)
+            .unwrap_or_default(),
+        generation_time_ms: 0.0,
+        utc_offset_seconds: 0,
+        timezone: chrono_tz::UTC,
+        timezone_abbreviation: "UTC".to_string(),
+        hourly: Some(hourly),
+        hourly_units: None,
+    }
+}
+
+/// Obtains forecasts from the MET Norway Locationforecast API. Forecasts are in UTC, and do not
+/// include the freezing level height.
+pub struct Gateway {
+    http_client: reqwest::Client,
+    options: Options,
+    time: &'static dyn time::Port,
+}
+
+impl Gateway {
+    /// Construct a new [`Gateway`].
+    #[must_use]
+    pub fn new(http_client: reqwest::Client, options: Options) -> Self {
+        Self {
+            http_client,
+            options,
+            time: &time::Gateway,
+        }
+    }
+
+    /// Record when forecasts are obtained using `time`.
+    #[must_use]
+    pub fn with_time(self, time: &'static dyn time::Port) -> Self {
+        Self { time, ..self }
+    }
+
+    async fn obtain_response(&self, parameters: &ForecastParameters) -> eyre::Result<Response> {
+        let url = format!(
+            "{}/weatherapi/locationforecast/2.0/compact",
+            self.options.base_url.as_str().trim_end_matches('/')
+        );
+        // The terms of service request that coordinates are truncated to 4 decimals.
+        let mut query = vec![
+            ("lat", format!("{:.4}", parameters.latitude)),
+            ("lon", format!("{:.4}", parameters.longitude)),
+        ];
+        if let Some(elevation) = parameters.elevation {
+            query.push(("altitude", format!("{:.0}", elevation)));
+        }
+        self.http_client
+            .get(url)
+            .header(reqwest::header::USER_AGENT, &self.options.user_agent)
+            .query(&query)
+            .send()
+            .await
+            .wrap_err("Error performing request")?
+            .error_for_status()
+            .wrap_err("Response status unsuccessful")?
+            .json()
+            .await
+            .wrap_err("Error deserializing response")
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn obtain_forecast(
+        &self,
+        parameters: &ForecastParameters,
+    ) -> Result<ObtainedForecast, Error> {
+        let response =
+            self.obtain_response(parameters)
+                .await
+                .map_err(|source| Error::Provider {
+                    provider: PROVIDER,
+                    source,
+                })?;
+        let obtained_at = self.time.utc_now();
+        let issued_at = response
+            .properties
+            .meta
+            .as_ref()
+            .map(|meta| meta.updated_at);
+        Ok(ObtainedForecast {
+            forecast: to_forecast(response, parameters),
+            source: ForecastSource {
+                provider: PROVIDER,
+                model_run: None,
+                issued_at,
+                generation_time_ms: None,
+                obtained_at,
+            },
+        })
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use open_meteo::{units::KmPerHour, ForecastParameters, GroundLevel, WeatherCode};
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use super::{weather_code, Gateway, ObtainedForecast, Options, Port};
+
+    #[test]
+    fn test_weather_code() {
+        assert_eq!(Some(WeatherCode::ClearSky), weather_code("clearsky_day"));
+        assert_eq!(
+            Some(WeatherCode::RainShowersSlight),
+            weather_code("lightrainshowers_polartwilight")
+        );
+        assert_eq!(
+            Some(WeatherCode::ThunderstormSlightOrModerate),
+            weather_code("heavysnowshowersandthunder_night")
+        );
+        assert_eq!(None, weather_code("unknown"));
+    }
+
+    #[tokio::test]
+    async fn test_obtain_forecast() {
+        let step = |time: &str, symbol_code: &str, precipitation: f32| {
+            serde_json::json!({
+                "time": time,
+                "data": {
+                    "instant": { "details": {
+                        "air_temperature": -2.5,
+                        "wind_from_direction": 320.0,
+                        "wind_speed": 5.0,
+                    }},
+                    "next_1_hours": {
+                        "summary": { "symbol_code": symbol_code },
+                        "details": { "precipitation_amount": precipitation },
+                    },
+                },
+            })
+        };
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/weatherapi/locationforecast/2.0/compact"))
+            .and(matchers::query_param("lat", "-43.5138"))
+            .and(matchers::query_param("lon", "170.3398"))
+            .and(matchers::header_exists("User-Agent"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "type": "Feature",
+                "geometry": { "type": "Point", "coordinates": [170.3398, -43.5138, 2216] },
+                "properties": {
+                    "meta": { "updated_at": "2022-12-03T08:00:00Z", "units": {} },
+                    "timeseries": [
+                        step("2022-12-03T08:00:00Z", "partlycloudy_day", 0.0),
+                        step("2022-12-03T09:00:00Z", "lightsnow", 0.5),
+                        // 6 hourly steps are not included.
+                        step("2022-12-03T15:00:00Z", "cloudy", 0.0),
+                    ],
+                },
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let gateway = Gateway::new(
+            reqwest::Client::new(),
+            Options {
+                base_url: server.uri().parse().unwrap(),
+                ..Options::default()
+            },
+        );
+        let ObtainedForecast { forecast, source } = gateway
+            .obtain_forecast(
+                &ForecastParameters::builder()
+                    .latitude(-43.513832)
+                    .longitude(170.33975)
+                    .build(),
+            )
+            .await
+            .unwrap();
+
+        assert_eq!(2216.0, forecast.elevation);
+        assert_eq!(chrono_tz::UTC, forecast.timezone);
+        assert_eq!("MET Norway", source.provider);
+        assert_eq!(
+            Some("2022-12-03T08:00:00Z".parse().unwrap()),
+            source.issued_at
+        );
+        let hourly = forecast.hourly.unwrap();
+        assert_eq!(2, hourly.time.len());
+        assert_eq!(
+            Some(vec![WeatherCode::PartlyCloudy, WeatherCode::SnowSlight]),
+            hourly.weather_code
+        );
+        assert_eq!(
+            Some(&vec![KmPerHour(18.0), KmPerHour(18.0)]),
+            hourly.wind_speed.value(&GroundLevel::L10)
+        );
+        assert!(hourly.freezing_level_height.is_none());
+    }
+}
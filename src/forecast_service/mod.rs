@@ -0,0 +1,626 @@
+//! External weather forecasting service.
+//! See [Port].
+//!
+//! Forecasts can be obtained from several providers (see [`Provider`]), which are tried in order
+//! by [`Failover`]. All providers produce an [`open_meteo::Forecast`], which is the data model
+//! used throughout this application, along with a [`ForecastSource`] describing where it came
+//! from (see [`ObtainedForecast`]).
+
+use std::{
+    collections::HashMap,
+    sync::{Arc, Mutex},
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use open_meteo::{Forecast, ForecastParameters};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::{circuit_breaker::CircuitBreakerPort, time};
+
+pub mod met_no;
+
+/// Error produced by a [Port].
+#[derive(Debug, thiserror::Error)]
+pub enum Error {
+    /// Error from the [open_meteo] service.
+    #[error(transparent)]
+    OpenMeteo(#[from] open_meteo::Error),
+    /// Error from another provider.
+    #[error("Error obtaining forecast from {provider}")]
+    Provider {
+        /// Name of the provider.
+        provider: &'static str,
+        /// The cause of the error.
+        #[source]
+        source: eyre::Error,
+    },
+}
+
+impl Error {
+    /// The reason given by the [open_meteo] API for rejecting the request, if it was rejected.
+    #[must_use]
+    pub fn api_error(&self) -> Option<&open_meteo::ApiError> {
+        match self {
+            Error::OpenMeteo(error) => error.api_error(),
+            Error::Provider { .. } => None,
+        }
+    }
+}
+
+/// A forecast obtained by a [Port], along with where it came from.
+#[derive(Debug, Clone)]
+pub struct ObtainedForecast {
+    /// The forecast.
+    pub forecast: Forecast,
+    /// Where the forecast came from.
+    pub source: ForecastSource,
+}
+
+impl ObtainedForecast {
+    /// Construct a new [`ObtainedForecast`] for `forecast` obtained from `provider` at
+    /// `obtained_at`, without any other details about its source.
+    #[must_use]
+    pub fn new(forecast: Forecast, provider: &'static str, obtained_at: DateTime<Utc>) -> Self {
+        Self {
+            forecast,
+            source: ForecastSource {
+                provider,
+                model_run: None,
+                issued_at: None,
+                generation_time_ms: None,
+                obtained_at,
+            },
+        }
+    }
+
+    /// Construct a new [`ObtainedForecast`] for a `forecast` fixture, obtained from Open-Meteo when
+    /// the fixtures were recorded.
+    #[cfg(test)]
+    pub(crate) fn fixture(forecast: Forecast) -> Self {
+        Self::new(
+            forecast,
+            Provider::OpenMeteo.name(),
+            "2022-12-03T08:00:00Z".parse().unwrap(),
+        )
+    }
+}
+
+/// Where the data of a forecast came from and how fresh it is, so that users can judge how up to
+/// date the forecast is, and the provider is credited.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForecastSource {
+    /// Name of the provider of the forecast data, e.g. `Open-Meteo`.
+    pub provider: &'static str,
+    /// The latest run of the weather model when the forecast was obtained, if it is known (see
+    /// [`ForecastOptions::model_run`]).
+    pub model_run: Option<ModelRun>,
+    /// When the forecast was last updated by the provider, if the provider reports it.
+    pub issued_at: Option<DateTime<Utc>>,
+    /// How long (in milliseconds) the provider took to generate the forecast, if the provider
+    /// reports it.
+    pub generation_time_ms: Option<f32>,
+    /// When the forecast was obtained from the provider, which is earlier than when it was used if
+    /// it was cached (see [`ForecastCache`]).
+    pub obtained_at: DateTime<Utc>,
+}
+
+/// A run of a weather model, see [`ForecastSource::model_run`].
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct ModelRun {
+    /// Name of the model, e.g. `GFS`.
+    pub model: String,
+    /// When the run was initialised.
+    pub initialised_at: DateTime<Utc>,
+}
+
+/// Trait used to allow mocking the forecasting service.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait Port: Send + Sync {
+    /// Obtain a weather forecast for `parameters`. Providers other than [open_meteo] may not
+    /// support all of the requested hourly variables, in which case they are omitted from the
+    /// forecast.
+    async fn obtain_forecast(
+        &self,
+        parameters: &ForecastParameters,
+    ) -> Result<ObtainedForecast, Error>;
+}
+
+/// A provider of weather forecasts, specified in [`ForecastOptions::providers`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Provider {
+    /// [Open-Meteo](https://open-meteo.com/), see [`Gateway`].
+    OpenMeteo,
+    /// [MET Norway Locationforecast](https://api.met.no/weatherapi/locationforecast/2.0/), see
+    /// [`met_no::Gateway`]. It does not provide the freezing level height.
+    MetNo(met_no::Options),
+}
+
+impl Provider {
+    /// Name of the provider, for logging and crediting the provider.
+    pub(crate) fn name(&self) -> &'static str {
+        match self {
+            Provider::OpenMeteo => "Open-Meteo",
+            Provider::MetNo(_) => "MET Norway",
+        }
+    }
+}
+
+/// Options for the forecasting service, specified in [`crate::options::Options`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ForecastOptions {
+    /// Providers used to obtain forecasts, in order of preference. If a provider fails, the next
+    /// provider is tried, see [`Failover`].
+    ///
+    /// Default is `[OpenMeteo]`.
+    #[serde(default = "default_providers")]
+    pub providers: Vec<Provider>,
+    /// How long (in seconds) forecasts obtained from Open-Meteo are cached in memory, so that
+    /// retrying a request does not obtain the same forecast again. `0` disables the cache.
+    ///
+    /// Default is `600`.
+    #[serde(default = "default_cache_ttl_secs")]
+    pub cache_ttl_secs: u32,
+    /// The weather model whose latest run is reported with forecasts obtained from Open-Meteo,
+    /// see [`ModelRunOptions`].
+    ///
+    /// Default is `None`, the model run is not reported.
+    #[serde(default)]
+    pub model_run: Option<ModelRunOptions>,
+}
+
+/// The Open-Meteo weather model whose latest run is reported with forecasts, specified in
+/// [`ForecastOptions::model_run`]. Open-Meteo combines several models by default, so this should
+/// be the model which forecasts for the region served by the deployment mostly rely on.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct ModelRunOptions {
+    /// Name of the model shown to users, e.g. `GFS`.
+    pub name: String,
+    /// Id of the model in the Open-Meteo API, used to obtain its metadata (see
+    /// [`open_meteo::model::obtain_model_metadata()`]), e.g. `ncep_gfs013`.
+    pub id: String,
+}
+
+fn default_providers() -> Vec<Provider> {
+    vec![Provider::OpenMeteo]
+}
+
+fn default_cache_ttl_secs() -> u32 {
+    600
+}
+
+impl Default for ForecastOptions {
+    fn default() -> Self {
+        Self {
+            providers: default_providers(),
+            cache_ttl_secs: default_cache_ttl_secs(),
+            model_run: None,
+        }
+    }
+}
+
+/// Key identifying the forecast requested with `parameters`. The query parameters are sorted
+/// because the order of the hourly variables is not stable.
+pub(crate) fn parameters_key(
+    parameters: &ForecastParameters,
+) -> Result<String, serde_urlencoded::ser::Error> {
+    let query = serde_urlencoded::to_string(parameters)?;
+    let mut pairs: Vec<&str> = query.split('&').collect();
+    pairs.sort_unstable();
+    Ok(pairs.join("&"))
+}
+
+/// In-memory cache of forecasts which expire after a time to live since they were obtained (see
+/// [`ForecastSource::obtained_at`]), see [`Gateway::with_cache()`].
+pub struct ForecastCache {
+    ttl: chrono::Duration,
+    time: &'static dyn time::Port,
+    entries: Mutex<HashMap<String, ObtainedForecast>>,
+}
+
+impl ForecastCache {
+    /// Construct a new [ForecastCache] where forecasts expire after `ttl`.
+    #[must_use]
+    pub fn new(ttl: chrono::Duration, time: &'static dyn time::Port) -> Self {
+        Self {
+            ttl,
+            time,
+            entries: Mutex::default(),
+        }
+    }
+
+    fn entries(&self) -> std::sync::MutexGuard<'_, HashMap<String, ObtainedForecast>> {
+        self.entries
+            .lock()
+            .expect("forecast cache mutex is poisoned")
+    }
+
+    /// Whether `forecast` has not expired at `now`.
+    fn is_fresh(&self, forecast: &ObtainedForecast, now: DateTime<Utc>) -> bool {
+        forecast.source.obtained_at + self.ttl > now
+    }
+
+    /// Obtain the cached forecast for `key`, if it has not expired.
+    fn get(&self, key: &str) -> Option<ObtainedForecast> {
+        let now = self.time.utc_now();
+        self.entries()
+            .get(key)
+            .filter(|forecast| self.is_fresh(forecast, now))
+            .cloned()
+    }
+
+    /// Cache the `forecast` for `key`, and remove expired forecasts.
+    fn insert(&self, key: String, forecast: ObtainedForecast) {
+        let now = self.time.utc_now();
+        let mut entries = self.entries();
+        entries.retain(|_, forecast| self.is_fresh(forecast, now));
+        entries.insert(key, forecast);
+    }
+}
+
+/// Concrete implementation of [Port] for [open_meteo].
+pub struct Gateway {
+    http_client: reqwest::Client,
+    base_url: String,
+    cache: Option<ForecastCache>,
+    model_run: Option<ModelRunOptions>,
+    time: &'static dyn time::Port,
+}
+
+impl Gateway {
+    /// Construct a new [Gateway] using the public API.
+    #[must_use]
+    pub fn new(http_client: reqwest::Client) -> Self {
+        Self::with_base_url(http_client, open_meteo::DEFAULT_BASE_URL.to_owned())
+    }
+
+    /// Construct a new [Gateway] using the API hosted at `base_url`.
+    #[must_use]
+    pub fn with_base_url(http_client: reqwest::Client, base_url: String) -> Self {
+        Self {
+            http_client,
+            base_url,
+            cache: None,
+            model_run: None,
+            time: &time::Gateway,
+        }
+    }
+
+    /// Cache forecasts using `cache`.
+    #[must_use]
+    pub fn with_cache(self, cache: ForecastCache) -> Self {
+        Self {
+            cache: Some(cache),
+            ..self
+        }
+    }
+
+    /// Report the latest run of the model specified in `model_run` with forecasts, if specified.
+    #[must_use]
+    pub fn with_model_run(self, model_run: Option<ModelRunOptions>) -> Self {
+        Self { model_run, ..self }
+    }
+
+    /// Record when forecasts are obtained using `time`.
+    #[must_use]
+    pub fn with_time(self, time: &'static dyn time::Port) -> Self {
+        Self { time, ..self }
+    }
+
+    /// Obtain the latest run of the model specified in `options`, `None` if it can't be obtained,
+    /// because it is not required for the forecast.
+    async fn obtain_model_run(&self, options: &ModelRunOptions) -> Option<ModelRun> {
+        match open_meteo::model::obtain_model_metadata_from(
+            &self.http_client,
+            &self.base_url,
+            &options.id,
+        )
+        .await
+        {
+            Ok(metadata) => Some(ModelRun {
+                model: options.name.clone(),
+                initialised_at: metadata.last_run_initialisation_time,
+            }),
+            Err(error) => {
+                tracing::warn!(
+                    "Error obtaining metadata of model {}: {}",
+                    options.id,
+                    error
+                );
+                None
+            }
+        }
+    }
+
+    /// Obtain the forecast as the json returned by the API, see
+    /// [open_meteo::obtain_forecast_json()].
+    pub async fn obtain_forecast_json(
+        &self,
+        parameters: &ForecastParameters,
+    ) -> Result<String, open_meteo::Error> {
+        open_meteo::obtain_forecast_json_from(&self.http_client, &self.base_url, parameters).await
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn obtain_forecast(
+        &self,
+        parameters: &ForecastParameters,
+    ) -> Result<ObtainedForecast, Error> {
+        let cache = match &self.cache {
+            Some(cache) => Some((
+                cache,
+                parameters_key(parameters).map_err(open_meteo::Error::from)?,
+            )),
+            None => None,
+        };
+        if let Some((cache, key)) = &cache {
+            if let Some(forecast) = cache.get(key) {
+                tracing::debug!("Using cached forecast");
+                return Ok(forecast);
+            }
+        }
+
+        let forecast =
+            open_meteo::obtain_forecast_from(&self.http_client, &self.base_url, parameters).await?;
+        let obtained_at = self.time.utc_now();
+        let model_run = match &self.model_run {
+            Some(options) => self.obtain_model_run(options).await,
+            None => None,
+        };
+        let forecast = ObtainedForecast {
+            source: ForecastSource {
+                provider: Provider::OpenMeteo.name(),
+                model_run,
+                issued_at: None,
+                generation_time_ms: Some(forecast.generation_time_ms),
+                obtained_at,
+            },
+            forecast,
+        };
+        if let Some((cache, key)) = cache {
+            cache.insert(key, forecast.clone());
+        }
+        Ok(forecast)
+    }
+}
+
+/// [Port] which obtains forecasts from each of its providers in order, until one succeeds.
+#[derive(Clone)]
+pub struct Failover {
+    providers: Vec<(&'static str, Arc<dyn Port>)>,
+}
+
+impl Failover {
+    /// Construct a new [Failover] which tries the `providers` in order.
+    #[must_use]
+    pub fn new(providers: Vec<(&'static str, Arc<dyn Port>)>) -> Self {
+        Self { providers }
+    }
+
+    /// Construct a new [Failover] using the providers specified in `options`.
+    #[must_use]
+    pub fn from_options(
+        http_client: &reqwest::Client,
+        options: &ForecastOptions,
+        time: &'static dyn time::Port,
+    ) -> Self {
+        let providers = options
+            .providers
+            .iter()
+            .map(|provider| {
+                let port: Arc<dyn Port> = match provider {
+                    Provider::OpenMeteo => {
+                        let gateway = Gateway::new(http_client.clone())
+                            .with_model_run(options.model_run.clone())
+                            .with_time(time);
+                        if options.cache_ttl_secs == 0 {
+                            Arc::new(gateway)
+                        } else {
+                            Arc::new(gateway.with_cache(ForecastCache::new(
+                                chrono::Duration::seconds(i64::from(options.cache_ttl_secs)),
+                                time,
+                            )))
+                        }
+                    }
+                    Provider::MetNo(met_no_options) => Arc::new(
+                        met_no::Gateway::new(http_client.clone(), met_no_options.clone())
+                            .with_time(time),
+                    ),
+                };
+                (provider.name(), port)
+            })
+            .collect();
+        Self::new(providers)
+    }
+}
+
+#[async_trait]
+impl Port for Failover {
+    async fn obtain_forecast(
+        &self,
+        parameters: &ForecastParameters,
+    ) -> Result<ObtainedForecast, Error> {
+        let mut last_error = None;
+        for (i, (name, provider)) in self.providers.iter().enumerate() {
+            match provider.obtain_forecast(parameters).await {
+                Ok(forecast) => {
+                    if i > 0 {
+                        tracing::info!("Obtained forecast from fallback provider {}", name);
+                    }
+                    return Ok(forecast);
+                }
+                Err(error) => {
+                    // A request rejected as invalid will also be rejected by other providers.
+                    if error.api_error().map_or(false, |api_error| {
+                        !matches!(api_error, open_meteo::ApiError::RateLimited(_))
+                    }) {
+                        return Err(error);
+                    }
+                    tracing::warn!("Error obtaining forecast from {}: {}", name, error);
+                    last_error = Some(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or_else(|| Error::Provider {
+            provider: "failover",
+            source: eyre::eyre!("No forecast providers are configured"),
+        }))
+    }
+}
+
+#[async_trait]
+impl<'a, P: Port> Port for CircuitBreakerPort<'a, P> {
+    async fn obtain_forecast(
+        &self,
+        parameters: &ForecastParameters,
+    ) -> Result<ObtainedForecast, Error> {
+        self.request(|inner| inner.obtain_forecast(parameters))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::Arc;
+
+    use once_cell::sync::Lazy;
+    use open_meteo::{Forecast, ForecastParameters};
+    use reqwest::StatusCode;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use crate::time;
+
+    use super::{
+        Error, Failover, ForecastCache, Gateway, MockPort, ModelRun, ModelRunOptions,
+        ObtainedForecast, Port,
+    };
+
+    static FORECAST_MT_COOK: Lazy<Forecast> = Lazy::new(|| {
+        serde_json::from_str(&std::fs::read_to_string("fixtures/forecast_mt_cook.json").unwrap())
+            .unwrap()
+    });
+
+    fn parameters() -> ForecastParameters {
+        ForecastParameters::builder()
+            .latitude(-43.513832)
+            .longitude(170.33975)
+            .build()
+    }
+
+    fn unavailable() -> Error {
+        Error::Provider {
+            provider: "test",
+            source: eyre::eyre!("unavailable"),
+        }
+    }
+
+    /// Test that the next provider is used when the first fails.
+    #[tokio::test]
+    async fn test_failover() {
+        let mut failing = MockPort::new();
+        failing
+            .expect_obtain_forecast()
+            .times(1)
+            .returning(|_| Err(unavailable()));
+        let mut succeeding = MockPort::new();
+        succeeding.expect_obtain_forecast().times(1).returning(|_| {
+            Ok(ObtainedForecast::new(
+                FORECAST_MT_COOK.clone(),
+                "succeeding",
+                "2022-12-03T08:00:00Z".parse().unwrap(),
+            ))
+        });
+
+        let failover = Failover::new(vec![
+            ("failing", Arc::new(failing) as Arc<dyn Port>),
+            ("succeeding", Arc::new(succeeding) as Arc<dyn Port>),
+        ]);
+        let forecast = failover.obtain_forecast(&parameters()).await.unwrap();
+        assert_eq!(FORECAST_MT_COOK.elevation, forecast.forecast.elevation);
+        assert_eq!("succeeding", forecast.source.provider);
+    }
+
+    /// Test that forecasts are obtained from the cache until they expire, along with when they
+    /// were obtained and the model run.
+    #[tokio::test]
+    async fn test_gateway_cache() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v1/forecast"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                std::fs::read("fixtures/forecast_mt_cook.json").unwrap(),
+                "application/json",
+            ))
+            .expect(2)
+            .mount(&server)
+            .await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/data/ncep_gfs013/static/meta.json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "last_run_availability_time": 1_670_058_300,
+                "last_run_initialisation_time": 1_670_047_200,
+            })))
+            .expect(2)
+            .mount(&server)
+            .await;
+
+        let time: &'static time::Simulated = Box::leak(Box::new(time::Simulated::new(
+            "2022-12-03T08:00:00Z".parse().unwrap(),
+        )));
+        let gateway = Gateway::with_base_url(reqwest::Client::new(), server.uri())
+            .with_cache(ForecastCache::new(chrono::Duration::seconds(600), time))
+            .with_model_run(Some(ModelRunOptions {
+                name: "GFS".to_owned(),
+                id: "ncep_gfs013".to_owned(),
+            }))
+            .with_time(time);
+
+        let first = gateway.obtain_forecast(&parameters()).await.unwrap();
+        assert_eq!("Open-Meteo", first.source.provider);
+        assert_eq!(
+            Some(ModelRun {
+                model: "GFS".to_owned(),
+                initialised_at: "2022-12-03T06:00:00Z".parse().unwrap(),
+            }),
+            first.source.model_run
+        );
+        assert!(first.source.generation_time_ms.is_some());
+        time.advance(std::time::Duration::from_secs(599));
+        let cached = gateway.obtain_forecast(&parameters()).await.unwrap();
+        assert_eq!(first.source, cached.source);
+        time.advance(std::time::Duration::from_secs(1));
+        let refreshed = gateway.obtain_forecast(&parameters()).await.unwrap();
+        assert_eq!(
+            "2022-12-03T08:10:00Z"
+                .parse::<chrono::DateTime<chrono::Utc>>()
+                .unwrap(),
+            refreshed.source.obtained_at
+        );
+    }
+
+    /// Test that a request rejected as invalid is not retried with other providers.
+    #[tokio::test]
+    async fn test_failover_rejected() {
+        let mut rejecting = MockPort::new();
+        rejecting.expect_obtain_forecast().times(1).returning(|_| {
+            Err(open_meteo::Error::ResponseStatusNotSuccessful {
+                code: StatusCode::BAD_REQUEST,
+                reason: open_meteo::ApiError::InvalidParameter("invalid".to_string()),
+            }
+            .into())
+        });
+        let mut unused = MockPort::new();
+        unused.expect_obtain_forecast().never();
+
+        let failover = Failover::new(vec![
+            ("rejecting", Arc::new(rejecting) as Arc<dyn Port>),
+            ("unused", Arc::new(unused) as Arc<dyn Port>),
+        ]);
+        let error = failover.obtain_forecast(&parameters()).await.unwrap_err();
+        assert!(error.api_error().is_some());
+    }
+}
@@ -0,0 +1,249 @@
+//! Erasure of the data stored about a sender, requested using the
+//! [`Command::ForgetMe`](crate::request::Command::ForgetMe) command or the admin api (see
+//! [`crate::admin_api`]).
+//!
+//! The only data stored about a sender outside of the queues is the hash of their identity in the
+//! [`Analytics`] and [`SenderConfirmations`] databases, the forecasts in their [`Feeds`] (if
+//! they subscribed to one), the digests of their [`PreviousForecasts`], and their registered
+//! [`PublicKeys`]. The queues are processed in order, so by the time a request to forget
+//! a sender is processed, their earlier requests have already been removed from the processing
+//! queue, and are not stored at rest with more than their address (see
+//! [`crate::redact::redact_at_rest()`]).
+
+use crate::{
+    analytics::{Analytics, Forgotten},
+    changes::PreviousForecasts,
+    confirmation::SenderConfirmations,
+    encryption::PublicKeys,
+    feed::Feeds,
+    redact::hash_identity,
+};
+
+/// Hash of a sender's `identity` (an email address, or an inReach device id), which is stored
+/// instead of the identity itself. Email addresses are case-insensitive.
+#[must_use]
+pub fn sender_hash(identity: &str) -> String {
+    let identity = identity.trim();
+    if identity.contains('@') {
+        hash_identity(&identity.to_lowercase())
+    } else {
+        hash_identity(identity)
+    }
+}
+
+/// Erase the data stored about the sender with the specified `identity` (see [`sender_hash()`]).
+pub fn forget(
+    identity: &str,
+    analytics: Option<&Analytics>,
+    confirmations: Option<&SenderConfirmations>,
+    feeds: Option<&Feeds>,
+    previous_forecasts: Option<&PreviousForecasts>,
+    public_keys: Option<&PublicKeys>,
+) -> eyre::Result<Forgotten> {
+    let sender = sender_hash(identity);
+    let mut forgotten = match analytics {
+        Some(analytics) => analytics.forget(&sender)?,
+        None => Forgotten::default(),
+    };
+    if let Some(confirmations) = confirmations {
+        forgotten.confirmations = confirmations.forget(&sender)?;
+    }
+    if let Some(feeds) = feeds {
+        forgotten.feeds = feeds.forget(&sender)?;
+    }
+    if let Some(previous_forecasts) = previous_forecasts {
+        forgotten.previous_forecasts = previous_forecasts.forget(&sender)?;
+    }
+    if let Some(public_keys) = public_keys {
+        forgotten.public_keys = public_keys.forget(&sender)?;
+    }
+    tracing::info!(
+        "Forgot sender {}: {} requests and {} replies erased",
+        sender,
+        forgotten.requests,
+        forgotten.replies
+    );
+    Ok(forgotten)
+}
+
+/// The message sent in reply to [`Command::ForgetMe`](crate::request::Command::ForgetMe). A
+/// `short` message fits within the 160 character limit of an inReach reply.
+#[must_use]
+pub fn message(forgotten: Forgotten, short: bool) -> String {
+    if short {
+        format!(
+            "Your data has been erased ({} requests)",
+            forgotten.requests
+        )
+    } else {
+        format!(
+            "The data stored about you has been erased: {} requests and {} replies.\n\
+            \n\
+            Any forecasts that you request in the future will be recorded again.",
+            forgotten.requests, forgotten.replies
+        )
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::{Duration, NaiveDate};
+
+    use crate::{
+        analytics::{
+            Analytics, DeviceKind, Forgotten, FormatKind, Outcome, ReplyEvent, ReplyOutcome,
+            RequestEvent,
+        },
+        changes::{ForecastDigest, PreviousForecasts},
+        confirmation::SenderConfirmations,
+        correlation::RequestId,
+        encryption::{KeyChange, PublicKeys},
+        feed::Feeds,
+        gis::Position,
+        store::Store,
+        time::test_now as now,
+    };
+
+    use super::{forget, message, sender_hash};
+
+    /// Number of rows of `sender` in each of the tables of `store` with a `sender` column.
+    fn sender_rows(store: &Store, sender: &str) -> Vec<(String, u64)> {
+        store
+            .sender_tables()
+            .into_iter()
+            .map(|table| {
+                let rows = store
+                    .connection()
+                    .query_row(
+                        &format!("SELECT COUNT(*) FROM {table} WHERE sender = ?1"),
+                        [sender],
+                        |row| row.get(0),
+                    )
+                    .unwrap();
+                (table, rows)
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_forget() {
+        let analytics = Analytics::open_in_memory().unwrap();
+        let confirmations = SenderConfirmations::open_in_memory().unwrap();
+        let feeds = Feeds::open_in_memory("https://example.com/".parse().unwrap()).unwrap();
+        let previous_forecasts = PreviousForecasts::open_in_memory().unwrap();
+        let public_keys = PublicKeys::open_in_memory().unwrap();
+
+        let mut digest = ForecastDigest::default();
+        digest.push(
+            NaiveDate::from_ymd_opt(2022, 12, 3).unwrap(),
+            Some(1800.0),
+            Some(0.0),
+            None,
+        );
+        for identity in ["luke@example.com", "luke@example.com", "leia@example.com"] {
+            let sender = sender_hash(identity);
+            let request_id = RequestId::new();
+            analytics
+                .insert(
+                    &RequestEvent::new(
+                        request_id,
+                        now(),
+                        None,
+                        DeviceKind::Plain,
+                        FormatKind::Short,
+                        Duration::milliseconds(100),
+                        Outcome::Success,
+                    )
+                    .with_sender(sender.clone()),
+                )
+                .unwrap();
+            analytics
+                .insert_reply(&ReplyEvent {
+                    request_id,
+                    timestamp: now(),
+                    device: DeviceKind::Plain,
+                    attempts: 1,
+                    outcome: ReplyOutcome::Sent,
+                })
+                .unwrap();
+            confirmations.check(&sender, None, now()).unwrap();
+            feeds.subscribe(&sender, now()).unwrap();
+            feeds.record(&sender, "03T21 <T5>", now()).unwrap();
+            previous_forecasts
+                .replace(&sender, Position::new(-43.5, 170.3), &digest, now())
+                .unwrap();
+            public_keys.register(&sender, "key", now()).unwrap();
+            public_keys
+                .request(&sender, &KeyChange::Remove, now())
+                .unwrap();
+        }
+
+        let forgotten = forget(
+            "Luke@Example.com",
+            Some(&analytics),
+            Some(&confirmations),
+            Some(&feeds),
+            Some(&previous_forecasts),
+            Some(&public_keys),
+        )
+        .unwrap();
+        assert_eq!(
+            Forgotten {
+                requests: 2,
+                replies: 2,
+                confirmations: 1,
+                feeds: 3,
+                previous_forecasts: 1,
+                public_keys: 2,
+            },
+            forgotten
+        );
+
+        // Every table which records senders no longer contains the forgotten sender, and still
+        // contains the other sender.
+        let stores = [
+            analytics.store(),
+            confirmations.store(),
+            feeds.store(),
+            previous_forecasts.store(),
+            public_keys.store(),
+        ];
+        for store in stores {
+            let tables = store.sender_tables();
+            assert!(!tables.is_empty());
+            for (table, rows) in sender_rows(store, &sender_hash("luke@example.com")) {
+                assert_eq!(0, rows, "{table}");
+            }
+            for (table, rows) in sender_rows(store, &sender_hash("leia@example.com")) {
+                assert!(rows > 0, "{table}");
+            }
+        }
+        // Replies are recorded with the request id rather than the sender.
+        assert_eq!(1, analytics.recent_replies(10, None).unwrap().len());
+    }
+
+    #[test]
+    fn test_sender_hash() {
+        assert_eq!(
+            sender_hash("Luke@Example.com "),
+            sender_hash("luke@example.com")
+        );
+        assert_ne!(sender_hash("ABC123"), sender_hash("abc123"));
+        // Identities which are already hashed when they are stored at rest are unchanged.
+        let hashed = sender_hash("000aa0e6");
+        assert_eq!(hashed, sender_hash(&hashed));
+    }
+
+    #[test]
+    fn test_message_short() {
+        let forgotten = Forgotten {
+            requests: 1000,
+            replies: 1000,
+            confirmations: 1,
+            feeds: 51,
+            previous_forecasts: 10,
+            public_keys: 1,
+        };
+        assert!(message(forgotten, true).len() <= 160);
+    }
+}
@@ -0,0 +1,659 @@
+//! Options for formatting forecasts (see [`FormatForecastOptions`]), and decoding forecasts
+//! received in the short format (see [`decode_short()`]). Unlike [`crate::process`], this is
+//! available without the `server` feature, so that it can be used in a browser (see
+//! `crate::wasm`).
+
+use std::fmt::Display;
+
+use open_meteo::WeatherCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::language::Language;
+
+/// Extra options for short [`FormatDetail`].
+#[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct ShortFormatDetail {
+    /// Limit to length of message.
+    pub length_limit: Option<usize>,
+}
+
+/// Extra options for long [`FormatDetail`].
+#[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct LongFormatDetail {
+    /// Render the table using html
+    pub style: Option<LongFormatStyle>,
+}
+
+/// Extra options for long [`FormatDetail`].
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum LongFormatStyle {
+    /// Render table and features using html.
+    Html,
+    /// Render table and features using plain text.
+    PlainText,
+}
+
+/// What amount of detail to use for formatting the forecast message.
+#[derive(PartialEq, Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum FormatDetail {
+    /// As short as possible. e.g. `F24`
+    Short(ShortFormatDetail),
+    /// Expanded with full detail. e.g. `Freezing Level: 2400m`
+    Long(LongFormatDetail),
+}
+
+impl Default for FormatDetail {
+    fn default() -> Self {
+        Self::Short(ShortFormatDetail::default())
+    }
+}
+
+/// What the forecast message contains.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum FormatMode {
+    /// Rows of the forecast variables at regular intervals over the forecast window.
+    Rows,
+    /// Temperature, dewpoint and wind at each of the
+    /// [`SOUNDING_PRESSURE_LEVELS`](crate::process::SOUNDING_PRESSURE_LEVELS) above the terrain,
+    /// for the current time.
+    Sounding,
+    /// Wind and temperature at each of the
+    /// [`WINDS_ALOFT_ALTITUDES`](crate::process::WINDS_ALOFT_ALTITUDES) above the terrain, for the
+    /// current time, in the style of an aviation winds aloft (FB) forecast.
+    WindsAloft,
+}
+
+impl Default for FormatMode {
+    fn default() -> Self {
+        Self::Rows
+    }
+}
+
+/// Unit used for wind speeds in the forecast message.
+#[derive(PartialEq, Eq, Clone, Copy, Debug, Serialize, Deserialize, JsonSchema)]
+pub enum WindUnit {
+    /// Kilometres per hour. e.g. `W2` (km/h/10) in the short format.
+    KmPerHour,
+    /// Force on the Beaufort scale. e.g. `WF4` in the short format, and `force 4 (moderate
+    /// breeze)` in the long format.
+    Beaufort,
+}
+
+impl Default for WindUnit {
+    fn default() -> Self {
+        Self::KmPerHour
+    }
+}
+
+/// Minimum wind speed (km/h) and description of each force on the Beaufort scale.
+pub(crate) const BEAUFORT_SCALE: [(f32, &str); 13] = [
+    (0.0, "calm"),
+    (1.0, "light air"),
+    (6.0, "light breeze"),
+    (12.0, "gentle breeze"),
+    (20.0, "moderate breeze"),
+    (29.0, "fresh breeze"),
+    (39.0, "strong breeze"),
+    (50.0, "near gale"),
+    (62.0, "gale"),
+    (75.0, "strong gale"),
+    (89.0, "storm"),
+    (103.0, "violent storm"),
+    (118.0, "hurricane force"),
+];
+
+/// Force on the Beaufort wind scale.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) struct Beaufort(pub(crate) usize);
+
+impl Beaufort {
+    /// Description of the force, e.g. `moderate breeze`.
+    fn description(self) -> &'static str {
+        BEAUFORT_SCALE
+            .get(self.0)
+            .map_or("unknown", |(_, description)| description)
+    }
+}
+
+impl Display for Beaufort {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "force {} ({})", self.0, self.description())
+    }
+}
+
+/// Options for formatting the forecast.
+#[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize, JsonSchema)]
+pub struct FormatForecastOptions {
+    /// Detail to apply to formatting the message.
+    pub detail: FormatDetail,
+    /// What the message contains.
+    #[serde(default)]
+    pub mode: FormatMode,
+    /// Unit used for wind speeds.
+    #[serde(default)]
+    pub wind_unit: WindUnit,
+    /// Language used for the times in the long format, the language of the reply (see
+    /// [`Language::detect()`]) rather than part of the request.
+    #[serde(skip)]
+    #[schemars(skip)]
+    pub language: Language,
+}
+
+/// Direction that a value is changing, compared with the previous row of the forecast.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Trend {
+    /// The value has increased (`+` in the short format).
+    Rising,
+    /// The value has decreased (`-` in the short format).
+    Falling,
+}
+
+/// The first line of a forecast in the short format, see [`decode_short()`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DecodedHeader {
+    /// Offset of the forecast times from UTC in minutes.
+    pub utc_offset_minutes: Option<i64>,
+    /// Elevation of the forecast model grid cell in metres.
+    pub forecast_elevation: Option<f32>,
+    /// Elevation of the terrain at the requested position in metres.
+    pub terrain_elevation: Option<f32>,
+    /// Whether the forecast is for a grid cell on the sea (rather than on land).
+    pub sea_cell: bool,
+    /// Whether errors occurred while processing the request (they are only described in the long
+    /// format).
+    pub errors: bool,
+}
+
+/// A forecast parameter decoded from the short format, with the values converted back to their
+/// units, see [`decode_short()`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "parameter", rename_all = "snake_case")]
+pub enum DecodedParameter {
+    /// WMO weather code (`C`).
+    WeatherCode {
+        /// The code.
+        code: u8,
+        /// Description of the code, `None` if the code is unknown.
+        description: Option<String>,
+    },
+    /// Freezing level height (`F`) in metres.
+    FreezingLevelHeight {
+        /// Height in metres.
+        metres: f32,
+        /// Trend compared with the previous row, `None` if steady or this is the first row.
+        trend: Option<Trend>,
+    },
+    /// Wind (`W`) at 10m above the ground.
+    Wind {
+        /// Speed in km/h.
+        speed_kmh: f32,
+        /// Speed of gusts in km/h, if included (`g`).
+        gusts_kmh: Option<f32>,
+        /// Direction in degrees that the wind is coming from.
+        direction_degrees: f32,
+    },
+    /// Wind (`WF`) at 10m above the ground, using [`WindUnit::Beaufort`].
+    WindBeaufort {
+        /// Force on the Beaufort scale.
+        force: u8,
+        /// Force of gusts on the Beaufort scale, if included (`gF`).
+        gusts_force: Option<u8>,
+        /// Direction in degrees that the wind is coming from.
+        direction_degrees: f32,
+    },
+    /// Precipitation (`P`) accumulated since the previous row in millimetres.
+    Precipitation {
+        /// Precipitation in millimetres.
+        millimetres: f32,
+        /// Probability of precipitation percentage, if included (`/`).
+        probability_percent: Option<f32>,
+    },
+    /// Cloud cover (`CL`) of the low, mid and high levels.
+    CloudCover {
+        /// Low level cloud cover percentage.
+        low_percent: f32,
+        /// Mid level cloud cover percentage.
+        mid_percent: f32,
+        /// High level cloud cover percentage.
+        high_percent: f32,
+    },
+    /// Relative humidity (`H`).
+    RelativeHumidity {
+        /// Relative humidity percentage.
+        percent: f32,
+    },
+    /// Dew point temperature (`D`).
+    Dewpoint {
+        /// Dew point in degrees Celsius.
+        celsius: f32,
+    },
+    /// Air pressure at mean sea level (`B`).
+    PressureMsl {
+        /// Pressure in hPa.
+        hpa: f32,
+        /// Tendency over the previous 3 hours, `None` if steady or unknown.
+        tendency: Option<Trend>,
+    },
+    /// Peak UV index of the day (`U`).
+    UvIndex {
+        /// The UV index.
+        index: f32,
+    },
+    /// Visibility (`V`).
+    Visibility {
+        /// Visibility in metres.
+        metres: f32,
+    },
+    /// Temperature (`T`) and apparent temperature (`/`).
+    ApparentTemperature {
+        /// Ambient air temperature in °C.
+        celsius: f32,
+        /// Perceived (feels-like) temperature in °C.
+        apparent_celsius: f32,
+    },
+}
+
+/// A row of a forecast in the short format, see [`decode_short()`].
+#[derive(Debug, Clone, PartialEq, Serialize)]
+pub struct DecodedRow {
+    /// Day of the month (in the forecast time zone).
+    pub day: u32,
+    /// Hour of the day (in the forecast time zone).
+    pub hour: u32,
+    /// Forecast parameters for this time.
+    pub parameters: Vec<DecodedParameter>,
+}
+
+/// A forecast decoded from the short format, see [`decode_short()`].
+#[derive(Debug, Clone, Default, PartialEq, Serialize)]
+pub struct DecodedForecast {
+    /// The header line.
+    pub header: DecodedHeader,
+    /// The forecast rows.
+    pub rows: Vec<DecodedRow>,
+    /// Tokens which could not be decoded.
+    pub unrecognized: Vec<String>,
+}
+
+/// Write a description of the `trend` (if any) following a value.
+fn write_trend(f: &mut std::fmt::Formatter<'_>, trend: Option<Trend>) -> std::fmt::Result {
+    match trend {
+        Some(Trend::Rising) => write!(f, " (rising)"),
+        Some(Trend::Falling) => write!(f, " (falling)"),
+        None => Ok(()),
+    }
+}
+
+impl Display for DecodedParameter {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Self::WeatherCode {
+                code,
+                description: Some(description),
+            } => write!(f, "{} (C{})", description, code),
+            Self::WeatherCode {
+                code,
+                description: None,
+            } => write!(f, "unknown weather (C{})", code),
+            Self::FreezingLevelHeight { metres, trend } => {
+                write!(f, "freezing level {:.0}m", metres)?;
+                write_trend(f, *trend)
+            }
+            Self::Wind {
+                speed_kmh,
+                gusts_kmh,
+                direction_degrees,
+            } => {
+                write!(f, "wind {:.0}km/h", speed_kmh)?;
+                if let Some(gusts_kmh) = gusts_kmh {
+                    write!(f, " gusting {:.0}km/h", gusts_kmh)?;
+                }
+                write!(f, " from {:.0}°", direction_degrees)
+            }
+            Self::WindBeaufort {
+                force,
+                gusts_force,
+                direction_degrees,
+            } => {
+                write!(f, "wind {}", Beaufort(usize::from(*force)))?;
+                if let Some(gusts_force) = gusts_force {
+                    write!(f, " gusting {}", Beaufort(usize::from(*gusts_force)))?;
+                }
+                write!(f, " from {:.0}°", direction_degrees)
+            }
+            Self::Precipitation {
+                millimetres,
+                probability_percent,
+            } => {
+                write!(f, "precipitation {:.0}mm", millimetres)?;
+                if let Some(probability_percent) = probability_percent {
+                    write!(f, " ({:.0}% chance)", probability_percent)?;
+                }
+                Ok(())
+            }
+            Self::CloudCover {
+                low_percent,
+                mid_percent,
+                high_percent,
+            } => write!(
+                f,
+                "cloud cover low {:.0}%, mid {:.0}%, high {:.0}%",
+                low_percent, mid_percent, high_percent
+            ),
+            Self::RelativeHumidity { percent } => write!(f, "humidity {:.0}%", percent),
+            Self::Dewpoint { celsius } => write!(f, "dew point {:.0}°C", celsius),
+            Self::PressureMsl { hpa, tendency } => {
+                write!(f, "pressure {:.0}hPa", hpa)?;
+                write_trend(f, *tendency)
+            }
+            Self::UvIndex { index } => write!(f, "peak UV index {:.0}", index),
+            Self::Visibility { metres } => write!(f, "visibility {:.1}km", metres / 1000.0),
+            Self::ApparentTemperature {
+                celsius,
+                apparent_celsius,
+            } => write!(
+                f,
+                "temperature {:.0}°C, feels like {:.0}°C",
+                celsius, apparent_celsius
+            ),
+        }
+    }
+}
+
+/// Expands the decoded forecast into labelled values, one row per line.
+impl Display for DecodedForecast {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        let header = &self.header;
+        if let Some(offset) = header.utc_offset_minutes {
+            let sign = if offset < 0 { '-' } else { '+' };
+            let offset = offset.abs();
+            writeln!(
+                f,
+                "Time zone: UTC{}{:02}:{:02}",
+                sign,
+                offset / 60,
+                offset % 60
+            )?;
+        }
+        if let Some(elevation) = header.forecast_elevation {
+            writeln!(f, "Forecast elevation: {:.0}m", elevation)?;
+        }
+        if let Some(elevation) = header.terrain_elevation {
+            writeln!(f, "Terrain elevation: {:.0}m", elevation)?;
+        }
+        if header.sea_cell {
+            writeln!(f, "Grid cell: sea")?;
+        }
+        if header.errors {
+            writeln!(
+                f,
+                "Errors occurred while processing the request (use the long format for details)"
+            )?;
+        }
+        for row in &self.rows {
+            write!(f, "Day {:02} {:02}:00:", row.day, row.hour)?;
+            for (i, parameter) in row.parameters.iter().enumerate() {
+                let separator = if i == 0 { " " } else { ", " };
+                write!(f, "{}{}", separator, parameter)?;
+            }
+            writeln!(f)?;
+        }
+        if !self.unrecognized.is_empty() {
+            writeln!(f, "Unrecognized: {}", self.unrecognized.join(" "))?;
+        }
+        Ok(())
+    }
+}
+
+fn decode_utc_offset(offset: &str) -> Option<i64> {
+    if offset == "GMT" {
+        return Some(0);
+    }
+    let (sign, offset) = if let Some(offset) = offset.strip_prefix('+') {
+        (1, offset)
+    } else {
+        (-1, offset.strip_prefix('-')?)
+    };
+    let (hours, minutes) = offset.split_once(':')?;
+    let hours: i64 = hours.parse().ok()?;
+    let minutes: i64 = minutes.parse().ok()?;
+    Some(sign * (hours * 60 + minutes))
+}
+
+fn decode_row_time(token: &str) -> Option<(u32, u32)> {
+    let (day, hour) = token.split_once('T')?;
+    if day.len() != 2 || hour.len() != 2 {
+        return None;
+    }
+    Some((day.parse().ok()?, hour.parse().ok()?))
+}
+
+/// Split the [`Trend::short_marker()`] (if any) from the end of a `value`.
+fn strip_trend(value: &str) -> (&str, Option<Trend>) {
+    if let Some(value) = value.strip_suffix('+') {
+        (value, Some(Trend::Rising))
+    } else if let Some(value) = value.strip_suffix('-') {
+        (value, Some(Trend::Falling))
+    } else {
+        (value, None)
+    }
+}
+
+pub(crate) fn decode_parameter(token: &str) -> Option<DecodedParameter> {
+    let value = |s: &str| s.parse::<f32>().ok().filter(|value| value.is_finite());
+    if let Some(cloud_cover) = token.strip_prefix("CL") {
+        let mut levels = cloud_cover
+            .split('/')
+            .map(|level| Some(value(level)? * 10.0));
+        let cloud_cover = DecodedParameter::CloudCover {
+            low_percent: levels.next()??,
+            mid_percent: levels.next()??,
+            high_percent: levels.next()??,
+        };
+        levels.next().is_none().then_some(cloud_cover)
+    } else if let Some(code) = token.strip_prefix('C') {
+        let code: u8 = code.parse().ok()?;
+        let description = WeatherCode::enumerate()
+            .iter()
+            .find(|weather_code| weather_code.code() == code)
+            .map(ToString::to_string);
+        Some(DecodedParameter::WeatherCode { code, description })
+    } else if let Some(height) = token.strip_prefix('F') {
+        let (height, trend) = strip_trend(height);
+        Some(DecodedParameter::FreezingLevelHeight {
+            metres: value(height)? * 100.0,
+            trend,
+        })
+    } else if let Some(wind) = token.strip_prefix("WF") {
+        let (force, direction) = wind.split_once('@')?;
+        let (force, gusts_force) = match force.split_once("gF") {
+            Some((force, gusts)) => (force, Some(gusts.parse().ok()?)),
+            None => (force, None),
+        };
+        Some(DecodedParameter::WindBeaufort {
+            force: force.parse().ok()?,
+            gusts_force,
+            direction_degrees: value(direction)? * 10.0,
+        })
+    } else if let Some(wind) = token.strip_prefix('W') {
+        let (speed, direction) = wind.split_once('@')?;
+        let (speed, gusts) = match speed.split_once('g') {
+            Some((speed, gusts)) => (speed, Some(value(gusts)? * 10.0)),
+            None => (speed, None),
+        };
+        Some(DecodedParameter::Wind {
+            speed_kmh: value(speed)? * 10.0,
+            gusts_kmh: gusts,
+            direction_degrees: value(direction)? * 10.0,
+        })
+    } else if let Some(precipitation) = token.strip_prefix('P') {
+        let (millimetres, probability) = match precipitation.split_once('/') {
+            Some((millimetres, probability)) => {
+                (millimetres, Some(value(probability.strip_suffix('%')?)?))
+            }
+            None => (precipitation, None),
+        };
+        Some(DecodedParameter::Precipitation {
+            millimetres: value(millimetres)?,
+            probability_percent: probability,
+        })
+    } else if let Some(humidity) = token.strip_prefix('H') {
+        Some(DecodedParameter::RelativeHumidity {
+            percent: value(humidity)? * 10.0,
+        })
+    } else if let Some(dewpoint) = token.strip_prefix('D') {
+        Some(DecodedParameter::Dewpoint {
+            celsius: value(dewpoint)?,
+        })
+    } else if let Some(pressure) = token.strip_prefix('B') {
+        let (pressure, tendency) = strip_trend(pressure);
+        Some(DecodedParameter::PressureMsl {
+            hpa: value(pressure)?,
+            tendency,
+        })
+    } else if let Some(uv_index) = token.strip_prefix('U') {
+        Some(DecodedParameter::UvIndex {
+            index: value(uv_index)?,
+        })
+    } else if let Some(visibility) = token.strip_prefix('V') {
+        Some(DecodedParameter::Visibility {
+            metres: value(visibility)? * 1000.0,
+        })
+    } else if let Some(temperature) = token.strip_prefix('T') {
+        let (temperature, apparent) = temperature.split_once('/')?;
+        Some(DecodedParameter::ApparentTemperature {
+            celsius: value(temperature)?,
+            apparent_celsius: value(apparent)?,
+        })
+    } else {
+        None
+    }
+}
+
+/// Decode a `token` from the header line into `header`, returns `false` if it was not recognized.
+fn decode_header_token(header: &mut DecodedHeader, token: &str) -> bool {
+    let elevation = |prefix: &str| {
+        token
+            .strip_prefix(prefix)
+            .and_then(|elevation| elevation.parse::<f32>().ok())
+    };
+    if let Some(offset) = token.strip_prefix("Tz").and_then(decode_utc_offset) {
+        header.utc_offset_minutes = Some(offset);
+    } else if let Some(forecast_elevation) = elevation("FE") {
+        header.forecast_elevation = Some(forecast_elevation);
+    } else if let Some(terrain_elevation) = elevation("TE") {
+        header.terrain_elevation = Some(terrain_elevation);
+    } else if token == "SEA" {
+        header.sea_cell = true;
+    } else if token == "E" {
+        header.errors = true;
+    } else {
+        return false;
+    }
+    true
+}
+
+/// Decode a forecast `message` in the short format (the inverse of [`FormatDetail::Short`]), e.g.
+/// `C3 F24 W2@31 P0`, converting the values back to their units. The message may have lost its
+/// line breaks in transit, so rows are identified by their time (e.g. `04T03`). Tokens which cannot
+/// be decoded are collected in [`DecodedForecast::unrecognized`].
+#[must_use]
+pub fn decode_short(message: &str) -> DecodedForecast {
+    let mut decoded = DecodedForecast::default();
+    for token in message.split_whitespace() {
+        if let Some((day, hour)) = decode_row_time(token) {
+            decoded.rows.push(DecodedRow {
+                day,
+                hour,
+                parameters: Vec::new(),
+            });
+            continue;
+        }
+
+        let recognized = match decoded.rows.last_mut() {
+            Some(row) => match decode_parameter(token) {
+                Some(parameter) => {
+                    row.parameters.push(parameter);
+                    true
+                }
+                None => false,
+            },
+            None => decode_header_token(&mut decoded.header, token),
+        };
+
+        if !recognized {
+            decoded.unrecognized.push(token.to_owned());
+        }
+    }
+    decoded
+}
+
+#[cfg(test)]
+mod test {
+    use super::{decode_short, DecodedHeader, DecodedParameter};
+
+    #[test]
+    fn test_decoded_forecast_display() {
+        let decoded = decode_short("Tz+13:00 FE1500 TE2216\n03T21 C2 F28 W1@32 P0");
+        assert_eq!(
+            "Time zone: UTC+13:00\n\
+            Forecast elevation: 1500m\n\
+            Terrain elevation: 2216m\n\
+            Day 03 21:00: partly cloudy (C2), freezing level 2800m, wind 10km/h from 320°, \
+            precipitation 0mm\n",
+            decoded.to_string()
+        );
+    }
+
+    #[test]
+    fn test_decode_short() {
+        let decoded = decode_short(
+            "Tz+13:00 FE1500 TE2216 E\n03T21 C2 F28 W1@32 P0\n04T03 C99 F33+ W2g4@31 P4 X1",
+        );
+        assert_eq!(
+            DecodedHeader {
+                utc_offset_minutes: Some(13 * 60),
+                forecast_elevation: Some(1500.0),
+                terrain_elevation: Some(2216.0),
+                sea_cell: false,
+                errors: true,
+            },
+            decoded.header
+        );
+        assert_eq!(2, decoded.rows.len());
+        assert_eq!((3, 21), (decoded.rows[0].day, decoded.rows[0].hour));
+        assert_eq!(
+            vec![
+                DecodedParameter::WeatherCode {
+                    code: 2,
+                    description: Some("partly cloudy".to_owned())
+                },
+                DecodedParameter::FreezingLevelHeight {
+                    metres: 2800.0,
+                    trend: None
+                },
+                DecodedParameter::Wind {
+                    speed_kmh: 10.0,
+                    gusts_kmh: None,
+                    direction_degrees: 320.0
+                },
+                DecodedParameter::Precipitation {
+                    millimetres: 0.0,
+                    probability_percent: None
+                },
+            ],
+            decoded.rows[0].parameters
+        );
+        assert_eq!(
+            DecodedParameter::Wind {
+                speed_kmh: 20.0,
+                gusts_kmh: Some(40.0),
+                direction_degrees: 310.0
+            },
+            decoded.rows[1].parameters[2]
+        );
+        assert_eq!(vec!["X1".to_owned()], decoded.unrecognized);
+    }
+}
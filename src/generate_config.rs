@@ -0,0 +1,175 @@
+//! Generation of a sample options file, with all the defaults filled in and each option
+//! commented using its documentation, see [`generate_config()`].
+
+use eyre::Context;
+use ron::ser::PrettyConfig;
+use serde_json::Value;
+
+use crate::options::{Format, Options};
+
+/// Placeholder used for [`Options::email_account`], which has no default.
+const EMAIL_ACCOUNT_PLACEHOLDER: &str = "weather@example.com";
+
+/// Comment at the start of the generated file, describing the secrets which are not stored in
+/// the options file.
+const HEADER: &str = "\
+// Options for email-weather, generated using `email-weather generate-config`.
+//
+// All options other than `email_account` are optional, and are shown here with their default
+// values. Options which are not required can be removed. Use `email-weather check-config` to
+// validate the options and secrets.
+//
+// Secrets are not stored in this file. They are read from environment variables (or the
+// `secrets_backend`), falling back to files in `secrets_dir`:
+//
+// CLIENT_SECRET='<OAUTH2 client secret json>' (secrets/client_secret.json)
+// TOKEN_CACHE='<OAUTH2 token cache json>' (secrets/token_cache.json)
+// ADMIN_PASSWORD_HASH='<argon2id password hash>' (secrets/admin_password_hash)
+// IDENTITY_KEY='<random key, e.g. openssl rand -base64 32>' (secrets/identity_key)
+// SENTRY_DSN='<sentry dsn>' (secrets/sentry_dsn)
+// TOPO_DATA_API_KEY='<api key>' (secrets/topo_data_api_key)
+// GOOGLE_ELEVATION_API_KEY='<api key>' (secrets/google_elevation_api_key)
+";
+
+/// Number of spaces for each level of indentation in the output of [`PrettyConfig`].
+const INDENT: usize = 4;
+
+/// A segment of the path to a value in the serialized options.
+#[derive(Debug, Clone, PartialEq)]
+enum Segment<'a> {
+    /// A field of a struct.
+    Field(&'a str),
+    /// An element of a list, or a value in a map.
+    Item,
+}
+
+/// Generate the contents of a sample `options.ron` file, containing all of the [`Options`] with
+/// their default values, each preceded by a comment containing its documentation (obtained from
+/// the [`Options::json_schema()`]).
+pub fn generate_config() -> eyre::Result<String> {
+    let options: Options = Format::Ron
+        .deserialize(&format!(
+            "Options(email_account: {:?})",
+            EMAIL_ACCOUNT_PLACEHOLDER
+        ))
+        .wrap_err("Error creating default options")?;
+    let options_ron = ron::ser::to_string_pretty(&options, PrettyConfig::default())
+        .wrap_err("Error serializing default options")?;
+    let schema = serde_json::to_value(schemars::schema_for!(Options))
+        .wrap_err("Error serializing options schema")?;
+    let definitions = &schema["definitions"];
+
+    let mut output = String::from(HEADER);
+    output.push('\n');
+    output.push_str("Options");
+    let mut path: Vec<Segment> = Vec::new();
+    for line in options_ron.lines() {
+        let trimmed = line.trim_start();
+        let depth = (line.len() - trimmed.len()) / INDENT;
+        if depth > 0 && !is_closing(trimmed) {
+            path.truncate(depth - 1);
+            let segment = segment(trimmed);
+            path.push(segment.clone());
+            if let Segment::Field(_) = segment {
+                if let Some(description) = lookup(&schema, definitions, &path)
+                    .and_then(|schema| description(schema, definitions))
+                {
+                    output.push('\n');
+                    for description_line in description.lines() {
+                        output.push_str(&" ".repeat(depth * INDENT));
+                        output.push_str("//");
+                        if !description_line.is_empty() {
+                            output.push(' ');
+                            output.push_str(description_line);
+                        }
+                        output.push('\n');
+                    }
+                }
+            }
+        }
+        output.push_str(line);
+        output.push('\n');
+    }
+    Ok(output)
+}
+
+/// Whether the `trimmed` line only closes a struct, list or map.
+fn is_closing(trimmed: &str) -> bool {
+    trimmed.starts_with(')') || trimmed.starts_with(']') || trimmed.starts_with('}')
+}
+
+/// The [`Segment`] for the `trimmed` line.
+fn segment(trimmed: &str) -> Segment<'_> {
+    match trimmed.split_once(": ") {
+        Some((key, _)) if key.chars().all(|c| c.is_ascii_alphanumeric() || c == '_') => {
+            Segment::Field(key)
+        }
+        _ => Segment::Item,
+    }
+}
+
+/// Follow references to definitions and single element `allOf` wrappers (which schemars uses to
+/// attach a description to a reference).
+fn resolve<'a>(schema: &'a Value, definitions: &'a Value) -> &'a Value {
+    if let Some(reference) = schema["$ref"].as_str() {
+        let name = reference.trim_start_matches("#/definitions/");
+        return resolve(&definitions[name], definitions);
+    }
+    match schema["allOf"].as_array().map(Vec::as_slice) {
+        Some([single]) => resolve(single, definitions),
+        _ => schema,
+    }
+}
+
+/// The schema for the value at `segment` in the value described by `schema`.
+fn child<'a>(schema: &'a Value, definitions: &'a Value, segment: &Segment) -> Option<&'a Value> {
+    let schema = resolve(schema, definitions);
+    let found = match segment {
+        Segment::Field(key) => schema["properties"].get(*key),
+        Segment::Item => schema
+            .get("items")
+            .or_else(|| schema.get("additionalProperties")),
+    };
+    found.or_else(|| {
+        // Options and enums are described using alternatives.
+        ["anyOf", "oneOf"]
+            .iter()
+            .filter_map(|key| schema[*key].as_array())
+            .flatten()
+            .find_map(|alternative| child(alternative, definitions, segment))
+    })
+}
+
+/// The schema for the value at `path` in the value described by `schema`.
+fn lookup<'a>(schema: &'a Value, definitions: &'a Value, path: &[Segment]) -> Option<&'a Value> {
+    path.iter().try_fold(schema, |schema, segment| {
+        child(schema, definitions, segment)
+    })
+}
+
+/// The description of the value described by `schema`, preferring the description of the field
+/// over the description of its type.
+fn description<'a>(schema: &'a Value, definitions: &'a Value) -> Option<&'a str> {
+    schema["description"]
+        .as_str()
+        .or_else(|| resolve(schema, definitions)["description"].as_str())
+}
+
+#[cfg(test)]
+mod test {
+    use crate::options::Format;
+
+    use super::{generate_config, EMAIL_ACCOUNT_PLACEHOLDER};
+
+    #[test]
+    fn test_generate_config() {
+        let config = generate_config().unwrap();
+        assert!(config.contains("    // Directory where application data is stored"));
+        assert!(config.contains("    data_dir: \"data\",\n"));
+        assert!(config.contains("SENTRY_DSN"));
+        assert!(config.contains("IDENTITY_KEY"));
+
+        let options = Format::Ron.deserialize(&config).unwrap();
+        assert_eq!(EMAIL_ACCOUNT_PLACEHOLDER, options.email_account.email_str());
+    }
+}
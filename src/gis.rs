@@ -54,3 +54,510 @@ impl CoordinateReferenceSystem for WGS84 {
         "WGS84"
     }
 }
+
+/// Semi-major axis (in metres) of the WGS84 ellipsoid.
+const WGS84_SEMI_MAJOR_AXIS: f64 = 6_378_137.0;
+
+/// Flattening of the WGS84 ellipsoid. The GRS80 ellipsoid used by NZGD2000 differs by less than
+/// a tenth of a millimetre at the surface, so it is treated as identical.
+const WGS84_FLATTENING: f64 = 1.0 / 298.257_223_563;
+
+/// Parameters of a transverse Mercator projection of the WGS84 ellipsoid, with the latitude of
+/// origin at the equator. Positions are projected using the Krüger series (to third order in the
+/// third flattening), which is accurate to within a millimetre inside the usual extent of a zone.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct TransverseMercator {
+    /// Longitude of the central meridian (in degrees).
+    pub central_meridian: f64,
+    /// Scale factor along the central meridian.
+    pub scale_factor: f64,
+    /// Easting (in metres) of the central meridian.
+    pub false_easting: f64,
+    /// Northing (in metres) of the equator.
+    pub false_northing: f64,
+}
+
+/// Coefficients of the Krüger series for the WGS84 ellipsoid.
+struct KruegerSeries {
+    /// Radius of the rectifying sphere (in metres).
+    radius: f64,
+    /// First eccentricity.
+    eccentricity: f64,
+    alpha: [f64; 3],
+    beta: [f64; 3],
+    delta: [f64; 3],
+}
+
+impl KruegerSeries {
+    fn wgs84() -> Self {
+        let n = WGS84_FLATTENING / (2.0 - WGS84_FLATTENING);
+        let n_squared = n * n;
+        let n_cubed = n_squared * n;
+        Self {
+            radius: WGS84_SEMI_MAJOR_AXIS / (1.0 + n)
+                * (1.0 + n_squared / 4.0 + n_squared * n_squared / 64.0),
+            eccentricity: 2.0 * n.sqrt() / (1.0 + n),
+            alpha: [
+                n / 2.0 - 2.0 * n_squared / 3.0 + 5.0 * n_cubed / 16.0,
+                13.0 * n_squared / 48.0 - 3.0 * n_cubed / 5.0,
+                61.0 * n_cubed / 240.0,
+            ],
+            beta: [
+                n / 2.0 - 2.0 * n_squared / 3.0 + 37.0 * n_cubed / 96.0,
+                n_squared / 48.0 + n_cubed / 15.0,
+                17.0 * n_cubed / 480.0,
+            ],
+            delta: [
+                2.0 * n - 2.0 * n_squared / 3.0 - 2.0 * n_cubed,
+                7.0 * n_squared / 3.0 - 8.0 * n_cubed / 5.0,
+                56.0 * n_cubed / 15.0,
+            ],
+        }
+    }
+}
+
+impl TransverseMercator {
+    /// Project the WGS84 `latitude` and `longitude` (in degrees), returning the easting and
+    /// northing (in metres).
+    #[must_use]
+    pub fn project(&self, latitude: f64, longitude: f64) -> (f64, f64) {
+        let series = KruegerSeries::wgs84();
+        let e = series.eccentricity;
+        let phi = latitude.to_radians();
+        let lambda = (longitude - self.central_meridian).to_radians();
+
+        let t = (phi.sin().atanh() - e * (e * phi.sin()).atanh()).sinh();
+        let xi_prime = t.atan2(lambda.cos());
+        let eta_prime = (lambda.sin() / (1.0 + t * t).sqrt()).atanh();
+
+        let mut xi = xi_prime;
+        let mut eta = eta_prime;
+        for (j, alpha) in (1..).zip(series.alpha) {
+            let j = f64::from(j) * 2.0;
+            xi += alpha * (j * xi_prime).sin() * (j * eta_prime).cosh();
+            eta += alpha * (j * xi_prime).cos() * (j * eta_prime).sinh();
+        }
+
+        let k = self.scale_factor * series.radius;
+        (self.false_easting + k * eta, self.false_northing + k * xi)
+    }
+
+    /// Convert the `easting` and `northing` (in metres) back to the WGS84 latitude and longitude
+    /// (in degrees), the inverse of [`TransverseMercator::project()`].
+    #[must_use]
+    pub fn unproject(&self, easting: f64, northing: f64) -> (f64, f64) {
+        let series = KruegerSeries::wgs84();
+        let k = self.scale_factor * series.radius;
+        let xi = (northing - self.false_northing) / k;
+        let eta = (easting - self.false_easting) / k;
+
+        let mut xi_prime = xi;
+        let mut eta_prime = eta;
+        for (j, beta) in (1..).zip(series.beta) {
+            let j = f64::from(j) * 2.0;
+            xi_prime -= beta * (j * xi).sin() * (j * eta).cosh();
+            eta_prime -= beta * (j * xi).cos() * (j * eta).sinh();
+        }
+
+        let chi = (xi_prime.sin() / eta_prime.cosh()).asin();
+        let mut phi = chi;
+        for (j, delta) in (1..).zip(series.delta) {
+            phi += delta * (f64::from(j) * 2.0 * chi).sin();
+        }
+        let lambda = eta_prime.sinh().atan2(xi_prime.cos());
+
+        (
+            phi.to_degrees(),
+            self.central_meridian + lambda.to_degrees(),
+        )
+    }
+}
+
+/// A coordinate reference system which projects WGS84 positions onto a plane, in which positions
+/// are a [`GridPosition`] (e.g. a grid reference read from a map).
+pub trait ProjectedCoordinateReferenceSystem: CoordinateReferenceSystem {
+    /// The projection used by this coordinate reference system.
+    fn projection(&self) -> TransverseMercator;
+}
+
+/// Hemisphere of a [`Utm`] zone.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
+pub enum Hemisphere {
+    /// North of the equator.
+    North,
+    /// South of the equator, northings are offset by 10,000km to keep them positive.
+    South,
+}
+
+/// Universal Transverse Mercator coordinate system, a zone 6° of longitude wide in one
+/// hemisphere. The exceptions to the zones around Norway and Svalbard are not applied by
+/// [`Utm::for_position()`].
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Utm {
+    zone: u8,
+    hemisphere: Hemisphere,
+}
+
+impl Utm {
+    /// Construct a new [`Utm`] for the `zone` (between `1` and `60`) in the `hemisphere`.
+    pub fn new(zone: u8, hemisphere: Hemisphere) -> eyre::Result<Self> {
+        if !(1..=60).contains(&zone) {
+            return Err(eyre::eyre!("UTM zone {} is not between 1 and 60", zone));
+        }
+        Ok(Self { zone, hemisphere })
+    }
+
+    /// The [`Utm`] zone containing `position`.
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation, clippy::cast_sign_loss)]
+    pub fn for_position(position: Position) -> Self {
+        let longitude = f64::from(position.longitude);
+        // Longitude 180° belongs to zone 60 rather than wrapping around to zone 1.
+        let zone = (((longitude + 180.0) / 6.0).floor() as u8 + 1).clamp(1, 60);
+        let hemisphere = if position.latitude < 0.0 {
+            Hemisphere::South
+        } else {
+            Hemisphere::North
+        };
+        Self { zone, hemisphere }
+    }
+
+    /// The zone number (between `1` and `60`).
+    #[must_use]
+    pub fn zone(self) -> u8 {
+        self.zone
+    }
+
+    /// The hemisphere of the zone.
+    #[must_use]
+    pub fn hemisphere(self) -> Hemisphere {
+        self.hemisphere
+    }
+}
+
+impl CoordinateReferenceSystem for Utm {
+    fn name() -> &'static str {
+        "UTM"
+    }
+}
+
+impl ProjectedCoordinateReferenceSystem for Utm {
+    fn projection(&self) -> TransverseMercator {
+        TransverseMercator {
+            central_meridian: f64::from(self.zone) * 6.0 - 183.0,
+            scale_factor: 0.9996,
+            false_easting: 500_000.0,
+            false_northing: match self.hemisphere {
+                Hemisphere::North => 0.0,
+                Hemisphere::South => 10_000_000.0,
+            },
+        }
+    }
+}
+
+/// New Zealand Transverse Mercator 2000 (EPSG:2193), the coordinate system of the NZ Topo50 maps.
+/// NZGD2000 is treated as identical to WGS84, they differed by less than a metre when this was
+/// written.
+#[derive(PartialEq, Eq, Debug, Copy, Clone, Serialize, Deserialize)]
+pub struct Nztm2000;
+
+impl CoordinateReferenceSystem for Nztm2000 {
+    fn name() -> &'static str {
+        "NZTM2000"
+    }
+}
+
+impl ProjectedCoordinateReferenceSystem for Nztm2000 {
+    fn projection(&self) -> TransverseMercator {
+        TransverseMercator {
+            central_meridian: 173.0,
+            scale_factor: 0.9996,
+            false_easting: 1_600_000.0,
+            false_northing: 10_000_000.0,
+        }
+    }
+}
+
+/// A position in a [`ProjectedCoordinateReferenceSystem`].
+#[derive(Clone, Copy, PartialEq)]
+pub struct GridPosition<CRS> {
+    /// Easting of the position (in metres).
+    pub easting: f64,
+    /// Northing of the position (in metres).
+    pub northing: f64,
+    coordinate_reference_system: CRS,
+}
+
+impl<CRS> std::fmt::Debug for GridPosition<CRS>
+where
+    CRS: CoordinateReferenceSystem + std::fmt::Debug,
+{
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct(&format!("GridPosition<{}>", CRS::name()))
+            .field("easting", &self.easting)
+            .field("northing", &self.northing)
+            .field(
+                "coordinate_reference_system",
+                &self.coordinate_reference_system,
+            )
+            .finish()
+    }
+}
+
+impl<CRS> GridPosition<CRS>
+where
+    CRS: ProjectedCoordinateReferenceSystem,
+{
+    /// Construct a new [`GridPosition`] in the `coordinate_reference_system`.
+    #[must_use]
+    pub fn new(easting: f64, northing: f64, coordinate_reference_system: CRS) -> Self {
+        Self {
+            easting,
+            northing,
+            coordinate_reference_system,
+        }
+    }
+
+    /// The coordinate reference system of this position.
+    #[must_use]
+    pub fn coordinate_reference_system(&self) -> &CRS {
+        &self.coordinate_reference_system
+    }
+
+    /// Convert this position to [`WGS84`].
+    #[must_use]
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn to_wgs84(&self) -> Position<WGS84> {
+        let (latitude, longitude) = self
+            .coordinate_reference_system
+            .projection()
+            .unproject(self.easting, self.northing);
+        Position::new(latitude as f32, longitude as f32)
+    }
+}
+
+impl Position<WGS84> {
+    /// Convert this position to the projected `coordinate_reference_system`.
+    #[must_use]
+    pub fn to_grid<CRS>(self, coordinate_reference_system: CRS) -> GridPosition<CRS>
+    where
+        CRS: ProjectedCoordinateReferenceSystem,
+    {
+        let (easting, northing) = coordinate_reference_system
+            .projection()
+            .project(f64::from(self.latitude), f64::from(self.longitude));
+        GridPosition::new(easting, northing, coordinate_reference_system)
+    }
+}
+
+/// Characters used to encode an [Open Location Code](https://maps.google.com/pluscodes/), in order
+/// of their value.
+const PLUS_CODE_ALPHABET: &str = "23456789CFGHJMPQRVWX";
+
+/// Characters used to encode a [geohash](https://en.wikipedia.org/wiki/Geohash), in order of their
+/// value.
+const GEOHASH_ALPHABET: &str = "0123456789bcdefghjkmnpqrstuvwxyz";
+
+/// The value of the character `c` in `alphabet`.
+fn alphabet_value(alphabet: &str, c: char) -> Option<u8> {
+    alphabet
+        .chars()
+        .position(|a| a == c)
+        .and_then(|value| u8::try_from(value).ok())
+}
+
+impl Position<WGS84> {
+    /// Decode a full [Open Location Code](https://maps.google.com/pluscodes/) (plus code), e.g.
+    /// `4VCPPR2M+9Q` or the padded `4VCP0000+`, into the position at the centre of its area.
+    /// Short codes (which are relative to a nearby locality) are not supported.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_plus_code(code: &str) -> eyre::Result<Self> {
+        let code = code.to_ascii_uppercase();
+        let invalid = || eyre::eyre!("Invalid plus code {:?}", code);
+        let (prefix, suffix) = code.split_once('+').ok_or_else(invalid)?;
+        if prefix.len() != 8 {
+            return Err(eyre::eyre!(
+                "Invalid plus code {:?}, expected 8 characters before the '+' (short codes are \
+                not supported)",
+                code
+            ));
+        }
+        let digits = prefix.trim_end_matches('0');
+        let padded = digits.len() < prefix.len();
+        if digits.len() < 2
+            || digits.len() % 2 != 0
+            || digits.contains('0')
+            || (padded && !suffix.is_empty())
+            || suffix.len() == 1
+        {
+            return Err(invalid());
+        }
+        let values = digits
+            .chars()
+            .chain(suffix.chars())
+            .map(|c| alphabet_value(PLUS_CODE_ALPHABET, c))
+            .collect::<Option<Vec<u8>>>()
+            .ok_or_else(invalid)?;
+        // The first pair of digits only encodes 9 rows of latitude and 18 columns of longitude.
+        if values[0] >= 9 || values[1] >= 18 {
+            return Err(invalid());
+        }
+
+        let (pairs, grid) = values.split_at(values.len().min(10));
+        let mut latitude = -90.0;
+        let mut longitude = -180.0;
+        let mut resolution = 400.0;
+        for pair in pairs.chunks(2) {
+            resolution /= 20.0;
+            latitude += f64::from(pair[0]) * resolution;
+            longitude += f64::from(pair[1]) * resolution;
+        }
+        // Characters after the first 10 each refine the area using a grid of 5 rows by 4 columns.
+        let mut latitude_resolution = resolution;
+        let mut longitude_resolution = resolution;
+        for value in grid {
+            latitude_resolution /= 5.0;
+            longitude_resolution /= 4.0;
+            latitude += f64::from(value / 4) * latitude_resolution;
+            longitude += f64::from(value % 4) * longitude_resolution;
+        }
+
+        Ok(Position::new(
+            (latitude + latitude_resolution / 2.0) as f32,
+            (longitude + longitude_resolution / 2.0) as f32,
+        ))
+    }
+
+    /// Decode a [geohash](https://en.wikipedia.org/wiki/Geohash) (case-insensitive), e.g.
+    /// `rbsm1hsuv`, into the position at the centre of its area.
+    #[allow(clippy::cast_possible_truncation)]
+    pub fn from_geohash(geohash: &str) -> eyre::Result<Self> {
+        if geohash.is_empty() {
+            return Err(eyre::eyre!("Geohash is empty"));
+        }
+        let mut latitude = (-90.0, 90.0);
+        let mut longitude = (-180.0, 180.0);
+        // Bits alternate between longitude and latitude, starting with longitude.
+        let mut is_longitude = true;
+        for c in geohash.to_ascii_lowercase().chars() {
+            let value = alphabet_value(GEOHASH_ALPHABET, c)
+                .ok_or_else(|| eyre::eyre!("Invalid character {:?} in geohash {:?}", c, geohash))?;
+            for bit in (0..5).rev() {
+                let range: &mut (f64, f64) = if is_longitude {
+                    &mut longitude
+                } else {
+                    &mut latitude
+                };
+                let middle = (range.0 + range.1) / 2.0;
+                if (value >> bit) & 1 == 1 {
+                    range.0 = middle;
+                } else {
+                    range.1 = middle;
+                }
+                is_longitude = !is_longitude;
+            }
+        }
+
+        Ok(Position::new(
+            ((latitude.0 + latitude.1) / 2.0) as f32,
+            ((longitude.0 + longitude.1) / 2.0) as f32,
+        ))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::{
+        GridPosition, Hemisphere, Nztm2000, Position, ProjectedCoordinateReferenceSystem, Utm,
+    };
+
+    /// Asserts that `actual` is within `tolerance` of `expected`.
+    fn assert_near(expected: f64, actual: f64, tolerance: f64) {
+        assert!(
+            (expected - actual).abs() <= tolerance,
+            "expected {expected}, actual {actual}"
+        );
+    }
+
+    #[test]
+    fn test_nztm2000() {
+        // Test coordinate provided with the LINZ conversion software.
+        let (latitude, longitude) = Nztm2000
+            .projection()
+            .unproject(1_576_041.150, 6_188_574.240);
+        assert_near(-34.444_066, latitude, 1e-6);
+        assert_near(172.739_194, longitude, 1e-6);
+
+        let position = GridPosition::new(1_576_041.150, 6_188_574.240, Nztm2000).to_wgs84();
+        assert_near(-34.444_066, f64::from(position.latitude), 1e-5);
+        assert_near(172.739_194, f64::from(position.longitude), 1e-5);
+    }
+
+    #[test]
+    fn test_utm() {
+        // CN Tower, Toronto, 17T 630084 4833438.
+        let position = Position::new(43.642_567, -79.387_139);
+        let utm = Utm::for_position(position);
+        assert_eq!(17, utm.zone());
+        assert_eq!(Hemisphere::North, utm.hemisphere());
+        let grid = position.to_grid(utm);
+        assert_near(630_084.0, grid.easting, 1.0);
+        assert_near(4_833_438.0, grid.northing, 1.0);
+
+        let utm = Utm::for_position(Position::new(-43.59572, 170.14229));
+        assert_eq!(59, utm.zone());
+        assert_eq!(Hemisphere::South, utm.hemisphere());
+        assert_eq!(60, Utm::for_position(Position::new(0.0, 180.0)).zone());
+        assert!(Utm::new(61, Hemisphere::North).is_err());
+    }
+
+    #[test]
+    fn test_from_plus_code() {
+        let position = Position::from_plus_code("4VCPPR2M+9Q").unwrap();
+        assert_near(-41.299_062, f64::from(position.latitude), 1e-5);
+        assert_near(174.834_437, f64::from(position.longitude), 1e-5);
+        let position = Position::from_plus_code("4vcppr2m+9q").unwrap();
+        assert_near(-41.299_062, f64::from(position.latitude), 1e-5);
+        let position = Position::from_plus_code("4VCP0000+").unwrap();
+        assert_near(-41.5, f64::from(position.latitude), 1e-5);
+        assert_near(174.5, f64::from(position.longitude), 1e-5);
+
+        for code in [
+            "PR2M+9Q",
+            "4VCPPR2M",
+            "4VCPPR2M+9",
+            "4VC00000+",
+            "4VCP0000+9Q",
+            "XVCPPR2M+9Q",
+        ] {
+            assert!(Position::from_plus_code(code).is_err(), "{code}");
+        }
+    }
+
+    #[test]
+    fn test_from_geohash() {
+        let position = Position::from_geohash("u4pruydqqvj").unwrap();
+        assert_near(57.649_111, f64::from(position.latitude), 1e-5);
+        assert_near(10.407_440, f64::from(position.longitude), 1e-5);
+        let position = Position::from_geohash("RBSM1HSUV").unwrap();
+        assert_near(-41.283_038, f64::from(position.latitude), 1e-5);
+        assert_near(174.777_267, f64::from(position.longitude), 1e-5);
+
+        assert!(Position::from_geohash("").is_err());
+        assert!(Position::from_geohash("rbsm1hsua").is_err());
+    }
+
+    #[test]
+    fn test_round_trip() {
+        let utm = Utm::new(59, Hemisphere::South).unwrap();
+        for projection in [utm.projection(), Nztm2000.projection()] {
+            for (latitude, longitude) in [(-43.59572, 170.14229), (-34.4, 172.7), (-47.0, 168.0)] {
+                let (easting, northing) = projection.project(latitude, longitude);
+                let (round_latitude, round_longitude) = projection.unproject(easting, northing);
+                // Within a millimetre.
+                assert_near(latitude, round_latitude, 1e-8);
+                assert_near(longitude, round_longitude, 1e-8);
+            }
+        }
+    }
+}
@@ -0,0 +1,589 @@
+//! Encoding of small [GRIB2](https://library.wmo.int/idurl/4/35625) files containing the wind,
+//! pressure and precipitation forecast for a bounding box around a position, which are attached
+//! to plain email replies (when the request contains `GRIB`) for use with the offline routing
+//! software used by sailors over satellite email, see [`build_grib()`].
+
+use chrono::{NaiveDateTime, Timelike};
+use eyre::Context;
+use open_meteo::{GroundLevel, HourlyVariable, WindspeedUnit};
+
+use crate::{forecast_service, gis::Position, reply::Attachment, time};
+
+/// Number of grid points in each direction (latitude and longitude).
+const GRID_POINTS: u32 = 3;
+/// Spacing between grid points (in degrees).
+const GRID_SPACING_DEGREES: f32 = 0.5;
+/// Number of hours after the reference time covered by the file.
+const FORECAST_HOURS: u32 = 72;
+/// Number of hours between the forecast times in the file.
+const INTERVAL_HOURS: u32 = 6;
+/// Grid coordinates are encoded in units of 10^-6 degrees.
+const MICRODEGREES: f32 = 1_000_000.0;
+
+/// Name of the attached file.
+pub const FILENAME: &str = "forecast.grb2";
+/// MIME type of the attached file.
+pub const CONTENT_TYPE: &str = "application/octet-stream";
+
+/// A regular latitude/longitude grid, scanned from west to east, then north to south.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Grid {
+    /// Latitude of the northernmost row.
+    north: f32,
+    /// Longitude of the westernmost column.
+    west: f32,
+    /// Number of points in each row and column.
+    points: u32,
+    /// Spacing between points (in degrees).
+    spacing: f32,
+}
+
+impl Grid {
+    /// A grid of [`GRID_POINTS`] centred on `position`, aligned to multiples of the
+    /// [`GRID_SPACING_DEGREES`] (clamped to the valid latitudes).
+    #[must_use]
+    pub fn around(position: Position) -> Self {
+        let half_width = GRID_SPACING_DEGREES * (GRID_POINTS - 1) as f32 / 2.0;
+        let align = |degrees: f32| (degrees / GRID_SPACING_DEGREES).round() * GRID_SPACING_DEGREES;
+        Self {
+            north: align(position.latitude + half_width).min(90.0),
+            west: align(position.longitude - half_width),
+            points: GRID_POINTS,
+            spacing: GRID_SPACING_DEGREES,
+        }
+    }
+
+    /// The positions of the grid points, in the order they are scanned.
+    pub fn positions(&self) -> impl Iterator<Item = Position> + '_ {
+        (0..self.points).flat_map(move |j| {
+            (0..self.points).map(move |i| {
+                let latitude = (self.north - j as f32 * self.spacing).max(-90.0);
+                Position::new(
+                    latitude,
+                    normalize_longitude(self.west + i as f32 * self.spacing),
+                )
+            })
+        })
+    }
+
+    /// Total number of points in the grid.
+    fn len(&self) -> u32 {
+        self.points * self.points
+    }
+
+    /// Latitude of the southernmost row.
+    fn south(&self) -> f32 {
+        (self.north - (self.points - 1) as f32 * self.spacing).max(-90.0)
+    }
+
+    /// Longitude of the easternmost column.
+    fn east(&self) -> f32 {
+        self.west + (self.points - 1) as f32 * self.spacing
+    }
+}
+
+/// Longitude in the range `(-180, 180]`.
+fn normalize_longitude(longitude: f32) -> f32 {
+    if longitude > 180.0 {
+        longitude - 360.0
+    } else if longitude <= -180.0 {
+        longitude + 360.0
+    } else {
+        longitude
+    }
+}
+
+/// A parameter included in the file.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Parameter {
+    /// U (eastward) component of the wind at 10m (m/s).
+    WindU,
+    /// V (northward) component of the wind at 10m (m/s).
+    WindV,
+    /// Pressure reduced to mean sea level (Pa).
+    PressureMsl,
+    /// Precipitation rate (kg m-2 s-1), averaged over the preceding hour.
+    PrecipitationRate,
+}
+
+impl Parameter {
+    /// All the parameters, in the order they are included in the file.
+    pub const ALL: [Self; 4] = [
+        Self::WindU,
+        Self::WindV,
+        Self::PressureMsl,
+        Self::PrecipitationRate,
+    ];
+
+    /// Parameter category and number (code table 4.1 and 4.2, discipline 0).
+    fn code(self) -> (u8, u8) {
+        match self {
+            Self::WindU => (2, 2),
+            Self::WindV => (2, 3),
+            Self::PressureMsl => (3, 1),
+            Self::PrecipitationRate => (1, 7),
+        }
+    }
+
+    /// Type and value of the fixed surface (code table 4.5).
+    fn surface(self) -> (u8, u32) {
+        match self {
+            // Specified height above ground (m).
+            Self::WindU | Self::WindV => (103, 10),
+            // Mean sea level.
+            Self::PressureMsl => (101, 0),
+            // Ground or water surface.
+            Self::PrecipitationRate => (1, 0),
+        }
+    }
+
+    /// Number of decimal digits that are preserved when packing the values.
+    fn decimal_scale(self) -> i16 {
+        match self {
+            Self::WindU | Self::WindV => 1,
+            Self::PressureMsl => 0,
+            Self::PrecipitationRate => 7,
+        }
+    }
+}
+
+/// The values of a [`Parameter`] at each point of the [`Grid`], at a forecast time.
+#[derive(Debug, Clone, PartialEq)]
+pub struct Field {
+    /// The parameter.
+    pub parameter: Parameter,
+    /// Number of hours after the reference time.
+    pub forecast_hours: u32,
+    /// The values in the order that the grid is scanned.
+    pub values: Vec<f32>,
+}
+
+/// Encode a signed integer using the sign and magnitude representation used by GRIB2.
+fn signed_32(value: i32) -> u32 {
+    if value < 0 {
+        0x8000_0000 | value.unsigned_abs()
+    } else {
+        value.unsigned_abs()
+    }
+}
+
+/// Encode a signed integer using the sign and magnitude representation used by GRIB2.
+fn signed_16(value: i16) -> u16 {
+    if value < 0 {
+        0x8000 | value.unsigned_abs()
+    } else {
+        value.unsigned_abs()
+    }
+}
+
+/// Pack the `values` using simple packing (data representation template 5.0) with the
+/// `decimal_scale`, returning the reference value, the number of bits per value, and the packed
+/// values.
+fn pack(values: &[f32], decimal_scale: i16) -> (f32, u8, Vec<u8>) {
+    let scale = 10_f32.powi(i32::from(decimal_scale));
+    let scaled: Vec<f32> = values.iter().map(|value| value * scale).collect();
+    let reference = scaled.iter().copied().fold(f32::INFINITY, f32::min);
+    let reference = if reference.is_finite() {
+        reference
+    } else {
+        0.0
+    };
+    let packed: Vec<u32> = scaled
+        .iter()
+        .map(|value| (value - reference).round().max(0.0) as u32)
+        .collect();
+    let max = packed.iter().copied().max().unwrap_or(0);
+    let bits = (32 - max.leading_zeros()) as u8;
+
+    let mut data = Vec::with_capacity((packed.len() * usize::from(bits) + 7) / 8);
+    let mut buffer: u64 = 0;
+    let mut buffered: u8 = 0;
+    for value in packed {
+        buffer = (buffer << bits) | u64::from(value);
+        buffered += bits;
+        while buffered >= 8 {
+            buffered -= 8;
+            data.push((buffer >> buffered) as u8);
+        }
+    }
+    if buffered > 0 {
+        data.push((buffer << (8 - buffered)) as u8);
+    }
+    (reference, bits, data)
+}
+
+/// Append a section with the `number` and `body` to the `message`.
+fn push_section(message: &mut Vec<u8>, number: u8, body: &[u8]) {
+    let length = u32::try_from(body.len() + 5).expect("GRIB section is too large");
+    message.extend_from_slice(&length.to_be_bytes());
+    message.push(number);
+    message.extend_from_slice(body);
+}
+
+/// Encode the `field` as a GRIB2 message on the `grid`, for the forecast starting at the
+/// `reference_time` (UTC).
+fn encode_message(reference_time: NaiveDateTime, grid: &Grid, field: &Field) -> Vec<u8> {
+    let mut sections = Vec::new();
+
+    // Section 1: identification.
+    let mut identification = Vec::with_capacity(16);
+    identification.extend_from_slice(&255_u16.to_be_bytes()); // Originating centre: missing.
+    identification.extend_from_slice(&0_u16.to_be_bytes()); // Sub-centre.
+    identification.push(2); // Master tables version.
+    identification.push(0); // Local tables are not used.
+    identification.push(1); // Reference time is the start of the forecast.
+    let date = reference_time.date();
+    identification.extend_from_slice(
+        &u16::try_from(chrono::Datelike::year(&date))
+            .unwrap_or_default()
+            .to_be_bytes(),
+    );
+    for component in [
+        chrono::Datelike::month(&date),
+        chrono::Datelike::day(&date),
+        reference_time.hour(),
+        reference_time.minute(),
+        reference_time.second(),
+    ] {
+        identification.push(component as u8);
+    }
+    identification.push(0); // Operational products.
+    identification.push(1); // Forecast products.
+    push_section(&mut sections, 1, &identification);
+
+    // Section 3: grid definition, using template 3.0 (latitude/longitude).
+    let microdegrees = |degrees: f32| (degrees * MICRODEGREES).round() as i32;
+    let spacing = microdegrees(grid.spacing).unsigned_abs();
+    let mut grid_definition = Vec::with_capacity(67);
+    grid_definition.push(0); // Grid is specified by the template.
+    grid_definition.extend_from_slice(&grid.len().to_be_bytes());
+    grid_definition.push(0); // No optional list of numbers of points.
+    grid_definition.push(0);
+    grid_definition.extend_from_slice(&0_u16.to_be_bytes()); // Template 3.0.
+    grid_definition.push(6); // Spherical earth with a radius of 6,371,229m.
+    grid_definition.extend_from_slice(&[0; 15]); // Earth radius and axes are not specified.
+    grid_definition.extend_from_slice(&grid.points.to_be_bytes()); // Points along a parallel.
+    grid_definition.extend_from_slice(&grid.points.to_be_bytes()); // Points along a meridian.
+    grid_definition.extend_from_slice(&0_u32.to_be_bytes()); // Basic angle.
+    grid_definition.extend_from_slice(&u32::MAX.to_be_bytes()); // Subdivisions are missing.
+    grid_definition.extend_from_slice(&signed_32(microdegrees(grid.north)).to_be_bytes());
+    let longitude = |degrees: f32| microdegrees(degrees.rem_euclid(360.0)).unsigned_abs();
+    grid_definition.extend_from_slice(&longitude(grid.west).to_be_bytes());
+    grid_definition.push(0b0011_0000); // Increments are given, winds are relative to east/north.
+    grid_definition.extend_from_slice(&signed_32(microdegrees(grid.south())).to_be_bytes());
+    grid_definition.extend_from_slice(&longitude(grid.east()).to_be_bytes());
+    grid_definition.extend_from_slice(&spacing.to_be_bytes()); // Longitude increment.
+    grid_definition.extend_from_slice(&spacing.to_be_bytes()); // Latitude increment.
+    grid_definition.push(0); // Scan west to east, then north to south.
+    push_section(&mut sections, 3, &grid_definition);
+
+    // Section 4: product definition, using template 4.0 (at a point in time).
+    let (category, number) = field.parameter.code();
+    let (surface_type, surface_value) = field.parameter.surface();
+    let mut product_definition = Vec::with_capacity(29);
+    product_definition.extend_from_slice(&0_u16.to_be_bytes()); // No coordinate values.
+    product_definition.extend_from_slice(&0_u16.to_be_bytes()); // Template 4.0.
+    product_definition.push(category);
+    product_definition.push(number);
+    product_definition.push(2); // Forecast.
+    product_definition.push(255); // Background generating process is missing.
+    product_definition.push(255); // Forecast generating process is missing.
+    product_definition.extend_from_slice(&0_u16.to_be_bytes()); // Hours of cut-off.
+    product_definition.push(0); // Minutes of cut-off.
+    product_definition.push(1); // Forecast time is in hours.
+    product_definition.extend_from_slice(&field.forecast_hours.to_be_bytes());
+    product_definition.push(surface_type);
+    product_definition.push(0); // Scale factor of the surface value.
+    product_definition.extend_from_slice(&surface_value.to_be_bytes());
+    product_definition.push(255); // No second surface.
+    product_definition.push(255);
+    product_definition.extend_from_slice(&u32::MAX.to_be_bytes());
+    push_section(&mut sections, 4, &product_definition);
+
+    // Section 5: data representation, using template 5.0 (simple packing).
+    let decimal_scale = field.parameter.decimal_scale();
+    let (reference, bits, data) = pack(&field.values, decimal_scale);
+    let mut data_representation = Vec::with_capacity(16);
+    data_representation.extend_from_slice(&grid.len().to_be_bytes());
+    data_representation.extend_from_slice(&0_u16.to_be_bytes()); // Template 5.0.
+    data_representation.extend_from_slice(&reference.to_be_bytes());
+    data_representation.extend_from_slice(&signed_16(0).to_be_bytes()); // Binary scale factor.
+    data_representation.extend_from_slice(&signed_16(decimal_scale).to_be_bytes());
+    data_representation.push(bits);
+    data_representation.push(0); // Original values are floating point.
+    push_section(&mut sections, 5, &data_representation);
+
+    // Section 6: bitmap, which is not used.
+    push_section(&mut sections, 6, &[255]);
+
+    // Section 7: data.
+    push_section(&mut sections, 7, &data);
+
+    // Section 0: indicator, which includes the total length.
+    let total_length = (16 + sections.len() + 4) as u64;
+    let mut message = Vec::with_capacity(total_length as usize);
+    message.extend_from_slice(b"GRIB");
+    message.extend_from_slice(&[0, 0]); // Reserved.
+    message.push(0); // Discipline: meteorological products.
+    message.push(2); // Edition.
+    message.extend_from_slice(&total_length.to_be_bytes());
+    message.extend_from_slice(&sections);
+
+    // Section 8: end.
+    message.extend_from_slice(b"7777");
+    message
+}
+
+/// Encode the `fields` on the `grid` as a GRIB2 file, containing a message for each field, for
+/// the forecast starting at the `reference_time` (UTC).
+#[must_use]
+pub fn encode(reference_time: NaiveDateTime, grid: &Grid, fields: &[Field]) -> Vec<u8> {
+    fields
+        .iter()
+        .flat_map(|field| encode_message(reference_time, grid, field))
+        .collect()
+}
+
+/// Obtain the forecast for each point of the [`Grid`] around `position`, and encode it as a
+/// GRIB2 file attachment, see [`encode()`]. The file contains the wind, pressure and
+/// precipitation every [`INTERVAL_HOURS`] for [`FORECAST_HOURS`], starting at the current hour.
+pub async fn build_grib(
+    time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    position: Position,
+) -> eyre::Result<Attachment> {
+    let now = time.utc_now().naive_utc();
+    let reference_time = now
+        .with_minute(0)
+        .and_then(|now| now.with_second(0))
+        .and_then(|now| now.with_nanosecond(0))
+        .ok_or_else(|| eyre::eyre!("Unable to truncate {} to the hour", now))?;
+    let end_time = reference_time + chrono::Duration::hours(i64::from(FORECAST_HOURS));
+    let steps: Vec<u32> = (0..=FORECAST_HOURS)
+        .step_by(INTERVAL_HOURS as usize)
+        .collect();
+
+    let grid = Grid::around(position);
+    let mut fields: Vec<Field> = steps
+        .iter()
+        .flat_map(|forecast_hours| {
+            Parameter::ALL.into_iter().map(|parameter| Field {
+                parameter,
+                forecast_hours: *forecast_hours,
+                values: Vec::with_capacity(grid.len() as usize),
+            })
+        })
+        .collect();
+
+    // Repeated requests for the same grid are served by the forecast service's cache.
+    for point in grid.positions() {
+        let mut parameters = open_meteo::ForecastParameters::builder()
+            .latitude(point.latitude)
+            .longitude(point.longitude)
+            .build();
+        parameters.hourly = [
+            HourlyVariable::WindSpeed(GroundLevel::L10),
+            HourlyVariable::WindDirection(GroundLevel::L10),
+            HourlyVariable::PressureMsl,
+            HourlyVariable::Precipitation,
+        ]
+        .into_iter()
+        .collect();
+        parameters.windspeed_unit = Some(WindspeedUnit::Ms);
+        parameters.start_hour = Some(reference_time);
+        parameters.end_hour = Some(end_time);
+
+        let forecast = forecast_service
+            .obtain_forecast(&parameters)
+            .await
+            .wrap_err_with(|| format!("Error obtaining forecast for grid point {:?}", point))?
+            .forecast;
+        let hourly = forecast
+            .hourly
+            .ok_or_else(|| eyre::eyre!("Expected hourly forecast to be present"))?;
+        // Requested in m/s using `windspeed_unit`.
+        let wind_speed: Vec<f32> = hourly
+            .wind_speed
+            .value(&GroundLevel::L10)
+            .ok_or_else(|| eyre::eyre!("Expected wind speed to be present"))?
+            .iter()
+            .map(|speed| speed.0)
+            .collect();
+        let wind_direction = hourly
+            .wind_direction
+            .value(&GroundLevel::L10)
+            .ok_or_else(|| eyre::eyre!("Expected wind direction to be present"))?;
+        let pressure_msl = hourly
+            .pressure_msl
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("Expected pressure to be present"))?;
+        let precipitation: Vec<f32> = hourly
+            .precipitation
+            .as_ref()
+            .ok_or_else(|| eyre::eyre!("Expected precipitation to be present"))?
+            .iter()
+            .map(|precipitation| precipitation.0)
+            .collect();
+
+        for field in &mut fields {
+            let valid_time =
+                reference_time + chrono::Duration::hours(i64::from(field.forecast_hours));
+            let i = hourly
+                .time
+                .iter()
+                .position(|hour| *hour == valid_time)
+                .ok_or_else(|| eyre::eyre!("Forecast is missing the time {}", valid_time))?;
+            let value = |values: &[f32]| {
+                values
+                    .get(i)
+                    .copied()
+                    .ok_or_else(|| eyre::eyre!("Forecast is missing values for {}", valid_time))
+            };
+            let speed = value(&wind_speed)?;
+            // Meteorological convention: the direction the wind is blowing from.
+            let direction = value(wind_direction)?.to_radians();
+            field.values.push(match field.parameter {
+                Parameter::WindU => -speed * direction.sin(),
+                Parameter::WindV => -speed * direction.cos(),
+                // hPa to Pa.
+                Parameter::PressureMsl => value(pressure_msl)? * 100.0,
+                // mm (kg m-2) in the preceding hour to kg m-2 s-1.
+                Parameter::PrecipitationRate => value(&precipitation)? / 3600.0,
+            });
+        }
+    }
+
+    Ok(Attachment {
+        filename: FILENAME.to_owned(),
+        content_type: CONTENT_TYPE.to_owned(),
+        data: encode(reference_time, &grid, &fields),
+    })
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDateTime;
+
+    use crate::gis::Position;
+
+    use super::{encode, pack, Field, Grid, Parameter};
+
+    /// Unpack values packed using [`pack()`].
+    fn unpack(reference: f32, bits: u8, decimal_scale: i16, data: &[u8], len: usize) -> Vec<f32> {
+        let scale = 10_f32.powi(i32::from(decimal_scale));
+        (0..len)
+            .map(|i| {
+                let mut value: u32 = 0;
+                for bit in i * usize::from(bits)..(i + 1) * usize::from(bits) {
+                    let set = (data[bit / 8] >> (7 - bit % 8)) & 1;
+                    value = (value << 1) | u32::from(set);
+                }
+                (reference + value as f32) / scale
+            })
+            .collect()
+    }
+
+    #[test]
+    fn test_pack() {
+        let values = [101_325.0, 100_980.0, 102_004.0, 101_325.0];
+        let (reference, bits, data) = pack(&values, 0);
+        assert_eq!(100_980.0, reference);
+        assert_eq!(11, bits);
+        assert_eq!(values.to_vec(), unpack(reference, bits, 0, &data, 4));
+
+        let values = [1.2, -3.4, 0.0];
+        let (reference, bits, data) = pack(&values, 1);
+        let unpacked = unpack(reference, bits, 1, &data, 3);
+        for (value, unpacked) in values.iter().zip(unpacked) {
+            assert!((value - unpacked).abs() < 0.01, "{} != {}", value, unpacked);
+        }
+
+        let (_, bits, data) = pack(&[5.0, 5.0], 0);
+        assert_eq!(0, bits);
+        assert!(data.is_empty());
+    }
+
+    #[test]
+    fn test_grid() {
+        let grid = Grid::around(Position::new(-43.6, 170.1));
+        let positions: Vec<(f32, f32)> = grid
+            .positions()
+            .map(|position| (position.latitude, position.longitude))
+            .collect();
+        assert_eq!(
+            vec![
+                (-43.0, 169.5),
+                (-43.0, 170.0),
+                (-43.0, 170.5),
+                (-43.5, 169.5),
+                (-43.5, 170.0),
+                (-43.5, 170.5),
+                (-44.0, 169.5),
+                (-44.0, 170.0),
+                (-44.0, 170.5),
+            ],
+            positions
+        );
+    }
+
+    #[test]
+    fn test_encode() {
+        let grid = Grid::around(Position::new(-43.6, 170.1));
+        let reference_time: NaiveDateTime = "2022-12-03T08:00:00".parse().unwrap();
+        let fields = [
+            Field {
+                parameter: Parameter::PressureMsl,
+                forecast_hours: 0,
+                values: vec![101_000.0; 9],
+            },
+            Field {
+                parameter: Parameter::WindU,
+                forecast_hours: 6,
+                values: (0..9).map(|i| i as f32).collect(),
+            },
+        ];
+        let file = encode(reference_time, &grid, &fields);
+
+        let mut offset = 0;
+        for field in &fields {
+            let message = &file[offset..];
+            assert_eq!(b"GRIB", &message[0..4]);
+            assert_eq!(2, message[7]);
+            let length = u64::from_be_bytes(message[8..16].try_into().unwrap()) as usize;
+            assert_eq!(b"7777", &message[length - 4..length]);
+
+            // Walk the sections, checking their numbers and lengths.
+            let mut section_offset = 16;
+            let mut numbers = Vec::new();
+            while section_offset < length - 4 {
+                let section_length = u32::from_be_bytes(
+                    message[section_offset..section_offset + 4]
+                        .try_into()
+                        .unwrap(),
+                ) as usize;
+                let number = message[section_offset + 4];
+                match number {
+                    1 => assert_eq!(21, section_length),
+                    3 => assert_eq!(72, section_length),
+                    4 => {
+                        assert_eq!(34, section_length);
+                        let forecast_hours = u32::from_be_bytes(
+                            message[section_offset + 18..section_offset + 22]
+                                .try_into()
+                                .unwrap(),
+                        );
+                        assert_eq!(field.forecast_hours, forecast_hours);
+                    }
+                    5 => assert_eq!(21, section_length),
+                    6 => assert_eq!(6, section_length),
+                    _ => {}
+                }
+                numbers.push(number);
+                section_offset += section_length;
+            }
+            assert_eq!(vec![1, 3, 4, 5, 6, 7], numbers);
+            offset += length;
+        }
+        assert_eq!(file.len(), offset);
+    }
+}
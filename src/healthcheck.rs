@@ -0,0 +1,76 @@
+//! Checking the health of a running instance of the service from the command line, for use as a
+//! container `HEALTHCHECK` or by a process supervisor, see [`healthcheck()`].
+
+use std::{
+    net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr},
+    time::Duration,
+};
+
+use eyre::Context;
+
+use crate::options::Options;
+
+/// How long to wait for the service to respond.
+const TIMEOUT: Duration = Duration::from_secs(10);
+
+/// The url of the health endpoint (see [`crate::status::serve_status()`]) of the service listening
+/// on `listen_address`. When listening on all interfaces, the loopback address is used.
+#[must_use]
+pub fn health_url(listen_address: SocketAddr, https: bool) -> String {
+    let ip = match listen_address.ip() {
+        IpAddr::V4(ip) if ip.is_unspecified() => IpAddr::V4(Ipv4Addr::LOCALHOST),
+        IpAddr::V6(ip) if ip.is_unspecified() => IpAddr::V6(Ipv6Addr::LOCALHOST),
+        ip => ip,
+    };
+    let scheme = if https { "https" } else { "http" };
+    format!(
+        "{}://{}/status/health",
+        scheme,
+        SocketAddr::new(ip, listen_address.port())
+    )
+}
+
+/// Check the health of the service running locally with `options`, returns an error if it could
+/// not be reached, or if it is degraded.
+pub async fn healthcheck(options: &Options) -> eyre::Result<String> {
+    let https = options.tls.is_some() || options.acme.is_some();
+    let url = health_url(options.listen_address, https);
+    // The certificate is for the public hostname, not the local address.
+    let http_client = reqwest::Client::builder()
+        .timeout(TIMEOUT)
+        .danger_accept_invalid_certs(true)
+        .build()?;
+    let response = http_client
+        .get(&url)
+        .send()
+        .await
+        .wrap_err_with(|| format!("Unable to reach the service at {}", url))?;
+    let status = response.status();
+    let body = response.text().await?;
+    if status.is_success() {
+        Ok(body)
+    } else {
+        Err(eyre::eyre!("Service is unhealthy ({}): {}", status, body))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::health_url;
+
+    #[test]
+    fn test_health_url() {
+        assert_eq!(
+            "http://127.0.0.1:3000/status/health",
+            health_url("0.0.0.0:3000".parse().unwrap(), false)
+        );
+        assert_eq!(
+            "https://[::1]:443/status/health",
+            health_url("[::]:443".parse().unwrap(), true)
+        );
+        assert_eq!(
+            "http://192.168.1.2:8080/status/health",
+            health_url("192.168.1.2:8080".parse().unwrap(), false)
+        );
+    }
+}
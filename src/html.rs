@@ -0,0 +1,98 @@
+//! Escaping of content which is written into html replies and pages, see [`escape()`],
+//! [`Escaped`] and [`attribute()`].
+//!
+//! Any content which may originate from a user (e.g. parse errors which quote the request, or file
+//! names) must be escaped before it is written into html.
+
+use std::{
+    borrow::Cow,
+    fmt::{Display, Write},
+};
+
+fn escape_char(c: char) -> Option<&'static str> {
+    match c {
+        '&' => Some("&amp;"),
+        '<' => Some("&lt;"),
+        '>' => Some("&gt;"),
+        '"' => Some("&quot;"),
+        '\'' => Some("&#x27;"),
+        _ => None,
+    }
+}
+
+/// Escape `text` so that it can be safely written into html, either as the content of an element
+/// or as a quoted attribute value.
+#[must_use]
+pub fn escape(text: &str) -> Cow<'_, str> {
+    if !text.chars().any(|c| escape_char(c).is_some()) {
+        return Cow::Borrowed(text);
+    }
+
+    let mut escaped = String::with_capacity(text.len() + 16);
+    for c in text.chars() {
+        match escape_char(c) {
+            Some(entity) => escaped.push_str(entity),
+            None => escaped.push(c),
+        }
+    }
+    Cow::Owned(escaped)
+}
+
+/// Format an html attribute `name="value"` with the `value` escaped, for use with the `attr()`
+/// method of [`html_builder`] nodes.
+#[must_use]
+pub fn attribute(name: &str, value: &str) -> String {
+    format!(r#"{}="{}""#, name, escape(value))
+}
+
+/// Wrapper which escapes the [`Display`] output of the wrapped value, for use with `write!`.
+pub struct Escaped<T>(pub T);
+
+struct EscapeWriter<'a, 'b>(&'a mut std::fmt::Formatter<'b>);
+
+impl Write for EscapeWriter<'_, '_> {
+    fn write_str(&mut self, s: &str) -> std::fmt::Result {
+        self.0.write_str(&escape(s))
+    }
+}
+
+impl<T: Display> Display for Escaped<T> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(EscapeWriter(f), "{}", self.0)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::borrow::Cow;
+
+    use super::{attribute, escape, Escaped};
+
+    #[test]
+    fn test_escape() {
+        assert!(matches!(
+            escape("-43.5,170.3"),
+            Cow::Borrowed("-43.5,170.3")
+        ));
+        assert_eq!(
+            "&lt;script&gt;alert(&quot;1&quot; &amp; &#x27;2&#x27;)&lt;/script&gt;",
+            escape(r#"<script>alert("1" & '2')</script>"#)
+        );
+    }
+
+    #[test]
+    fn test_escaped_display() {
+        assert_eq!(
+            "Unknown profile: &lt;b&gt;ski&lt;/b&gt;",
+            format!("Unknown profile: {}", Escaped("<b>ski</b>"))
+        );
+    }
+
+    #[test]
+    fn test_attribute() {
+        assert_eq!(
+            r#"href="/logs/&quot;&gt;&lt;script&gt;""#,
+            attribute("href", r#"/logs/"><script>"#)
+        );
+    }
+}
@@ -1,5 +1,6 @@
 //! Parsing emails received from an inreach device.
 
+use chrono::{DateTime, TimeZone, Utc};
 use eyre::Context;
 use once_cell::sync::Lazy;
 use regex::Regex;
@@ -14,13 +15,22 @@ use crate::{
 /// An email received from an inreach device.
 #[derive(Clone, Deserialize, Serialize, Debug)]
 pub struct Received {
-    /// The name of the person who sent the message.
-    /// TODO: remove as part of anonymizing #12
+    /// The name of the person who sent the message, which is hashed before it is stored in the
+    /// queue (see [`crate::redact::redact_at_rest()`]).
     pub from_name: String,
     /// The url used to send a reply to the message via the inreach web interface.
     pub referral_url: url::Url,
     /// The position of the inreach device at the time that the message was sent.
     pub position: Position,
+    /// The MapShare page of the sender, if it was included in the message.
+    #[serde(default)]
+    pub mapshare_url: Option<url::Url>,
+    /// Identifier of the sending device (the `extId` of the [`Received::referral_url`]).
+    #[serde(default)]
+    pub device_id: Option<String>,
+    /// The time that the message was sent (from the `Date` header).
+    #[serde(default)]
+    pub sent_at: Option<DateTime<Utc>>,
     /// Weather forecast request.
     pub forecast_request: ParsedForecastRequest,
 }
@@ -33,12 +43,22 @@ impl receive::Received for Received {
     fn forecast_request(&self) -> &ParsedForecastRequest {
         &self.forecast_request
     }
+
+    fn sent_at(&self) -> Option<DateTime<Utc>> {
+        self.sent_at
+    }
+
+    fn sender_identity(&self) -> Option<&str> {
+        Some(self.device_id.as_deref().unwrap_or(&self.from_name))
+    }
 }
 
 static VIEW_LOCATION_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"View the location or send a reply to (.*)[:]").unwrap());
 static MESSAGE_FROM_RE: Lazy<Regex> =
     Lazy::new(|| Regex::new(r"(.*) sent this message from: Lat (.*) Lon (.*)").unwrap());
+static MAPSHARE_RE: Lazy<Regex> =
+    Lazy::new(|| Regex::new(r"https?://share\.garmin\.com/\S+").unwrap());
 
 #[derive(PartialEq)]
 enum ParseState {
@@ -53,7 +73,11 @@ impl ParseReceivedEmail for Received {
 
     fn parse_email(message: mail_parser::Message) -> Result<Self, Self::Err> {
         let body = text_body(&message)?;
-        Self::parse(&body)
+        let mut received = Self::parse(&body)?;
+        received.sent_at = message
+            .date()
+            .and_then(|date| Utc.timestamp_opt(date.to_timestamp(), 0).single());
+        Ok(received)
     }
 }
 
@@ -127,11 +151,29 @@ impl Received {
             eyre::bail!("Unable to parse email text as a complete inreach message")
         }
 
+        let referral_url = referral_url.unwrap();
+        let device_id = referral_url
+            .query_pairs()
+            .find(|(key, _)| key == "extId")
+            .map(|(_, value)| value.into_owned());
+        let mapshare_url = match (*MAPSHARE_RE).find(body) {
+            Some(mapshare) => Some(
+                mapshare
+                    .as_str()
+                    .parse()
+                    .wrap_err("unable to parse MapShare url")?,
+            ),
+            None => None,
+        };
+
         let forecast_request = ParsedForecastRequest::parse(&message_body);
         Ok(Self {
             from_name: from_name.unwrap(),
-            referral_url: referral_url.unwrap(),
+            referral_url,
             position: Position::new(latitude.unwrap(), longitude.unwrap()),
+            mapshare_url,
+            device_id,
+            sent_at: None,
             forecast_request,
         })
     }
@@ -139,6 +181,8 @@ impl Received {
 
 #[cfg(test)]
 mod test {
+    use crate::receive::ParseReceivedEmail;
+
     use super::Received;
 
     const TEST_BODY: &'static str = r#"
@@ -166,23 +210,52 @@ learn more, visit http://explore.garmin.com/inreach.
             "latitude": -44.68953,
             "longitude": 169.13235
           },
+          "mapshare_url": null,
+          "device_id": "000aa0e6-8e00-2501-000d-3aa730600000",
+          "sent_at": null,
           "forecast_request": {
             "request": {
               "position": {
                 "latitude": -37.8245,
                 "longitude": 145.30328
               },
-              "format": {
-                "detail": {
-                  "Short": {
-                    "length_limit": null
-                  }
-                }
-              }
+              "format": null,
+              "profile": null,
+              "past_days": null,
+              "grib": false,
+              "fly": false,
+              "snow_outlook": null,
+              "metar": null,
+              "past_weather": null,
+              "flood": null,
+              "solar": false
             },
-            "errors": []
+            "errors": [],
+            "command": null
           }
         }
         "###);
     }
+
+    #[test]
+    fn test_parse_email_mapshare() {
+        let body = TEST_BODY.replace(
+            "Do not reply directly to this message.",
+            "View Luke Frisken's MapShare: https://share.garmin.com/LukeFrisken\n\n\
+            Do not reply directly to this message.",
+        );
+        let email = Received::parse(&body).unwrap();
+        assert_eq!(
+            Some("https://share.garmin.com/LukeFrisken".parse().unwrap()),
+            email.mapshare_url
+        );
+    }
+
+    #[test]
+    fn test_parse_email_sent_at() {
+        let rfc822 = std::fs::read("fixtures/emails/inreach_forecast.eml").unwrap();
+        let message = mail_parser::Message::parse(&rfc822).unwrap();
+        let email = Received::parse_email(message).unwrap();
+        assert_eq!(Some("2022-12-03T07:57:40Z".parse().unwrap()), email.sent_at);
+    }
 }
@@ -128,7 +128,8 @@ pub async fn reply(
         .to_string();
     let content_length = post_body.len();
 
-    tracing::debug!("{:?}", post_body);
+    // The body contains the reply message and the sender's tokens, so only its length is logged.
+    tracing::debug!("Posting reply ({} bytes) to {}", content_length, post_url);
 
     let post_response = client
         .post(post_url)
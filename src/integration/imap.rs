@@ -0,0 +1,201 @@
+//! A fake IMAP server which serves messages from an in memory [`Inbox`], implementing just
+//! enough of the protocol for [`crate::receive`].
+
+use std::{
+    net::SocketAddr,
+    sync::{Arc, Mutex, MutexGuard},
+};
+
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    task::JoinHandle,
+};
+
+struct Message {
+    rfc822: Vec<u8>,
+    seen: bool,
+}
+
+/// The messages served by [`FakeImap`].
+#[derive(Clone, Default)]
+pub(super) struct Inbox(Arc<Mutex<Vec<Message>>>);
+
+impl Inbox {
+    fn lock(&self) -> MutexGuard<'_, Vec<Message>> {
+        self.0.lock().expect("inbox mutex is poisoned")
+    }
+
+    /// Deliver an unseen `rfc822` message to the inbox.
+    pub(super) fn deliver(&self, rfc822: impl Into<Vec<u8>>) {
+        self.lock().push(Message {
+            rfc822: rfc822.into(),
+            seen: false,
+        });
+    }
+
+    fn select(&self, tag: &str) -> Vec<u8> {
+        format!(
+            "* FLAGS (\\Seen)\r\n\
+            * {} EXISTS\r\n\
+            * 0 RECENT\r\n\
+            * OK [UIDVALIDITY 1] UIDs valid\r\n\
+            {} OK [READ-WRITE] SELECT completed\r\n",
+            self.lock().len(),
+            tag
+        )
+        .into_bytes()
+    }
+
+    fn search(&self, tag: &str, criteria: &str) -> Vec<u8> {
+        let unseen_only = match criteria.to_ascii_uppercase().as_str() {
+            "UNSEEN" => true,
+            "ALL" => false,
+            _ => return format!("{} BAD Unsupported search criteria\r\n", tag).into_bytes(),
+        };
+        let sequences: String = self
+            .lock()
+            .iter()
+            .enumerate()
+            .filter(|(_, message)| !unseen_only || !message.seen)
+            .map(|(i, _)| format!(" {}", i + 1))
+            .collect();
+        format!("* SEARCH{}\r\n{} OK SEARCH completed\r\n", sequences, tag).into_bytes()
+    }
+
+    /// Fetch the messages in the sequence set (e.g. `1,3:4`) at the start of `arguments`, marking
+    /// them as seen. Only the `RFC822` data item is supported.
+    fn fetch(&self, tag: &str, arguments: &str) -> Vec<u8> {
+        let (sequence_set, item) = arguments.split_once(' ').unwrap_or((arguments, ""));
+        if !item.eq_ignore_ascii_case("RFC822") {
+            return format!("{} BAD Unsupported fetch data item\r\n", tag).into_bytes();
+        }
+
+        let mut messages = self.lock();
+        let last = messages.len();
+        let mut response = Vec::new();
+        for range in sequence_set.split(',') {
+            let (start, end) = range.split_once(':').unwrap_or((range, range));
+            let parse = |sequence: &str| match sequence {
+                "*" => Some(last),
+                _ => sequence.parse::<usize>().ok(),
+            };
+            let (start, end) = match (parse(start), parse(end)) {
+                (Some(start), Some(end)) => (start.min(end), start.max(end)),
+                _ => return format!("{} BAD Invalid sequence set\r\n", tag).into_bytes(),
+            };
+            for sequence in start.max(1)..=end.min(last) {
+                let message = &mut messages[sequence - 1];
+                message.seen = true;
+                response.extend_from_slice(
+                    format!(
+                        "* {} FETCH (RFC822 {{{}}}\r\n",
+                        sequence,
+                        message.rfc822.len()
+                    )
+                    .as_bytes(),
+                );
+                response.extend_from_slice(&message.rfc822);
+                response.extend_from_slice(b")\r\n");
+            }
+        }
+        response.extend_from_slice(format!("{} OK FETCH completed\r\n", tag).as_bytes());
+        response
+    }
+}
+
+async fn serve_connection(stream: TcpStream, inbox: Inbox) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer
+        .write_all(b"* OK [CAPABILITY IMAP4rev1] Fake IMAP server ready\r\n")
+        .await?;
+
+    while let Some(line) = lines.next_line().await? {
+        let mut parts = line.splitn(3, ' ');
+        let tag = parts.next().unwrap_or_default();
+        let command = parts.next().unwrap_or_default().to_ascii_uppercase();
+        let arguments = parts.next().unwrap_or_default();
+
+        let response = match command.as_str() {
+            "LOGIN" | "NOOP" | "CHECK" | "CLOSE" => {
+                format!("{} OK {} completed\r\n", tag, command).into_bytes()
+            }
+            "SELECT" | "EXAMINE" => inbox.select(tag),
+            "SEARCH" => inbox.search(tag, arguments),
+            "FETCH" => inbox.fetch(tag, arguments),
+            "LOGOUT" => {
+                writer
+                    .write_all(
+                        format!("* BYE Logging out\r\n{} OK LOGOUT completed\r\n", tag).as_bytes(),
+                    )
+                    .await?;
+                return Ok(());
+            }
+            _ => format!("{} BAD Unsupported command\r\n", tag).into_bytes(),
+        };
+        writer.write_all(&response).await?;
+    }
+
+    Ok(())
+}
+
+/// A fake IMAP server listening on a local port, which serves the messages in its [`Inbox`] to
+/// any user.
+pub(super) struct FakeImap {
+    address: SocketAddr,
+    inbox: Inbox,
+    server: JoinHandle<()>,
+}
+
+impl FakeImap {
+    /// Start the server listening on a random local port.
+    pub(super) async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let inbox = Inbox::default();
+        let server = tokio::spawn({
+            let inbox = inbox.clone();
+            async move {
+                while let Ok((stream, _)) = listener.accept().await {
+                    let inbox = inbox.clone();
+                    tokio::spawn(async move {
+                        if let Err(error) = serve_connection(stream, inbox).await {
+                            tracing::error!("Fake IMAP connection error: {:?}", error);
+                        }
+                    });
+                }
+            }
+        });
+
+        Self {
+            address,
+            inbox,
+            server,
+        }
+    }
+
+    /// The inbox served by this server.
+    pub(super) fn inbox(&self) -> &Inbox {
+        &self.inbox
+    }
+
+    /// Connect and log in to this server.
+    pub(super) async fn connect(&self) -> async_imap::Session<TcpStream> {
+        let stream = TcpStream::connect(self.address).await.unwrap();
+        let mut client = async_imap::Client::new(stream);
+        let greeting = client.read_response().await;
+        assert!(greeting.is_some(), "Expected a greeting from the server");
+        client
+            .login("test.email.weather.service@gmail.com", "password")
+            .await
+            .map_err(|(error, _)| error)
+            .unwrap()
+    }
+}
+
+impl Drop for FakeImap {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
@@ -0,0 +1,391 @@
+//! Integration tests which run the real receive, process and reply tasks against a fake IMAP
+//! server ([`imap::FakeImap`]) and SMTP server ([`smtp::SmtpSink`]), with the external APIs
+//! served by [`wiremock`] and time simulated using [`time::Simulated`], and assert on the replies
+//! which are sent.
+
+mod imap;
+mod smtp;
+
+use std::{collections::HashMap, path::PathBuf, sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use tokio::{
+    sync::{watch, Mutex},
+    task::JoinHandle,
+};
+use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+use crate::{
+    aviation_weather,
+    circuit_breaker::{CircuitBreakerOptions, CircuitBreakerPort, CircuitBreakers},
+    control::Controls,
+    correlation::RequestId,
+    email, flood, forecast_service,
+    latency::Latency,
+    options::DynamicOptions,
+    process::process_emails_impl,
+    queue,
+    quota::Quotas,
+    receive::{receive_emails_session, text_body},
+    reply::{self, send_replies_impl, Reply, SmtpTransport},
+    retry::BackoffOptions,
+    status::ServiceStatus,
+    task::TaskContext,
+    time, topo_data_service,
+    webhook::Webhooks,
+};
+
+use self::{
+    imap::FakeImap,
+    smtp::{SentEmail, SmtpSink},
+};
+
+/// Account which the service receives requests with and sends replies from.
+const SERVICE_ACCOUNT: &str = "test.email.weather.service@gmail.com";
+
+/// Real time is only used to prevent a failing test from hanging.
+const TIMEOUT: Duration = Duration::from_secs(30);
+
+fn start() -> DateTime<Utc> {
+    "2022-12-03T08:00:00Z".parse().unwrap()
+}
+
+fn fixture(name: &str) -> String {
+    std::fs::read_to_string(format!("fixtures/emails/{}.eml", name)).unwrap()
+}
+
+/// Implementation of [`reply::Port`] which sends plain replies using an unauthenticated SMTP
+/// `transport` (instead of the Gmail transport used by [`reply::Gateway`]).
+struct SmtpGateway {
+    transport: SmtpTransport,
+    http_client: reqwest::Client,
+    email_account: email::Account,
+}
+
+#[async_trait]
+impl reply::Port for SmtpGateway {
+    async fn test_connection(&self) -> eyre::Result<()> {
+        if self.transport.test_connection().await? {
+            Ok(())
+        } else {
+            Err(eyre::eyre!("Test connection was unsuccessful"))
+        }
+    }
+
+    async fn send_reply(&self, request_id: RequestId, reply: &Reply) -> eyre::Result<()> {
+        reply::send_reply(
+            request_id,
+            reply,
+            &self.transport,
+            &self.http_client,
+            &self.email_account,
+        )
+        .await
+    }
+}
+
+/// Spawn a task which is expected to run until it is aborted.
+fn spawn_task<F>(name: &'static str, task: F) -> JoinHandle<()>
+where
+    F: std::future::Future<Output = eyre::Result<()>> + Send + 'static,
+{
+    tokio::spawn(async move {
+        let result = task.await;
+        panic!("{} task exited unexpectedly: {:?}", name, result);
+    })
+}
+
+/// The fake servers, and the tasks which run against them once [`Harness::start()`] is called.
+/// Queues are stored in a temporary directory, which is removed when dropped.
+struct Harness {
+    imap: FakeImap,
+    smtp: SmtpSink,
+    open_meteo: MockServer,
+    open_topo_data: MockServer,
+    inreach: MockServer,
+    queue_dir: PathBuf,
+    time: &'static time::Simulated,
+    context: TaskContext,
+    /// Keeps the options channel open while the tasks are running.
+    _options_tx: Option<watch::Sender<DynamicOptions>>,
+    tasks: Vec<JoinHandle<()>>,
+}
+
+impl Harness {
+    /// Start the fake servers, with the external APIs responding with the forecast and
+    /// elevation for Mt Cook.
+    async fn new() -> Self {
+        let open_meteo = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v1/forecast"))
+            .respond_with(ResponseTemplate::new(200).set_body_raw(
+                std::fs::read("fixtures/forecast_mt_cook.json").unwrap(),
+                "application/json",
+            ))
+            .mount(&open_meteo)
+            .await;
+
+        let open_topo_data = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path_regex(r"^/v1/[a-z0-9]+$"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{
+                    "dataset": "srtm90m",
+                    "elevation": 2216.0,
+                    "location": { "lat": -43.513832, "lng": 170.33975 },
+                }],
+                "status": "OK",
+            })))
+            .mount(&open_topo_data)
+            .await;
+
+        let inreach = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/textmessage/txtmsg"))
+            .respond_with(
+                ResponseTemplate::new(200)
+                    .insert_header("set-cookie", "BrowsingMode=Desktop; path=/")
+                    .set_body_raw(
+                        r#"<html><body><input id="MessageId" name="MessageId" type="hidden" value="66270435"></body></html>"#,
+                        "text/html; charset=utf-8",
+                    ),
+            )
+            .mount(&inreach)
+            .await;
+        Mock::given(matchers::method("POST"))
+            .and(matchers::path("/TextMessage/TxtMsg"))
+            .respond_with(
+                ResponseTemplate::new(200).set_body_json(serde_json::json!({"Success": true})),
+            )
+            .mount(&inreach)
+            .await;
+
+        let time: &'static time::Simulated = Box::leak(Box::new(time::Simulated::new(start())));
+        let context = TaskContext {
+            status: Box::leak(Box::new(ServiceStatus::new(start()))),
+            webhooks: Box::leak(Box::new(Webhooks::new(
+                Vec::new(),
+                reqwest::Client::new(),
+                time,
+            ))),
+            circuit_breakers: Box::leak(Box::new(CircuitBreakers::new(
+                CircuitBreakerOptions::default(),
+            ))),
+            analytics: None,
+            confirmations: None,
+            feeds: None,
+            previous_forecasts: None,
+            public_keys: None,
+            archive: None,
+            quotas: Box::leak(Box::new(Quotas::new(
+                watch::channel(DynamicOptions::default()).1,
+            ))),
+            latency: Box::leak(Box::new(Latency::new())),
+            controls: Box::leak(Box::new(Controls::new())),
+            backoff: BackoffOptions::default(),
+            time,
+        };
+
+        Self {
+            imap: FakeImap::start().await,
+            smtp: SmtpSink::start().await,
+            open_meteo,
+            open_topo_data,
+            inreach,
+            queue_dir: std::env::temp_dir().join(format!(
+                "email-weather-integration-{}",
+                uuid::Uuid::new_v4()
+            )),
+            time,
+            context,
+            _options_tx: None,
+            tasks: Vec::new(),
+        }
+    }
+
+    /// Deliver the recorded email named `name` to the inbox, replacing the inReach reply
+    /// service with the mock server.
+    fn deliver(&self, name: &str) {
+        self.deliver_rfc822(&fixture(name));
+    }
+
+    fn deliver_rfc822(&self, rfc822: &str) {
+        let rfc822 = rfc822.replace("https://aus.explore.garmin.com", &self.inreach.uri());
+        self.imap.inbox().deliver(rfc822);
+    }
+
+    /// Start the receive, process and reply tasks, the inbox is polled immediately.
+    async fn start(&mut self, options: DynamicOptions) {
+        let context = self.context;
+        let (options_tx, options_rx) = watch::channel(options);
+        self._options_tx = Some(options_tx);
+        let http_client = reqwest::Client::new();
+
+        let process_path = self.queue_dir.join("process");
+        let reply_path = self.queue_dir.join("reply");
+        let process_sender = queue::Sender::from(yaque::Sender::open(&process_path).unwrap());
+        let mut process_receiver =
+            queue::Receiver::from(yaque::Receiver::open(&process_path).unwrap());
+        let mut reply_sender = queue::Sender::from(yaque::Sender::open(&reply_path).unwrap());
+        let mut reply_receiver = queue::Receiver::from(yaque::Receiver::open(&reply_path).unwrap());
+
+        let mut imap_session = self.imap.connect().await;
+        let receive_options_rx = options_rx.clone();
+        self.tasks.push(spawn_task("Receive", async move {
+            receive_emails_session(
+                Arc::new(Mutex::new(process_sender)),
+                &mut imap_session,
+                &receive_options_rx,
+                &context,
+            )
+            .await
+        }));
+
+        let forecast_service = CircuitBreakerPort::new(
+            forecast_service::Gateway::with_base_url(http_client.clone(), self.open_meteo.uri()),
+            &context.circuit_breakers.forecast,
+            context.time,
+        );
+        let topo_data_service = CircuitBreakerPort::new(
+            topo_data_service::Gateway::with_base_url(
+                http_client.clone(),
+                self.open_topo_data.uri(),
+            ),
+            &context.circuit_breakers.topo_data,
+            context.time,
+        );
+        self.tasks.push(spawn_task("Process", async move {
+            process_emails_impl(
+                &mut process_receiver,
+                &mut reply_sender,
+                &forecast_service,
+                &topo_data_service,
+                &aviation_weather::MockPort::new(),
+                &flood::MockPort::new(),
+                &options_rx,
+                &context,
+            )
+            .await
+        }));
+
+        let reply_port = SmtpGateway {
+            transport: self.smtp.transport(),
+            http_client,
+            email_account: SERVICE_ACCOUNT.parse().unwrap(),
+        };
+        self.tasks.push(spawn_task("Reply", async move {
+            send_replies_impl(&mut reply_receiver, &reply_port, context.backoff, context).await
+        }));
+    }
+
+    /// Wait for the receive task to finish polling the inbox, and then advance the clock by
+    /// `poll_interval` so that it polls again.
+    async fn poll_again(&self, poll_interval: Duration) {
+        tokio::time::timeout(TIMEOUT, async {
+            while self.time.pending_timers() == 0 {
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for the inbox to be polled");
+        self.time.advance(poll_interval);
+    }
+
+    /// Wait for the next email to be sent to the SMTP server.
+    async fn expect_email(&mut self) -> SentEmail {
+        tokio::time::timeout(TIMEOUT, self.smtp.recv())
+            .await
+            .expect("Timed out waiting for an email to be sent")
+    }
+
+    /// Wait for `count` replies to be sent to the inReach reply service, and return the form data
+    /// of each.
+    async fn expect_inreach_replies(&self, count: usize) -> Vec<HashMap<String, String>> {
+        tokio::time::timeout(TIMEOUT, async {
+            loop {
+                let replies: Vec<HashMap<String, String>> = self
+                    .inreach
+                    .received_requests()
+                    .await
+                    .unwrap_or_default()
+                    .into_iter()
+                    .filter(|request| request.method == wiremock::http::Method::Post)
+                    .map(|request| serde_urlencoded::from_bytes(&request.body).unwrap())
+                    .collect();
+                if replies.len() >= count {
+                    return replies;
+                }
+                tokio::time::sleep(Duration::from_millis(10)).await;
+            }
+        })
+        .await
+        .expect("Timed out waiting for inReach replies to be sent")
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        for task in &self.tasks {
+            task.abort();
+        }
+        let _ = std::fs::remove_dir_all(&self.queue_dir);
+    }
+}
+
+/// Receives a plain email, an email from a sender who is not on the whitelist (which is
+/// ignored), and an inReach email, and then another plain email on the next poll of the inbox.
+#[tokio::test]
+async fn test_receive_process_reply() {
+    let mut harness = Harness::new().await;
+    let options = DynamicOptions {
+        whitelist: Some(vec!["l.frisken@gmail.com".to_owned()]),
+        ..DynamicOptions::default()
+    };
+    let poll_interval = options.poll_interval;
+
+    harness.deliver("plain_forecast");
+    harness.deliver("plain_not_whitelisted");
+    harness.deliver("inreach_forecast");
+    harness.start(options).await;
+
+    let email = harness.expect_email().await;
+    assert_eq!(SERVICE_ACCOUNT, email.from);
+    assert_eq!(vec!["l.frisken@gmail.com".to_owned()], email.to);
+    let message = mail_parser::Message::parse(&email.data).unwrap();
+    assert_eq!(Some("Re: Forecast"), message.subject());
+    assert!(message.header("X-Request-Id").is_some());
+    assert!(String::from_utf8_lossy(&email.data)
+        .contains("CAH+3HA1rdRyAyLW+-6zkHLW6UV2Y7bbK2h5Yujq-C6ydX3y1AQ@mail.gmail.com"));
+    // Lines of the sent body are terminated with CRLF.
+    assert!(text_body(&message)
+        .unwrap()
+        .replace("\r\n", "\n")
+        .starts_with("Tz+13:00 FE0 TE2216\n03T21 C2 F28 W1@32 P0"));
+
+    let inreach_replies = harness.expect_inreach_replies(1).await;
+    assert_eq!(1, inreach_replies.len());
+    let inreach_reply = &inreach_replies[0];
+    assert_eq!(
+        "email.weather.service@gmail.com",
+        inreach_reply["ReplyAddress"]
+    );
+    assert_eq!("66270435", inreach_reply["MessageId"]);
+    assert!(inreach_reply["ReplyMessage"].starts_with("Tz+13:00 FE0 TE2216\n03T21 C2"));
+
+    harness.deliver_rfc822(
+        &fixture("plain_forecast").replace("Subject: Forecast", "Subject: Second forecast"),
+    );
+    harness.poll_again(poll_interval).await;
+
+    // The email from the sender who is not on the whitelist was received before this one, so it
+    // would have been replied to first if it had not been ignored.
+    let email = harness.expect_email().await;
+    let message = mail_parser::Message::parse(&email.data).unwrap();
+    assert_eq!(Some("Re: Second forecast"), message.subject());
+
+    assert_eq!(
+        Some(start() + chrono::Duration::from_std(poll_interval).unwrap()),
+        harness.context.status.last_forecast_processed()
+    );
+}
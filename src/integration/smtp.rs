@@ -0,0 +1,149 @@
+//! A fake SMTP server which accepts any email and records it, see [`SmtpSink`].
+
+use std::net::SocketAddr;
+
+use lettre::{AsyncSmtpTransport, Tokio1Executor};
+use tokio::{
+    io::{AsyncBufReadExt, AsyncWriteExt, BufReader},
+    net::{TcpListener, TcpStream},
+    sync::mpsc,
+    task::JoinHandle,
+};
+
+use crate::reply::SmtpTransport;
+
+/// An email received by [`SmtpSink`].
+#[derive(Debug)]
+pub(super) struct SentEmail {
+    /// Address from the `MAIL FROM` command.
+    pub from: String,
+    /// Addresses from the `RCPT TO` commands.
+    pub to: Vec<String>,
+    /// The message data, with lines separated by `\r\n`.
+    pub data: Vec<u8>,
+}
+
+/// Extract the address from a `FROM:<address>` or `TO:<address>` command argument.
+fn path_address(argument: &str) -> String {
+    argument
+        .split_once('<')
+        .and_then(|(_, address)| address.split_once('>'))
+        .map(|(address, _)| address.to_owned())
+        .unwrap_or_default()
+}
+
+async fn serve_connection(
+    stream: TcpStream,
+    sent_tx: mpsc::UnboundedSender<SentEmail>,
+) -> std::io::Result<()> {
+    let (reader, mut writer) = stream.into_split();
+    let mut lines = BufReader::new(reader).lines();
+    writer
+        .write_all(b"220 localhost Fake SMTP sink\r\n")
+        .await?;
+
+    let mut from = String::new();
+    let mut to = Vec::new();
+    while let Some(line) = lines.next_line().await? {
+        let command = line.to_ascii_uppercase();
+        let response: &[u8] = if command.starts_with("EHLO") {
+            b"250-localhost\r\n250 8BITMIME\r\n"
+        } else if command.starts_with("HELO") {
+            b"250 localhost\r\n"
+        } else if command.starts_with("MAIL FROM:") {
+            from = path_address(&line);
+            b"250 OK\r\n"
+        } else if command.starts_with("RCPT TO:") {
+            to.push(path_address(&line));
+            b"250 OK\r\n"
+        } else if command == "DATA" {
+            writer
+                .write_all(b"354 End data with <CR><LF>.<CR><LF>\r\n")
+                .await?;
+            let mut data = Vec::new();
+            while let Some(line) = lines.next_line().await? {
+                if line == "." {
+                    break;
+                }
+                // Undo dot stuffing.
+                let line = line.strip_prefix('.').unwrap_or(&line);
+                data.extend_from_slice(line.as_bytes());
+                data.extend_from_slice(b"\r\n");
+            }
+            let _ = sent_tx.send(SentEmail {
+                from: std::mem::take(&mut from),
+                to: std::mem::take(&mut to),
+                data,
+            });
+            b"250 OK: queued\r\n"
+        } else if command == "RSET" {
+            from.clear();
+            to.clear();
+            b"250 OK\r\n"
+        } else if command == "NOOP" {
+            b"250 OK\r\n"
+        } else if command == "QUIT" {
+            writer.write_all(b"221 Bye\r\n").await?;
+            return Ok(());
+        } else {
+            b"502 Command not implemented\r\n"
+        };
+        writer.write_all(response).await?;
+    }
+
+    Ok(())
+}
+
+/// A fake SMTP server listening on a local port, which accepts all emails without
+/// authentication, and records them to be received using [`SmtpSink::recv()`].
+pub(super) struct SmtpSink {
+    address: SocketAddr,
+    sent_rx: mpsc::UnboundedReceiver<SentEmail>,
+    server: JoinHandle<()>,
+}
+
+impl SmtpSink {
+    /// Start the server listening on a random local port.
+    pub(super) async fn start() -> Self {
+        let listener = TcpListener::bind("127.0.0.1:0").await.unwrap();
+        let address = listener.local_addr().unwrap();
+        let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+        let server = tokio::spawn(async move {
+            while let Ok((stream, _)) = listener.accept().await {
+                let sent_tx = sent_tx.clone();
+                tokio::spawn(async move {
+                    if let Err(error) = serve_connection(stream, sent_tx).await {
+                        tracing::error!("Fake SMTP connection error: {:?}", error);
+                    }
+                });
+            }
+        });
+
+        Self {
+            address,
+            sent_rx,
+            server,
+        }
+    }
+
+    /// Construct a transport which sends emails to this server.
+    pub(super) fn transport(&self) -> SmtpTransport {
+        AsyncSmtpTransport::<Tokio1Executor>::builder_dangerous(self.address.ip().to_string())
+            .port(self.address.port())
+            .build()
+    }
+
+    /// Wait for the next email to be received.
+    pub(super) async fn recv(&mut self) -> SentEmail {
+        self.sent_rx
+            .recv()
+            .await
+            .expect("SMTP sink server has stopped")
+    }
+}
+
+impl Drop for SmtpSink {
+    fn drop(&mut self) {
+        self.server.abort();
+    }
+}
@@ -0,0 +1,252 @@
+//! Detection of the language of received emails, used to select the language of the reply, see
+//! [`Language`].
+
+use chrono::{Datelike, NaiveDateTime, Weekday};
+use serde::{Deserialize, Serialize};
+
+/// A language that replies can be sent in.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Language {
+    /// English (`en`).
+    English,
+    /// French (`fr`).
+    French,
+    /// German (`de`).
+    German,
+    /// Spanish (`es`).
+    Spanish,
+}
+
+impl Default for Language {
+    fn default() -> Self {
+        Self::English
+    }
+}
+
+/// Minimum number of [`Language::stopwords()`] that need to be present in a text to detect its
+/// language.
+const MIN_STOPWORDS: usize = 2;
+
+impl Language {
+    /// All the supported languages.
+    pub const ALL: &'static [Self] = &[Self::English, Self::French, Self::German, Self::Spanish];
+
+    /// The ISO 639-1 code for this language.
+    #[must_use]
+    pub fn code(self) -> &'static str {
+        match self {
+            Self::English => "en",
+            Self::French => "fr",
+            Self::German => "de",
+            Self::Spanish => "es",
+        }
+    }
+
+    /// The language for a language `tag` (e.g. `fr-CH`), `None` if it is not supported.
+    #[must_use]
+    pub fn from_tag(tag: &str) -> Option<Self> {
+        let primary = tag.trim().split(['-', '_']).next()?.to_lowercase();
+        Self::ALL
+            .iter()
+            .copied()
+            .find(|language| language.code() == primary)
+    }
+
+    /// Common words which are distinctive of this language.
+    fn stopwords(self) -> &'static [&'static str] {
+        match self {
+            Self::English => &[
+                "the", "and", "please", "thanks", "thank", "you", "for", "with", "what", "is",
+                "weather",
+            ],
+            Self::French => &[
+                "le", "la", "les", "et", "merci", "pour", "avec", "est", "une", "météo", "vous",
+            ],
+            Self::German => &[
+                "der", "die", "das", "und", "danke", "bitte", "für", "mit", "ist", "wetter", "ich",
+            ],
+            Self::Spanish => &[
+                "el", "los", "las", "y", "gracias", "por", "para", "con", "es", "tiempo", "favor",
+            ],
+        }
+    }
+
+    /// Detect the language of an email, from its `Content-Language` header (if present), or
+    /// otherwise from the words in its `text`. Returns `None` if the language could not be
+    /// detected.
+    #[must_use]
+    pub fn detect(content_language: Option<&str>, text: &str) -> Option<Self> {
+        if let Some(language) = content_language
+            .and_then(|header| header.split(',').next())
+            .and_then(Self::from_tag)
+        {
+            return Some(language);
+        }
+
+        let words: Vec<String> = text
+            .split(|c: char| !c.is_alphabetic())
+            .filter(|word| !word.is_empty())
+            .map(str::to_lowercase)
+            .collect();
+        let mut counts: Vec<(Self, usize)> = Self::ALL
+            .iter()
+            .map(|language| {
+                let stopwords = language.stopwords();
+                let count = words
+                    .iter()
+                    .filter(|word| stopwords.contains(&word.as_str()))
+                    .count();
+                (*language, count)
+            })
+            .collect();
+        counts.sort_by(|(_, a), (_, b)| b.cmp(a));
+        match counts.as_slice() {
+            [(language, first), (_, second), ..] if *first >= MIN_STOPWORDS && first > second => {
+                Some(*language)
+            }
+            _ => None,
+        }
+    }
+
+    /// Abbreviated name of the `weekday`.
+    fn weekday_abbreviation(self, weekday: Weekday) -> &'static str {
+        let names = match self {
+            Self::English => ["Mon", "Tue", "Wed", "Thu", "Fri", "Sat", "Sun"],
+            Self::French => ["lun.", "mar.", "mer.", "jeu.", "ven.", "sam.", "dim."],
+            Self::German => ["Mo.", "Di.", "Mi.", "Do.", "Fr.", "Sa.", "So."],
+            Self::Spanish => ["lun.", "mar.", "mié.", "jue.", "vie.", "sáb.", "dom."],
+        };
+        names[weekday.num_days_from_monday() as usize]
+    }
+
+    /// Abbreviated name of the `month` (starting from 1).
+    fn month_abbreviation(self, month: u32) -> &'static str {
+        let names = match self {
+            Self::English => [
+                "Jan", "Feb", "Mar", "Apr", "May", "Jun", "Jul", "Aug", "Sep", "Oct", "Nov", "Dec",
+            ],
+            Self::French => [
+                "janv.", "févr.", "mars", "avr.", "mai", "juin", "juil.", "août", "sept.", "oct.",
+                "nov.", "déc.",
+            ],
+            Self::German => [
+                "Jan.", "Feb.", "März", "Apr.", "Mai", "Juni", "Juli", "Aug.", "Sept.", "Okt.",
+                "Nov.", "Dez.",
+            ],
+            Self::Spanish => [
+                "ene.", "feb.", "mar.", "abr.", "may.", "jun.", "jul.", "ago.", "sept.", "oct.",
+                "nov.", "dic.",
+            ],
+        };
+        names[month as usize - 1]
+    }
+
+    /// Format a (local) forecast `time` for the long format, with the day of the week, the date
+    /// and the time of day, using a 12 hour clock in English and a 24 hour clock otherwise, e.g.
+    /// `Sat 3 Dec 9:00 PM` or `Sa. 3. Dez. 21:00`.
+    #[must_use]
+    pub fn format_time(self, time: NaiveDateTime) -> String {
+        let weekday = self.weekday_abbreviation(time.weekday());
+        let month = self.month_abbreviation(time.month());
+        let day = time.day();
+        match self {
+            Self::English => format!("{weekday} {day} {month} {}", time.format("%-I:%M %p")),
+            Self::German => format!("{weekday} {day}. {month} {}", time.format("%H:%M")),
+            Self::French | Self::Spanish => {
+                format!("{weekday} {day} {month} {}", time.format("%H:%M"))
+            }
+        }
+    }
+
+    /// Reply when the request did not specify a position.
+    #[must_use]
+    pub fn no_position(self) -> &'static str {
+        match self {
+            Self::English => "No forecast position specified",
+            Self::French => "Aucune position de prévision spécifiée",
+            Self::German => "Keine Vorhersageposition angegeben",
+            Self::Spanish => "No se especificó la posición del pronóstico",
+        }
+    }
+
+    /// Suffix of the reply when a service is temporarily unavailable.
+    #[must_use]
+    pub fn try_again_later(self) -> &'static str {
+        match self {
+            Self::English => "please try again later",
+            Self::French => "veuillez réessayer plus tard",
+            Self::German => "bitte versuchen Sie es später erneut",
+            Self::Spanish => "por favor, inténtelo más tarde",
+        }
+    }
+
+    /// Reply when the service is receiving more requests than it can process, followed by
+    /// [`Language::try_again_later()`].
+    #[must_use]
+    pub fn busy(self) -> &'static str {
+        match self {
+            Self::English => "The service is busy",
+            Self::French => "Le service est surchargé",
+            Self::German => "Der Dienst ist überlastet",
+            Self::Spanish => "El servicio está ocupado",
+        }
+    }
+
+    /// Reply when an unexpected error occurred.
+    #[must_use]
+    pub fn unexpected_error(self) -> &'static str {
+        match self {
+            Self::English => "An error occurred while processing your request",
+            Self::French => "Une erreur s'est produite lors du traitement de votre demande",
+            Self::German => "Bei der Bearbeitung Ihrer Anfrage ist ein Fehler aufgetreten",
+            Self::Spanish => "Se produjo un error al procesar su solicitud",
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Language;
+
+    #[test]
+    fn test_from_tag() {
+        assert_eq!(Some(Language::French), Language::from_tag("fr-CH"));
+        assert_eq!(Some(Language::German), Language::from_tag("DE"));
+        assert_eq!(None, Language::from_tag("ja"));
+    }
+
+    #[test]
+    fn test_format_time() {
+        let time = "2022-12-03T21:00:00".parse().unwrap();
+        assert_eq!("Sat 3 Dec 9:00 PM", Language::English.format_time(time));
+        assert_eq!("sam. 3 déc. 21:00", Language::French.format_time(time));
+        assert_eq!("Sa. 3. Dez. 21:00", Language::German.format_time(time));
+        assert_eq!("sáb. 3 dic. 21:00", Language::Spanish.format_time(time));
+        let time = "2022-05-01T09:00:00".parse().unwrap();
+        assert_eq!("Sun 1 May 9:00 AM", Language::English.format_time(time));
+        assert_eq!("So. 1. Mai 09:00", Language::German.format_time(time));
+    }
+
+    #[test]
+    fn test_detect_content_language() {
+        assert_eq!(
+            Some(Language::Spanish),
+            Language::detect(Some("es-AR, en"), "the weather please")
+        );
+    }
+
+    #[test]
+    fn test_detect_text() {
+        assert_eq!(
+            Some(Language::German),
+            Language::detect(None, "-43.5,170.3 ML\nDanke für das Wetter")
+        );
+        assert_eq!(
+            Some(Language::French),
+            Language::detect(None, "Merci pour la météo")
+        );
+        assert_eq!(None, Language::detect(None, "-43.5,170.3 ML"));
+        assert_eq!(None, Language::detect(None, "Forecast"));
+    }
+}
@@ -0,0 +1,197 @@
+//! Histograms of the time spent in each stage of processing a request, so that performance
+//! regressions (e.g. a slow forecast service) are visible per stage, see [`Latency`].
+//!
+//! The histograms are exported in the
+//! [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/) by
+//! [`metrics_routes()`], along with the restarts of each supervised task (see
+//! [`Supervisor::prometheus()`]).
+
+use std::sync::atomic::{AtomicU64, Ordering};
+
+use axum::{http::header, response::IntoResponse, routing::get, Router};
+use chrono::Duration;
+
+use crate::supervisor::Supervisor;
+
+/// A stage of processing a request.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Stage {
+    /// From fetching the email from the IMAP server until it is placed in the process queue.
+    Receive,
+    /// Time spent waiting in the process queue.
+    Queue,
+    /// Obtaining the forecast (and any additional information requested) from the external
+    /// services.
+    Forecast,
+    /// Formatting the forecast into the reply.
+    Format,
+    /// From the reply being placed in the reply queue until it has been sent.
+    Reply,
+}
+
+impl Stage {
+    /// All the stages, in the order in which they occur.
+    pub const ALL: [Stage; 5] = [
+        Stage::Receive,
+        Stage::Queue,
+        Stage::Forecast,
+        Stage::Format,
+        Stage::Reply,
+    ];
+
+    /// Name of the stage, used as the `stage` label of the exported histograms.
+    #[must_use]
+    pub fn name(self) -> &'static str {
+        match self {
+            Stage::Receive => "receive",
+            Stage::Queue => "queue",
+            Stage::Forecast => "forecast",
+            Stage::Format => "format",
+            Stage::Reply => "reply",
+        }
+    }
+
+    fn index(self) -> usize {
+        self as usize
+    }
+}
+
+/// Upper bounds (inclusive, in milliseconds) of the histogram buckets. Durations greater than the
+/// last bound are only counted in the implicit `+Inf` bucket.
+const BUCKET_BOUNDS_MS: [u64; 12] = [
+    10, 50, 100, 250, 500, 1_000, 2_500, 5_000, 10_000, 30_000, 60_000, 300_000,
+];
+
+/// A histogram of durations with fixed buckets, see [`BUCKET_BOUNDS_MS`].
+#[derive(Debug, Default)]
+struct Histogram {
+    /// Number of durations which fall in each bucket (not cumulative).
+    buckets: [AtomicU64; BUCKET_BOUNDS_MS.len()],
+    /// Total number of durations recorded.
+    count: AtomicU64,
+    /// Sum of the durations recorded, in milliseconds.
+    sum_ms: AtomicU64,
+}
+
+impl Histogram {
+    fn record(&self, duration_ms: u64) {
+        if let Some(bucket) = BUCKET_BOUNDS_MS
+            .iter()
+            .position(|bound| duration_ms <= *bound)
+        {
+            self.buckets[bucket].fetch_add(1, Ordering::Relaxed);
+        }
+        self.count.fetch_add(1, Ordering::Relaxed);
+        self.sum_ms.fetch_add(duration_ms, Ordering::Relaxed);
+    }
+}
+
+/// Histograms of the time spent in each [`Stage`], recorded by the receive, process and reply
+/// tasks.
+#[derive(Debug, Default)]
+pub struct Latency {
+    histograms: [Histogram; Stage::ALL.len()],
+}
+
+impl Latency {
+    /// Construct a new [`Latency`] with empty histograms.
+    #[must_use]
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Record that `duration` was spent in `stage`. Negative durations (e.g. caused by the clock
+    /// being adjusted) are recorded as zero.
+    pub fn record(&self, stage: Stage, duration: Duration) {
+        let duration_ms = u64::try_from(duration.num_milliseconds()).unwrap_or(0);
+        self.histograms[stage.index()].record(duration_ms);
+    }
+
+    /// Number of durations recorded for `stage`.
+    #[must_use]
+    pub fn count(&self, stage: Stage) -> u64 {
+        self.histograms[stage.index()].count.load(Ordering::Relaxed)
+    }
+
+    /// Render the histograms in the Prometheus text format.
+    #[must_use]
+    #[allow(clippy::cast_precision_loss)]
+    pub fn prometheus(&self) -> String {
+        const NAME: &str = "email_weather_stage_duration_seconds";
+        let mut output = format!(
+            "# HELP {NAME} Time spent in each stage of processing a request.\n\
+            # TYPE {NAME} histogram\n"
+        );
+        for stage in Stage::ALL {
+            let histogram = &self.histograms[stage.index()];
+            let stage = stage.name();
+            let mut cumulative = 0;
+            for (bound, bucket) in BUCKET_BOUNDS_MS.iter().zip(&histogram.buckets) {
+                cumulative += bucket.load(Ordering::Relaxed);
+                let le = *bound as f64 / 1000.0;
+                output.push_str(&format!(
+                    "{NAME}_bucket{{stage=\"{stage}\",le=\"{le}\"}} {cumulative}\n"
+                ));
+            }
+            let count = histogram.count.load(Ordering::Relaxed);
+            let sum = histogram.sum_ms.load(Ordering::Relaxed) as f64 / 1000.0;
+            output.push_str(&format!(
+                "{NAME}_bucket{{stage=\"{stage}\",le=\"+Inf\"}} {count}\n\
+                {NAME}_sum{{stage=\"{stage}\"}} {sum}\n\
+                {NAME}_count{{stage=\"{stage}\"}} {count}\n"
+            ));
+        }
+        output
+    }
+}
+
+/// Serve the histograms recorded in `latency`, and the task restarts counted by `supervisor`, in
+/// the Prometheus text format.
+pub fn metrics_routes(latency: &'static Latency, supervisor: &'static Supervisor) -> Router {
+    Router::new().route(
+        "/",
+        get(move || async move {
+            (
+                [(header::CONTENT_TYPE, "text/plain; version=0.0.4")],
+                latency.prometheus() + &supervisor.prometheus(),
+            )
+                .into_response()
+        }),
+    )
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use super::{Latency, Stage};
+
+    #[test]
+    fn test_record_prometheus() {
+        let latency = Latency::new();
+        latency.record(Stage::Forecast, Duration::milliseconds(40));
+        latency.record(Stage::Forecast, Duration::milliseconds(1_500));
+        latency.record(Stage::Forecast, Duration::minutes(10));
+        latency.record(Stage::Reply, Duration::milliseconds(-5));
+        assert_eq!(3, latency.count(Stage::Forecast));
+        assert_eq!(1, latency.count(Stage::Reply));
+        assert_eq!(0, latency.count(Stage::Receive));
+
+        let output = latency.prometheus();
+        let expected = [
+            "# TYPE email_weather_stage_duration_seconds histogram",
+            r#"email_weather_stage_duration_seconds_bucket{stage="forecast",le="0.01"} 0"#,
+            r#"email_weather_stage_duration_seconds_bucket{stage="forecast",le="0.05"} 1"#,
+            r#"email_weather_stage_duration_seconds_bucket{stage="forecast",le="2.5"} 2"#,
+            r#"email_weather_stage_duration_seconds_bucket{stage="forecast",le="300"} 2"#,
+            r#"email_weather_stage_duration_seconds_bucket{stage="forecast",le="+Inf"} 3"#,
+            r#"email_weather_stage_duration_seconds_sum{stage="forecast"} 601.54"#,
+            r#"email_weather_stage_duration_seconds_count{stage="forecast"} 3"#,
+            r#"email_weather_stage_duration_seconds_bucket{stage="reply",le="0.01"} 1"#,
+            r#"email_weather_stage_duration_seconds_count{stage="receive"} 0"#,
+        ];
+        for line in expected {
+            assert!(output.lines().any(|l| l == line), "{line}\n{output}");
+        }
+    }
+}
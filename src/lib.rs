@@ -4,23 +4,143 @@
 #![warn(clippy::pedantic)]
 #![allow(clippy::missing_errors_doc)]
 
+#[cfg(feature = "server")]
+pub mod about;
+#[cfg(feature = "server")]
+pub mod admin_api;
+#[cfg(feature = "server")]
+pub mod analytics;
+#[cfg(feature = "server")]
+pub mod api;
+#[cfg(feature = "server")]
+pub mod aprs;
+#[cfg(feature = "server")]
+pub mod archive;
+#[cfg(feature = "server")]
+pub mod audit;
+#[cfg(feature = "server")]
+pub mod auth;
+#[cfg(feature = "server")]
+pub mod aviation_weather;
+#[cfg(feature = "server")]
+pub mod back_pressure;
+#[cfg(all(test, feature = "server"))]
+mod cassette;
+#[cfg(feature = "server")]
+pub mod changes;
+#[cfg(feature = "server")]
+pub mod check_config;
+#[cfg(feature = "server")]
+pub mod circuit_breaker;
+#[cfg(feature = "server")]
+pub mod client_ip;
+#[cfg(feature = "server")]
+pub mod confirmation;
+#[cfg(feature = "server")]
+pub mod control;
+#[cfg(feature = "server")]
+pub mod correlation;
+#[cfg(feature = "server")]
+pub mod duplicate;
+#[cfg(feature = "server")]
 pub mod email;
+#[cfg(feature = "server")]
+pub mod encryption;
+#[cfg(feature = "server")]
+pub mod feed;
+#[cfg(feature = "server")]
+pub mod flood;
+#[cfg(feature = "server")]
+pub mod flying;
+#[cfg(feature = "server")]
+pub mod forecast;
+#[cfg(feature = "server")]
 pub mod forecast_service;
+#[cfg(feature = "server")]
+pub mod forget;
+pub mod format;
+#[cfg(feature = "server")]
 pub mod fs;
+#[cfg(feature = "server")]
+pub mod generate_config;
 pub mod gis;
+#[cfg(feature = "server")]
+pub mod grib;
+#[cfg(feature = "server")]
+pub mod healthcheck;
+#[cfg(feature = "server")]
+pub mod html;
+#[cfg(feature = "server")]
 pub mod inreach;
+#[cfg(all(test, feature = "server"))]
+mod integration;
+pub mod language;
+#[cfg(feature = "server")]
+pub mod latency;
+#[cfg(feature = "server")]
+pub mod matrix;
+#[cfg(feature = "server")]
 pub mod oauth2;
+#[cfg(feature = "server")]
 pub mod options;
+#[cfg(feature = "server")]
+pub mod past_weather;
+#[cfg(feature = "server")]
 pub mod plain;
+#[cfg(feature = "server")]
 pub mod process;
+#[cfg(feature = "server")]
+pub mod profile;
+#[cfg(feature = "server")]
+pub mod queue;
+#[cfg(feature = "server")]
+pub mod quota;
+#[cfg(feature = "server")]
+pub mod rate_limit;
+#[cfg(feature = "server")]
 pub mod receive;
+#[cfg(feature = "server")]
+pub mod redact;
+#[cfg(all(test, feature = "server"))]
+mod replay;
+#[cfg(feature = "server")]
 pub mod reply;
+#[cfg(feature = "server")]
 pub mod reporting;
 pub mod request;
+#[cfg(feature = "server")]
 pub mod retry;
+#[cfg(feature = "server")]
 pub mod secrets;
+#[cfg(feature = "server")]
 pub mod serve_http;
+#[cfg(feature = "server")]
 pub mod smtp;
+#[cfg(feature = "server")]
+pub mod snow_outlook;
+#[cfg(feature = "server")]
+pub mod solar;
+#[cfg(feature = "server")]
+pub mod startup;
+#[cfg(feature = "server")]
+pub mod status;
+#[cfg(feature = "server")]
+pub mod store;
+#[cfg(feature = "server")]
+pub mod subject;
+#[cfg(feature = "server")]
+pub mod supervisor;
+#[cfg(feature = "server")]
 pub mod task;
+#[cfg(feature = "server")]
 pub mod time;
+#[cfg(feature = "server")]
 pub mod topo_data_service;
+#[cfg(feature = "server")]
+pub mod twilio;
+#[cfg(feature = "wasm-bindgen")]
+pub mod wasm;
+#[cfg(feature = "server")]
+pub mod watchdog;
+#[cfg(feature = "server")]
+pub mod webhook;
@@ -1,26 +1,313 @@
-use std::sync::Arc;
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
 use email_weather::{
+    analytics::Analytics,
+    api,
+    aprs::serve_aprs,
+    archive::{self, EmailArchive},
+    auth::{hash_password, PasswordHashAlgorithm},
+    aviation_weather,
+    changes::PreviousForecasts,
+    check_config::check_config,
+    circuit_breaker::CircuitBreakers,
+    confirmation::SenderConfirmations,
+    control::Controls,
+    encryption::PublicKeys,
+    feed::Feeds,
+    flood,
+    forecast::ForecastService,
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle},
     fs,
+    generate_config::generate_config,
+    gis::Position,
+    healthcheck::healthcheck,
+    latency::Latency,
+    matrix::{self, serve_matrix},
     oauth2::RedirectParameters,
-    options::{self, Options},
+    options::{self, DynamicOptions, Options},
     process::process_emails,
+    queue::QueueCipher,
+    quota::Quotas,
     receive::receive_emails,
+    redact,
     reply::send_replies,
     reporting,
-    secrets::Secrets,
-    serve_http, time,
+    request::ForecastRequest,
+    secrets::{self, Secrets},
+    serve_http, startup,
+    status::ServiceStatus,
+    supervisor::Supervisor,
+    task::TaskContext,
+    time::{self, Port},
+    topo_data_service,
+    twilio::{self, Twilio},
+    watchdog::watchdog,
+    webhook::Webhooks,
 };
 use eyre::Context;
 use tokio::{
     signal::unix::SignalKind,
-    sync::{broadcast, mpsc},
+    sync::{broadcast, mpsc, watch},
 };
 use tracing_appender::rolling::Rotation;
 
+/// Command specified as the first command line argument.
+enum Command {
+    /// Run the service (default).
+    Run,
+    /// Validate the options and secrets, then exit.
+    CheckConfig,
+    /// Print the JSON schema for the options file, then exit.
+    ConfigSchema,
+    /// Write a sample options file with all the defaults filled in to the specified path
+    /// (default `options.ron`), then exit.
+    GenerateConfig(Option<PathBuf>),
+    /// Obtain and print a forecast, then exit.
+    Forecast(ForecastArgs),
+    /// Check the health of the service running locally, then exit (with a non-zero status if it
+    /// is unhealthy).
+    Healthcheck,
+    /// Reinject the archived RFC822 message at the specified path into the process queue, then
+    /// exit.
+    Replay(PathBuf),
+    /// Prompt for a password and print its hash for the `ADMIN_PASSWORD_HASH` secret using the
+    /// specified algorithm (default `argon2id`), then exit.
+    HashPassword(PasswordHashAlgorithm),
+}
+
+impl Command {
+    fn from_args() -> eyre::Result<Self> {
+        match std::env::args().nth(1).as_deref() {
+            None => Ok(Self::Run),
+            Some("check-config") => Ok(Self::CheckConfig),
+            Some("config-schema") => Ok(Self::ConfigSchema),
+            Some("generate-config") => Ok(Self::GenerateConfig(
+                std::env::args().nth(2).map(PathBuf::from),
+            )),
+            Some("forecast") => Ok(Self::Forecast(ForecastArgs::parse(
+                std::env::args().skip(2),
+            )?)),
+            Some("healthcheck") => Ok(Self::Healthcheck),
+            Some("replay") => Ok(Self::Replay(
+                std::env::args()
+                    .nth(2)
+                    .map(PathBuf::from)
+                    .ok_or_else(|| eyre::eyre!("replay requires the path of a message file"))?,
+            )),
+            Some("hash-password") => Ok(Self::HashPassword(
+                std::env::args()
+                    .nth(2)
+                    .map(|algorithm| algorithm.parse())
+                    .transpose()?
+                    .unwrap_or(PasswordHashAlgorithm::Argon2id),
+            )),
+            Some(unknown) => Err(eyre::eyre!(
+                "Unknown command {:?}, expected one of: check-config, config-schema, \
+                generate-config, forecast, healthcheck, replay, hash-password",
+                unknown
+            )),
+        }
+    }
+}
+
+/// Arguments for [`Command::Forecast`], e.g.
+/// `forecast --lat -43.5 --lon 170.3 --format long --profile alpine`.
+struct ForecastArgs {
+    position: Position,
+    /// `None` uses the format of the profile, or the default format from the options.
+    format: Option<FormatForecastOptions>,
+    profile: Option<String>,
+}
+
+impl ForecastArgs {
+    fn parse(mut args: impl Iterator<Item = String>) -> eyre::Result<Self> {
+        let mut latitude: Option<f32> = None;
+        let mut longitude: Option<f32> = None;
+        let mut format: Option<FormatForecastOptions> = None;
+        let mut profile: Option<String> = None;
+
+        while let Some(arg) = args.next() {
+            let mut value = || {
+                args.next()
+                    .ok_or_else(|| eyre::eyre!("Expected a value for {}", arg))
+            };
+            match arg.as_str() {
+                "--lat" => latitude = Some(value()?.parse().wrap_err("Invalid --lat")?),
+                "--lon" => longitude = Some(value()?.parse().wrap_err("Invalid --lon")?),
+                "--format" => {
+                    let style = match value()?.as_str() {
+                        "short" => None,
+                        "long" => Some(LongFormatStyle::PlainText),
+                        "html" => Some(LongFormatStyle::Html),
+                        unknown => {
+                            return Err(eyre::eyre!(
+                                "Unknown format {:?}, expected one of: short, long, html",
+                                unknown
+                            ))
+                        }
+                    };
+                    let detail = match style {
+                        None => FormatDetail::default(),
+                        Some(style) => FormatDetail::Long(LongFormatDetail { style: Some(style) }),
+                    };
+                    format = Some(FormatForecastOptions {
+                        detail,
+                        ..FormatForecastOptions::default()
+                    });
+                }
+                "--profile" => profile = Some(value()?),
+                unknown => {
+                    return Err(eyre::eyre!(
+                        "Unknown argument {:?}, expected one of: --lat, --lon, --format, --profile",
+                        unknown
+                    ))
+                }
+            }
+        }
+
+        let latitude = latitude.ok_or_else(|| eyre::eyre!("--lat is required"))?;
+        let longitude = longitude.ok_or_else(|| eyre::eyre!("--lon is required"))?;
+        api::validate_position(latitude, longitude).map_err(|error| eyre::eyre!(error))?;
+
+        Ok(Self {
+            position: Position::new(latitude, longitude),
+            format,
+            profile,
+        })
+    }
+}
+
 #[tokio::main]
 async fn main() -> eyre::Result<()> {
     reporting::setup_error_hooks()?;
+    match Command::from_args()? {
+        Command::Run => run().await,
+        Command::CheckConfig => run_check_config().await,
+        Command::ConfigSchema => {
+            println!("{}", Options::json_schema()?);
+            Ok(())
+        }
+        Command::GenerateConfig(path) => run_generate_config(path),
+        Command::Forecast(args) => run_forecast(args).await,
+        Command::Healthcheck => run_healthcheck().await,
+        Command::Replay(path) => run_replay(&path).await,
+        Command::HashPassword(algorithm) => run_hash_password(algorithm),
+    }
+}
+
+fn run_hash_password(algorithm: PasswordHashAlgorithm) -> eyre::Result<()> {
+    let password = rpassword::prompt_password("Enter password to be hashed: ")?;
+    if password.is_empty() {
+        return Err(eyre::eyre!("Password must not be empty"));
+    }
+    if rpassword::prompt_password("Confirm password: ")? != password {
+        return Err(eyre::eyre!("Passwords do not match"));
+    }
+    println!("{}", hash_password(&password, algorithm)?);
+    Ok(())
+}
+
+fn run_generate_config(path: Option<PathBuf>) -> eyre::Result<()> {
+    let path = path.unwrap_or_else(|| PathBuf::from("options.ron"));
+    if path.exists() {
+        return Err(eyre::eyre!(
+            "{:?} already exists, remove it or specify a different path",
+            path
+        ));
+    }
+    std::fs::write(&path, generate_config()?)
+        .wrap_err_with(|| format!("Error writing options file {:?}", path))?;
+    println!("Wrote sample options to {:?}", path);
+    Ok(())
+}
+
+async fn run_forecast(args: ForecastArgs) -> eyre::Result<()> {
+    let options_init = options::Options::initialize().await;
+    let options = options_init.result.map_err(|error| {
+        options_init.logs.print();
+        error
+    })?;
+    let http_client = reqwest::Client::new();
+    let provider = options.secrets_backend.provider(http_client.clone()).await;
+    let api_keys = topo_data_service::ApiKeys::initialize(&*provider, &options.secrets_dir).await?;
+    let forecast_service =
+        forecast_service::Failover::from_options(&http_client, &options.forecast, &time::Gateway);
+    let topo_data_service =
+        topo_data_service::Failover::from_options(&http_client, &options.topo_data, &api_keys);
+    let options = DynamicOptions::from(&options);
+    let service = ForecastService::new(
+        &time::Gateway,
+        &forecast_service,
+        Some(&topo_data_service),
+        &options,
+    );
+
+    let forecast = service
+        .forecast(&ForecastRequest {
+            position: Some(args.position),
+            format: args.format,
+            profile: args.profile,
+            ..ForecastRequest::default()
+        })
+        .await?;
+    println!("{}", forecast.html_message.unwrap_or(forecast.message));
+    Ok(())
+}
+
+async fn run_healthcheck() -> eyre::Result<()> {
+    let options_init = options::Options::initialize().await;
+    let options = options_init.result.map_err(|error| {
+        options_init.logs.print();
+        error
+    })?;
+    println!("{}", healthcheck(&options).await?);
+    Ok(())
+}
+
+async fn run_replay(path: &Path) -> eyre::Result<()> {
+    let options_init = options::Options::initialize().await;
+    let options = options_init.result.map_err(|error| {
+        options_init.logs.print();
+        error
+    })?;
+    let provider = options
+        .secrets_backend
+        .provider(reqwest::Client::new())
+        .await;
+    let queue_cipher = secrets::optional_secret(&*provider, &options.secrets_dir, "QUEUE_KEY")
+        .await?
+        .map(|queue_key| QueueCipher::new(&queue_key))
+        .transpose()?
+        .map(|queue_cipher| &*Box::leak(Box::new(queue_cipher)));
+    redact::init_identity_key(&secrets::identity_key(&*provider, &options.secrets_dir).await?)?;
+    let request_id = archive::replay(path, &options, queue_cipher).await?;
+    println!(
+        "Message {:?} queued for processing as request {}",
+        path, request_id
+    );
+    Ok(())
+}
+
+async fn run_check_config() -> eyre::Result<()> {
+    let options_init = options::Options::initialize().await;
+    options_init.logs.print();
+    let options = options_init.result?;
+
+    let report = check_config(&options, reqwest::Client::new()).await;
+    print!("{}", report);
+    if report.is_ok() {
+        println!("Configuration is valid");
+        Ok(())
+    } else {
+        Err(eyre::eyre!("Configuration is invalid"))
+    }
+}
+
+async fn run() -> eyre::Result<()> {
     let options_init = options::Options::initialize().await;
     let options: &'static Options = options_init
         .result
@@ -37,9 +324,101 @@ async fn main() -> eyre::Result<()> {
             error
         })?;
 
+    let analytics: Option<&'static Analytics> = if options.analytics {
+        let path = options.data_dir.join("analytics.sqlite");
+        let analytics = Analytics::open(&path).map_err(|error| {
+            options_init.logs.print();
+            error
+        })?;
+        Some(Box::leak(Box::new(analytics)))
+    } else {
+        None
+    };
+
+    let confirmations: Option<&'static SenderConfirmations> = if options.confirm_senders {
+        let path = options.data_dir.join("senders.sqlite");
+        let confirmations = SenderConfirmations::open(&path).map_err(|error| {
+            options_init.logs.print();
+            error
+        })?;
+        Some(Box::leak(Box::new(confirmations)))
+    } else {
+        None
+    };
+
+    let feeds: Option<&'static Feeds> = if options.feeds {
+        let path = options.data_dir.join("feeds.sqlite");
+        let feeds = Feeds::open(&path, options.base_url.clone()).map_err(|error| {
+            options_init.logs.print();
+            error
+        })?;
+        Some(Box::leak(Box::new(feeds)))
+    } else {
+        None
+    };
+
+    let previous_forecasts: Option<&'static PreviousForecasts> = if options.forecast_changes {
+        let path = options.data_dir.join("forecasts.sqlite");
+        let previous_forecasts = PreviousForecasts::open(&path).map_err(|error| {
+            options_init.logs.print();
+            error
+        })?;
+        Some(Box::leak(Box::new(previous_forecasts)))
+    } else {
+        None
+    };
+
+    let public_keys: Option<&'static PublicKeys> = if options.encrypted_replies {
+        let path = options.data_dir.join("keys.sqlite");
+        let public_keys = PublicKeys::open(&path).map_err(|error| {
+            options_init.logs.print();
+            error
+        })?;
+        Some(Box::leak(Box::new(public_keys)))
+    } else {
+        None
+    };
+
+    let archive: Option<&'static EmailArchive> = match &options.email_archive {
+        Some(archive_options) => {
+            let archive = EmailArchive::new(&options.email_archive_dir(), archive_options.clone())
+                .map_err(|error| {
+                    options_init.logs.print();
+                    error
+                })?;
+            Some(Box::leak(Box::new(archive)))
+        }
+        None => None,
+    };
+
+    let time: &'static time::Gateway = Box::leak(Box::new(time::Gateway));
+    let supervisor: &'static Supervisor =
+        Box::leak(Box::new(Supervisor::new(options.task_backoff, time)));
+
+    // Loaded before logging is set up (and before the other secrets), so that errors while
+    // initializing are also reported.
+    let sentry_provider = options
+        .secrets_backend
+        .provider(reqwest::Client::new())
+        .await;
+    let sentry_dsn =
+        secrets::optional_secret(&*sentry_provider, &options.secrets_dir, "SENTRY_DSN")
+            .await
+            .map_err(|error| {
+                options_init.logs.print();
+                error
+            })?;
+
     let reporting_options: &'static reporting::Options = Box::leak(Box::new(reporting::Options {
         data_dir: options.data_dir.clone(),
         log_rotation: Rotation::DAILY,
+        log_retention: options.log_retention.clone(),
+        prune_stats: reporting::PruneStats::default(),
+        sanitized_options: options.sanitized().ok(),
+        supervisor,
+        analytics,
+        sentry: options.sentry.clone(),
+        sentry_dsn,
     }));
 
     let _reporting_guard = reporting::setup_logging(reporting_options).map_err(|error| {
@@ -56,20 +435,47 @@ async fn main() -> eyre::Result<()> {
         )
     })?;
 
-    let time: &'static time::Gateway = Box::leak(Box::new(time::Gateway));
+    let status: &'static ServiceStatus = Box::leak(Box::new(ServiceStatus::new(time.utc_now())));
+    let latency: &'static Latency = Box::leak(Box::new(Latency::new()));
+    let controls: &'static Controls = Box::leak(Box::new(Controls::new()));
+
+    let http_client = reqwest::Client::new();
 
     let secrets = Box::leak(Box::new(
-        Secrets::initialize(&options.secrets_dir)
-            .await
-            .wrap_err("Error while initializing secrets")?,
+        Secrets::initialize(
+            &options.secrets_dir,
+            &options.secrets_backend,
+            http_client.clone(),
+        )
+        .await
+        .wrap_err("Error while initializing secrets")?,
     ));
+    redact::init_identity_key(&secrets.identity_key)?;
 
-    let http_client = reqwest::Client::new();
+    let webhooks: &'static Webhooks = Box::leak(Box::new(Webhooks::new(
+        options.webhooks.clone(),
+        http_client.clone(),
+        time,
+    )));
+
+    let (shutdown_tx, _) = broadcast::channel::<()>(1);
+
+    let (options_tx, options_rx) = watch::channel(DynamicOptions::from(options));
+    if let Some(options_path) = options_init.path.clone() {
+        tokio::spawn(options::watch_file(
+            options_path,
+            options_tx,
+            time,
+            shutdown_tx.subscribe(),
+        ));
+    }
 
-    let (shutdown_tx, emails_receive_shutdown_rx) = broadcast::channel::<()>(1);
-    let emails_process_shutdown_rx = shutdown_tx.subscribe();
-    let send_replies_shutdown_rx = shutdown_tx.subscribe();
-    let serve_http_shutdown_rx = shutdown_tx.subscribe();
+    tokio::spawn(reporting::prune_logs(
+        reporting_options,
+        shutdown_tx.subscribe(),
+        options.task_backoff,
+        time,
+    ));
 
     let (oauth_redirect_tx, oauth_redirect_rx) = mpsc::channel::<RedirectParameters>(1);
 
@@ -97,58 +503,306 @@ async fn main() -> eyre::Result<()> {
             .expect("failed to send shutdown broadcast");
     });
 
-    let process_queue_path = options.data_dir.join("process");
-    let reply_queue_path = options.data_dir.join("reply");
-    let (process_sender, process_receiver) = yaque::channel(&process_queue_path)
-        .wrap_err_with(|| format!("Unable to create process queue at {:?}", process_queue_path))?;
-    let (reply_sender, reply_receiver) = yaque::channel(&reply_queue_path)
-        .wrap_err_with(|| format!("Unable to create reply queue at {:?}", reply_queue_path))?;
+    // The http server is started first, because it is required to receive the OAUTH2 redirect
+    // when the receive and reply tasks authenticate.
+    let (http_ready_tx, http_ready_rx) = startup::ready_channel();
+    let admin_password_hash = secrets.admin_password_hash.as_ref();
+    let api_tokens = secrets.api_tokens.as_ref();
+    let queue_cipher: Option<&'static QueueCipher> = match &secrets.queue_key {
+        Some(queue_key) => {
+            tracing::info!("Queue payloads will be encrypted");
+            Some(Box::leak(Box::new(QueueCipher::new(queue_key)?)))
+        }
+        None => None,
+    };
+    let topo_data_service = topo_data_service::Failover::from_options(
+        &http_client,
+        &options.topo_data,
+        &secrets.topo_data_api_keys,
+    );
+    let forecast_service =
+        forecast_service::Failover::from_options(&http_client, &options.forecast, time);
+    let serve_http_forecast_service = forecast_service.clone();
+    let serve_http_topo_data_service = topo_data_service.clone();
+    let quotas: &'static Quotas = Box::leak(Box::new(Quotas::new(options_rx.clone())));
+    let serve_http_twilio: Option<Twilio> =
+        match (&options.twilio, secrets.twilio_auth_token.as_ref()) {
+            (Some(twilio_options), Some(auth_token)) => Some(Twilio {
+                options: twilio_options.clone(),
+                auth_token,
+                gateway: twilio::Gateway::new(
+                    http_client.clone(),
+                    twilio_options.clone(),
+                    auth_token,
+                ),
+                forecast_service: forecast_service.clone(),
+                topo_data_service: topo_data_service.clone(),
+                aviation_weather: aviation_weather::Gateway::new(
+                    http_client.clone(),
+                    options.aviation_weather.clone(),
+                ),
+                flood: flood::Gateway::new(http_client.clone(), options.flood.clone()),
+                options_rx: options_rx.clone(),
+                quotas,
+                time,
+            }),
+            (Some(_), None) => {
+                return Err(eyre::eyre!(
+                    "The twilio option requires the TWILIO_AUTH_TOKEN secret"
+                ))
+            }
+            (None, _) => None,
+        };
+    // Spawned once, outside the supervised task, so that they are shared by its restarts.
+    let serve_http_handle = axum_server::Handle::new();
+    serve_http::spawn_handle_tasks(&serve_http_handle, shutdown_tx.subscribe(), http_ready_tx);
+    let serve_http_acme = match &options.acme {
+        Some(acme) => {
+            let cache_dir = acme
+                .cache_dir
+                .clone()
+                .unwrap_or_else(|| options.data_dir.join("acme"));
+            Some(serve_http::spawn_acme(&options.base_url, acme, cache_dir)?)
+        }
+        None => None,
+    };
+    let serve_http_task =
+        supervisor.supervise("serve_http", shutdown_tx.clone(), move |_shutdown_rx| {
+            let serve_http_options = serve_http::Options {
+                reporting: reporting_options,
+                admin_password_hash,
+                oauth_redirect_tx: oauth_redirect_tx.clone(),
+                base_url: options.base_url.clone(),
+                listen_address: options.listen_address,
+                forecast_api: options.forecast_api,
+                api_cors_origins: options.api_cors_origins.clone(),
+                api_tokens,
+                metrics: options.metrics,
+                latency,
+                forecast_service: serve_http_forecast_service.clone(),
+                topo_data_service: serve_http_topo_data_service.clone(),
+                time,
+                tls: options.tls.clone(),
+                acme: serve_http_acme.clone(),
+                rate_limit: options.http_rate_limit,
+                max_body_bytes: options.http_max_body_bytes,
+                client_ip_source: options.client_ip_source,
+                status,
+                confirmations,
+                feeds,
+                previous_forecasts,
+                public_keys,
+                controls,
+                handle: serve_http_handle.clone(),
+                twilio: serve_http_twilio.clone(),
+            };
+            Ok(serve_http::serve_http(serve_http_options))
+        });
+    let serve_http_join = tokio::spawn(serve_http_task);
+
+    let data_dir = &options.data_dir;
+    options.queue.validate(data_dir, "process")?;
+    options.queue.validate(data_dir, "reply")?;
 
     let oauth_flow = Arc::new(email_weather::oauth2::setup_flow(
         &secrets.oauth_secrets,
         &options.base_url,
         oauth_redirect_rx,
+        webhooks,
     )?);
 
-    let receive_join = tokio::spawn(receive_emails(
-        emails_receive_shutdown_rx,
-        process_sender,
-        oauth_flow.clone(),
-        options.email_account.email_str(),
-        time,
-    ));
-    let process_join = tokio::spawn(process_emails(
-        process_receiver,
-        reply_sender,
-        emails_process_shutdown_rx,
-        http_client.clone(),
+    let circuit_breakers: &'static CircuitBreakers =
+        Box::leak(Box::new(CircuitBreakers::new(options.circuit_breaker)));
+    let task_context = TaskContext {
+        status,
+        webhooks,
+        circuit_breakers,
+        analytics,
+        confirmations,
+        feeds,
+        previous_forecasts,
+        public_keys,
+        archive,
+        quotas,
+        latency,
+        controls,
+        backoff: options.task_backoff,
         time,
-    ));
-    let reply_join = tokio::spawn(send_replies(
-        reply_receiver,
-        send_replies_shutdown_rx,
-        http_client,
-        &options.email_account,
-        oauth_flow,
-        time,
-    ));
+    };
 
-    let serve_http_options = serve_http::Options {
-        reporting: reporting_options,
-        admin_password_hash: secrets.admin_password_hash.as_ref(),
-        oauth_redirect_tx,
-        base_url: options.base_url.clone(),
-        listen_address: options.listen_address,
+    let process_forecast_service = forecast_service.clone();
+    let process_topo_data_service = topo_data_service.clone();
+    let process_aviation_weather =
+        aviation_weather::Gateway::new(http_client.clone(), options.aviation_weather.clone());
+    let aprs_aviation_weather = process_aviation_weather.clone();
+    let matrix_aviation_weather = process_aviation_weather.clone();
+    let process_flood = flood::Gateway::new(http_client.clone(), options.flood.clone());
+    let aprs_flood = process_flood.clone();
+    let matrix_flood = process_flood.clone();
+    let process_options_rx = options_rx.clone();
+    let process_task = supervisor.supervise("process", shutdown_tx.clone(), move |shutdown_rx| {
+        Ok(process_emails(
+            options
+                .queue
+                .open_receiver(data_dir, "process")?
+                .with_cipher(queue_cipher),
+            options
+                .queue
+                .open_sender(data_dir, "reply")?
+                .with_cipher(queue_cipher),
+            shutdown_rx,
+            process_forecast_service.clone(),
+            process_topo_data_service.clone(),
+            process_aviation_weather.clone(),
+            process_flood.clone(),
+            process_options_rx.clone(),
+            task_context,
+        ))
+    });
+    let process_join = tokio::spawn(process_task);
+
+    let matrix_join = match (&options.matrix, secrets.matrix_access_token.as_ref()) {
+        (Some(matrix_options), Some(access_token)) => {
+            let matrix_gateway = matrix::Gateway::new(
+                http_client.clone(),
+                matrix_options.homeserver_url.clone(),
+                access_token,
+            );
+            let matrix_forecast_service = forecast_service.clone();
+            let matrix_topo_data_service = topo_data_service.clone();
+            let matrix_options_rx = options_rx.clone();
+            let matrix_task =
+                supervisor.supervise("matrix", shutdown_tx.clone(), move |shutdown_rx| {
+                    Ok(serve_matrix(
+                        shutdown_rx,
+                        matrix_options,
+                        matrix_gateway.clone(),
+                        matrix_forecast_service.clone(),
+                        matrix_topo_data_service.clone(),
+                        matrix_aviation_weather.clone(),
+                        matrix_flood.clone(),
+                        matrix_options_rx.clone(),
+                        task_context,
+                    ))
+                });
+            Some(tokio::spawn(matrix_task))
+        }
+        (Some(_), None) => {
+            return Err(eyre::eyre!(
+                "The matrix option requires the MATRIX_ACCESS_TOKEN secret"
+            ))
+        }
+        (None, _) => None,
     };
-    let serve_http_join = tokio::spawn(serve_http::serve_http(
-        serve_http_shutdown_rx,
-        serve_http_options,
-    ));
+
+    let aprs_join = options.aprs.as_ref().map(|aprs_options| {
+        let aprs_options_rx = options_rx.clone();
+        let aprs_task = supervisor.supervise("aprs", shutdown_tx.clone(), move |shutdown_rx| {
+            Ok(serve_aprs(
+                shutdown_rx,
+                aprs_options,
+                forecast_service.clone(),
+                topo_data_service.clone(),
+                aprs_aviation_weather.clone(),
+                aprs_flood.clone(),
+                aprs_options_rx.clone(),
+                task_context,
+            ))
+        });
+        tokio::spawn(aprs_task)
+    });
+
+    if options.dry_run {
+        tracing::warn!(
+            "Dry run is enabled, replies will be archived in {:?} instead of being sent",
+            data_dir.join("dry_run")
+        );
+    }
+    let watchdog_http_client = http_client.clone();
+    let reply_oauth_flow = oauth_flow.clone();
+    let reply_task = supervisor.supervise("reply", shutdown_tx.clone(), move |shutdown_rx| {
+        Ok(send_replies(
+            options
+                .queue
+                .open_receiver(data_dir, "reply")?
+                .with_cipher(queue_cipher),
+            shutdown_rx,
+            http_client.clone(),
+            &options.email_account,
+            reply_oauth_flow.clone(),
+            options.reply_backoff,
+            options.dry_run.then(|| data_dir.join("dry_run")),
+            task_context,
+        ))
+    });
+    let reply_http_ready_rx = http_ready_rx.clone();
+    let reply_shutdown_rx = shutdown_tx.subscribe();
+    let reply_join = tokio::spawn(async move {
+        if reply_http_ready_rx
+            .wait_or_shutdown("http server", reply_shutdown_rx)
+            .await
+        {
+            reply_task.await;
+        }
+    });
+
+    let watchdog_oauth_flow = oauth_flow.clone();
+    let watchdog_task = supervisor.supervise("watchdog", shutdown_tx.clone(), move |shutdown_rx| {
+        Ok(watchdog(
+            shutdown_rx,
+            watchdog_http_client.clone(),
+            &options.email_account,
+            watchdog_oauth_flow.clone(),
+            options.watchdog.clone(),
+            task_context,
+        ))
+    });
+    let watchdog_http_ready_rx = http_ready_rx.clone();
+    let watchdog_shutdown_rx = shutdown_tx.subscribe();
+    let watchdog_join = tokio::spawn(async move {
+        if watchdog_http_ready_rx
+            .wait_or_shutdown("http server", watchdog_shutdown_rx)
+            .await
+        {
+            watchdog_task.await;
+        }
+    });
+
+    let receive_task = supervisor.supervise("receive", shutdown_tx.clone(), move |shutdown_rx| {
+        Ok(receive_emails(
+            shutdown_rx,
+            options
+                .queue
+                .open_sender(data_dir, "process")?
+                .with_cipher(queue_cipher),
+            oauth_flow.clone(),
+            options.email_account.email_str(),
+            options_rx.clone(),
+            options.queue.leader_election()?,
+            task_context,
+        ))
+    });
+    let receive_shutdown_rx = shutdown_tx.subscribe();
+    let receive_join = tokio::spawn(async move {
+        if http_ready_rx
+            .wait_or_shutdown("http server", receive_shutdown_rx)
+            .await
+        {
+            receive_task.await;
+        }
+    });
 
     serve_http_join.await?;
     receive_join.await?;
     process_join.await?;
     reply_join.await?;
+    watchdog_join.await?;
+    if let Some(aprs_join) = aprs_join {
+        aprs_join.await?;
+    }
+    if let Some(matrix_join) = matrix_join {
+        matrix_join.await?;
+    }
 
     Ok(())
 }
@@ -0,0 +1,528 @@
+//! An optional [Matrix](https://matrix.org/) bot, so that clubs can share the deployment in their
+//! chat. The bot joins [`MatrixOptions::rooms`], and replies to messages containing a
+//! `!forecast` command followed by a forecast request (e.g. `!forecast -43.5,170.3 ML`) with the
+//! forecast, see [`serve_matrix()`].
+//!
+//! The [client-server API](https://spec.matrix.org/latest/client-server-api/) is used directly,
+//! authenticated using the access token of the bot's account from the `MATRIX_ACCESS_TOKEN`
+//! secret.
+
+use std::collections::{BTreeMap, HashSet};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use schemars::JsonSchema;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::{
+    aviation_weather,
+    circuit_breaker::CircuitBreakerPort,
+    flood,
+    forecast::{ForecastError, ForecastService},
+    forecast_service,
+    options::DynamicOptions,
+    quota::Quotas,
+    request::ForecastRequest,
+    task::{run_retry_log_errors, TaskContext},
+    topo_data_service,
+};
+
+/// Prefix of the messages which contain a forecast request.
+pub const COMMAND: &str = "!forecast";
+
+/// How long (in milliseconds) the homeserver waits for new events before responding to a sync.
+const SYNC_TIMEOUT_MS: u64 = 30_000;
+
+/// Time allowed for the homeserver to respond to a request, in addition to the sync timeout.
+const REQUEST_TIMEOUT: std::time::Duration = std::time::Duration::from_secs(30);
+
+/// Options for the Matrix bot, specified in [`crate::options::Options::matrix`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct MatrixOptions {
+    /// Base url of the bot account's homeserver, e.g. `https://matrix.org`.
+    pub homeserver_url: url::Url,
+    /// User id of the bot account, e.g. `@weather:example.org`. Messages sent by this user are
+    /// ignored.
+    pub user_id: String,
+    /// Ids or aliases of the rooms that the bot joins and responds in, e.g.
+    /// `#club:example.org`.
+    pub rooms: Vec<String>,
+}
+
+/// Content of an `m.room.message` event.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MessageContent {
+    /// Type of the message, e.g. `m.text`.
+    #[serde(default)]
+    pub msgtype: String,
+    /// Plain text body of the message.
+    #[serde(default)]
+    pub body: String,
+    /// Format of [`MessageContent::formatted_body`], `org.matrix.custom.html`.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub format: Option<String>,
+    /// Html body of the message.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub formatted_body: Option<String>,
+}
+
+/// An event in the timeline of a room.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct RoomEvent {
+    /// Type of the event, e.g. `m.room.message`.
+    #[serde(rename = "type")]
+    pub kind: String,
+    /// User id of the sender.
+    pub sender: String,
+    /// Content of the event, which is a [`MessageContent`] for `m.room.message` events.
+    #[serde(default)]
+    pub content: serde_json::Value,
+}
+
+/// Timeline of a room in a [`SyncResponse`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Timeline {
+    /// New events in the room.
+    #[serde(default)]
+    pub events: Vec<RoomEvent>,
+}
+
+/// A joined room in a [`SyncResponse`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct JoinedRoom {
+    /// New events in the room.
+    #[serde(default)]
+    pub timeline: Timeline,
+}
+
+/// Rooms in a [`SyncResponse`].
+#[derive(Debug, Clone, Default, PartialEq, Deserialize)]
+pub struct Rooms {
+    /// Joined rooms with new events, by room id.
+    #[serde(default)]
+    pub join: BTreeMap<String, JoinedRoom>,
+}
+
+/// Response to a sync request, containing the new events since the previous sync.
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+pub struct SyncResponse {
+    /// Token used to obtain the events after this response.
+    pub next_batch: String,
+    /// Rooms with new events.
+    #[serde(default)]
+    pub rooms: Rooms,
+}
+
+#[derive(Deserialize)]
+struct JoinResponse {
+    room_id: String,
+}
+
+/// Trait used to allow mocking the Matrix homeserver.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait Port: Send + Sync {
+    /// Join the room with the id or alias `room`, returning the room id.
+    async fn join(&self, room: &str) -> eyre::Result<String>;
+    /// Obtain the events since the `since` token, waiting up to `timeout_ms` for new events.
+    /// All the current state is returned if `since` is `None`.
+    async fn sync(&self, since: Option<String>, timeout_ms: u64) -> eyre::Result<SyncResponse>;
+    /// Send a message with `content` to the room with `room_id`.
+    async fn send(&self, room_id: &str, content: &MessageContent) -> eyre::Result<()>;
+}
+
+/// Concrete implementation of [`Port`] using the client-server API.
+#[derive(Clone)]
+pub struct Gateway {
+    http_client: reqwest::Client,
+    homeserver_url: url::Url,
+    access_token: &'static SecretString,
+}
+
+impl Gateway {
+    /// Construct a new [`Gateway`].
+    #[must_use]
+    pub fn new(
+        http_client: reqwest::Client,
+        homeserver_url: url::Url,
+        access_token: &'static SecretString,
+    ) -> Self {
+        Self {
+            http_client,
+            homeserver_url,
+            access_token,
+        }
+    }
+
+    /// Url of the client-server API endpoint with the path `segments`, which are percent encoded.
+    fn url(&self, segments: &[&str]) -> eyre::Result<url::Url> {
+        let mut url = self.homeserver_url.clone();
+        url.path_segments_mut()
+            .map_err(|_| eyre::eyre!("Invalid homeserver url {}", self.homeserver_url))?
+            .pop_if_empty()
+            .extend(["_matrix", "client", "v3"])
+            .extend(segments);
+        Ok(url)
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn join(&self, room: &str) -> eyre::Result<String> {
+        let response: JoinResponse = self
+            .http_client
+            .post(self.url(&["join", room])?)
+            .bearer_auth(self.access_token.expose_secret())
+            .json(&serde_json::json!({}))
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err_with(|| format!("Error joining room {}", room))?
+            .json()
+            .await
+            .wrap_err_with(|| format!("Error parsing response to joining room {}", room))?;
+        Ok(response.room_id)
+    }
+
+    async fn sync(&self, since: Option<String>, timeout_ms: u64) -> eyre::Result<SyncResponse> {
+        let mut request = self
+            .http_client
+            .get(self.url(&["sync"])?)
+            .bearer_auth(self.access_token.expose_secret())
+            .query(&[("timeout", timeout_ms.to_string())])
+            .timeout(std::time::Duration::from_millis(timeout_ms) + REQUEST_TIMEOUT);
+        if let Some(since) = &since {
+            request = request.query(&[("since", since)]);
+        }
+        request
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err("Error syncing with Matrix homeserver")?
+            .json()
+            .await
+            .wrap_err("Error parsing Matrix sync response")
+    }
+
+    async fn send(&self, room_id: &str, content: &MessageContent) -> eyre::Result<()> {
+        let transaction_id = uuid::Uuid::new_v4().to_string();
+        self.http_client
+            .put(self.url(&["rooms", room_id, "send", "m.room.message", &transaction_id])?)
+            .bearer_auth(self.access_token.expose_secret())
+            .json(content)
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err_with(|| format!("Error sending message to room {}", room_id))?;
+        Ok(())
+    }
+}
+
+/// Responds to the `!forecast` commands in the joined rooms, see [`Bot::handle()`].
+#[derive(Debug)]
+pub struct Bot {
+    user_id: String,
+    room_ids: HashSet<String>,
+}
+
+impl Bot {
+    /// Construct a new [`Bot`] for the user `user_id`, which responds in the rooms with
+    /// `room_ids`.
+    #[must_use]
+    pub fn new(user_id: String, room_ids: HashSet<String>) -> Self {
+        Self { user_id, room_ids }
+    }
+
+    /// The request following the [`COMMAND`] in an event, `None` if the event is not a command
+    /// for the bot.
+    fn request<'e>(&self, event: &'e RoomEvent) -> Option<&'e str> {
+        if event.kind != "m.room.message" || event.sender == self.user_id {
+            return None;
+        }
+        let body = event.content.get("body")?.as_str()?.trim();
+        let request = body.strip_prefix(COMMAND)?;
+        (request.is_empty() || request.starts_with(char::is_whitespace)).then(|| request.trim())
+    }
+
+    /// Obtain the forecast for `request` received at `now`.
+    async fn forecast(
+        service: &ForecastService<'_>,
+        quotas: &Quotas,
+        now: DateTime<Utc>,
+        request: &str,
+    ) -> MessageContent {
+        let text = |body: String| MessageContent {
+            msgtype: "m.notice".to_owned(),
+            body,
+            format: None,
+            formatted_body: None,
+        };
+        if let Err(error) = quotas.check(None, now) {
+            tracing::warn!("Request refused: {}", error);
+            return text(format!("{}, please try again later", error));
+        }
+        let (request, errors) = ForecastRequest::parse(request);
+        let errors = errors
+            .into_iter()
+            .map(|error| format!("Error parsing request: {}", error))
+            .collect();
+        match service
+            .forecast_with(&request, None, errors, |format| format)
+            .await
+        {
+            Ok(forecast) => MessageContent {
+                format: forecast
+                    .html_message
+                    .as_ref()
+                    .map(|_| "org.matrix.custom.html".to_owned()),
+                formatted_body: forecast.html_message,
+                ..text(forecast.message)
+            },
+            Err(ForecastError::NoPosition) => text(format!(
+                "Include a position in the request, e.g. {} -43.5,170.3",
+                COMMAND
+            )),
+            Err(error) => {
+                tracing::error!("Error obtaining forecast for Matrix message: {:?}", error);
+                text("Forecast is unavailable, please try again later".to_owned())
+            }
+        }
+    }
+
+    /// Handle the events in a `sync` response received at `now`, returning the messages to send
+    /// in reply, with the id of the room to send each to.
+    pub async fn handle(
+        &self,
+        sync: &SyncResponse,
+        service: &ForecastService<'_>,
+        quotas: &Quotas,
+        now: DateTime<Utc>,
+    ) -> Vec<(String, MessageContent)> {
+        let mut replies = Vec::new();
+        for (room_id, room) in &sync.rooms.join {
+            if !self.room_ids.contains(room_id) {
+                continue;
+            }
+            for event in &room.timeline.events {
+                if let Some(request) = self.request(event) {
+                    tracing::info!("Received Matrix command in {}", room_id);
+                    let content = Self::forecast(service, quotas, now, request).await;
+                    replies.push((room_id.clone(), content));
+                }
+            }
+        }
+        replies
+    }
+}
+
+/// Join the rooms and respond to commands until an error occurs.
+async fn serve_matrix_impl(
+    options: &MatrixOptions,
+    matrix: &dyn Port,
+    forecast_service: &dyn forecast_service::Port,
+    topo_data_service: &dyn topo_data_service::Port,
+    aviation_weather: &dyn aviation_weather::Port,
+    flood: &dyn flood::Port,
+    options_rx: &watch::Receiver<DynamicOptions>,
+    context: &TaskContext,
+) -> eyre::Result<()> {
+    let mut room_ids = HashSet::new();
+    for room in &options.rooms {
+        let room_id = matrix.join(room).await?;
+        tracing::info!("Joined Matrix room {} ({})", room, room_id);
+        room_ids.insert(room_id);
+    }
+    let bot = Bot::new(options.user_id.clone(), room_ids);
+
+    // The initial sync contains the history of the rooms, which has already been responded to.
+    let mut since = matrix.sync(None, 0).await?.next_batch;
+    loop {
+        let sync = matrix.sync(Some(since), SYNC_TIMEOUT_MS).await?;
+        let dynamic_options = options_rx.borrow().clone();
+        let service = ForecastService::new(
+            context.time,
+            forecast_service,
+            Some(topo_data_service),
+            &dynamic_options,
+        )
+        .with_aviation_weather(Some(aviation_weather))
+        .with_flood(Some(flood));
+        for (room_id, content) in bot
+            .handle(&sync, &service, context.quotas, context.time.utc_now())
+            .await
+        {
+            matrix.send(&room_id, &content).await?;
+            context
+                .status
+                .record_forecast_processed(context.time.utc_now());
+        }
+        since = sync.next_batch;
+    }
+}
+
+/// Task which joins [`MatrixOptions::rooms`] and replies to the `!forecast` commands sent in
+/// them. Restarts (with backoff) if an error occurs.
+#[allow(clippy::too_many_arguments)]
+pub async fn serve_matrix(
+    shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    options: &'static MatrixOptions,
+    matrix: Gateway,
+    forecast_service: forecast_service::Failover,
+    topo_data_service: topo_data_service::Failover,
+    aviation_weather: aviation_weather::Gateway,
+    flood: flood::Gateway,
+    options_rx: watch::Receiver<DynamicOptions>,
+    context: TaskContext,
+) {
+    tracing::debug!("Starting Matrix job");
+    run_retry_log_errors(
+        move || {
+            let matrix = matrix.clone();
+            let forecast_service = forecast_service.clone();
+            let topo_data_service = topo_data_service.clone();
+            let aviation_weather = aviation_weather.clone();
+            let flood = flood.clone();
+            let options_rx = options_rx.clone();
+            async move {
+                let forecast_service = CircuitBreakerPort::new(
+                    forecast_service,
+                    &context.circuit_breakers.forecast,
+                    context.time,
+                );
+                let topo_data_service = CircuitBreakerPort::new(
+                    topo_data_service,
+                    &context.circuit_breakers.topo_data,
+                    context.time,
+                );
+                serve_matrix_impl(
+                    options,
+                    &matrix,
+                    &forecast_service,
+                    &topo_data_service,
+                    &aviation_weather,
+                    &flood,
+                    &options_rx,
+                    &context,
+                )
+                .await
+            }
+        },
+        shutdown_rx,
+        context.backoff,
+        context.time,
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod test {
+    use once_cell::sync::Lazy;
+    use open_meteo::Forecast;
+    use tokio::sync::watch;
+
+    use crate::{
+        forecast::ForecastService,
+        forecast_service::{self, ObtainedForecast},
+        options::DynamicOptions,
+        quota::Quotas,
+    };
+
+    use super::{Bot, SyncResponse};
+
+    static FORECAST_MT_COOK: Lazy<Forecast> = Lazy::new(|| {
+        serde_json::from_str(&std::fs::read_to_string("fixtures/forecast_mt_cook.json").unwrap())
+            .unwrap()
+    });
+
+    #[tokio::test]
+    async fn test_handle() {
+        let sync: SyncResponse = serde_json::from_value(serde_json::json!({
+            "next_batch": "s2",
+            "rooms": {
+                "join": {
+                    "!club:example.org": {
+                        "timeline": {
+                            "events": [
+                                {
+                                    "type": "m.room.message",
+                                    "sender": "@alice:example.org",
+                                    "content": { "msgtype": "m.text", "body": "!forecast -43.5,170.3" }
+                                },
+                                {
+                                    "type": "m.room.message",
+                                    "sender": "@alice:example.org",
+                                    "content": { "msgtype": "m.text", "body": "hello" }
+                                },
+                                {
+                                    "type": "m.room.message",
+                                    "sender": "@alice:example.org",
+                                    "content": { "msgtype": "m.text", "body": "!forecasts" }
+                                },
+                                {
+                                    "type": "m.room.message",
+                                    "sender": "@weather:example.org",
+                                    "content": { "msgtype": "m.notice", "body": "!forecast -43.5,170.3" }
+                                },
+                                {
+                                    "type": "m.room.message",
+                                    "sender": "@alice:example.org",
+                                    "content": { "msgtype": "m.text", "body": "!forecast" }
+                                }
+                            ]
+                        }
+                    },
+                    "!other:example.org": {
+                        "timeline": {
+                            "events": [
+                                {
+                                    "type": "m.room.message",
+                                    "sender": "@alice:example.org",
+                                    "content": { "msgtype": "m.text", "body": "!forecast -43.5,170.3" }
+                                }
+                            ]
+                        }
+                    }
+                }
+            }
+        }))
+        .unwrap();
+
+        let mut time = crate::time::MockPort::new();
+        time.expect_utc_now()
+            .returning(|| "2022-12-03T08:00:00Z".parse().unwrap());
+        let mut forecast_service = forecast_service::MockPort::new();
+        forecast_service
+            .expect_obtain_forecast()
+            .times(1)
+            .returning(|_| Ok(ObtainedForecast::fixture(FORECAST_MT_COOK.clone())));
+        let options = DynamicOptions::default();
+        let service = ForecastService::new(&time, &forecast_service, None, &options);
+        let quotas = Quotas::new(watch::channel(DynamicOptions::default()).1);
+        let bot = Bot::new(
+            "@weather:example.org".to_owned(),
+            ["!club:example.org".to_owned()].into_iter().collect(),
+        );
+
+        let replies = bot
+            .handle(
+                &sync,
+                &service,
+                &quotas,
+                "2022-12-03T08:00:00Z".parse().unwrap(),
+            )
+            .await;
+        assert_eq!(2, replies.len(), "{:?}", replies);
+        assert!(replies
+            .iter()
+            .all(|(room_id, _)| room_id == "!club:example.org"));
+        assert!(replies[0].1.body.contains("03T21"), "{}", replies[0].1.body);
+        assert!(
+            replies[1].1.body.starts_with("Include a position"),
+            "{}",
+            replies[1].1.body
+        );
+    }
+}
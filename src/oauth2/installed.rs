@@ -8,6 +8,8 @@ use oauth2::{
     TokenResponse,
 };
 
+use crate::webhook::{WebhookEvent, Webhooks};
+
 use super::{
     authenticate_with_token_cache, refresh_token, AuthenticationFlow, ClientSecretDefinition,
     ConsentRedirect, StandardTokenResponse, TokenCache,
@@ -19,6 +21,7 @@ pub struct Flow {
     scopes: Vec<Scope>,
     client: BasicClient,
     token_cache: TokenCache,
+    webhooks: &'static Webhooks,
 }
 
 impl Flow {
@@ -28,6 +31,7 @@ impl Flow {
         client_secret: &ClientSecretDefinition,
         scopes: Vec<Scope>,
         token_cache_path: impl Into<PathBuf>,
+        webhooks: &'static Webhooks,
     ) -> Self {
         let client = BasicClient::new(
             client_secret.client_id().clone(),
@@ -43,6 +47,7 @@ impl Flow {
             scopes,
             client,
             token_cache,
+            webhooks,
         }
     }
 
@@ -82,6 +87,9 @@ impl Flow {
                     "Open this URL to obtain the OAUTH2 authentication approval for your email account:\n{}",
                     auth_url
                 );
+                self.webhooks.notify(WebhookEvent::ReauthRequired {
+                    authorization_url: auth_url.to_string(),
+                });
 
                 let mut rx = redirect_rx.lock().await;
                 let parameters = rx.recv()
@@ -29,7 +29,7 @@ pub mod service_account;
 
 pub use service_account::ServiceAccountFlow;
 
-use crate::secrets::OauthSecrets;
+use crate::{secrets::OauthSecrets, webhook::Webhooks};
 
 /// Method used to redirect the user to obtain their consent for authentication.
 pub enum ConsentRedirect {
@@ -408,6 +408,7 @@ pub fn setup_flow(
     secrets: &OauthSecrets,
     base_url: &url::Url,
     oauth_redirect_rx: mpsc::Receiver<RedirectParameters>,
+    webhooks: &'static Webhooks,
 ) -> eyre::Result<installed::Flow> {
     let scopes = vec![
         // https://developers.google.com/gmail/imap/xoauth2-protocol
@@ -427,6 +428,7 @@ pub fn setup_flow(
         })?,
         scopes,
         secrets.token_cache_path.clone(),
+        webhooks,
     ))
 }
 
@@ -4,21 +4,32 @@
 
 use std::{
     borrow::Cow,
+    collections::BTreeMap,
     fmt::Display,
     net::SocketAddr,
     path::{Path, PathBuf},
+    time::{Duration, SystemTime},
 };
 
 use color_eyre::Help;
 use eyre::Context;
 use ron::ser::PrettyConfig;
+use schemars::JsonSchema;
 use serde::{ser::Error, Deserialize, Serialize};
+use tokio::sync::{broadcast, watch};
 use tracing::Level;
 
-use crate::email;
+use crate::{
+    aprs::AprsOptions, archive, aviation_weather::AviationWeatherOptions,
+    back_pressure::BackPressureOptions, circuit_breaker::CircuitBreakerOptions, client_ip, email,
+    flood::FloodOptions, forecast_service::ForecastOptions, format::FormatForecastOptions,
+    matrix::MatrixOptions, profile::ForecastProfile, queue, quota::QuotaOptions, rate_limit,
+    reporting, retry::BackoffOptions, secrets, serve_http, time,
+    topo_data_service::TopoDataOptions, twilio::TwilioOptions, watchdog::WatchdogOptions, webhook,
+};
 
 /// Global options for the application.
-#[derive(Debug, Serialize, Deserialize)]
+#[derive(Debug, Serialize, Deserialize, JsonSchema)]
 pub struct Options {
     /// Directory where application data is stored (including logs).
     ///
@@ -54,6 +65,266 @@ pub struct Options {
     /// Default is `false`.
     #[serde(default = "default_overwrite_token_cache")]
     pub overwrite_token_cache: bool,
+    /// Where secrets are loaded from, see [`secrets::Backend`].
+    ///
+    /// Default is `Environment`.
+    #[serde(default)]
+    pub secrets_backend: secrets::Backend,
+    /// If `true`, replies are generated but not sent, instead they are logged and archived as JSON
+    /// files in the `dry_run` directory of `data_dir`, so that changes to the options can be
+    /// validated against real incoming emails.
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub dry_run: bool,
+    /// Where the process and reply queues are stored, see [`queue::Backend`]. Use `Redis` to run
+    /// multiple replicas of the service with the same `email_account`.
+    ///
+    /// Default is `Local` (stored in `data_dir`).
+    #[serde(default)]
+    pub queue: queue::Backend,
+    /// If specified, the http server will serve https using the specified certificate and private
+    /// key, see [`serve_http::TlsOptions`].
+    ///
+    /// Default is `None` (serve plain http).
+    #[serde(default)]
+    pub tls: Option<serve_http::TlsOptions>,
+    /// If specified, the http server will serve https using a certificate for the hostname of
+    /// `base_url`, which is provisioned and renewed automatically using ACME, see
+    /// [`serve_http::AcmeOptions`]. Cannot be used together with `tls`.
+    ///
+    /// Default is `None`.
+    #[serde(default)]
+    pub acme: Option<serve_http::AcmeOptions>,
+    /// Per client IP address rate limit applied to all http routes, see
+    /// [`rate_limit::RateLimitOptions`].
+    ///
+    /// Default is a burst of 20 requests, and 60 requests per minute.
+    #[serde(default)]
+    pub http_rate_limit: rate_limit::RateLimitOptions,
+    /// Maximum size (in bytes) of http request bodies.
+    ///
+    /// Default is `65536`.
+    #[serde(default = "default_http_max_body_bytes")]
+    pub http_max_body_bytes: usize,
+    /// Where the client IP address of http requests (used for rate limiting and admin login
+    /// lockouts) is obtained from. Set this to `FlyClientIp` or `XForwardedFor` when running
+    /// behind a proxy which sets the corresponding header, see [`client_ip::ClientIpSource`].
+    ///
+    /// Default is `Peer`, the address of the connection.
+    #[serde(default)]
+    pub client_ip_source: client_ip::ClientIpSource,
+    /// Whether to serve the public forecast api at `/api/forecast`, see
+    /// [`crate::api::forecast_api()`].
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub forecast_api: bool,
+    /// Origins (e.g. `https://tools.example.org`) of the browser based tools which are permitted
+    /// to call the api at `/api` using CORS, or `["*"]` to permit any origin, see
+    /// [`crate::api::cors_layer()`]. Access to the api can be restricted using the `API_TOKENS`
+    /// secret, see [`crate::api::ApiTokens`].
+    ///
+    /// Default is `[]` (no cross-origin requests are permitted).
+    #[serde(default)]
+    pub api_cors_origins: Vec<String>,
+    /// Whether to serve histograms of the time spent in each stage of processing a request at
+    /// `/metrics`, in the Prometheus text format, see [`crate::latency::metrics_routes()`].
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub metrics: bool,
+    /// Retention policy for log files, see [`reporting::LogRetention`].
+    ///
+    /// Default is to remove log files older than 30 days.
+    #[serde(default)]
+    pub log_retention: reporting::LogRetention,
+    /// If specified, the raw RFC822 message of each received email is archived in the `archive`
+    /// directory of `data_dir` (including rejected emails), so that it can be reinjected into the
+    /// process queue using the `replay` subcommand, see [`archive::EmailArchiveOptions`].
+    ///
+    /// Default is `None` (emails are not archived).
+    #[serde(default)]
+    pub email_archive: Option<archive::EmailArchiveOptions>,
+    /// Options for reporting errors to [sentry.io](https://sentry.io), which is enabled when the
+    /// `SENTRY_DSN` secret is provided (using the `secrets_backend`, or in `secrets_dir`), see
+    /// [`reporting::SentryOptions`].
+    ///
+    /// Default is reporting all errors and 10% of transactions.
+    #[serde(default)]
+    pub sentry: reporting::SentryOptions,
+    /// How often (in seconds) the IMAP inbox is polled for new emails. Can be changed while the
+    /// application is running.
+    ///
+    /// Default is `10`.
+    #[serde(default = "default_poll_interval_secs")]
+    pub poll_interval_secs: u64,
+    /// If specified, only plain emails from senders matching an entry in this list will be
+    /// processed. An entry can either be an email address (e.g. `name@example.com`), or a domain
+    /// preceded by `@` (e.g. `@example.com`). Can be changed while the application is running.
+    ///
+    /// Default is `None` (emails from all senders are processed).
+    #[serde(default)]
+    pub whitelist: Option<Vec<String>>,
+    /// Template for the subject of plain email replies containing a forecast, so that forecasts
+    /// are easier to find in a crowded inbox, e.g. `"{summary} {dates} ({subject})"`. See
+    /// [`crate::subject::render()`] for the available placeholders. Can be changed while the
+    /// application is running.
+    ///
+    /// Default is `None` (the subject is `Re: <subject>`).
+    #[serde(default)]
+    pub reply_subject: Option<String>,
+    /// Format used for replies when the request does not specify one. Can be changed while the
+    /// application is running.
+    ///
+    /// Default is the short format.
+    #[serde(default)]
+    pub default_format: FormatForecastOptions,
+    /// Named forecast profiles which can be selected by users in their request (e.g. `P=alpine`),
+    /// see [`ForecastProfile`]. Profile names are matched case-insensitively. Can be changed while
+    /// the application is running.
+    ///
+    /// Default is no profiles.
+    #[serde(default)]
+    pub profiles: BTreeMap<String, ForecastProfile>,
+    /// Name of the profile in [`Options::profiles`] used when the request does not select one.
+    /// Can be changed while the application is running.
+    ///
+    /// Default is `None`.
+    #[serde(default)]
+    pub default_profile: Option<String>,
+    /// Webhooks which are notified when a forecast is processed, a reply fails to send, or the
+    /// email account needs to be authorized again, see [`webhook::WebhookOptions`].
+    ///
+    /// Default is no webhooks.
+    #[serde(default)]
+    pub webhooks: Vec<webhook::WebhookOptions>,
+    /// Backoff used when restarting a task (e.g. receiving emails via IMAP) after it has failed,
+    /// see [`BackoffOptions`].
+    ///
+    /// Default is starting at 10 seconds, increasing up to 10 minutes, with jitter.
+    #[serde(default)]
+    pub task_backoff: BackoffOptions,
+    /// Backoff used when retrying sending a reply after it has failed, see [`BackoffOptions`].
+    ///
+    /// Default is starting at 5 seconds, increasing up to 10 minutes, with jitter.
+    #[serde(default = "default_reply_backoff")]
+    pub reply_backoff: BackoffOptions,
+    /// Circuit breaker applied to each external service (forecasts, terrain elevation and inReach
+    /// replies), see [`CircuitBreakerOptions`]. While the circuit for the forecast service is open,
+    /// users are sent a reply stating that the service is temporarily unavailable.
+    ///
+    /// Default is opening after 5 consecutive failures, for 5 minutes.
+    #[serde(default)]
+    pub circuit_breaker: CircuitBreakerOptions,
+    /// Whether to record anonymized analytics of each request (time, position rounded to 0.1
+    /// degrees, device kind, format, processing duration and outcome) in `analytics.sqlite` in
+    /// the data directory, see [`crate::analytics::Analytics`]. Simple statistics are displayed on
+    /// the logs page.
+    ///
+    /// Default is `true`.
+    #[serde(default = "default_analytics")]
+    pub analytics: bool,
+    /// Whether plain email senders need to confirm their address (by replying to a confirmation
+    /// request with a code) before their requests are processed, so that the service can't be
+    /// used to send replies to addresses which didn't request them, see
+    /// [`crate::confirmation::SenderConfirmations`]. Confirmations are stored in `senders.sqlite`
+    /// in the data directory. Messages from inReach devices don't require confirmation.
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub confirm_senders: bool,
+    /// Whether senders can subscribe to an Atom feed of their forecasts using the `FEED` command,
+    /// served relative to `base_url`, see [`crate::feed::Feeds`]. Feeds are stored in
+    /// `feeds.sqlite` in the data directory.
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub feeds: bool,
+    /// Whether forecasts include the changes since the previous forecast sent to the same sender
+    /// for the same position, see [`crate::changes`]. The previous forecasts are stored in
+    /// `forecasts.sqlite` in the data directory.
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub forecast_changes: bool,
+    /// Whether plain email senders can register a PGP public key using the `KEY` command, which
+    /// their replies are then encrypted to, see [`crate::encryption`]. The keys are stored in
+    /// `keys.sqlite` in the data directory.
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub encrypted_replies: bool,
+    /// Back-pressure applied when too many requests are waiting in the process queue, either
+    /// replying that the service is busy or deferring receiving new emails, see
+    /// [`BackPressureOptions`]. Can be changed while the application is running.
+    ///
+    /// Default is unlimited.
+    #[serde(default)]
+    pub back_pressure: BackPressureOptions,
+    /// If specified, a forecast request identical to one answered for the same sender within this
+    /// many seconds is treated as a duplicate, and not answered again, see [`crate::duplicate`].
+    /// Can be changed while the application is running.
+    ///
+    /// Default is `None` (duplicates are answered).
+    #[serde(default)]
+    pub duplicate_window_secs: Option<u64>,
+    /// Service-wide and per-domain daily quotas for the number of requests processed, to
+    /// protect against abuse, see [`QuotaOptions`].
+    ///
+    /// Can be changed while the application is running.
+    ///
+    /// Default is unlimited.
+    #[serde(default)]
+    pub quota: QuotaOptions,
+    /// Options for the watchdog, which alerts (via `webhooks` and email) when the inbox has not
+    /// been polled or replies have not been sent for too long, see [`WatchdogOptions`].
+    ///
+    /// Default is alerting after 30 minutes, only via webhooks.
+    #[serde(default)]
+    pub watchdog: WatchdogOptions,
+    /// Options for the forecast service, e.g. fallback providers, see [`ForecastOptions`].
+    ///
+    /// Default is Open-Meteo.
+    #[serde(default)]
+    pub forecast: ForecastOptions,
+    /// Options for the terrain elevation service, e.g. to use a self-hosted server, see
+    /// [`TopoDataOptions`].
+    ///
+    /// Default is the public API.
+    #[serde(default)]
+    pub topo_data: TopoDataOptions,
+    /// Options for the aviation weather service, which provides the METAR and TAF reports for
+    /// requests which include `METAR`, see [`AviationWeatherOptions`].
+    ///
+    /// Default is the public Aviation Weather Center API.
+    #[serde(default)]
+    pub aviation_weather: AviationWeatherOptions,
+    /// Options for the flood service, which provides the river discharge for requests which
+    /// include `FLOOD`, see [`FloodOptions`].
+    ///
+    /// Default is the public Open-Meteo Flood API.
+    #[serde(default)]
+    pub flood: FloodOptions,
+    /// If specified, the service also listens on APRS-IS for requests sent as APRS messages to
+    /// the callsign, and replies with the short format forecast, see [`AprsOptions`].
+    ///
+    /// Default is `None`.
+    #[serde(default)]
+    pub aprs: Option<AprsOptions>,
+    /// If specified, the http server receives requests sent by SMS to a Twilio phone number via
+    /// the Twilio webhook, and replies with the short format forecast, see [`TwilioOptions`].
+    /// Requires the `TWILIO_AUTH_TOKEN` secret.
+    ///
+    /// Default is `None`.
+    #[serde(default)]
+    pub twilio: Option<TwilioOptions>,
+    /// If specified, a Matrix bot joins the rooms and replies to `!forecast` commands sent in
+    /// them, see [`MatrixOptions`]. Requires the `MATRIX_ACCESS_TOKEN` secret.
+    ///
+    /// Default is `None`.
+    #[serde(default)]
+    pub matrix: Option<MatrixOptions>,
 }
 
 fn default_data_dir() -> PathBuf {
@@ -82,6 +353,25 @@ fn default_overwrite_token_cache() -> bool {
     false
 }
 
+fn default_analytics() -> bool {
+    true
+}
+
+fn default_http_max_body_bytes() -> usize {
+    64 * 1024
+}
+
+fn default_reply_backoff() -> BackoffOptions {
+    BackoffOptions {
+        start_secs: 5.0,
+        ..BackoffOptions::default()
+    }
+}
+
+fn default_poll_interval_secs() -> u64 {
+    10
+}
+
 impl Display for Options {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
         let options_str = ron::ser::to_string_pretty(self, PrettyConfig::default())
@@ -91,6 +381,252 @@ impl Display for Options {
     }
 }
 
+/// Fields of [`Options`] (paths separated by `.`) which are known not to contain personal
+/// information (or credentials), and are included by [`Options::sanitized()`] along with all of
+/// their nested fields. All other fields are redacted, so that new options are redacted unless
+/// they are added here.
+const UNSANITIZED_FIELDS: &[&str] = &[
+    "data_dir",
+    "secrets_dir",
+    "base_url",
+    "listen_address",
+    "delete_token_cache",
+    "overwrite_token_cache",
+    "dry_run",
+    "tls",
+    "acme.production",
+    "acme.cache_dir",
+    "http_rate_limit",
+    "http_max_body_bytes",
+    "client_ip_source",
+    "forecast_api",
+    "api_cors_origins",
+    "metrics",
+    "log_retention",
+    "email_archive",
+    "sentry",
+    "poll_interval_secs",
+    "reply_subject",
+    "default_format",
+    "profiles",
+    "default_profile",
+    "task_backoff",
+    "reply_backoff",
+    "circuit_breaker",
+    "analytics",
+    "confirm_senders",
+    "feeds",
+    "forecast_changes",
+    "encrypted_replies",
+    "back_pressure",
+    "duplicate_window_secs",
+    "quota",
+    "watchdog.inbox_poll_minutes",
+    "watchdog.reply_queue_minutes",
+    "forecast",
+    "topo_data",
+    "aviation_weather",
+    "flood",
+    "aprs.server",
+    "aprs.max_messages",
+    "twilio.length_limit",
+    "twilio.api_base_url",
+    "matrix.homeserver_url",
+];
+
+/// Redact the fields of `value` (located at `path` in the options) which are not listed in
+/// [`UNSANITIZED_FIELDS`]. Fields which are not specified (`null`) are kept.
+fn sanitize(value: &mut serde_json::Value, path: &str) {
+    if UNSANITIZED_FIELDS.contains(&path) {
+        return;
+    }
+    let prefix = if path.is_empty() {
+        String::new()
+    } else {
+        format!("{}.", path)
+    };
+    match value {
+        serde_json::Value::Null => {}
+        serde_json::Value::Object(map)
+            if UNSANITIZED_FIELDS
+                .iter()
+                .any(|field| field.starts_with(&prefix)) =>
+        {
+            for (key, field_value) in map.iter_mut() {
+                sanitize(field_value, &format!("{}{}", prefix, key));
+            }
+        }
+        _ => *value = serde_json::Value::String("<redacted>".to_string()),
+    }
+}
+
+impl Options {
+    /// Serialize the options as pretty printed JSON, with fields that may contain personal
+    /// information redacted (see [`UNSANITIZED_FIELDS`]), suitable for attaching to bug reports.
+    pub fn sanitized(&self) -> eyre::Result<String> {
+        let mut value = serde_json::to_value(self).wrap_err("Error serializing options")?;
+        sanitize(&mut value, "");
+        serde_json::to_string_pretty(&value).wrap_err("Error serializing sanitized options")
+    }
+
+    /// Generate a [JSON Schema](https://json-schema.org/) for [`Options`], which can be used by
+    /// editors to provide completion and validation when editing the options file.
+    pub fn json_schema() -> eyre::Result<String> {
+        let schema = schemars::schema_for!(Options);
+        serde_json::to_string_pretty(&schema).wrap_err("Error serializing options schema")
+    }
+}
+
+/// The subset of [`Options`] which can be safely changed while the application is running. Running
+/// tasks receive updates to these options via a [`watch`] channel, see [`watch_file()`].
+#[derive(Debug, Clone, PartialEq)]
+pub struct DynamicOptions {
+    /// See [`Options::poll_interval_secs`].
+    pub poll_interval: Duration,
+    /// See [`Options::whitelist`].
+    pub whitelist: Option<Vec<String>>,
+    /// See [`Options::reply_subject`].
+    pub reply_subject: Option<String>,
+    /// See [`Options::default_format`].
+    pub default_format: FormatForecastOptions,
+    /// See [`Options::profiles`].
+    pub profiles: BTreeMap<String, ForecastProfile>,
+    /// See [`Options::default_profile`].
+    pub default_profile: Option<String>,
+    /// See [`Options::back_pressure`].
+    pub back_pressure: BackPressureOptions,
+    /// See [`Options::duplicate_window_secs`].
+    pub duplicate_window: Option<Duration>,
+    /// See [`Options::quota`].
+    pub quota: QuotaOptions,
+}
+
+impl Default for DynamicOptions {
+    fn default() -> Self {
+        Self {
+            poll_interval: Duration::from_secs(default_poll_interval_secs()),
+            whitelist: None,
+            reply_subject: None,
+            default_format: FormatForecastOptions::default(),
+            profiles: BTreeMap::new(),
+            default_profile: None,
+            back_pressure: BackPressureOptions::default(),
+            duplicate_window: None,
+            quota: QuotaOptions::default(),
+        }
+    }
+}
+
+impl From<&Options> for DynamicOptions {
+    fn from(options: &Options) -> Self {
+        Self {
+            poll_interval: Duration::from_secs(options.poll_interval_secs),
+            whitelist: options.whitelist.clone(),
+            reply_subject: options.reply_subject.clone(),
+            default_format: options.default_format.clone(),
+            profiles: options.profiles.clone(),
+            default_profile: options.default_profile.clone(),
+            back_pressure: options.back_pressure,
+            duplicate_window: options.duplicate_window_secs.map(Duration::from_secs),
+            quota: options.quota,
+        }
+    }
+}
+
+impl DynamicOptions {
+    /// Returns `true` if emails from the specified email `address` are permitted by
+    /// [`DynamicOptions::whitelist`].
+    #[must_use]
+    pub fn is_whitelisted(&self, address: &str) -> bool {
+        let whitelist = if let Some(whitelist) = &self.whitelist {
+            whitelist
+        } else {
+            return true;
+        };
+        let address = address.to_lowercase();
+
+        whitelist.iter().any(|entry| {
+            let entry = entry.to_lowercase();
+            if entry.starts_with('@') {
+                address.ends_with(&entry)
+            } else {
+                address == entry
+            }
+        })
+    }
+
+    /// Look up a profile in [`DynamicOptions::profiles`] by `name` (case-insensitive).
+    #[must_use]
+    pub fn profile(&self, name: &str) -> Option<&ForecastProfile> {
+        self.profiles
+            .iter()
+            .find(|(profile_name, _)| profile_name.eq_ignore_ascii_case(name))
+            .map(|(_, profile)| profile)
+    }
+}
+
+/// How often the options file is checked for modifications by [`watch_file()`].
+const WATCH_INTERVAL: Duration = Duration::from_secs(5);
+
+async fn modified_time(path: &Path) -> std::io::Result<SystemTime> {
+    tokio::fs::metadata(path).await?.modified()
+}
+
+/// Watch the options file at `path` for modifications, reload it when it changes, and broadcast
+/// the [`DynamicOptions`] to running tasks using `options_tx`. Options which are not part of
+/// [`DynamicOptions`] require a restart to take effect.
+#[tracing::instrument(skip(options_tx, time, shutdown_rx))]
+pub async fn watch_file(
+    path: PathBuf,
+    options_tx: watch::Sender<DynamicOptions>,
+    time: &dyn time::Port,
+    mut shutdown_rx: broadcast::Receiver<()>,
+) {
+    let watch_loop = async {
+        let mut modified = modified_time(&path).await.ok();
+        loop {
+            time.async_sleep(WATCH_INTERVAL).await;
+            let new_modified = match modified_time(&path).await {
+                Ok(new_modified) => new_modified,
+                Err(error) => {
+                    tracing::warn!("Unable to read options file metadata: {}", error);
+                    continue;
+                }
+            };
+            if modified == Some(new_modified) {
+                continue;
+            }
+            modified = Some(new_modified);
+
+            match read_options_file(&path).await {
+                Ok(options) => {
+                    let dynamic_options = DynamicOptions::from(&options);
+                    if *options_tx.borrow() != dynamic_options {
+                        tracing::info!(
+                            "Options file changed, applying new options: {:?}",
+                            dynamic_options
+                        );
+                        options_tx.send_replace(dynamic_options);
+                    }
+                }
+                Err(error) => {
+                    tracing::error!("Error reloading options file: {:?}", error);
+                }
+            }
+        }
+    };
+
+    tokio::select! {
+        result = shutdown_rx.recv() => {
+            tracing::debug!("Received shutdown broadcast");
+            if let Err(error) = result {
+                tracing::error!("Error receiving shutdown message: {:?}", error);
+            }
+        }
+        _ = watch_loop => {}
+    }
+}
+
 /// Storage for logging entries that will be printed later, when application logging setup is
 /// completed.
 #[derive(Default)]
@@ -131,22 +667,74 @@ pub struct OptionsInit {
     /// Messages that are destined to logged after tracing has been
     /// initialized.
     pub logs: Logs,
+    /// Path to the file that the options were loaded from, if they were loaded from a file.
+    pub path: Option<PathBuf>,
 }
 
 impl Options {
-    /// Initialize the options using the `OPTIONS` environment variable, otherwise load from file
-    /// `options.ron` by default. If `OPTIONS` contains a file path, it will load the options from
-    /// that path, if `OPTIONS` contains a RON file definition then it will load the options from
-    /// the string contained in the variable.
+    /// Initialize the options using the `OPTIONS` environment variable, otherwise load from the
+    /// first of `options.ron`, `options.toml`, `options.yaml` or `options.yml` that exists. If
+    /// `OPTIONS` contains a file path, it will load the options from that path (the format is
+    /// detected by the file extension), if `OPTIONS` contains a RON file definition then it will
+    /// load the options from the string contained in the variable.
     pub async fn initialize() -> OptionsInit {
         let mut logs = Logs::default();
-        let result = initialize_impl(&mut logs).await;
+        let mut path = None;
+        let result = initialize_impl(&mut logs, &mut path).await;
 
-        OptionsInit { result, logs }
+        OptionsInit { result, logs, path }
     }
 }
 
-async fn initialize_impl(logs: &mut Logs) -> eyre::Result<Options> {
+/// Default options file paths, searched in order.
+const DEFAULT_PATHS: &[&str] = &["options.ron", "options.toml", "options.yaml", "options.yml"];
+
+/// Format of an options file or string.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Format {
+    /// [RON](https://github.com/ron-rs/ron) format.
+    Ron,
+    /// [TOML](https://toml.io/) format.
+    Toml,
+    /// [YAML](https://yaml.org/) format.
+    Yaml,
+}
+
+impl Format {
+    /// Detect the format of an options file using its extension. Returns `None` if the extension
+    /// is not recognised.
+    pub fn from_path(path: &Path) -> Option<Self> {
+        match path.extension()?.to_str()? {
+            "ron" => Some(Self::Ron),
+            "toml" => Some(Self::Toml),
+            "yaml" | "yml" => Some(Self::Yaml),
+            _ => None,
+        }
+    }
+
+    /// Deserialize [`Options`] from a string in this format.
+    pub fn deserialize(self, options_str: &str) -> eyre::Result<Options> {
+        Ok(match self {
+            Format::Ron => ron::from_str(options_str)?,
+            Format::Toml => toml::from_str(options_str)?,
+            Format::Yaml => serde_yaml::from_str(options_str)?,
+        })
+    }
+}
+
+/// Read options from a file, the format is detected using the file extension, defaulting to RON
+/// if it is not recognised.
+async fn read_options_file(path: &Path) -> eyre::Result<Options> {
+    let format = Format::from_path(path).unwrap_or(Format::Ron);
+    let options_str = tokio::fs::read_to_string(path)
+        .await
+        .wrap_err_with(|| format!("Error reading options file: {:?}", path))?;
+    format
+        .deserialize(&options_str)
+        .wrap_err_with(|| format!("Error deserializing {:?} options file: {:?}", format, path))
+}
+
+async fn initialize_impl(logs: &mut Logs, path_out: &mut Option<PathBuf>) -> eyre::Result<Options> {
     let result = match std::env::var("OPTIONS") {
         Ok(options) => match ron::from_str(&options) {
             Ok(options) => {
@@ -159,11 +747,9 @@ async fn initialize_impl(logs: &mut Logs) -> eyre::Result<Options> {
             Err(error) => {
                 let path = PathBuf::from(&options);
                 if path.is_file() {
-                    let options_str = tokio::fs::read_to_string(&path).await?;
-                    let options: Options = ron::from_str(&options_str).wrap_err_with(|| {
-                        format!("Error deserializing options file: {:?}", path)
-                    })?;
+                    let options: Options = read_options_file(&path).await?;
                     logs.push(Level::INFO, format!("Options loaded from file specified in `OPTIONS` environment variable: {:?}", path));
+                    *path_out = Some(path);
                     Ok(options)
                 } else {
                     Err(error).wrap_err_with(|| {
@@ -177,27 +763,31 @@ async fn initialize_impl(logs: &mut Logs) -> eyre::Result<Options> {
             }
         },
         Err(std::env::VarError::NotPresent) => {
-            let path = Path::new("options.ron");
-            if !path.is_file() {
+            let path = if let Some(path) = DEFAULT_PATHS
+                .iter()
+                .map(Path::new)
+                .find(|path| path.is_file())
+            {
+                path
+            } else {
                 return Err(eyre::eyre!(
-                    "No `OPTIONS` environment variable specified, and options file \
-                    `options.ron` does not exist."
+                    "No `OPTIONS` environment variable specified, and no options file \
+                    (`options.ron`, `options.toml` or `options.yaml`) exists."
                 )
                 .suggestion(
                     "The following options are available to solve this:\n\
-                    + Create `options.ron`.\n\
+                    + Create `options.ron`, `options.toml` or `options.yaml`.\n\
                     + Specify options file location with `OPTIONS` environment variable.\n\
                     + Specify options in RON format in `OPTIONS` environment variable as a string.",
                 ));
-            }
-            let options_str = tokio::fs::read_to_string(&path).await?;
-            let options = ron::from_str(&options_str)
-                .wrap_err_with(|| format!("Error deserializing options file: {:?}", path))?;
+            };
+            let options = read_options_file(path).await?;
 
             logs.push(
                 Level::INFO,
                 format!("Options loaded from default file: {:?}", path),
             );
+            *path_out = Some(path.to_path_buf());
 
             Ok(options)
         }
@@ -208,3 +798,132 @@ async fn initialize_impl(logs: &mut Logs) -> eyre::Result<Options> {
     }
     result
 }
+
+#[cfg(test)]
+mod test {
+    use std::path::Path;
+
+    use crate::profile::ForecastProfile;
+
+    use super::{DynamicOptions, Format};
+
+    #[test]
+    fn test_is_whitelisted() {
+        let options = DynamicOptions::default();
+        assert!(options.is_whitelisted("anyone@example.com"));
+
+        let options = DynamicOptions {
+            whitelist: Some(vec![
+                "Name@example.com".to_string(),
+                "@example.org".to_string(),
+            ]),
+            ..DynamicOptions::default()
+        };
+        assert!(options.is_whitelisted("name@example.com"));
+        assert!(options.is_whitelisted("other@example.org"));
+        assert!(!options.is_whitelisted("other@example.com"));
+    }
+
+    #[test]
+    fn test_profile() {
+        let options = DynamicOptions {
+            profiles: [("alpine".to_string(), ForecastProfile::default())]
+                .into_iter()
+                .collect(),
+            ..DynamicOptions::default()
+        };
+        assert!(options.profile("ALPINE").is_some());
+        assert!(options.profile("alpine").is_some());
+        assert!(options.profile("marine").is_none());
+    }
+
+    #[test]
+    fn test_format_from_path() {
+        assert_eq!(
+            Some(Format::Ron),
+            Format::from_path(Path::new("options.ron"))
+        );
+        assert_eq!(
+            Some(Format::Toml),
+            Format::from_path(Path::new("options.toml"))
+        );
+        assert_eq!(
+            Some(Format::Yaml),
+            Format::from_path(Path::new("options.yaml"))
+        );
+        assert_eq!(
+            Some(Format::Yaml),
+            Format::from_path(Path::new("options.yml"))
+        );
+        assert_eq!(None, Format::from_path(Path::new("options")));
+    }
+
+    #[test]
+    fn test_deserialize_formats() {
+        let ron_options = Format::Ron
+            .deserialize(r#"Options(email_account: "weather@example.com")"#)
+            .unwrap();
+        let toml_options = Format::Toml
+            .deserialize(r#"email_account = "weather@example.com""#)
+            .unwrap();
+        let yaml_options = Format::Yaml
+            .deserialize("email_account: weather@example.com")
+            .unwrap();
+
+        assert_eq!("weather@example.com", ron_options.email_account.email_str());
+        assert_eq!(
+            "weather@example.com",
+            toml_options.email_account.email_str()
+        );
+        assert_eq!(
+            "weather@example.com",
+            yaml_options.email_account.email_str()
+        );
+    }
+
+    #[test]
+    fn test_sanitized() {
+        let options = Format::Ron
+            .deserialize(
+                r#"Options(email_account: "weather@example.com", whitelist: Some(["a@example.com"]))"#,
+            )
+            .unwrap();
+        let sanitized = options.sanitized().unwrap();
+        assert!(!sanitized.contains("example.com"));
+        assert!(sanitized.contains("<redacted>"));
+    }
+
+    #[test]
+    fn test_sanitized_nested() {
+        let options = Format::Yaml
+            .deserialize(
+                "email_account: weather@example.com
+acme:
+  contact: [\"mailto:admin@example.com\"]
+  production: true
+watchdog:
+  inbox_poll_minutes: 15
+  alert_email: admin@example.com
+aprs:
+  callsign: ZL1ABC
+  server: rotate.aprs2.net:14580
+poll_interval_secs: 20",
+            )
+            .unwrap();
+        let sanitized: serde_json::Value =
+            serde_json::from_str(&options.sanitized().unwrap()).unwrap();
+        assert_eq!("<redacted>", sanitized["acme"]["contact"]);
+        assert_eq!(
+            serde_json::Value::Bool(true),
+            sanitized["acme"]["production"]
+        );
+        assert_eq!("<redacted>", sanitized["watchdog"]["alert_email"]);
+        assert_eq!(15, sanitized["watchdog"]["inbox_poll_minutes"]);
+        assert_eq!("<redacted>", sanitized["aprs"]["callsign"]);
+        assert_eq!("rotate.aprs2.net:14580", sanitized["aprs"]["server"]);
+        assert_eq!(20, sanitized["poll_interval_secs"]);
+        assert!(sanitized["twilio"].is_null());
+        // Options which are not known to be safe are redacted.
+        assert_eq!("<redacted>", sanitized["secrets_backend"]);
+    }
+}
@@ -0,0 +1,278 @@
+//! A summary of the recent (observed and analysed) precipitation and temperature, to help judge
+//! river levels and the snowpack, requested using `PAST` (for the last
+//! [`DEFAULT_PAST_WEATHER_HOURS`]) or e.g. `PAST=72` (for the last 72 hours), see
+//! [`build_past_weather()`].
+//!
+//! The recent hours are obtained from the forecast service using
+//! [`open_meteo::ForecastParameters::past_days`], and summarised for each (local) day.
+
+use chrono::{NaiveDate, NaiveDateTime};
+use eyre::Context;
+use open_meteo::{HourlyVariable, TimeZone};
+
+use crate::{
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions},
+    gis::Position,
+    process::{format_table, newline},
+    time,
+};
+
+/// Number of hours summarised if not specified in the request.
+pub const DEFAULT_PAST_WEATHER_HOURS: u8 = 48;
+
+/// The summary for a single (local) day, which may be partial at the start and end of the
+/// summarised hours.
+#[derive(Debug, Clone, PartialEq)]
+pub struct PastWeatherDay {
+    /// The (local) date.
+    pub date: NaiveDate,
+    /// Total precipitation (mm).
+    pub precipitation: f32,
+    /// Minimum temperature (°C).
+    pub temperature_min: f32,
+    /// Maximum temperature (°C).
+    pub temperature_max: f32,
+}
+
+/// Hourly values used to compute the summary, see [`PastWeather::from_hours()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct PastWeatherHour {
+    /// The (local) time of the hour.
+    pub time: NaiveDateTime,
+    /// Temperature (°C).
+    pub temperature: f32,
+    /// Precipitation (mm) in the preceding hour.
+    pub precipitation: f32,
+}
+
+/// The summary of the recent weather, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct PastWeather {
+    /// Number of hours summarised.
+    pub hours: u8,
+    /// The summary for each day.
+    pub days: Vec<PastWeatherDay>,
+}
+
+impl PastWeather {
+    /// Summarise the `hours` (in chronological order) which are within the number of `hours`
+    /// before the `now` (local time).
+    #[must_use]
+    pub fn from_hours(now: NaiveDateTime, hours: u8, hourly: &[PastWeatherHour]) -> Self {
+        let start = now - chrono::Duration::hours(i64::from(hours));
+        let mut days: Vec<PastWeatherDay> = Vec::new();
+        for hour in hourly
+            .iter()
+            .filter(|hour| hour.time > start && hour.time <= now)
+        {
+            let date = hour.time.date();
+            let day = match days.last_mut() {
+                Some(day) if day.date == date => day,
+                _ => {
+                    days.push(PastWeatherDay {
+                        date,
+                        precipitation: 0.0,
+                        temperature_min: hour.temperature,
+                        temperature_max: hour.temperature,
+                    });
+                    days.last_mut().expect("day was just pushed")
+                }
+            };
+            day.precipitation += hour.precipitation;
+            day.temperature_min = day.temperature_min.min(hour.temperature);
+            day.temperature_max = day.temperature_max.max(hour.temperature);
+        }
+        Self { hours, days }
+    }
+
+    /// Total precipitation (mm) over all the summarised hours.
+    #[must_use]
+    pub fn precipitation(&self) -> f32 {
+        self.days.iter().map(|day| day.precipitation).sum()
+    }
+
+    /// Format the summary as a block to be appended to the forecast, using the detail in
+    /// `options`.
+    #[must_use]
+    pub fn format(&self, options: &FormatForecastOptions) -> String {
+        match &options.detail {
+            FormatDetail::Short(_) => {
+                let lines = self.days.iter().map(|day| {
+                    format!(
+                        "{} P{:.0} T{:.0}/{:.0}",
+                        day.date.format("%d"),
+                        day.precipitation.round(),
+                        day.temperature_min.round(),
+                        day.temperature_max.round()
+                    )
+                });
+                std::iter::once(format!(
+                    "PAST{} P{:.0}",
+                    self.hours,
+                    self.precipitation().round()
+                ))
+                .chain(lines)
+                .collect::<Vec<_>>()
+                .join(newline(&options.detail))
+            }
+            FormatDetail::Long(long) => {
+                let mut output = format!(
+                    "Past {} hours: {:.0}mm precipitation",
+                    self.hours,
+                    self.precipitation().round()
+                );
+                output.push_str(newline(&options.detail));
+                let columns = vec![
+                    "Date".to_owned(),
+                    "Precipitation".to_owned(),
+                    "Temperature".to_owned(),
+                ];
+                let records = self
+                    .days
+                    .iter()
+                    .map(|day| {
+                        vec![
+                            day.date.to_string(),
+                            format!("{:.0}mm", day.precipitation.round()),
+                            format!(
+                                "{:.0}°C to {:.0}°C",
+                                day.temperature_min.round(),
+                                day.temperature_max.round()
+                            ),
+                        ]
+                    })
+                    .collect();
+                output.push_str(&format_table(long.style.as_ref(), columns, records));
+                output
+            }
+        }
+    }
+}
+
+/// Obtain the recent weather at `position` for the last number of `hours`, and summarise it, see
+/// the [module documentation](self).
+pub async fn build_past_weather(
+    time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    position: Position,
+    hours: u8,
+) -> eyre::Result<PastWeather> {
+    let utc_now = time.utc_now().naive_utc();
+    let mut parameters = open_meteo::ForecastParameters::builder()
+        .latitude(position.latitude)
+        .longitude(position.longitude)
+        .timezone(TimeZone::Auto)
+        .build();
+    parameters.hourly = [HourlyVariable::Temperature2m, HourlyVariable::Precipitation]
+        .into_iter()
+        .collect();
+    // Include the whole of the first day, which may start before the local midnight.
+    parameters.past_days = Some(hours / 24 + 1);
+
+    let forecast = forecast_service
+        .obtain_forecast(&parameters)
+        .await
+        .wrap_err("Error obtaining forecast for past weather")?
+        .forecast;
+    let hourly = forecast
+        .hourly
+        .ok_or_else(|| eyre::eyre!("Expected hourly forecast to be present"))?;
+    let temperature = hourly
+        .temperature_2m
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected temperature to be present"))?;
+    let precipitation = hourly
+        .precipitation
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected precipitation to be present"))?;
+
+    let hourly: Vec<PastWeatherHour> = hourly
+        .time
+        .iter()
+        .zip(temperature)
+        .zip(precipitation)
+        .map(|((time, temperature), precipitation)| PastWeatherHour {
+            time: *time,
+            temperature: temperature.0,
+            precipitation: precipitation.0,
+        })
+        .collect();
+    let local_now = chrono::TimeZone::from_utc_datetime(&forecast.timezone, &utc_now).naive_local();
+    let past_weather = PastWeather::from_hours(local_now, hours, &hourly);
+    if past_weather.days.is_empty() {
+        return Err(eyre::eyre!(
+            "Forecast is missing the times for past weather"
+        ));
+    }
+    Ok(past_weather)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDateTime;
+
+    use crate::format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle};
+
+    use super::{PastWeather, PastWeatherHour};
+
+    fn past_weather() -> PastWeather {
+        let hour = |time: &str, temperature, precipitation| PastWeatherHour {
+            time: time.parse().unwrap(),
+            temperature,
+            precipitation,
+        };
+        let now: NaiveDateTime = "2022-12-03T08:00:00".parse().unwrap();
+        PastWeather::from_hours(
+            now,
+            48,
+            &[
+                // Before the summarised hours.
+                hour("2022-12-01T08:00:00", 20.0, 50.0),
+                hour("2022-12-01T09:00:00", 4.6, 2.0),
+                hour("2022-12-01T18:00:00", 9.0, 10.0),
+                hour("2022-12-02T03:00:00", -1.6, 0.5),
+                hour("2022-12-02T15:00:00", 12.0, 0.0),
+                hour("2022-12-03T08:00:00", 3.0, 1.0),
+                // After the current time.
+                hour("2022-12-03T09:00:00", 30.0, 50.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_from_hours() {
+        let past_weather = past_weather();
+        assert_eq!(3, past_weather.days.len());
+
+        let day = &past_weather.days[0];
+        assert_eq!(12.0, day.precipitation);
+        assert_eq!(4.6, day.temperature_min);
+        assert_eq!(9.0, day.temperature_max);
+
+        let day = &past_weather.days[1];
+        assert_eq!(0.5, day.precipitation);
+        assert_eq!(-1.6, day.temperature_min);
+        assert_eq!(12.0, day.temperature_max);
+
+        assert_eq!(13.5, past_weather.precipitation());
+    }
+
+    #[test]
+    fn test_format() {
+        let past_weather = past_weather();
+        assert_eq!(
+            "PAST48 P14\n01 P12 T5/9\n02 P1 T-2/12\n03 P1 T3/3",
+            past_weather.format(&FormatForecastOptions::default())
+        );
+
+        let long = past_weather.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(long.contains("Past 48 hours: 14mm precipitation"), "{long}");
+        assert!(long.contains("-2°C to 12°C"), "{long}");
+    }
+}
@@ -1,11 +1,11 @@
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    email,
+    email, encryption,
     gis::Position,
-    process::{FormatDetail, LongFormatStyle},
+    language::Language,
     receive::{self, from_account, message_id, text_body, ParseReceivedEmail},
-    request::ParsedForecastRequest,
+    request::{Command, ParsedForecastRequest},
 };
 
 /// A plain text email that was received.
@@ -13,14 +13,30 @@ use crate::{
 pub struct Received {
     /// Address that this email was received from.
     pub from: email::Account,
+    /// Address from the `Reply-To` header (if present), which the reply is sent to instead of
+    /// [`Received::from`], e.g. for users of mailing lists and email relays.
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub reply_to: Option<email::Account>,
     /// Identifier for the received message, will be used to specify the reply.
     pub message_id: Option<String>,
     /// Subject of the received email.
     pub subject: Option<String>,
     /// Requested forecast.
     pub forecast_request: ParsedForecastRequest,
+    /// Requests on the lines following the first request, see [`MAX_ADDITIONAL_REQUESTS`].
+    #[serde(default)]
+    pub additional_requests: Vec<ParsedForecastRequest>,
+    /// Language of the email, detected from its `Content-Language` header or its content.
+    #[serde(default)]
+    pub language: Option<Language>,
+    /// The ASCII armored PGP public key sent with [`Command::Key`], see [`crate::encryption`].
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    pub public_key: Option<String>,
 }
 
+/// Maximum number of requests (after the first) which will be processed from a single email.
+pub const MAX_ADDITIONAL_REQUESTS: usize = 4;
+
 impl receive::Received for Received {
     fn position(&self) -> Option<Position> {
         None
@@ -29,6 +45,18 @@ impl receive::Received for Received {
     fn forecast_request(&self) -> &ParsedForecastRequest {
         &self.forecast_request
     }
+
+    fn additional_requests(&self) -> &[ParsedForecastRequest] {
+        &self.additional_requests
+    }
+
+    fn language(&self) -> Option<Language> {
+        self.language
+    }
+
+    fn sender_identity(&self) -> Option<&str> {
+        Some(self.from.email_str())
+    }
 }
 
 impl ParseReceivedEmail for Received {
@@ -36,6 +64,7 @@ impl ParseReceivedEmail for Received {
 
     fn parse_email(message: mail_parser::Message) -> Result<Self, Self::Err> {
         let from = from_account(&message)?;
+        let reply_to = reply_to_account(&message);
         let message_id = message_id(&message).map(|id| id.to_string());
         let subject = match message.header("Subject") {
             Some(subject_header) => match subject_header {
@@ -50,25 +79,122 @@ impl ParseReceivedEmail for Received {
             },
             None => None,
         };
-        let body = text_body(&message)?.to_string();
+        let body = match text_body(&message) {
+            Ok(body) => body.to_string(),
+            // The request may be in the subject instead.
+            Err(_) if subject.is_some() => String::new(),
+            Err(error) => return Err(error),
+        };
         let trimmed_body = trim_body(&body);
 
         let mut forecast_request = ParsedForecastRequest::parse(trimmed_body);
 
-        // Default to Html style if format detail is long.
-        if let FormatDetail::Long(long) = &mut forecast_request.request.format.detail {
-            if long.style.is_none() {
-                long.style = Some(LongFormatStyle::Html);
+        // Many users put the request in the subject, with an empty body.
+        if forecast_request.request.position.is_none() && forecast_request.command.is_none() {
+            if let Some(subject) = &subject {
+                let subject_request = ParsedForecastRequest::parse(strip_reply_prefix(subject));
+                if subject_request.request.position.is_some() || subject_request.command.is_some() {
+                    forecast_request = subject_request;
+                }
             }
         }
 
+        let public_key = if forecast_request.command == Some(Command::Key) {
+            public_key(&message, &body)
+        } else {
+            None
+        };
+        let additional_requests = parse_additional_requests(&body);
+        let language = Language::detect(
+            content_language(&message).as_deref(),
+            &format!("{}\n{}", subject.as_deref().unwrap_or_default(), body),
+        );
+
         Ok(Self {
             from,
+            reply_to,
             message_id,
             subject,
             forecast_request,
+            additional_requests,
+            language,
+            public_key,
+        })
+    }
+}
+
+/// The ASCII armored PGP public key pasted in the `body` of the `message`, or otherwise attached
+/// to it (e.g. as a `.asc` file).
+fn public_key(message: &mail_parser::Message, body: &str) -> Option<String> {
+    encryption::extract_public_key(body)
+        .or_else(|| {
+            message.attachments().find_map(|attachment| {
+                std::str::from_utf8(attachment.contents())
+                    .ok()
+                    .and_then(encryption::extract_public_key)
+            })
         })
+        .map(ToOwned::to_owned)
+}
+
+/// The address of the `Reply-To` header of the `message` (if present). When the header contains
+/// multiple addresses, only the first is used. Invalid addresses are ignored, so that the reply is
+/// sent to the `From` address instead.
+fn reply_to_account(message: &mail_parser::Message) -> Option<email::Account> {
+    let address = match message.header("Reply-To")? {
+        mail_parser::HeaderValue::Address(address) => address,
+        mail_parser::HeaderValue::AddressList(list) => list.first()?,
+        mail_parser::HeaderValue::Empty => return None,
+        header => {
+            tracing::warn!("Unexpected `Reply-To` header value: {:?}", header);
+            return None;
+        }
+    };
+    match email::Account::try_from(address) {
+        Ok(account) => Some(account),
+        Err(error) => {
+            tracing::warn!("Ignoring invalid `Reply-To` header address: {:?}", error);
+            None
+        }
+    }
+}
+
+/// The value of the `Content-Language` header of the `message` (if present).
+fn content_language(message: &mail_parser::Message) -> Option<String> {
+    match message.header("Content-Language")? {
+        mail_parser::HeaderValue::Text(text) => Some(text.to_string()),
+        mail_parser::HeaderValue::TextList(list) => Some(list.join(",")),
+        _ => None,
+    }
+}
+
+/// Parse the requests on the lines following the first request line of the `body`, up until
+/// the first line which is not a valid request with a position (e.g. a blank line, or the start
+/// of a quoted reply).
+fn parse_additional_requests(body: &str) -> Vec<ParsedForecastRequest> {
+    body.trim_start()
+        .lines()
+        .skip(1)
+        .map(str::trim)
+        .take_while(|line| !line.is_empty())
+        .map(ParsedForecastRequest::parse)
+        .take_while(|request| request.request.position.is_some() && request.errors.is_empty())
+        .take(MAX_ADDITIONAL_REQUESTS)
+        .collect()
+}
+
+/// Strip the prefixes (e.g. `Re: `) that email clients add to the subject of replies and
+/// forwarded messages.
+fn strip_reply_prefix(subject: &str) -> &str {
+    let mut subject = subject.trim();
+    while let Some((prefix, rest)) = subject.split_once(':') {
+        if ["re", "fw", "fwd"].contains(&prefix.trim().to_lowercase().as_str()) {
+            subject = rest.trim_start();
+        } else {
+            break;
+        }
     }
+    subject
 }
 
 /// Trim the body to only include the request line, removing extra newlines, and quoted replies.
@@ -96,9 +222,9 @@ fn trim_body<'a>(body: &'a str) -> &'a str {
 
 #[cfg(test)]
 mod test {
-    use crate::receive::ParseReceivedEmail;
+    use crate::{gis::Position, language::Language, receive::ParseReceivedEmail, request::Command};
 
-    use super::{trim_body, Received};
+    use super::{parse_additional_requests, strip_reply_prefix, trim_body, Received};
 
     #[test]
     fn test_trim_body_with_reply() {
@@ -121,6 +247,167 @@ wrote:
         assert_eq!("-37.8245005,145.3032913", trimmed);
     }
 
+    #[test]
+    fn test_parse_additional_requests() {
+        let body = "\n-37.8245,145.3032 MS\n-43.5,170.3 ML\n-41.3,174.8\n\nOn Tue wrote:\n> -1,1\n";
+        let requests = parse_additional_requests(body);
+        assert_eq!(2, requests.len());
+        assert_eq!(
+            Some(Position::new(-43.5, 170.3)),
+            requests[0].request.position
+        );
+        assert!(requests[0].request.format.is_some());
+        assert_eq!(
+            Some(Position::new(-41.3, 174.8)),
+            requests[1].request.position
+        );
+
+        let body = "-37.8245005,145.3032913\nOn Tue, Nov 15, 2022 at 5:55 PM wrote:\n-1,1";
+        assert!(parse_additional_requests(body).is_empty());
+    }
+
+    #[test]
+    fn test_strip_reply_prefix() {
+        assert_eq!("-43.5,170.3 ML", strip_reply_prefix("-43.5,170.3 ML"));
+        assert_eq!("-43.5,170.3 ML", strip_reply_prefix("Re: -43.5,170.3 ML"));
+        assert_eq!(
+            "-43.5,170.3 ML",
+            strip_reply_prefix("RE: Fwd: -43.5,170.3 ML")
+        );
+        assert_eq!(
+            "Forecast: tomorrow",
+            strip_reply_prefix("Forecast: tomorrow")
+        );
+    }
+
+    #[test]
+    fn test_parse_email_subject() {
+        let raw_message = r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+Message-ID: <CAH+3HA1rdRyAyLW+-6zkHLW6UV2Y7bbK2h5Yujq-C6ydX3y1AQ@mail.gmail.com>
+Subject: -43.5,170.3 ML
+From: Luke Frisken <l.frisken@gmail.com>
+To: test.email.weather.service@gmail.com
+Content-Type: text/plain; charset="UTF-8"
+
+
+"#;
+
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = Received::parse_email(message).unwrap();
+        let request = &received.forecast_request.request;
+        assert_eq!(Some(Position::new(-43.5, 170.3)), request.position);
+        assert!(request.format.is_some());
+        assert!(received.forecast_request.errors.is_empty());
+    }
+
+    #[test]
+    fn test_parse_email_language() {
+        let raw_message = r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+Subject: -43.5,170.3 ML
+From: Luke Frisken <l.frisken@gmail.com>
+To: test.email.weather.service@gmail.com
+Content-Type: text/plain; charset="UTF-8"
+
+-43.5,170.3 ML
+Merci pour la météo !
+"#;
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = Received::parse_email(message).unwrap();
+        assert_eq!(Some(Language::French), received.language);
+
+        let raw_message = r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+Subject: -43.5,170.3 ML
+From: Luke Frisken <l.frisken@gmail.com>
+To: test.email.weather.service@gmail.com
+Content-Language: de-AT
+Content-Type: text/plain; charset="UTF-8"
+
+-43.5,170.3 ML
+"#;
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = Received::parse_email(message).unwrap();
+        assert_eq!(Some(Language::German), received.language);
+    }
+
+    #[test]
+    fn test_parse_email_reply_to() {
+        let raw_message = r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+Subject: -43.5,170.3 ML
+From: Luke Frisken <l.frisken@gmail.com>
+Reply-To: Hut Warden <warden@example.com>
+To: test.email.weather.service@gmail.com
+Content-Type: text/plain; charset="UTF-8"
+
+-43.5,170.3 ML
+"#;
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = Received::parse_email(message).unwrap();
+        assert_eq!("l.frisken@gmail.com", received.from.email_str());
+        assert_eq!(
+            Some("warden@example.com"),
+            received
+                .reply_to
+                .as_ref()
+                .map(|account| account.email_str())
+        );
+
+        let raw_message = raw_message.replace("Reply-To: Hut Warden <warden@example.com>\n", "");
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = Received::parse_email(message).unwrap();
+        assert!(received.reply_to.is_none());
+    }
+
+    #[test]
+    fn test_parse_email_public_key() {
+        let public_key = std::fs::read_to_string("fixtures/pgp_public_key.asc").unwrap();
+        let raw_message = format!(
+            r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+Subject: Encryption
+From: Luke Frisken <l.frisken@gmail.com>
+To: test.email.weather.service@gmail.com
+Content-Type: multipart/mixed; boundary="0000000000003a5f9c05ed7cd67a"
+
+--0000000000003a5f9c05ed7cd67a
+Content-Type: text/plain; charset="UTF-8"
+
+KEY
+
+--0000000000003a5f9c05ed7cd67a
+Content-Type: application/pgp-keys; name="public.asc"
+Content-Disposition: attachment; filename="public.asc"
+
+{public_key}
+--0000000000003a5f9c05ed7cd67a--
+"#
+        );
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = Received::parse_email(message).unwrap();
+        assert_eq!(Some(Command::Key), received.forecast_request.command);
+        assert_eq!(Some(public_key.trim()), received.public_key.as_deref());
+
+        // The key may also be pasted below the command.
+        let raw_message = format!(
+            r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+From: Luke Frisken <l.frisken@gmail.com>
+To: test.email.weather.service@gmail.com
+Content-Type: text/plain; charset="UTF-8"
+
+key
+
+{public_key}
+"#
+        );
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = Received::parse_email(message).unwrap();
+        assert_eq!(Some(public_key.trim()), received.public_key.as_deref());
+    }
+
     #[test]
     fn test_parse_email() {
         let raw_message = r#"MIME-Version: 1.0
@@ -158,16 +445,22 @@ Content-Type: text/html; charset="UTF-8"
                 "latitude": -37.8245,
                 "longitude": 145.30328
               },
-              "format": {
-                "detail": {
-                  "Short": {
-                    "length_limit": null
-                  }
-                }
-              }
+              "format": null,
+              "profile": null,
+              "past_days": null,
+              "grib": false,
+              "fly": false,
+              "snow_outlook": null,
+              "metar": null,
+              "past_weather": null,
+              "flood": null,
+              "solar": false
             },
-            "errors": []
-          }
+            "errors": [],
+            "command": null
+          },
+          "additional_requests": [],
+          "language": null
         }
         "###);
     }
@@ -222,16 +515,22 @@ padding-left:1ex">An error occurred while processing your request<br>
                 "latitude": -37.8245,
                 "longitude": 145.30328
               },
-              "format": {
-                "detail": {
-                  "Short": {
-                    "length_limit": null
-                  }
-                }
-              }
+              "format": null,
+              "profile": null,
+              "past_days": null,
+              "grib": false,
+              "fly": false,
+              "snow_outlook": null,
+              "metar": null,
+              "past_weather": null,
+              "flood": null,
+              "solar": false
             },
-            "errors": []
-          }
+            "errors": [],
+            "command": null
+          },
+          "additional_requests": [],
+          "language": null
         }
         "###);
     }
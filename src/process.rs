@@ -1,28 +1,58 @@
 //! See [`process_emails()`].
 
 use std::{
-    borrow::Cow,
-    collections::HashSet,
     convert::TryFrom,
     fmt::{Display, Write},
     sync::Arc,
 };
 
-use chrono::NaiveDateTime;
+use chrono::{NaiveDateTime, Timelike};
 use chrono_tz::OffsetComponents;
 use eyre::Context;
 use html_builder::Html5;
-use open_meteo::{GroundLevel, Hourly, HourlyVariable, TimeZone, WeatherCode};
-use serde::{Deserialize, Serialize};
-use tokio::sync::Mutex;
+use open_meteo::{
+    units::{Celsius, KmPerHour, Metres, Millimetres},
+    ApiError, CellSelection, GroundLevel, Hourly, HourlyVariable, PressureLevel, TimeZone,
+    WeatherCode,
+};
+use tokio::sync::{watch, Mutex};
+use tracing::Instrument;
 
 use crate::{
-    forecast_service,
+    about,
+    analytics::{Analytics, DeviceKind, FormatKind, Outcome, RequestEvent},
+    aviation_weather,
+    back_pressure::Overload,
+    changes::{ForecastDigest, PreviousForecasts},
+    circuit_breaker::CircuitBreakerPort,
+    confirmation::{self, Confirmation, SenderConfirmations},
+    correlation::Queued,
+    duplicate::{self, Duplicates},
+    encryption::{self, KeyChange, PublicKeys},
+    feed::{self, Feeds},
+    flood,
+    forecast::{ForecastError, ForecastService, FormattedForecast},
+    forecast_service::{self, ForecastSource, ObtainedForecast},
+    forget,
+    format::{
+        Beaufort, FormatDetail, FormatForecastOptions, FormatMode, LongFormatDetail,
+        LongFormatStyle, ShortFormatDetail, Trend, WindUnit, BEAUFORT_SCALE,
+    },
+    gis::Position,
+    grib, html,
+    latency::{Latency, Stage},
+    options::DynamicOptions,
+    profile::ForecastVariable,
+    queue,
     receive::{Received, ReceivedKind},
-    reply::Reply,
-    request::ParsedForecastRequest,
-    task::run_retry_log_errors,
+    redact::Redact,
+    reply::{self, Reply},
+    reporting,
+    request::Command,
+    subject::{self, ForecastSummary, Weather},
+    task::{run_retry_log_errors, TaskContext},
     time, topo_data_service,
+    webhook::WebhookEvent,
 };
 
 #[derive(PartialEq, Debug)]
@@ -86,72 +116,175 @@ impl Display for WindDirection {
     }
 }
 
-#[derive(Debug, thiserror::Error)]
-enum ProcessEmailError {
-    #[error("No forecast position specified")]
-    NoPosition,
-    #[error(transparent)]
-    Unexpected(#[from] eyre::Error),
-}
-
-trait FormatForecast {
+pub(crate) trait FormatForecast {
     fn format(&self, options: &FormatForecastOptions) -> String;
 }
 
-/// Extra options for short [`FormatDetail`].
-#[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize)]
-pub struct ShortFormatDetail {
-    /// Limit to length of message.
-    pub length_limit: Option<usize>,
-}
+impl WindUnit {
+    /// Format a wind `speed` for the short format.
+    fn short(self, speed: KmPerHour) -> String {
+        match self {
+            Self::KmPerHour => format!("{:.0}", (speed.0 / 10.0).round()),
+            Self::Beaufort => format!("F{}", Beaufort::from_speed(speed).0),
+        }
+    }
 
-/// Extra options for long [`FormatDetail`].
-#[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize)]
-pub struct LongFormatDetail {
-    /// Render the table using html
-    pub style: Option<LongFormatStyle>,
+    /// Format a wind `speed` for the long format.
+    fn long(self, speed: KmPerHour) -> String {
+        match self {
+            Self::KmPerHour => format!("{:.0} km/h", speed.0.round()),
+            Self::Beaufort => Beaufort::from_speed(speed).to_string(),
+        }
+    }
 }
 
-/// Extra options for long [`FormatDetail`].
-#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
-pub enum LongFormatStyle {
-    /// Render table and features using html.
-    Html,
-    /// Render table and features using plain text.
-    PlainText,
+impl Beaufort {
+    /// The force corresponding to the wind `speed`.
+    fn from_speed(KmPerHour(speed): KmPerHour) -> Self {
+        let speed = speed.round();
+        Self(
+            BEAUFORT_SCALE
+                .iter()
+                .rposition(|(min_speed, _)| speed >= *min_speed)
+                .unwrap_or(0),
+        )
+    }
 }
 
-/// What amount of detail to use for formatting the forecast message.
-#[derive(PartialEq, Clone, Debug, Serialize, Deserialize)]
-pub enum FormatDetail {
-    /// As short as possible. e.g. `F24`
-    Short(ShortFormatDetail),
-    /// Expanded with full detail. e.g. `Freezing Level: 2400m`
-    Long(LongFormatDetail),
-}
+impl Trend {
+    /// The trend from `previous` to `current`, when compared with the precision of `step`.
+    /// `None` if the value is steady.
+    fn between(previous: f32, current: f32, step: f32) -> Option<Self> {
+        let previous = (previous / step).round();
+        let current = (current / step).round();
+        if current > previous {
+            Some(Self::Rising)
+        } else if current < previous {
+            Some(Self::Falling)
+        } else {
+            None
+        }
+    }
 
-impl Default for FormatDetail {
-    fn default() -> Self {
-        Self::Short(ShortFormatDetail::default())
+    /// Marker appended to the value in the short format.
+    fn short_marker(self) -> char {
+        match self {
+            Self::Rising => '+',
+            Self::Falling => '-',
+        }
     }
-}
 
-/// Options for formatting the forecast.
-#[derive(Default, PartialEq, Clone, Debug, Serialize, Deserialize)]
-pub struct FormatForecastOptions {
-    /// Detail to apply to formatting the message.
-    pub detail: FormatDetail,
+    /// Marker appended to the value in the long format.
+    fn long_marker(self) -> char {
+        match self {
+            Self::Rising => '↑',
+            Self::Falling => '↓',
+        }
+    }
 }
 
-struct ForecastOutput {
+/// A forecast which has been obtained and is ready to be formatted, see [`build_forecast()`].
+pub(crate) struct ForecastOutput {
     errors: Vec<String>,
     total_timezone_offset: chrono::Duration,
     forecast_elevation: f32,
     terrain_elevation: Option<f32>,
-    rows: Vec<ForecastRow>,
+    /// Whether the forecast is for a grid cell on the sea, see [`is_offshore()`].
+    sea_cell: bool,
+    /// Where the forecast data came from, included at the end of the long format.
+    source: Option<ForecastSource>,
+    body: ForecastBody,
+}
+
+/// Format the `source` of a forecast for the end of the long format, e.g. `Forecast data by
+/// Open-Meteo, GFS run 06Z, generated in 0.6 ms, obtained 2022-12-03 08:35 UTC.`
+fn format_source_long(source: &ForecastSource) -> String {
+    const TIME_FORMAT: &str = "%Y-%m-%d %H:%M UTC";
+    let mut parts = vec![format!("Forecast data by {}", source.provider)];
+    if let Some(model_run) = &source.model_run {
+        parts.push(format!(
+            "{} run {}",
+            model_run.model,
+            model_run.initialised_at.format("%HZ")
+        ));
+    }
+    if let Some(issued_at) = source.issued_at {
+        parts.push(format!("issued {}", issued_at.format(TIME_FORMAT)));
+    }
+    if let Some(generation_time_ms) = source.generation_time_ms {
+        parts.push(format!("generated in {generation_time_ms:.1} ms"));
+    }
+    parts.push(format!(
+        "obtained {}",
+        source.obtained_at.format(TIME_FORMAT)
+    ));
+    format!("{}.", parts.join(", "))
+}
+
+/// The body of a [`ForecastOutput`], depending on the [`FormatMode`].
+enum ForecastBody {
+    Rows(Vec<ForecastRow>),
+    Sounding(Sounding),
+    WindsAloft(WindsAloft),
+}
+
+impl ForecastOutput {
+    /// Summarise the forecast at `position`, see [`ForecastSummary`].
+    pub(crate) fn summary(&self, position: Position) -> ForecastSummary {
+        match &self.body {
+            ForecastBody::Rows(rows) => {
+                let times: Vec<NaiveDateTime> = rows.iter().map(|row| row.time).collect();
+                let weather = rows.iter().flat_map(|row| {
+                    row.parameters
+                        .iter()
+                        .filter_map(|parameter| match parameter {
+                            ForecastParameter::WeatherCode(code) => Some((row.time, *code)),
+                            _ => None,
+                        })
+                });
+                ForecastSummary::new(position, &times, weather)
+            }
+            ForecastBody::Sounding(sounding) => {
+                ForecastSummary::new(position, &[sounding.time], std::iter::empty())
+            }
+            ForecastBody::WindsAloft(winds_aloft) => {
+                ForecastSummary::new(position, &[winds_aloft.time], std::iter::empty())
+            }
+        }
+    }
+
+    /// The digest of the forecast which is compared with the previous forecast, see
+    /// [`ForecastDigest`]. Empty if the forecast doesn't contain rows (e.g. a sounding).
+    pub(crate) fn digest(&self) -> ForecastDigest {
+        let mut digest = ForecastDigest::default();
+        if let ForecastBody::Rows(rows) = &self.body {
+            for row in rows {
+                let mut freezing_level = None;
+                let mut precipitation = None;
+                let mut weather = None;
+                for parameter in &row.parameters {
+                    match parameter {
+                        ForecastParameter::FreezingLevelHeight { height, .. } => {
+                            freezing_level = Some(height.0);
+                        }
+                        ForecastParameter::AccumulatedPrecipitation { amount, .. } => {
+                            precipitation = Some(amount.0);
+                        }
+                        ForecastParameter::WeatherCode(code) => {
+                            weather = Some(Weather::from_code(*code));
+                        }
+                        _ => {}
+                    }
+                }
+                digest.push(row.time.date(), freezing_level, precipitation, weather);
+            }
+        }
+        digest
+    }
 }
 
-fn newline(format_detail: &FormatDetail) -> &str {
+/// The line separator for the `format_detail`.
+pub(crate) fn newline(format_detail: &FormatDetail) -> &str {
     match format_detail {
         FormatDetail::Short(_) => "\n",
         FormatDetail::Long(long) => match long.style {
@@ -195,6 +328,13 @@ impl FormatForecast for ForecastOutput {
             });
         }
 
+        if self.sea_cell {
+            output.push_str(match options.detail {
+                FormatDetail::Short(_) => " SEA",
+                FormatDetail::Long(_) => ", Grid Cell: Sea",
+            });
+        }
+
         if !self.errors.is_empty() {
             if let FormatDetail::Short(_) = options.detail {
                 output.push_str(" E")
@@ -207,92 +347,132 @@ impl FormatForecast for ForecastOutput {
             if let FormatDetail::Long(_) = options.detail {
                 output.push_str("These errors occured:");
                 output.push_str(newline(&options.detail));
+                let is_html = matches!(
+                    &options.detail,
+                    FormatDetail::Long(LongFormatDetail {
+                        style: Some(LongFormatStyle::Html)
+                    })
+                );
                 for error in &self.errors {
-                    output.push_str(&error);
+                    // Errors may quote the user's request.
+                    if is_html {
+                        output.push_str(&html::escape(error));
+                    } else {
+                        output.push_str(error);
+                    }
                     output.push_str(newline(&options.detail));
                 }
                 output.push_str(newline(&options.detail));
             }
         }
 
-        match &options.detail {
-            FormatDetail::Short(short) => {
-                for (i, r) in self.rows.iter().enumerate() {
-                    let row_output = r.format(options);
+        match &self.body {
+            ForecastBody::Rows(rows) => format_rows(&mut output, rows, options),
+            ForecastBody::Sounding(sounding) => sounding.format_into(&mut output, options),
+            ForecastBody::WindsAloft(winds_aloft) => winds_aloft.format_into(&mut output, options),
+        }
 
-                    if let Some(length_limit) = short.length_limit {
-                        if output.len() + row_output.len() > length_limit {
-                            break;
-                        }
-                    }
+        if let (FormatDetail::Long(_), Some(source)) = (&options.detail, &self.source) {
+            output.push_str(newline(&options.detail));
+            output.push_str(newline(&options.detail));
+            output.push_str(&format_source_long(source));
+        }
 
-                    if i > 0 {
-                        output.push_str(newline(&options.detail))
-                    }
-                    output.push_str(&row_output);
-                }
-            }
-            FormatDetail::Long(long) => match long.style {
-                Some(LongFormatStyle::Html) => {
-                    if !self.rows.is_empty() {
-                        let style_attr =
-                            r#"style="border: 1px solid black;border-collapse: collapse;""#;
-                        let mut buffer = html_builder::Buffer::new();
-                        let mut table = buffer.table().attr(style_attr);
-                        let mut header_row = table.tr();
-
-                        let mut th = header_row.th().attr(style_attr);
-                        th.write_str("Time").unwrap();
-
-                        let r = self.rows.first().expect("expected at least one row");
-                        for p in &r.parameters {
-                            let mut th = header_row.th().attr(style_attr);
-                            th.write_str(&p.header()).unwrap();
-                        }
+        output
+    }
+}
 
-                        for r in &self.rows {
-                            let mut tr = table.tr();
+/// Append the short format `lines` to `output`, stopping before the length limit is exceeded.
+fn push_short_lines(
+    output: &mut String,
+    lines: impl Iterator<Item = String>,
+    short: &ShortFormatDetail,
+    options: &FormatForecastOptions,
+) {
+    for (i, line) in lines.enumerate() {
+        if let Some(length_limit) = short.length_limit {
+            if output.len() + line.len() > length_limit {
+                break;
+            }
+        }
 
-                            let mut td = tr.td().attr(style_attr);
-                            write!(td, "{}", r.time).unwrap();
+        if i > 0 {
+            output.push_str(newline(&options.detail))
+        }
+        output.push_str(&line);
+    }
+}
 
-                            for p in &r.parameters {
-                                let mut td = tr.td().attr(style_attr);
-                                td.write_str(&p.format(options)).unwrap();
-                            }
-                        }
+/// Render a table with the `columns` and `records` for the long format, using `style`.
+pub(crate) fn format_table(
+    style: Option<&LongFormatStyle>,
+    columns: Vec<String>,
+    records: Vec<Vec<String>>,
+) -> String {
+    if records.is_empty() {
+        return String::new();
+    }
+    match style {
+        Some(LongFormatStyle::Html) => {
+            let style_attr = r#"style="border: 1px solid black;border-collapse: collapse;""#;
+            let mut buffer = html_builder::Buffer::new();
+            let mut table = buffer.table().attr(style_attr);
+            let mut header_row = table.tr();
+
+            for column in &columns {
+                let mut th = header_row.th().attr(style_attr);
+                th.write_str(&html::escape(column)).unwrap();
+            }
 
-                        output.push_str(&buffer.finish());
-                    }
+            for record in &records {
+                let mut tr = table.tr();
+                for cell in record {
+                    let mut td = tr.td().attr(style_attr);
+                    td.write_str(&html::escape(cell)).unwrap();
                 }
-                _ => {
-                    if !self.rows.is_empty() {
-                        let mut builder = tabled::builder::Builder::new();
-
-                        for r in &self.rows {
-                            let mut record = vec![r.time.to_string()];
-                            for p in &r.parameters {
-                                record.push(p.format(options))
-                            }
-
-                            builder.add_record(record);
-                        }
+            }
 
-                        let r = self.rows.first().expect("expected at least one row");
-                        let mut columns = vec!["Time".to_string()];
-                        for p in &r.parameters {
-                            columns.push(p.header());
-                        }
-                        builder.set_columns(columns);
-                        let mut table = builder.build();
-                        table.with(tabled::Style::ascii());
-                        output.push_str(&table.to_string());
-                    }
-                }
-            },
+            buffer.finish()
+        }
+        _ => {
+            let mut builder = tabled::builder::Builder::new();
+            for record in records {
+                builder.add_record(record);
+            }
+            builder.set_columns(columns);
+            let mut table = builder.build();
+            table.with(tabled::Style::ascii());
+            table.to_string()
         }
+    }
+}
 
-        output
+/// Append the forecast `rows` to `output`.
+fn format_rows(output: &mut String, rows: &[ForecastRow], options: &FormatForecastOptions) {
+    match &options.detail {
+        FormatDetail::Short(short) => {
+            push_short_lines(
+                output,
+                rows.iter().map(|r| r.format(options)),
+                short,
+                options,
+            );
+        }
+        FormatDetail::Long(long) => {
+            let mut columns = vec!["Time".to_string()];
+            if let Some(r) = rows.first() {
+                columns.extend(r.parameters.iter().flat_map(ForecastParameter::headers));
+            }
+            let records = rows
+                .iter()
+                .map(|r| {
+                    let mut record = vec![options.language.format_time(r.time)];
+                    record.extend(r.parameters.iter().flat_map(|p| p.long_cells(options)));
+                    record
+                })
+                .collect();
+            output.push_str(&format_table(long.style.as_ref(), columns, records));
+        }
     }
 }
 
@@ -316,20 +496,94 @@ impl FormatForecast for ForecastRow {
 
 enum ForecastParameter {
     WeatherCode(WeatherCode),
-    FreezingLevelHeight(f32),
-    Wind10m { speed: f32, direction: f32 },
-    AccumulatedPrecipitation(f32),
+    FreezingLevelHeight {
+        height: Metres,
+        /// Trend compared with the previous row, `None` if steady or this is the first row.
+        trend: Option<Trend>,
+    },
+    Wind10m {
+        speed: KmPerHour,
+        /// `None` if gusts are not available from the forecast service.
+        gusts: Option<KmPerHour>,
+        direction: f32,
+    },
+    AccumulatedPrecipitation {
+        amount: Millimetres,
+        /// Maximum probability (percentage) of precipitation during the accumulation period,
+        /// `None` if not available from the forecast service.
+        probability: Option<f32>,
+    },
+    /// Cloud cover percentage of each level.
+    CloudCover {
+        low: f32,
+        mid: f32,
+        high: f32,
+    },
+    /// Relative humidity percentage.
+    RelativeHumidity(f32),
+    Dewpoint(Celsius),
+    PressureMsl {
+        /// Pressure in hPa.
+        pressure: f32,
+        /// Tendency over the previous 3 hours, `None` if steady or unknown.
+        tendency: Option<Trend>,
+    },
+    /// Peak UV index of the (local) day.
+    UvIndex(f32),
+    Visibility(Metres),
+    /// Ambient air temperature and the perceived (feels-like) temperature, including wind chill.
+    ApparentTemperature {
+        temperature: Celsius,
+        apparent: Celsius,
+    },
 }
 
 impl ForecastParameter {
-    fn header(&self) -> String {
+    /// Headers of the columns for this parameter in the long format table.
+    fn headers(&self) -> Vec<String> {
+        let headers: &[&str] = match self {
+            ForecastParameter::WeatherCode(_) => &["Weather Code"],
+            ForecastParameter::FreezingLevelHeight { .. } => &["Freezing Level"],
+            ForecastParameter::Wind10m { gusts: None, .. } => &["Wind"],
+            ForecastParameter::Wind10m { gusts: Some(_), .. } => &["Wind", "Gusts"],
+            ForecastParameter::AccumulatedPrecipitation {
+                probability: None, ..
+            } => &["Precipitation"],
+            ForecastParameter::AccumulatedPrecipitation {
+                probability: Some(_),
+                ..
+            } => &["Precipitation", "Probability"],
+            ForecastParameter::CloudCover { .. } => &["Cloud (Low/Mid/High)"],
+            ForecastParameter::RelativeHumidity(_) => &["Humidity"],
+            ForecastParameter::Dewpoint(_) => &["Dewpoint"],
+            ForecastParameter::PressureMsl { .. } => &["Pressure"],
+            ForecastParameter::UvIndex(_) => &["Peak UV"],
+            ForecastParameter::Visibility(_) => &["Visibility"],
+            ForecastParameter::ApparentTemperature { .. } => &["Temperature", "Feels Like"],
+        };
+        headers.iter().map(ToString::to_string).collect()
+    }
+
+    /// Cells of the columns (see [`ForecastParameter::headers()`]) for this parameter in the long
+    /// format table.
+    fn long_cells(&self, options: &FormatForecastOptions) -> Vec<String> {
         match self {
-            ForecastParameter::WeatherCode(_) => "Weather Code",
-            ForecastParameter::FreezingLevelHeight(_) => "Freezing Level",
-            ForecastParameter::Wind10m { .. } => "Wind",
-            ForecastParameter::AccumulatedPrecipitation(_) => "Precipitation",
+            ForecastParameter::Wind10m {
+                gusts: Some(gusts), ..
+            } => vec![self.format(options), options.wind_unit.long(*gusts)],
+            ForecastParameter::AccumulatedPrecipitation {
+                probability: Some(probability),
+                ..
+            } => vec![self.format(options), format!("{:.0}%", probability.round())],
+            ForecastParameter::ApparentTemperature {
+                temperature: Celsius(temperature),
+                apparent: Celsius(apparent),
+            } => vec![
+                format!("{:.0}°C", temperature.round()),
+                format!("{:.0}°C", apparent.round()),
+            ],
+            _ => vec![self.format(options)],
         }
-        .to_string()
     }
 }
 
@@ -338,38 +592,493 @@ impl FormatForecast for ForecastParameter {
         match self {
             ForecastParameter::WeatherCode(code) => match options.detail {
                 FormatDetail::Short(_) => format!("C{:.0}", *code as u8),
+                FormatDetail::Long(LongFormatDetail {
+                    style: Some(LongFormatStyle::Html),
+                }) => format!("{} {}", code.emoji(), code),
                 FormatDetail::Long(_) => format!("{}", code),
             },
 
-            ForecastParameter::FreezingLevelHeight(height) => match options.detail {
-                FormatDetail::Short(_) => format!("F{:.0}", (height / 100.0).round()),
-                FormatDetail::Long(_) => format!("{:.0}m", height.round()),
+            ForecastParameter::FreezingLevelHeight {
+                height: Metres(height),
+                trend,
+            } => match options.detail {
+                FormatDetail::Short(_) => {
+                    let mut output = format!("F{:.0}", (height / 100.0).round());
+                    output.extend(trend.map(Trend::short_marker));
+                    output
+                }
+                FormatDetail::Long(_) => {
+                    let mut output = format!("{:.0}m", height.round());
+                    if let Some(trend) = trend {
+                        output.push(' ');
+                        output.push(trend.long_marker());
+                    }
+                    output
+                }
+            },
+            ForecastParameter::Wind10m {
+                speed,
+                gusts,
+                direction,
+            } => match options.detail {
+                FormatDetail::Short(_) => {
+                    let gusts = gusts
+                        .map(|gusts| format!("g{}", options.wind_unit.short(gusts)))
+                        .unwrap_or_default();
+                    format!(
+                        "W{}{}@{:.0}",
+                        options.wind_unit.short(*speed),
+                        gusts,
+                        (direction / 10.0).round()
+                    )
+                }
+                FormatDetail::Long(_) => {
+                    format!(
+                        "{} at {:.0}°",
+                        options.wind_unit.long(*speed),
+                        direction.round()
+                    )
+                }
+            },
+            ForecastParameter::AccumulatedPrecipitation {
+                amount: Millimetres(precip),
+                probability,
+            } => match options.detail {
+                FormatDetail::Short(_) => {
+                    let mut output = format!("P{:.0}", precip.round());
+                    if let Some(probability) = probability {
+                        output.push_str(&format!("/{:.0}%", probability.round()));
+                    }
+                    output
+                }
+                FormatDetail::Long(_) => format!("{:.1}mm", precip.round()),
             },
-            ForecastParameter::Wind10m { speed, direction } => match options.detail {
+            ForecastParameter::CloudCover { low, mid, high } => match options.detail {
                 FormatDetail::Short(_) => format!(
-                    "W{:.0}@{:.0}",
-                    (speed / 10.0).round(),
-                    (direction / 10.0).round()
+                    "CL{:.0}/{:.0}/{:.0}",
+                    (low / 10.0).round(),
+                    (mid / 10.0).round(),
+                    (high / 10.0).round()
+                ),
+                FormatDetail::Long(_) => format!(
+                    "{:.0}%/{:.0}%/{:.0}%",
+                    low.round(),
+                    mid.round(),
+                    high.round()
                 ),
+            },
+            ForecastParameter::RelativeHumidity(humidity) => match options.detail {
+                FormatDetail::Short(_) => format!("H{:.0}", (humidity / 10.0).round()),
+                FormatDetail::Long(_) => format!("{:.0}%", humidity.round()),
+            },
+            ForecastParameter::Dewpoint(Celsius(dewpoint)) => match options.detail {
+                FormatDetail::Short(_) => format!("D{:.0}", dewpoint.round()),
+                FormatDetail::Long(_) => format!("{:.0}°C", dewpoint.round()),
+            },
+            ForecastParameter::PressureMsl { pressure, tendency } => match options.detail {
+                FormatDetail::Short(_) => {
+                    let mut output = format!("B{:.0}", pressure.round());
+                    output.extend(tendency.map(Trend::short_marker));
+                    output
+                }
                 FormatDetail::Long(_) => {
-                    format!("{:.0} km/h at {:.0}°", speed.round(), direction.round())
+                    let mut output = format!("{:.0}hPa", pressure.round());
+                    if let Some(tendency) = tendency {
+                        output.push(' ');
+                        output.push(tendency.long_marker());
+                    }
+                    output
                 }
             },
-            ForecastParameter::AccumulatedPrecipitation(precip) => match options.detail {
-                FormatDetail::Short(_) => format!("P{:.0}", precip.round()),
-                FormatDetail::Long(_) => format!("{:.1}mm", precip.round()),
+            ForecastParameter::UvIndex(uv_index) => match options.detail {
+                FormatDetail::Short(_) => format!("U{:.0}", uv_index.round()),
+                FormatDetail::Long(_) => format!("{:.0}", uv_index.round()),
+            },
+            ForecastParameter::Visibility(Metres(visibility)) => {
+                let km = visibility / 1000.0;
+                match options.detail {
+                    // Below 1km the decimal is significant (fog).
+                    FormatDetail::Short(_) if km < 1.0 => format!("V{:.1}", km),
+                    FormatDetail::Short(_) => format!("V{:.0}", km.round()),
+                    FormatDetail::Long(_) => format!("{:.1} km", km),
+                }
+            }
+            ForecastParameter::ApparentTemperature {
+                temperature: Celsius(temperature),
+                apparent: Celsius(apparent),
+            } => match options.detail {
+                FormatDetail::Short(_) => {
+                    format!("T{:.0}/{:.0}", temperature.round(), apparent.round())
+                }
+                FormatDetail::Long(_) => format!(
+                    "{:.0}°C (feels like {:.0}°C)",
+                    temperature.round(),
+                    apparent.round()
+                ),
             },
         }
     }
 }
 
-/// Validate the request from a received email, report any problems via logging, and transform it to a valid
-/// request.
-fn validate_transform_request(received_email: &ReceivedKind) -> Cow<'_, ParsedForecastRequest> {
+/// The pressure levels included in a [`FormatMode::Sounding`] forecast, from the surface upwards.
+pub(crate) const SOUNDING_PRESSURE_LEVELS: &[PressureLevel] = &[
+    PressureLevel::L1000,
+    PressureLevel::L925,
+    PressureLevel::L850,
+    PressureLevel::L700,
+    PressureLevel::L600,
+    PressureLevel::L500,
+    PressureLevel::L400,
+    PressureLevel::L300,
+    PressureLevel::L250,
+    PressureLevel::L200,
+];
+
+/// The hourly variables that need to be requested from Open-Meteo for a sounding.
+fn sounding_hourly_variables() -> impl Iterator<Item = HourlyVariable> {
+    SOUNDING_PRESSURE_LEVELS.iter().flat_map(|level| {
+        [
+            HourlyVariable::PressureGeopotentialHeight(*level),
+            HourlyVariable::PressureTemperature(*level),
+            HourlyVariable::PressureDewpoint(*level),
+            HourlyVariable::PressureWindSpeed(*level),
+            HourlyVariable::PressureWindDirection(*level),
+        ]
+    })
+}
+
+/// Conditions at each pressure level above the forecast position at a single time, see
+/// [`FormatMode::Sounding`].
+struct Sounding {
+    time: NaiveDateTime,
+    levels: Vec<SoundingLevel>,
+}
+
+struct SoundingLevel {
+    pressure: PressureLevel,
+    height: Metres,
+    temperature: Celsius,
+    dewpoint: Celsius,
+    wind_speed: KmPerHour,
+    wind_direction: f32,
+}
+
+impl Sounding {
+    /// Obtain the sounding at index `i` of the `hourly` forecast, including only the levels above
+    /// `surface_elevation`. Levels which are missing any values are skipped.
+    fn from_hourly(hourly: &Hourly, i: usize, surface_elevation: f32) -> eyre::Result<Self> {
+        fn value<T: Copy>(values: Option<&Vec<T>>, i: usize) -> Option<T> {
+            values?.get(i).copied()
+        }
+
+        let levels = SOUNDING_PRESSURE_LEVELS
+            .iter()
+            .filter_map(|level| {
+                Some(SoundingLevel {
+                    pressure: *level,
+                    height: value(hourly.pressure_geopotential_height.value(level), i)?,
+                    temperature: value(hourly.pressure_temperature.value(level), i)?,
+                    dewpoint: value(hourly.pressure_dewpoint.value(level), i)?,
+                    wind_speed: value(hourly.pressure_wind_speed.value(level), i)?,
+                    wind_direction: value(hourly.pressure_wind_direction.value(level), i)?,
+                })
+            })
+            .filter(|level| level.height.0 >= surface_elevation)
+            .collect();
+
+        Ok(Self {
+            time: *hourly
+                .time
+                .get(i)
+                .ok_or_else(|| eyre::eyre!("expected forecast time {} to be present", i))?,
+            levels,
+        })
+    }
+
+    /// Append the sounding to `output`.
+    fn format_into(&self, output: &mut String, options: &FormatForecastOptions) {
+        match &options.detail {
+            FormatDetail::Short(short) => {
+                let time = self.time.format("%dT%H").to_string();
+                let levels = self.levels.iter().map(|level| {
+                    format!(
+                        "{} H{:.0} T{:.0}/{:.0} W{}@{:.0}",
+                        level.pressure as u32,
+                        (level.height.0 / 100.0).round(),
+                        level.temperature.0.round(),
+                        level.dewpoint.0.round(),
+                        options.wind_unit.short(level.wind_speed),
+                        (level.wind_direction / 10.0).round()
+                    )
+                });
+                push_short_lines(output, std::iter::once(time).chain(levels), short, options);
+            }
+            FormatDetail::Long(long) => {
+                output.push_str(&format!(
+                    "Sounding at {}",
+                    options.language.format_time(self.time)
+                ));
+                output.push_str(newline(&options.detail));
+                let columns = ["Pressure", "Height", "Temperature", "Dewpoint", "Wind"]
+                    .into_iter()
+                    .map(ToString::to_string)
+                    .collect();
+                let records = self
+                    .levels
+                    .iter()
+                    .map(|level| {
+                        vec![
+                            format!("{}hPa", level.pressure as u32),
+                            format!("{:.0}m", level.height.0.round()),
+                            format!("{:.0}°C", level.temperature.0.round()),
+                            format!("{:.0}°C", level.dewpoint.0.round()),
+                            format!(
+                                "{} at {:.0}°",
+                                options.wind_unit.long(level.wind_speed),
+                                level.wind_direction.round()
+                            ),
+                        ]
+                    })
+                    .collect();
+                output.push_str(&format_table(long.style.as_ref(), columns, records));
+            }
+        }
+    }
+}
+
+/// The altitudes (feet above mean sea level) included in a [`FormatMode::WindsAloft`] forecast,
+/// the same as those used in the FB winds aloft forecasts.
+pub(crate) const WINDS_ALOFT_ALTITUDES: &[u32] =
+    &[3000, 6000, 9000, 12000, 18000, 24000, 30000, 34000, 39000];
+
+/// The pressure levels that the [`WINDS_ALOFT_ALTITUDES`] are interpolated between, from the
+/// surface upwards.
+const WINDS_ALOFT_PRESSURE_LEVELS: &[PressureLevel] = &[
+    PressureLevel::L1000,
+    PressureLevel::L925,
+    PressureLevel::L850,
+    PressureLevel::L700,
+    PressureLevel::L600,
+    PressureLevel::L500,
+    PressureLevel::L400,
+    PressureLevel::L300,
+    PressureLevel::L250,
+    PressureLevel::L200,
+    PressureLevel::L150,
+];
+
+/// Altitudes closer than this to the surface (in feet) are omitted from a winds aloft forecast.
+const WINDS_ALOFT_SURFACE_CLEARANCE_FT: f32 = 1500.0;
+
+/// Temperatures are omitted from winds aloft forecasts at and below this altitude (in feet).
+const WINDS_ALOFT_NO_TEMPERATURE_FT: u32 = 3000;
+
+/// Temperatures above this altitude (in feet) are always negative, so the sign is omitted.
+const WINDS_ALOFT_UNSIGNED_TEMPERATURE_FT: u32 = 24000;
+
+/// Metres in a foot.
+const METRES_PER_FOOT: f32 = 0.3048;
+
+/// Kilometres per hour in a knot.
+const KM_PER_HOUR_PER_KNOT: f32 = 1.852;
+
+/// The hourly variables that need to be requested from Open-Meteo for winds aloft.
+fn winds_aloft_hourly_variables() -> impl Iterator<Item = HourlyVariable> {
+    WINDS_ALOFT_PRESSURE_LEVELS.iter().flat_map(|level| {
+        [
+            HourlyVariable::PressureGeopotentialHeight(*level),
+            HourlyVariable::PressureTemperature(*level),
+            HourlyVariable::PressureWindSpeed(*level),
+            HourlyVariable::PressureWindDirection(*level),
+        ]
+    })
+}
+
+/// Wind and temperature at standard altitudes above the forecast position at a single time, see
+/// [`FormatMode::WindsAloft`].
+struct WindsAloft {
+    time: NaiveDateTime,
+    levels: Vec<WindsAloftLevel>,
+}
+
+struct WindsAloftLevel {
+    /// Altitude in feet above mean sea level.
+    altitude: u32,
+    temperature: Celsius,
+    wind_speed: KmPerHour,
+    wind_direction: f32,
+}
+
+impl WindsAloftLevel {
+    /// Interpolate the level at `altitude` (in feet) between the pressure levels `below` and
+    /// `above`, with winds interpolated using their components. Each level is `(height,
+    /// temperature, wind speed, wind direction)`.
+    fn interpolate(
+        altitude: u32,
+        below: (Metres, Celsius, KmPerHour, f32),
+        above: (Metres, Celsius, KmPerHour, f32),
+    ) -> Self {
+        #[allow(clippy::cast_precision_loss)]
+        let height = altitude as f32 * METRES_PER_FOOT;
+        let fraction = if (above.0 .0 - below.0 .0).abs() < f32::EPSILON {
+            0.0
+        } else {
+            (height - below.0 .0) / (above.0 .0 - below.0 .0)
+        };
+        let lerp = |a: f32, b: f32| a + (b - a) * fraction;
+
+        // Components of the wind in the direction it is blowing towards.
+        let components = |speed: KmPerHour, direction: f32| {
+            let (sin, cos) = direction.to_radians().sin_cos();
+            (-speed.0 * sin, -speed.0 * cos)
+        };
+        let (below_u, below_v) = components(below.2, below.3);
+        let (above_u, above_v) = components(above.2, above.3);
+        let (u, v) = (lerp(below_u, above_u), lerp(below_v, above_v));
+
+        Self {
+            altitude,
+            temperature: Celsius(lerp(below.1 .0, above.1 .0)),
+            wind_speed: KmPerHour(u.hypot(v)),
+            wind_direction: (-u).atan2(-v).to_degrees().rem_euclid(360.0),
+        }
+    }
+
+    /// The level encoded in the style of an FB winds aloft forecast, e.g. `2725+05`:
+    ///
+    /// + The wind direction in tens of degrees true, followed by the speed in knots. Speeds of 100
+    ///   to 199 knots are encoded by adding 50 to the direction and subtracting 100 from the
+    ///   speed. Light and variable winds (less than 5 knots) are encoded as `9900`.
+    /// + The temperature in degrees Celsius, which is omitted at 3000ft, and which has its
+    ///   (negative) sign omitted above 24000ft.
+    fn fb(&self) -> String {
+        let knots = (self.wind_speed.0 / KM_PER_HOUR_PER_KNOT).round();
+        let wind = if knots < 5.0 {
+            "9900".to_owned()
+        } else {
+            let mut direction = (self.wind_direction / 10.0).round();
+            if direction < 1.0 {
+                direction = 36.0;
+            }
+            let mut speed = knots;
+            if speed >= 100.0 {
+                direction += 50.0;
+                speed = (speed - 100.0).min(99.0);
+            }
+            format!("{:02.0}{:02.0}", direction, speed)
+        };
+
+        let temperature = self.temperature.0.round();
+        if self.altitude <= WINDS_ALOFT_NO_TEMPERATURE_FT {
+            wind
+        } else if self.altitude > WINDS_ALOFT_UNSIGNED_TEMPERATURE_FT {
+            format!("{wind}{:02.0}", temperature.abs())
+        } else {
+            let sign = if temperature >= 0.0 { '+' } else { '-' };
+            format!("{wind}{sign}{:02.0}", temperature.abs())
+        }
+    }
+}
+
+impl WindsAloft {
+    /// Obtain the winds aloft at index `i` of the `hourly` forecast, including only the altitudes
+    /// which are at least [`WINDS_ALOFT_SURFACE_CLEARANCE_FT`] above `surface_elevation`, and
+    /// within the range of the available pressure levels.
+    fn from_hourly(hourly: &Hourly, i: usize, surface_elevation: f32) -> eyre::Result<Self> {
+        fn value<T: Copy>(values: Option<&Vec<T>>, i: usize) -> Option<T> {
+            values?.get(i).copied()
+        }
+
+        let pressure_levels: Vec<(Metres, Celsius, KmPerHour, f32)> = WINDS_ALOFT_PRESSURE_LEVELS
+            .iter()
+            .filter_map(|level| {
+                Some((
+                    value(hourly.pressure_geopotential_height.value(level), i)?,
+                    value(hourly.pressure_temperature.value(level), i)?,
+                    value(hourly.pressure_wind_speed.value(level), i)?,
+                    value(hourly.pressure_wind_direction.value(level), i)?,
+                ))
+            })
+            .collect();
+
+        let minimum_height = surface_elevation + WINDS_ALOFT_SURFACE_CLEARANCE_FT * METRES_PER_FOOT;
+        let levels = WINDS_ALOFT_ALTITUDES
+            .iter()
+            .filter_map(|altitude| {
+                #[allow(clippy::cast_precision_loss)]
+                let height = *altitude as f32 * METRES_PER_FOOT;
+                if height < minimum_height {
+                    return None;
+                }
+                let (below, above) = pressure_levels
+                    .windows(2)
+                    .map(|window| (window[0], window[1]))
+                    .find(|(below, above)| below.0 .0 <= height && height <= above.0 .0)?;
+                Some(WindsAloftLevel::interpolate(*altitude, below, above))
+            })
+            .collect();
+
+        Ok(Self {
+            time: *hourly
+                .time
+                .get(i)
+                .ok_or_else(|| eyre::eyre!("expected forecast time {} to be present", i))?,
+            levels,
+        })
+    }
+
+    /// Append the winds aloft to `output`. Wind speeds are always in knots, as is the convention
+    /// in aviation.
+    fn format_into(&self, output: &mut String, options: &FormatForecastOptions) {
+        match &options.detail {
+            FormatDetail::Short(short) => {
+                let time = format!("FB {}", self.time.format("%dT%H"));
+                let levels = self
+                    .levels
+                    .iter()
+                    .map(|level| format!("{} {}", level.altitude, level.fb()));
+                push_short_lines(output, std::iter::once(time).chain(levels), short, options);
+            }
+            FormatDetail::Long(long) => {
+                output.push_str(&format!(
+                    "Winds aloft at {}",
+                    options.language.format_time(self.time)
+                ));
+                output.push_str(newline(&options.detail));
+                let columns = ["Altitude", "FB", "Wind", "Temperature"]
+                    .into_iter()
+                    .map(ToString::to_string)
+                    .collect();
+                let records = self
+                    .levels
+                    .iter()
+                    .map(|level| {
+                        vec![
+                            format!("{}ft", level.altitude),
+                            level.fb(),
+                            format!(
+                                "{:.0} kt at {:.0}°",
+                                (level.wind_speed.0 / KM_PER_HOUR_PER_KNOT).round(),
+                                level.wind_direction.round()
+                            ),
+                            format!("{:.0}°C", level.temperature.0.round()),
+                        ]
+                    })
+                    .collect();
+                output.push_str(&format_table(long.style.as_ref(), columns, records));
+            }
+        }
+    }
+}
+
+/// Validate the `format` selected for the request from a received email, imposing the limitations
+/// of the device which sent it, and report any problems via logging.
+fn validate_transform_format(
+    received_email: &ReceivedKind,
+    mut format: FormatForecastOptions,
+) -> FormatForecastOptions {
     match received_email {
-        ReceivedKind::Inreach(email) => {
-            let mut request = email.forecast_request.clone();
-            let format = &mut request.request.format;
+        ReceivedKind::Inreach(_) => {
             match &mut format.detail {
                 FormatDetail::Short(short) => {
                     // Impose a message length limit of 160 characters for inreach.
@@ -394,42 +1103,260 @@ fn validate_transform_request(received_email: &ReceivedKind) -> Cow<'_, ParsedFo
                     format.detail = FormatDetail::Short(ShortFormatDetail::default());
                 }
             }
+        }
+        ReceivedKind::Plain(_) => {
+            format.language = received_email.language().unwrap_or_default();
+            // Default to Html style if format detail is long.
+            if let FormatDetail::Long(long) = &mut format.detail {
+                if long.style.is_none() {
+                    long.style = Some(LongFormatStyle::Html);
+                }
+            }
+        }
+    }
+
+    format
+}
+
+/// Obtain the hourly `values` for a variable named `name`, checking that they are present and that
+/// there is a value for each of the `len` forecast times.
+fn hourly_values<'a, T>(values: Option<&'a [T]>, name: &str, len: usize) -> eyre::Result<&'a [T]> {
+    let values = values.ok_or_else(|| eyre::eyre!("expected {} to be present", name))?;
+    if values.len() != len {
+        return Err(eyre::eyre!("forecast hourly array lengths don't match"));
+    }
+    Ok(values)
+}
+
+/// Whether the values for `variable` are present in the `hourly` forecast.
+fn variable_available(variable: ForecastVariable, hourly: &Hourly) -> bool {
+    match variable {
+        ForecastVariable::WeatherCode => hourly.weather_code.is_some(),
+        ForecastVariable::FreezingLevelHeight => hourly.freezing_level_height.is_some(),
+        ForecastVariable::Wind10m => {
+            hourly.wind_speed.value(&GroundLevel::L10).is_some()
+                && hourly.wind_direction.value(&GroundLevel::L10).is_some()
+        }
+        ForecastVariable::Precipitation => hourly.precipitation.is_some(),
+        ForecastVariable::CloudCover => {
+            hourly.cloud_cover_low.is_some()
+                && hourly.cloud_cover_mid.is_some()
+                && hourly.cloud_cover_high.is_some()
+        }
+        ForecastVariable::RelativeHumidity => hourly.relative_humidity_2m.is_some(),
+        ForecastVariable::Dewpoint => hourly.dewpoint_2m.is_some(),
+        ForecastVariable::PressureMsl => hourly.pressure_msl.is_some(),
+        ForecastVariable::UvIndex => hourly.uv_index.is_some(),
+        ForecastVariable::Visibility => hourly.visibility.is_some(),
+        ForecastVariable::ApparentTemperature => {
+            hourly.temperature_2m.is_some() && hourly.apparent_temperature.is_some()
+        }
+    }
+}
 
-            Cow::Owned(request)
+/// Number of hours over which the tendency of [`ForecastVariable::PressureMsl`] is measured.
+const PRESSURE_TENDENCY_HOURS: usize = 3;
+
+/// Obtain the [`ForecastParameter`] for `variable` at index `i` of the `hourly` forecast.
+fn forecast_parameter(
+    variable: ForecastVariable,
+    hourly: &Hourly,
+    i: usize,
+    acc_precipitation: Millimetres,
+) -> eyre::Result<ForecastParameter> {
+    let len = hourly.time.len();
+    Ok(match variable {
+        ForecastVariable::WeatherCode => ForecastParameter::WeatherCode(
+            hourly_values(hourly.weather_code.as_deref(), "weather_code", len)?[i],
+        ),
+        ForecastVariable::FreezingLevelHeight => ForecastParameter::FreezingLevelHeight {
+            height: hourly_values(
+                hourly.freezing_level_height.as_deref(),
+                "freezing_level_height",
+                len,
+            )?[i],
+            trend: None,
+        },
+        ForecastVariable::Wind10m => ForecastParameter::Wind10m {
+            speed: hourly_values(
+                hourly
+                    .wind_speed
+                    .value(&GroundLevel::L10)
+                    .map(Vec::as_slice),
+                "wind_speed_10m",
+                len,
+            )?[i],
+            gusts: match hourly.wind_gusts_10m.as_deref() {
+                Some(gusts) => Some(hourly_values(Some(gusts), "wind_gusts_10m", len)?[i]),
+                None => None,
+            },
+            direction: hourly_values(
+                hourly
+                    .wind_direction
+                    .value(&GroundLevel::L10)
+                    .map(Vec::as_slice),
+                "wind_direction_10m",
+                len,
+            )?[i],
+        },
+        ForecastVariable::Precipitation => ForecastParameter::AccumulatedPrecipitation {
+            amount: acc_precipitation,
+            probability: None,
+        },
+        ForecastVariable::CloudCover => ForecastParameter::CloudCover {
+            low: hourly_values(hourly.cloud_cover_low.as_deref(), "cloud_cover_low", len)?[i],
+            mid: hourly_values(hourly.cloud_cover_mid.as_deref(), "cloud_cover_mid", len)?[i],
+            high: hourly_values(hourly.cloud_cover_high.as_deref(), "cloud_cover_high", len)?[i],
+        },
+        ForecastVariable::RelativeHumidity => ForecastParameter::RelativeHumidity(
+            hourly_values(
+                hourly.relative_humidity_2m.as_deref(),
+                "relative_humidity_2m",
+                len,
+            )?[i],
+        ),
+        ForecastVariable::Dewpoint => ForecastParameter::Dewpoint(
+            hourly_values(hourly.dewpoint_2m.as_deref(), "dewpoint_2m", len)?[i],
+        ),
+        ForecastVariable::PressureMsl => {
+            let pressure = hourly_values(hourly.pressure_msl.as_deref(), "pressure_msl", len)?;
+            ForecastParameter::PressureMsl {
+                pressure: pressure[i],
+                tendency: i
+                    .checked_sub(PRESSURE_TENDENCY_HOURS)
+                    .and_then(|previous_i| Trend::between(pressure[previous_i], pressure[i], 1.0)),
+            }
         }
-        _ => Cow::Borrowed(&received_email.forecast_request()),
+        ForecastVariable::UvIndex => {
+            let uv_index = hourly_values(hourly.uv_index.as_deref(), "uv_index", len)?;
+            let date = hourly.time[i].date();
+            ForecastParameter::UvIndex(
+                hourly
+                    .time
+                    .iter()
+                    .zip(uv_index)
+                    .filter(|(time, _)| time.date() == date)
+                    .map(|(_, uv_index)| *uv_index)
+                    .fold(0.0, f32::max),
+            )
+        }
+        ForecastVariable::Visibility => ForecastParameter::Visibility(
+            hourly_values(hourly.visibility.as_deref(), "visibility", len)?[i],
+        ),
+        ForecastVariable::ApparentTemperature => ForecastParameter::ApparentTemperature {
+            temperature: hourly_values(hourly.temperature_2m.as_deref(), "temperature_2m", len)?[i],
+            apparent: hourly_values(
+                hourly.apparent_temperature.as_deref(),
+                "apparent_temperature",
+                len,
+            )?[i],
+        },
+    })
+}
+
+/// The time window included in a forecast, relative to the current time.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub(crate) struct ForecastWindow {
+    /// Number of days before the current time to include (none if `None`).
+    pub past_days: Option<u8>,
+    /// Number of days after the current time to include (all available days if `None`).
+    pub days: Option<u8>,
+}
+
+impl ForecastWindow {
+    /// The maximum number of [`ForecastWindow::days`] for which
+    /// [`ForecastWindow::end_hour()`] is always within the 7 days available from the forecast
+    /// service.
+    const MAX_END_HOUR_DAYS: u8 = 4;
+
+    /// The last local hour required for this window, to request from the forecast service.
+    ///
+    /// The time zone of the position is only known after the forecast has been obtained, so this
+    /// uses the latest possible local time (UTC+14:00) for `utc_now`, and the rows are trimmed to
+    /// the exact window afterwards.
+    fn end_hour(self, utc_now: NaiveDateTime) -> Option<NaiveDateTime> {
+        let days = self.days.filter(|days| *days <= Self::MAX_END_HOUR_DAYS)?;
+        let latest_local_now = utc_now + chrono::Duration::hours(14);
+        let end = latest_local_now + chrono::Duration::days(i64::from(days));
+        // Round up to the next hour.
+        Some(end.date().and_hms(end.hour(), 0, 0) + chrono::Duration::hours(1))
     }
 }
 
-async fn process_email(
+/// Obtain the forecast for `position` and build the [`ForecastOutput`], ready to be formatted.
+///
+/// + `variables` are the variables to include in each row of the forecast.
+/// + `window` is the time window included in the forecast.
+/// + `mode` selects whether to build rows of the `variables`, or a sounding (which ignores
+///   `variables`).
+/// + `errors` are included at the start of the formatted output.
+#[allow(clippy::too_many_arguments)]
+pub(crate) async fn build_forecast(
     time: &dyn time::Port,
     forecast_service: &dyn forecast_service::Port,
-    topo_data_service: &dyn topo_data_service::Port,
-    received_email: &ReceivedKind,
-) -> Result<Reply, ProcessEmailError> {
-    let parsed_request = validate_transform_request(received_email);
-    let request = &parsed_request.request;
-
-    let position = request
-        .position
-        .or(received_email.position())
-        .ok_or_else(|| ProcessEmailError::NoPosition)?;
-    let forecast_parameters = open_meteo::ForecastParameters::builder()
+    topo_data_service: Option<&dyn topo_data_service::Port>,
+    position: Position,
+    variables: &[ForecastVariable],
+    window: ForecastWindow,
+    mode: FormatMode,
+    errors: Vec<String>,
+) -> eyre::Result<ForecastOutput> {
+    let utc_now: chrono::NaiveDateTime = time.utc_now().naive_utc();
+    let mut forecast_parameters = open_meteo::ForecastParameters::builder()
         .latitude(position.latitude)
         .longitude(position.longitude)
-        .hourly_entry(HourlyVariable::FreezingLevelHeight)
-        .hourly_entry(HourlyVariable::WindSpeed(GroundLevel::L10))
-        .hourly_entry(HourlyVariable::WindDirection(GroundLevel::L10))
-        .hourly_entry(HourlyVariable::WeatherCode)
-        .hourly_entry(HourlyVariable::Precipitation)
         .timezone(TimeZone::Auto)
         .build();
+    forecast_parameters.hourly = match mode {
+        FormatMode::Rows => variables
+            .iter()
+            .flat_map(ForecastVariable::hourly_variables)
+            .cloned()
+            .collect(),
+        FormatMode::Sounding => sounding_hourly_variables().collect(),
+        FormatMode::WindsAloft => winds_aloft_hourly_variables().collect(),
+    };
+    forecast_parameters.past_days = window.past_days;
+    forecast_parameters.end_hour = window.end_hour(utc_now);
+
+    let terrain_elevation = if let Some(topo_data_service) = topo_data_service {
+        match topo_data_service
+            .obtain_elevation(&open_topo_data::Parameters {
+                latitude: position.latitude,
+                longitude: position.longitude,
+                dataset: open_topo_data::Dataset::Mapzen,
+            })
+            .await
+            .wrap_err("Error obtaining terrain elevation")
+        {
+            Ok(terrain_elevation) => Some(terrain_elevation),
+            Err(error) => {
+                tracing::error!("{}", error);
+                None
+            }
+        }
+    } else {
+        tracing::warn!("Terrain elevation service is unavailable, skipping elevation");
+        None
+    };
+
+    // By default the forecast service selects a grid cell on land, so positions just offshore
+    // would be given a land forecast. The sea is preferred instead when the terrain is below sea
+    // level.
+    let sea_cell = terrain_elevation.map_or(false, is_offshore);
+    if sea_cell {
+        forecast_parameters.cell_selection = Some(CellSelection::Sea);
+    } else {
+        // Downscale the forecast to the terrain elevation, which is more accurate in mountainous
+        // terrain than the elevation model used by the forecast service.
+        forecast_parameters.elevation = terrain_elevation;
+    }
 
     tracing::debug!(
         "Obtaining forecast for forecast parameters {}",
         serde_json::to_string_pretty(&forecast_parameters).map_err(eyre::Error::from)?
     );
-    let forecast: open_meteo::Forecast = forecast_service
+    let ObtainedForecast { forecast, source } = forecast_service
         .obtain_forecast(&forecast_parameters)
         .await
         .wrap_err("Error obtaining forecast")?;
@@ -440,41 +1367,6 @@ async fn process_email(
         .ok_or_else(|| eyre::eyre!("expected hourly forecast to be present"))?;
     let forecast_time: &[chrono::NaiveDateTime] = &hourly.time;
 
-    let freezing_level_height: &[f32] = &hourly
-        .freezing_level_height
-        .ok_or_else(|| eyre::eyre!("expected freezing_level_height to be present"))?;
-    let wind_speed_10m: &[f32] = &hourly
-        .wind_speed
-        .value(&GroundLevel::L10)
-        .ok_or_else(|| eyre::eyre!("expected wind_speed_10m to be present"))?;
-    let wind_direction_10m: &[f32] = &hourly
-        .wind_direction
-        .value(&GroundLevel::L10)
-        .ok_or_else(|| eyre::eyre!("expected wind_direction_10m to be present"))?;
-    let weather_code: &[WeatherCode] = &hourly
-        .weather_code
-        .ok_or_else(|| eyre::eyre!("expected weather_code to be present"))?;
-    let precipitation: &[f32] = &hourly
-        .precipitation
-        .ok_or_else(|| eyre::eyre!("expected precipitation to be present"))?;
-
-    if [
-        forecast_time.len(),
-        freezing_level_height.len(),
-        wind_speed_10m.len(),
-        wind_direction_10m.len(),
-        weather_code.len(),
-        precipitation.len(),
-    ]
-    .into_iter()
-    .collect::<HashSet<usize>>()
-    .len()
-        != 1
-    {
-        return Err(eyre::eyre!("forecast hourly array lengths don't match").into());
-    }
-
-    let utc_now: chrono::NaiveDateTime = time.utc_now().naive_utc();
     let offset = chrono::TimeZone::offset_from_utc_datetime(&forecast.timezone, &utc_now);
     let current_local_time: chrono::NaiveDateTime =
         chrono::TimeZone::from_utc_datetime(&forecast.timezone, &utc_now).naive_local();
@@ -489,26 +1381,8 @@ async fn process_email(
         );
     }
 
-    let terrain_elevation = match topo_data_service
-        .obtain_elevation(&open_topo_data::Parameters {
-            latitude: position.latitude,
-            longitude: position.longitude,
-            dataset: open_topo_data::Dataset::Mapzen,
-        })
-        .await
-        .wrap_err("Error obtaining terrain elevation")
-    {
-        Ok(terrain_elevation) => Some(terrain_elevation),
-        Err(error) => {
-            tracing::error!("{}", error);
-            None
-        }
-    };
-
-    let mut forecast_rows: Vec<ForecastRow> = Vec::with_capacity(16);
-
-    // Skip times that are after the current local time.
-    let start_i: usize = forecast_time
+    // Index of the first time that is not before the current local time.
+    let now_i: usize = forecast_time
         .iter()
         .enumerate()
         .fold(0, |acc, (i, local_time)| {
@@ -519,68 +1393,253 @@ async fn process_email(
             }
         });
 
-    let mut i = start_i;
-    let mut acc_precipitation: f32 = 0.0;
-    while i <= usize::min(forecast_time.len() - 1, i + 48) {
-        acc_precipitation += precipitation[i];
-        if (i - start_i) % 6 == 0 {
-            forecast_rows.push(ForecastRow {
-                time: forecast_time[i],
-                parameters: vec![
-                    ForecastParameter::WeatherCode(weather_code[i]),
-                    ForecastParameter::FreezingLevelHeight(freezing_level_height[i]),
-                    ForecastParameter::Wind10m {
-                        speed: wind_speed_10m[i],
-                        direction: wind_direction_10m[i],
-                    },
-                    ForecastParameter::AccumulatedPrecipitation(acc_precipitation),
-                ],
-            });
-            acc_precipitation = 0.0;
-        }
-        i += 1;
-    }
+    let start_i: usize = window.past_days.map_or(now_i, |past_days| {
+        now_i.saturating_sub(usize::from(past_days) * 24)
+    });
+    let end_i: usize = window.days.map_or(forecast_time.len() - 1, |days| {
+        usize::min(forecast_time.len() - 1, now_i + usize::from(days) * 24)
+    });
 
-    let errors: Vec<String> = parsed_request
-        .errors
-        .iter()
-        .map(|error| format!("Error parsing request: {}", error))
-        .collect();
+    let mut errors = errors;
+    let body = match mode {
+        FormatMode::Rows => ForecastBody::Rows(forecast_rows(
+            &hourly,
+            variables,
+            start_i,
+            end_i,
+            &mut errors,
+        )?),
+        FormatMode::Sounding => {
+            let sounding = Sounding::from_hourly(&hourly, now_i, forecast.elevation)?;
+            if sounding.levels.is_empty() {
+                tracing::warn!("Sounding is unavailable");
+                errors.push("Sounding is unavailable".to_string());
+            }
+            ForecastBody::Sounding(sounding)
+        }
+        FormatMode::WindsAloft => {
+            let winds_aloft = WindsAloft::from_hourly(&hourly, now_i, forecast.elevation)?;
+            if winds_aloft.levels.is_empty() {
+                tracing::warn!("Winds aloft are unavailable");
+                errors.push("Winds aloft are unavailable".to_string());
+            }
+            ForecastBody::WindsAloft(winds_aloft)
+        }
+    };
 
-    let forecast_output = ForecastOutput {
+    Ok(ForecastOutput {
         errors,
         total_timezone_offset: total_offset,
         forecast_elevation: forecast.elevation,
         terrain_elevation,
-        rows: forecast_rows,
-    };
+        sea_cell,
+        source: Some(source),
+        body,
+    })
+}
 
-    let message: String = forecast_output.format(&request.format);
-    let (plain_message, html_message): (String, Option<String>) =
-        if let FormatDetail::Long(long) = &request.format.detail {
-            if let Some(LongFormatStyle::Html) = long.style {
-                let mut plain_long = long.clone();
-                let mut plain_format = request.format.clone();
-                plain_long.style = Some(LongFormatStyle::PlainText);
-                plain_format.detail = FormatDetail::Long(plain_long);
-
-                let plain_message = forecast_output.format(&plain_format);
-                (plain_message, Some(message))
-            } else {
-                (message, None)
-            }
-        } else {
-            (message, None)
+/// Returns `true` if a position with the `terrain_elevation` (in metres) is offshore. The
+/// [`open_topo_data::Dataset::Mapzen`] dataset includes the bathymetry, so the elevation of the
+/// sea floor is negative, while the coastline is at sea level.
+fn is_offshore(terrain_elevation: f32) -> bool {
+    terrain_elevation < 0.0
+}
+
+/// Build the rows of the forecast for the `variables`, every 6 hours from `start_i` to `end_i`
+/// of the `hourly` forecast. Variables which are unavailable are omitted and reported in `errors`.
+fn forecast_rows(
+    hourly: &Hourly,
+    variables: &[ForecastVariable],
+    start_i: usize,
+    end_i: usize,
+    errors: &mut Vec<String>,
+) -> eyre::Result<Vec<ForecastRow>> {
+    let forecast_time: &[chrono::NaiveDateTime] = &hourly.time;
+
+    // Fallback providers may not provide all of the variables.
+    let variables: Vec<ForecastVariable> = variables
+        .iter()
+        .copied()
+        .filter(|variable| {
+            let available = variable_available(*variable, hourly);
+            if !available {
+                tracing::warn!("Forecast variable {:?} is unavailable", variable);
+                errors.push(format!("{:?} is unavailable", variable));
+            }
+            available
+        })
+        .collect();
+
+    let precipitation: Option<&[Millimetres]> =
+        if variables.contains(&ForecastVariable::Precipitation) {
+            Some(hourly_values(
+                hourly.precipitation.as_deref(),
+                "precipitation",
+                forecast_time.len(),
+            )?)
+        } else {
+            None
+        };
+    let precipitation_probability: Option<&[f32]> =
+        match hourly.precipitation_probability.as_deref() {
+            Some(probability) if precipitation.is_some() => Some(hourly_values(
+                Some(probability),
+                "precipitation_probability",
+                forecast_time.len(),
+            )?),
+            _ => None,
         };
 
-    tracing::info!("Sending reply for email {:?}", received_email);
+    let mut forecast_rows: Vec<ForecastRow> = Vec::with_capacity(16);
+    let mut i = start_i;
+    let mut acc_precipitation = Millimetres::default();
+    let mut max_precipitation_probability: Option<f32> = None;
+    let mut previous_freezing_level: Option<Metres> = None;
+    while i <= end_i {
+        if let Some(precipitation) = precipitation {
+            acc_precipitation += precipitation[i];
+        }
+        if let Some(probability) = precipitation_probability {
+            max_precipitation_probability = Some(
+                max_precipitation_probability.map_or(probability[i], |max| max.max(probability[i])),
+            );
+        }
+        if (i - start_i) % 6 == 0 {
+            let mut parameters: Vec<ForecastParameter> = variables
+                .iter()
+                .map(|variable| forecast_parameter(*variable, hourly, i, acc_precipitation))
+                .collect::<eyre::Result<_>>()?;
+            for parameter in &mut parameters {
+                if let ForecastParameter::FreezingLevelHeight { height, trend } = parameter {
+                    *trend = previous_freezing_level
+                        .and_then(|previous| Trend::between(previous.0, height.0, 100.0));
+                    previous_freezing_level = Some(*height);
+                }
+                if let ForecastParameter::AccumulatedPrecipitation { probability, .. } = parameter {
+                    *probability = max_precipitation_probability;
+                }
+            }
+            forecast_rows.push(ForecastRow {
+                time: forecast_time[i],
+                parameters,
+            });
+            acc_precipitation = Millimetres::default();
+            max_precipitation_probability = None;
+        }
+        i += 1;
+    }
+
+    Ok(forecast_rows)
+}
+
+/// Message explaining to the user why the forecast service rejected their request.
+fn rejected_message(error: &ApiError) -> String {
+    match error {
+        ApiError::CoordinatesOutOfRange(_) => "The forecast position is out of range, latitude \
+            must be between -90 and 90, and longitude must be between -180 and 180"
+            .to_string(),
+        ApiError::RateLimited(_) => {
+            "The forecast service is receiving too many requests, please try again later"
+                .to_string()
+        }
+        ApiError::InvalidParameter(reason) => {
+            format!(
+                "The forecast request contained an invalid parameter: {}",
+                reason
+            )
+        }
+        ApiError::Other(reason) => format!("The forecast service rejected the request: {}", reason),
+    }
+}
+
+#[allow(clippy::too_many_arguments)]
+async fn process_email(
+    time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    topo_data_service: Option<&dyn topo_data_service::Port>,
+    aviation_weather: Option<&dyn aviation_weather::Port>,
+    flood: Option<&dyn flood::Port>,
+    latency: Option<&Latency>,
+    previous_forecasts: Option<(&'static PreviousForecasts, &str)>,
+    options: &DynamicOptions,
+    received_email: &ReceivedKind,
+    quota_remaining: Option<u32>,
+) -> Result<Reply, ForecastError> {
+    let clock = time;
+    // Align the forecast window with the time that the message was sent, rather than when it
+    // is being processed, which may be later if it was queued.
+    let sent_time = received_email
+        .sent_at()
+        .filter(|sent_at| *sent_at <= time.utc_now())
+        .map(|sent_at| time::Fixed::new(sent_at, time));
+    let time: &dyn time::Port = match &sent_time {
+        Some(sent_time) => sent_time,
+        None => time,
+    };
+
+    if received_email.forecast_request().command == Some(Command::About) {
+        let message = about::message(
+            matches!(received_email, ReceivedKind::Inreach(_)),
+            quota_remaining,
+        );
+        return Ok(Reply::from_received(received_email.clone(), message, None));
+    }
+
+    let service = ForecastService::new(time, forecast_service, topo_data_service, options)
+        .with_aviation_weather(aviation_weather)
+        .with_flood(flood);
+    let service = match latency {
+        Some(latency) => service.with_latency(latency, clock),
+        None => service,
+    };
+    let service = match previous_forecasts {
+        Some((previous_forecasts, sender)) => {
+            service.with_previous_forecasts(previous_forecasts, sender)
+        }
+        None => service,
+    };
+    let parsed_requests = std::iter::once(received_email.forecast_request())
+        .chain(received_email.additional_requests());
+    let mut forecasts: Vec<FormattedForecast> = Vec::with_capacity(1);
+    let mut grib_positions: Vec<Position> = Vec::new();
+    // Repeated positions are served by the forecast service's cache.
+    for parsed_request in parsed_requests {
+        if parsed_request.request.grib {
+            if let Some(position) = parsed_request
+                .request
+                .position
+                .or_else(|| received_email.position())
+            {
+                grib_positions.push(position);
+            }
+        }
+        let errors: Vec<String> = parsed_request
+            .errors
+            .iter()
+            .map(|error| format!("Error parsing request: {}", error))
+            .collect();
+
+        forecasts.push(
+            service
+                .forecast_with(
+                    &parsed_request.request,
+                    received_email.position(),
+                    errors,
+                    |format| validate_transform_format(received_email, format),
+                )
+                .await?,
+        );
+    }
+    let forecast = FormattedForecast::combine(forecasts);
+
+    tracing::info!("Sending reply for email {:?}", received_email.redacted());
 
     tracing::info!(
         "plain_message (len: {}):\n{}",
-        plain_message.len(),
-        plain_message
+        forecast.message.len(),
+        forecast.message
     );
-    if let Some(html_message) = &html_message {
+    if let Some(html_message) = &forecast.html_message {
         tracing::info!(
             "html_message (len: {}):\n{}",
             html_message.len(),
@@ -588,49 +1647,575 @@ async fn process_email(
         );
     }
 
-    Ok(Reply::from_received(
+    let mut reply = Reply::from_received(
         received_email.clone(),
-        plain_message,
-        html_message,
-    ))
+        forecast.message,
+        forecast.html_message,
+    );
+    if let (Reply::Plain(plain), Some(template)) = (&mut reply, &options.reply_subject) {
+        plain.reply_subject = subject::render(
+            template,
+            plain.subject.as_deref(),
+            forecast.summary.as_ref(),
+        );
+    }
+    match &mut reply {
+        Reply::Plain(plain) => {
+            attach_gribs(time, forecast_service, &grib_positions, plain).await;
+        }
+        Reply::InReach(_) => {
+            if !grib_positions.is_empty() {
+                tracing::warn!("GRIB attachments are not supported for inReach replies");
+            }
+        }
+    }
+    Ok(reply)
 }
 
-async fn process_emails_impl(
-    process_receiver: &mut yaque::Receiver,
-    reply_sender: &mut yaque::Sender,
-    http_client: reqwest::Client,
+/// Attach a GRIB file (see [`grib::build_grib()`]) to the `plain` reply for each of the
+/// `positions`. If a file can't be built, a note is appended to the message instead of failing
+/// the whole reply.
+async fn attach_gribs(
     time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    positions: &[Position],
+    plain: &mut reply::Plain,
+) {
+    for (i, position) in positions.iter().enumerate() {
+        match grib::build_grib(time, forecast_service, *position).await {
+            Ok(mut attachment) => {
+                if positions.len() > 1 {
+                    attachment.filename = format!("forecast-{}.grb2", i + 1);
+                }
+                plain.attachments.push(attachment);
+            }
+            Err(error) => {
+                tracing::error!("Error building GRIB attachment: {:?}", error);
+                let note = format!(
+                    "\n\nGRIB attachment is unavailable for {:.2},{:.2}",
+                    position.latitude, position.longitude
+                );
+                plain.plain_message.push_str(&note);
+                if let Some(html_message) = &mut plain.html_message {
+                    html_message.push_str(&format!("<p>{}</p>", note.trim()));
+                }
+            }
+        }
+    }
+}
+
+/// Erase the data stored about the sender of `received_email`, see [`forget::forget()`].
+async fn forget_sender(
+    received_email: &ReceivedKind,
+    analytics: Option<&'static Analytics>,
+    confirmations: Option<&'static SenderConfirmations>,
+    feeds: Option<&'static Feeds>,
+    previous_forecasts: Option<&'static PreviousForecasts>,
+    public_keys: Option<&'static PublicKeys>,
+) -> Result<Reply, ForecastError> {
+    let identity = received_email
+        .sender_identity()
+        .ok_or_else(|| eyre::eyre!("Unable to identify the sender of the email"))?
+        .to_owned();
+    let forgotten = tokio::task::spawn_blocking(move || {
+        forget::forget(
+            &identity,
+            analytics,
+            confirmations,
+            feeds,
+            previous_forecasts,
+            public_keys,
+        )
+    })
+    .await
+    .wrap_err("Error while erasing the sender's data")??;
+    let message = forget::message(
+        forgotten,
+        matches!(received_email, ReceivedKind::Inreach(_)),
+    );
+    Ok(Reply::from_received(received_email.clone(), message, None))
+}
+
+/// Subscribe the sender of `received_email` to a feed of their forecasts, see
+/// [`Feeds::subscribe()`], and reply with the url of the feed.
+async fn subscribe_feed(
+    received_email: &ReceivedKind,
+    feeds: Option<&'static Feeds>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Reply, ForecastError> {
+    let feeds = match feeds {
+        Some(feeds) => feeds,
+        None => {
+            return Ok(Reply::from_received(
+                received_email.clone(),
+                "Forecast feeds are not enabled for this service".to_owned(),
+                None,
+            ))
+        }
+    };
+    let sender = received_email
+        .sender_identity()
+        .map(forget::sender_hash)
+        .ok_or_else(|| eyre::eyre!("Unable to identify the sender of the email"))?;
+    let url = tokio::task::spawn_blocking(move || {
+        let token = feeds.subscribe(&sender, now)?;
+        feeds.url(&token)
+    })
+    .await
+    .wrap_err("Error while subscribing to feed")??;
+    let message =
+        feed::subscribed_message(&url, matches!(received_email, ReceivedKind::Inreach(_)));
+    Ok(Reply::from_received(received_email.clone(), message, None))
+}
+
+/// Request the registration of the PGP public key sent by the sender of `received_email` with
+/// [`Command::Key`], or its removal for [`Command::KeyOff`], and make the change once it is
+/// confirmed with [`Command::KeyConfirm`], see [`PublicKeys`]. Only plain email replies are
+/// encrypted.
+async fn register_key(
+    received_email: &ReceivedKind,
+    public_keys: Option<&'static PublicKeys>,
+    now: chrono::DateTime<chrono::Utc>,
+) -> Result<Reply, ForecastError> {
+    // Codes are sent to the sender's address rather than `Reply-To`, so that they prove ownership
+    // of the address whose key is being changed.
+    let reply = |message: String| Ok(Reply::to_sender(received_email.clone(), message, None));
+    let (public_keys, email) = match (public_keys, received_email) {
+        (Some(public_keys), ReceivedKind::Plain(email)) => (public_keys, email),
+        (None, _) => return reply("Encrypted replies are not enabled for this service".to_owned()),
+        (Some(_), ReceivedKind::Inreach(_)) => {
+            return reply("Encrypted replies are only available for email".to_owned())
+        }
+    };
+    let sender = forget::sender_hash(email.from.email_str());
+
+    let (change, fingerprint) = match email.forecast_request.command {
+        Some(Command::KeyConfirm(code)) => {
+            let change =
+                tokio::task::spawn_blocking(move || public_keys.confirm(&sender, code, now))
+                    .await
+                    .wrap_err("Error while confirming public key change")??;
+            return match change {
+                Some(KeyChange::Register(key)) => reply(encryption::registered_message(
+                    &encryption::fingerprint(&key)?,
+                )),
+                Some(KeyChange::Remove) => reply(encryption::REMOVED_MESSAGE.to_owned()),
+                None => reply(encryption::INVALID_CODE_MESSAGE.to_owned()),
+            };
+        }
+        Some(Command::KeyOff) => (KeyChange::Remove, None),
+        _ => {
+            let key = match &email.public_key {
+                Some(key) => key.clone(),
+                None => return reply(encryption::MISSING_KEY_MESSAGE.to_owned()),
+            };
+            match encryption::fingerprint(&key) {
+                Ok(fingerprint) => (KeyChange::Register(key), Some(fingerprint)),
+                Err(error) => {
+                    tracing::warn!("Invalid public key: {:?}", error);
+                    return reply(format!("Unable to use your public key: {}", error));
+                }
+            }
+        }
+    };
+    // The change is only made once the code sent to the sender's address is returned, so that a
+    // forged sender can't change the key.
+    let code = tokio::task::spawn_blocking(move || public_keys.request(&sender, &change, now))
+        .await
+        .wrap_err("Error while requesting public key change")??;
+    reply(encryption::confirm_message(fingerprint.as_deref(), code))
+}
+
+/// Encrypt the `plain` reply to the public key registered by `sender` (if they registered one),
+/// see [`encryption::encrypt_reply()`].
+async fn encrypt_reply(
+    public_keys: &'static PublicKeys,
+    sender: String,
+    plain: &mut reply::Plain,
+) -> eyre::Result<()> {
+    let key = match tokio::task::spawn_blocking(move || public_keys.key(&sender)).await?? {
+        Some(key) => key,
+        None => return Ok(()),
+    };
+    encryption::encrypt_reply(plain, &key)?;
+    tracing::debug!("Encrypted reply");
+    Ok(())
+}
+
+/// Record the forecast in `reply` in the feed of `sender`, if they are subscribed, see
+/// [`Feeds::record()`].
+async fn record_feed(
+    feeds: &'static Feeds,
+    sender: String,
+    reply: &Reply,
+    now: chrono::DateTime<chrono::Utc>,
+) -> eyre::Result<()> {
+    let message = match reply {
+        Reply::InReach(reply) => reply.message.clone(),
+        Reply::Plain(reply) => reply.plain_message.clone(),
+    };
+    if tokio::task::spawn_blocking(move || feeds.record(&sender, &message, now)).await?? {
+        tracing::debug!("Recorded forecast in feed");
+    }
+    Ok(())
+}
+
+/// Check whether the sender of `received_email` has confirmed their address (see
+/// [`SenderConfirmations::check()`]). Only plain emails require confirmation, `None` is returned for
+/// inReach messages.
+async fn check_confirmation(
+    received_email: &ReceivedKind,
+    confirmations: &'static SenderConfirmations,
+    now: chrono::DateTime<chrono::Utc>,
+) -> eyre::Result<Option<Confirmation>> {
+    let email = match received_email {
+        ReceivedKind::Plain(email) => email,
+        ReceivedKind::Inreach(_) => return Ok(None),
+    };
+    let sender = forget::sender_hash(email.from.email_str());
+    let code = match email.forecast_request.command {
+        Some(Command::Confirm(code)) => Some(code),
+        _ => None,
+    };
+    let confirmation =
+        tokio::task::spawn_blocking(move || confirmations.check(&sender, code, now)).await??;
+    Ok(Some(confirmation))
+}
+
+/// The domain that `received_email` is counted against by
+/// [`Quotas::check()`](crate::quota::Quotas::check), `None` for satellite devices.
+fn quota_domain(received_email: &ReceivedKind) -> Option<&str> {
+    match received_email {
+        ReceivedKind::Plain(email) => email
+            .from
+            .email_str()
+            .rsplit_once('@')
+            .map(|(_, domain)| domain),
+        ReceivedKind::Inreach(_) => None,
+    }
+}
+
+/// Anonymized details of the request in `received_email` which are recorded in
+/// [`crate::analytics::Analytics`].
+fn analytics_details(received_email: &ReceivedKind) -> (Option<Position>, DeviceKind, FormatKind) {
+    let request = &received_email.forecast_request().request;
+    let position = request.position.or(received_email.position());
+    let device = match received_email {
+        ReceivedKind::Inreach(_) => DeviceKind::Inreach,
+        ReceivedKind::Plain(_) => DeviceKind::Plain,
+    };
+    let format = match request.format.as_ref().map(|format| &format.detail) {
+        None => FormatKind::Default,
+        Some(FormatDetail::Short(_)) => FormatKind::Short,
+        Some(FormatDetail::Long(_)) => FormatKind::Long,
+    };
+    (position, device, format)
+}
+
+/// Process emails received from `process_receiver` using the specified services, and submit the
+/// replies to `reply_sender`. The services are expected to record their outcomes with the
+/// [`TaskContext::circuit_breakers`], see [`CircuitBreakerPort`].
+pub(crate) async fn process_emails_impl(
+    process_receiver: &mut queue::Receiver,
+    reply_sender: &mut queue::Sender,
+    forecast_service: &dyn forecast_service::Port,
+    topo_data_service: &dyn topo_data_service::Port,
+    aviation_weather: &dyn aviation_weather::Port,
+    flood: &dyn flood::Port,
+    options_rx: &watch::Receiver<DynamicOptions>,
+    context: &TaskContext,
 ) -> eyre::Result<()> {
-    let forecast_service = forecast_service::Gateway::new(http_client.clone());
-    let topo_data_service = topo_data_service::Gateway::new(http_client);
+    let TaskContext {
+        status,
+        webhooks,
+        circuit_breakers,
+        analytics,
+        confirmations,
+        feeds,
+        previous_forecasts,
+        public_keys,
+        quotas,
+        latency,
+        time,
+        ..
+    } = *context;
+    let mut duplicates = Duplicates::default();
     loop {
-        let received = process_receiver.recv().await?;
-        let received_email: ReceivedKind = serde_json::from_slice(&*received)?;
+        let received = match process_receiver.recv().await? {
+            queue::Message::Received(received) => received,
+            queue::Message::Undecryptable(undecryptable) => {
+                tracing::error!(
+                    "Unable to decrypt queued email: {:?}",
+                    undecryptable.error()
+                );
+                let path = undecryptable.dead_letter().await?;
+                tracing::warn!("Moved undecryptable email to dead letter {:?}", path);
+                continue;
+            }
+        };
+        let Queued {
+            request_id,
+            queued_at,
+            payload: received_email,
+        }: Queued<ReceivedKind> = serde_json::from_slice(&*received)?;
+        let options = options_rx.borrow().clone();
+        let started = time.utc_now();
+        if let Some(queued_at) = queued_at {
+            latency.record(Stage::Queue, started - queued_at);
+        }
+        let (position, device, format) = analytics_details(&received_email);
+        let command = received_email.forecast_request().command;
+        let forget_me = command == Some(Command::ForgetMe);
+        // Don't record the sender of a request to forget them.
+        let sender = received_email
+            .sender_identity()
+            .filter(|_| !forget_me)
+            .map(forget::sender_hash);
+        // Requests to forget the sender don't require confirmation, which would record them.
+        let confirmation = match confirmations {
+            Some(confirmations) if !forget_me => {
+                check_confirmation(&received_email, confirmations, time.utc_now()).await?
+            }
+            _ => None,
+        };
+        // Only forecast requests are checked for duplicates, commands are always processed.
+        let duplicate_key = match (&sender, command) {
+            (Some(sender), None) => {
+                duplicate::request_key(&received_email).map(|key| (sender.clone(), key))
+            }
+            _ => None,
+        };
+        let duplicate_of = match (&duplicate_key, options.duplicate_window) {
+            (Some((sender, key)), Some(window)) => chrono::Duration::from_std(window)
+                .ok()
+                .and_then(|window| duplicates.find(sender, key, window, time.utc_now())),
+            _ => None,
+        };
+
+        let reply_outcome = async {
+            match confirmation {
+                Some(Confirmation::Required { code, send }) => {
+                    tracing::info!("Sender has not confirmed their address");
+                    // The code must reach the sender's own address to confirm it.
+                    let reply = send.then(|| {
+                        Reply::to_sender(
+                            received_email.clone(),
+                            confirmation::required_message(code),
+                            None,
+                        )
+                    });
+                    return (reply, Outcome::Unconfirmed);
+                }
+                Some(Confirmation::NewlyConfirmed) => {
+                    tracing::info!("Sender confirmed their address");
+                    let reply = Reply::from_received(
+                        received_email.clone(),
+                        confirmation::CONFIRMED_MESSAGE.to_owned(),
+                        None,
+                    );
+                    return (Some(reply), Outcome::Success);
+                }
+                Some(Confirmation::Confirmed) | None => {}
+            }
 
-        let reply =
-            match process_email(time, &forecast_service, &topo_data_service, &received_email).await
+            if let Some(answered_at) = duplicate_of {
+                tracing::info!(
+                    "Not replying to duplicate of the request answered at {}",
+                    answered_at
+                );
+                return (None, Outcome::Duplicate);
+            }
+
+            // Requests to forget the sender are always processed.
+            let pending_requests = status.pending_requests();
+            if !forget_me
+                && options
+                    .back_pressure
+                    .is_overloaded(pending_requests, Overload::Reply)
             {
-                Ok(reply) => reply,
+                tracing::warn!(
+                    "Request refused, process queue is saturated ({} requests pending)",
+                    pending_requests
+                );
+                let language = received_email.language().unwrap_or_default();
+                let reply = Reply::from_received(
+                    received_email.clone(),
+                    format!("{}, {}", language.busy(), language.try_again_later()),
+                    None,
+                );
+                return (Some(reply), Outcome::Overloaded);
+            }
+            if !forget_me {
+                if let Err(error) = quotas.check(quota_domain(&received_email), time.utc_now()) {
+                    tracing::warn!("Request refused: {}", error);
+                    // Only satellite devices are notified, to avoid sending more email while
+                    // the service may be being abused.
+                    let reply = matches!(received_email, ReceivedKind::Inreach(_)).then(|| {
+                        let language = received_email.language().unwrap_or_default();
+                        Reply::from_received(
+                            received_email.clone(),
+                            format!("{}, {}", error, language.try_again_later()),
+                            None,
+                        )
+                    });
+                    return (reply, Outcome::QuotaExceeded);
+                }
+            }
+
+            let now = time.utc_now();
+            let result = if forget_me {
+                forget_sender(
+                    &received_email,
+                    analytics,
+                    confirmations,
+                    feeds,
+                    previous_forecasts,
+                    public_keys,
+                )
+                .await
+            } else if command == Some(Command::Feed) {
+                subscribe_feed(&received_email, feeds, now).await
+            } else if matches!(
+                command,
+                Some(Command::Key | Command::KeyOff | Command::KeyConfirm(_))
+            ) {
+                register_key(&received_email, public_keys, now).await
+            } else {
+                match circuit_breakers.forecast.check(now) {
+                    Ok(()) => {
+                        let topo_data_service: Option<&dyn topo_data_service::Port> =
+                            match circuit_breakers.topo_data.check(now) {
+                                Ok(()) => Some(topo_data_service),
+                                Err(_) => None,
+                            };
+                        process_email(
+                            time,
+                            forecast_service,
+                            topo_data_service,
+                            Some(aviation_weather),
+                            Some(flood),
+                            Some(latency),
+                            previous_forecasts.zip(sender.as_deref()),
+                            &options,
+                            &received_email,
+                            quotas.remaining(now),
+                        )
+                        .await
+                    }
+                    Err(error) => Err(error.into()),
+                }
+            };
+            // Error replies use the language of the received email (if it could be detected).
+            let language = received_email.language().unwrap_or_default();
+            let (reply, outcome) = match result {
+                Ok(reply) => (reply, Outcome::Success),
                 Err(error) => match &error {
-                    ProcessEmailError::NoPosition => Reply::from_received(
-                        received_email,
-                        "No forecast position specified".to_string(),
-                        None,
-                    ),
-                    ProcessEmailError::Unexpected(error) => {
-                        tracing::error!("Unexpected error occurred: {:?}", error);
+                    ForecastError::NoPosition => (
                         Reply::from_received(
                             received_email,
-                            "An error occurred while processing your request".to_string(),
+                            language.no_position().to_string(),
                             None,
+                        ),
+                        Outcome::NoPosition,
+                    ),
+                    ForecastError::Unavailable(error) => {
+                        tracing::warn!("{}", error);
+                        (
+                            Reply::from_received(
+                                received_email,
+                                format!("{}, {}", error, language.try_again_later()),
+                                None,
+                            ),
+                            Outcome::Unavailable,
+                        )
+                    }
+                    ForecastError::Rejected(error) => {
+                        tracing::warn!("Forecast service rejected the request: {}", error);
+                        let outcome = match error {
+                            ApiError::RateLimited(_) => Outcome::Unavailable,
+                            _ => Outcome::Error,
+                        };
+                        (
+                            Reply::from_received(received_email, rejected_message(error), None),
+                            outcome,
+                        )
+                    }
+                    ForecastError::Unexpected(error) => {
+                        tracing::error!("Unexpected error occurred: {:?}", error);
+                        (
+                            Reply::from_received(
+                                received_email,
+                                language.unexpected_error().to_string(),
+                                None,
+                            ),
+                            Outcome::Error,
                         )
                     }
                 },
             };
-        let reply_bytes = serde_json::to_vec(&reply).wrap_err("Failed to serialize reply")?;
-        reply_sender.send(&reply_bytes).await?;
+            (Some(reply), outcome)
+        }
+        .instrument(tracing::info_span!("request", id = %request_id));
+        let (reply, outcome) = reporting::bind_request_id(request_id, reply_outcome).await;
+        if let (Some((sender, key)), Outcome::Success, true) =
+            (duplicate_key, outcome, reply.is_some())
+        {
+            duplicates.record(sender, key, time.utc_now());
+        }
+        if let Some(mut reply) = reply {
+            // Only forecasts are recorded in feeds, not the replies to commands or errors.
+            if let (Some(feeds), Some(sender), Outcome::Success, None) =
+                (feeds, sender.clone(), outcome, command)
+            {
+                if let Err(error) = record_feed(feeds, sender, &reply, time.utc_now()).await {
+                    tracing::error!("Error recording forecast in feed: {:?}", error);
+                }
+            }
+            if let (Some(public_keys), Some(sender), Reply::Plain(plain)) =
+                (public_keys, sender.clone(), &mut reply)
+            {
+                if let Err(error) = encrypt_reply(public_keys, sender, plain).await {
+                    // The reply is not sent unencrypted.
+                    tracing::error!("Error encrypting reply: {:?}", error);
+                    *plain = reply::Plain {
+                        plain_message: encryption::ENCRYPTION_FAILED_MESSAGE.to_owned(),
+                        html_message: None,
+                        reply_subject: None,
+                        attachments: Vec::new(),
+                        encrypted: None,
+                        ..plain.clone()
+                    };
+                }
+            }
+            let reply_bytes =
+                serde_json::to_vec(&Queued::new(request_id, reply).with_queued_at(time.utc_now()))
+                    .wrap_err("Failed to serialize reply")?;
+            reply_sender.send(&reply_bytes).await?;
+            status.record_reply_queued(time.utc_now());
+        } else {
+            tracing::info!("Not sending a reply for request {}", request_id);
+        }
+        status.record_forecast_processed(time.utc_now());
+        webhooks.notify(WebhookEvent::ForecastProcessed { request_id });
+        if let Some(analytics) = analytics {
+            let event = RequestEvent::new(
+                request_id,
+                started,
+                position,
+                device,
+                format,
+                time.utc_now() - started,
+                outcome,
+            );
+            let event = match sender {
+                Some(sender) => event.with_sender(sender),
+                None => event,
+            };
+            analytics.record(event);
+        }
 
-        received.commit()?;
+        received.commit().await?;
+        status.record_request_processed();
     }
 }
 
@@ -638,25 +2223,54 @@ async fn process_emails_impl(
 /// requested, and dispatch a reply.
 #[tracing::instrument(skip_all)]
 pub async fn process_emails(
-    process_receiver: yaque::Receiver,
-    reply_sender: yaque::Sender,
+    process_receiver: queue::Receiver,
+    reply_sender: queue::Sender,
     shutdown_rx: tokio::sync::broadcast::Receiver<()>,
-    http_client: reqwest::Client,
-    time: &dyn time::Port,
+    forecast_service: forecast_service::Failover,
+    topo_data_service: topo_data_service::Failover,
+    aviation_weather: aviation_weather::Gateway,
+    flood: flood::Gateway,
+    options_rx: watch::Receiver<DynamicOptions>,
+    context: TaskContext,
 ) {
     tracing::debug!("Starting processing emails job");
     let queues = Arc::new(Mutex::new((process_receiver, reply_sender)));
     run_retry_log_errors(
         move || {
             let queues = queues.clone();
-            let http_client = http_client.clone();
+            let forecast_service = forecast_service.clone();
+            let topo_data_service = topo_data_service.clone();
+            let aviation_weather = aviation_weather.clone();
+            let flood = flood.clone();
+            let options_rx = options_rx.clone();
             async move {
+                let forecast_service = CircuitBreakerPort::new(
+                    forecast_service,
+                    &context.circuit_breakers.forecast,
+                    context.time,
+                );
+                let topo_data_service = CircuitBreakerPort::new(
+                    topo_data_service,
+                    &context.circuit_breakers.topo_data,
+                    context.time,
+                );
                 let (process_receiver, reply_sender) = &mut *queues.lock().await;
-                process_emails_impl(process_receiver, reply_sender, http_client, time).await
+                process_emails_impl(
+                    process_receiver,
+                    reply_sender,
+                    &forecast_service,
+                    &topo_data_service,
+                    &aviation_weather,
+                    &flood,
+                    &options_rx,
+                    &context,
+                )
+                .await
             }
         },
         shutdown_rx,
-        time,
+        context.backoff,
+        context.time,
     )
     .await;
 }
@@ -665,21 +2279,38 @@ pub async fn process_emails(
 mod test {
     use std::convert::TryFrom;
 
+    use chrono::{Datelike, Timelike};
     use mockall::predicate::eq;
     use once_cell::sync::Lazy;
-    use open_meteo::{Forecast, ForecastParameters, GroundLevel, HourlyVariable};
+    use open_meteo::{
+        units::{Celsius, KmPerHour, Metres, Millimetres},
+        ApiError, Forecast, ForecastParameters, GroundLevel, HourlyVariable, PressureLevel,
+        WeatherCode,
+    };
 
     use crate::{
-        forecast_service,
+        forecast_service::{self, ModelRun, ObtainedForecast},
+        format::{
+            decode_parameter, decode_short, Beaufort, DecodedHeader, DecodedParameter,
+            FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle,
+            ShortFormatDetail, Trend, WindUnit,
+        },
         gis::Position,
         inreach,
-        process::{FormatDetail, FormatForecastOptions, ShortFormatDetail},
+        language::Language,
+        options::DynamicOptions,
+        profile::ForecastVariable,
         reply::{self, Reply},
         request::{ForecastRequest, ParsedForecastRequest},
         topo_data_service,
     };
 
-    use super::{process_email, WindDirection};
+    use super::{
+        forecast_parameter, format_source_long, is_offshore, process_email, rejected_message,
+        ForecastBody, ForecastOutput, ForecastParameter, ForecastRow, ForecastSource,
+        ForecastWindow, FormatForecast, Sounding, SoundingLevel, WindDirection, WindsAloft,
+        WindsAloftLevel,
+    };
 
     #[test]
     fn test_wind_direction_from_float() {
@@ -709,6 +2340,597 @@ mod test {
         assert_eq!(WindDirection::NW, WindDirection::try_from(325.0).unwrap());
     }
 
+    /// Test that a forecast formatted in the short format can be decoded back into the values
+    /// that were formatted (to the precision of the short format).
+    #[test]
+    fn test_decode_short_round_trip() {
+        let output = ForecastOutput {
+            errors: vec!["Unknown profile: ski".to_owned()],
+            total_timezone_offset: chrono::Duration::minutes(5 * 60 + 45),
+            forecast_elevation: 1500.0,
+            terrain_elevation: Some(2216.0),
+            sea_cell: false,
+            source: None,
+            body: ForecastBody::Rows(vec![
+                ForecastRow {
+                    time: "2022-12-03T21:00:00".parse().unwrap(),
+                    parameters: vec![
+                        ForecastParameter::WeatherCode(WeatherCode::PartlyCloudy),
+                        ForecastParameter::FreezingLevelHeight {
+                            height: Metres(2830.0),
+                            trend: None,
+                        },
+                        ForecastParameter::Wind10m {
+                            speed: KmPerHour(12.0),
+                            gusts: None,
+                            direction: 318.0,
+                        },
+                        ForecastParameter::AccumulatedPrecipitation {
+                            amount: Millimetres(0.2),
+                            probability: None,
+                        },
+                    ],
+                },
+                ForecastRow {
+                    time: "2022-12-04T03:00:00".parse().unwrap(),
+                    parameters: vec![
+                        ForecastParameter::WeatherCode(WeatherCode::SnowHeavy),
+                        ForecastParameter::FreezingLevelHeight {
+                            height: Metres(1260.0),
+                            trend: Some(Trend::Falling),
+                        },
+                        ForecastParameter::Wind10m {
+                            speed: KmPerHour(55.0),
+                            gusts: Some(KmPerHour(92.0)),
+                            direction: 4.0,
+                        },
+                        ForecastParameter::AccumulatedPrecipitation {
+                            amount: Millimetres(12.6),
+                            probability: Some(85.0),
+                        },
+                        ForecastParameter::CloudCover {
+                            low: 100.0,
+                            mid: 64.0,
+                            high: 0.0,
+                        },
+                        ForecastParameter::RelativeHumidity(96.0),
+                        ForecastParameter::Dewpoint(Celsius(-3.4)),
+                        ForecastParameter::PressureMsl {
+                            pressure: 998.4,
+                            tendency: Some(Trend::Falling),
+                        },
+                        ForecastParameter::UvIndex(6.7),
+                        ForecastParameter::Visibility(Metres(650.0)),
+                        ForecastParameter::ApparentTemperature {
+                            temperature: Celsius(-2.3),
+                            apparent: Celsius(-9.6),
+                        },
+                    ],
+                },
+            ]),
+        };
+        let rows = match &output.body {
+            ForecastBody::Rows(rows) => rows,
+            ForecastBody::Sounding(_) | ForecastBody::WindsAloft(_) => unreachable!(),
+        };
+        let formatted = output.format(&FormatForecastOptions::default());
+        let decoded = decode_short(&formatted);
+
+        assert!(
+            decoded.unrecognized.is_empty(),
+            "{:?}",
+            decoded.unrecognized
+        );
+        assert_eq!(
+            DecodedHeader {
+                utc_offset_minutes: Some(5 * 60 + 45),
+                forecast_elevation: Some(1500.0),
+                terrain_elevation: Some(2216.0),
+                sea_cell: false,
+                errors: true,
+            },
+            decoded.header
+        );
+        assert_eq!(rows.len(), decoded.rows.len());
+        for (row, decoded_row) in rows.iter().zip(&decoded.rows) {
+            assert_eq!(row.time.day(), decoded_row.day);
+            assert_eq!(row.time.hour(), decoded_row.hour);
+            assert_eq!(row.parameters.len(), decoded_row.parameters.len());
+            for (parameter, decoded_parameter) in row.parameters.iter().zip(&decoded_row.parameters)
+            {
+                match (parameter, decoded_parameter) {
+                    (
+                        ForecastParameter::WeatherCode(code),
+                        DecodedParameter::WeatherCode {
+                            code: decoded_code,
+                            description,
+                        },
+                    ) => {
+                        assert_eq!(code.code(), *decoded_code);
+                        assert_eq!(Some(code.to_string()), *description);
+                    }
+                    (
+                        ForecastParameter::FreezingLevelHeight {
+                            height: Metres(height),
+                            trend,
+                        },
+                        DecodedParameter::FreezingLevelHeight {
+                            metres,
+                            trend: decoded_trend,
+                        },
+                    ) => {
+                        assert!((height - metres).abs() <= 50.0);
+                        assert_eq!(trend, decoded_trend);
+                    }
+                    (
+                        ForecastParameter::Wind10m {
+                            speed: KmPerHour(speed),
+                            gusts,
+                            direction,
+                        },
+                        DecodedParameter::Wind {
+                            speed_kmh,
+                            gusts_kmh,
+                            direction_degrees,
+                        },
+                    ) => {
+                        assert!((speed - speed_kmh).abs() <= 5.0);
+                        assert_eq!(gusts.is_some(), gusts_kmh.is_some());
+                        if let (Some(KmPerHour(gusts)), Some(gusts_kmh)) = (gusts, gusts_kmh) {
+                            assert!((gusts - gusts_kmh).abs() <= 5.0);
+                        }
+                        assert!((direction - direction_degrees).abs() <= 5.0);
+                    }
+                    (
+                        ForecastParameter::AccumulatedPrecipitation {
+                            amount: Millimetres(precipitation),
+                            probability,
+                        },
+                        DecodedParameter::Precipitation {
+                            millimetres,
+                            probability_percent,
+                        },
+                    ) => {
+                        assert!((precipitation - millimetres).abs() <= 0.5);
+                        assert_eq!(probability.is_some(), probability_percent.is_some());
+                        if let (Some(probability), Some(probability_percent)) =
+                            (probability, probability_percent)
+                        {
+                            assert!((probability - probability_percent).abs() <= 0.5);
+                        }
+                    }
+                    (
+                        ForecastParameter::CloudCover { low, mid, high },
+                        DecodedParameter::CloudCover {
+                            low_percent,
+                            mid_percent,
+                            high_percent,
+                        },
+                    ) => {
+                        assert!((low - low_percent).abs() <= 5.0);
+                        assert!((mid - mid_percent).abs() <= 5.0);
+                        assert!((high - high_percent).abs() <= 5.0);
+                    }
+                    (
+                        ForecastParameter::RelativeHumidity(humidity),
+                        DecodedParameter::RelativeHumidity { percent },
+                    ) => assert!((humidity - percent).abs() <= 5.0),
+                    (
+                        ForecastParameter::Dewpoint(Celsius(dewpoint)),
+                        DecodedParameter::Dewpoint { celsius },
+                    ) => assert!((dewpoint - celsius).abs() <= 0.5),
+                    (
+                        ForecastParameter::PressureMsl { pressure, tendency },
+                        DecodedParameter::PressureMsl {
+                            hpa,
+                            tendency: decoded_tendency,
+                        },
+                    ) => {
+                        assert!((pressure - hpa).abs() <= 0.5);
+                        assert_eq!(tendency, decoded_tendency);
+                    }
+                    (ForecastParameter::UvIndex(uv_index), DecodedParameter::UvIndex { index }) => {
+                        assert!((uv_index - index).abs() <= 0.5)
+                    }
+                    (
+                        ForecastParameter::Visibility(Metres(visibility)),
+                        DecodedParameter::Visibility { metres },
+                    ) => {
+                        let tolerance = if *visibility < 1000.0 { 50.0 } else { 500.0 };
+                        assert!((visibility - metres).abs() <= tolerance);
+                    }
+                    (
+                        ForecastParameter::ApparentTemperature {
+                            temperature: Celsius(temperature),
+                            apparent: Celsius(apparent),
+                        },
+                        DecodedParameter::ApparentTemperature {
+                            celsius,
+                            apparent_celsius,
+                        },
+                    ) => {
+                        assert!((temperature - celsius).abs() <= 0.5);
+                        assert!((apparent - apparent_celsius).abs() <= 0.5);
+                    }
+                    _ => panic!("Unexpected decoded parameter {:?}", decoded_parameter),
+                }
+            }
+        }
+    }
+
+    /// Test that errors (which may quote the user's request) are escaped in html output.
+    #[test]
+    fn test_forecast_window_end_hour() {
+        let utc_now = chrono::NaiveDate::from_ymd(2022, 11, 3).and_hms(8, 30, 0);
+        let window = ForecastWindow {
+            past_days: Some(1),
+            days: Some(2),
+        };
+        assert_eq!(
+            Some(chrono::NaiveDate::from_ymd(2022, 11, 5).and_hms(23, 0, 0)),
+            window.end_hour(utc_now)
+        );
+        assert_eq!(None, ForecastWindow::default().end_hour(utc_now));
+        let window = ForecastWindow {
+            past_days: None,
+            days: Some(7),
+        };
+        assert_eq!(None, window.end_hour(utc_now));
+    }
+
+    #[test]
+    fn test_rejected_message() {
+        assert_eq!(
+            "The forecast position is out of range, latitude must be between -90 and 90, and \
+            longitude must be between -180 and 180",
+            rejected_message(&ApiError::CoordinatesOutOfRange(
+                "Latitude must be in range of -90 to 90°. Given: 100.0.".to_string()
+            ))
+        );
+        assert_eq!(
+            "The forecast request contained an invalid parameter: Invalid hourly variable",
+            rejected_message(&ApiError::InvalidParameter(
+                "Invalid hourly variable".to_string()
+            ))
+        );
+    }
+
+    #[test]
+    fn test_beaufort_from_speed() {
+        assert_eq!(Beaufort(0), Beaufort::from_speed(KmPerHour(0.4)));
+        assert_eq!(Beaufort(1), Beaufort::from_speed(KmPerHour(1.0)));
+        assert_eq!(Beaufort(3), Beaufort::from_speed(KmPerHour(19.0)));
+        assert_eq!(Beaufort(4), Beaufort::from_speed(KmPerHour(19.6)));
+        assert_eq!(Beaufort(12), Beaufort::from_speed(KmPerHour(150.0)));
+        assert_eq!("force 8 (gale)", Beaufort(8).to_string());
+    }
+
+    #[test]
+    fn test_format_wind_beaufort() {
+        let wind = ForecastParameter::Wind10m {
+            speed: KmPerHour(25.0),
+            gusts: Some(KmPerHour(45.0)),
+            direction: 4.0,
+        };
+        let short = wind.format(&FormatForecastOptions {
+            wind_unit: WindUnit::Beaufort,
+            ..FormatForecastOptions::default()
+        });
+        assert_eq!("WF4gF6@0", short);
+        assert_eq!(
+            Some(DecodedParameter::WindBeaufort {
+                force: 4,
+                gusts_force: Some(6),
+                direction_degrees: 0.0
+            }),
+            decode_parameter(&short)
+        );
+
+        let long_options = FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail::default()),
+            wind_unit: WindUnit::Beaufort,
+            ..FormatForecastOptions::default()
+        };
+        assert_eq!(
+            "force 4 (moderate breeze) at 4°",
+            wind.format(&long_options)
+        );
+        assert_eq!(
+            vec![
+                "force 4 (moderate breeze) at 4°".to_owned(),
+                "force 6 (strong breeze)".to_owned()
+            ],
+            wind.long_cells(&long_options)
+        );
+    }
+
+    /// Test that the UV index is the peak of the local day of the row.
+    #[test]
+    fn test_forecast_parameter_uv_index() {
+        let start = chrono::NaiveDate::from_ymd(2022, 12, 3).and_hms(18, 0, 0);
+        let hourly = open_meteo::Hourly {
+            time: (0..12)
+                .map(|hour| start + chrono::Duration::hours(hour))
+                .collect(),
+            uv_index: Some(vec![
+                4.0, 2.0, 1.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0, 0.0,
+            ]),
+            ..open_meteo::Hourly::default()
+        };
+        let uv_index = |i| match forecast_parameter(
+            ForecastVariable::UvIndex,
+            &hourly,
+            i,
+            Millimetres::default(),
+        )
+        .unwrap()
+        {
+            ForecastParameter::UvIndex(uv_index) => uv_index,
+            _ => unreachable!(),
+        };
+        assert_eq!(4.0, uv_index(1));
+        assert_eq!(0.0, uv_index(8));
+    }
+
+    #[test]
+    fn test_format_sounding() {
+        let level =
+            |pressure, height, temperature, dewpoint, wind_speed, wind_direction| SoundingLevel {
+                pressure,
+                height: Metres(height),
+                temperature: Celsius(temperature),
+                dewpoint: Celsius(dewpoint),
+                wind_speed: KmPerHour(wind_speed),
+                wind_direction,
+            };
+        let output = ForecastOutput {
+            errors: Vec::new(),
+            total_timezone_offset: chrono::Duration::hours(13),
+            forecast_elevation: 1500.0,
+            terrain_elevation: None,
+            sea_cell: false,
+            source: None,
+            body: ForecastBody::Sounding(Sounding {
+                time: "2022-12-03T21:00:00".parse().unwrap(),
+                levels: vec![
+                    level(PressureLevel::L850, 1520.0, 4.2, -1.6, 22.0, 275.0),
+                    level(PressureLevel::L700, 3080.0, -6.4, -12.3, 48.0, 290.0),
+                ],
+            }),
+        };
+
+        let short = output.format(&FormatForecastOptions::default());
+        assert_eq!(
+            "Tz+13:00 FE1500\n03T21\n850 H15 T4/-2 W2@28\n700 H31 T-6/-12 W5@29",
+            short
+        );
+
+        let long = output.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(long.contains("Sounding at Sat 3 Dec 9:00 PM"), "{long}");
+        assert!(long.contains("850hPa"), "{long}");
+        assert!(long.contains("-12°C"), "{long}");
+        assert!(long.contains("48 km/h at 290°"), "{long}");
+
+        let long = output.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            language: Language::German,
+            ..FormatForecastOptions::default()
+        });
+        assert!(long.contains("Sounding at Sa. 3. Dez. 21:00"), "{long}");
+    }
+
+    #[test]
+    fn test_winds_aloft_interpolate() {
+        let level = WindsAloftLevel::interpolate(
+            6000,
+            (Metres(1500.0), Celsius(10.0), KmPerHour(40.0), 270.0),
+            (Metres(3000.0), Celsius(0.0), KmPerHour(60.0), 270.0),
+        );
+        assert_eq!(6000, level.altitude);
+        assert_eq!(8.0, level.temperature.0.round());
+        assert_eq!(44.0, level.wind_speed.0.round());
+        assert_eq!(270.0, level.wind_direction.round());
+        assert_eq!("2724+08", level.fb());
+
+        // Winds on either side of north.
+        let level = WindsAloftLevel::interpolate(
+            6000,
+            (Metres(1000.0), Celsius(0.0), KmPerHour(40.0), 350.0),
+            (Metres(2657.6), Celsius(0.0), KmPerHour(40.0), 10.0),
+        );
+        assert_eq!(0.0, level.wind_direction.round().rem_euclid(360.0));
+        assert_eq!("3621+00", level.fb());
+    }
+
+    #[test]
+    fn test_winds_aloft_fb() {
+        let level = |altitude, temperature, knots: f32, wind_direction| WindsAloftLevel {
+            altitude,
+            temperature: Celsius(temperature),
+            wind_speed: KmPerHour(knots * 1.852),
+            wind_direction,
+        };
+        assert_eq!("9900", level(3000, 12.0, 3.0, 180.0).fb());
+        assert_eq!("2714", level(3000, 12.0, 14.0, 270.0).fb());
+        assert_eq!("0905-03", level(9000, -2.6, 5.0, 92.0).fb());
+        assert_eq!("2725+00", level(12000, -0.4, 25.0, 270.0).fb());
+        assert_eq!("751549", level(34000, -48.6, 115.0, 250.0).fb());
+        assert_eq!("759956", level(39000, -56.0, 230.0, 250.0).fb());
+    }
+
+    #[test]
+    fn test_format_winds_aloft() {
+        let level = |altitude, temperature, wind_speed, wind_direction| WindsAloftLevel {
+            altitude,
+            temperature: Celsius(temperature),
+            wind_speed: KmPerHour(wind_speed),
+            wind_direction,
+        };
+        let output = ForecastOutput {
+            errors: Vec::new(),
+            total_timezone_offset: chrono::Duration::hours(13),
+            forecast_elevation: 500.0,
+            terrain_elevation: None,
+            sea_cell: false,
+            source: None,
+            body: ForecastBody::WindsAloft(WindsAloft {
+                time: "2022-12-03T21:00:00".parse().unwrap(),
+                levels: vec![
+                    level(6000, 4.2, 46.3, 275.0),
+                    level(9000, -6.4, 74.1, 290.0),
+                ],
+            }),
+        };
+
+        let short = output.format(&FormatForecastOptions::default());
+        assert_eq!(
+            "Tz+13:00 FE500\nFB 03T21\n6000 2825+04\n9000 2940-06",
+            short
+        );
+
+        let long = output.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(long.contains("Winds aloft at Sat 3 Dec 9:00 PM"), "{long}");
+        assert!(long.contains("9000ft"), "{long}");
+        assert!(long.contains("40 kt at 290°"), "{long}");
+        assert!(long.contains("-6°C"), "{long}");
+    }
+
+    #[test]
+    fn test_format_sea_cell() {
+        assert!(is_offshore(-12.0));
+        assert!(!is_offshore(0.0));
+        assert!(!is_offshore(2216.0));
+
+        let output = ForecastOutput {
+            errors: Vec::new(),
+            total_timezone_offset: chrono::Duration::zero(),
+            forecast_elevation: 0.0,
+            terrain_elevation: Some(-12.0),
+            sea_cell: true,
+            source: None,
+            body: ForecastBody::Rows(Vec::new()),
+        };
+        let formatted = output.format(&FormatForecastOptions::default());
+        assert_eq!("TzGMT FE0 TE-12 SEA\n", formatted);
+        let decoded = decode_short(&formatted);
+        assert!(decoded.header.sea_cell);
+        assert!(
+            decoded.unrecognized.is_empty(),
+            "{:?}",
+            decoded.unrecognized
+        );
+
+        let long = output.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(
+            long.contains("Terrain Elevation: -12m, Grid Cell: Sea"),
+            "{long}"
+        );
+    }
+
+    #[test]
+    fn test_format_html_escapes_errors() {
+        let output = ForecastOutput {
+            errors: vec!["Error parsing request: <script>alert(1)</script>".to_owned()],
+            total_timezone_offset: chrono::Duration::zero(),
+            forecast_elevation: 1000.0,
+            terrain_elevation: None,
+            sea_cell: false,
+            source: None,
+            body: ForecastBody::Rows(Vec::new()),
+        };
+        let formatted = output.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::Html),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(!formatted.contains("<script>"));
+        assert!(formatted.contains("&lt;script&gt;alert(1)&lt;/script&gt;<br>"));
+    }
+
+    #[test]
+    fn test_format_weather_code_emoji() {
+        let parameter = ForecastParameter::WeatherCode(WeatherCode::SnowModerate);
+        let format = |style| {
+            parameter.format(&FormatForecastOptions {
+                detail: FormatDetail::Long(LongFormatDetail { style }),
+                ..FormatForecastOptions::default()
+            })
+        };
+        assert_eq!("🌨️ moderate snow", format(Some(LongFormatStyle::Html)));
+        assert_eq!("moderate snow", format(Some(LongFormatStyle::PlainText)));
+        assert_eq!("C73", parameter.format(&FormatForecastOptions::default()));
+    }
+
+    #[test]
+    fn test_format_source() {
+        let output = ForecastOutput {
+            errors: Vec::new(),
+            total_timezone_offset: chrono::Duration::zero(),
+            forecast_elevation: 1000.0,
+            terrain_elevation: None,
+            sea_cell: false,
+            source: Some(ForecastSource {
+                provider: "Open-Meteo",
+                model_run: Some(ModelRun {
+                    model: "GFS".to_owned(),
+                    initialised_at: "2022-12-03T06:00:00Z".parse().unwrap(),
+                }),
+                issued_at: None,
+                generation_time_ms: Some(0.6123),
+                obtained_at: "2022-12-03T08:35:10Z".parse().unwrap(),
+            }),
+            body: ForecastBody::Rows(Vec::new()),
+        };
+        let long = output.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(
+            long.ends_with(
+                "\n\nForecast data by Open-Meteo, GFS run 06Z, generated in 0.6 ms, \
+                obtained 2022-12-03 08:35 UTC."
+            ),
+            "{long}"
+        );
+
+        // The short format is kept as short as possible.
+        let short = output.format(&FormatForecastOptions::default());
+        assert!(!short.contains("Open-Meteo"), "{short}");
+
+        let source = ForecastSource {
+            provider: "MET Norway",
+            model_run: None,
+            issued_at: Some("2022-12-03T08:00:00Z".parse().unwrap()),
+            generation_time_ms: None,
+            obtained_at: "2022-12-03T08:35:10Z".parse().unwrap(),
+        };
+        assert_eq!(
+            "Forecast data by MET Norway, issued 2022-12-03 08:00 UTC, \
+            obtained 2022-12-03 08:35 UTC.",
+            format_source_long(&source)
+        );
+    }
+
     static FORECAST_MT_COOK: Lazy<Forecast> = Lazy::new(|| {
         serde_json::from_str(&std::fs::read_to_string("fixtures/forecast_mt_cook.json").unwrap())
             .unwrap()
@@ -721,9 +2943,19 @@ mod test {
         let forecast_request = ParsedForecastRequest {
             request: ForecastRequest {
                 position: Some(Position::new(-43.513832, 170.33975)),
-                format: FormatForecastOptions {
+                format: Some(FormatForecastOptions {
                     detail: FormatDetail::Short(ShortFormatDetail::default()),
-                },
+                    ..FormatForecastOptions::default()
+                }),
+                profile: None,
+                past_days: None,
+                grib: false,
+                fly: false,
+                snow_outlook: None,
+                metar: None,
+                past_weather: None,
+                flood: None,
+                solar: false,
             },
             ..ParsedForecastRequest::default()
         };
@@ -732,6 +2964,9 @@ mod test {
             from_name: "Test".to_owned(),
             referral_url: referral_url.clone(),
             position: Position::new(-43.75905, 170.115),
+            mapshare_url: None,
+            device_id: None,
+            sent_at: Some("2022-12-03T08:00:00Z".parse().unwrap()),
             forecast_request,
         });
 
@@ -744,11 +2979,14 @@ mod test {
                 .hourly_entry(HourlyVariable::FreezingLevelHeight)
                 .hourly_entry(HourlyVariable::WindSpeed(GroundLevel::L10))
                 .hourly_entry(HourlyVariable::WindDirection(GroundLevel::L10))
+                .hourly_entry(HourlyVariable::WindGusts10m)
                 .hourly_entry(HourlyVariable::WeatherCode)
                 .hourly_entry(HourlyVariable::Precipitation)
+                .hourly_entry(HourlyVariable::PrecipitationProbability)
                 .timezone(open_meteo::TimeZone::Auto)
+                .elevation(2216.0)
                 .build()))
-            .return_once(|_| Ok(FORECAST_MT_COOK.clone()));
+            .return_once(|_| Ok(ObtainedForecast::fixture(FORECAST_MT_COOK.clone())));
 
         let mut topo_data_service = topo_data_service::MockPort::new();
         topo_data_service
@@ -760,13 +2998,25 @@ mod test {
             }))
             .return_once(|_| Ok(2216.0));
 
+        // The message was queued, the forecast should still be aligned with when it was sent.
         let mut time = crate::time::MockPort::new();
         time.expect_utc_now()
-            .return_once(|| "2022-12-03T08:00:00Z".parse().unwrap());
-
-        let reply = process_email(&time, &forecast_service, &topo_data_service, received_email)
-            .await
-            .unwrap();
+            .return_once(|| "2022-12-03T11:30:00Z".parse().unwrap());
+
+        let reply = process_email(
+            &time,
+            &forecast_service,
+            Some(&topo_data_service),
+            None,
+            None,
+            None,
+            None,
+            &DynamicOptions::default(),
+            received_email,
+            None,
+        )
+        .await
+        .unwrap();
 
         let reply: reply::InReach = match reply {
             Reply::InReach(reply) => reply,
@@ -776,4 +3026,45 @@ mod test {
         assert_eq!(referral_url, reply.referral_url);
         insta::assert_snapshot!(reply.message);
     }
+
+    /// Test that the `ABOUT` command is replied to without obtaining a forecast.
+    #[tokio::test]
+    async fn test_process_email_about() {
+        let referral_url: url::Url = "https://example.org".parse().unwrap();
+        let received_email = &crate::receive::ReceivedKind::Inreach(inreach::email::Received {
+            from_name: "Test".to_owned(),
+            referral_url: referral_url.clone(),
+            position: Position::new(-43.75905, 170.115),
+            mapshare_url: None,
+            device_id: None,
+            sent_at: None,
+            forecast_request: ParsedForecastRequest::parse("ABOUT"),
+        });
+
+        let reply = process_email(
+            &crate::time::MockPort::new(),
+            &forecast_service::MockPort::new(),
+            Some(&topo_data_service::MockPort::new()),
+            None,
+            None,
+            None,
+            None,
+            &DynamicOptions::default(),
+            received_email,
+            None,
+        )
+        .await
+        .unwrap();
+
+        let reply: reply::InReach = match reply {
+            Reply::InReach(reply) => reply,
+            _ => panic!("Unexpected reply: {:?}", reply),
+        };
+        assert_eq!(referral_url, reply.referral_url);
+        assert!(
+            reply.message.starts_with("email-weather v"),
+            "{}",
+            reply.message
+        );
+    }
 }
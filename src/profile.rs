@@ -0,0 +1,113 @@
+//! Operator defined forecast profiles, see [`ForecastProfile`].
+
+use open_meteo::{GroundLevel, HourlyVariable};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::format::FormatForecastOptions;
+
+/// A variable which can be included in the forecast reply.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+pub enum ForecastVariable {
+    /// WMO weather code.
+    WeatherCode,
+    /// Altitude of the 0°C level.
+    FreezingLevelHeight,
+    /// Wind speed, gusts and direction at 10m above the ground.
+    Wind10m,
+    /// Precipitation accumulated since the previous row in the forecast, and its probability.
+    Precipitation,
+    /// Cloud cover of the low (up to 3km), mid (3km to 8km) and high (above 8km) levels.
+    CloudCover,
+    /// Relative humidity at 2m above the ground.
+    RelativeHumidity,
+    /// Dew point temperature at 2m above the ground.
+    Dewpoint,
+    /// Air pressure reduced to mean sea level, with the tendency over the previous 3 hours.
+    PressureMsl,
+    /// Peak UV index of the day.
+    UvIndex,
+    /// Horizontal visibility, important for fog at sea and in the air.
+    Visibility,
+    /// Air temperature at 2m above the ground, alongside the apparent (feels-like) temperature
+    /// which includes wind chill.
+    ApparentTemperature,
+}
+
+impl ForecastVariable {
+    /// The variables included in the forecast when no profile is selected.
+    pub const DEFAULT: &'static [Self] = &[
+        Self::WeatherCode,
+        Self::FreezingLevelHeight,
+        Self::Wind10m,
+        Self::Precipitation,
+    ];
+
+    /// The hourly variables that need to be requested from Open-Meteo for this variable.
+    #[must_use]
+    pub fn hourly_variables(&self) -> &'static [HourlyVariable] {
+        match self {
+            ForecastVariable::WeatherCode => &[HourlyVariable::WeatherCode],
+            ForecastVariable::FreezingLevelHeight => &[HourlyVariable::FreezingLevelHeight],
+            ForecastVariable::Wind10m => &[
+                HourlyVariable::WindSpeed(GroundLevel::L10),
+                HourlyVariable::WindDirection(GroundLevel::L10),
+                HourlyVariable::WindGusts10m,
+            ],
+            ForecastVariable::Precipitation => &[
+                HourlyVariable::Precipitation,
+                HourlyVariable::PrecipitationProbability,
+            ],
+            ForecastVariable::CloudCover => &[
+                HourlyVariable::CloudCoverLow,
+                HourlyVariable::CloudCoverMid,
+                HourlyVariable::CloudCoverHigh,
+            ],
+            ForecastVariable::RelativeHumidity => &[HourlyVariable::RelativeHumidity2m],
+            ForecastVariable::Dewpoint => &[HourlyVariable::Dewpoint2m],
+            ForecastVariable::PressureMsl => &[HourlyVariable::PressureMsl],
+            ForecastVariable::UvIndex => &[HourlyVariable::UvIndex],
+            ForecastVariable::Visibility => &[HourlyVariable::Visibility],
+            ForecastVariable::ApparentTemperature => &[
+                HourlyVariable::Temperature2m,
+                HourlyVariable::ApparentTemperature,
+            ],
+        }
+    }
+}
+
+fn default_variables() -> Vec<ForecastVariable> {
+    ForecastVariable::DEFAULT.to_vec()
+}
+
+/// A named forecast profile, defined in [`crate::options::Options::profiles`]. Users can select
+/// a profile in their request (e.g. `P=alpine`), and operators can select a default profile using
+/// [`crate::options::Options::default_profile`].
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct ForecastProfile {
+    /// Variables to include in the forecast, in the order that they will be displayed.
+    ///
+    /// Default is [`ForecastVariable::DEFAULT`].
+    #[serde(default = "default_variables")]
+    pub variables: Vec<ForecastVariable>,
+    /// Format used for the reply if the request does not specify one.
+    ///
+    /// Default is `None` (use [`crate::options::Options::default_format`]).
+    #[serde(default)]
+    pub format: Option<FormatForecastOptions>,
+    /// Number of days to include in the forecast.
+    ///
+    /// Default is `None` (all available days).
+    #[serde(default)]
+    pub days: Option<u8>,
+}
+
+impl Default for ForecastProfile {
+    fn default() -> Self {
+        Self {
+            variables: default_variables(),
+            format: None,
+            days: None,
+        }
+    }
+}
@@ -0,0 +1,102 @@
+//! Encryption of queue payloads at rest, so that the personal data in queued emails and replies
+//! (addresses, positions, inReach referral urls) is not readable from the queue files or Redis,
+//! see [`QueueCipher`].
+
+use chacha20poly1305::{
+    aead::{Aead, KeyInit},
+    XChaCha20Poly1305, XNonce,
+};
+use rand::RngCore;
+use secrecy::{ExposeSecret, SecretString};
+
+/// Prefix of encrypted payloads, which distinguishes them from plain JSON payloads queued before
+/// encryption was enabled.
+const MAGIC: &[u8] = b"ewq1";
+/// Length of the random nonce which follows [`MAGIC`].
+const NONCE_LEN: usize = 24;
+
+/// Encrypts queue payloads using XChaCha20-Poly1305 with a key provided by the `QUEUE_KEY` secret,
+/// see [`super::Sender::with_cipher()`] and [`super::Receiver::with_cipher()`].
+pub struct QueueCipher {
+    cipher: XChaCha20Poly1305,
+}
+
+impl QueueCipher {
+    /// Construct a new [`QueueCipher`] from a base64 encoded 32 byte `key` (e.g. generated with
+    /// `openssl rand -base64 32`).
+    pub fn new(key: &SecretString) -> eyre::Result<Self> {
+        let key = base64::decode(key.expose_secret().trim())
+            .map_err(|error| eyre::eyre!("QUEUE_KEY is not valid base64: {}", error))?;
+        let cipher = XChaCha20Poly1305::new_from_slice(&key)
+            .map_err(|_| eyre::eyre!("QUEUE_KEY needs to be 32 bytes, not {} bytes", key.len()))?;
+        Ok(Self { cipher })
+    }
+
+    /// Encrypt `data`, prefixed with [`MAGIC`] and a random nonce.
+    pub fn encrypt(&self, data: &[u8]) -> eyre::Result<Vec<u8>> {
+        let mut nonce = [0u8; NONCE_LEN];
+        rand::thread_rng().fill_bytes(&mut nonce);
+        let ciphertext = self
+            .cipher
+            .encrypt(XNonce::from_slice(&nonce), data)
+            .map_err(|_| eyre::eyre!("Error encrypting queue payload"))?;
+
+        let mut payload = Vec::with_capacity(MAGIC.len() + NONCE_LEN + ciphertext.len());
+        payload.extend_from_slice(MAGIC);
+        payload.extend_from_slice(&nonce);
+        payload.extend_from_slice(&ciphertext);
+        Ok(payload)
+    }
+
+    /// Decrypt a `payload` produced by [`QueueCipher::encrypt()`]. Returns `None` if the payload
+    /// isn't encrypted (it was queued before encryption was enabled).
+    pub fn decrypt(&self, payload: &[u8]) -> Option<eyre::Result<Vec<u8>>> {
+        let encrypted = payload.strip_prefix(MAGIC)?;
+        if encrypted.len() < NONCE_LEN {
+            return Some(Err(eyre::eyre!("Encrypted queue payload is truncated")));
+        }
+        let (nonce, ciphertext) = encrypted.split_at(NONCE_LEN);
+        Some(
+            self.cipher
+                .decrypt(XNonce::from_slice(nonce), ciphertext)
+                .map_err(|_| eyre::eyre!("Error decrypting queue payload, was QUEUE_KEY changed?")),
+        )
+    }
+}
+
+/// Returns `true` if `payload` was encrypted using [`QueueCipher::encrypt()`].
+pub fn is_encrypted(payload: &[u8]) -> bool {
+    payload.starts_with(MAGIC)
+}
+
+#[cfg(test)]
+mod test {
+    use secrecy::SecretString;
+
+    use super::{is_encrypted, QueueCipher};
+
+    fn key(bytes: &[u8]) -> SecretString {
+        SecretString::new(base64::encode(bytes))
+    }
+
+    #[test]
+    fn test_encrypt_decrypt() {
+        let cipher = QueueCipher::new(&key(&[7; 32])).unwrap();
+        let payload = cipher.encrypt(br#"{"from": "user@example.com"}"#).unwrap();
+        assert!(is_encrypted(&payload));
+        assert!(!payload
+            .windows(b"user@example.com".len())
+            .any(|window| window == b"user@example.com"));
+        assert_eq!(
+            br#"{"from": "user@example.com"}"#.to_vec(),
+            cipher.decrypt(&payload).unwrap().unwrap()
+        );
+
+        // Payloads queued before encryption was enabled are passed through.
+        assert!(cipher.decrypt(br#"{"from": "user@example.com"}"#).is_none());
+
+        let other = QueueCipher::new(&key(&[8; 32])).unwrap();
+        assert!(other.decrypt(&payload).unwrap().is_err());
+        assert!(QueueCipher::new(&key(&[7; 16])).is_err());
+    }
+}
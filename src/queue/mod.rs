@@ -0,0 +1,454 @@
+//! Queues which pass received emails from the receive task to the process task, and replies from
+//! the process task to the reply task.
+//!
+//! See [`Backend`], [`Sender`] and [`Receiver`]. Messages can be encrypted at rest using a
+//! [`QueueCipher`]. Messages which can't be decrypted are moved to a dead letter directory, see
+//! [`Undecryptable`].
+
+use std::{
+    ops::Deref,
+    path::{Path, PathBuf},
+};
+
+use eyre::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::startup;
+
+pub mod cipher;
+pub mod redis;
+
+pub use cipher::QueueCipher;
+
+/// Where the queues are stored, specified in [`crate::options::Options`].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Backend {
+    /// Queues are stored in files in the `data_dir`, using [`yaque`]. Only a single instance of
+    /// the service can use the queues.
+    Local,
+    /// Queues are stored in [Redis Streams](https://redis.io/docs/data-types/streams/), so that
+    /// multiple replicas of the service can share the same mailbox, see [`redis`].
+    Redis(redis::Options),
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::Local
+    }
+}
+
+impl Backend {
+    /// Validate that the queue named `name` can be opened (creating it if it does not exist),
+    /// before starting the tasks which use it.
+    pub fn validate(&self, data_dir: &Path, name: &str) -> eyre::Result<()> {
+        match self {
+            Backend::Local => startup::validate_queue(&data_dir.join(name)),
+            Backend::Redis(options) => options.client().map(drop),
+        }
+    }
+
+    /// Open the queue named `name` for sending.
+    pub fn open_sender(&self, data_dir: &Path, name: &str) -> eyre::Result<Sender> {
+        match self {
+            Backend::Local => {
+                let path = data_dir.join(name);
+                yaque::Sender::open(&path)
+                    .map(Sender::from)
+                    .wrap_err_with(|| format!("Unable to open queue sender at {:?}", path))
+            }
+            Backend::Redis(options) => redis::StreamSender::new(options, name)
+                .map(|sender| SenderKind::Redis(Box::new(sender)).into()),
+        }
+    }
+
+    /// Open the queue named `name` for receiving.
+    pub fn open_receiver(&self, data_dir: &Path, name: &str) -> eyre::Result<Receiver> {
+        match self {
+            Backend::Local => {
+                let path = data_dir.join(name);
+                yaque::Receiver::open(&path)
+                    .map(Receiver::from)
+                    .wrap_err_with(|| format!("Unable to open queue receiver at {:?}", path))
+            }
+            Backend::Redis(options) => redis::StreamReceiver::new(options, name)
+                .map(|receiver| ReceiverKind::Redis(Box::new(receiver)).into()),
+        }
+        .map(|receiver| receiver.with_dead_letter_dir(dead_letter_dir(data_dir, name)))
+    }
+
+    /// The election of the replica which receives emails, `None` if there is only a single
+    /// instance of the service.
+    pub fn leader_election(&self) -> eyre::Result<Option<redis::LeaderElection>> {
+        match self {
+            Backend::Local => Ok(None),
+            Backend::Redis(options) => redis::LeaderElection::new(options).map(Some),
+        }
+    }
+}
+
+/// The directory that messages from the queue named `name` which can't be decrypted are moved to,
+/// see [`Undecryptable::dead_letter()`].
+#[must_use]
+pub fn dead_letter_dir(data_dir: &Path, name: &str) -> PathBuf {
+    data_dir.join("dead_letter").join(name)
+}
+
+/// Sends messages to a queue, see [`Backend::open_sender()`].
+pub struct Sender {
+    kind: SenderKind,
+    cipher: Option<&'static QueueCipher>,
+}
+
+/// The backend of a [`Sender`].
+pub enum SenderKind {
+    /// See [`Backend::Local`].
+    Local(yaque::Sender),
+    /// See [`Backend::Redis`].
+    Redis(Box<redis::StreamSender>),
+}
+
+impl From<SenderKind> for Sender {
+    fn from(kind: SenderKind) -> Self {
+        Self { kind, cipher: None }
+    }
+}
+
+impl From<yaque::Sender> for Sender {
+    fn from(sender: yaque::Sender) -> Self {
+        SenderKind::Local(sender).into()
+    }
+}
+
+impl Sender {
+    /// Encrypt the messages which are sent using `cipher`, if specified.
+    #[must_use]
+    pub fn with_cipher(mut self, cipher: Option<&'static QueueCipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Send `data` to the queue.
+    pub async fn send(&mut self, data: &[u8]) -> eyre::Result<()> {
+        let encrypted;
+        let data = match self.cipher {
+            Some(cipher) => {
+                encrypted = cipher.encrypt(data)?;
+                encrypted.as_slice()
+            }
+            None => data,
+        };
+        match &mut self.kind {
+            SenderKind::Local(sender) => sender
+                .send(data)
+                .await
+                .wrap_err("Error sending to local queue"),
+            SenderKind::Redis(sender) => sender.send(data).await,
+        }
+    }
+}
+
+/// Receives messages from a queue, see [`Backend::open_receiver()`].
+pub struct Receiver {
+    kind: ReceiverKind,
+    cipher: Option<&'static QueueCipher>,
+    dead_letter_dir: Option<PathBuf>,
+}
+
+/// The backend of a [`Receiver`].
+pub enum ReceiverKind {
+    /// See [`Backend::Local`].
+    Local(yaque::Receiver),
+    /// See [`Backend::Redis`].
+    Redis(Box<redis::StreamReceiver>),
+}
+
+impl From<ReceiverKind> for Receiver {
+    fn from(kind: ReceiverKind) -> Self {
+        Self {
+            kind,
+            cipher: None,
+            dead_letter_dir: None,
+        }
+    }
+}
+
+impl From<yaque::Receiver> for Receiver {
+    fn from(receiver: yaque::Receiver) -> Self {
+        ReceiverKind::Local(receiver).into()
+    }
+}
+
+impl Receiver {
+    /// Decrypt the messages which are received using `cipher`, if specified. Messages which were
+    /// queued before encryption was enabled are received unchanged.
+    #[must_use]
+    pub fn with_cipher(mut self, cipher: Option<&'static QueueCipher>) -> Self {
+        self.cipher = cipher;
+        self
+    }
+
+    /// Move the messages which can't be decrypted to `dir`, see [`Undecryptable::dead_letter()`].
+    #[must_use]
+    pub fn with_dead_letter_dir(mut self, dir: PathBuf) -> Self {
+        self.dead_letter_dir = Some(dir);
+        self
+    }
+
+    /// Wait for the next message in the queue. The message remains in the queue until it is
+    /// [committed](Received::commit), if it is dropped instead it will be received again.
+    pub async fn recv(&mut self) -> eyre::Result<Message<'_>> {
+        let kind = match &mut self.kind {
+            ReceiverKind::Local(receiver) => receiver
+                .recv()
+                .await
+                .map(ReceivedKind::Local)
+                .wrap_err("Error receiving from local queue")?,
+            ReceiverKind::Redis(receiver) => receiver.recv().await.map(ReceivedKind::Redis)?,
+        };
+        let decrypted = match self.cipher {
+            Some(cipher) => cipher.decrypt(kind.data()).transpose(),
+            None if cipher::is_encrypted(kind.data()) => Err(eyre::eyre!(
+                "Received an encrypted message, but the QUEUE_KEY secret is not provided"
+            )),
+            None => Ok(None),
+        };
+        Ok(match decrypted {
+            Ok(decrypted) => Message::Received(Received { kind, decrypted }),
+            Err(error) => Message::Undecryptable(Undecryptable {
+                kind,
+                error,
+                dead_letter_dir: self.dead_letter_dir.as_deref(),
+            }),
+        })
+    }
+}
+
+/// A message received from a queue, see [`Receiver::recv()`].
+pub enum Message<'a> {
+    /// A message which can be handled.
+    Received(Received<'a>),
+    /// A message which can't be decrypted, and needs to be removed from the queue so that the
+    /// messages after it can be received.
+    Undecryptable(Undecryptable<'a>),
+}
+
+/// A message which can't be decrypted (e.g. it was encrypted using a different `QUEUE_KEY`), see
+/// [`Message::Undecryptable`].
+pub struct Undecryptable<'a> {
+    kind: ReceivedKind<'a>,
+    error: eyre::Report,
+    dead_letter_dir: Option<&'a Path>,
+}
+
+impl Undecryptable<'_> {
+    /// The reason that the message can't be decrypted.
+    #[must_use]
+    pub fn error(&self) -> &eyre::Report {
+        &self.error
+    }
+
+    /// Write the message (as it was received) to a new file in the dead letter directory of the
+    /// queue (see [`dead_letter_dir()`]), so that it can be recovered with the correct key, and
+    /// remove it from the queue. Returns the path of the file, `None` if the receiver has no dead
+    /// letter directory, in which case the message is discarded.
+    pub async fn dead_letter(self) -> eyre::Result<Option<PathBuf>> {
+        let path = match self.dead_letter_dir {
+            Some(dir) => {
+                tokio::fs::create_dir_all(dir)
+                    .await
+                    .wrap_err_with(|| format!("Unable to create dead letter directory {dir:?}"))?;
+                let path = dir.join(format!("{}.bin", uuid::Uuid::new_v4()));
+                tokio::fs::write(&path, self.kind.data())
+                    .await
+                    .wrap_err_with(|| format!("Unable to write dead letter {path:?}"))?;
+                Some(path)
+            }
+            None => None,
+        };
+        self.kind.commit().await?;
+        Ok(path)
+    }
+}
+
+/// A message received from a queue, see [`Message::Received`].
+pub struct Received<'a> {
+    kind: ReceivedKind<'a>,
+    /// The decrypted message, if it was encrypted.
+    decrypted: Option<Vec<u8>>,
+}
+
+/// The backend of a [`Received`] message.
+pub enum ReceivedKind<'a> {
+    /// See [`Backend::Local`].
+    Local(yaque::queue::RecvGuard<'a, Vec<u8>>),
+    /// See [`Backend::Redis`].
+    Redis(redis::Entry<'a>),
+}
+
+impl ReceivedKind<'_> {
+    fn data(&self) -> &[u8] {
+        match self {
+            ReceivedKind::Local(guard) => guard.as_slice(),
+            ReceivedKind::Redis(entry) => entry.data(),
+        }
+    }
+
+    async fn commit(self) -> eyre::Result<()> {
+        match self {
+            ReceivedKind::Local(guard) => guard.commit().wrap_err("Error committing local queue"),
+            ReceivedKind::Redis(entry) => entry.commit().await,
+        }
+    }
+}
+
+impl Received<'_> {
+    /// Remove the message from the queue, once it has been handled.
+    pub async fn commit(self) -> eyre::Result<()> {
+        self.kind.commit().await
+    }
+}
+
+impl Deref for Received<'_> {
+    type Target = [u8];
+
+    fn deref(&self) -> &Self::Target {
+        match &self.decrypted {
+            Some(decrypted) => decrypted.as_slice(),
+            None => self.kind.data(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secrecy::SecretString;
+
+    use super::{dead_letter_dir, Backend, Message, QueueCipher, Received, Receiver};
+
+    async fn recv(receiver: &mut Receiver) -> Received<'_> {
+        match receiver.recv().await.unwrap() {
+            Message::Received(received) => received,
+            Message::Undecryptable(undecryptable) => panic!("{:?}", undecryptable.error()),
+        }
+    }
+
+    fn cipher(key: u8) -> &'static QueueCipher {
+        Box::leak(Box::new(
+            QueueCipher::new(&SecretString::new(base64::encode([key; 32]))).unwrap(),
+        ))
+    }
+
+    #[tokio::test]
+    async fn test_local_send_recv_commit() {
+        let data_dir = std::env::temp_dir().join("email-weather-test-local-queue");
+        let _ = std::fs::remove_dir_all(&data_dir);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let backend = Backend::Local;
+        backend.validate(&data_dir, "process").unwrap();
+
+        let mut sender = backend.open_sender(&data_dir, "process").unwrap();
+        let mut receiver = backend.open_receiver(&data_dir, "process").unwrap();
+        sender.send(b"first").await.unwrap();
+        sender.send(b"second").await.unwrap();
+
+        // A message which is not committed is received again.
+        let received = recv(&mut receiver).await;
+        assert_eq!(b"first", &*received);
+        drop(received);
+        let received = recv(&mut receiver).await;
+        assert_eq!(b"first", &*received);
+        received.commit().await.unwrap();
+        let received = recv(&mut receiver).await;
+        assert_eq!(b"second", &*received);
+        received.commit().await.unwrap();
+
+        drop((sender, receiver));
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_encrypted() {
+        let data_dir = std::env::temp_dir().join("email-weather-test-local-queue-encrypted");
+        let _ = std::fs::remove_dir_all(&data_dir);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let backend = Backend::Local;
+        let cipher = cipher(1);
+
+        // Sent before encryption was enabled.
+        let mut plain_sender = backend.open_sender(&data_dir, "process").unwrap();
+        plain_sender.send(b"plain").await.unwrap();
+        drop(plain_sender);
+        let mut sender = backend
+            .open_sender(&data_dir, "process")
+            .unwrap()
+            .with_cipher(Some(cipher));
+        sender.send(b"secret").await.unwrap();
+
+        let mut receiver = backend
+            .open_receiver(&data_dir, "process")
+            .unwrap()
+            .with_cipher(Some(cipher));
+        let received = recv(&mut receiver).await;
+        assert_eq!(b"plain", &*received);
+        received.commit().await.unwrap();
+        let received = recv(&mut receiver).await;
+        assert_eq!(b"secret", &*received);
+        drop(received);
+        drop(receiver);
+
+        // Encrypted messages can't be received without the key.
+        let mut receiver = backend.open_receiver(&data_dir, "process").unwrap();
+        assert!(matches!(
+            receiver.recv().await.unwrap(),
+            Message::Undecryptable(_)
+        ));
+
+        drop((sender, receiver));
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_local_dead_letter() {
+        let data_dir = std::env::temp_dir().join("email-weather-test-local-queue-dead-letter");
+        let _ = std::fs::remove_dir_all(&data_dir);
+        std::fs::create_dir_all(&data_dir).unwrap();
+        let backend = Backend::Local;
+
+        let mut other_sender = backend
+            .open_sender(&data_dir, "process")
+            .unwrap()
+            .with_cipher(Some(cipher(2)));
+        other_sender.send(b"other key").await.unwrap();
+        drop(other_sender);
+        let mut sender = backend
+            .open_sender(&data_dir, "process")
+            .unwrap()
+            .with_cipher(Some(cipher(1)));
+        sender.send(b"secret").await.unwrap();
+
+        let mut receiver = backend
+            .open_receiver(&data_dir, "process")
+            .unwrap()
+            .with_cipher(Some(cipher(1)));
+        let undecryptable = match receiver.recv().await.unwrap() {
+            Message::Undecryptable(undecryptable) => undecryptable,
+            Message::Received(_) => panic!("Expected an undecryptable message"),
+        };
+        let path = undecryptable.dead_letter().await.unwrap().unwrap();
+        assert!(path.starts_with(dead_letter_dir(&data_dir, "process")));
+        let dead_letter = std::fs::read(&path).unwrap();
+        assert_eq!(
+            b"other key",
+            cipher(2).decrypt(&dead_letter).unwrap().unwrap().as_slice()
+        );
+
+        // The message after it is still received.
+        let received = recv(&mut receiver).await;
+        assert_eq!(b"secret", &*received);
+        received.commit().await.unwrap();
+
+        drop((sender, receiver));
+        std::fs::remove_dir_all(&data_dir).unwrap();
+    }
+}
@@ -0,0 +1,501 @@
+//! Queues stored in [Redis Streams](https://redis.io/docs/data-types/streams/), so that multiple
+//! replicas of the service can share the same mailbox (requires Redis 6.2 or later).
+//!
+//! Each queue is a stream, read by all the replicas as members of the same consumer group, so
+//! that each message is handled by a single replica. A message remains pending in the group until
+//! it is [committed](Entry::commit), if the replica handling it stops, it is claimed by another
+//! replica after [`Options::claim_idle_secs`]. Entries which have been delivered more than
+//! [`Options::max_deliveries`] times (e.g. because they cause the replica to crash) are moved to a
+//! dead letter stream.
+//!
+//! Only a single replica (the leader) receives emails from the mailbox at a time, see
+//! [`LeaderElection`].
+
+use std::time::Duration;
+
+use eyre::Context;
+use redis::{
+    aio::Connection,
+    streams::{StreamPendingCountReply, StreamReadReply},
+    AsyncCommands, FromRedisValue,
+};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+/// Name of the consumer group that all replicas read the streams as.
+const GROUP: &str = "email-weather";
+
+/// Name of the field of the stream entries containing the message.
+const DATA_FIELD: &str = "data";
+
+/// How long to wait for a new message before checking for messages to claim from other replicas.
+const BLOCK_MILLIS: u64 = 5_000;
+
+/// Atomically acquire the leader lock `KEYS[1]` for `ARGV[1]` (or renew it, if it is already held
+/// by `ARGV[1]`) for `ARGV[2]` milliseconds. Returns `1` if the lock is held.
+const ACQUIRE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("PEXPIRE", KEYS[1], ARGV[2])
+end
+if redis.call("SET", KEYS[1], ARGV[1], "NX", "PX", ARGV[2]) then
+    return 1
+end
+return 0
+"#;
+
+/// Release the leader lock `KEYS[1]`, if it is held by `ARGV[1]`.
+const RELEASE_SCRIPT: &str = r#"
+if redis.call("GET", KEYS[1]) == ARGV[1] then
+    return redis.call("DEL", KEYS[1])
+end
+return 0
+"#;
+
+/// Options for [`super::Backend::Redis`].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Options {
+    /// Url of the Redis server, e.g. `redis://:password@redis.example.com:6379/0`.
+    pub url: String,
+    /// Prefix applied to the keys used by the service, so that the same Redis server can be
+    /// used by other applications (or other deployments of this service).
+    ///
+    /// Default is `email-weather`.
+    #[serde(default = "default_key_prefix")]
+    pub key_prefix: String,
+    /// Name identifying this replica, must be unique among the replicas, and should remain the
+    /// same when the replica is restarted so that it continues with the messages it was handling.
+    ///
+    /// Default is the `HOSTNAME` environment variable.
+    #[serde(default)]
+    pub replica: Option<String>,
+    /// How long (in seconds) the leader lock is held without being renewed. Must be longer than
+    /// the `poll_interval_secs`, because the leader renews it each time it polls the mailbox.
+    ///
+    /// Default is `60`.
+    #[serde(default = "default_leader_ttl_secs")]
+    pub leader_ttl_secs: u64,
+    /// How long (in seconds) a message can be pending on a replica before it is claimed by
+    /// another replica, because the replica handling it is assumed to have stopped. Must be
+    /// longer than it takes to process a request or send a reply (including retries).
+    ///
+    /// Default is `900`.
+    #[serde(default = "default_claim_idle_secs")]
+    pub claim_idle_secs: u64,
+    /// Number of times an entry can be delivered (to any replica) before it is assumed that
+    /// handling it fails, and it is moved to the dead letter stream of the queue (e.g.
+    /// `email-weather:dead_letter:process`) instead of being delivered again.
+    ///
+    /// Default is `5`.
+    #[serde(default = "default_max_deliveries")]
+    pub max_deliveries: usize,
+}
+
+fn default_key_prefix() -> String {
+    "email-weather".to_owned()
+}
+
+fn default_leader_ttl_secs() -> u64 {
+    60
+}
+
+fn default_claim_idle_secs() -> u64 {
+    900
+}
+
+fn default_max_deliveries() -> usize {
+    5
+}
+
+impl Options {
+    /// Create a client for the Redis server at [`Options::url`]. The connection is not
+    /// established until it is used.
+    pub fn client(&self) -> eyre::Result<redis::Client> {
+        redis::Client::open(self.url.as_str()).wrap_err("Invalid Redis url")
+    }
+
+    /// Key of the stream containing the queue named `name`.
+    fn stream_key(&self, name: &str) -> String {
+        format!("{}:queue:{}", self.key_prefix, name)
+    }
+
+    /// Key of the stream containing the dead letters of the queue named `name`, see
+    /// [`Options::max_deliveries`].
+    fn dead_letter_key(&self, name: &str) -> String {
+        format!("{}:dead_letter:{}", self.key_prefix, name)
+    }
+
+    /// Name identifying this replica, see [`Options::replica`].
+    fn replica(&self) -> eyre::Result<String> {
+        match &self.replica {
+            Some(replica) => Ok(replica.clone()),
+            None => std::env::var("HOSTNAME").wrap_err(
+                "The HOSTNAME environment variable is required when the Redis replica is not \
+                specified",
+            ),
+        }
+    }
+}
+
+/// Connect to the Redis server if `connection` is not already connected. The connection is
+/// discarded when an error occurs, so that it is re-established the next time it is used.
+async fn connect<'a>(
+    client: &redis::Client,
+    connection: &'a mut Option<Connection>,
+) -> eyre::Result<&'a mut Connection> {
+    if connection.is_none() {
+        let new_connection = client
+            .get_async_connection()
+            .await
+            .wrap_err("Error connecting to Redis")?;
+        *connection = Some(new_connection);
+    }
+    Ok(connection
+        .as_mut()
+        .expect("connection was just established"))
+}
+
+/// Discard the `connection` if `result` is an error, see [`connect()`].
+fn check<T>(connection: &mut Option<Connection>, result: redis::RedisResult<T>) -> eyre::Result<T> {
+    if result.is_err() {
+        *connection = None;
+    }
+    Ok(result?)
+}
+
+/// Sends messages to a stream, see [`super::SenderKind::Redis`].
+pub struct StreamSender {
+    client: redis::Client,
+    connection: Option<Connection>,
+    key: String,
+}
+
+impl StreamSender {
+    /// Create a new [`StreamSender`] for the queue named `name`.
+    pub fn new(options: &Options, name: &str) -> eyre::Result<Self> {
+        Ok(Self {
+            client: options.client()?,
+            connection: None,
+            key: options.stream_key(name),
+        })
+    }
+
+    /// Append `data` to the stream.
+    pub async fn send(&mut self, data: &[u8]) -> eyre::Result<()> {
+        let connection = connect(&self.client, &mut self.connection).await?;
+        let result: redis::RedisResult<String> =
+            connection.xadd(&self.key, "*", &[(DATA_FIELD, data)]).await;
+        check(&mut self.connection, result)
+            .map(drop)
+            .wrap_err_with(|| format!("Error sending to Redis stream {}", self.key))
+    }
+}
+
+/// Receives messages from a stream, see [`super::ReceiverKind::Redis`].
+pub struct StreamReceiver {
+    client: redis::Client,
+    connection: Option<Connection>,
+    key: String,
+    dead_letter_key: String,
+    replica: String,
+    claim_idle: Duration,
+    max_deliveries: usize,
+}
+
+impl StreamReceiver {
+    /// Create a new [`StreamReceiver`] for the queue named `name`.
+    pub fn new(options: &Options, name: &str) -> eyre::Result<Self> {
+        Ok(Self {
+            client: options.client()?,
+            connection: None,
+            key: options.stream_key(name),
+            dead_letter_key: options.dead_letter_key(name),
+            replica: options.replica()?,
+            claim_idle: Duration::from_secs(options.claim_idle_secs),
+            max_deliveries: options.max_deliveries,
+        })
+    }
+
+    /// Connect to the Redis server (if not already connected), and create the consumer group if
+    /// it does not exist.
+    async fn connection(&mut self) -> eyre::Result<&mut Connection> {
+        if self.connection.is_none() {
+            let connection = connect(&self.client, &mut self.connection).await?;
+            let result: redis::RedisResult<()> = redis::cmd("XGROUP")
+                .arg("CREATE")
+                .arg(&self.key)
+                .arg(GROUP)
+                .arg("0")
+                .arg("MKSTREAM")
+                .query_async(connection)
+                .await;
+            match result {
+                Err(error) if error.code() != Some("BUSYGROUP") => {
+                    self.connection = None;
+                    return Err(error).wrap_err_with(|| {
+                        format!(
+                            "Error creating consumer group for Redis stream {}",
+                            self.key
+                        )
+                    });
+                }
+                _ => {}
+            }
+        }
+        Ok(self
+            .connection
+            .as_mut()
+            .expect("connection was established"))
+    }
+
+    /// Read the next entry for this replica, after `id` (`0` for the entries which are pending
+    /// on this replica, or `>` for new entries), waiting up to `block` for one to arrive.
+    async fn read(
+        &mut self,
+        id: &str,
+        block: Option<Duration>,
+    ) -> eyre::Result<Option<(String, Vec<u8>)>> {
+        let mut command = redis::cmd("XREADGROUP");
+        command
+            .arg("GROUP")
+            .arg(GROUP)
+            .arg(&self.replica)
+            .arg("COUNT")
+            .arg(1);
+        if let Some(block) = block {
+            command
+                .arg("BLOCK")
+                .arg(u64::try_from(block.as_millis()).unwrap_or(u64::MAX));
+        }
+        command.arg("STREAMS").arg(&self.key).arg(id);
+
+        let connection = self.connection().await?;
+        let result: redis::RedisResult<Option<StreamReadReply>> =
+            command.query_async(connection).await;
+        let reply = check(&mut self.connection, result)
+            .wrap_err_with(|| format!("Error reading from Redis stream {}", self.key))?;
+        let entry = reply
+            .and_then(|reply| reply.keys.into_iter().next())
+            .and_then(|key| key.ids.into_iter().next());
+        match entry {
+            Some(entry) => {
+                let data: Vec<u8> = entry
+                    .map
+                    .get(DATA_FIELD)
+                    .map(Vec::<u8>::from_redis_value)
+                    .transpose()?
+                    .ok_or_else(|| {
+                        eyre::eyre!(
+                            "Redis stream entry {} has no {} field",
+                            entry.id,
+                            DATA_FIELD
+                        )
+                    })?;
+                Ok(Some((entry.id, data)))
+            }
+            None => Ok(None),
+        }
+    }
+
+    /// Claim the entries which have been pending on other replicas for longer than
+    /// [`Options::claim_idle_secs`], so that they are read by this replica.
+    async fn claim_idle(&mut self) -> eyre::Result<()> {
+        let mut command = redis::cmd("XAUTOCLAIM");
+        command
+            .arg(&self.key)
+            .arg(GROUP)
+            .arg(&self.replica)
+            .arg(u64::try_from(self.claim_idle.as_millis()).unwrap_or(u64::MAX))
+            .arg("0-0")
+            .arg("COUNT")
+            .arg(1)
+            .arg("JUSTID");
+        let connection = self.connection().await?;
+        let result: redis::RedisResult<redis::Value> = command.query_async(connection).await;
+        check(&mut self.connection, result)
+            .map(drop)
+            .wrap_err_with(|| format!("Error claiming from Redis stream {}", self.key))
+    }
+
+    /// The number of times the pending entry `id` has been delivered.
+    async fn deliveries(&mut self, id: &str) -> eyre::Result<usize> {
+        let key = self.key.clone();
+        let connection = self.connection().await?;
+        let result: redis::RedisResult<StreamPendingCountReply> =
+            connection.xpending_count(&key, GROUP, id, id, 1).await;
+        let reply = check(&mut self.connection, result)
+            .wrap_err_with(|| format!("Error reading pending entry {id} of Redis stream {key}"))?;
+        Ok(reply
+            .ids
+            .first()
+            .map_or(0, |pending| pending.times_delivered))
+    }
+
+    /// Move the entry `id` containing `data` to the dead letter stream, and remove it from the
+    /// stream.
+    async fn dead_letter(&mut self, id: &str, data: &[u8]) -> eyre::Result<()> {
+        let mut pipeline = redis::pipe();
+        pipeline
+            .atomic()
+            .xadd(&self.dead_letter_key, "*", &[(DATA_FIELD, data)])
+            .ignore()
+            .xack(&self.key, GROUP, &[id])
+            .ignore()
+            .xdel(&self.key, &[id])
+            .ignore();
+        let connection = self.connection().await?;
+        let result: redis::RedisResult<()> = pipeline.query_async(connection).await;
+        check(&mut self.connection, result).wrap_err_with(|| {
+            format!(
+                "Error moving entry {} of Redis stream {} to {}",
+                id, self.key, self.dead_letter_key
+            )
+        })
+    }
+
+    /// Wait for the next entry in the stream. Entries which are pending on this replica (because
+    /// they were not committed before the task was restarted), or claimed from other replicas,
+    /// are received first, unless they have been delivered more than
+    /// [`Options::max_deliveries`] times, in which case they are moved to the dead letter stream.
+    pub async fn recv(&mut self) -> eyre::Result<Entry<'_>> {
+        loop {
+            self.claim_idle().await?;
+            if let Some((id, data)) = self.read("0", None).await? {
+                let deliveries = self.deliveries(&id).await?;
+                if deliveries > self.max_deliveries {
+                    self.dead_letter(&id, &data).await?;
+                    tracing::error!(
+                        "Moved entry {} of Redis stream {} to {} after {} deliveries",
+                        id,
+                        self.key,
+                        self.dead_letter_key,
+                        deliveries
+                    );
+                    continue;
+                }
+                return Ok(Entry {
+                    receiver: self,
+                    id,
+                    data,
+                });
+            }
+            if let Some((id, data)) = self
+                .read(">", Some(Duration::from_millis(BLOCK_MILLIS)))
+                .await?
+            {
+                return Ok(Entry {
+                    receiver: self,
+                    id,
+                    data,
+                });
+            }
+        }
+    }
+}
+
+/// An entry received from a stream, see [`StreamReceiver::recv()`].
+pub struct Entry<'a> {
+    receiver: &'a mut StreamReceiver,
+    id: String,
+    data: Vec<u8>,
+}
+
+impl Entry<'_> {
+    /// The message contained in the entry.
+    #[must_use]
+    pub fn data(&self) -> &[u8] {
+        &self.data
+    }
+
+    /// Acknowledge the entry, and remove it from the stream.
+    pub async fn commit(self) -> eyre::Result<()> {
+        let Entry { receiver, id, .. } = self;
+        let mut pipeline = redis::pipe();
+        pipeline
+            .atomic()
+            .xack(&receiver.key, GROUP, &[&id])
+            .ignore()
+            .xdel(&receiver.key, &[&id])
+            .ignore();
+        let connection = receiver.connection().await?;
+        let result: redis::RedisResult<()> = pipeline.query_async(connection).await;
+        check(&mut receiver.connection, result).wrap_err_with(|| {
+            format!(
+                "Error committing entry {} of Redis stream {}",
+                id, receiver.key
+            )
+        })
+    }
+}
+
+/// Election of the replica which receives emails from the mailbox, so that each email is only
+/// received once. The leader holds a lock in Redis which expires after
+/// [`Options::leader_ttl_secs`] unless it is renewed, so that another replica takes over if the
+/// leader stops.
+pub struct LeaderElection {
+    client: redis::Client,
+    connection: tokio::sync::Mutex<Option<Connection>>,
+    key: String,
+    replica: String,
+    ttl: Duration,
+}
+
+impl LeaderElection {
+    /// Create a new [`LeaderElection`], the lock is not acquired until
+    /// [`LeaderElection::is_leader()`] is called.
+    pub fn new(options: &Options) -> eyre::Result<Self> {
+        Ok(Self {
+            client: options.client()?,
+            connection: tokio::sync::Mutex::new(None),
+            key: format!("{}:leader", options.key_prefix),
+            replica: options.replica()?,
+            ttl: Duration::from_secs(options.leader_ttl_secs),
+        })
+    }
+
+    async fn invoke(&self, script: &str, ttl: Option<Duration>) -> eyre::Result<bool> {
+        let mut connection = self.connection.lock().await;
+        let redis_connection = connect(&self.client, &mut connection).await?;
+        let script = redis::Script::new(script);
+        let mut invocation = script.key(&self.key);
+        invocation.arg(&self.replica);
+        if let Some(ttl) = ttl {
+            invocation.arg(u64::try_from(ttl.as_millis()).unwrap_or(u64::MAX));
+        }
+        let result: redis::RedisResult<i64> = invocation.invoke_async(redis_connection).await;
+        Ok(check(&mut connection, result).wrap_err("Error invoking Redis leader script")? == 1)
+    }
+
+    /// Acquire the lock (or renew it if this replica is already the leader), returns `true` if
+    /// this replica is the leader.
+    pub async fn is_leader(&self) -> eyre::Result<bool> {
+        self.invoke(ACQUIRE_SCRIPT, Some(self.ttl)).await
+    }
+
+    /// Release the lock if this replica is the leader, so that another replica can take over
+    /// without waiting for it to expire.
+    pub async fn resign(&self) -> eyre::Result<()> {
+        self.invoke(RELEASE_SCRIPT, None).await.map(drop)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Options;
+
+    #[test]
+    fn test_options() {
+        let options: Options =
+            ron::from_str(r#"(url: "redis://localhost:6379", replica: Some("replica-1"))"#)
+                .unwrap();
+        assert_eq!("email-weather", options.key_prefix);
+        assert_eq!(60, options.leader_ttl_secs);
+        assert_eq!(5, options.max_deliveries);
+        assert_eq!("email-weather:queue:process", options.stream_key("process"));
+        assert_eq!(
+            "email-weather:dead_letter:process",
+            options.dead_letter_key("process")
+        );
+        assert_eq!("replica-1", options.replica().unwrap());
+        options.client().unwrap();
+    }
+}
@@ -0,0 +1,243 @@
+//! Service-wide and per-domain daily quotas, which limit the number of requests processed each
+//! (UTC) day to protect the service and its upstream providers from abuse, see [`Quotas`].
+
+use std::{collections::HashMap, sync::Mutex};
+
+use chrono::{DateTime, NaiveDate, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::watch;
+
+use crate::options::DynamicOptions;
+
+/// Options for [`Quotas`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct QuotaOptions {
+    /// Maximum number of requests processed by the service each day (UTC).
+    ///
+    /// Default is `None` (unlimited).
+    #[serde(default)]
+    pub global_daily: Option<u32>,
+    /// Maximum number of requests processed each day (UTC) from plain email senders with the same
+    /// email domain (e.g. `example.com`).
+    ///
+    /// Default is `None` (unlimited).
+    #[serde(default)]
+    pub per_domain_daily: Option<u32>,
+    /// Percentage of [`QuotaOptions::global_daily`] which is reserved for satellite devices
+    /// (inReach). Once less than this remains, requests from plain email senders are refused so
+    /// that satellite device users, who may depend on the forecast, are served first.
+    ///
+    /// Default is `10`.
+    #[serde(default = "default_satellite_reserve_percent")]
+    pub satellite_reserve_percent: u8,
+}
+
+fn default_satellite_reserve_percent() -> u8 {
+    10
+}
+
+impl Default for QuotaOptions {
+    fn default() -> Self {
+        Self {
+            global_daily: None,
+            per_domain_daily: None,
+            satellite_reserve_percent: default_satellite_reserve_percent(),
+        }
+    }
+}
+
+/// Error returned by [`Quotas::check()`] when a request exceeds a quota.
+#[derive(Debug, Clone, PartialEq, Eq, thiserror::Error)]
+pub enum QuotaExceeded {
+    /// [`QuotaOptions::global_daily`] has been reached.
+    #[error("The service's daily quota has been reached")]
+    Global,
+    /// The remainder of [`QuotaOptions::global_daily`] is reserved for satellite devices.
+    #[error("The service's daily quota has nearly been reached")]
+    Reserved,
+    /// [`QuotaOptions::per_domain_daily`] has been reached for the domain.
+    #[error("The daily quota for {0} has been reached")]
+    Domain(String),
+}
+
+/// Requests counted during a day.
+#[derive(Debug, Default)]
+struct Usage {
+    day: Option<NaiveDate>,
+    total: u32,
+    by_domain: HashMap<String, u32>,
+}
+
+impl Usage {
+    /// Reset the counts if `now` is on a different day to the counts.
+    fn reset_if_stale(&mut self, now: DateTime<Utc>) {
+        let today = now.naive_utc().date();
+        if self.day != Some(today) {
+            *self = Self {
+                day: Some(today),
+                ..Self::default()
+            };
+        }
+    }
+}
+
+/// Counts the requests processed each day against the current [`DynamicOptions::quota`], so that
+/// changes to the limits apply immediately. Counts are stored in memory, so they are reset when
+/// the service is restarted.
+#[derive(Debug)]
+pub struct Quotas {
+    options_rx: watch::Receiver<DynamicOptions>,
+    usage: Mutex<Usage>,
+}
+
+impl Quotas {
+    /// Create a new [`Quotas`] with no requests counted.
+    #[must_use]
+    pub fn new(options_rx: watch::Receiver<DynamicOptions>) -> Self {
+        Self {
+            options_rx,
+            usage: Mutex::new(Usage::default()),
+        }
+    }
+
+    fn options(&self) -> QuotaOptions {
+        self.options_rx.borrow().quota
+    }
+
+    fn usage(&self, now: DateTime<Utc>) -> std::sync::MutexGuard<'_, Usage> {
+        let mut usage = self.usage.lock().expect("quota mutex is poisoned");
+        usage.reset_if_stale(now);
+        usage
+    }
+
+    /// Number of requests from the global quota reserved for satellite devices.
+    fn reserved(options: &QuotaOptions, global_daily: u32) -> u32 {
+        let reserved =
+            u64::from(global_daily) * u64::from(options.satellite_reserve_percent.min(100)) / 100;
+        u32::try_from(reserved).unwrap_or(global_daily)
+    }
+
+    /// Check whether a request received at `now` can be processed, and if so count it against
+    /// the quotas. `domain` is the email domain of a plain email sender, or `None` for a
+    /// satellite device.
+    pub fn check(&self, domain: Option<&str>, now: DateTime<Utc>) -> Result<(), QuotaExceeded> {
+        let options = self.options();
+        let mut usage = self.usage(now);
+
+        if let Some(global_daily) = options.global_daily {
+            if usage.total >= global_daily {
+                return Err(QuotaExceeded::Global);
+            }
+            if domain.is_some()
+                && usage.total >= global_daily - Self::reserved(&options, global_daily)
+            {
+                return Err(QuotaExceeded::Reserved);
+            }
+        }
+
+        let domain = domain.map(str::to_lowercase);
+        if let (Some(domain), Some(per_domain_daily)) = (&domain, options.per_domain_daily) {
+            if usage.by_domain.get(domain).copied().unwrap_or_default() >= per_domain_daily {
+                return Err(QuotaExceeded::Domain(domain.clone()));
+            }
+        }
+
+        usage.total += 1;
+        if let Some(domain) = domain {
+            *usage.by_domain.entry(domain).or_default() += 1;
+        }
+        Ok(())
+    }
+
+    /// Number of requests which can still be processed today by the service, `None` if it is
+    /// unlimited.
+    pub fn remaining(&self, now: DateTime<Utc>) -> Option<u32> {
+        let usage = self.usage(now);
+        self.options()
+            .global_daily
+            .map(|global_daily| global_daily.saturating_sub(usage.total))
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+    use tokio::sync::watch;
+
+    use crate::{options::DynamicOptions, time::test_now as now};
+
+    use super::{QuotaExceeded, QuotaOptions, Quotas};
+
+    fn quotas(quota: QuotaOptions) -> Quotas {
+        let (_options_tx, options_rx) = watch::channel(DynamicOptions {
+            quota,
+            ..DynamicOptions::default()
+        });
+        Quotas::new(options_rx)
+    }
+
+    #[test]
+    fn test_unlimited() {
+        let quotas = quotas(QuotaOptions::default());
+        for _ in 0..1000 {
+            quotas.check(Some("example.com"), now()).unwrap();
+        }
+        assert_eq!(None, quotas.remaining(now()));
+    }
+
+    #[test]
+    fn test_global_reserve() {
+        let quotas = quotas(QuotaOptions {
+            global_daily: Some(10),
+            satellite_reserve_percent: 20,
+            ..QuotaOptions::default()
+        });
+        for _ in 0..8 {
+            quotas.check(Some("example.com"), now()).unwrap();
+        }
+        assert_eq!(
+            Err(QuotaExceeded::Reserved),
+            quotas.check(Some("example.com"), now())
+        );
+        quotas.check(None, now()).unwrap();
+        quotas.check(None, now()).unwrap();
+        assert_eq!(Some(0), quotas.remaining(now()));
+        assert_eq!(Err(QuotaExceeded::Global), quotas.check(None, now()));
+
+        // The quotas are reset the next day.
+        let tomorrow = now() + Duration::days(1);
+        assert_eq!(Some(10), quotas.remaining(tomorrow));
+        quotas.check(Some("example.com"), tomorrow).unwrap();
+    }
+
+    #[test]
+    fn test_per_domain() {
+        let quotas = quotas(QuotaOptions {
+            per_domain_daily: Some(2),
+            ..QuotaOptions::default()
+        });
+        quotas.check(Some("example.com"), now()).unwrap();
+        quotas.check(Some("Example.COM"), now()).unwrap();
+        assert_eq!(
+            Err(QuotaExceeded::Domain("example.com".to_owned())),
+            quotas.check(Some("example.com"), now())
+        );
+        quotas.check(Some("example.org"), now()).unwrap();
+        quotas.check(None, now()).unwrap();
+    }
+
+    /// Test that changes to the options apply to the requests already counted.
+    #[test]
+    fn test_options_changed() {
+        let (options_tx, options_rx) = watch::channel(DynamicOptions::default());
+        let quotas = Quotas::new(options_rx);
+        quotas.check(None, now()).unwrap();
+        quotas.check(None, now()).unwrap();
+        assert_eq!(None, quotas.remaining(now()));
+
+        options_tx.send_modify(|options| options.quota.global_daily = Some(2));
+        assert_eq!(Some(0), quotas.remaining(now()));
+        assert_eq!(Err(QuotaExceeded::Global), quotas.check(None, now()));
+    }
+}
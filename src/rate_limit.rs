@@ -0,0 +1,209 @@
+//! Rate limiting using the token bucket algorithm, see [`TokenBucket`] and [`KeyedRateLimiter`].
+
+use std::{
+    collections::HashMap,
+    hash::Hash,
+    net::IpAddr,
+    sync::{Arc, Mutex},
+    time::{Duration, Instant},
+};
+
+use axum::{
+    extract::State,
+    http::Request,
+    middleware::Next,
+    response::{IntoResponse, Response},
+};
+use reqwest::StatusCode;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use crate::client_ip::ClientIp;
+
+/// Options for [`KeyedRateLimiter`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct RateLimitOptions {
+    /// Sustained number of requests permitted per minute.
+    ///
+    /// Default is `60`.
+    #[serde(default = "default_requests_per_minute")]
+    pub requests_per_minute: u32,
+    /// Number of requests that can be made in a burst, before being limited to
+    /// `requests_per_minute`.
+    ///
+    /// Default is `20`.
+    #[serde(default = "default_burst")]
+    pub burst: u32,
+}
+
+fn default_requests_per_minute() -> u32 {
+    60
+}
+
+fn default_burst() -> u32 {
+    20
+}
+
+impl Default for RateLimitOptions {
+    fn default() -> Self {
+        Self {
+            requests_per_minute: default_requests_per_minute(),
+            burst: default_burst(),
+        }
+    }
+}
+
+/// A bucket that is filled with tokens at a constant rate up to a maximum capacity. Each permitted
+/// action consumes a token, and actions are not permitted while the bucket is empty.
+#[derive(Debug, Clone)]
+pub struct TokenBucket {
+    capacity: f64,
+    tokens: f64,
+    /// Tokens added per second.
+    rate: f64,
+    last_refill: Instant,
+}
+
+impl TokenBucket {
+    /// Create a new full [`TokenBucket`] with the specified `capacity`, refilled at `rate` tokens
+    /// per `per` duration.
+    #[must_use]
+    pub fn new(capacity: u32, rate: u32, per: Duration, now: Instant) -> Self {
+        Self {
+            capacity: f64::from(capacity),
+            tokens: f64::from(capacity),
+            rate: f64::from(rate) / per.as_secs_f64(),
+            last_refill: now,
+        }
+    }
+
+    fn refill(&mut self, now: Instant) {
+        let elapsed = now
+            .saturating_duration_since(self.last_refill)
+            .as_secs_f64();
+        self.tokens = f64::min(self.capacity, self.tokens + elapsed * self.rate);
+        self.last_refill = now;
+    }
+
+    /// Attempt to take a token from the bucket, returns `true` if the action is permitted.
+    pub fn try_acquire(&mut self, now: Instant) -> bool {
+        self.refill(now);
+        if self.tokens >= 1.0 {
+            self.tokens -= 1.0;
+            true
+        } else {
+            false
+        }
+    }
+
+    /// The duration until a token will be available (zero if one is available now).
+    #[must_use]
+    pub fn time_until_available(&self, now: Instant) -> Duration {
+        let mut bucket = self.clone();
+        bucket.refill(now);
+        if bucket.tokens >= 1.0 || bucket.rate <= 0.0 {
+            Duration::ZERO
+        } else {
+            Duration::from_secs_f64((1.0 - bucket.tokens) / bucket.rate)
+        }
+    }
+
+    fn is_full(&self, now: Instant) -> bool {
+        let mut bucket = self.clone();
+        bucket.refill(now);
+        bucket.tokens >= bucket.capacity
+    }
+}
+
+/// Number of keys tracked by [`KeyedRateLimiter`] before buckets which have been idle long enough
+/// to be full again are removed.
+const MAX_KEYS_BEFORE_CLEANUP: usize = 10_000;
+
+/// Rate limiter with a separate [`TokenBucket`] for each key (e.g. the client IP address).
+#[derive(Debug)]
+pub struct KeyedRateLimiter<K> {
+    options: RateLimitOptions,
+    buckets: Mutex<HashMap<K, TokenBucket>>,
+}
+
+impl<K: Eq + Hash> KeyedRateLimiter<K> {
+    /// Create a new [`KeyedRateLimiter`].
+    #[must_use]
+    pub fn new(options: RateLimitOptions) -> Self {
+        Self {
+            options,
+            buckets: Mutex::new(HashMap::new()),
+        }
+    }
+
+    /// Attempt to perform an action for `key`, returns `true` if the action is permitted.
+    pub fn try_acquire(&self, key: K, now: Instant) -> bool {
+        let mut buckets = self.buckets.lock().expect("rate limiter mutex is poisoned");
+        if buckets.len() >= MAX_KEYS_BEFORE_CLEANUP {
+            buckets.retain(|_, bucket| !bucket.is_full(now));
+        }
+        let options = &self.options;
+        buckets
+            .entry(key)
+            .or_insert_with(|| {
+                TokenBucket::new(
+                    options.burst,
+                    options.requests_per_minute,
+                    Duration::from_secs(60),
+                    now,
+                )
+            })
+            .try_acquire(now)
+    }
+}
+
+/// Middleware which limits the rate of requests from each client IP address (see [`ClientIp`]),
+/// responding with `429 Too Many Requests` when the limit is exceeded. Requires the router to be
+/// served using `into_make_service_with_connect_info::<SocketAddr>()`.
+pub async fn rate_limit_by_ip<B>(
+    State(limiter): State<Arc<KeyedRateLimiter<IpAddr>>>,
+    ClientIp(ip): ClientIp,
+    request: Request<B>,
+    next: Next<B>,
+) -> Response {
+    if limiter.try_acquire(ip, Instant::now()) {
+        next.run(request).await
+    } else {
+        tracing::warn!("Rate limit exceeded for {}", ip);
+        StatusCode::TOO_MANY_REQUESTS.into_response()
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, Instant};
+
+    use super::{KeyedRateLimiter, RateLimitOptions, TokenBucket};
+
+    #[test]
+    fn test_token_bucket() {
+        let now = Instant::now();
+        let mut bucket = TokenBucket::new(2, 1, Duration::from_secs(1), now);
+        assert!(bucket.try_acquire(now));
+        assert!(bucket.try_acquire(now));
+        assert!(!bucket.try_acquire(now));
+        assert_eq!(Duration::from_secs(1), bucket.time_until_available(now));
+
+        let later = now + Duration::from_secs(1);
+        assert!(bucket.try_acquire(later));
+        assert!(!bucket.try_acquire(later));
+    }
+
+    #[test]
+    fn test_keyed_rate_limiter() {
+        let now = Instant::now();
+        let limiter = KeyedRateLimiter::new(RateLimitOptions {
+            requests_per_minute: 60,
+            burst: 1,
+        });
+        assert!(limiter.try_acquire("a", now));
+        assert!(!limiter.try_acquire("a", now));
+        assert!(limiter.try_acquire("b", now));
+        assert!(limiter.try_acquire("a", now + Duration::from_secs(1)));
+    }
+}
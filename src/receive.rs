@@ -3,6 +3,7 @@
 use std::{borrow::Cow, sync::Arc};
 
 use async_imap::types::Fetch;
+use chrono::{DateTime, Utc};
 use eyre::Context;
 use futures::{StreamExt, TryStreamExt};
 use mail_parser::MessagePart;
@@ -10,13 +11,25 @@ use oauth2::AccessToken;
 use serde::{Deserialize, Serialize};
 use tokio::{
     io::{AsyncRead, AsyncWrite},
-    sync::{broadcast, Mutex},
+    sync::{broadcast, watch, Mutex},
 };
 use tracing::Instrument;
 
 use crate::{
-    email, gis::Position, inreach, oauth2::AuthenticationFlow, plain,
-    request::ParsedForecastRequest, task::run_retry_log_errors, time,
+    back_pressure::Overload,
+    correlation::{Queued, RequestId},
+    email,
+    gis::Position,
+    inreach,
+    language::Language,
+    latency::Stage,
+    oauth2::AuthenticationFlow,
+    options::DynamicOptions,
+    plain,
+    queue::{self, redis::LeaderElection},
+    redact::{redact_at_rest, Redact},
+    request::ParsedForecastRequest,
+    task::{run_retry_log_errors, TaskContext},
 };
 
 /// An email received via IMAP.
@@ -25,6 +38,25 @@ pub trait Received {
     fn position(&self) -> Option<Position>;
     /// The subset of the received message containing the request specification.
     fn forecast_request(&self) -> &ParsedForecastRequest;
+    /// Requests which follow the [`Received::forecast_request()`] in the same message, each of
+    /// which has its own section in the reply.
+    fn additional_requests(&self) -> &[ParsedForecastRequest] {
+        &[]
+    }
+    /// The time that the message was sent by the device (if available), used to align the
+    /// forecast window with when the request was made.
+    fn sent_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        None
+    }
+    /// The language that the message was written in (if detected), which the reply should use.
+    fn language(&self) -> Option<Language> {
+        None
+    }
+    /// Identity of the sender (an email address or an inReach device id, which may already be
+    /// hashed, see [`crate::forget::sender_hash()`]).
+    fn sender_identity(&self) -> Option<&str> {
+        None
+    }
 }
 
 /// Sum type of all possible [`Email`]s that can be received and parsed via IMAP.
@@ -105,7 +137,6 @@ impl ParseReceivedEmail for ReceivedKind {
             "no.reply.inreach@garmin.com" => {
                 Self::Inreach(inreach::email::Received::parse_email(message)?)
             }
-            // TODO: use a whitelist from options as per #14
             _ => Self::Plain(plain::email::Received::parse_email(message)?),
         };
 
@@ -127,6 +158,34 @@ impl Received for ReceivedKind {
             ReceivedKind::Plain(email) => email.forecast_request(),
         }
     }
+
+    fn additional_requests(&self) -> &[ParsedForecastRequest] {
+        match self {
+            ReceivedKind::Inreach(email) => email.additional_requests(),
+            ReceivedKind::Plain(email) => email.additional_requests(),
+        }
+    }
+
+    fn sent_at(&self) -> Option<chrono::DateTime<chrono::Utc>> {
+        match self {
+            ReceivedKind::Inreach(email) => email.sent_at(),
+            ReceivedKind::Plain(email) => email.sent_at(),
+        }
+    }
+
+    fn language(&self) -> Option<Language> {
+        match self {
+            ReceivedKind::Inreach(email) => email.language(),
+            ReceivedKind::Plain(email) => email.language(),
+        }
+    }
+
+    fn sender_identity(&self) -> Option<&str> {
+        match self {
+            ReceivedKind::Inreach(email) => email.sender_identity(),
+            ReceivedKind::Plain(email) => email.sender_identity(),
+        }
+    }
 }
 
 struct GmailOAuth2 {
@@ -207,9 +266,110 @@ fn map_imap_connection_error(
     }
 }
 
+/// Check that the sender of `email` is permitted by [`DynamicOptions::whitelist`].
+fn check_whitelist(
+    email: &ReceivedKind,
+    options: &DynamicOptions,
+) -> Result<(), ParseReceivedEmailError> {
+    match email {
+        ReceivedKind::Plain(email) if !options.is_whitelisted(email.from.email_str()) => {
+            Err(ParseReceivedEmailError::Rejected {
+                reason: format!("Sender {} is not on the whitelist", email.from).into(),
+            })
+        }
+        _ => Ok(()),
+    }
+}
+
+/// Check that the `Reply-To` address of `email` is on [`DynamicOptions::whitelist`], otherwise it
+/// is dropped and the reply is sent to the sender, so that the service cannot be used to send
+/// forecasts to arbitrary addresses. When no whitelist is configured, `Reply-To` is never honored.
+fn check_reply_to(email: ReceivedKind, options: &DynamicOptions) -> ReceivedKind {
+    match email {
+        ReceivedKind::Plain(mut email) => {
+            if let Some(reply_to) = &email.reply_to {
+                if options.whitelist.is_none() || !options.is_whitelisted(reply_to.email_str()) {
+                    tracing::warn!(
+                        "Reply-To address {} is not on the whitelist, replying to the sender instead",
+                        reply_to
+                    );
+                    email.reply_to = None;
+                }
+            }
+            ReceivedKind::Plain(email)
+        }
+        email => email,
+    }
+}
+
+/// Parse a received RFC822 message, and check that it is accepted by `options` (e.g. the sender is
+/// on the whitelist). The accepted email is redacted for storage in the processing queue.
+pub fn accept_message(
+    rfc822_body: &[u8],
+    options: &DynamicOptions,
+) -> Result<ReceivedKind, ParseReceivedEmailError> {
+    let message: mail_parser::Message = mail_parser::Message::parse(rfc822_body)
+        .ok_or_else(|| eyre::eyre!("Unable to parse message body"))?;
+    let email = ReceivedKind::parse_email(message)?;
+    check_whitelist(&email, options)?;
+    Ok(redact_at_rest(check_reply_to(email, options)))
+}
+
+/// Archive a received RFC822 message (if [`TaskContext::archive`] is enabled), then parse it, and
+/// if it is accepted, submit it to the processing queue via `emails_sender`. Messages which are
+/// rejected (e.g. not on the whitelist) are logged and ignored. The time since the message was
+/// `fetched_at` is recorded in [`Stage::Receive`].
+pub(crate) async fn receive_message(
+    rfc822_body: &[u8],
+    emails_sender: &Mutex<queue::Sender>,
+    options: &DynamicOptions,
+    context: &TaskContext,
+    fetched_at: DateTime<Utc>,
+) -> eyre::Result<()> {
+    if let Some(archive) = context.archive {
+        match archive.archive(rfc822_body, fetched_at).await {
+            Ok(path) => tracing::debug!("Archived message to {:?}", path),
+            Err(error) => tracing::error!("Error archiving message: {:?}", error),
+        }
+    }
+
+    match accept_message(rfc822_body, options) {
+        Ok(email) => {
+            let request_id = RequestId::new();
+            tracing::Span::current().record("request_id", &tracing::field::display(request_id));
+            let queued_at = context.time.utc_now();
+            let email_data =
+                serde_json::to_vec(&Queued::new(request_id, &email).with_queued_at(queued_at))
+                    .wrap_err("Error serializing email data to json bytes")?;
+
+            let mut sender = emails_sender.lock().await;
+            sender
+                .send(&email_data)
+                .await
+                .wrap_err("Error submitting email data to send queue")?;
+            context.status.record_request_queued();
+            context
+                .latency
+                .record(Stage::Receive, queued_at - fetched_at);
+
+            tracing::debug!("email added to queue: {:?}", email.redacted());
+        }
+        Err(error) => match error {
+            ParseReceivedEmailError::Rejected { .. } => {
+                tracing::warn!("{}", error);
+            }
+            ParseReceivedEmailError::Unexpected(error) => return Err(error),
+        },
+    }
+
+    Ok(())
+}
+
 async fn receive_emails_poll_inbox<T>(
-    emails_sender: Arc<Mutex<yaque::Sender>>,
+    emails_sender: Arc<Mutex<queue::Sender>>,
     imap_session: &mut async_imap::Session<T>,
+    options: &DynamicOptions,
+    context: &TaskContext,
 ) -> Result<(), PollEmailsError>
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug,
@@ -233,6 +393,7 @@ where
         tracing::debug!("Obtained UNSEEN messages: {:?}", sequence_set);
         // TODO: fetch and check RFC822.SIZE before fetching the entire body.
         let fetch_sequences: String = sequence_set.join(",");
+        let fetched_at = context.time.utc_now();
         {
             let fetch_stream = imap_session
                 .fetch(fetch_sequences, "RFC822")
@@ -261,41 +422,21 @@ where
                         let rfc822_body = if let Some(body) = fetch.body() {
                             body
                         } else {
-                            tracing::debug!("Ignoring fetched message with no body: {:?}", fetch);
+                            // The fetch may contain the message's headers, so it is not logged.
+                            tracing::debug!("Ignoring fetched message {} with no body", sequence);
                             return Ok(());
                         };
 
-                        let message: mail_parser::Message =
-                            mail_parser::Message::parse(rfc822_body).ok_or_else(|| {
-                                eyre::eyre!("Unable to parse fetched message body: {:?}", fetch)
-                            })?;
-
-                        match ReceivedKind::parse_email(message) {
-                            Ok(email) => {
-                                let email_data = serde_json::to_vec(&email)
-                                    .wrap_err("Error serializing email data to json bytes")?;
-
-                                let mut sender = emails_sender.lock().await;
-                                sender
-                                    .send(email_data)
-                                    .await
-                                    .wrap_err("Error submitting email data to send queue")?;
-
-                                tracing::debug!("email added to queue: {:?}", email);
-                            }
-                            Err(error) => match error {
-                                ParseReceivedEmailError::Rejected { .. } => {
-                                    tracing::warn!("{}", error);
-                                }
-                                ParseReceivedEmailError::Unexpected(error) => {
-                                    return Err(error.into())
-                                }
-                            },
-                        }
-
+                        receive_message(rfc822_body, &emails_sender, options, context, fetched_at)
+                            .await
+                            .wrap_err_with(|| format!("Error receiving message {}", sequence))?;
                         Ok(())
                     }
-                    .instrument(tracing::info_span!("process_message", seq = sequence))
+                    .instrument(tracing::info_span!(
+                        "process_message",
+                        seq = sequence,
+                        request_id = tracing::field::Empty
+                    ))
                 })
                 .for_each(|result| async move {
                     match result {
@@ -312,25 +453,80 @@ where
     Ok(())
 }
 
+/// Poll the inbox every [`DynamicOptions::poll_interval`], or as soon as it is requested with
+/// [`crate::control::Controls::poll_now()`]. When there are multiple replicas of the service, the
+/// inbox is only polled while this replica is elected as the `leader`.
 async fn receive_emails_poll_inbox_loop<T>(
-    process_sender: Arc<Mutex<yaque::Sender>>,
+    process_sender: Arc<Mutex<queue::Sender>>,
     imap_session: &mut async_imap::Session<T>,
-    time: &dyn time::Port,
+    options_rx: &watch::Receiver<DynamicOptions>,
+    context: &TaskContext,
+    leader: Option<&LeaderElection>,
 ) -> Result<(), PollEmailsError>
 where
     T: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug,
 {
+    let TaskContext {
+        status,
+        controls,
+        time,
+        ..
+    } = *context;
     loop {
-        receive_emails_poll_inbox(process_sender.clone(), imap_session).await?;
-        time.async_sleep(std::time::Duration::from_secs(10)).await;
+        let options = options_rx.borrow().clone();
+        let pending_requests = status.pending_requests();
+        let is_leader = match leader {
+            Some(leader) => leader
+                .is_leader()
+                .await
+                .map_err(PollEmailsError::Unexpected)?,
+            None => true,
+        };
+        if !is_leader {
+            tracing::debug!("Another replica is receiving emails");
+        } else if options
+            .back_pressure
+            .is_overloaded(pending_requests, Overload::Defer)
+        {
+            tracing::warn!(
+                "Process queue is saturated ({} requests pending), deferring receiving emails",
+                pending_requests
+            );
+        } else {
+            receive_emails_poll_inbox(process_sender.clone(), imap_session, &options, context)
+                .await?;
+        }
+        status.record_inbox_poll(time.utc_now());
+        tokio::select! {
+            _ = time.async_sleep(options.poll_interval) => {}
+            _ = controls.poll_requested() => {}
+        }
     }
 }
 
+/// Poll the inbox of an established `imap_session` every [`DynamicOptions::poll_interval`],
+/// submitting received emails to `process_sender`, until an error occurs.
+pub(crate) async fn receive_emails_session<T>(
+    process_sender: Arc<Mutex<queue::Sender>>,
+    imap_session: &mut async_imap::Session<T>,
+    options_rx: &watch::Receiver<DynamicOptions>,
+    context: &TaskContext,
+) -> eyre::Result<()>
+where
+    T: AsyncRead + AsyncWrite + Unpin + Send + std::fmt::Debug,
+{
+    receive_emails_poll_inbox_loop(process_sender, imap_session, options_rx, context, None)
+        .await
+        .map_err(PollEmailsError::into_eyre)
+}
+
 async fn receive_emails_impl<AUTH>(
-    process_sender: Arc<Mutex<yaque::Sender>>,
+    process_sender: Arc<Mutex<queue::Sender>>,
     oauth_flow: &AUTH,
     imap_username: &str,
-    time: &dyn time::Port,
+    options_rx: &watch::Receiver<DynamicOptions>,
+    context: &TaskContext,
+    leader: Option<&LeaderElection>,
 ) -> eyre::Result<()>
 where
     AUTH: AuthenticationFlow,
@@ -361,7 +557,14 @@ where
         // let mut imap_session = imap_client.login(imap_username, imap_password).await.map_err(|error| error.0)?;
         tracing::info!("Successful IMAP session login");
 
-        match receive_emails_poll_inbox_loop(process_sender.clone(), &mut imap_session, time).await
+        match receive_emails_poll_inbox_loop(
+            process_sender.clone(),
+            &mut imap_session,
+            options_rx,
+            context,
+            leader,
+        )
+        .await
         {
             Ok(_) => {}
             Err(error) => match error {
@@ -388,34 +591,101 @@ where
     Ok(())
 }
 
-/// This function spawns a task to receive emails via IMAP, and submit them for processing.
+/// This function spawns a task to receive emails via IMAP, and submit them for processing. When
+/// there are multiple replicas of the service, emails are only received while this replica is
+/// elected as the `leader`, and it resigns when the task is shutdown.
 #[tracing::instrument(skip_all)]
 pub async fn receive_emails<AUTH>(
     shutdown_rx: broadcast::Receiver<()>,
-    process_sender: yaque::Sender,
+    process_sender: queue::Sender,
     oauth_flow: Arc<AUTH>,
     imap_username: &str,
-    time: &dyn time::Port,
+    options_rx: watch::Receiver<DynamicOptions>,
+    leader: Option<LeaderElection>,
+    context: TaskContext,
 ) where
     AUTH: AuthenticationFlow,
 {
+    let TaskContext { backoff, time, .. } = context;
     let process_sender = Arc::new(Mutex::new(process_sender));
+    let leader = leader.map(Arc::new);
+    let retry_leader = leader.clone();
     run_retry_log_errors(
         move || {
             let process_sender = process_sender.clone();
             let oauth_flow = oauth_flow.clone();
+            let options_rx = options_rx.clone();
+            let leader = retry_leader.clone();
             async move {
                 receive_emails_impl(
                     process_sender,
                     &*oauth_flow,
                     imap_username,
-                    time,
+                    &options_rx,
+                    &context,
+                    leader.as_deref(),
                 )
                 .await
             }
         },
         shutdown_rx,
+        backoff,
         time,
     )
     .await;
+
+    if let Some(leader) = leader {
+        if let Err(error) = leader.resign().await {
+            tracing::error!("Error resigning as the leader: {:?}", error);
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use crate::{
+        options::DynamicOptions,
+        receive::{check_reply_to, ParseReceivedEmail, ReceivedKind},
+    };
+
+    fn reply_to(options: &DynamicOptions) -> Option<String> {
+        let raw_message = r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+Subject: -43.5,170.3 ML
+From: Luke Frisken <l.frisken@gmail.com>
+Reply-To: Hut Warden <warden@example.com>
+To: test.email.weather.service@gmail.com
+Content-Type: text/plain; charset="UTF-8"
+
+-43.5,170.3 ML
+"#;
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let email = ReceivedKind::parse_email(message).unwrap();
+        match check_reply_to(email, options) {
+            ReceivedKind::Plain(email) => email
+                .reply_to
+                .map(|account| account.email_str().to_string()),
+            ReceivedKind::Inreach(_) => panic!("Expected a plain email"),
+        }
+    }
+
+    #[test]
+    fn test_check_reply_to() {
+        assert_eq!(None, reply_to(&DynamicOptions::default()));
+
+        let options = DynamicOptions {
+            whitelist: Some(vec!["l.frisken@gmail.com".to_string()]),
+            ..DynamicOptions::default()
+        };
+        assert_eq!(None, reply_to(&options));
+
+        let options = DynamicOptions {
+            whitelist: Some(vec![
+                "l.frisken@gmail.com".to_string(),
+                "warden@example.com".to_string(),
+            ]),
+            ..DynamicOptions::default()
+        };
+        assert_eq!(Some("warden@example.com".to_string()), reply_to(&options));
+    }
 }
@@ -0,0 +1,257 @@
+//! Redaction of personal information from received emails and replies, before they are logged
+//! (see [`Redact`]) or stored at rest in the queues (see [`redact_at_rest()`]).
+
+use hmac::{Hmac, Mac};
+use once_cell::sync::OnceCell;
+use secrecy::{ExposeSecret, SecretString};
+use sha2::Sha256;
+
+use crate::{email, inreach, plain, receive::ReceivedKind, reply::Reply};
+
+/// Prefix of identities which have been hashed using [`hash_identity()`].
+const HASH_PREFIX: &str = "sha256:";
+
+/// Minimum length (in bytes) of the `IDENTITY_KEY` secret.
+const MIN_IDENTITY_KEY_BYTES: usize = 16;
+
+/// Key used by [`hash_identity()`], see [`init_identity_key()`].
+static IDENTITY_KEY: OnceCell<Hmac<Sha256>> = OnceCell::new();
+
+/// Domain of email addresses which have been redacted, see [`redact_account()`].
+const REDACTED_DOMAIN: &str = "redacted.invalid";
+
+/// Mask which replaces redacted values.
+const MASK: &str = "***";
+
+/// Initialize the secret `key` (the `IDENTITY_KEY` secret) used by [`hash_identity()`], this needs
+/// to be called before any identities are hashed. Subsequent calls have no effect.
+pub fn init_identity_key(key: &SecretString) -> eyre::Result<()> {
+    let key = key.expose_secret().as_bytes();
+    if key.len() < MIN_IDENTITY_KEY_BYTES {
+        return Err(eyre::eyre!(
+            "IDENTITY_KEY needs to be at least {} bytes, not {} bytes",
+            MIN_IDENTITY_KEY_BYTES,
+            key.len()
+        ));
+    }
+    let mac = Hmac::<Sha256>::new_from_slice(key)
+        .map_err(|error| eyre::eyre!("Invalid IDENTITY_KEY: {}", error))?;
+    let _ = IDENTITY_KEY.set(mac);
+    Ok(())
+}
+
+/// The key initialized by [`init_identity_key()`], tests use a fixed key.
+fn identity_key() -> &'static Hmac<Sha256> {
+    #[cfg(test)]
+    {
+        IDENTITY_KEY.get_or_init(|| {
+            Hmac::new_from_slice(b"email-weather-test-identity-key")
+                .expect("HMAC can take a key of any size")
+        })
+    }
+    #[cfg(not(test))]
+    {
+        IDENTITY_KEY
+            .get()
+            .expect("The identity key has not been initialized, see init_identity_key()")
+    }
+}
+
+/// Hash a sender `identity` (e.g. a name or email address) using HMAC-SHA256 with the key
+/// initialized by [`init_identity_key()`], so that requests from the same sender can still be
+/// correlated without revealing who they are, even to someone who can guess their identity.
+/// Identities which have already been hashed are returned unchanged.
+///
+/// # Panics
+///
+/// If [`init_identity_key()`] has not been called.
+#[must_use]
+pub fn hash_identity(identity: &str) -> String {
+    if identity.starts_with(HASH_PREFIX) {
+        return identity.to_owned();
+    }
+    let mut mac = identity_key().clone();
+    mac.update(identity.as_bytes());
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .take(8)
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("{}{}", HASH_PREFIX, hex)
+}
+
+/// Mask the values of the query parameters of `url` (e.g. the tokens in an inReach referral url).
+#[must_use]
+pub fn mask_url(url: &url::Url) -> url::Url {
+    let mut masked = url.clone();
+    let keys: Vec<String> = url.query_pairs().map(|(key, _)| key.into_owned()).collect();
+    if !keys.is_empty() {
+        masked
+            .query_pairs_mut()
+            .clear()
+            .extend_pairs(keys.iter().map(|key| (key, MASK)));
+    }
+    masked
+}
+
+/// Replace the address of `account` with a hash of the address, and remove the name.
+fn redact_account(account: &email::Account) -> email::Account {
+    let local = hash_identity(account.email_str()).replace(':', "-");
+    format!("{}@{}", local, REDACTED_DOMAIN)
+        .parse()
+        .expect("Redacted email address is valid")
+}
+
+/// A value which can have personal information redacted.
+pub trait Redact {
+    /// A copy of this value with personal information redacted, suitable for logging.
+    #[must_use]
+    fn redacted(&self) -> Self;
+}
+
+impl Redact for inreach::email::Received {
+    fn redacted(&self) -> Self {
+        Self {
+            from_name: hash_identity(&self.from_name),
+            referral_url: mask_url(&self.referral_url),
+            mapshare_url: None,
+            device_id: self.device_id.as_deref().map(hash_identity),
+            ..self.clone()
+        }
+    }
+}
+
+impl Redact for plain::email::Received {
+    fn redacted(&self) -> Self {
+        Self {
+            from: redact_account(&self.from),
+            reply_to: self.reply_to.as_ref().map(redact_account),
+            subject: self.subject.as_ref().map(|_| MASK.to_owned()),
+            ..self.clone()
+        }
+    }
+}
+
+impl Redact for ReceivedKind {
+    fn redacted(&self) -> Self {
+        match self {
+            Self::Inreach(email) => Self::Inreach(email.redacted()),
+            Self::Plain(email) => Self::Plain(email.redacted()),
+        }
+    }
+}
+
+impl Redact for Reply {
+    fn redacted(&self) -> Self {
+        match self {
+            Self::InReach(reply) => Self::InReach(crate::reply::InReach {
+                referral_url: mask_url(&reply.referral_url),
+                ..reply.clone()
+            }),
+            Self::Plain(reply) => Self::Plain(crate::reply::Plain {
+                to: redact_account(&reply.to),
+                subject: reply.subject.as_ref().map(|_| MASK.to_owned()),
+                reply_subject: reply.reply_subject.as_ref().map(|_| MASK.to_owned()),
+                // The contents of attachments are omitted from the logs.
+                attachments: reply
+                    .attachments
+                    .iter()
+                    .map(|attachment| crate::reply::Attachment {
+                        data: Vec::new(),
+                        ..attachment.clone()
+                    })
+                    .collect(),
+                encrypted: reply.encrypted.as_ref().map(|_| MASK.to_owned()),
+                ..reply.clone()
+            }),
+        }
+    }
+}
+
+/// Redact the personal information in `email` which is not required to process the request and
+/// send the reply, before it is stored at rest in the queue.
+#[must_use]
+pub fn redact_at_rest(email: ReceivedKind) -> ReceivedKind {
+    match email {
+        ReceivedKind::Inreach(email) => ReceivedKind::Inreach(inreach::email::Received {
+            from_name: hash_identity(&email.from_name),
+            mapshare_url: None,
+            device_id: email.device_id.as_deref().map(hash_identity),
+            ..email
+        }),
+        ReceivedKind::Plain(email) => ReceivedKind::Plain(plain::email::Received {
+            // The reply only needs the address.
+            from: email
+                .from
+                .email_str()
+                .parse()
+                .expect("Email address is valid"),
+            reply_to: email.reply_to.map(|reply_to| {
+                reply_to
+                    .email_str()
+                    .parse()
+                    .expect("Email address is valid")
+            }),
+            ..email
+        }),
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secrecy::SecretString;
+
+    use crate::{gis::Position, inreach, request::ParsedForecastRequest};
+
+    use super::{hash_identity, init_identity_key, mask_url, redact_account, Redact};
+
+    #[test]
+    fn test_hash_identity() {
+        let hashed = hash_identity("Luke Frisken");
+        assert!(hashed.starts_with("sha256:"));
+        assert!(!hashed.contains("Luke"));
+        assert_eq!(hashed, hash_identity("Luke Frisken"));
+        assert_eq!(hashed, hash_identity(&hashed));
+        assert_ne!(hashed, hash_identity("Someone Else"));
+
+        assert!(init_identity_key(&SecretString::new("short".to_owned())).is_err());
+    }
+
+    #[test]
+    fn test_mask_url() {
+        let url: url::Url =
+            "https://aus.explore.garmin.com/textmessage/txtmsg?extId=000aa0e6&adr=a"
+                .parse()
+                .unwrap();
+        assert_eq!(
+            "https://aus.explore.garmin.com/textmessage/txtmsg?extId=***&adr=***",
+            mask_url(&url).as_str()
+        );
+    }
+
+    #[test]
+    fn test_redact_account() {
+        let account = "Luke Frisken <luke@example.com>".parse().unwrap();
+        let redacted = redact_account(&account).to_string();
+        assert!(redacted.ends_with("@redacted.invalid"), "{redacted}");
+        assert!(!redacted.contains("luke"), "{redacted}");
+    }
+
+    #[test]
+    fn test_redact_inreach_received() {
+        let received = inreach::email::Received {
+            from_name: "Luke Frisken".to_owned(),
+            referral_url: "https://example.org/?extId=secret".parse().unwrap(),
+            position: Position::new(-43.75905, 170.115),
+            mapshare_url: Some("https://share.garmin.com/LukeFrisken".parse().unwrap()),
+            device_id: Some("secret".to_owned()),
+            sent_at: None,
+            forecast_request: ParsedForecastRequest::default(),
+        };
+        let redacted = format!("{:?}", received.redacted());
+        assert!(!redacted.contains("Luke"), "{redacted}");
+        assert!(!redacted.contains("secret"), "{redacted}");
+    }
+}
@@ -0,0 +1,299 @@
+//! End-to-end tests which replay recorded emails (from `fixtures/emails/`) through the receive,
+//! process and reply stages, with the external services mocked and time simulated using
+//! [`time::Simulated`].
+
+use std::{
+    path::PathBuf,
+    sync::atomic::{AtomicUsize, Ordering},
+    time::Duration,
+};
+
+use async_trait::async_trait;
+use chrono::{DateTime, Utc};
+use once_cell::sync::Lazy;
+use open_meteo::Forecast;
+use tokio::sync::{mpsc, watch, Mutex};
+
+use crate::{
+    aviation_weather,
+    circuit_breaker::{CircuitBreakerOptions, CircuitBreakers},
+    control::Controls,
+    correlation::RequestId,
+    flood,
+    forecast_service::{self, ObtainedForecast},
+    latency::Latency,
+    options::DynamicOptions,
+    process::process_emails_impl,
+    queue,
+    quota::Quotas,
+    receive::receive_message,
+    reply::{self, send_replies_impl, Reply},
+    retry::BackoffOptions,
+    status::ServiceStatus,
+    task::TaskContext,
+    time::{self, Port},
+    topo_data_service,
+    webhook::Webhooks,
+};
+
+static FORECAST_MT_COOK: Lazy<Forecast> = Lazy::new(|| {
+    serde_json::from_str(&std::fs::read_to_string("fixtures/forecast_mt_cook.json").unwrap())
+        .unwrap()
+});
+
+fn start() -> DateTime<Utc> {
+    "2022-12-03T08:00:00Z".parse().unwrap()
+}
+
+/// Implementation of [`reply::Port`] which records replies instead of sending them. The first
+/// `inreach_failures` attempts to send an inReach reply fail.
+struct RecordingReplies {
+    inreach_failures: AtomicUsize,
+    sent_tx: mpsc::UnboundedSender<(RequestId, Reply)>,
+}
+
+impl RecordingReplies {
+    fn new(inreach_failures: usize) -> (Self, mpsc::UnboundedReceiver<(RequestId, Reply)>) {
+        let (sent_tx, sent_rx) = mpsc::unbounded_channel();
+        let replies = Self {
+            inreach_failures: AtomicUsize::new(inreach_failures),
+            sent_tx,
+        };
+        (replies, sent_rx)
+    }
+}
+
+#[async_trait]
+impl reply::Port for RecordingReplies {
+    async fn test_connection(&self) -> eyre::Result<()> {
+        Ok(())
+    }
+
+    async fn send_reply(&self, request_id: RequestId, reply: &Reply) -> eyre::Result<()> {
+        if matches!(reply, Reply::InReach(_))
+            && self
+                .inreach_failures
+                .fetch_update(Ordering::SeqCst, Ordering::SeqCst, |f| f.checked_sub(1))
+                .is_ok()
+        {
+            return Err(eyre::eyre!("Simulated failure sending inReach reply"));
+        }
+        self.sent_tx
+            .send((request_id, reply.clone()))
+            .map_err(|_| eyre::eyre!("Replies are no longer being recorded"))
+    }
+}
+
+/// Runs the stages against queues in a temporary directory, which is removed when dropped.
+struct Harness {
+    queue_dir: PathBuf,
+    time: &'static time::Simulated,
+    context: TaskContext,
+}
+
+impl Harness {
+    fn new() -> Self {
+        let queue_dir =
+            std::env::temp_dir().join(format!("email-weather-replay-{}", uuid::Uuid::new_v4()));
+        let time: &'static time::Simulated =
+            Box::leak(Box::new(time::Simulated::new_auto_advance(start())));
+        let context = TaskContext {
+            status: Box::leak(Box::new(ServiceStatus::new(start()))),
+            webhooks: Box::leak(Box::new(Webhooks::new(
+                Vec::new(),
+                reqwest::Client::new(),
+                time,
+            ))),
+            circuit_breakers: Box::leak(Box::new(CircuitBreakers::new(
+                CircuitBreakerOptions::default(),
+            ))),
+            analytics: None,
+            confirmations: None,
+            feeds: None,
+            previous_forecasts: None,
+            public_keys: None,
+            archive: None,
+            quotas: Box::leak(Box::new(Quotas::new(
+                watch::channel(DynamicOptions::default()).1,
+            ))),
+            latency: Box::leak(Box::new(Latency::new())),
+            controls: Box::leak(Box::new(Controls::new())),
+            backoff: BackoffOptions::default(),
+            time,
+        };
+        Self {
+            queue_dir,
+            time,
+            context,
+        }
+    }
+
+    /// Replay the recorded emails named `fixtures` through the receive, process and reply stages,
+    /// and return the first `expected_replies` replies that were sent, in order. The first
+    /// `inreach_failures` attempts to send an inReach reply fail.
+    async fn replay(
+        &self,
+        fixtures: &[&str],
+        options: DynamicOptions,
+        forecast_service: &dyn forecast_service::Port,
+        topo_data_service: &dyn topo_data_service::Port,
+        inreach_failures: usize,
+        expected_replies: usize,
+    ) -> Vec<(RequestId, Reply)> {
+        let process_path = self.queue_dir.join("process");
+        let reply_path = self.queue_dir.join("reply");
+
+        let emails_sender = Mutex::new(queue::Sender::from(
+            yaque::Sender::open(&process_path).unwrap(),
+        ));
+        for fixture in fixtures {
+            let rfc822 = std::fs::read(format!("fixtures/emails/{}.eml", fixture)).unwrap();
+            receive_message(
+                &rfc822,
+                &emails_sender,
+                &options,
+                &self.context,
+                self.context.time.utc_now(),
+            )
+            .await
+            .unwrap();
+        }
+
+        let (_options_tx, options_rx) = watch::channel(options);
+        let mut process_receiver =
+            queue::Receiver::from(yaque::Receiver::open(&process_path).unwrap());
+        let mut reply_sender = queue::Sender::from(yaque::Sender::open(&reply_path).unwrap());
+        let mut reply_receiver = queue::Receiver::from(yaque::Receiver::open(&reply_path).unwrap());
+        let (replies, mut sent_rx) = RecordingReplies::new(inreach_failures);
+        let reply_backoff = BackoffOptions {
+            jitter: false,
+            ..BackoffOptions::default()
+        };
+
+        let aviation_weather = aviation_weather::MockPort::new();
+        let flood = flood::MockPort::new();
+        let process = process_emails_impl(
+            &mut process_receiver,
+            &mut reply_sender,
+            forecast_service,
+            topo_data_service,
+            &aviation_weather,
+            &flood,
+            &options_rx,
+            &self.context,
+        );
+        let reply = send_replies_impl(&mut reply_receiver, &replies, reply_backoff, self.context);
+        let sent = async {
+            let mut sent = Vec::with_capacity(expected_replies);
+            while sent.len() < expected_replies {
+                sent.push(sent_rx.recv().await.unwrap());
+            }
+            sent
+        };
+
+        // Real time is only used to prevent a failing test from hanging.
+        tokio::time::timeout(Duration::from_secs(30), async {
+            tokio::select! {
+                result = process => panic!("Process stage exited: {:?}", result),
+                result = reply => panic!("Reply stage exited: {:?}", result),
+                sent = sent => sent,
+            }
+        })
+        .await
+        .expect("Timed out waiting for replies")
+    }
+}
+
+impl Drop for Harness {
+    fn drop(&mut self) {
+        let _ = std::fs::remove_dir_all(&self.queue_dir);
+    }
+}
+
+/// Replays a plain email, an email from a sender who is not on the whitelist (which is ignored),
+/// and an inReach email where the first attempt to send the reply fails.
+#[tokio::test]
+async fn test_replay_emails() {
+    let harness = Harness::new();
+
+    let mut forecast_service = forecast_service::MockPort::new();
+    forecast_service
+        .expect_obtain_forecast()
+        .times(2)
+        .returning(|_| Ok(ObtainedForecast::fixture(FORECAST_MT_COOK.clone())));
+    let mut topo_data_service = topo_data_service::MockPort::new();
+    topo_data_service
+        .expect_obtain_elevation()
+        .times(2)
+        .returning(|_| Ok(2216.0));
+
+    let options = DynamicOptions {
+        whitelist: Some(vec!["l.frisken@gmail.com".to_owned()]),
+        reply_subject: Some("{summary} {dates} ({subject})".to_owned()),
+        ..DynamicOptions::default()
+    };
+
+    let sent = harness
+        .replay(
+            &[
+                "plain_forecast",
+                "plain_not_whitelisted",
+                "inreach_forecast",
+            ],
+            options,
+            &forecast_service,
+            &topo_data_service,
+            1,
+            2,
+        )
+        .await;
+
+    assert_eq!(2, sent.len());
+    assert_ne!(sent[0].0, sent[1].0);
+
+    let plain = match &sent[0].1 {
+        Reply::Plain(reply) => reply,
+        reply => panic!("Unexpected reply: {:?}", reply),
+    };
+    assert_eq!("l.frisken@gmail.com", plain.to.email_str());
+    assert_eq!(Some("Forecast"), plain.subject.as_deref());
+    assert_eq!(
+        Some("Rain Fri 3-9 Dec (Forecast)"),
+        plain.reply_subject.as_deref()
+    );
+    assert_eq!(
+        Some("CAH+3HA1rdRyAyLW+-6zkHLW6UV2Y7bbK2h5Yujq-C6ydX3y1AQ@mail.gmail.com"),
+        plain.in_reply_to_message_id.as_deref()
+    );
+    assert!(plain.plain_message.starts_with("Tz+13:00 FE0 TE2216\n"));
+
+    let inreach = match &sent[1].1 {
+        Reply::InReach(reply) => reply,
+        reply => panic!("Unexpected reply: {:?}", reply),
+    };
+    assert_eq!(
+        "https://aus.explore.garmin.com/textmessage/txtmsg?extId=000aa0e6-8e00-2501-000d-3aa730600000&adr=email.weather.service%40gmail.com",
+        inreach.referral_url.as_str()
+    );
+    assert_eq!(
+        "Tz+13:00 FE0 TE2216\n\
+        03T21 C2 F28 W1@32 P0\n\
+        04T03 C3 F33+ W2@31 P0\n\
+        04T09 C1 F33 W2@31 P0\n\
+        04T15 C2 F33 W2@31 P0\n\
+        04T21 C1 F31- W1@31 P0\n\
+        05T03 C3 F29- W1@31 P0",
+        inreach.message.trim_end()
+    );
+
+    // The failed inReach reply was retried after the first backoff of 10 seconds, without
+    // waiting in real time.
+    assert_eq!(
+        start() + chrono::Duration::seconds(10),
+        harness.time.utc_now()
+    );
+    assert_eq!(
+        Some(start()),
+        harness.context.status.last_forecast_processed()
+    );
+}
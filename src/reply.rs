@@ -1,23 +1,38 @@
 //! See [`send_replies()`].
 
-use std::{sync::Arc, time::Duration};
+use std::{
+    path::{Path, PathBuf},
+    sync::Arc,
+};
 
+use async_trait::async_trait;
 use eyre::Context;
 use lettre::{
-    message::MultiPart,
+    message::{header::ContentType, MultiPart, SinglePart},
     transport::smtp::authentication::{Credentials, Mechanism},
     AsyncSmtpTransport, AsyncTransport, Tokio1Executor,
 };
 use serde::{Deserialize, Serialize};
 use tokio::sync::Mutex;
+use tracing::Instrument;
 
 use crate::{
-    email, inreach, oauth2::AuthenticationFlow, receive::ReceivedKind, retry::ExponentialBackoff,
-    task::run_retry_log_errors, time,
+    analytics::{DeviceKind, ReplyEvent, ReplyOutcome},
+    correlation::{Queued, RequestId, XRequestId},
+    email, encryption, fs, inreach,
+    latency::Stage,
+    oauth2::AuthenticationFlow,
+    queue,
+    receive::ReceivedKind,
+    redact::Redact,
+    reporting,
+    retry::{BackoffOptions, ExponentialBackoff},
+    task::{run_retry_log_errors, TaskContext},
+    webhook::WebhookEvent,
 };
 
 /// A reply to an inreach device.
-#[derive(Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct InReach {
     /// The url used to send the reply via the web interface (that was supplied in the original
     /// message from the device).
@@ -39,7 +54,7 @@ impl InReach {
 }
 
 /// Reply to a standard plain text email.
-#[derive(Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub struct Plain {
     /// Subject of the email that is being replied to.
     pub subject: Option<String>,
@@ -51,6 +66,28 @@ pub struct Plain {
     pub to: email::Account,
     /// Message id that this is in reply to.
     pub in_reply_to_message_id: Option<String>,
+    /// Subject of the reply, if `None` the subject is `Re: <subject>` (or `Weather Forecast` if
+    /// the email being replied to has no subject).
+    #[serde(default)]
+    pub reply_subject: Option<String>,
+    /// Files attached to the reply, e.g. a GRIB file, see [`crate::grib`].
+    #[serde(default)]
+    pub attachments: Vec<Attachment>,
+    /// The ASCII armored PGP message containing the message, html message and attachments, which
+    /// is sent instead of them, see [`crate::encryption`].
+    #[serde(default)]
+    pub encrypted: Option<String>,
+}
+
+/// A file attached to a [`Plain`] reply.
+#[derive(Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
+pub struct Attachment {
+    /// Name of the file.
+    pub filename: String,
+    /// MIME type of the file.
+    pub content_type: String,
+    /// Contents of the file.
+    pub data: Vec<u8>,
 }
 
 impl Plain {
@@ -61,17 +98,66 @@ impl Plain {
         html_message: Option<String>,
     ) -> Self {
         Self {
-            to: email.from,
+            to: email.reply_to.unwrap_or(email.from),
             plain_message,
             html_message,
             in_reply_to_message_id: email.message_id,
             subject: email.subject,
+            reply_subject: None,
+            attachments: Vec::new(),
+            encrypted: None,
         }
     }
+
+    /// Construct a plain reply to the sender (the `From` address) of a received plain email
+    /// [`Received`](crate::plain::email::Received), ignoring any `Reply-To` address. Used for
+    /// messages containing codes which prove ownership of the sender's address.
+    #[must_use]
+    pub fn to_sender(
+        email: crate::plain::email::Received,
+        plain_message: String,
+        html_message: Option<String>,
+    ) -> Self {
+        Self::from_received(
+            crate::plain::email::Received {
+                reply_to: None,
+                ..email
+            },
+            plain_message,
+            html_message,
+        )
+    }
+
+    /// The MIME parts containing the message (with `html_message` as an alternative, if
+    /// specified) and the attachments.
+    pub(crate) fn multipart(&self, html_message: Option<String>) -> eyre::Result<MultiPart> {
+        let body = html_message.map(|html_message| {
+            MultiPart::alternative_plain_html(self.plain_message.clone(), html_message)
+        });
+        let mut mixed = match body {
+            Some(body) if self.attachments.is_empty() => return Ok(body),
+            Some(body) => MultiPart::mixed().multipart(body),
+            None => MultiPart::mixed().singlepart(SinglePart::plain(self.plain_message.clone())),
+        };
+        for attachment in &self.attachments {
+            let content_type =
+                ContentType::parse(&attachment.content_type).wrap_err_with(|| {
+                    format!(
+                        "Invalid content type for attachment {:?}",
+                        attachment.filename
+                    )
+                })?;
+            mixed = mixed.singlepart(
+                lettre::message::Attachment::new(attachment.filename.clone())
+                    .body(attachment.data.clone(), content_type),
+            );
+        }
+        Ok(mixed)
+    }
 }
 
 /// A reply message.
-#[derive(Eq, PartialEq, Serialize, Deserialize, Debug)]
+#[derive(Eq, PartialEq, Serialize, Deserialize, Debug, Clone)]
 pub enum Reply {
     /// See [`InReach`].
     InReach(InReach),
@@ -95,15 +181,36 @@ impl Reply {
             }
         }
     }
+
+    /// Create a [`Reply`] from [`ReceivedKind`] with the specified `message`, addressed to the
+    /// sender of a plain email, see [`Plain::to_sender()`].
+    #[must_use]
+    pub fn to_sender(
+        email: ReceivedKind,
+        plain_message: String,
+        html_message: Option<String>,
+    ) -> Self {
+        match email {
+            ReceivedKind::Inreach(email) => {
+                Reply::InReach(InReach::from_received(email, plain_message))
+            }
+            ReceivedKind::Plain(email) => {
+                Reply::Plain(Plain::to_sender(email, plain_message, html_message))
+            }
+        }
+    }
 }
 
-async fn send_reply(
+/// Send a `reply` for the request with `request_id`, plain emails are sent from `email_account`
+/// using `sender`.
+pub(crate) async fn send_reply(
+    request_id: RequestId,
     reply: &Reply,
     sender: &SmtpTransport,
     http_client: &reqwest::Client,
     email_account: &email::Account,
 ) -> eyre::Result<()> {
-    tracing::info!("Sending reply: {:?}", reply);
+    tracing::info!("Sending reply: {:?}", reply.redacted());
 
     match reply {
         Reply::InReach(reply) => {
@@ -114,7 +221,8 @@ async fn send_reply(
         Reply::Plain(reply) => {
             let builder = lettre::Message::builder()
                 .from(email_account.clone().into())
-                .to(reply.to.clone().into());
+                .to(reply.to.clone().into())
+                .header(XRequestId(request_id));
 
             let builder = if let Some(id) = &reply.in_reply_to_message_id {
                 builder.in_reply_to(id.clone())
@@ -122,22 +230,25 @@ async fn send_reply(
                 builder
             };
 
-            let builder = if let Some(subject) = &reply.subject {
+            let builder = if let Some(reply_subject) = &reply.reply_subject {
+                builder.subject(reply_subject.clone())
+            } else if let Some(subject) = &reply.subject {
                 builder.subject(format!("Re: {}", subject))
             } else {
                 builder.subject("Weather Forecast")
             };
 
-            let message: lettre::Message = if let Some(html_message) = &reply.html_message {
-                builder.multipart(MultiPart::alternative_plain_html(
-                    reply.plain_message.clone(),
-                    html_message.clone(),
-                ))?
-            } else {
-                builder.body(reply.plain_message.clone())?
-            };
+            let html_message: Option<String> = reply.html_message.as_ref().map(|html_message| {
+                format!("{}\n<!-- request id: {} -->", html_message, request_id)
+            });
 
-            tracing::trace!("Replying: {:?}", message);
+            let message: lettre::Message = match &reply.encrypted {
+                Some(encrypted) => builder.multipart(encryption::mime_body(encrypted)?)?,
+                None if html_message.is_none() && reply.attachments.is_empty() => {
+                    builder.body(reply.plain_message.clone())?
+                }
+                None => builder.multipart(reply.multipart(html_message)?)?,
+            };
 
             sender
                 .send(message)
@@ -153,7 +264,7 @@ async fn send_reply(
 /// Number of attempts to retry sending a message before discarding it.
 const RETRY_ATTEMPTS: usize = 5;
 
-type SmtpTransport = AsyncSmtpTransport<Tokio1Executor>;
+pub(crate) type SmtpTransport = AsyncSmtpTransport<Tokio1Executor>;
 
 async fn setup_sender<AUTH: AuthenticationFlow>(
     email_account: &email::Account,
@@ -179,73 +290,235 @@ async fn setup_sender<AUTH: AuthenticationFlow>(
     Ok(sender)
 }
 
-async fn send_replies_impl<AUTH>(
-    reply_receiver: &mut yaque::Receiver,
+/// Interface for sending replies, to allow mocking the external email and inReach services.
+/// See [`Gateway`] for implementation.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait Port: Send + Sync {
+    /// Test that replies are able to be sent.
+    async fn test_connection(&self) -> eyre::Result<()>;
+    /// Send a `reply` for the request with `request_id`.
+    async fn send_reply(&self, request_id: RequestId, reply: &Reply) -> eyre::Result<()>;
+}
+
+/// Implementation of [`Port`] which sends replies via SMTP (authenticated using `oauth_flow`) and
+/// the inReach web interface.
+pub struct Gateway<'a, AUTH> {
     http_client: reqwest::Client,
-    email_account: &email::Account,
-    oauth_flow: &AUTH,
-    time: &dyn time::Port,
-) -> eyre::Result<()>
+    email_account: &'a email::Account,
+    oauth_flow: &'a AUTH,
+}
+
+impl<'a, AUTH> Gateway<'a, AUTH> {
+    /// Construct a new [`Gateway`].
+    #[must_use]
+    pub fn new(
+        http_client: reqwest::Client,
+        email_account: &'a email::Account,
+        oauth_flow: &'a AUTH,
+    ) -> Self {
+        Self {
+            http_client,
+            email_account,
+            oauth_flow,
+        }
+    }
+}
+
+#[async_trait]
+impl<'a, AUTH> Port for Gateway<'a, AUTH>
 where
-    AUTH: AuthenticationFlow,
+    AUTH: AuthenticationFlow + Send + Sync,
 {
-    drop(
-        setup_sender(email_account, &*oauth_flow)
+    async fn test_connection(&self) -> eyre::Result<()> {
+        drop(setup_sender(self.email_account, self.oauth_flow).await?);
+        Ok(())
+    }
+
+    async fn send_reply(&self, request_id: RequestId, reply: &Reply) -> eyre::Result<()> {
+        let sender = setup_sender(self.email_account, self.oauth_flow)
             .await
-            .wrap_err("Error while setting up SMTP sender")?,
-    );
+            .wrap_err("Error setting up SMTP sender")?;
+        // .pool_config(PoolConfig::new().max_size(20))
+        send_reply(
+            request_id,
+            reply,
+            &sender,
+            &self.http_client,
+            self.email_account,
+        )
+        .await
+    }
+}
+
+/// Implementation of [`Port`] used in dry run mode (see [`crate::options::Options::dry_run`]),
+/// which logs replies and archives them as JSON files in a directory, instead of sending them.
+pub struct DryRun {
+    archive_dir: PathBuf,
+}
+
+impl DryRun {
+    /// Construct a new [`DryRun`] which archives replies in `archive_dir`.
+    #[must_use]
+    pub fn new(archive_dir: &Path) -> Self {
+        Self {
+            archive_dir: archive_dir.to_owned(),
+        }
+    }
+}
+
+#[async_trait]
+impl Port for DryRun {
+    async fn test_connection(&self) -> eyre::Result<()> {
+        fs::create_dir_if_not_exists(&self.archive_dir)
+    }
+
+    async fn send_reply(&self, request_id: RequestId, reply: &Reply) -> eyre::Result<()> {
+        tracing::info!("Dry run, not sending reply: {:?}", reply.redacted());
+        let path = self.archive_dir.join(format!("{}.json", request_id));
+        let reply_json = serde_json::to_vec_pretty(reply)?;
+        tokio::fs::write(&path, reply_json)
+            .await
+            .wrap_err_with(|| format!("Error archiving reply to {:?}", path))
+    }
+}
+
+/// Send replies received from `reply_receiver` using `reply_port`, retrying each reply using an
+/// [`ExponentialBackoff`] constructed from `backoff`. A retry is brought forward when
+/// [`crate::control::Controls::flush_queues()`] is called.
+pub(crate) async fn send_replies_impl(
+    reply_receiver: &mut queue::Receiver,
+    reply_port: &dyn Port,
+    backoff: BackoffOptions,
+    context: TaskContext,
+) -> eyre::Result<()> {
+    let TaskContext {
+        status,
+        webhooks,
+        circuit_breakers,
+        analytics,
+        latency,
+        controls,
+        time,
+        ..
+    } = context;
+    reply_port
+        .test_connection()
+        .await
+        .wrap_err("Error while setting up SMTP sender")?;
     tracing::info!("Successfully set up and tested SMTP sender connection");
 
     loop {
-        let reply_bytes = reply_receiver.recv().await?;
-        let reply: Reply =
+        let reply_bytes = match reply_receiver.recv().await? {
+            queue::Message::Received(received) => received,
+            queue::Message::Undecryptable(undecryptable) => {
+                tracing::error!(
+                    "Unable to decrypt queued reply: {:?}",
+                    undecryptable.error()
+                );
+                let path = undecryptable.dead_letter().await?;
+                tracing::warn!("Moved undecryptable reply to dead letter {:?}", path);
+                continue;
+            }
+        };
+        let Queued {
+            request_id,
+            queued_at,
+            payload: reply,
+        }: Queued<Reply> =
             serde_json::from_slice(&*reply_bytes).wrap_err("Failed to deserialize reply")?;
 
-        let mut send_backoff =
-            ExponentialBackoff::new(Duration::from_secs(5), Duration::from_secs(60 * 10))
-                .expect("Invalid backoff");
+        let send = async {
+            let mut send_backoff = ExponentialBackoff::from_options(&backoff)
+                .wrap_err("Invalid reply backoff options")?;
 
-        'retry: loop {
-            let sender = setup_sender(email_account, oauth_flow)
-                .await
-                .wrap_err("Error setting up SMTP sender")?;
-            // .pool_config(PoolConfig::new().max_size(20))
-            match send_reply(&reply, &sender, &http_client, email_account).await {
-                Ok(_) => break 'retry,
-                Err(error) => {
-                    tracing::error!("{:?}", error);
-                    if send_backoff.iteration() < RETRY_ATTEMPTS {
-                        send_backoff.sleep(time).await;
-                        tracing::warn!(
-                            "Retrying {}/{}...",
-                            send_backoff.iteration(),
-                            RETRY_ATTEMPTS
-                        );
-                        continue;
+            let is_inreach = matches!(reply, Reply::InReach(_));
+
+            let outcome = 'retry: loop {
+                if is_inreach {
+                    // Fail fast while the inReach reply service is unavailable, rather than
+                    // retrying each reply and holding up the queue.
+                    if let Err(error) = circuit_breakers.inreach.check(time.utc_now()) {
+                        let reply_json = serde_json::to_string(&reply)?;
+                        tracing::error!("{}, discarding reply\n{}", error, reply_json);
+                        status.record_reply_failed(time.utc_now());
+                        webhooks.notify(WebhookEvent::ReplyFailed { request_id });
+                        break ReplyOutcome::Failed;
                     }
+                }
 
-                    let reply_json = serde_json::to_string(&reply)?;
-                    tracing::error!("Max retries exceeded, discarding reply\n{}", reply_json);
-                    break;
+                let result = reply_port.send_reply(request_id, &reply).await;
+                if is_inreach {
+                    circuit_breakers.inreach.record(&result, time.utc_now());
                 }
+                match result {
+                    Ok(_) => {
+                        let sent_at = time.utc_now();
+                        status.record_reply_sent(sent_at);
+                        if let Some(queued_at) = queued_at {
+                            latency.record(Stage::Reply, sent_at - queued_at);
+                        }
+                        break 'retry ReplyOutcome::Sent;
+                    }
+                    Err(error) => {
+                        tracing::error!("{:?}", error);
+                        if send_backoff.iteration() < RETRY_ATTEMPTS {
+                            send_backoff
+                                .sleep_or(time, controls.flush_requested())
+                                .await;
+                            tracing::warn!(
+                                "Retrying {}/{}...",
+                                send_backoff.iteration(),
+                                RETRY_ATTEMPTS
+                            );
+                            continue;
+                        }
+
+                        let reply_json = serde_json::to_string(&reply)?;
+                        tracing::error!("Max retries exceeded, discarding reply\n{}", reply_json);
+                        status.record_reply_failed(time.utc_now());
+                        webhooks.notify(WebhookEvent::ReplyFailed { request_id });
+                        break ReplyOutcome::Failed;
+                    }
+                }
+            };
+
+            if let Some(analytics) = analytics {
+                analytics.record_reply(ReplyEvent {
+                    request_id,
+                    timestamp: time.utc_now(),
+                    device: if is_inreach {
+                        DeviceKind::Inreach
+                    } else {
+                        DeviceKind::Plain
+                    },
+                    attempts: u32::try_from(send_backoff.iteration() + 1).unwrap_or(u32::MAX),
+                    outcome,
+                });
             }
+            eyre::Result::<()>::Ok(())
         }
-        reply_bytes.commit()?;
+        .instrument(tracing::info_span!("request", id = %request_id));
+        reporting::bind_request_id(request_id, send).await?;
+        reply_bytes.commit().await?;
     }
 }
 
 /// This function spawns a task to send replies to received emails using the results of
-/// [`crate::processing`].
+/// [`crate::processing`]. If `dry_run_dir` is specified, replies are archived there instead of
+/// being sent, see [`DryRun`].
 #[tracing::instrument(skip_all)]
 pub async fn send_replies<AUTH>(
-    reply_receiver: yaque::Receiver,
+    reply_receiver: queue::Receiver,
     shutdown_rx: tokio::sync::broadcast::Receiver<()>,
     http_client: reqwest::Client,
     email_account: &email::Account,
     oauth_flow: Arc<AUTH>,
-    time: &dyn time::Port,
+    reply_backoff: BackoffOptions,
+    dry_run_dir: Option<PathBuf>,
+    context: TaskContext,
 ) where
-    AUTH: AuthenticationFlow,
+    AUTH: AuthenticationFlow + Send + Sync,
 {
     let reply_receiver = Arc::new(Mutex::new(reply_receiver));
     tracing::debug!("Starting send replies job");
@@ -254,20 +527,68 @@ pub async fn send_replies<AUTH>(
             let http_client = http_client.clone();
             let reply_receiver = reply_receiver.clone();
             let oauth_flow = oauth_flow.clone();
+            let dry_run_dir = dry_run_dir.clone();
             async move {
                 let mut reply_receiver = reply_receiver.lock().await;
-                send_replies_impl(
-                    &mut reply_receiver,
-                    http_client.clone(),
-                    email_account,
-                    &*oauth_flow,
-                    time,
-                )
-                .await
+                let reply_port: Box<dyn Port + '_> = match &dry_run_dir {
+                    Some(dry_run_dir) => Box::new(DryRun::new(dry_run_dir)),
+                    None => Box::new(Gateway::new(http_client, email_account, &*oauth_flow)),
+                };
+                send_replies_impl(&mut reply_receiver, &*reply_port, reply_backoff, context).await
             }
         },
         shutdown_rx,
-        time,
+        context.backoff,
+        context.time,
     )
     .await;
 }
+
+#[cfg(test)]
+mod test {
+    use crate::{correlation::RequestId, plain, receive::ParseReceivedEmail};
+
+    use super::{DryRun, InReach, Plain, Port, Reply};
+
+    #[tokio::test]
+    async fn test_dry_run() {
+        let archive_dir = std::env::temp_dir().join("email-weather-test-dry-run");
+        let _ = std::fs::remove_dir_all(&archive_dir);
+        let dry_run = DryRun::new(&archive_dir);
+        dry_run.test_connection().await.unwrap();
+
+        let request_id = RequestId::new();
+        let reply = Reply::InReach(InReach {
+            referral_url: "https://example.com/textmessage/txtmsg?extId=1"
+                .parse()
+                .unwrap(),
+            message: "Forecast".to_owned(),
+        });
+        dry_run.send_reply(request_id, &reply).await.unwrap();
+
+        let archived = std::fs::read(archive_dir.join(format!("{}.json", request_id))).unwrap();
+        assert_eq!(reply, serde_json::from_slice::<Reply>(&archived).unwrap());
+        std::fs::remove_dir_all(&archive_dir).unwrap();
+    }
+
+    #[test]
+    fn test_plain_to_sender() {
+        let raw_message = r#"MIME-Version: 1.0
+Date: Tue, 15 Nov 2022 17:55:01 +1100
+Subject: KEY
+From: Luke Frisken <l.frisken@gmail.com>
+Reply-To: Hut Warden <warden@example.com>
+To: test.email.weather.service@gmail.com
+Content-Type: text/plain; charset="UTF-8"
+
+KEY
+"#;
+        let message = mail_parser::Message::parse(raw_message.as_bytes()).unwrap();
+        let received = plain::email::Received::parse_email(message).unwrap();
+
+        let reply = Plain::from_received(received.clone(), "Forecast".to_owned(), None);
+        assert_eq!("warden@example.com", reply.to.email_str());
+        let reply = Plain::to_sender(received, "Code".to_owned(), None);
+        assert_eq!("l.frisken@gmail.com", reply.to.email_str());
+    }
+}
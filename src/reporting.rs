@@ -1,12 +1,23 @@
 //! Utilities for logging and automated bug reporting.
 
 use std::{
+    borrow::Cow,
     ffi::OsStr,
+    future::Future,
     path::{Path, PathBuf},
     str::FromStr,
+    sync::{
+        atomic::{AtomicU64, Ordering},
+        Arc,
+    },
+    time::{Duration, SystemTime},
 };
 
 use axum::{
+    body::StreamBody,
+    extract::Query,
+    http::header,
+    middleware,
     response::{Html, IntoResponse},
     routing::get,
     Router,
@@ -16,17 +27,30 @@ use eyre::Context;
 use futures::{stream, Stream, StreamExt, TryStreamExt};
 use html_builder::Html5;
 use reqwest::StatusCode;
-use secrecy::SecretString;
+use schemars::JsonSchema;
+use secrecy::{ExposeSecret, SecretString};
+use sentry::SentryFutureExt;
+use serde::{Deserialize, Serialize};
 use tokio_stream::wrappers::ReadDirStream;
+use tokio_util::io::{ReaderStream, SyncIoBridge};
 use tower::ServiceBuilder;
-use tower_http::{auth::RequireAuthorizationLayer, trace::TraceLayer};
+use tower_http::trace::TraceLayer;
 use tracing_appender::{
     non_blocking::{NonBlockingBuilder, WorkerGuard},
     rolling::{RollingFileAppender, Rotation},
 };
 use tracing_subscriber::{prelude::__tracing_subscriber_SubscriberExt, util::SubscriberInitExt};
 
-use crate::{fs, serve_http::MyBasicAuth};
+use crate::{
+    analytics::Analytics,
+    auth::{require_session, AdminAuth},
+    correlation::RequestId,
+    fs, html,
+    retry::BackoffOptions,
+    supervisor::Supervisor,
+    task::run_retry_log_errors,
+    time,
+};
 
 /// Options for writing to log file.
 #[derive(Clone)]
@@ -137,6 +161,133 @@ pub struct Guard {
 pub struct Options {
     pub data_dir: PathBuf,
     pub log_rotation: Rotation,
+    pub log_retention: LogRetention,
+    pub prune_stats: PruneStats,
+    /// Application options with personal information redacted, which can be included in log
+    /// bundles, see [`crate::options::Options::sanitized()`].
+    pub sanitized_options: Option<String>,
+    /// Supervisor of the application's tasks, whose restarts are displayed on the logs index page.
+    pub supervisor: &'static Supervisor,
+    /// Analytics of processed requests, whose statistics are displayed on the logs index page.
+    /// `None` if analytics are disabled.
+    pub analytics: Option<&'static Analytics>,
+    /// Options for reporting errors to [sentry.io](https://sentry.io).
+    pub sentry: SentryOptions,
+    /// The `SENTRY_DSN` secret, Sentry reporting is disabled if this is `None`.
+    pub sentry_dsn: Option<SecretString>,
+}
+
+/// Options for reporting errors to [sentry.io](https://sentry.io). Reporting is only enabled when
+/// the `SENTRY_DSN` secret is provided.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct SentryOptions {
+    /// Whether to report to Sentry (when `SENTRY_DSN` is provided). Allows reporting to be
+    /// disabled without removing the secret.
+    ///
+    /// Default is `true`.
+    #[serde(default = "default_sentry_enabled")]
+    pub enabled: bool,
+    /// Name of the environment (e.g. `production` or `staging`) that events are reported with.
+    ///
+    /// Default is `None` (Sentry's default).
+    #[serde(default)]
+    pub environment: Option<String>,
+    /// Proportion (from `0.0` to `1.0`) of error events which are reported.
+    ///
+    /// Default is `1.0`.
+    #[serde(default = "default_sample_rate")]
+    pub sample_rate: f32,
+    /// Proportion (from `0.0` to `1.0`) of transactions which are reported for performance
+    /// monitoring.
+    ///
+    /// Default is `0.1`.
+    #[serde(default = "default_traces_sample_rate")]
+    pub traces_sample_rate: f32,
+}
+
+fn default_sentry_enabled() -> bool {
+    true
+}
+
+fn default_sample_rate() -> f32 {
+    1.0
+}
+
+fn default_traces_sample_rate() -> f32 {
+    0.1
+}
+
+impl Default for SentryOptions {
+    fn default() -> Self {
+        Self {
+            enabled: default_sentry_enabled(),
+            environment: None,
+            sample_rate: default_sample_rate(),
+            traces_sample_rate: default_traces_sample_rate(),
+        }
+    }
+}
+
+/// Bind a new Sentry hub to `future` so that any events reported to Sentry while it runs are
+/// tagged with the `request_id`, for correlating them with the logs and analytics.
+pub fn bind_request_id<F: Future>(
+    request_id: RequestId,
+    future: F,
+) -> impl Future<Output = F::Output> {
+    let hub = sentry::Hub::new_from_top(sentry::Hub::current());
+    hub.configure_scope(|scope| scope.set_tag("request_id", request_id));
+    future.bind_hub(hub)
+}
+
+/// Retention policy for log files, enforced by [`prune_logs()`]. The most recent log file (which
+/// is currently being written to) is never removed.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct LogRetention {
+    /// Log files last modified more than this many days ago are removed.
+    ///
+    /// Default is `Some(30)`.
+    #[serde(default = "default_max_age_days")]
+    pub max_age_days: Option<u64>,
+    /// The oldest log files are removed until the total size of the log files is less than this
+    /// many megabytes.
+    ///
+    /// Default is `None` (no limit).
+    #[serde(default)]
+    pub max_total_size_mb: Option<u64>,
+}
+
+fn default_max_age_days() -> Option<u64> {
+    Some(30)
+}
+
+impl Default for LogRetention {
+    fn default() -> Self {
+        Self {
+            max_age_days: default_max_age_days(),
+            max_total_size_mb: None,
+        }
+    }
+}
+
+/// Statistics about the log files removed by [`prune_logs()`] since the application started.
+#[derive(Debug, Default)]
+pub struct PruneStats {
+    files_removed: AtomicU64,
+    bytes_reclaimed: AtomicU64,
+}
+
+impl PruneStats {
+    /// Number of log files that have been removed.
+    #[must_use]
+    pub fn files_removed(&self) -> u64 {
+        self.files_removed.load(Ordering::Relaxed)
+    }
+
+    /// Total size of the log files that have been removed.
+    #[must_use]
+    pub fn bytes_reclaimed(&self) -> u64 {
+        self.bytes_reclaimed.load(Ordering::Relaxed)
+    }
 }
 
 impl Options {
@@ -146,20 +297,21 @@ impl Options {
 }
 
 pub fn setup_logging(options: &Options) -> eyre::Result<Guard> {
-    let sentry = if let Ok(sentry_dsn) = std::env::var("SENTRY_DSN") {
-        Some(sentry::init(sentry::ClientOptions {
+    let sentry = match &options.sentry_dsn {
+        Some(sentry_dsn) if options.sentry.enabled => Some(sentry::init(sentry::ClientOptions {
             dsn: Some(
                 sentry_dsn
+                    .expose_secret()
                     .parse()
-                    .wrap_err("Unable to parse SENTRY_DSN environment variable")?,
+                    .wrap_err("Unable to parse SENTRY_DSN secret")?,
             ),
             release: sentry::release_name!(),
-            // TODO: set this lower for production
-            traces_sample_rate: 1.0,
+            environment: options.sentry.environment.clone().map(Cow::Owned),
+            sample_rate: options.sentry.sample_rate,
+            traces_sample_rate: options.sentry.traces_sample_rate,
             ..sentry::ClientOptions::default()
-        }))
-    } else {
-        None
+        })),
+        _ => None,
     };
 
     let log_dir = options.log_dir();
@@ -200,6 +352,109 @@ pub fn setup_logging(options: &Options) -> eyre::Result<Guard> {
     })
 }
 
+/// How often [`prune_logs()`] checks the log files against the [`LogRetention`] policy.
+const PRUNE_INTERVAL: Duration = Duration::from_secs(60 * 60);
+
+#[derive(Debug, PartialEq)]
+struct LogFile {
+    path: PathBuf,
+    modified: SystemTime,
+    len: u64,
+}
+
+/// Select the log `files` which should be removed according to the `retention` policy.
+fn select_pruned(
+    mut files: Vec<LogFile>,
+    retention: &LogRetention,
+    now: SystemTime,
+) -> Vec<LogFile> {
+    // Newest files first, the first file is currently being written to.
+    files.sort_by(|a, b| b.modified.cmp(&a.modified));
+
+    let max_age = retention
+        .max_age_days
+        .map(|days| Duration::from_secs(days * 24 * 60 * 60));
+    let max_total_size = retention.max_total_size_mb.map(|mb| mb * 1_000_000);
+
+    let mut pruned = Vec::new();
+    let mut total_size: u64 = 0;
+    for (i, file) in files.into_iter().enumerate() {
+        total_size += file.len;
+        if i == 0 {
+            continue;
+        }
+
+        let too_old = max_age.map_or(false, |max_age| {
+            now.duration_since(file.modified)
+                .map_or(false, |age| age > max_age)
+        });
+        let too_large = max_total_size.map_or(false, |max_total_size| total_size > max_total_size);
+
+        if too_old || too_large {
+            total_size -= file.len;
+            pruned.push(file);
+        }
+    }
+    pruned
+}
+
+/// Remove log files which violate the [`Options::log_retention`] policy, and record the removed
+/// files in [`Options::prune_stats`].
+async fn prune_logs_once(options: &Options, now: SystemTime) -> eyre::Result<()> {
+    let log_dir = options.log_dir();
+    let files: Vec<LogFile> = files_stream(&log_dir)
+        .await
+        .wrap_err("Error creating files stream in log directory")?
+        .and_then(|path| async move {
+            let metadata = tokio::fs::metadata(&path).await?;
+            Ok(LogFile {
+                path,
+                modified: metadata.modified()?,
+                len: metadata.len(),
+            })
+        })
+        .try_collect()
+        .await
+        .wrap_err("Error reading log file metadata")?;
+
+    for file in select_pruned(files, &options.log_retention, now) {
+        tokio::fs::remove_file(&file.path)
+            .await
+            .wrap_err_with(|| format!("Error removing log file {:?}", file.path))?;
+        tracing::info!("Removed log file {:?} ({})", file.path, ByteSize(file.len));
+        options
+            .prune_stats
+            .files_removed
+            .fetch_add(1, Ordering::Relaxed);
+        options
+            .prune_stats
+            .bytes_reclaimed
+            .fetch_add(file.len, Ordering::Relaxed);
+    }
+    Ok(())
+}
+
+/// Periodically remove log files which violate the [`Options::log_retention`] policy, until a
+/// shutdown message is received on `shutdown_rx`.
+pub async fn prune_logs(
+    options: &'static Options,
+    shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    backoff: BackoffOptions,
+    time: &dyn time::Port,
+) {
+    run_retry_log_errors(
+        move || async move {
+            prune_logs_once(options, time.utc_now().into()).await?;
+            time.async_sleep(PRUNE_INTERVAL).await;
+            Ok(())
+        },
+        shutdown_rx,
+        backoff,
+        time,
+    )
+    .await;
+}
+
 /// Setup panic hooks and [`eyre`] formatting hooks.
 pub fn setup_error_hooks() -> eyre::Result<()> {
     let (eyre_panic_hook, eyre_hook) = color_eyre::config::HookBuilder::new().into_hooks();
@@ -265,7 +520,7 @@ async fn serve_log(
     let mut html = buf.html();
     let mut head = html.head();
     let mut title = head.title();
-    write!(title, "log {}", filename).unwrap();
+    write!(title, "log {}", html::Escaped(&filename)).unwrap();
 
     let mut style = head.style();
     write!(
@@ -314,7 +569,16 @@ async fn files_stream(
     )
 }
 
-async fn serve_logs_index(log_dir: &Path) -> eyre::Result<Html<String>> {
+/// Number of the most recent admin actions displayed on the logs index page.
+const AUDIT_ENTRIES: usize = 20;
+
+async fn serve_logs_index(
+    log_dir: &Path,
+    prune_stats: &PruneStats,
+    supervisor: &Supervisor,
+    analytics: Option<&'static Analytics>,
+    auth: Arc<AdminAuth>,
+) -> eyre::Result<Html<String>> {
     use std::fmt::Write;
     let mut buf = html_builder::Buffer::new();
     let mut html = buf.html();
@@ -338,6 +602,96 @@ async fn serve_logs_index(log_dir: &Path) -> eyre::Result<Html<String>> {
         write!(p, "Log Size: {}", ByteSize(total_size))?;
     }
 
+    {
+        let mut p = body.p();
+        write!(
+            p,
+            "Reclaimed: {} ({} files removed)",
+            ByteSize(prune_stats.bytes_reclaimed()),
+            prune_stats.files_removed()
+        )?;
+    }
+
+    {
+        write!(body.h2(), "Tasks")?;
+        let mut ul = body.ul();
+        for (name, stats) in supervisor.stats() {
+            let mut li = ul.li();
+            write!(li, "{}: {} restarts", html::Escaped(name), stats.restarts)?;
+            if let (Some(last_failure), Some(last_error)) = (stats.last_failure, stats.last_error) {
+                write!(
+                    li,
+                    ", last failure at {}: {}",
+                    last_failure.to_rfc3339(),
+                    ansi_to_html::convert_escaped(&last_error)?
+                )?;
+            }
+        }
+    }
+
+    if let Some(analytics) = analytics {
+        // Querying the database blocks.
+        let stats = tokio::task::spawn_blocking(move || analytics.stats()).await??;
+        write!(body.h2(), "Requests")?;
+        let mut ul = body.ul();
+        write!(ul.li(), "Total: {}", stats.total)?;
+        if let Some(mean_duration_ms) = stats.mean_duration_ms {
+            write!(ul.li(), "Mean processing time: {:.0}ms", mean_duration_ms)?;
+        }
+        for (label, counts) in [
+            ("Outcome", &stats.by_outcome),
+            ("Device", &stats.by_device),
+            ("Format", &stats.by_format),
+        ] {
+            let counts: Vec<String> = counts
+                .iter()
+                .map(|(value, count)| format!("{}: {}", value, count))
+                .collect();
+            write!(ul.li(), "{}: {}", label, html::Escaped(counts.join(", ")))?;
+        }
+    }
+
+    if auth.audit_log().is_some() {
+        let entries = tokio::task::spawn_blocking(move || match auth.audit_log() {
+            Some(audit_log) => audit_log.recent(AUDIT_ENTRIES),
+            None => Ok(Vec::new()),
+        })
+        .await??;
+        write!(body.h2(), "Admin Actions")?;
+        let mut ul = body.ul();
+        for entry in entries {
+            let ip = entry
+                .ip
+                .map_or_else(|| "unknown".to_owned(), |ip| ip.to_string());
+            write!(
+                ul.li(),
+                "{} from {}: {}",
+                entry.timestamp.to_rfc3339(),
+                ip,
+                html::Escaped(entry.action.to_string())
+            )?;
+        }
+    }
+
+    {
+        write!(body.h2(), "Controls")?;
+        for (action, label) in [
+            ("/admin/poll", "Poll inbox now"),
+            ("/admin/flush", "Retry pending replies now"),
+        ] {
+            let action_attr = html::attribute("action", action);
+            let mut form = body.form().attr(r#"method="post""#).attr(&action_attr);
+            let value_attr = html::attribute("value", label);
+            form.input().attr(r#"type="submit""#).attr(&value_attr);
+        }
+    }
+
+    {
+        let mut p = body.p();
+        let mut a = p.a().attr(r#"href="/logs/bundle.tar.gz?options=true""#);
+        write!(a, "Download bundle (all logs and sanitized options)")?;
+    }
+
     {
         let mut ul = body.ul();
         for path in file_paths {
@@ -347,28 +701,155 @@ async fn serve_logs_index(log_dir: &Path) -> eyre::Result<Html<String>> {
                 .ok_or_else(|| eyre::eyre!("Expected path to have a filename"))?
                 .to_str()
                 .ok_or_else(|| eyre::eyre!("Unable to convert filename to utf-8 string"))?;
-            let href_attr = format!(r#"href="/logs/{}""#, filename);
+            let href_attr =
+                html::attribute("href", &format!("/logs/{}", urlencoding::encode(filename)));
             let mut a = li.a().attr(&href_attr);
-            write!(a, "{}", filename)?;
+            write!(a, "{}", html::Escaped(filename))?;
         }
     }
 
     Ok(Html::from(buf.finish()))
 }
 
+/// Query parameters for the log bundle route.
+#[derive(Debug, Deserialize)]
+struct BundleQuery {
+    /// Comma separated list of log file names to include in the bundle. If not specified, all log
+    /// files are included.
+    files: Option<String>,
+    /// Whether to include the sanitized application options in the bundle.
+    #[serde(default)]
+    options: bool,
+}
+
+/// Serve a gzip compressed tar archive containing the log files selected in `query`, for
+/// attaching to bug reports.
+async fn serve_log_bundle(
+    query: BundleQuery,
+    options: &Options,
+) -> axum::response::Result<impl IntoResponse, ServeLogError> {
+    let selected: Option<Vec<String>> = query.files.map(|files| {
+        files
+            .split(',')
+            .map(str::trim)
+            .filter(|file| !file.is_empty())
+            .map(ToOwned::to_owned)
+            .collect()
+    });
+
+    // Only files which are present in the log directory may be included, which prevents
+    // arbitrary files from being read using the query.
+    let file_paths: Vec<PathBuf> = files_stream(&options.log_dir())
+        .await
+        .wrap_err("Error creating files stream in log directory")?
+        .try_filter(|path| {
+            futures::future::ready(match &selected {
+                Some(selected) => path
+                    .file_name()
+                    .and_then(OsStr::to_str)
+                    .map_or(false, |filename| selected.iter().any(|s| s == filename)),
+                None => true,
+            })
+        })
+        .try_collect()
+        .await
+        .wrap_err("Error listing log files")?;
+
+    if file_paths.is_empty() {
+        return Err(ServeLogError::NotFound);
+    }
+
+    let sanitized_options = if query.options {
+        options.sanitized_options.clone()
+    } else {
+        None
+    };
+
+    // The bundle is written to a pipe by a blocking task, and streamed as it is written, so that
+    // the whole bundle is never held in memory.
+    let (reader, writer) = tokio::io::duplex(BUNDLE_PIPE_CAPACITY);
+    let writer = SyncIoBridge::new(writer);
+    tokio::task::spawn_blocking(move || {
+        if let Err(error) = write_bundle(writer, &file_paths, sanitized_options) {
+            tracing::error!("Error creating log bundle: {:?}", error);
+        }
+    });
+
+    Ok((
+        [
+            (header::CONTENT_TYPE, "application/gzip"),
+            (
+                header::CONTENT_DISPOSITION,
+                r#"attachment; filename="email-weather-logs.tar.gz""#,
+            ),
+        ],
+        StreamBody::new(ReaderStream::new(reader)),
+    ))
+}
+
+/// Capacity (in bytes) of the pipe which a log bundle is streamed through.
+const BUNDLE_PIPE_CAPACITY: usize = 64 * 1024;
+
+/// Write a gzip compressed tar archive containing the files at `file_paths`, and
+/// `sanitized_options` (if specified) as `options.json`, to `writer`. Returns the `writer` once
+/// the archive is complete.
+fn write_bundle<W: std::io::Write>(
+    writer: W,
+    file_paths: &[PathBuf],
+    sanitized_options: Option<String>,
+) -> eyre::Result<W> {
+    let encoder = flate2::write::GzEncoder::new(writer, flate2::Compression::default());
+    let mut archive = tar::Builder::new(encoder);
+
+    for path in file_paths {
+        let filename = path
+            .file_name()
+            .ok_or_else(|| eyre::eyre!("Expected path to have a filename"))?;
+        archive
+            .append_path_with_name(path, Path::new("logs").join(filename))
+            .wrap_err_with(|| format!("Error adding {:?} to bundle", path))?;
+    }
+
+    if let Some(sanitized_options) = sanitized_options {
+        let mut header = tar::Header::new_gnu();
+        header.set_size(sanitized_options.len() as u64);
+        header.set_mode(0o644);
+        header.set_mtime(
+            SystemTime::now()
+                .duration_since(SystemTime::UNIX_EPOCH)
+                .map_or(0, |duration| duration.as_secs()),
+        );
+        header.set_cksum();
+        archive
+            .append_data(&mut header, "options.json", sanitized_options.as_bytes())
+            .wrap_err("Error adding options to bundle")?;
+    }
+
+    Ok(archive.into_inner()?.finish()?)
+}
+
 /// Implementation for serving logs.
 ///
-/// + `admin_password_hash` is the `admin` user password hashed using bcrypt.
-pub fn serve_logs(options: &'static Options, admin_password_hash: &'static SecretString) -> Router {
+/// + `auth` is used to require an admin session to access the logs.
+pub fn serve_logs(options: &'static Options, auth: Arc<AdminAuth>) -> Router {
     let log_dir_1 = options.log_dir();
     let log_dir_2 = options.log_dir();
+    let index_auth = auth.clone();
 
     // build our application with a route
     Router::new()
         .route(
             "/",
             get(move || async move {
-                match serve_logs_index(&log_dir_1).await {
+                match serve_logs_index(
+                    &log_dir_1,
+                    &options.prune_stats,
+                    options.supervisor,
+                    options.analytics,
+                    index_auth.clone(),
+                )
+                .await
+                {
                     Ok(html) => axum::response::Result::Ok(html),
                     Err(error) => {
                         tracing::error!("{:?}", error);
@@ -377,6 +858,12 @@ pub fn serve_logs(options: &'static Options, admin_password_hash: &'static Secre
                 }
             }),
         )
+        .route(
+            "/bundle.tar.gz",
+            get(move |Query(query): Query<BundleQuery>| async move {
+                serve_log_bundle(query, options).await
+            }),
+        )
         .route(
             "/:filename",
             get(move |filename| async move { serve_log(filename, &log_dir_2).await }),
@@ -384,8 +871,97 @@ pub fn serve_logs(options: &'static Options, admin_password_hash: &'static Secre
         .layer(
             ServiceBuilder::new()
                 .layer(TraceLayer::new_for_http())
-                .layer(RequireAuthorizationLayer::custom(MyBasicAuth {
-                    admin_password_hash,
-                })),
+                .layer(middleware::from_fn_with_state(auth, require_session)),
         )
 }
+
+#[cfg(test)]
+mod test {
+    use std::time::{Duration, SystemTime};
+
+    use super::{select_pruned, write_bundle, LogFile, LogRetention};
+
+    #[test]
+    fn test_write_bundle() {
+        let dir = std::env::temp_dir().join("email-weather-test-create-bundle");
+        std::fs::create_dir_all(&dir).unwrap();
+        let log_path = dir.join("email-weather.log.2022-01-01");
+        std::fs::write(&log_path, "log contents").unwrap();
+
+        let bundle = write_bundle(Vec::new(), &[log_path], Some("{}".to_string())).unwrap();
+        let mut archive = tar::Archive::new(flate2::read::GzDecoder::new(bundle.as_slice()));
+        let mut paths: Vec<String> = archive
+            .entries()
+            .unwrap()
+            .map(|entry| entry.unwrap().path().unwrap().display().to_string())
+            .collect();
+        paths.sort();
+        assert_eq!(
+            vec![
+                "logs/email-weather.log.2022-01-01".to_string(),
+                "options.json".to_string()
+            ],
+            paths
+        );
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    const DAY: Duration = Duration::from_secs(24 * 60 * 60);
+
+    fn log_file(name: &str, age_days: u32, len: u64, now: SystemTime) -> LogFile {
+        LogFile {
+            path: name.into(),
+            modified: now - DAY * age_days,
+            len,
+        }
+    }
+
+    #[test]
+    fn test_select_pruned_max_age() {
+        let now = SystemTime::now();
+        let files = vec![
+            log_file("old", 40, 10, now),
+            log_file("current", 0, 10, now),
+            log_file("recent", 5, 10, now),
+        ];
+        let retention = LogRetention {
+            max_age_days: Some(30),
+            max_total_size_mb: None,
+        };
+        let pruned = select_pruned(files, &retention, now);
+        assert_eq!(vec![log_file("old", 40, 10, now)], pruned);
+    }
+
+    #[test]
+    fn test_select_pruned_max_total_size() {
+        let now = SystemTime::now();
+        let files = vec![
+            log_file("oldest", 3, 600_000, now),
+            log_file("older", 2, 600_000, now),
+            log_file("current", 0, 600_000, now),
+        ];
+        let retention = LogRetention {
+            max_age_days: None,
+            max_total_size_mb: Some(1),
+        };
+        let pruned = select_pruned(files, &retention, now);
+        assert_eq!(
+            vec![
+                log_file("older", 2, 600_000, now),
+                log_file("oldest", 3, 600_000, now)
+            ],
+            pruned
+        );
+    }
+
+    #[test]
+    fn test_select_pruned_keeps_current() {
+        let now = SystemTime::now();
+        let files = vec![log_file("current", 100, 10_000_000, now)];
+        let retention = LogRetention {
+            max_age_days: Some(1),
+            max_total_size_mb: Some(1),
+        };
+        assert!(select_pruned(files, &retention, now).is_empty());
+    }
+}
@@ -5,7 +5,7 @@ use std::str::FromStr;
 
 use chumsky::{
     prelude::Simple,
-    primitive::{choice, end, just},
+    primitive::{choice, empty, end, filter, just, one_of},
     recovery::skip_until,
     text::{self, TextParser},
     Parser,
@@ -14,10 +14,11 @@ use color_eyre::Help;
 use serde::{Deserialize, Serialize};
 
 use crate::{
-    gis::Position,
-    process::{
-        FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle, ShortFormatDetail,
+    format::{
+        FormatDetail, FormatForecastOptions, FormatMode, LongFormatDetail, LongFormatStyle,
+        ShortFormatDetail, WindUnit,
     },
+    gis::Position,
 };
 
 /// A request for a weather forecast.
@@ -25,18 +26,143 @@ use crate::{
 pub struct ForecastRequest {
     /// Requested forecast position.
     pub position: Option<Position>,
-    /// Options for formatting the output message.
-    pub format: FormatForecastOptions,
+    /// Options for formatting the output message. If not specified, then the default format
+    /// specified in the application options is used.
+    pub format: Option<FormatForecastOptions>,
+    /// Name of the forecast profile (see [`crate::profile::ForecastProfile`]) selected by the
+    /// request. If not specified, then the default profile specified in the application options
+    /// is used.
+    #[serde(default)]
+    pub profile: Option<String>,
+    /// Number of days before the current time to include in the forecast (e.g. to see recent
+    /// precipitation). If not specified, the forecast starts at the current time.
+    #[serde(default)]
+    pub past_days: Option<u8>,
+    /// Whether a GRIB file containing the forecast for the surrounding area is attached to the
+    /// reply (`GRIB`), see [`crate::grib`].
+    #[serde(default)]
+    pub grib: bool,
+    /// Whether an analysis of the thermals and stability for free-flight pilots is included in
+    /// the forecast (`FLY`), see [`crate::flying`].
+    #[serde(default)]
+    pub fly: bool,
+    /// Whether a three day outlook of the freezing level, snowline and new snow is included in
+    /// the forecast (`SNOW`, or `SNOW=2000` for the new snow above 2000m), see
+    /// [`crate::snow_outlook`].
+    #[serde(default)]
+    pub snow_outlook: Option<SnowOutlookRequest>,
+    /// Whether the latest METAR and TAF for an aviation weather station are included in the
+    /// forecast (`METAR` for the nearest station, or e.g. `METAR NZMC`), see
+    /// [`crate::aviation_weather`].
+    #[serde(default)]
+    pub metar: Option<MetarRequest>,
+    /// Whether a summary of the recent precipitation and temperature is included in the forecast
+    /// (`PAST`, or e.g. `PAST=72` for the last 72 hours), see [`crate::past_weather`].
+    #[serde(default)]
+    pub past_weather: Option<PastWeatherRequest>,
+    /// Whether a forecast of the river discharge is included in the forecast (`FLOOD`, or e.g.
+    /// `FLOOD=14` for the next 14 days), see [`crate::flood`].
+    #[serde(default)]
+    pub flood: Option<FloodRequest>,
+    /// Whether a three day outlook of the daily solar energy is included in the forecast
+    /// (`SOLAR`), see [`crate::solar`].
+    #[serde(default)]
+    pub solar: bool,
+}
+
+/// Options for the aviation weather reports included in the forecast, see
+/// [`ForecastRequest::metar`].
+#[derive(Clone, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct MetarRequest {
+    /// ICAO identifier of the station, e.g. `NZMC`. If not specified, the nearest station to the
+    /// forecast position is used.
+    pub station: Option<String>,
+}
+
+/// Options for the summary of the recent weather included in the forecast, see
+/// [`ForecastRequest::past_weather`].
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct PastWeatherRequest {
+    /// Number of hours before the current time that are summarised. If not specified,
+    /// [`crate::past_weather::DEFAULT_PAST_WEATHER_HOURS`] is used.
+    pub hours: Option<u8>,
+}
+
+/// Options for the river discharge forecast included in the forecast, see
+/// [`ForecastRequest::flood`].
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct FloodRequest {
+    /// Number of days forecast. If not specified, [`crate::flood::DEFAULT_FLOOD_DAYS`] is used.
+    pub days: Option<u8>,
+}
+
+/// Options for the snow outlook included in the forecast, see
+/// [`ForecastRequest::snow_outlook`].
+#[derive(Clone, Copy, Default, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub struct SnowOutlookRequest {
+    /// Elevation (metres above sea level) that the new snow is calculated for. If not specified,
+    /// the elevation of the forecast is used.
+    pub elevation: Option<u16>,
 }
 
 impl ForecastRequest {
-    /// Parse request from a string.
+    /// Parse request from a string. Keywords are matched case-insensitively, while values (e.g.
+    /// profile names) keep the case in which they were written.
     pub fn parse(request_string: &str) -> (Self, Vec<Simple<char>>) {
-        let (request, errors) = request_parser().parse_recovery(request_string.to_uppercase());
+        let (request, errors) = request_parser().parse_recovery(request_string);
         (request.unwrap_or_default(), errors)
     }
 }
 
+/// A command which can be sent instead of a [`ForecastRequest`].
+#[derive(Clone, Copy, Debug, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Command {
+    /// `ABOUT` or `INFO`, reply with information about the service.
+    About,
+    /// `FORGET ME`, erase the data stored about the sender, see [`crate::forget`].
+    ForgetMe,
+    /// `CONFIRM <code>`, confirm the sender's address, see [`crate::confirmation`].
+    Confirm(u32),
+    /// `FEED`, subscribe to an Atom feed of the sender's forecasts, see [`crate::feed`].
+    Feed,
+    /// `KEY`, register the PGP public key pasted below the command or attached to the email, see
+    /// [`crate::encryption`].
+    Key,
+    /// `KEY OFF`, remove the sender's PGP public key, see [`crate::encryption`].
+    KeyOff,
+    /// `KEY <code>`, confirm the change to the sender's PGP public key requested using `KEY` or
+    /// `KEY OFF`, see [`crate::encryption`].
+    KeyConfirm(u32),
+}
+
+impl Command {
+    /// Parse a command from the entire `request_string` (case-insensitive), returns `None` if it
+    /// is not a command.
+    #[must_use]
+    pub fn parse(request_string: &str) -> Option<Self> {
+        let words: Vec<String> = request_string
+            .split(|c: char| c.is_whitespace() || c == '-')
+            .filter(|word| !word.is_empty())
+            .map(str::to_uppercase)
+            .collect();
+        match words
+            .iter()
+            .map(String::as_str)
+            .collect::<Vec<&str>>()
+            .as_slice()
+        {
+            ["ABOUT"] | ["INFO"] => Some(Self::About),
+            ["FORGET", "ME"] | ["FORGETME"] => Some(Self::ForgetMe),
+            ["CONFIRM", code] => code.parse().ok().map(Self::Confirm),
+            ["FEED"] => Some(Self::Feed),
+            ["KEY"] => Some(Self::Key),
+            ["KEY", "OFF"] => Some(Self::KeyOff),
+            ["KEY", code] => code.parse().ok().map(Self::KeyConfirm),
+            _ => None,
+        }
+    }
+}
+
 /// A parsed [`ForecastRequest`], with parsing errors stored alongside.
 #[derive(Clone, Default, Debug, Serialize, Deserialize)]
 pub struct ParsedForecastRequest {
@@ -44,11 +170,21 @@ pub struct ParsedForecastRequest {
     pub request: ForecastRequest,
     /// Errors encountered while parsing the request.
     pub errors: Vec<String>,
+    /// The command, if one was sent instead of a forecast request.
+    #[serde(default)]
+    pub command: Option<Command>,
 }
 
 impl ParsedForecastRequest {
     /// Parse request from a string.
     pub fn parse(request_string: &str) -> Self {
+        if let Some(command) = Command::parse(request_string) {
+            return Self {
+                command: Some(command),
+                ..Self::default()
+            };
+        }
+
         let (request, errors) = ForecastRequest::parse(request_string);
         let errors: Vec<String> = errors.iter().map(ToString::to_string).collect();
 
@@ -66,22 +202,56 @@ impl ParsedForecastRequest {
             )
         }
 
-        Self { request, errors }
+        Self {
+            request,
+            errors,
+            command: None,
+        }
     }
 }
 
+/// Matches `keyword` (specified in uppercase) case-insensitively.
+fn keyword(keyword: &'static str) -> impl Parser<char, &'static str, Error = Simple<char>> {
+    keyword
+        .chars()
+        .fold(empty().boxed(), |parser, expected| {
+            parser
+                .then_ignore(one_of([expected, expected.to_ascii_lowercase()]))
+                .boxed()
+        })
+        .to(keyword)
+}
+
 fn request_parser() -> impl Parser<char, ForecastRequest, Error = Simple<char>> {
-    #[derive(Debug)]
+    #[derive(Debug, Clone)]
     enum Expr {
         Position(Position),
         Format(FormatForecastOptions),
+        Profile(String),
+        PastDays(u8),
+        Grib,
+        Fly,
+        SnowOutlook(SnowOutlookRequest),
+        Metar(MetarRequest),
+        PastWeather(PastWeatherRequest),
+        Flood(FloodRequest),
+        Solar,
         Invalid,
     }
 
     fn fold_expr(mut request: ForecastRequest, expr: Expr) -> ForecastRequest {
         match expr {
             Expr::Position(position) => request.position = Some(position),
-            Expr::Format(f) => request.format = f,
+            Expr::Format(f) => request.format = Some(f),
+            Expr::Profile(name) => request.profile = Some(name),
+            Expr::PastDays(days) => request.past_days = Some(days),
+            Expr::Grib => request.grib = true,
+            Expr::Fly => request.fly = true,
+            Expr::SnowOutlook(snow_outlook) => request.snow_outlook = Some(snow_outlook),
+            Expr::Metar(metar) => request.metar = Some(metar),
+            Expr::PastWeather(past_weather) => request.past_weather = Some(past_weather),
+            Expr::Flood(flood) => request.flood = Some(flood),
+            Expr::Solar => request.solar = true,
             Expr::Invalid => {}
         };
         request
@@ -90,14 +260,46 @@ fn request_parser() -> impl Parser<char, ForecastRequest, Error = Simple<char>>
     let pos = position_parser()
         .map(Expr::Position)
         .recover_with(skip_until([' '], |_| Expr::Invalid));
-    let fmt = format_parser()
-        .map(Expr::Format)
-        .recover_with(skip_until([' '], |_| Expr::Invalid));
+    // Format, profile, past days, GRIB, FLY, SNOW, METAR, PAST, FLOOD and SOLAR may be specified
+    // in any order. METAR is attempted before the format, which would otherwise accept its `M`.
+    let option = || {
+        choice((
+            metar_parser().map(Expr::Metar),
+            format_parser().map(Expr::Format),
+            past_days_parser().map(Expr::PastDays),
+            profile_parser().map(Expr::Profile),
+            keyword("GRIB").to(Expr::Grib),
+            keyword("FLY").to(Expr::Fly),
+            snow_outlook_parser().map(Expr::SnowOutlook),
+            past_weather_parser().map(Expr::PastWeather),
+            flood_parser().map(Expr::Flood),
+            keyword("SOLAR").to(Expr::Solar),
+        ))
+        .recover_with(skip_until([' '], |_| Expr::Invalid))
+    };
 
     pos.or_not()
         .map(|expr_option| expr_option.into_iter().collect::<Vec<Expr>>())
         .then_ignore(just(' ').or_not())
-        .chain(fmt.or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
+        .then_ignore(just(' ').or_not())
+        .chain(option().or_not())
         .map(|exprs| (ForecastRequest::default(), exprs))
         .foldl(fold_expr)
         .padded()
@@ -112,10 +314,10 @@ fn request_parser() -> impl Parser<char, ForecastRequest, Error = Simple<char>>
 /// + `LH` - Long with [`LongFormatStyle::Html`] style.
 /// + `LP` - Long with [`LongFormatStyle::PlainText`] style.
 fn long_format_parser() -> impl Parser<char, LongFormatDetail, Error = Simple<char>> {
-    let html_style = just('H').map(|_| LongFormatStyle::Html);
-    let plain_style = just('P').map(|_| LongFormatStyle::PlainText);
+    let html_style = keyword("H").to(LongFormatStyle::Html);
+    let plain_style = keyword("P").to(LongFormatStyle::PlainText);
 
-    just('L')
+    keyword("L")
         .ignore_then(choice((html_style, plain_style)).or_not())
         .map(|style| LongFormatDetail { style })
 }
@@ -130,7 +332,7 @@ fn short_format_parser() -> impl Parser<char, ShortFormatDetail, Error = Simple<
         s.parse::<usize>()
             .map_err(|e| Simple::custom(span, e.to_string()))
     });
-    just('S')
+    keyword("S")
         .ignore_then(length_limit.or_not())
         .map(|limit_option| {
             let mut short = ShortFormatDetail::default();
@@ -146,30 +348,230 @@ fn short_format_parser() -> impl Parser<char, ShortFormatDetail, Error = Simple<
 ///   variations.
 /// + `ML` - [`FormatDetail::Long`] message format. See [`long_format_parser()`] for more
 ///   variations.
+/// + `MSND` - [`FormatMode::Sounding`] with the default format detail, which may be followed by
+///   a format detail, e.g. `MSNDL`.
+/// + `MFB` - [`FormatMode::WindsAloft`] with the default format detail, which may be followed by
+///   a format detail, e.g. `MFBL`.
+/// + `MBF` - Wind speeds using [`WindUnit::Beaufort`], which may be followed by a format detail,
+///   e.g. `MBFL`, and may follow the format mode, e.g. `MSNDBF`.
 fn format_parser() -> impl Parser<char, FormatForecastOptions, Error = Simple<char>> {
     enum Expr {
         FormatDetail(FormatDetail),
+        FormatMode(FormatMode),
+        WindUnit(WindUnit),
     }
 
     fn fold_expr(mut options: FormatForecastOptions, expr: Expr) -> FormatForecastOptions {
         match expr {
             Expr::FormatDetail(detail) => options.detail = detail,
+            Expr::FormatMode(mode) => options.mode = mode,
+            Expr::WindUnit(wind_unit) => options.wind_unit = wind_unit,
         };
         options
     }
 
-    let format_ident = just('M');
+    let format_ident = keyword("M");
 
+    let mode = choice((
+        keyword("SND").to(FormatMode::Sounding),
+        keyword("FB").to(FormatMode::WindsAloft),
+    ));
+    let beaufort = keyword("BF").to(WindUnit::Beaufort);
     let short = short_format_parser().map(FormatDetail::Short);
     let long = long_format_parser().map(FormatDetail::Long);
 
     format_ident
-        .ignore_then(choice((short, long)).map(Expr::FormatDetail).or_not())
-        .map(|exprs| (FormatForecastOptions::default(), exprs))
+        .ignore_then(mode.map(Expr::FormatMode).or_not())
+        .then(beaufort.map(Expr::WindUnit).or_not())
+        .then(choice((short, long)).map(Expr::FormatDetail).or_not())
+        .map(|((mode, wind_unit), detail)| {
+            (
+                FormatForecastOptions::default(),
+                mode.into_iter().chain(wind_unit).chain(detail),
+            )
+        })
         .foldl(fold_expr)
         .labelled("format")
 }
 
+/// Parses a forecast profile selection.
+///
+/// For example:
+/// + `P=alpine` - Select the profile named `alpine` (profile names are matched
+///   case-insensitively by [`crate::options::Options::profile()`]).
+fn profile_parser() -> impl Parser<char, String, Error = Simple<char>> {
+    keyword("P=").ignore_then(text::ident()).labelled("profile")
+}
+
+/// Maximum number of past days that can be requested, limited by the forecast service.
+pub const MAX_PAST_DAYS: u8 = 92;
+
+/// Parses a number of past days to include in the forecast.
+///
+/// For example:
+/// + `PD=2` - Include the previous 2 days.
+fn past_days_parser() -> impl Parser<char, u8, Error = Simple<char>> {
+    keyword("PD=")
+        .ignore_then(text::int(10))
+        .try_map(|s: String, span| {
+            let days = s
+                .parse::<u8>()
+                .map_err(|e| Simple::custom(span.clone(), e.to_string()))?;
+            if days > MAX_PAST_DAYS {
+                return Err(Simple::custom(
+                    span,
+                    format!(
+                        "Invalid past days {}. It needs to be in the range [0, {}]",
+                        days, MAX_PAST_DAYS
+                    ),
+                ));
+            }
+            Ok(days)
+        })
+        .labelled("past days")
+}
+
+/// Maximum elevation (metres) that can be requested for the snow outlook.
+pub const MAX_SNOW_OUTLOOK_ELEVATION: u16 = 9000;
+
+/// Parses a snow outlook request.
+///
+/// For example:
+/// + `SNOW` - Include the snow outlook for the elevation of the forecast.
+/// + `SNOW=2000` - Include the snow outlook for the new snow above 2000m.
+fn snow_outlook_parser() -> impl Parser<char, SnowOutlookRequest, Error = Simple<char>> {
+    keyword("SNOW")
+        .ignore_then(just('=').ignore_then(text::int(10)).or_not())
+        .try_map(|s: Option<String>, span| {
+            s.map(|s| {
+                let elevation = s
+                    .parse::<u16>()
+                    .map_err(|e| Simple::custom(span.clone(), e.to_string()))?;
+                if elevation > MAX_SNOW_OUTLOOK_ELEVATION {
+                    return Err(Simple::custom(
+                        span.clone(),
+                        format!(
+                            "Invalid snow outlook elevation {}. It needs to be in the range \
+                            [0, {}]",
+                            elevation, MAX_SNOW_OUTLOOK_ELEVATION
+                        ),
+                    ));
+                }
+                Ok(elevation)
+            })
+            .transpose()
+        })
+        .map(|elevation| SnowOutlookRequest { elevation })
+        .labelled("snow outlook")
+}
+
+/// Keywords of the request which could otherwise be mistaken for a METAR station identifier.
+const RESERVED_STATIONS: &[&str] = &["GRIB", "PAST", "SNOW"];
+
+/// Parses an aviation weather reports request.
+///
+/// For example:
+/// + `METAR` - Include the reports for the nearest station to the forecast position.
+/// + `METAR NZMC` - Include the reports for the station `NZMC` (station identifiers are
+///   converted to uppercase).
+///
+/// A following keyword (see [`RESERVED_STATIONS`]) or format specification (e.g. `METAR MFBL`) is
+/// not a station.
+fn metar_parser() -> impl Parser<char, MetarRequest, Error = Simple<char>> {
+    let format = format_parser().then_ignore(end());
+    let station = filter(char::is_ascii_alphabetic)
+        .chain(filter(char::is_ascii_alphanumeric).repeated().exactly(3))
+        .collect::<String>()
+        .map(|station| station.to_ascii_uppercase())
+        .then_ignore(
+            filter(|c: &char| c.is_whitespace())
+                .ignored()
+                .or(end())
+                .rewind(),
+        )
+        .try_map(move |station, span| {
+            if RESERVED_STATIONS.contains(&station.as_str())
+                || format.parse(station.as_str()).is_ok()
+            {
+                Err(Simple::custom(
+                    span,
+                    format!("{} is not a station", station),
+                ))
+            } else {
+                Ok(station)
+            }
+        });
+    keyword("METAR")
+        .ignore_then(just(' ').ignore_then(station).or_not())
+        .map(|station| MetarRequest { station })
+        .labelled("metar")
+}
+
+/// Maximum number of hours that can be requested for the summary of the recent weather.
+pub const MAX_PAST_WEATHER_HOURS: u8 = 72;
+
+/// Parses a request for the summary of the recent weather.
+///
+/// For example:
+/// + `PAST` - Include the summary for the default number of hours.
+/// + `PAST=72` - Include the summary for the last 72 hours.
+fn past_weather_parser() -> impl Parser<char, PastWeatherRequest, Error = Simple<char>> {
+    keyword("PAST")
+        .ignore_then(just('=').ignore_then(text::int(10)).or_not())
+        .try_map(|s: Option<String>, span| {
+            s.map(|s| {
+                s.parse::<u8>()
+                    .ok()
+                    .filter(|hours| (1..=MAX_PAST_WEATHER_HOURS).contains(hours))
+                    .ok_or_else(|| {
+                        Simple::custom(
+                            span.clone(),
+                            format!(
+                                "Invalid past weather hours {}. It needs to be in the range \
+                                [1, {}]",
+                                s, MAX_PAST_WEATHER_HOURS
+                            ),
+                        )
+                    })
+            })
+            .transpose()
+        })
+        .map(|hours| PastWeatherRequest { hours })
+        .labelled("past weather")
+}
+
+/// Maximum number of days that can be requested for the river discharge forecast.
+pub const MAX_FLOOD_DAYS: u8 = 30;
+
+/// Parses a request for the river discharge forecast.
+///
+/// For example:
+/// + `FLOOD` - Include the forecast for the default number of days.
+/// + `FLOOD=14` - Include the forecast for the next 14 days.
+fn flood_parser() -> impl Parser<char, FloodRequest, Error = Simple<char>> {
+    keyword("FLOOD")
+        .ignore_then(just('=').ignore_then(text::int(10)).or_not())
+        .try_map(|s: Option<String>, span| {
+            s.map(|s| {
+                s.parse::<u8>()
+                    .ok()
+                    .filter(|days| (1..=MAX_FLOOD_DAYS).contains(days))
+                    .ok_or_else(|| {
+                        Simple::custom(
+                            span.clone(),
+                            format!(
+                                "Invalid flood days {}. It needs to be in the range [1, {}]",
+                                s, MAX_FLOOD_DAYS
+                            ),
+                        )
+                    })
+            })
+            .transpose()
+        })
+        .map(|days| FloodRequest { days })
+        .labelled("flood")
+}
+
 /// Parses 32bit floating point numbers:
 ///
 /// e.g:
@@ -194,7 +596,23 @@ fn f32_parser() -> impl Parser<char, f32, Error = Simple<char>> {
         .labelled("number")
 }
 
+/// Parses a position, specified using decimal degrees (see [`decimal_position_parser()`]), an
+/// Open Location Code (see [`plus_code_parser()`]) or a geohash (see [`geohash_parser()`]).
 fn position_parser() -> impl Parser<char, Position, Error = Simple<char>> {
+    choice((
+        decimal_position_parser(),
+        plus_code_parser(),
+        geohash_parser(),
+    ))
+    .labelled("position")
+}
+
+/// Parses a position specified as a latitude and longitude in decimal degrees.
+///
+/// For example:
+/// + `-43.5,170.3`
+/// + `-43.5, 170.3`
+fn decimal_position_parser() -> impl Parser<char, Position, Error = Simple<char>> {
     f32_parser()
         .try_map(|latitude, span| {
             if latitude > 90.0 || latitude < -90.0 {
@@ -224,7 +642,40 @@ fn position_parser() -> impl Parser<char, Position, Error = Simple<char>> {
             Ok(longitude)
         }))
         .map(|(latitude, longitude)| Position::new(latitude, longitude))
-        .labelled("position")
+}
+
+/// Parses a full [Open Location Code](https://maps.google.com/pluscodes/) (plus code), see
+/// [`Position::from_plus_code()`].
+///
+/// For example:
+/// + `4VCPPR2M+9Q`
+fn plus_code_parser() -> impl Parser<char, Position, Error = Simple<char>> {
+    let code_char = || filter(char::is_ascii_alphanumeric);
+    code_char()
+        .repeated()
+        .exactly(8)
+        .chain(just('+'))
+        .chain::<char, _, _>(code_char().repeated())
+        .collect::<String>()
+        .try_map(|code, span| {
+            Position::from_plus_code(&code).map_err(|error| Simple::custom(span, error.to_string()))
+        })
+        .labelled("plus code")
+}
+
+/// Parses a [geohash](https://en.wikipedia.org/wiki/Geohash), see [`Position::from_geohash()`].
+///
+/// For example:
+/// + `GH=rbsm1hsuv`
+fn geohash_parser() -> impl Parser<char, Position, Error = Simple<char>> {
+    keyword("GH=")
+        .ignore_then(filter(char::is_ascii_alphanumeric).repeated().at_least(1))
+        .collect::<String>()
+        .try_map(|geohash, span| {
+            Position::from_geohash(&geohash)
+                .map_err(|error| Simple::custom(span, error.to_string()))
+        })
+        .labelled("geohash")
 }
 
 /// Convert parsing errors to an eyre formatted error.
@@ -244,8 +695,10 @@ impl FromStr for Position {
             .then_ignore(end())
             .parse(s)
             .map_err(|errors| {
-                errors_to_eyre(errors)
-                    .suggestion("Expected a latitude,longitude in degrees like: `-24.0,45.0`")
+                errors_to_eyre(errors).suggestion(
+                    "Expected a latitude,longitude in degrees like: `-24.0,45.0`, a plus code \
+                        like: `4VCPPR2M+9Q`, or a geohash like: `GH=rbsm1hsuv`",
+                )
             })
     }
 }
@@ -255,12 +708,18 @@ mod test {
     use chumsky::{prelude::Simple, Parser};
 
     use crate::{
+        format::{
+            FormatDetail, FormatForecastOptions, FormatMode, LongFormatDetail, ShortFormatDetail,
+            WindUnit,
+        },
         gis::Position,
-        process::{FormatDetail, FormatForecastOptions, LongFormatDetail, ShortFormatDetail},
-        request::{format_parser, ParsedForecastRequest},
+        request::{format_parser, Command, ParsedForecastRequest},
     };
 
-    use super::{f32_parser, position_parser, ForecastRequest};
+    use super::{
+        f32_parser, position_parser, FloodRequest, ForecastRequest, MetarRequest,
+        PastWeatherRequest, SnowOutlookRequest,
+    };
 
     #[test]
     fn test_parse_f32_positive_no_fraction() {
@@ -302,6 +761,29 @@ mod test {
         assert_eq!(Position::new(53.035, 158.654), p);
     }
 
+    #[test]
+    fn test_parse_position_plus_code_geohash() {
+        let p = position_parser().parse("4VCPPR2M+9Q").unwrap();
+        assert_eq!(Position::from_plus_code("4VCPPR2M+9Q").unwrap(), p);
+        let p = position_parser().parse("gh=rbsm1hsuv").unwrap();
+        assert_eq!(Position::from_geohash("rbsm1hsuv").unwrap(), p);
+        assert!(position_parser().parse("4VCPPR2M+9").is_err());
+        assert!(position_parser().parse("GH=rbsm1hsua").is_err());
+
+        let (request, errors) = ForecastRequest::parse("4vcppr2m+9q ML");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Position::from_plus_code("4VCPPR2M+9Q").ok(),
+            request.position
+        );
+        assert!(request.format.is_some());
+
+        let (request, errors) = ForecastRequest::parse("GH=rbsm1hsuv P=alpine");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Position::from_geohash("rbsm1hsuv").ok(), request.position);
+        assert_eq!(Some("alpine"), request.profile.as_deref());
+    }
+
     #[test]
     fn test_parse_position_out_of_bounds() {
         assert!(position_parser().parse("100.0,40.0").is_err());
@@ -319,7 +801,10 @@ mod test {
         let (request, errors) = ForecastRequest::parse("45,-24 ML");
         assert_eq!(Vec::<Simple<char>>::new(), errors);
         assert_eq!(Some(Position::new(45.0, -24.0)), request.position);
-        assert!(matches!(request.format.detail, FormatDetail::Long(_)));
+        assert!(matches!(
+            request.format.map(|format| format.detail),
+            Some(FormatDetail::Long(_))
+        ));
 
         let parsed = ParsedForecastRequest::parse("-37.8245005,145.3032913");
         assert_eq!(Vec::<String>::new(), parsed.errors);
@@ -329,6 +814,239 @@ mod test {
         );
     }
 
+    #[test]
+    fn test_parse_request_profile() {
+        let (request, errors) = ForecastRequest::parse("45,-24 P=alpine");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(Position::new(45.0, -24.0)), request.position);
+        assert_eq!(Some("alpine"), request.profile.as_deref());
+        assert!(request.format.is_none());
+
+        let (request, errors) = ForecastRequest::parse("45,-24 p=Marine ml");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some("Marine"), request.profile.as_deref());
+        assert!(matches!(
+            request.format.map(|format| format.detail),
+            Some(FormatDetail::Long(_))
+        ));
+
+        let (request, errors) = ForecastRequest::parse("45,-24 ML P=marine");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some("marine"), request.profile.as_deref());
+        assert!(matches!(
+            request.format.map(|format| format.detail),
+            Some(FormatDetail::Long(_))
+        ));
+    }
+
+    #[test]
+    fn test_parse_request_past_days() {
+        let (request, errors) = ForecastRequest::parse("45,-24 PD=2");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(2), request.past_days);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 ML P=alpine PD=1");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(1), request.past_days);
+        assert_eq!(Some("alpine"), request.profile.as_deref());
+        assert!(request.format.is_some());
+
+        let (request, errors) = ForecastRequest::parse("45,-24 PD=100");
+        assert_eq!(1, errors.len());
+        assert!(request.past_days.is_none());
+    }
+
+    #[test]
+    fn test_parse_request_grib() {
+        let (request, errors) = ForecastRequest::parse("45,-24 GRIB");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert!(request.grib);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 GRIB ML P=alpine PD=1");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert!(request.grib);
+        assert_eq!(Some(1), request.past_days);
+        assert_eq!(Some("alpine"), request.profile.as_deref());
+
+        let (request, errors) = ForecastRequest::parse("45,-24 ML");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert!(!request.grib);
+    }
+
+    #[test]
+    fn test_parse_request_fly() {
+        let (request, errors) = ForecastRequest::parse("45,-24 FLY");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert!(request.fly);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 ML P=alpine PD=1 GRIB FLY");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert!(request.fly);
+        assert!(request.grib);
+        assert_eq!(Some(1), request.past_days);
+    }
+
+    #[test]
+    fn test_parse_request_snow_outlook() {
+        let (request, errors) = ForecastRequest::parse("45,-24 SNOW");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(SnowOutlookRequest { elevation: None }),
+            request.snow_outlook
+        );
+
+        let (request, errors) = ForecastRequest::parse("45,-24 ML FLY snow=2000");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(SnowOutlookRequest {
+                elevation: Some(2000)
+            }),
+            request.snow_outlook
+        );
+        assert!(request.fly);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 SNOW=10000");
+        assert_eq!(1, errors.len(), "{:?}", errors);
+        assert!(request.snow_outlook.is_none());
+    }
+
+    #[test]
+    fn test_parse_request_metar() {
+        let (request, errors) = ForecastRequest::parse("45,-24 METAR");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(MetarRequest { station: None }), request.metar);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 metar nzmc ML");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(MetarRequest {
+                station: Some("NZMC".to_owned())
+            }),
+            request.metar
+        );
+        assert!(request.format.is_some());
+
+        let (request, errors) = ForecastRequest::parse("45,-24 METAR GRIB SNOW=2000");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(MetarRequest { station: None }), request.metar);
+        assert!(request.grib);
+        assert!(request.snow_outlook.is_some());
+
+        let (request, errors) = ForecastRequest::parse("45,-24 METAR MFBL");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(MetarRequest { station: None }), request.metar);
+        let format = request.format.unwrap();
+        assert_eq!(FormatMode::WindsAloft, format.mode);
+        assert!(matches!(format.detail, FormatDetail::Long(_)));
+
+        let (request, errors) = ForecastRequest::parse("45,-24 METAR MBFL");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(MetarRequest { station: None }), request.metar);
+        assert_eq!(WindUnit::Beaufort, request.format.unwrap().wind_unit);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 metar ms10");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(MetarRequest { station: None }), request.metar);
+        assert_eq!(
+            FormatDetail::Short(ShortFormatDetail {
+                length_limit: Some(10)
+            }),
+            request.format.unwrap().detail
+        );
+
+        let (request, errors) = ForecastRequest::parse("45,-24 METAR MSLP");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(MetarRequest {
+                station: Some("MSLP".to_owned())
+            }),
+            request.metar
+        );
+        assert!(request.format.is_none());
+    }
+
+    #[test]
+    fn test_parse_request_past_weather() {
+        let (request, errors) = ForecastRequest::parse("45,-24 PAST");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(PastWeatherRequest { hours: None }),
+            request.past_weather
+        );
+
+        let (request, errors) = ForecastRequest::parse("45,-24 ML past=72 METAR");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(PastWeatherRequest { hours: Some(72) }),
+            request.past_weather
+        );
+        assert_eq!(Some(MetarRequest { station: None }), request.metar);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 METAR PAST");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(MetarRequest { station: None }), request.metar);
+        assert!(request.past_weather.is_some());
+
+        let (request, errors) = ForecastRequest::parse("45,-24 PAST=100");
+        assert_eq!(1, errors.len(), "{:?}", errors);
+        assert!(request.past_weather.is_none());
+    }
+
+    #[test]
+    fn test_parse_request_flood() {
+        let (request, errors) = ForecastRequest::parse("45,-24 FLOOD");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(FloodRequest { days: None }), request.flood);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 FLY flood=14 PAST");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(Some(FloodRequest { days: Some(14) }), request.flood);
+        assert!(request.fly);
+        assert!(request.past_weather.is_some());
+
+        let (request, errors) = ForecastRequest::parse("45,-24 FLOOD=0");
+        assert_eq!(1, errors.len(), "{:?}", errors);
+        assert!(request.flood.is_none());
+    }
+
+    #[test]
+    fn test_parse_request_solar() {
+        let (request, errors) = ForecastRequest::parse("45,-24 SNOW solar");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert!(request.solar);
+        assert!(request.snow_outlook.is_some());
+    }
+
+    #[test]
+    fn test_parse_request_case_insensitive_keywords() {
+        let (request, errors) =
+            ForecastRequest::parse("45,-24 msndbfl p=Alpine_Ski pd=1 grib fly Solar");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(FormatForecastOptions {
+                detail: FormatDetail::Long(LongFormatDetail::default()),
+                mode: FormatMode::Sounding,
+                wind_unit: WindUnit::Beaufort,
+                ..FormatForecastOptions::default()
+            }),
+            request.format
+        );
+        assert_eq!(Some("Alpine_Ski"), request.profile.as_deref());
+        assert_eq!(Some(1), request.past_days);
+        assert!(request.grib);
+        assert!(request.fly);
+        assert!(request.solar);
+
+        let (request, errors) = ForecastRequest::parse("45,-24 Metar Nzmc");
+        assert_eq!(Vec::<Simple<char>>::new(), errors);
+        assert_eq!(
+            Some(MetarRequest {
+                station: Some("NZMC".to_owned())
+            }),
+            request.metar
+        );
+    }
+
     #[test]
     fn test_parse_empty_request() {
         let (request, errors) = ForecastRequest::parse("");
@@ -376,7 +1094,10 @@ mod test {
             Some(Position::new(-37.8245005, 145.3032913)),
             request.position
         );
-        assert!(matches!(request.format.detail, FormatDetail::Long(_)));
+        assert!(matches!(
+            request.format.map(|format| format.detail),
+            Some(FormatDetail::Long(_))
+        ));
 
         let (request, errors) = ForecastRequest::parse("-37.8245005,145.3032913 ML LKJDFLSKDJF ");
         assert!(!errors.is_empty());
@@ -384,7 +1105,10 @@ mod test {
             Some(Position::new(-37.8245005, 145.3032913)),
             request.position
         );
-        assert!(matches!(request.format.detail, FormatDetail::Long(_)));
+        assert!(matches!(
+            request.format.map(|format| format.detail),
+            Some(FormatDetail::Long(_))
+        ));
     }
 
     #[test]
@@ -410,7 +1134,7 @@ mod test {
     #[test]
     fn test_parse_format_short_limit_success() {
         let expected_format_options = FormatForecastOptions {
-            detail: FormatDetail::Short(crate::process::ShortFormatDetail {
+            detail: FormatDetail::Short(crate::format::ShortFormatDetail {
                 length_limit: Some(1000),
             }),
             ..FormatForecastOptions::default()
@@ -418,4 +1142,95 @@ mod test {
         let format_options = format_parser().parse("MS1000").unwrap();
         assert_eq!(expected_format_options, format_options);
     }
+
+    #[test]
+    fn test_parse_format_sounding_success() {
+        let expected_format_options = FormatForecastOptions {
+            mode: FormatMode::Sounding,
+            ..FormatForecastOptions::default()
+        };
+        let format_options = format_parser().parse("MSND").unwrap();
+        assert_eq!(expected_format_options, format_options);
+
+        let expected_format_options = FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail::default()),
+            mode: FormatMode::Sounding,
+            ..FormatForecastOptions::default()
+        };
+        let format_options = format_parser().parse("MSNDL").unwrap();
+        assert_eq!(expected_format_options, format_options);
+    }
+
+    #[test]
+    fn test_parse_format_winds_aloft_success() {
+        let expected_format_options = FormatForecastOptions {
+            mode: FormatMode::WindsAloft,
+            ..FormatForecastOptions::default()
+        };
+        let format_options = format_parser().parse("MFB").unwrap();
+        assert_eq!(expected_format_options, format_options);
+
+        let expected_format_options = FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail::default()),
+            mode: FormatMode::WindsAloft,
+            ..FormatForecastOptions::default()
+        };
+        let format_options = format_parser().parse("MFBL").unwrap();
+        assert_eq!(expected_format_options, format_options);
+    }
+
+    #[test]
+    fn test_parse_format_beaufort_success() {
+        let expected_format_options = FormatForecastOptions {
+            wind_unit: WindUnit::Beaufort,
+            ..FormatForecastOptions::default()
+        };
+        let format_options = format_parser().parse("MBF").unwrap();
+        assert_eq!(expected_format_options, format_options);
+
+        let expected_format_options = FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail::default()),
+            mode: FormatMode::Sounding,
+            wind_unit: WindUnit::Beaufort,
+            ..FormatForecastOptions::default()
+        };
+        let format_options = format_parser().parse("MSNDBFL").unwrap();
+        assert_eq!(expected_format_options, format_options);
+    }
+
+    #[test]
+    fn test_parse_command() {
+        for text in ["ABOUT", "about", " Info\n"] {
+            let parsed = ParsedForecastRequest::parse(text);
+            assert_eq!(Some(Command::About), parsed.command, "{text:?}");
+            assert!(parsed.errors.is_empty());
+        }
+        for text in ["FORGET ME", "forget-me", "Forget  me\n"] {
+            let parsed = ParsedForecastRequest::parse(text);
+            assert_eq!(Some(Command::ForgetMe), parsed.command, "{text:?}");
+        }
+        assert_eq!(None, ParsedForecastRequest::parse("-43.5,170.3").command);
+        assert_eq!(None, ParsedForecastRequest::parse("FORGET").command);
+        assert_eq!(
+            Some(Command::Confirm(123456)),
+            ParsedForecastRequest::parse("confirm 123456").command
+        );
+        assert_eq!(None, ParsedForecastRequest::parse("CONFIRM abc").command);
+        assert_eq!(
+            Some(Command::Feed),
+            ParsedForecastRequest::parse(" feed\n").command
+        );
+        assert_eq!(
+            Some(Command::Key),
+            ParsedForecastRequest::parse("Key").command
+        );
+        assert_eq!(
+            Some(Command::KeyOff),
+            ParsedForecastRequest::parse("KEY off").command
+        );
+        assert_eq!(
+            Some(Command::KeyConfirm(123456)),
+            ParsedForecastRequest::parse("KEY 123456").command
+        );
+    }
 }
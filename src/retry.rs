@@ -1,16 +1,78 @@
-use std::{fmt::Display, time::Duration};
+use std::{fmt::Display, future::Future, time::Duration};
+
+use rand::Rng;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
 
 use crate::time;
 
+/// Options for constructing an [`ExponentialBackoff`] using
+/// [`ExponentialBackoff::from_options()`].
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize, JsonSchema)]
+pub struct BackoffOptions {
+    /// The first sleep duration in seconds.
+    ///
+    /// Default is `10.0`.
+    #[serde(default = "default_start_secs")]
+    pub start_secs: f64,
+    /// The maximum sleep duration in seconds.
+    ///
+    /// Default is `600.0`.
+    #[serde(default = "default_max_secs")]
+    pub max_secs: f64,
+    /// Each sleep duration is this many times longer than the previous one.
+    ///
+    /// Default is `e` (`2.718...`).
+    #[serde(default = "default_multiplier")]
+    pub multiplier: f64,
+    /// Whether to apply "full jitter", sleeping for a random duration between zero and the
+    /// calculated sleep duration. This avoids tasks which failed at the same time (e.g. when both
+    /// IMAP and SMTP are unavailable) from retrying in lockstep.
+    ///
+    /// Default is `true`.
+    #[serde(default = "default_jitter")]
+    pub jitter: bool,
+}
+
+fn default_start_secs() -> f64 {
+    10.0
+}
+
+fn default_max_secs() -> f64 {
+    600.0
+}
+
+fn default_multiplier() -> f64 {
+    std::f64::consts::E
+}
+
+fn default_jitter() -> bool {
+    true
+}
+
+impl Default for BackoffOptions {
+    fn default() -> Self {
+        Self {
+            start_secs: default_start_secs(),
+            max_secs: default_max_secs(),
+            multiplier: default_multiplier(),
+            jitter: default_jitter(),
+        }
+    }
+}
+
 /// A utility for performing sleeps which progressively get exponentially longer according to
-/// `start * e^(i)` where `i` is the iteration, incremented each time
-/// [`ExponentialBackoff::sleep()`] is called, and `start` is the starting delay provided in
-/// [`ExponentialBackoff::new()`]. The delay increases until `max` duration is reached, whereupon
-/// subsequent calls to [`ExponentialBackoff::sleep()`] are capped at `max` specified in
-/// [`ExponentialBackoff::new()`].
+/// `start * multiplier^(i)` where `i` is the iteration, incremented each time
+/// [`ExponentialBackoff::sleep()`] is called, `start` is the starting delay, and `multiplier`
+/// defaults to `e`. The delay increases until `max` duration is reached, whereupon subsequent
+/// calls to [`ExponentialBackoff::sleep()`] are capped at `max`. If jitter is enabled (see
+/// [`BackoffOptions::jitter`]), each sleep is for a random duration between zero and the
+/// calculated delay.
 pub struct ExponentialBackoff {
     start: std::time::Duration,
     max: std::time::Duration,
+    multiplier: f64,
+    jitter: bool,
     at_max: bool,
     i: usize,
 }
@@ -25,6 +87,16 @@ pub enum ExponentialBackoffError {
         /// Specified maximum sleep duration.
         max: Duration,
     },
+    /// `multiplier` is less than `1.0` (or is not a number).
+    InvalidMultiplier {
+        /// Specified multiplier.
+        multiplier: f64,
+    },
+    /// A duration in [`BackoffOptions`] is negative, or is not a number.
+    InvalidDuration {
+        /// Specified duration in seconds.
+        secs: f64,
+    },
 }
 
 impl Display for ExponentialBackoffError {
@@ -36,12 +108,20 @@ impl Display for ExponentialBackoffError {
                 humantime::format_duration(*start),
                 humantime::format_duration(*max)
             ),
+            ExponentialBackoffError::InvalidMultiplier { multiplier } => write!(
+                f,
+                "Multiplier ({}) needs to be greater than or equal to 1.0",
+                multiplier
+            ),
+            ExponentialBackoffError::InvalidDuration { secs } => {
+                write!(f, "Invalid duration ({} seconds)", secs)
+            }
         }
     }
 }
 
 impl ExponentialBackoff {
-    /// Construct a new [`ExponentialBackoff`].
+    /// Construct a new [`ExponentialBackoff`], with a multiplier of `e` and no jitter.
     pub fn new(start: Duration, max: Duration) -> Result<Self, ExponentialBackoffError> {
         if start >= max {
             return Err(ExponentialBackoffError::StartNotLessThanMax { start, max });
@@ -49,18 +129,63 @@ impl ExponentialBackoff {
         Ok(Self {
             start,
             max,
+            multiplier: std::f64::consts::E,
+            jitter: false,
             i: 0,
             at_max: false,
         })
     }
 
+    /// Construct a new [`ExponentialBackoff`] from [`BackoffOptions`].
+    pub fn from_options(options: &BackoffOptions) -> Result<Self, ExponentialBackoffError> {
+        let duration = |secs: f64| {
+            if secs.is_finite() && secs >= 0.0 {
+                Ok(Duration::from_secs_f64(secs))
+            } else {
+                Err(ExponentialBackoffError::InvalidDuration { secs })
+            }
+        };
+        if options.multiplier.is_nan() || options.multiplier < 1.0 {
+            return Err(ExponentialBackoffError::InvalidMultiplier {
+                multiplier: options.multiplier,
+            });
+        }
+        let mut backoff = Self::new(duration(options.start_secs)?, duration(options.max_secs)?)?;
+        backoff.multiplier = options.multiplier;
+        backoff.jitter = options.jitter;
+        Ok(backoff)
+    }
+
+    /// The duration of the next sleep before jitter is applied, capped at `max`.
+    fn next_duration(&self) -> Duration {
+        let exp_secs = self.start.as_secs_f64() * self.multiplier.powf(self.i as f64);
+        if exp_secs >= self.max.as_secs_f64() {
+            self.max
+        } else {
+            Duration::from_secs_f64(exp_secs)
+        }
+    }
+
     /// Perform one iteration of sleep, see [`ExponentialBackoff`] for a more detailed description.
     pub async fn sleep(&mut self, t: &dyn time::Port) {
-        let exp_duration =
-            Duration::from_secs_f64(self.start.as_secs_f64() * (self.i as f64).exp());
-        let sleep_duration = Duration::min(exp_duration, self.max);
-        t.async_sleep(sleep_duration).await;
-        self.at_max = sleep_duration == self.max;
+        self.sleep_or(t, std::future::pending::<()>()).await;
+    }
+
+    /// Perform one iteration of sleep like [`ExponentialBackoff::sleep()`], which ends early if
+    /// `wake` completes first (e.g. [`crate::control::Controls::flush_requested()`]). The iteration
+    /// is counted either way.
+    pub async fn sleep_or(&mut self, t: &dyn time::Port, wake: impl Future<Output = ()>) {
+        let duration = self.next_duration();
+        let sleep_duration = if self.jitter {
+            full_jitter(duration, &mut rand::thread_rng())
+        } else {
+            duration
+        };
+        tokio::select! {
+            _ = t.async_sleep(sleep_duration) => {}
+            _ = wake => {}
+        }
+        self.at_max = duration == self.max;
         self.i += 1;
     }
 
@@ -82,15 +207,21 @@ impl ExponentialBackoff {
     }
 }
 
+/// A random duration between zero and `duration`.
+fn full_jitter(duration: Duration, rng: &mut impl Rng) -> Duration {
+    duration.mul_f64(rng.gen_range(0.0..=1.0))
+}
+
 #[cfg(test)]
 mod test {
     use std::time::Duration;
 
     use approx::relative_eq;
+    use rand::SeedableRng;
 
     use crate::time;
 
-    use super::ExponentialBackoff;
+    use super::{full_jitter, BackoffOptions, ExponentialBackoff};
 
     #[tokio::test]
     async fn test_exponential_backoff() {
@@ -122,4 +253,65 @@ mod test {
             t.checkpoint();
         }
     }
+
+    #[tokio::test]
+    async fn test_exponential_backoff_sleep_or() {
+        let mut backoff =
+            ExponentialBackoff::new(Duration::from_secs(3600), Duration::from_secs(7200)).unwrap();
+        let wake = tokio::sync::Notify::new();
+        wake.notify_one();
+        tokio::time::timeout(
+            Duration::from_secs(10),
+            backoff.sleep_or(&time::Gateway, wake.notified()),
+        )
+        .await
+        .expect("Expected sleep to end when woken");
+        assert_eq!(1, backoff.iteration());
+    }
+
+    #[tokio::test]
+    async fn test_exponential_backoff_from_options() {
+        let mut backoff = ExponentialBackoff::from_options(&BackoffOptions {
+            start_secs: 1.0,
+            max_secs: 5.0,
+            multiplier: 2.0,
+            jitter: false,
+        })
+        .unwrap();
+        let mut t = time::MockPort::new();
+
+        for et in [1.0, 2.0, 4.0, 5.0] {
+            t.expect_async_sleep()
+                .withf(move |d| relative_eq!(d.as_secs_f64(), et))
+                .times(1)
+                .returning(|_| {});
+            backoff.sleep(&t).await;
+            t.checkpoint();
+        }
+        assert!(backoff.at_max());
+    }
+
+    #[test]
+    fn test_backoff_options_invalid() {
+        let options = BackoffOptions {
+            multiplier: 0.5,
+            ..BackoffOptions::default()
+        };
+        assert!(ExponentialBackoff::from_options(&options).is_err());
+        let options = BackoffOptions {
+            start_secs: -1.0,
+            ..BackoffOptions::default()
+        };
+        assert!(ExponentialBackoff::from_options(&options).is_err());
+        assert!(ExponentialBackoff::from_options(&BackoffOptions::default()).is_ok());
+    }
+
+    #[test]
+    fn test_full_jitter() {
+        let mut rng = rand::rngs::StdRng::seed_from_u64(0);
+        let duration = Duration::from_secs(10);
+        for _ in 0..100 {
+            assert!(full_jitter(duration, &mut rng) <= duration);
+        }
+    }
 }
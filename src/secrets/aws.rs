@@ -0,0 +1,69 @@
+//! [`Provider`] for [AWS Secrets Manager](https://aws.amazon.com/secrets-manager/).
+
+use async_trait::async_trait;
+use aws_sdk_secretsmanager::types::SdkError;
+use eyre::Context;
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::Provider;
+
+/// Options for [`SecretsManager`].
+#[derive(Debug, Clone, Default, Serialize, Deserialize, JsonSchema)]
+pub struct Options {
+    /// AWS region to use, if not specified then the region is obtained from the environment.
+    #[serde(default)]
+    pub region: Option<String>,
+    /// Prefix applied to secret names, e.g. with a prefix of `email-weather/` the
+    /// `CLIENT_SECRET` secret will be read from `email-weather/CLIENT_SECRET`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+/// Reads secrets from AWS Secrets Manager.
+pub struct SecretsManager {
+    client: aws_sdk_secretsmanager::Client,
+    prefix: Option<String>,
+}
+
+impl SecretsManager {
+    /// Construct a new [`SecretsManager`], loading AWS configuration from the environment.
+    pub async fn new(options: &Options) -> Self {
+        let loader = aws_config::from_env();
+        let loader = if let Some(region) = &options.region {
+            loader.region(aws_sdk_secretsmanager::Region::new(region.clone()))
+        } else {
+            loader
+        };
+        let config = loader.load().await;
+
+        Self {
+            client: aws_sdk_secretsmanager::Client::new(&config),
+            prefix: options.prefix.clone(),
+        }
+    }
+}
+
+#[async_trait]
+impl Provider for SecretsManager {
+    async fn secret(&self, name: &str) -> eyre::Result<Option<String>> {
+        let secret_id = format!("{}{}", self.prefix.as_deref().unwrap_or_default(), name);
+        tracing::debug!("Reading secret {:?} from AWS Secrets Manager", secret_id);
+
+        match self
+            .client
+            .get_secret_value()
+            .secret_id(&secret_id)
+            .send()
+            .await
+        {
+            Ok(output) => Ok(output.secret_string().map(ToString::to_string)),
+            Err(SdkError::ServiceError { err, .. }) if err.is_resource_not_found_exception() => {
+                Ok(None)
+            }
+            Err(error) => Err(error).wrap_err_with(|| {
+                format!("Error reading secret {secret_id:?} from AWS Secrets Manager")
+            }),
+        }
+    }
+}
@@ -0,0 +1,148 @@
+//! [`Provider`] for [GCP Secret Manager](https://cloud.google.com/secret-manager).
+
+use std::time::{Duration, Instant};
+
+use async_trait::async_trait;
+use eyre::Context;
+use reqwest::{Response, StatusCode};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use super::Provider;
+
+/// Url of the metadata server endpoint used to obtain an access token for the default service
+/// account.
+const METADATA_TOKEN_URL: &str =
+    "http://metadata.google.internal/computeMetadata/v1/instance/service-accounts/default/token";
+/// A cached access token is refreshed this long before it expires, so that it doesn't expire
+/// during a request.
+const TOKEN_EXPIRY_MARGIN: Duration = Duration::from_secs(60);
+
+/// Options for [`SecretManager`].
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct Options {
+    /// The GCP project id that the secrets belong to.
+    pub project: String,
+    /// Prefix applied to secret names, e.g. with a prefix of `email-weather-` the
+    /// `CLIENT_SECRET` secret will be read from `email-weather-CLIENT_SECRET`.
+    #[serde(default)]
+    pub prefix: Option<String>,
+}
+
+/// Reads the latest version of secrets from GCP Secret Manager. Authenticates using the default
+/// service account provided by the metadata server, the access token is cached until it expires.
+pub struct SecretManager {
+    options: Options,
+    http_client: reqwest::Client,
+    token: Mutex<Option<CachedToken>>,
+}
+
+#[derive(Deserialize)]
+struct MetadataToken {
+    access_token: String,
+    /// Number of seconds until the token expires.
+    expires_in: u64,
+}
+
+struct CachedToken {
+    access_token: String,
+    /// When the token needs to be refreshed.
+    refresh_at: Instant,
+}
+
+#[derive(Deserialize)]
+struct AccessSecretVersionResponse {
+    payload: SecretPayload,
+}
+
+#[derive(Deserialize)]
+struct SecretPayload {
+    /// Base64 encoded secret data.
+    data: String,
+}
+
+impl SecretManager {
+    /// Construct a new [`SecretManager`].
+    pub fn new(options: Options, http_client: reqwest::Client) -> Self {
+        Self {
+            options,
+            http_client,
+            token: Mutex::new(None),
+        }
+    }
+
+    /// Obtain an access token, using the cached token unless it is about to expire.
+    async fn access_token(&self) -> eyre::Result<String> {
+        let mut cached = self.token.lock().await;
+        if let Some(token) = &*cached {
+            if Instant::now() < token.refresh_at {
+                return Ok(token.access_token.clone());
+            }
+        }
+
+        let requested_at = Instant::now();
+        let token: MetadataToken = self
+            .http_client
+            .get(METADATA_TOKEN_URL)
+            .header("Metadata-Flavor", "Google")
+            .send()
+            .await
+            .and_then(Response::error_for_status)
+            .wrap_err("Error requesting access token from metadata server")?
+            .json()
+            .await
+            .wrap_err("Error deserializing access token from metadata server")?;
+        tracing::debug!(
+            "Obtained access token from metadata server, expires in {}s",
+            token.expires_in
+        );
+        let expires_in = Duration::from_secs(token.expires_in);
+        *cached = Some(CachedToken {
+            access_token: token.access_token.clone(),
+            refresh_at: requested_at + expires_in.saturating_sub(TOKEN_EXPIRY_MARGIN),
+        });
+        Ok(token.access_token)
+    }
+}
+
+#[async_trait]
+impl Provider for SecretManager {
+    async fn secret(&self, name: &str) -> eyre::Result<Option<String>> {
+        let secret_name = format!(
+            "{}{}",
+            self.options.prefix.as_deref().unwrap_or_default(),
+            name
+        );
+        tracing::debug!("Reading secret {:?} from GCP Secret Manager", secret_name);
+
+        let url = format!(
+            "https://secretmanager.googleapis.com/v1/projects/{}/secrets/{}/versions/latest:access",
+            self.options.project, secret_name
+        );
+        let response = self
+            .http_client
+            .get(url)
+            .bearer_auth(self.access_token().await?)
+            .send()
+            .await
+            .wrap_err_with(|| format!("Error requesting secret {secret_name:?}"))?;
+
+        if response.status() == StatusCode::NOT_FOUND {
+            return Ok(None);
+        }
+
+        let response: AccessSecretVersionResponse = response
+            .error_for_status()
+            .wrap_err_with(|| format!("Error requesting secret {secret_name:?}"))?
+            .json()
+            .await
+            .wrap_err_with(|| format!("Error deserializing secret {secret_name:?}"))?;
+
+        let data = base64::decode(response.payload.data)
+            .wrap_err_with(|| format!("Error decoding secret {secret_name:?} payload"))?;
+        String::from_utf8(data)
+            .map(Some)
+            .wrap_err_with(|| format!("Secret {secret_name:?} is not valid utf-8"))
+    }
+}
@@ -0,0 +1,476 @@
+//! Loading of secrets required by this application.
+//!
+//! See [`Secrets`] and [`Backend`].
+
+use std::path::{Path, PathBuf};
+
+use async_trait::async_trait;
+use eyre::Context;
+use schemars::JsonSchema;
+use secrecy::SecretString;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    oauth2::{service_account, ClientSecretDefinition},
+    topo_data_service::ApiKeys,
+};
+
+pub mod aws;
+pub mod gcp;
+
+/// Backend that secrets are loaded from, specified in [`crate::options::Options`].
+///
+/// Regardless of the backend, secrets which are not found will fall back to being read from
+/// files in `secrets_dir`.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub enum Backend {
+    /// Secrets are read from environment variables.
+    Environment,
+    /// Secrets are read from [AWS Secrets Manager](https://aws.amazon.com/secrets-manager/).
+    /// Credentials are obtained from the standard AWS environment (environment variables,
+    /// profile, instance metadata).
+    AwsSecretsManager(aws::Options),
+    /// Secrets are read from [GCP Secret Manager](https://cloud.google.com/secret-manager).
+    /// Credentials are obtained from the GCE/Cloud Run metadata server.
+    GcpSecretManager(gcp::Options),
+}
+
+impl Default for Backend {
+    fn default() -> Self {
+        Self::Environment
+    }
+}
+
+impl Backend {
+    /// Create the [`Provider`] for this backend.
+    pub async fn provider(&self, http_client: reqwest::Client) -> Box<dyn Provider> {
+        match self {
+            Backend::Environment => Box::new(Environment),
+            Backend::AwsSecretsManager(options) => {
+                Box::new(aws::SecretsManager::new(options).await)
+            }
+            Backend::GcpSecretManager(options) => {
+                Box::new(gcp::SecretManager::new(options.clone(), http_client))
+            }
+        }
+    }
+}
+
+/// A source of secrets, as part of the hexagonal architecture.
+#[async_trait]
+pub trait Provider: Send + Sync {
+    /// Obtain the secret with the specified `name` (e.g. `CLIENT_SECRET`). Returns `None` if the
+    /// secret does not exist.
+    async fn secret(&self, name: &str) -> eyre::Result<Option<String>>;
+}
+
+/// [`Provider`] which reads secrets from environment variables.
+pub struct Environment;
+
+#[async_trait]
+impl Provider for Environment {
+    async fn secret(&self, name: &str) -> eyre::Result<Option<String>> {
+        match std::env::var(name) {
+            Ok(value) => Ok(Some(value)),
+            Err(std::env::VarError::NotPresent) => Ok(None),
+            Err(unexpected) => Err(unexpected).wrap_err_with(|| {
+                format!("Error attempting to read {} environment variable", name)
+            }),
+        }
+    }
+}
+
+/// Obtain the optional secret with the specified `name` from `provider`, falling back to the file
+/// in `secrets_dir` named with the lowercase `name` (e.g. `topo_data_api_key`). Returns `None` if
+/// neither exist.
+pub async fn optional_secret(
+    provider: &dyn Provider,
+    secrets_dir: &Path,
+    name: &str,
+) -> eyre::Result<Option<SecretString>> {
+    if let Some(secret) = provider.secret(name).await? {
+        return Ok(Some(SecretString::new(secret)));
+    }
+
+    let path = secrets_dir.join(name.to_lowercase());
+    if !path.is_file() {
+        return Ok(None);
+    }
+    tracing::info!("Reading {} from secret file: {:?}", name, path);
+    let secret = tokio::fs::read_to_string(&path)
+        .await
+        .wrap_err_with(|| format!("Error while reading secret file {:?}", path))?;
+    Ok(Some(SecretString::new(secret.trim_end().to_string())))
+}
+
+/// Secrets used to access email account via IMAP.
+pub struct OauthSecrets {
+    /// The path to the json file used for the OAUTH2 token cache. This file will be updated by
+    /// this application when tokens expire and are refreshed.
+    pub token_cache_path: PathBuf,
+    /// OAUTH2 Installed client secret.
+    pub client_secret: Option<ClientSecretDefinition>,
+    /// Private key used for accessing IMAP via OAUTH2 with a service account.
+    /// **Note**: Currently unused.
+    pub service_account_key: Option<service_account::Key>,
+}
+
+async fn initialize_client_secret(
+    secrets_dir: &Path,
+    provider: &dyn Provider,
+) -> eyre::Result<Option<ClientSecretDefinition>> {
+    Ok(match provider.secret("CLIENT_SECRET").await? {
+        Some(client_secret) => {
+            tracing::debug!("Reading client secret from CLIENT_SECRET secret.");
+            Some(
+                serde_json::from_str::<ClientSecretDefinition>(&client_secret)
+                    .wrap_err("Unable to parse client secret from CLIENT_SECRET secret")?,
+            )
+        }
+        None => {
+            let secret_path = secrets_dir.join("client_secret.json");
+            tracing::debug!("Reading client secret from file {:?}", &secret_path);
+
+            if secret_path.exists() {
+                Some(
+                    {
+                        let client_secret = tokio::fs::read_to_string(&secret_path).await?;
+                        serde_json::from_str::<ClientSecretDefinition>(&client_secret)
+                            .wrap_err("Unable to parse client secret")
+                    }
+                    .wrap_err_with(|| {
+                        format!("Error reading oauth2 secret from file {:?}", secret_path)
+                    })?,
+                )
+            } else {
+                None
+            }
+        }
+    })
+}
+
+/// Initialize the token cache file in `secrets_dir`. The file is only deleted or written if
+/// `modify` is `true`.
+async fn initialize_token_cache(
+    secrets_dir: &Path,
+    provider: &dyn Provider,
+    modify: bool,
+) -> eyre::Result<PathBuf> {
+    let token_cache_path = secrets_dir.join("token_cache.json");
+    if !modify {
+        return Ok(token_cache_path);
+    }
+
+    if std::env::var("DELETE_TOKEN_CACHE").is_ok() && token_cache_path.is_file() {
+        tracing::warn!("Deleting existing token cache file: {:?}", token_cache_path);
+        tokio::fs::remove_file(&token_cache_path)
+            .await
+            .wrap_err_with(|| format!("Error removing token cache file: {:?}", token_cache_path))?;
+    }
+
+    match provider.secret("TOKEN_CACHE").await? {
+        Some(secret) => {
+            tracing::debug!("Reading token cache from TOKEN_CACHE secret.");
+            let write: bool = if token_cache_path.exists() {
+                if let Ok(var) = std::env::var("OVERWRITE_TOKEN_CACHE") {
+                    var == "true"
+                } else {
+                    tracing::debug!(
+                        "Token cache file {:?} already exists, will not overwrite",
+                        token_cache_path
+                    );
+                    false
+                }
+            } else {
+                true
+            };
+
+            if write {
+                if token_cache_path.exists() {
+                    tracing::warn!("Overwriting token cache file {:?}", token_cache_path);
+                } else {
+                    tracing::info!("Writing to new token cache file {:?}", token_cache_path);
+                }
+                tokio::fs::write(&token_cache_path, &secret)
+                    .await
+                    .wrap_err_with(|| {
+                        format!("Error writing token cache file: {:?}", token_cache_path)
+                    })?;
+            }
+        }
+        None => {
+            if token_cache_path.exists() {
+                tracing::debug!(
+                    "Pre-existing token cache file {:?} will be used",
+                    token_cache_path
+                );
+            } else {
+                tracing::debug!(
+                    "Token cache {:?} will be automatically generated with Installed OAUTH2 flow",
+                    token_cache_path
+                );
+            }
+        }
+    }
+    Ok(token_cache_path)
+}
+
+async fn initialize_service_account_key(
+    secrets_dir: &Path,
+    provider: &dyn Provider,
+) -> eyre::Result<Option<service_account::Key>> {
+    Ok(match provider.secret("SERVICE_ACCOUNT_KEY").await? {
+        Some(service_account_key) => {
+            tracing::debug!("Reading service account key from SERVICE_ACCOUNT_KEY secret.");
+            Some(
+                serde_json::from_str::<service_account::Key>(&service_account_key).wrap_err(
+                    "Unable to parse service account key from SERVICE_ACCOUNT_KEY secret",
+                )?,
+            )
+        }
+        None => {
+            let secret_path = secrets_dir.join("service_account_key.json");
+            tracing::debug!("Reading service account key from file {:?}", &secret_path);
+
+            if secret_path.exists() {
+                Some(
+                    {
+                        let service_account_key = tokio::fs::read_to_string(&secret_path).await?;
+                        serde_json::from_str::<service_account::Key>(&service_account_key)
+                            .wrap_err("Unable to parse service account key")
+                    }
+                    .wrap_err_with(|| {
+                        format!("Error reading oauth2 secret from file {:?}", secret_path)
+                    })?,
+                )
+            } else {
+                None
+            }
+        }
+    })
+}
+
+impl OauthSecrets {
+    /// Initializes secrets required for accessing IMAP, using `provider` to look up secrets.
+    ///
+    /// + If `CLIENT_SECRET` secret is set, the contents will be parsed, otherwise it
+    ///   will be read from `client_secret.json` in the specified `secrets_dir` directory.
+    /// + If `TOKEN_CACHE` secret is set, the contents will be written to
+    ///   `token_cache.json` inside the specified `secrets_dir` directory. If the file already
+    ///   exists then the existing file will be used instead. If the environment variable is not
+    ///   set, then the cache will be initialized automatically using the interactive Installed
+    ///   OAUTH2 flow.
+    /// + If `OVERWRITE_TOKEN_CACHE` environment variable is set, and `TOKEN_CACHE` is also set,
+    ///   then the existing token cache file is overwritten with the contents of `TOKEN_CACHE`.
+    /// + If `DELETE_TOKEN_CACHE` environment variable is set, then the existing token cache file
+    ///   is deleted.
+    /// + `secrets_dir` needs to exist and have read/write permissions for this application.
+    pub async fn initialize(secrets_dir: &Path, provider: &dyn Provider) -> eyre::Result<Self> {
+        Self::initialize_impl(secrets_dir, provider, true).await
+    }
+
+    /// Load the secrets in the same way as [`OauthSecrets::initialize()`], but without deleting
+    /// or writing the token cache file, e.g. to validate the configuration.
+    pub async fn load(secrets_dir: &Path, provider: &dyn Provider) -> eyre::Result<Self> {
+        Self::initialize_impl(secrets_dir, provider, false).await
+    }
+
+    async fn initialize_impl(
+        secrets_dir: &Path,
+        provider: &dyn Provider,
+        modify_token_cache: bool,
+    ) -> eyre::Result<Self> {
+        if !secrets_dir.is_dir() {
+            return Err(eyre::eyre!(
+                "secrets_dir {:?} does not exist or is not a directory",
+                secrets_dir
+            ));
+        }
+        let client_secret = initialize_client_secret(secrets_dir, provider)
+            .await
+            .wrap_err("Error initializing client secret")?;
+        let token_cache_path = initialize_token_cache(secrets_dir, provider, modify_token_cache)
+            .await
+            .wrap_err("Error initializing token cache")?;
+        let service_account_key = initialize_service_account_key(secrets_dir, provider)
+            .await
+            .wrap_err("Error initializing service account key")?;
+
+        Ok(Self {
+            token_cache_path,
+            client_secret,
+            service_account_key,
+        })
+    }
+}
+
+/// Secrets necessary for the operation of this application.
+pub struct Secrets {
+    /// Secrets used for accessing the service email account via IMAP.
+    pub oauth_secrets: OauthSecrets,
+    /// `admin` user's password hashed using argon2id (or bcrypt, which is deprecated).
+    pub admin_password_hash: Option<SecretString>,
+    /// API keys for the terrain elevation providers, see [`ApiKeys`].
+    pub topo_data_api_keys: ApiKeys,
+    /// Auth token of the Twilio account used for the SMS transport, see [`crate::twilio`].
+    pub twilio_auth_token: Option<SecretString>,
+    /// Access token of the Matrix account used by the bot, see [`crate::matrix`].
+    pub matrix_access_token: Option<SecretString>,
+    /// Tokens which are permitted to use the api, see [`crate::api::ApiTokens`].
+    pub api_tokens: Option<SecretString>,
+    /// Key used to encrypt queue payloads at rest, see [`crate::queue::QueueCipher`].
+    pub queue_key: Option<SecretString>,
+    /// Key used to hash sender identities, see [`crate::redact::hash_identity()`].
+    pub identity_key: SecretString,
+}
+
+impl Secrets {
+    /// In addition to the secrets loaded by [`ImapSecrets`], there are the following:
+    ///
+    /// + `ADMIN_PASSWORD_HASH`: An `argon2id` (or deprecated `bcrypt`) hash of the administrator
+    ///   password used to access the application logs.
+    /// + API keys for the terrain elevation providers, see [`ApiKeys`].
+    /// + `TWILIO_AUTH_TOKEN`: Auth token of the Twilio account used for the SMS transport.
+    /// + `MATRIX_ACCESS_TOKEN`: Access token of the Matrix account used by the bot.
+    /// + `API_TOKENS`: Comma or newline separated tokens which are permitted to use the api. If
+    ///   provided, requests to the api without one of these tokens are rejected.
+    /// + `QUEUE_KEY`: Base64 encoded 32 byte key used to encrypt queue payloads at rest.
+    /// + `IDENTITY_KEY` (required): Random key (at least 16 bytes) used to hash the identities of
+    ///   senders.
+    ///
+    /// Secrets are looked up using the specified [`Backend`], falling back to files in
+    /// `secrets_dir`.
+    pub async fn initialize(
+        secrets_dir: &Path,
+        backend: &Backend,
+        http_client: reqwest::Client,
+    ) -> eyre::Result<Self> {
+        Self::initialize_impl(secrets_dir, backend, http_client, true).await
+    }
+
+    /// Load the secrets in the same way as [`Secrets::initialize()`], but without any side
+    /// effects on the files in `secrets_dir` (see [`OauthSecrets::load()`]), e.g. to validate the
+    /// configuration with the `check-config` command.
+    pub async fn load(
+        secrets_dir: &Path,
+        backend: &Backend,
+        http_client: reqwest::Client,
+    ) -> eyre::Result<Self> {
+        Self::initialize_impl(secrets_dir, backend, http_client, false).await
+    }
+
+    async fn initialize_impl(
+        secrets_dir: &Path,
+        backend: &Backend,
+        http_client: reqwest::Client,
+        modify_token_cache: bool,
+    ) -> eyre::Result<Self> {
+        let provider = backend.provider(http_client).await;
+        let provider: &dyn Provider = &*provider;
+        let imap_secrets = if modify_token_cache {
+            OauthSecrets::initialize(secrets_dir, provider).await
+        } else {
+            OauthSecrets::load(secrets_dir, provider).await
+        }
+        .wrap_err("Error initializing secrets for IMAP client")?;
+
+        let admin_password_hash = match provider.secret("ADMIN_PASSWORD_HASH").await? {
+            Some(admin_password) => {
+                tracing::info!("Admin password hash was read from ADMIN_PASSWORD_HASH secret");
+                Some(SecretString::new(admin_password))
+            }
+            None => {
+                let admin_password_path = secrets_dir.join("admin_password_hash");
+                if admin_password_path.is_file() {
+                    tracing::info!(
+                        "Reading admin password hash from secret file: {:?}",
+                        admin_password_path
+                    );
+                    let password = tokio::fs::read_to_string(&admin_password_path)
+                        .await
+                        .wrap_err_with(|| {
+                            format!(
+                                "Error while reading admin password secret hash file {:?}",
+                                admin_password_path
+                            )
+                        })?;
+
+                    let stripped_password =
+                        password.strip_suffix('\n').unwrap_or(&password).to_string();
+                    Some(SecretString::new(stripped_password))
+                } else {
+                    tracing::warn!("Admin debug/log interface disabled (because ADMIN_PASSWORD_HASH secret is unavailable)");
+                    None
+                }
+            }
+        };
+
+        let topo_data_api_keys = ApiKeys::initialize(provider, secrets_dir)
+            .await
+            .wrap_err("Error initializing secrets for elevation providers")?;
+
+        let twilio_auth_token = optional_secret(provider, secrets_dir, "TWILIO_AUTH_TOKEN")
+            .await
+            .wrap_err("Error initializing Twilio auth token")?;
+        let matrix_access_token = optional_secret(provider, secrets_dir, "MATRIX_ACCESS_TOKEN")
+            .await
+            .wrap_err("Error initializing Matrix access token")?;
+        let api_tokens = optional_secret(provider, secrets_dir, "API_TOKENS")
+            .await
+            .wrap_err("Error initializing api tokens")?;
+        let queue_key = optional_secret(provider, secrets_dir, "QUEUE_KEY")
+            .await
+            .wrap_err("Error initializing queue key")?;
+        let identity_key = identity_key(provider, secrets_dir).await?;
+
+        Ok(Self {
+            oauth_secrets: imap_secrets,
+            admin_password_hash,
+            topo_data_api_keys,
+            twilio_auth_token,
+            matrix_access_token,
+            api_tokens,
+            queue_key,
+            identity_key,
+        })
+    }
+}
+
+/// Read the required `IDENTITY_KEY` secret, see [`crate::redact::init_identity_key()`].
+pub async fn identity_key(
+    provider: &dyn Provider,
+    secrets_dir: &Path,
+) -> eyre::Result<SecretString> {
+    optional_secret(provider, secrets_dir, "IDENTITY_KEY")
+        .await
+        .wrap_err("Error initializing identity key")?
+        .ok_or_else(|| {
+            eyre::eyre!(
+                "IDENTITY_KEY secret has not been provided, generate one using e.g. \
+                `openssl rand -base64 32`"
+            )
+        })
+}
+
+#[cfg(test)]
+mod test {
+    use super::{Environment, OauthSecrets};
+
+    /// Test that loading the secrets (e.g. for `check-config`) leaves the token cache alone.
+    #[tokio::test]
+    async fn test_load_does_not_modify_token_cache() {
+        let secrets_dir = std::env::temp_dir().join("email-weather-test-secrets-load");
+        let _ = std::fs::remove_dir_all(&secrets_dir);
+        std::fs::create_dir_all(&secrets_dir).unwrap();
+        let token_cache_path = secrets_dir.join("token_cache.json");
+        std::fs::write(&token_cache_path, "[]").unwrap();
+
+        std::env::set_var("DELETE_TOKEN_CACHE", "true");
+        let secrets = OauthSecrets::load(&secrets_dir, &Environment).await;
+        std::env::remove_var("DELETE_TOKEN_CACHE");
+
+        assert_eq!(token_cache_path, secrets.unwrap().token_cache_path);
+        assert_eq!("[]", std::fs::read_to_string(&token_cache_path).unwrap());
+        std::fs::remove_dir_all(&secrets_dir).unwrap();
+    }
+}
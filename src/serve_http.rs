@@ -1,19 +1,75 @@
-use std::net::SocketAddr;
+use std::{net::SocketAddr, path::PathBuf, sync::Arc, time::Duration};
 
-use axum::{http::HeaderValue, response::IntoResponse, Router};
+use axum::{extract::DefaultBodyLimit, middleware, Extension, Router};
+use axum_server::{tls_rustls::RustlsConfig, Handle};
 use eyre::Context;
-use reqwest::StatusCode;
+use futures::StreamExt;
+use rustls_acme::{
+    axum::AxumAcceptor, caches::DirCache, futures_rustls::rustls::ServerConfig, AcmeConfig,
+};
+use schemars::JsonSchema;
 use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
 use tokio::sync::mpsc;
-use tower_http::auth::AuthorizeRequest;
 
-use crate::{oauth2::RedirectParameters, reporting};
+use crate::{
+    admin_api, api,
+    audit::AuditLog,
+    auth::{self, AdminAuth},
+    changes::PreviousForecasts,
+    client_ip::ClientIpSource,
+    confirmation::SenderConfirmations,
+    control::{self, Controls},
+    encryption::PublicKeys,
+    feed::{self, Feeds},
+    forecast_service,
+    latency::{self, Latency},
+    oauth2::RedirectParameters,
+    rate_limit::{rate_limit_by_ip, KeyedRateLimiter, RateLimitOptions},
+    reporting,
+    startup::ReadySender,
+    status::{self, ServiceStatus},
+    time, topo_data_service,
+    twilio::{self, Twilio},
+};
+
+/// Options for serving https directly from this application's http server, instead of relying on a
+/// reverse proxy to provide TLS.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct TlsOptions {
+    /// Path to the PEM encoded certificate chain.
+    pub certificate_path: PathBuf,
+    /// Path to the PEM encoded private key.
+    pub private_key_path: PathBuf,
+}
+
+/// Options for automatically provisioning and renewing a certificate for the hostname of
+/// `base_url` using [ACME](https://datatracker.ietf.org/doc/html/rfc8555) (e.g.
+/// [Let's Encrypt](https://letsencrypt.org/)), using the TLS-ALPN-01 challenge.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct AcmeOptions {
+    /// Contact email addresses provided to the certificate authority, used to notify about
+    /// problems with certificates.
+    #[serde(default)]
+    pub contact: Vec<String>,
+    /// Whether to use the production Let's Encrypt directory. When `false`, the staging directory
+    /// is used, which issues untrusted certificates but has much more generous rate limits.
+    ///
+    /// Default is `false`.
+    #[serde(default)]
+    pub production: bool,
+    /// Directory where the ACME account and certificates are cached.
+    ///
+    /// Default is `None` (`acme` in the data directory).
+    #[serde(default)]
+    pub cache_dir: Option<PathBuf>,
+}
 
 /// Options for running this application's http server.
 pub struct Options {
     /// Options relating to reporting/logging.
     pub reporting: &'static reporting::Options,
-    /// `admin` user's password hash using `bcrypt`. See [`MyBasicAuth`].
+    /// `admin` user's password hash using argon2 or bcrypt. See [`AdminAuth`].
     pub admin_password_hash: Option<&'static SecretString>,
     /// A channel to send authorization codes received.
     pub oauth_redirect_tx: mpsc::Sender<RedirectParameters>,
@@ -21,116 +77,296 @@ pub struct Options {
     pub base_url: url::Url,
     /// Address by the http server for listening.
     pub listen_address: SocketAddr,
+    /// Whether to serve the public forecast api, see [`api::forecast_api()`].
+    pub forecast_api: bool,
+    /// Origins permitted to call the api, see [`api::cors_layer()`].
+    pub api_cors_origins: Vec<String>,
+    /// If specified, requests to the api require one of these tokens, see [`api::ApiTokens`].
+    pub api_tokens: Option<&'static SecretString>,
+    /// Whether to serve the processing latency histograms, see [`latency::metrics_routes()`].
+    pub metrics: bool,
+    /// Histograms of the time spent in each stage of processing a request.
+    pub latency: &'static Latency,
+    /// Service used to obtain forecasts for the forecast api.
+    pub forecast_service: forecast_service::Failover,
+    /// Service used to obtain terrain elevation for the forecast api.
+    pub topo_data_service: topo_data_service::Failover,
+    /// Time used to obtain forecasts for the forecast api.
+    pub time: &'static dyn time::Port,
+    /// If specified, serve https instead of http.
+    pub tls: Option<TlsOptions>,
+    /// If specified, serve https using a certificate obtained automatically with ACME, see
+    /// [`spawn_acme()`].
+    pub acme: Option<AxumAcceptor>,
+    /// Per client IP address rate limit applied to all routes.
+    pub rate_limit: RateLimitOptions,
+    /// Maximum size (in bytes) of request bodies.
+    pub max_body_bytes: usize,
+    /// Where the client IP address used for rate limiting and admin login lockouts is obtained
+    /// from.
+    pub client_ip_source: ClientIpSource,
+    /// Health of the service, displayed on the public status page.
+    pub status: &'static ServiceStatus,
+    /// Sender confirmations erased by the admin api, see [`admin_api::admin_api()`].
+    pub confirmations: Option<&'static SenderConfirmations>,
+    /// If specified, serve the feeds of subscribed senders, see [`feed::feed_routes()`]. Feeds are
+    /// also erased by the admin api.
+    pub feeds: Option<&'static Feeds>,
+    /// Previous forecasts erased by the admin api, see [`admin_api::admin_api()`].
+    pub previous_forecasts: Option<&'static PreviousForecasts>,
+    /// Public keys erased by the admin api, see [`admin_api::admin_api()`].
+    pub public_keys: Option<&'static PublicKeys>,
+    /// Operated by the admin controls, see [`control::control_routes()`].
+    pub controls: &'static Controls,
+    /// Handle shared by every run of the server, see [`spawn_handle_tasks()`].
+    pub handle: Handle,
+    /// If specified, serve the Twilio inbound SMS webhook, see [`twilio::twilio_routes()`].
+    pub twilio: Option<Twilio>,
 }
 
-// TODO: turn this into a generic web server, and provide a channel for transmitting the
-// result of OAUTH2 redirect back to the InstalledFlow.
-/// Run this service's http server.
-#[tracing::instrument(skip(shutdown_rx, options))]
-pub async fn serve_http(mut shutdown_rx: tokio::sync::broadcast::Receiver<()>, options: Options) {
-    tokio::select! {
-        result = shutdown_rx.recv() => {
-            tracing::debug!("Received shutdown broadcast");
-            let result = result.wrap_err("Error receiving shutdown message");
-            if let Err(error) = &result {
-                tracing::error!("{:?}", error);
-            }
+/// Maximum time to wait for in-flight requests to complete after a shutdown message has been
+/// received, before the server is stopped.
+const SHUTDOWN_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Spawn the tasks which drive `handle`, the handle shared by every run of [`serve_http`]: once a
+/// shutdown message is received on `shutdown_rx` the server stops accepting new connections, and
+/// waits up to [`SHUTDOWN_TIMEOUT`] for in-flight requests to complete. `ready` is signalled once
+/// the server is first listening.
+///
+/// These are spawned once, rather than by [`serve_http`], so that they are not duplicated each
+/// time the supervisor restarts the server.
+pub fn spawn_handle_tasks(
+    handle: &Handle,
+    mut shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    ready: ReadySender,
+) {
+    let shutdown_handle = handle.clone();
+    tokio::spawn(async move {
+        let result = shutdown_rx
+            .recv()
+            .await
+            .wrap_err("Error receiving shutdown message");
+        tracing::debug!("Received shutdown broadcast, draining in-flight requests");
+        if let Err(error) = &result {
+            tracing::error!("{:?}", error);
         }
-        _ = serve_http_impl(options) => {}
-    }
-}
+        shutdown_handle.graceful_shutdown(Some(SHUTDOWN_TIMEOUT));
+    });
 
-/// Basic authentication for accessing logs.
-#[derive(Clone, Copy)]
-pub struct MyBasicAuth {
-    /// `admin` user password hash, hashed using bcrypt.
-    pub admin_password_hash: &'static SecretString,
+    let listening_handle = handle.clone();
+    tokio::spawn(async move {
+        // `listening()` returns `None` when the server fails to bind, keep waiting for a restart.
+        loop {
+            if let Some(address) = listening_handle.listening().await {
+                tracing::info!("Listening on {}", address);
+                ready.ready();
+                break;
+            }
+        }
+    });
 }
 
-impl<B> AuthorizeRequest<B> for MyBasicAuth {
-    type ResponseBody = http_body::combinators::UnsyncBoxBody<axum::body::Bytes, axum::Error>;
-
-    fn authorize(
-        &mut self,
-        request: &mut axum::http::Request<B>,
-    ) -> Result<(), axum::http::Response<Self::ResponseBody>> {
-        if check_auth(request, self.admin_password_hash) {
-            Ok(())
-        } else {
-            let unauthorized_response = axum::http::Response::builder()
-                .status(StatusCode::UNAUTHORIZED)
-                .header(
-                    "WWW-Authenticate",
-                    r#"Basic realm="User Visible Realm", charset="UTF-8""#,
-                )
-                .body(axum::body::Body::empty())
-                .unwrap();
+/// Start obtaining a certificate for the hostname of `base_url` using ACME, returning the acceptor
+/// used by [`serve_http`] to serve https with it. The task which drives the provisioning and
+/// renewal of the certificate is spawned once here, so that it is shared by every run of
+/// [`serve_http`].
+pub fn spawn_acme(
+    base_url: &url::Url,
+    acme: &AcmeOptions,
+    cache_dir: PathBuf,
+) -> eyre::Result<AxumAcceptor> {
+    let domain = base_url
+        .host_str()
+        .ok_or_else(|| eyre::eyre!("base_url {} has no hostname", base_url))?
+        .to_string();
+    tracing::info!(
+        "Obtaining certificate for {:?} using ACME (production: {})",
+        domain,
+        acme.production
+    );
+    let mut state = AcmeConfig::new([domain])
+        .contact(acme.contact.iter().map(|email| format!("mailto:{}", email)))
+        .cache_option(Some(DirCache::new(cache_dir)))
+        .directory_lets_encrypt(acme.production)
+        .state();
+    let rustls_config = ServerConfig::builder()
+        .with_safe_defaults()
+        .with_no_client_auth()
+        .with_cert_resolver(state.resolver());
+    let acceptor = state.axum_acceptor(Arc::new(rustls_config));
 
-            Err(unauthorized_response.into_response())
+    tokio::spawn(async move {
+        while let Some(result) = state.next().await {
+            match result {
+                Ok(event) => tracing::info!("ACME event: {:?}", event),
+                Err(error) => tracing::error!("ACME error: {:?}", error),
+            }
         }
-    }
-}
-
-struct BasicCredentials {
-    username: String,
-    password: SecretString,
-}
+    });
 
-fn parse_auth_header_credentials(header: &HeaderValue) -> Option<BasicCredentials> {
-    let header_str: &str = header.to_str().ok()?;
-    let credentials_base64: &str = header_str.split_once("Basic ")?.1;
-    let credentials = String::from_utf8(base64::decode(credentials_base64).ok()?).ok()?;
-    let (username, password) = credentials.split_once(':')?;
-    Some(BasicCredentials {
-        username: username.to_string(),
-        password: SecretString::new(password.to_string()),
-    })
+    Ok(acceptor)
 }
 
-/// Check authorization for a request. Returns `true` if the request is authorized, returns `false` otherwise. Uses Basic http authentication and bcrypt for password hashing.
-fn check_auth<B>(
-    request: &axum::http::Request<B>,
-    admin_password_hash: &'static SecretString,
-) -> bool {
-    let credentials: BasicCredentials =
-        if let Some(auth_header) = request.headers().get("Authorization") {
-            if let Some(credentials) = parse_auth_header_credentials(auth_header) {
-                credentials
-            } else {
-                return false;
-            }
-        } else {
-            return false;
-        };
-
-    let password_match = bcrypt::verify(
-        credentials.password.expose_secret(),
-        admin_password_hash.expose_secret(),
-    )
-    .unwrap_or(false);
-    credentials.username == "admin" && password_match
+// TODO: turn this into a generic web server, and provide a channel for transmitting the
+// result of OAUTH2 redirect back to the InstalledFlow.
+/// Run this service's http server, until [`Options::handle`] is shut down (see
+/// [`spawn_handle_tasks()`]).
+#[tracing::instrument(skip(options))]
+pub async fn serve_http(options: Options) {
+    if let Err(error) = serve_http_impl(options).await {
+        tracing::error!("{:?}", error);
+    }
 }
 
 async fn serve_http_impl(options: Options) -> eyre::Result<()> {
-    let app = Router::new().nest(
-        "/oauth2/",
-        crate::oauth2::redirect_server(options.oauth_redirect_tx),
-    );
+    let handle = options.handle.clone();
+    let app = Router::new()
+        .nest(
+            "/oauth2/",
+            crate::oauth2::redirect_server(options.oauth_redirect_tx),
+        )
+        .nest(
+            "/status",
+            status::serve_status(options.status, options.time),
+        );
 
-    let app = if let Some(admin_password_hash) = &options.admin_password_hash {
+    let app = if let Some(admin_password_hash) = options.admin_password_hash {
         let logs_url = options.base_url.join("logs/")?;
         tracing::info!("Serving logs at {}", logs_url);
+        let audit_log_path = options.reporting.data_dir.join("audit.jsonl");
+        tracing::info!("Recording admin actions in {:?}", audit_log_path);
+        let auth = Arc::new(
+            AdminAuth::new(admin_password_hash, options.base_url.scheme() == "https")
+                .with_audit_log(AuditLog::open(&audit_log_path)?),
+        );
+        let admin_routes = match options.reporting.analytics {
+            Some(analytics) => {
+                tracing::info!("Serving admin api at {}", options.base_url.join("admin/")?);
+                auth::admin_routes(auth.clone()).merge(admin_api::admin_api(
+                    analytics,
+                    options.confirmations,
+                    options.feeds,
+                    options.previous_forecasts,
+                    options.public_keys,
+                    auth.clone(),
+                ))
+            }
+            None => auth::admin_routes(auth.clone()),
+        };
+        let admin_routes =
+            admin_routes.merge(control::control_routes(options.controls, auth.clone()));
+        app.nest("/admin", admin_routes)
+            .nest("/logs/", reporting::serve_logs(options.reporting, auth))
+    } else {
+        tracing::info!("No admin password secret provided, logs will not be served");
+        app
+    };
+
+    let api_routes = if options.forecast_api {
+        let api_url = options.base_url.join("api/forecast")?;
+        tracing::info!("Serving forecast api at {}", api_url);
+        api::decode_api().merge(api::forecast_api(
+            options.time,
+            options.forecast_service,
+            options.topo_data_service,
+        ))
+    } else {
+        api::decode_api()
+    };
+    let api_routes = match options.api_tokens {
+        Some(api_tokens) => {
+            let api_tokens = api::ApiTokens::new(api_tokens.expose_secret());
+            if api_tokens.is_empty() {
+                tracing::warn!("API_TOKENS secret contains no tokens, the api is inaccessible");
+            }
+            api_routes.route_layer(middleware::from_fn_with_state(
+                Arc::new(api_tokens),
+                api::require_api_token,
+            ))
+        }
+        None => api_routes,
+    };
+    let api_routes = if options.api_cors_origins.is_empty() {
+        api_routes
+    } else {
+        tracing::info!(
+            "Permitting cross-origin api requests from {:?}",
+            options.api_cors_origins
+        );
+        api_routes.layer(api::cors_layer(&options.api_cors_origins)?)
+    };
+    let app = app.nest("/api", api_routes);
+
+    let app = if options.metrics {
+        tracing::info!("Serving metrics at {}", options.base_url.join("metrics")?);
         app.nest(
-            "/logs/",
-            reporting::serve_logs(options.reporting, admin_password_hash),
+            "/metrics",
+            latency::metrics_routes(options.latency, options.reporting.supervisor),
         )
     } else {
-        tracing::info!("No admin password secret provided, logs will not be served");
         app
     };
 
-    axum::Server::bind(&options.listen_address)
-        .serve(app.into_make_service())
-        .await
-        .wrap_err("Server error")
+    let app = if let Some(feeds) = options.feeds {
+        tracing::info!("Serving feeds at {}", options.base_url.join("feed/")?);
+        app.nest("/feed", feed::feed_routes(feeds))
+    } else {
+        app
+    };
+
+    let app = if let Some(twilio) = options.twilio {
+        tracing::info!(
+            "Serving Twilio SMS webhook at {}",
+            options.base_url.join("twilio/sms")?
+        );
+        app.nest("/twilio", twilio::twilio_routes(twilio, &options.base_url)?)
+    } else {
+        app
+    };
+
+    let rate_limiter = Arc::new(KeyedRateLimiter::new(options.rate_limit));
+    let app = app
+        .layer(middleware::from_fn_with_state(
+            rate_limiter,
+            rate_limit_by_ip,
+        ))
+        .layer(DefaultBodyLimit::max(options.max_body_bytes))
+        .layer(Extension(options.client_ip_source));
+
+    if options.tls.is_some() && options.acme.is_some() {
+        return Err(eyre::eyre!(
+            "Only one of the tls and acme options may be specified"
+        ));
+    }
+
+    if let Some(acceptor) = &options.acme {
+        tracing::info!("Serving https on {}", options.listen_address);
+        axum_server::bind(options.listen_address)
+            .handle(handle)
+            .acceptor(acceptor.clone())
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .wrap_err("Server error")
+    } else if let Some(tls) = &options.tls {
+        let config = RustlsConfig::from_pem_file(&tls.certificate_path, &tls.private_key_path)
+            .await
+            .wrap_err_with(|| {
+                format!(
+                    "Error loading TLS certificate {:?} and private key {:?}",
+                    tls.certificate_path, tls.private_key_path
+                )
+            })?;
+        tracing::info!("Serving https on {}", options.listen_address);
+        axum_server::bind_rustls(options.listen_address, config)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .wrap_err("Server error")
+    } else {
+        axum_server::bind(options.listen_address)
+            .handle(handle)
+            .serve(app.into_make_service_with_connect_info::<SocketAddr>())
+            .await
+            .wrap_err("Server error")
+    }
 }
@@ -0,0 +1,295 @@
+//! A three day outlook of the freezing level, snowline and new snow for mountaineers, requested
+//! using `SNOW` (or `SNOW=2000` for the new snow above 2000m), see [`build_snow_outlook()`].
+//!
+//! For each (local) day of the outlook:
+//!
+//! + **Freezing level**, the minimum and maximum height of the 0°C level.
+//! + **Snowline**, the lowest height that precipitation is expected to fall as snow, estimated to
+//!   be [`SNOWLINE_BELOW_FREEZING_LEVEL`] below the freezing level during the hours with
+//!   precipitation. `None` if no precipitation is expected.
+//! + **New snow**, the sum of the precipitation during the hours where the elevation is above the
+//!   snowline, converted to snow depth using [`SNOW_CM_PER_MM`].
+
+use chrono::NaiveDate;
+use eyre::Context;
+use open_meteo::{HourlyVariable, TimeZone};
+
+use crate::{
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions},
+    gis::Position,
+    process::{format_table, newline},
+    time,
+};
+
+/// Number of days included in the outlook, starting from the current day.
+const OUTLOOK_DAYS: i64 = 3;
+
+/// Distance (metres) below the freezing level that precipitation typically falls as snow.
+pub const SNOWLINE_BELOW_FREEZING_LEVEL: f32 = 300.0;
+
+/// Centimetres of new snow for each millimetre of precipitation (a 10:1 snow to liquid ratio).
+pub const SNOW_CM_PER_MM: f32 = 1.0;
+
+/// Precipitation (mm) in an hour below which it is considered to be dry.
+const PRECIPITATION_THRESHOLD_MM: f32 = 0.1;
+
+/// The outlook for a single day, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnowOutlookDay {
+    /// The (local) date.
+    pub date: NaiveDate,
+    /// Minimum freezing level (metres above sea level).
+    pub freezing_level_min: f32,
+    /// Maximum freezing level (metres above sea level).
+    pub freezing_level_max: f32,
+    /// Lowest snowline (metres above sea level) while precipitation is expected, `None` if it is
+    /// expected to be dry.
+    pub snowline: Option<f32>,
+    /// New snow (cm) expected above the [`SnowOutlook::elevation`].
+    pub new_snow: f32,
+}
+
+/// Hourly values used to compute the outlook, see [`SnowOutlook::from_hours()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SnowOutlookHour {
+    /// The (local) date of the hour.
+    pub date: NaiveDate,
+    /// Freezing level (metres above sea level).
+    pub freezing_level: f32,
+    /// Precipitation (mm) in the preceding hour.
+    pub precipitation: f32,
+}
+
+/// The outlook for each day, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SnowOutlook {
+    /// Elevation (metres above sea level) that the new snow is calculated for.
+    pub elevation: f32,
+    /// The outlook for each day.
+    pub days: Vec<SnowOutlookDay>,
+}
+
+impl SnowOutlook {
+    /// Compute the outlook for the new snow at `elevation` using the `hours` (in chronological
+    /// order).
+    #[must_use]
+    pub fn from_hours(elevation: f32, hours: &[SnowOutlookHour]) -> Self {
+        let mut days: Vec<SnowOutlookDay> = Vec::new();
+        for hour in hours {
+            let day = match days.last_mut() {
+                Some(day) if day.date == hour.date => day,
+                _ => {
+                    days.push(SnowOutlookDay {
+                        date: hour.date,
+                        freezing_level_min: hour.freezing_level,
+                        freezing_level_max: hour.freezing_level,
+                        snowline: None,
+                        new_snow: 0.0,
+                    });
+                    days.last_mut().expect("day was just pushed")
+                }
+            };
+            day.freezing_level_min = day.freezing_level_min.min(hour.freezing_level);
+            day.freezing_level_max = day.freezing_level_max.max(hour.freezing_level);
+
+            if hour.precipitation >= PRECIPITATION_THRESHOLD_MM {
+                let snowline = (hour.freezing_level - SNOWLINE_BELOW_FREEZING_LEVEL).max(0.0);
+                day.snowline = Some(day.snowline.map_or(snowline, |line| line.min(snowline)));
+                if elevation >= snowline {
+                    day.new_snow += hour.precipitation * SNOW_CM_PER_MM;
+                }
+            }
+        }
+        Self { elevation, days }
+    }
+
+    /// Format the outlook as a block to be appended to the forecast, using the detail in
+    /// `options`.
+    #[must_use]
+    pub fn format(&self, options: &FormatForecastOptions) -> String {
+        let elevation = self.elevation.round();
+        match &options.detail {
+            FormatDetail::Short(_) => {
+                let lines = self.days.iter().map(|day| {
+                    let snowline = day
+                        .snowline
+                        .map(|snowline| format!(" S{:.0}", (snowline / 100.0).round()))
+                        .unwrap_or_default();
+                    format!(
+                        "{} F{:.0}-{:.0}{} N{:.0}",
+                        day.date.format("%d"),
+                        (day.freezing_level_min / 100.0).round(),
+                        (day.freezing_level_max / 100.0).round(),
+                        snowline,
+                        day.new_snow.round()
+                    )
+                });
+                std::iter::once(format!("SNOW>{elevation:.0}"))
+                    .chain(lines)
+                    .collect::<Vec<_>>()
+                    .join(newline(&options.detail))
+            }
+            FormatDetail::Long(long) => {
+                let mut output = format!("Snow outlook above {elevation:.0}m");
+                output.push_str(newline(&options.detail));
+                let columns = vec![
+                    "Date".to_owned(),
+                    "Freezing Level".to_owned(),
+                    "Snowline".to_owned(),
+                    format!("New Snow above {elevation:.0}m"),
+                ];
+                let records = self
+                    .days
+                    .iter()
+                    .map(|day| {
+                        vec![
+                            day.date.to_string(),
+                            format!(
+                                "{:.0}m to {:.0}m",
+                                day.freezing_level_min.round(),
+                                day.freezing_level_max.round()
+                            ),
+                            day.snowline.map_or_else(
+                                || "dry".to_owned(),
+                                |snowline| format!("{:.0}m", snowline.round()),
+                            ),
+                            format!("{:.0}cm", day.new_snow.round()),
+                        ]
+                    })
+                    .collect();
+                output.push_str(&format_table(long.style.as_ref(), columns, records));
+                output
+            }
+        }
+    }
+}
+
+/// Obtain the forecast required for the outlook at `position`, and compute the outlook for the
+/// new snow above `elevation` (metres), or the elevation of the forecast if `None`, see the
+/// [module documentation](self).
+pub async fn build_snow_outlook(
+    time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    position: Position,
+    elevation: Option<f32>,
+) -> eyre::Result<SnowOutlook> {
+    let today = time.utc_now().naive_utc().date();
+    let mut parameters = open_meteo::ForecastParameters::builder()
+        .latitude(position.latitude)
+        .longitude(position.longitude)
+        .timezone(TimeZone::Auto)
+        .build();
+    parameters.hourly = [
+        HourlyVariable::FreezingLevelHeight,
+        HourlyVariable::Precipitation,
+    ]
+    .into_iter()
+    .collect();
+    parameters.start_date = Some(today);
+    parameters.end_date = Some(today + chrono::Duration::days(OUTLOOK_DAYS - 1));
+
+    let forecast = forecast_service
+        .obtain_forecast(&parameters)
+        .await
+        .wrap_err("Error obtaining forecast for snow outlook")?
+        .forecast;
+    let hourly = forecast
+        .hourly
+        .ok_or_else(|| eyre::eyre!("Expected hourly forecast to be present"))?;
+    let freezing_level = hourly
+        .freezing_level_height
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected freezing level height to be present"))?;
+    let precipitation = hourly
+        .precipitation
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected precipitation to be present"))?;
+
+    let hours: Vec<SnowOutlookHour> = hourly
+        .time
+        .iter()
+        .zip(freezing_level)
+        .zip(precipitation)
+        .map(|((time, freezing_level), precipitation)| SnowOutlookHour {
+            date: time.date(),
+            freezing_level: freezing_level.0,
+            precipitation: precipitation.0,
+        })
+        .collect();
+    if hours.is_empty() {
+        return Err(eyre::eyre!(
+            "Forecast is missing the times for snow outlook"
+        ));
+    }
+    Ok(SnowOutlook::from_hours(
+        elevation.unwrap_or(forecast.elevation),
+        &hours,
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use crate::format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle};
+
+    use super::{SnowOutlook, SnowOutlookHour};
+
+    fn outlook() -> SnowOutlook {
+        let hour = |day, freezing_level, precipitation| SnowOutlookHour {
+            date: NaiveDate::from_ymd_opt(2022, 12, day).unwrap(),
+            freezing_level,
+            precipitation,
+        };
+        SnowOutlook::from_hours(
+            2000.0,
+            &[
+                hour(3, 2700.0, 0.0),
+                hour(3, 2200.0, 4.0),
+                hour(3, 1800.0, 6.5),
+                hour(4, 3100.0, 2.0),
+                hour(4, 2500.0, 0.0),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_from_hours() {
+        let outlook = outlook();
+        assert_eq!(2, outlook.days.len());
+
+        let day = &outlook.days[0];
+        assert_eq!(1800.0, day.freezing_level_min);
+        assert_eq!(2700.0, day.freezing_level_max);
+        assert_eq!(Some(1500.0), day.snowline);
+        // Both hours with precipitation are above the snowline at 2000m.
+        assert_eq!(10.5, day.new_snow);
+
+        let day = &outlook.days[1];
+        assert_eq!(2500.0, day.freezing_level_min);
+        assert_eq!(3100.0, day.freezing_level_max);
+        assert_eq!(Some(2800.0), day.snowline);
+        // Rain at 2000m.
+        assert_eq!(0.0, day.new_snow);
+    }
+
+    #[test]
+    fn test_format() {
+        let outlook = outlook();
+        assert_eq!(
+            "SNOW>2000\n03 F18-27 S15 N11\n04 F25-31 S28 N0",
+            outlook.format(&FormatForecastOptions::default())
+        );
+
+        let long = outlook.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(long.contains("Snow outlook above 2000m"), "{long}");
+        assert!(long.contains("1800m to 2700m"), "{long}");
+        assert!(long.contains("11cm"), "{long}");
+    }
+}
@@ -0,0 +1,257 @@
+//! A three day outlook of the solar energy for off-grid users (e.g. sailors and hut wardens) to
+//! plan their battery budgets, requested using `SOLAR`, see [`build_solar_outlook()`].
+//!
+//! For each (local) day of the outlook, the energy (kWh/m²) received by a horizontal surface is
+//! estimated from the sum of the hourly mean irradiance (W/m²) obtained from the forecast service:
+//!
+//! + **Total**, the global horizontal irradiance ([`HourlyVariable::ShortwaveRadiation`]).
+//! + **Direct**, the direct (beam) component ([`HourlyVariable::DirectRadiation`]).
+//! + **Diffuse**, the component scattered by the atmosphere and clouds
+//!   ([`HourlyVariable::DiffuseRadiation`]), which is all that remains on overcast days.
+
+use chrono::NaiveDate;
+use eyre::Context;
+use open_meteo::{HourlyVariable, TimeZone};
+
+use crate::{
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions},
+    gis::Position,
+    process::{format_table, newline},
+    time,
+};
+
+/// Number of days included in the outlook, starting from the current day.
+const OUTLOOK_DAYS: i64 = 3;
+
+/// The outlook for a single day, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolarDay {
+    /// The (local) date.
+    pub date: NaiveDate,
+    /// Total energy (kWh/m²) received by a horizontal surface.
+    pub total: f32,
+    /// Energy (kWh/m²) of the direct radiation.
+    pub direct: f32,
+    /// Energy (kWh/m²) of the diffuse radiation.
+    pub diffuse: f32,
+}
+
+/// Hourly values used to compute the outlook, see [`SolarOutlook::from_hours()`].
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct SolarHour {
+    /// The (local) date of the hour.
+    pub date: NaiveDate,
+    /// Mean global horizontal irradiance (W/m²) of the preceding hour.
+    pub shortwave: f32,
+    /// Mean direct irradiance (W/m²) of the preceding hour.
+    pub direct: f32,
+    /// Mean diffuse irradiance (W/m²) of the preceding hour.
+    pub diffuse: f32,
+}
+
+/// The outlook for each day, see the [module documentation](self).
+#[derive(Debug, Clone, PartialEq)]
+pub struct SolarOutlook {
+    /// The outlook for each day.
+    pub days: Vec<SolarDay>,
+}
+
+impl SolarOutlook {
+    /// Compute the outlook using the `hours` (in chronological order). The mean irradiance
+    /// (W/m²) over an hour is the energy received during that hour (Wh/m²).
+    #[must_use]
+    pub fn from_hours(hours: &[SolarHour]) -> Self {
+        let mut days: Vec<SolarDay> = Vec::new();
+        for hour in hours {
+            let day = match days.last_mut() {
+                Some(day) if day.date == hour.date => day,
+                _ => {
+                    days.push(SolarDay {
+                        date: hour.date,
+                        total: 0.0,
+                        direct: 0.0,
+                        diffuse: 0.0,
+                    });
+                    days.last_mut().expect("day was just pushed")
+                }
+            };
+            day.total += hour.shortwave / 1000.0;
+            day.direct += hour.direct / 1000.0;
+            day.diffuse += hour.diffuse / 1000.0;
+        }
+        Self { days }
+    }
+
+    /// Format the outlook as a block to be appended to the forecast, using the detail in
+    /// `options`.
+    #[must_use]
+    pub fn format(&self, options: &FormatForecastOptions) -> String {
+        match &options.detail {
+            FormatDetail::Short(_) => {
+                let lines = self.days.iter().map(|day| {
+                    format!(
+                        "{} E{:.1} D{:.1}",
+                        day.date.format("%d"),
+                        day.total,
+                        day.diffuse
+                    )
+                });
+                std::iter::once("SOLAR kWh/m2".to_owned())
+                    .chain(lines)
+                    .collect::<Vec<_>>()
+                    .join(newline(&options.detail))
+            }
+            FormatDetail::Long(long) => {
+                let mut output = "Solar energy on a horizontal surface".to_owned();
+                output.push_str(newline(&options.detail));
+                let columns = vec![
+                    "Date".to_owned(),
+                    "Total".to_owned(),
+                    "Direct".to_owned(),
+                    "Diffuse".to_owned(),
+                ];
+                let records = self
+                    .days
+                    .iter()
+                    .map(|day| {
+                        vec![
+                            day.date.to_string(),
+                            format!("{:.1} kWh/m²", day.total),
+                            format!("{:.1} kWh/m²", day.direct),
+                            format!("{:.1} kWh/m²", day.diffuse),
+                        ]
+                    })
+                    .collect();
+                output.push_str(&format_table(long.style.as_ref(), columns, records));
+                output
+            }
+        }
+    }
+}
+
+/// Obtain the forecast irradiance at `position`, and compute the outlook of the daily solar
+/// energy, see the [module documentation](self).
+pub async fn build_solar_outlook(
+    time: &dyn time::Port,
+    forecast_service: &dyn forecast_service::Port,
+    position: Position,
+) -> eyre::Result<SolarOutlook> {
+    let today = time.utc_now().naive_utc().date();
+    let mut parameters = open_meteo::ForecastParameters::builder()
+        .latitude(position.latitude)
+        .longitude(position.longitude)
+        .timezone(TimeZone::Auto)
+        .build();
+    parameters.hourly = [
+        HourlyVariable::ShortwaveRadiation,
+        HourlyVariable::DirectRadiation,
+        HourlyVariable::DiffuseRadiation,
+    ]
+    .into_iter()
+    .collect();
+    parameters.start_date = Some(today);
+    parameters.end_date = Some(today + chrono::Duration::days(OUTLOOK_DAYS - 1));
+
+    let forecast = forecast_service
+        .obtain_forecast(&parameters)
+        .await
+        .wrap_err("Error obtaining forecast for solar outlook")?
+        .forecast;
+    let hourly = forecast
+        .hourly
+        .ok_or_else(|| eyre::eyre!("Expected hourly forecast to be present"))?;
+    let shortwave = hourly
+        .shortwave_radiation
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected shortwave radiation to be present"))?;
+    let direct = hourly
+        .direct_radiation
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected direct radiation to be present"))?;
+    let diffuse = hourly
+        .diffuse_radiation
+        .as_ref()
+        .ok_or_else(|| eyre::eyre!("Expected diffuse radiation to be present"))?;
+
+    let hours: Vec<SolarHour> = hourly
+        .time
+        .iter()
+        .zip(shortwave)
+        .zip(direct)
+        .zip(diffuse)
+        .map(|(((time, shortwave), direct), diffuse)| SolarHour {
+            date: time.date(),
+            shortwave: shortwave.0,
+            direct: direct.0,
+            diffuse: diffuse.0,
+        })
+        .collect();
+    if hours.is_empty() {
+        return Err(eyre::eyre!(
+            "Forecast is missing the times for solar outlook"
+        ));
+    }
+    Ok(SolarOutlook::from_hours(&hours))
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDate;
+
+    use crate::format::{FormatDetail, FormatForecastOptions, LongFormatDetail, LongFormatStyle};
+
+    use super::{SolarHour, SolarOutlook};
+
+    fn outlook() -> SolarOutlook {
+        let hour = |day, direct: f32, diffuse: f32| SolarHour {
+            date: NaiveDate::from_ymd_opt(2022, 12, day).unwrap(),
+            shortwave: direct + diffuse,
+            direct,
+            diffuse,
+        };
+        SolarOutlook::from_hours(&[
+            hour(3, 0.0, 0.0),
+            hour(3, 500.0, 250.0),
+            hour(3, 1000.0, 250.0),
+            hour(4, 0.0, 200.0),
+            hour(4, 0.0, 300.0),
+        ])
+    }
+
+    #[test]
+    fn test_from_hours() {
+        let outlook = outlook();
+        assert_eq!(2, outlook.days.len());
+
+        let day = &outlook.days[0];
+        assert_eq!(2.0, day.total);
+        assert_eq!(1.5, day.direct);
+        assert_eq!(0.5, day.diffuse);
+
+        let day = &outlook.days[1];
+        assert_eq!(0.0, day.direct);
+        assert_eq!(day.total, day.diffuse);
+    }
+
+    #[test]
+    fn test_format() {
+        let outlook = outlook();
+        assert_eq!(
+            "SOLAR kWh/m2\n03 E2.0 D0.5\n04 E0.5 D0.5",
+            outlook.format(&FormatForecastOptions::default())
+        );
+
+        let long = outlook.format(&FormatForecastOptions {
+            detail: FormatDetail::Long(LongFormatDetail {
+                style: Some(LongFormatStyle::PlainText),
+            }),
+            ..FormatForecastOptions::default()
+        });
+        assert!(
+            long.contains("Solar energy on a horizontal surface"),
+            "{long}"
+        );
+        assert!(long.contains("1.5 kWh/m²"), "{long}");
+    }
+}
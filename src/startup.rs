@@ -0,0 +1,103 @@
+//! Ordering of the application's startup, see [`ready_channel()`] and [`validate_queue()`].
+
+use std::{path::Path, sync::Arc};
+
+use eyre::Context;
+use tokio::sync::{broadcast, watch};
+
+/// Signals that a component has become ready, see [`ready_channel()`].
+#[derive(Clone)]
+pub struct ReadySender(Arc<watch::Sender<bool>>);
+
+impl ReadySender {
+    /// Signal that the component is ready.
+    pub fn ready(&self) {
+        self.0.send_replace(true);
+    }
+}
+
+/// Waits for a component to become ready, see [`ready_channel()`].
+#[derive(Clone)]
+pub struct ReadyReceiver(watch::Receiver<bool>);
+
+impl ReadyReceiver {
+    /// Wait until the component is ready.
+    pub async fn wait(&mut self) -> eyre::Result<()> {
+        while !*self.0.borrow() {
+            self.0
+                .changed()
+                .await
+                .wrap_err("Component was dropped before it became ready")?;
+        }
+        Ok(())
+    }
+
+    /// Wait until the component named `name` is ready, returns `false` if a shutdown message was
+    /// received on `shutdown_rx` (or the component was dropped) before it became ready.
+    pub async fn wait_or_shutdown(
+        mut self,
+        name: &str,
+        mut shutdown_rx: broadcast::Receiver<()>,
+    ) -> bool {
+        tracing::debug!("Waiting for {} to be ready", name);
+        tokio::select! {
+            result = self.wait() => match result {
+                Ok(()) => {
+                    tracing::debug!("{} is ready", name);
+                    true
+                }
+                Err(error) => {
+                    tracing::error!("Error waiting for {} to be ready: {:?}", name, error);
+                    false
+                }
+            },
+            _ = shutdown_rx.recv() => false,
+        }
+    }
+}
+
+/// Create a channel used by a component to signal to tasks which depend on it that it is ready.
+#[must_use]
+pub fn ready_channel() -> (ReadySender, ReadyReceiver) {
+    let (tx, rx) = watch::channel(false);
+    (ReadySender(Arc::new(tx)), ReadyReceiver(rx))
+}
+
+/// Validate that the queue at `path` can be opened for sending and receiving (creating it if it
+/// does not exist), before starting the tasks which use it.
+pub fn validate_queue(path: &Path) -> eyre::Result<()> {
+    drop(
+        yaque::Sender::open(path)
+            .wrap_err_with(|| format!("Unable to open queue sender at {:?}", path))?,
+    );
+    drop(
+        yaque::Receiver::open(path)
+            .wrap_err_with(|| format!("Unable to open queue receiver at {:?}", path))?,
+    );
+    Ok(())
+}
+
+#[cfg(test)]
+mod test {
+    use tokio::sync::broadcast;
+
+    use super::ready_channel;
+
+    #[tokio::test]
+    async fn test_ready_channel() {
+        let (ready_tx, mut ready_rx) = ready_channel();
+        let (_shutdown_tx, shutdown_rx) = broadcast::channel::<()>(1);
+        let waiting = tokio::spawn(ready_rx.clone().wait_or_shutdown("test", shutdown_rx));
+        ready_tx.ready();
+        assert!(waiting.await.unwrap());
+        ready_rx.wait().await.unwrap();
+    }
+
+    #[tokio::test]
+    async fn test_ready_channel_shutdown() {
+        let (_ready_tx, ready_rx) = ready_channel();
+        let (shutdown_tx, shutdown_rx) = broadcast::channel::<()>(1);
+        shutdown_tx.send(()).unwrap();
+        assert!(!ready_rx.wait_or_shutdown("test", shutdown_rx).await);
+    }
+}
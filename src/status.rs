@@ -0,0 +1,333 @@
+//! Coarse health of the service, displayed on the public status page, see [`serve_status()`].
+
+use std::sync::atomic::{AtomicI64, Ordering};
+
+use axum::{response::Html, routing::get, Router};
+use chrono::{DateTime, Duration, TimeZone, Utc};
+use html_builder::Html5;
+use reqwest::StatusCode;
+
+use crate::time;
+
+/// If the inbox has not been successfully polled for longer than this, the service is considered
+/// to be degraded.
+const STALE_INBOX_POLL_MINUTES: i64 = 15;
+
+/// Sentinel value stored in the atomic timestamps when an event has not yet occurred.
+const NEVER: i64 = i64::MIN;
+
+/// Timestamps of significant events, recorded by the receive, process and reply tasks so that the
+/// health of the service can be reported by [`serve_status()`].
+#[derive(Debug)]
+pub struct ServiceStatus {
+    started: DateTime<Utc>,
+    last_inbox_poll: AtomicI64,
+    last_forecast_processed: AtomicI64,
+    last_reply_sent: AtomicI64,
+    last_reply_failed: AtomicI64,
+    /// Number of replies queued since the service started which have not yet been sent (or
+    /// discarded).
+    pending_replies: AtomicI64,
+    /// When a reply was last sent (or discarded), or the reply queue became non-empty.
+    last_reply_progress: AtomicI64,
+    /// Number of requests queued since the service started which have not yet been processed.
+    pending_requests: AtomicI64,
+}
+
+/// Coarse health of the service.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Health {
+    /// Emails are being received and replies are being sent.
+    Ok,
+    /// Emails are not being received, or replies are failing to send.
+    Degraded,
+}
+
+impl std::fmt::Display for Health {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            Health::Ok => f.write_str("OK"),
+            Health::Degraded => f.write_str("Degraded"),
+        }
+    }
+}
+
+fn load(timestamp: &AtomicI64) -> Option<DateTime<Utc>> {
+    match timestamp.load(Ordering::Relaxed) {
+        NEVER => None,
+        seconds => Utc.timestamp_opt(seconds, 0).single(),
+    }
+}
+
+impl ServiceStatus {
+    /// Create a new [`ServiceStatus`] for a service started at `now`.
+    #[must_use]
+    pub fn new(now: DateTime<Utc>) -> Self {
+        Self {
+            started: now,
+            last_inbox_poll: AtomicI64::new(NEVER),
+            last_forecast_processed: AtomicI64::new(NEVER),
+            last_reply_sent: AtomicI64::new(NEVER),
+            last_reply_failed: AtomicI64::new(NEVER),
+            pending_replies: AtomicI64::new(0),
+            last_reply_progress: AtomicI64::new(NEVER),
+            pending_requests: AtomicI64::new(0),
+        }
+    }
+
+    /// Record that a reply was removed from the reply queue, after it was sent or discarded.
+    fn record_reply_progress(&self, now: DateTime<Utc>) {
+        // Replies queued before the service started are not counted.
+        let _ =
+            self.pending_replies
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                    Some((pending - 1).max(0))
+                });
+        self.last_reply_progress
+            .store(now.timestamp(), Ordering::Relaxed);
+    }
+
+    /// Record that the inbox was successfully polled for new emails.
+    pub fn record_inbox_poll(&self, now: DateTime<Utc>) {
+        self.last_inbox_poll
+            .store(now.timestamp(), Ordering::Relaxed);
+    }
+
+    /// Record that a forecast request was processed.
+    pub fn record_forecast_processed(&self, now: DateTime<Utc>) {
+        self.last_forecast_processed
+            .store(now.timestamp(), Ordering::Relaxed);
+    }
+
+    /// Record that a request was added to the process queue.
+    pub fn record_request_queued(&self) {
+        self.pending_requests.fetch_add(1, Ordering::Relaxed);
+    }
+
+    /// Record that a request was removed from the process queue after it was processed.
+    pub fn record_request_processed(&self) {
+        // Requests queued before the service started are not counted.
+        let _ =
+            self.pending_requests
+                .fetch_update(Ordering::Relaxed, Ordering::Relaxed, |pending| {
+                    Some((pending - 1).max(0))
+                });
+    }
+
+    /// Record that a reply was added to the reply queue.
+    pub fn record_reply_queued(&self, now: DateTime<Utc>) {
+        if self.pending_replies.fetch_add(1, Ordering::Relaxed) == 0 {
+            self.last_reply_progress
+                .store(now.timestamp(), Ordering::Relaxed);
+        }
+    }
+
+    /// Record that a reply was successfully sent.
+    pub fn record_reply_sent(&self, now: DateTime<Utc>) {
+        self.last_reply_sent
+            .store(now.timestamp(), Ordering::Relaxed);
+        self.record_reply_progress(now);
+    }
+
+    /// Record that a reply was discarded after failing to send.
+    pub fn record_reply_failed(&self, now: DateTime<Utc>) {
+        self.last_reply_failed
+            .store(now.timestamp(), Ordering::Relaxed);
+        self.record_reply_progress(now);
+    }
+
+    /// When the inbox was last successfully polled, or when the service started if it has not yet
+    /// been polled.
+    #[must_use]
+    pub fn last_inbox_poll_or_started(&self) -> DateTime<Utc> {
+        load(&self.last_inbox_poll).unwrap_or(self.started)
+    }
+
+    /// If there are replies waiting in the reply queue, the number of replies and when a reply
+    /// was last sent (or the queue became non-empty).
+    #[must_use]
+    pub fn pending_replies(&self) -> Option<(u64, DateTime<Utc>)> {
+        let pending = u64::try_from(self.pending_replies.load(Ordering::Relaxed)).unwrap_or(0);
+        if pending == 0 {
+            return None;
+        }
+        load(&self.last_reply_progress).map(|progress| (pending, progress))
+    }
+
+    /// Number of requests waiting in the process queue (including the request being processed).
+    #[must_use]
+    pub fn pending_requests(&self) -> u64 {
+        u64::try_from(self.pending_requests.load(Ordering::Relaxed)).unwrap_or(0)
+    }
+
+    /// When a forecast request was last processed.
+    #[must_use]
+    pub fn last_forecast_processed(&self) -> Option<DateTime<Utc>> {
+        load(&self.last_forecast_processed)
+    }
+
+    /// The coarse health of the service at `now`. The service is degraded if the inbox has not
+    /// been polled recently (allowing time for the first poll after starting), or if the most
+    /// recent reply failed to send.
+    #[must_use]
+    pub fn health(&self, now: DateTime<Utc>) -> Health {
+        let stale = Duration::minutes(STALE_INBOX_POLL_MINUTES);
+        if now - self.last_inbox_poll_or_started() > stale {
+            return Health::Degraded;
+        }
+
+        match (load(&self.last_reply_failed), load(&self.last_reply_sent)) {
+            (Some(failed), Some(sent)) if failed >= sent => Health::Degraded,
+            (Some(_), None) => Health::Degraded,
+            _ => Health::Ok,
+        }
+    }
+}
+
+/// Describe how long ago `then` was, relative to `now`, in coarse units.
+fn format_ago(then: DateTime<Utc>, now: DateTime<Utc>) -> String {
+    let minutes = (now - then).num_minutes().max(0);
+    if minutes < 1 {
+        "less than a minute ago".to_string()
+    } else if minutes < 120 {
+        format!("{} minutes ago", minutes)
+    } else if minutes < 48 * 60 {
+        format!("{} hours ago", minutes / 60)
+    } else {
+        format!("{} days ago", minutes / (24 * 60))
+    }
+}
+
+fn status_page(status: &ServiceStatus, now: DateTime<Utc>) -> eyre::Result<Html<String>> {
+    use std::fmt::Write;
+    let mut buf = html_builder::Buffer::new();
+    let mut html = buf.html();
+    write!(html.head().title(), "email-weather status")?;
+    let mut body = html.body();
+
+    write!(body.h1(), "Service: {}", status.health(now))?;
+    {
+        let mut p = body.p();
+        match status.last_forecast_processed() {
+            Some(processed) => write!(p, "Last forecast processed {}", format_ago(processed, now))?,
+            None => write!(p, "No forecasts processed since the service started")?,
+        }
+    }
+    write!(
+        body.p(),
+        "If you did not receive a reply and the service is OK, your request may not have been \
+        understood, or may still be in transit from your device."
+    )?;
+
+    Ok(Html::from(buf.finish()))
+}
+
+/// Routes for the public status page, which does not require authentication:
+///
+/// + `GET /` responds with the coarse health of the service, and how long ago a forecast was last
+///   processed.
+/// + `GET /health` responds with the coarse health of the service as plain text, with the status
+///   code `503 Service Unavailable` if it is degraded, for use by health checks.
+pub fn serve_status(status: &'static ServiceStatus, time: &'static dyn time::Port) -> Router {
+    Router::new()
+        .route(
+            "/",
+            get(move || async move {
+                status_page(status, time.utc_now()).map_err(|error| {
+                    tracing::error!("{:?}", error);
+                    StatusCode::INTERNAL_SERVER_ERROR
+                })
+            }),
+        )
+        .route(
+            "/health",
+            get(move || async move {
+                let health = status.health(time.utc_now());
+                let status_code = match health {
+                    Health::Ok => StatusCode::OK,
+                    Health::Degraded => StatusCode::SERVICE_UNAVAILABLE,
+                };
+                (status_code, health.to_string())
+            }),
+        )
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::time::test_now as now;
+
+    use super::{format_ago, Health, ServiceStatus};
+
+    #[test]
+    fn test_health() {
+        let status = ServiceStatus::new(now());
+        assert_eq!(Health::Ok, status.health(now()));
+        assert_eq!(
+            Health::Degraded,
+            status.health(now() + Duration::minutes(20))
+        );
+
+        status.record_inbox_poll(now() + Duration::minutes(19));
+        assert_eq!(Health::Ok, status.health(now() + Duration::minutes(20)));
+
+        status.record_reply_failed(now() + Duration::minutes(19));
+        assert_eq!(
+            Health::Degraded,
+            status.health(now() + Duration::minutes(20))
+        );
+
+        status.record_reply_sent(now() + Duration::minutes(20));
+        assert_eq!(Health::Ok, status.health(now() + Duration::minutes(20)));
+    }
+
+    #[test]
+    fn test_pending_replies() {
+        let status = ServiceStatus::new(now());
+        assert_eq!(None, status.pending_replies());
+
+        status.record_reply_queued(now());
+        status.record_reply_queued(now() + Duration::minutes(1));
+        assert_eq!(Some((2, now())), status.pending_replies());
+
+        status.record_reply_sent(now() + Duration::minutes(2));
+        assert_eq!(
+            Some((1, now() + Duration::minutes(2))),
+            status.pending_replies()
+        );
+        status.record_reply_failed(now() + Duration::minutes(3));
+        assert_eq!(None, status.pending_replies());
+
+        // Replies queued before the service started are not counted.
+        status.record_reply_sent(now() + Duration::minutes(4));
+        assert_eq!(None, status.pending_replies());
+    }
+
+    #[test]
+    fn test_pending_requests() {
+        let status = ServiceStatus::new(now());
+        assert_eq!(0, status.pending_requests());
+
+        status.record_request_queued();
+        status.record_request_queued();
+        status.record_request_processed();
+        assert_eq!(1, status.pending_requests());
+        status.record_request_processed();
+
+        // Requests queued before the service started are not counted.
+        status.record_request_processed();
+        assert_eq!(0, status.pending_requests());
+    }
+
+    #[test]
+    fn test_format_ago() {
+        assert_eq!("less than a minute ago", format_ago(now(), now()));
+        assert_eq!(
+            "5 minutes ago",
+            format_ago(now(), now() + Duration::minutes(5))
+        );
+        assert_eq!("3 hours ago", format_ago(now(), now() + Duration::hours(3)));
+        assert_eq!("4 days ago", format_ago(now(), now() + Duration::days(4)));
+    }
+}
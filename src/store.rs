@@ -0,0 +1,142 @@
+//! SQLite databases used to store data about senders, see [`Store`]. Senders are identified by the
+//! hash of their address (see [`crate::forget::sender_hash()`]) in a `sender` column, so that
+//! their data can be erased (see [`crate::forget`]).
+
+use std::{path::Path, sync::Mutex};
+
+use eyre::Context;
+use rusqlite::{Connection, OptionalExtension};
+
+/// A SQLite database which can be shared between threads, with its schema created when it is
+/// opened.
+pub struct Store {
+    name: &'static str,
+    connection: Mutex<Connection>,
+}
+
+impl Store {
+    /// Open (or create) the database at `path`, creating the tables in `schema` if they don't
+    /// already exist. `name` describes the database in errors.
+    pub fn open(name: &'static str, path: &Path, schema: &str) -> eyre::Result<Self> {
+        let connection = Connection::open(path)
+            .wrap_err_with(|| format!("Unable to open {name} database {path:?}"))?;
+        Self::from_connection(name, connection, schema)
+    }
+
+    /// Open a database which is stored in memory, and lost when it is dropped.
+    pub fn open_in_memory(name: &'static str, schema: &str) -> eyre::Result<Self> {
+        let connection = Connection::open_in_memory()
+            .wrap_err_with(|| format!("Unable to open in memory {name} database"))?;
+        Self::from_connection(name, connection, schema)
+    }
+
+    /// Create the tables in `schema` in the database of `connection`, if they don't already exist.
+    pub fn from_connection(
+        name: &'static str,
+        connection: Connection,
+        schema: &str,
+    ) -> eyre::Result<Self> {
+        connection
+            .execute_batch(schema)
+            .wrap_err_with(|| format!("Unable to create {name} database schema"))?;
+        Ok(Self {
+            name,
+            connection: Mutex::new(connection),
+        })
+    }
+
+    /// Add the `columns` (name and definition) which are missing from `table` in databases
+    /// created by previous versions.
+    pub fn migrate(self, table: &str, columns: &[(&str, &str)]) -> eyre::Result<Self> {
+        let connection = self.connection();
+        for (column, definition) in columns {
+            let exists = connection
+                .query_row(
+                    "SELECT 1 FROM pragma_table_info(?1) WHERE name = ?2",
+                    [table, column],
+                    |_| Ok(()),
+                )
+                .optional()?
+                .is_some();
+            if !exists {
+                connection
+                    .execute_batch(&format!(
+                        "ALTER TABLE {table} ADD COLUMN {column} {definition}"
+                    ))
+                    .wrap_err_with(|| format!("Unable to migrate {} database", self.name))?;
+            }
+        }
+        drop(connection);
+        Ok(self)
+    }
+
+    /// Lock the connection to the database.
+    ///
+    /// # Panics
+    ///
+    /// Panics if a thread panicked while holding the lock.
+    pub fn connection(&self) -> std::sync::MutexGuard<'_, Connection> {
+        self.connection
+            .lock()
+            .unwrap_or_else(|_| panic!("{} database mutex is poisoned", self.name))
+    }
+
+    /// Erase the rows of `sender` from each of the `tables` in a single transaction, returns the
+    /// number of rows erased.
+    pub fn forget(&self, sender: &str, tables: &[&str]) -> eyre::Result<u64> {
+        let mut connection = self.connection();
+        let transaction = connection.transaction()?;
+        let mut erased = 0;
+        for table in tables {
+            erased += transaction
+                .execute(&format!("DELETE FROM {table} WHERE sender = ?1"), [sender])
+                .wrap_err_with(|| format!("Unable to erase {} {table}", self.name))?;
+        }
+        transaction.commit()?;
+        Ok(erased as u64)
+    }
+
+    /// The tables of the database which have a `sender` column.
+    ///
+    /// # Panics
+    ///
+    /// Panics if the tables can't be queried.
+    #[cfg(test)]
+    pub fn sender_tables(&self) -> Vec<String> {
+        let connection = self.connection();
+        let mut statement = connection
+            .prepare(
+                "SELECT m.name FROM sqlite_master AS m, pragma_table_info(m.name) AS c \
+                WHERE m.type = 'table' AND c.name = 'sender'",
+            )
+            .unwrap();
+        let tables = statement
+            .query_map([], |row| row.get(0))
+            .unwrap()
+            .collect::<rusqlite::Result<Vec<String>>>()
+            .unwrap();
+        tables
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use super::Store;
+
+    #[test]
+    fn test_migrate() {
+        let store = Store::open_in_memory("test", "CREATE TABLE items (id INTEGER PRIMARY KEY);")
+            .unwrap()
+            .migrate("items", &[("sender", "TEXT")])
+            .unwrap()
+            // Existing columns are not added again.
+            .migrate("items", &[("sender", "TEXT")])
+            .unwrap();
+        store
+            .connection()
+            .execute("INSERT INTO items (sender) VALUES ('sha256:aaaa')", [])
+            .unwrap();
+        assert_eq!(vec!["items".to_owned()], store.sender_tables());
+        assert_eq!(1, store.forget("sha256:aaaa", &["items"]).unwrap());
+    }
+}
@@ -0,0 +1,221 @@
+//! Templating of the subject of plain email replies, so that forecasts are easier to find in a
+//! crowded inbox, see [`render()`] and [`crate::options::Options::reply_subject`].
+
+use chrono::{Datelike, NaiveDate, NaiveDateTime};
+use open_meteo::WeatherCode;
+use serde::{Deserialize, Serialize};
+
+use crate::gis::Position;
+
+/// The most significant weather in a forecast, in order of increasing significance.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize, Deserialize)]
+pub enum Weather {
+    /// Clear or partly cloudy.
+    Clear,
+    /// Overcast.
+    Cloudy,
+    /// Fog.
+    Fog,
+    /// Drizzle or rain (including showers).
+    Rain,
+    /// Snow (including showers).
+    Snow,
+    /// Thunderstorms.
+    Storm,
+}
+
+impl Weather {
+    /// The [`Weather`] described by a weather `code`.
+    #[must_use]
+    pub fn from_code(code: WeatherCode) -> Self {
+        match code as u8 {
+            3 => Self::Cloudy,
+            45 | 48 => Self::Fog,
+            51..=67 | 80..=82 => Self::Rain,
+            71..=77 | 85 | 86 => Self::Snow,
+            95..=99 => Self::Storm,
+            _ => Self::Clear,
+        }
+    }
+
+    /// Label used in the subject.
+    #[must_use]
+    pub fn label(self) -> &'static str {
+        match self {
+            Self::Clear => "Clear",
+            Self::Cloudy => "Cloudy",
+            Self::Fog => "Fog",
+            Self::Rain => "Rain",
+            Self::Snow => "Snow",
+            Self::Storm => "Storm",
+        }
+    }
+}
+
+/// Summary of a forecast, used to fill in the placeholders of the subject template.
+#[derive(Debug, Clone, PartialEq)]
+pub struct ForecastSummary {
+    /// Position of the forecast.
+    pub position: Position,
+    /// The first and last (local) dates of the forecast. `None` if the forecast doesn't contain
+    /// rows (e.g. a sounding).
+    pub dates: Option<(NaiveDate, NaiveDate)>,
+    /// The most significant weather in the forecast, and the (local) time it first occurs. `None`
+    /// if the forecast doesn't include weather codes.
+    pub headline: Option<(Weather, NaiveDateTime)>,
+}
+
+impl ForecastSummary {
+    /// Summarise the forecast at `position`, with rows at the (local) `times`, and the `weather`
+    /// codes at those times.
+    #[must_use]
+    pub fn new(
+        position: Position,
+        times: &[NaiveDateTime],
+        weather: impl IntoIterator<Item = (NaiveDateTime, WeatherCode)>,
+    ) -> Self {
+        let dates = times
+            .first()
+            .zip(times.last())
+            .map(|(first, last)| (first.date(), last.date()));
+        // The earliest occurrence of the most significant weather.
+        let headline = weather
+            .into_iter()
+            .map(|(time, code)| (Weather::from_code(code), time))
+            .fold(
+                None,
+                |headline: Option<(Weather, NaiveDateTime)>, current| match headline {
+                    Some(headline) if headline.0 >= current.0 => Some(headline),
+                    _ => Some(current),
+                },
+            );
+        Self {
+            position,
+            dates,
+            headline,
+        }
+    }
+
+    /// The position formatted for the subject, e.g. `-43.51,170.34`.
+    fn position(&self) -> String {
+        format!(
+            "{:.2},{:.2}",
+            self.position.latitude, self.position.longitude
+        )
+    }
+
+    /// The date range formatted for the subject, e.g. `3-5 Dec` or `30 Nov-2 Dec`.
+    fn dates(&self) -> Option<String> {
+        let (first, last) = self.dates?;
+        Some(if first == last {
+            first.format("%-d %b").to_string()
+        } else if (first.year(), first.month()) == (last.year(), last.month()) {
+            format!("{}-{}", first.format("%-d"), last.format("%-d %b"))
+        } else {
+            format!("{}-{}", first.format("%-d %b"), last.format("%-d %b"))
+        })
+    }
+
+    /// The headline formatted for the subject, e.g. `Snow Sat`. The day is omitted for
+    /// unremarkable weather.
+    fn summary(&self) -> Option<String> {
+        let (weather, time) = self.headline?;
+        Some(if weather > Weather::Cloudy {
+            format!("{} {}", weather.label(), time.format("%a"))
+        } else {
+            weather.label().to_owned()
+        })
+    }
+}
+
+/// Render the subject `template`, replacing the placeholders:
+///
+/// + `{subject}`: the subject of the received email.
+/// + `{position}`: the position of the forecast, e.g. `-43.51,170.34`.
+/// + `{dates}`: the dates covered by the forecast, e.g. `3-5 Dec`.
+/// + `{summary}`: the most significant weather and the day it occurs, e.g. `Snow Sat`.
+///
+/// Placeholders which are unavailable are removed. Returns `None` if the rendered subject is
+/// empty, in which case the default subject should be used.
+#[must_use]
+pub fn render(
+    template: &str,
+    subject: Option<&str>,
+    summary: Option<&ForecastSummary>,
+) -> Option<String> {
+    let values = [
+        ("{subject}", subject.map(ToOwned::to_owned)),
+        ("{position}", summary.map(ForecastSummary::position)),
+        ("{dates}", summary.and_then(ForecastSummary::dates)),
+        ("{summary}", summary.and_then(ForecastSummary::summary)),
+    ];
+    let rendered = values
+        .iter()
+        .fold(template.to_owned(), |rendered, (placeholder, value)| {
+            rendered.replace(placeholder, value.as_deref().unwrap_or_default())
+        });
+    // Tidy the whitespace left by placeholders which were removed.
+    let rendered = rendered.split_whitespace().collect::<Vec<_>>().join(" ");
+    (!rendered.is_empty()).then_some(rendered)
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::NaiveDateTime;
+    use open_meteo::WeatherCode;
+
+    use crate::gis::Position;
+
+    use super::{render, ForecastSummary, Weather};
+
+    fn time(time: &str) -> NaiveDateTime {
+        NaiveDateTime::parse_from_str(time, "%Y-%m-%dT%H:%M").unwrap()
+    }
+
+    fn summary() -> ForecastSummary {
+        let times = [
+            time("2022-12-02T21:00"),
+            time("2022-12-03T03:00"),
+            time("2022-12-04T09:00"),
+        ];
+        let codes = [
+            WeatherCode::SnowSlight,
+            WeatherCode::Overcast,
+            WeatherCode::SnowHeavy,
+        ];
+        ForecastSummary::new(
+            Position::new(-43.513832, 170.33975),
+            &times,
+            times.iter().copied().zip(codes),
+        )
+    }
+
+    #[test]
+    fn test_summary() {
+        let summary = summary();
+        assert_eq!(
+            Some((Weather::Snow, time("2022-12-02T21:00"))),
+            summary.headline
+        );
+        assert_eq!(Some("2-4 Dec".to_owned()), summary.dates());
+        assert_eq!(Some("Snow Fri".to_owned()), summary.summary());
+        assert_eq!("-43.51,170.34", summary.position());
+    }
+
+    #[test]
+    fn test_render() {
+        assert_eq!(
+            Some("Snow Fri 2-4 Dec -43.51,170.34 (Forecast)".to_owned()),
+            render(
+                "{summary} {dates} {position} ({subject})",
+                Some("Forecast"),
+                Some(&summary())
+            )
+        );
+        assert_eq!(
+            Some("Weather".to_owned()),
+            render("{summary} Weather {dates}", None, None)
+        );
+        assert_eq!(None, render("{summary} {dates}", None, None));
+    }
+}
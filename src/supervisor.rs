@@ -0,0 +1,207 @@
+//! Supervision of the application's long running tasks, see [`Supervisor`].
+
+use std::{any::Any, collections::BTreeMap, sync::Mutex};
+
+use chrono::{DateTime, Utc};
+use futures::Future;
+use tokio::sync::broadcast::{self, error::TryRecvError};
+
+use crate::{
+    retry::{BackoffOptions, ExponentialBackoff},
+    time,
+};
+
+/// Statistics about the restarts of a supervised task.
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct TaskStats {
+    /// Number of times the task has been restarted.
+    pub restarts: u64,
+    /// When the task last failed.
+    pub last_failure: Option<DateTime<Utc>>,
+    /// Description of the last failure (e.g. the panic message).
+    pub last_error: Option<String>,
+}
+
+/// Supervises long running tasks, restarting them with backoff if they panic or exit before a
+/// shutdown message has been broadcast, see [`Supervisor::supervise()`].
+pub struct Supervisor {
+    backoff: BackoffOptions,
+    time: &'static dyn time::Port,
+    stats: Mutex<BTreeMap<&'static str, TaskStats>>,
+}
+
+/// Obtain a message from the payload of a panic.
+fn panic_message(payload: &(dyn Any + Send)) -> &str {
+    if let Some(message) = payload.downcast_ref::<&str>() {
+        message
+    } else if let Some(message) = payload.downcast_ref::<String>() {
+        message
+    } else {
+        "unknown panic payload"
+    }
+}
+
+/// Whether a shutdown message has been broadcast (or can no longer be received).
+fn is_shutdown(shutdown_rx: &mut broadcast::Receiver<()>) -> bool {
+    !matches!(shutdown_rx.try_recv(), Err(TryRecvError::Empty))
+}
+
+impl Supervisor {
+    /// Construct a new [`Supervisor`], which restarts tasks using an [`ExponentialBackoff`]
+    /// constructed from `backoff`.
+    #[must_use]
+    pub fn new(backoff: BackoffOptions, time: &'static dyn time::Port) -> Self {
+        Self {
+            backoff,
+            time,
+            stats: Mutex::new(BTreeMap::new()),
+        }
+    }
+
+    /// A snapshot of the statistics for each supervised task, ordered by name.
+    #[must_use]
+    pub fn stats(&self) -> Vec<(&'static str, TaskStats)> {
+        self.stats
+            .lock()
+            .expect("supervisor mutex is poisoned")
+            .iter()
+            .map(|(name, stats)| (*name, stats.clone()))
+            .collect()
+    }
+
+    /// Render the number of restarts of each supervised task in the
+    /// [Prometheus text format](https://prometheus.io/docs/instrumenting/exposition_formats/).
+    #[must_use]
+    pub fn prometheus(&self) -> String {
+        const NAME: &str = "email_weather_task_restarts_total";
+        let mut output = format!(
+            "# HELP {NAME} Number of times each supervised task has been restarted.\n\
+            # TYPE {NAME} counter\n"
+        );
+        for (task, stats) in self.stats() {
+            output.push_str(&format!("{NAME}{{task=\"{task}\"}} {}\n", stats.restarts));
+        }
+        output
+    }
+
+    fn record_failure(&self, name: &'static str, error: &eyre::Error) {
+        let mut stats = self.stats.lock().expect("supervisor mutex is poisoned");
+        let task_stats = stats.entry(name).or_default();
+        task_stats.restarts += 1;
+        task_stats.last_failure = Some(self.time.utc_now());
+        task_stats.last_error = Some(format!("{:#}", error));
+    }
+
+    /// Run the task created by `spawn` until a shutdown message is broadcast on `shutdown_tx`.
+    /// `spawn` is provided with a new receiver for the shutdown broadcast, and is called again to
+    /// restart the task (after a backoff) each time it panics or exits before the shutdown
+    /// message has been broadcast.
+    pub async fn supervise<F, FUT>(
+        &self,
+        name: &'static str,
+        shutdown_tx: broadcast::Sender<()>,
+        spawn: F,
+    ) where
+        F: Fn(broadcast::Receiver<()>) -> eyre::Result<FUT>,
+        FUT: Future<Output = ()> + Send + 'static,
+    {
+        self.stats
+            .lock()
+            .expect("supervisor mutex is poisoned")
+            .entry(name)
+            .or_default();
+        let mut shutdown_rx = shutdown_tx.subscribe();
+        let mut backoff = ExponentialBackoff::from_options(&self.backoff).unwrap_or_else(|error| {
+            tracing::error!("Invalid backoff options, using defaults instead: {}", error);
+            ExponentialBackoff::from_options(&BackoffOptions::default())
+                .expect("Invalid default backoff")
+        });
+
+        loop {
+            let result: eyre::Result<()> = match spawn(shutdown_tx.subscribe()) {
+                Ok(task) => match tokio::spawn(task).await {
+                    Ok(()) if is_shutdown(&mut shutdown_rx) => return,
+                    Ok(()) => Err(eyre::eyre!("Task exited unexpectedly")),
+                    Err(error) if error.is_panic() => {
+                        let payload = error.into_panic();
+                        Err(eyre::eyre!(
+                            "Task panicked: {}",
+                            panic_message(payload.as_ref())
+                        ))
+                    }
+                    Err(error) => Err(eyre::Error::from(error).wrap_err("Task was cancelled")),
+                },
+                Err(error) => Err(error.wrap_err("Error starting task")),
+            };
+
+            if let Err(error) = result {
+                tracing::error!("Supervised task {:?} failed: {:?}", name, error);
+                self.record_failure(name, &error);
+                tokio::select! {
+                    _ = shutdown_rx.recv() => return,
+                    _ = backoff.sleep(self.time) => {}
+                }
+                tracing::warn!("Restarting supervised task {:?}", name);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use std::sync::{
+        atomic::{AtomicUsize, Ordering},
+        Arc,
+    };
+
+    use tokio::sync::broadcast;
+
+    use crate::retry::BackoffOptions;
+
+    use super::Supervisor;
+
+    #[tokio::test]
+    async fn test_supervise_restarts_panicked_task() {
+        let mut time = crate::time::MockPort::new();
+        time.expect_utc_now()
+            .returning(|| "2022-12-03T08:00:00Z".parse().unwrap());
+        time.expect_async_sleep().returning(|_| ());
+        let time: &'static crate::time::MockPort = Box::leak(Box::new(time));
+
+        let supervisor = Supervisor::new(BackoffOptions::default(), time);
+        let (shutdown_tx, _) = broadcast::channel::<()>(1);
+        let starts = Arc::new(AtomicUsize::new(0));
+
+        let task_shutdown_tx = shutdown_tx.clone();
+        supervisor
+            .supervise("test", shutdown_tx, move |mut shutdown_rx| {
+                let starts = starts.clone();
+                let shutdown_tx = task_shutdown_tx.clone();
+                Ok(async move {
+                    if starts.fetch_add(1, Ordering::SeqCst) == 0 {
+                        panic!("first start");
+                    }
+                    shutdown_tx.send(()).unwrap();
+                    shutdown_rx.recv().await.unwrap();
+                })
+            })
+            .await;
+
+        let stats = supervisor.stats();
+        assert_eq!(1, stats.len());
+        assert_eq!("test", stats[0].0);
+        assert_eq!(1, stats[0].1.restarts);
+        assert_eq!(
+            Some("Task panicked: first start"),
+            stats[0].1.last_error.as_deref()
+        );
+
+        let output = supervisor.prometheus();
+        assert!(
+            output
+                .lines()
+                .any(|line| line == r#"email_weather_task_restarts_total{task="test"} 1"#),
+            "{output}"
+        );
+    }
+}
@@ -1,27 +1,82 @@
 //! Utilitis for executing/spawning async tasks.
 
-use std::time::Duration;
-
 use eyre::Context;
 use futures::Future;
 
-use crate::{retry::ExponentialBackoff, time};
+use crate::{
+    analytics::Analytics,
+    archive::EmailArchive,
+    changes::PreviousForecasts,
+    circuit_breaker::CircuitBreakers,
+    confirmation::SenderConfirmations,
+    control::Controls,
+    encryption::PublicKeys,
+    feed::Feeds,
+    latency::Latency,
+    quota::Quotas,
+    retry::{BackoffOptions, ExponentialBackoff},
+    status::ServiceStatus,
+    time,
+    webhook::Webhooks,
+};
+
+/// Services and options shared by the application's long running tasks.
+#[derive(Clone, Copy)]
+pub struct TaskContext {
+    /// Records the health of the service, see [`ServiceStatus`].
+    pub status: &'static ServiceStatus,
+    /// Notifies webhooks of significant events, see [`Webhooks`].
+    pub webhooks: &'static Webhooks,
+    /// Circuit breakers for the external services used by the tasks, see [`CircuitBreakers`].
+    pub circuit_breakers: &'static CircuitBreakers,
+    /// Records anonymized analytics of processed requests, `None` if analytics are disabled, see
+    /// [`Analytics`].
+    pub analytics: Option<&'static Analytics>,
+    /// Records which plain email senders have confirmed their address, `None` if confirmation is
+    /// not required, see [`SenderConfirmations`].
+    pub confirmations: Option<&'static SenderConfirmations>,
+    /// Records the forecasts of senders who have subscribed to a feed, `None` if feeds are
+    /// disabled, see [`Feeds`].
+    pub feeds: Option<&'static Feeds>,
+    /// Records the previous forecast sent to each sender, `None` if the changes since the previous
+    /// forecast are not included in forecasts, see [`PreviousForecasts`].
+    pub previous_forecasts: Option<&'static PreviousForecasts>,
+    /// Records the PGP public keys that replies are encrypted to, `None` if encrypted replies are
+    /// disabled, see [`PublicKeys`].
+    pub public_keys: Option<&'static PublicKeys>,
+    /// Archives the raw messages which are received, `None` if archiving is disabled, see
+    /// [`EmailArchive`].
+    pub archive: Option<&'static EmailArchive>,
+    /// Daily quotas for the requests which are processed, see [`Quotas`].
+    pub quotas: &'static Quotas,
+    /// Histograms of the time spent in each stage of processing a request, see [`Latency`].
+    pub latency: &'static Latency,
+    /// Wakes the tasks early when requested by an operator, see [`Controls`].
+    pub controls: &'static Controls,
+    /// Backoff used when restarting a task after it has failed, see [`run_retry_log_errors()`].
+    pub backoff: BackoffOptions,
+    /// Time used by the tasks.
+    pub time: &'static dyn time::Port,
+}
 
 /// In a loop, runs a future created by `run`, logs an error if it occurs. In parallel using a
 /// `select!`, it listens to `shutdown_rx` and cancels the loop if a shutdown message has been
-/// broadcast.
+/// broadcast. Retries are delayed using an [`ExponentialBackoff`] constructed from `backoff`.
 pub async fn run_retry_log_errors<F, FUT>(
     run: F,
     mut shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    backoff: BackoffOptions,
     time: &dyn time::Port,
 ) where
     F: Fn() -> FUT,
     FUT: Future<Output = eyre::Result<()>>,
 {
     let run_loop = async move {
-        let mut backoff =
-            ExponentialBackoff::new(Duration::from_secs(10), Duration::from_secs(60 * 10))
-                .expect("Invalid backoff");
+        let mut backoff = ExponentialBackoff::from_options(&backoff).unwrap_or_else(|error| {
+            tracing::error!("Invalid backoff options, using defaults instead: {}", error);
+            ExponentialBackoff::from_options(&BackoffOptions::default())
+                .expect("Invalid default backoff")
+        });
         loop {
             if let Err(error) = run().await {
                 tracing::error!("{:?}", error);
@@ -1,10 +1,14 @@
 //! Abstraction over system provided time, as part of the hexagonal architecture.
 
+use std::sync::Mutex;
+
 use async_trait::async_trait;
-use chrono::Utc;
+use chrono::{DateTime, Utc};
+use tokio::sync::oneshot;
 
 /// Interface for accessing system provided time functionality.
-/// See [`Gateway`] for implementation.
+/// See [`Gateway`] for implementation, and [`Simulated`] for a deterministic implementation used
+/// in tests.
 #[cfg_attr(test, mockall::automock)]
 #[async_trait]
 pub trait Port: Send + Sync {
@@ -34,13 +38,198 @@ impl Port for Gateway {
     }
 }
 
+/// Implementation of [`Port`] where [`Port::utc_now()`] is fixed at a particular time (e.g. the
+/// time that a message was sent), and sleeps are delegated to an `inner` [`Port`].
+pub struct Fixed<'a> {
+    now: DateTime<Utc>,
+    inner: &'a dyn Port,
+}
+
+impl<'a> Fixed<'a> {
+    /// Construct a new [`Fixed`] clock at `now`.
+    #[must_use]
+    pub fn new(now: DateTime<Utc>, inner: &'a dyn Port) -> Self {
+        Self { now, inner }
+    }
+}
+
+#[async_trait]
+impl Port for Fixed<'_> {
+    async fn async_sleep(&self, duration: std::time::Duration) {
+        self.inner.async_sleep(duration).await;
+    }
+
+    fn sleep(&self, duration: std::time::Duration) {
+        self.inner.sleep(duration);
+    }
+
+    fn utc_now(&self) -> chrono::DateTime<Utc> {
+        self.now
+    }
+}
+
+struct SimulatedState {
+    now: DateTime<Utc>,
+    /// Sleeps which are waiting for the clock to reach their deadline.
+    timers: Vec<(DateTime<Utc>, oneshot::Sender<()>)>,
+}
+
+impl SimulatedState {
+    /// Advance the clock to `time` (if it is in the future), and wake the sleeps which are due.
+    fn advance_to(&mut self, time: DateTime<Utc>) {
+        if time > self.now {
+            self.now = time;
+        }
+        let now = self.now;
+        let (due, pending) = std::mem::take(&mut self.timers)
+            .into_iter()
+            .partition(|(deadline, _)| *deadline <= now);
+        self.timers = pending;
+        for (_, timer) in due {
+            // The sleep may have been cancelled, in which case there is nothing to wake.
+            let _ = timer.send(());
+        }
+    }
+}
+
+fn simulated_duration(duration: std::time::Duration) -> chrono::Duration {
+    chrono::Duration::from_std(duration).expect("Duration is too large to simulate")
+}
+
+/// Implementation of [`Port`] with a simulated clock that only advances when requested, so that
+/// tests involving time (e.g. backoff and timeouts) are deterministic and do not need to wait.
+///
+/// With [`Simulated::new()`] the clock is advanced manually using [`Simulated::advance()`], which
+/// wakes any sleeps that are due. With [`Simulated::new_auto_advance()`] each sleep immediately
+/// advances the clock to its deadline.
+pub struct Simulated {
+    state: Mutex<SimulatedState>,
+    auto_advance: bool,
+}
+
+impl Simulated {
+    /// Construct a new [`Simulated`] clock starting at `start`, which is advanced manually using
+    /// [`Simulated::advance()`].
+    #[must_use]
+    pub fn new(start: DateTime<Utc>) -> Self {
+        Self {
+            state: Mutex::new(SimulatedState {
+                now: start,
+                timers: Vec::new(),
+            }),
+            auto_advance: false,
+        }
+    }
+
+    /// Construct a new [`Simulated`] clock starting at `start`, where each sleep immediately
+    /// advances the clock to its deadline.
+    #[must_use]
+    pub fn new_auto_advance(start: DateTime<Utc>) -> Self {
+        Self {
+            auto_advance: true,
+            ..Self::new(start)
+        }
+    }
+
+    fn lock(&self) -> std::sync::MutexGuard<'_, SimulatedState> {
+        self.state.lock().expect("simulated time mutex is poisoned")
+    }
+
+    /// Advance the clock by `duration`, waking any sleeps which are due.
+    pub fn advance(&self, duration: std::time::Duration) {
+        let mut state = self.lock();
+        let time = state.now + simulated_duration(duration);
+        state.advance_to(time);
+    }
+
+    /// Number of sleeps which are waiting for the clock to advance.
+    #[must_use]
+    pub fn pending_timers(&self) -> usize {
+        self.lock().timers.len()
+    }
+}
+
+#[async_trait]
+impl Port for Simulated {
+    async fn async_sleep(&self, duration: std::time::Duration) {
+        let timer = {
+            let mut state = self.lock();
+            let deadline = state.now + simulated_duration(duration);
+            if self.auto_advance || deadline <= state.now {
+                state.advance_to(deadline);
+                None
+            } else {
+                let (timer_tx, timer_rx) = oneshot::channel();
+                state.timers.push((deadline, timer_tx));
+                Some(timer_rx)
+            }
+        };
+
+        match timer {
+            Some(timer) => {
+                // The sender is only dropped after it has been sent, or when the clock is dropped.
+                let _ = timer.await;
+            }
+            // Allow other tasks to make progress, as they would during a real sleep.
+            None => tokio::task::yield_now().await,
+        }
+    }
+
+    fn sleep(&self, duration: std::time::Duration) {
+        // Blocking until another thread advances the clock would be prone to deadlocks, so
+        // synchronous sleeps always advance the clock.
+        self.advance(duration);
+    }
+
+    fn utc_now(&self) -> chrono::DateTime<Utc> {
+        self.lock().now
+    }
+}
+
+/// Fixed time used as the current time by tests.
+#[cfg(test)]
+pub(crate) fn test_now() -> DateTime<Utc> {
+    "2022-12-03T08:00:00Z".parse().unwrap()
+}
+
 #[cfg(test)]
 mod test {
-    use super::{Gateway, Port};
+    use std::time::Duration;
+
+    use super::{test_now as start, Gateway, Port, Simulated};
     fn gateway_is_send_sync<P: Port + Send + Sync>(_: P) {}
 
     #[test]
     fn test_gateway_is_send_sync() {
         gateway_is_send_sync(Gateway);
     }
+
+    #[tokio::test]
+    async fn test_simulated_advance() {
+        let time: &'static Simulated = Box::leak(Box::new(Simulated::new(start())));
+        let sleep = tokio::spawn(async move {
+            time.async_sleep(Duration::from_secs(10)).await;
+            time.utc_now()
+        });
+        while time.pending_timers() == 0 {
+            tokio::task::yield_now().await;
+        }
+
+        time.advance(Duration::from_secs(9));
+        assert_eq!(1, time.pending_timers());
+        time.advance(Duration::from_secs(1));
+        assert_eq!(0, time.pending_timers());
+        assert_eq!(
+            start() + chrono::Duration::seconds(10),
+            sleep.await.unwrap()
+        );
+    }
+
+    #[tokio::test]
+    async fn test_simulated_auto_advance() {
+        let time = Simulated::new_auto_advance(start());
+        time.async_sleep(Duration::from_secs(5)).await;
+        time.sleep(Duration::from_secs(2));
+        assert_eq!(start() + chrono::Duration::seconds(7), time.utc_now());
+    }
 }
@@ -0,0 +1,165 @@
+//! [`Port`] implementation for the
+//! [Google Elevation API](https://developers.google.com/maps/documentation/elevation).
+
+use async_trait::async_trait;
+use open_topo_data::{Error, Parameters};
+use secrecy::{ExposeSecret, SecretString};
+use serde::Deserialize;
+
+use super::Port;
+
+/// Base url of the Google Maps APIs.
+pub const DEFAULT_BASE_URL: &str = "https://maps.googleapis.com";
+
+#[derive(Deserialize)]
+struct ElevationResponse {
+    #[serde(default)]
+    results: Vec<ElevationResult>,
+    status: String,
+    #[serde(default)]
+    error_message: Option<String>,
+}
+
+#[derive(Deserialize)]
+struct ElevationResult {
+    elevation: f32,
+}
+
+/// Obtains elevation from the Google Elevation API, which requires an API key (the
+/// `GOOGLE_ELEVATION_API_KEY` secret). [`Parameters::dataset`] is ignored.
+#[derive(Clone)]
+pub struct Gateway {
+    http_client: reqwest::Client,
+    base_url: String,
+    api_key: SecretString,
+}
+
+impl Gateway {
+    /// Construct a new [`Gateway`] using the public API.
+    #[must_use]
+    pub fn new(http_client: reqwest::Client, api_key: SecretString) -> Self {
+        Self::with_base_url(http_client, DEFAULT_BASE_URL.to_owned(), api_key)
+    }
+
+    /// Construct a new [`Gateway`] using the API hosted at `base_url`.
+    #[must_use]
+    pub fn with_base_url(
+        http_client: reqwest::Client,
+        base_url: String,
+        api_key: SecretString,
+    ) -> Self {
+        Self {
+            http_client,
+            base_url,
+            api_key,
+        }
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn obtain_elevation(&self, parameters: &Parameters) -> Result<f32, Error> {
+        let url = format!(
+            "{}/maps/api/elevation/json",
+            self.base_url.trim_end_matches('/')
+        );
+        let response: ElevationResponse = self
+            .http_client
+            .get(url)
+            .query(&[
+                (
+                    "locations",
+                    format!("{},{}", parameters.latitude, parameters.longitude).as_str(),
+                ),
+                ("key", self.api_key.expose_secret().as_str()),
+            ])
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+
+        if response.status != "OK" {
+            return Err(Error::ResponseStatus {
+                status: response.status,
+                message: response.error_message.unwrap_or_default(),
+            });
+        }
+        Ok(response.results.get(0).ok_or(Error::NoResults)?.elevation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use open_topo_data::Error;
+    use secrecy::SecretString;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use super::{Gateway, Port};
+
+    fn parameters() -> open_topo_data::Parameters {
+        open_topo_data::Parameters {
+            latitude: -43.5,
+            longitude: 170.25,
+            dataset: open_topo_data::Dataset::Mapzen,
+        }
+    }
+
+    fn gateway(server: &MockServer) -> Gateway {
+        Gateway::with_base_url(
+            reqwest::Client::new(),
+            server.uri(),
+            SecretString::new("google-key".to_string()),
+        )
+    }
+
+    #[tokio::test]
+    async fn test_obtain_elevation() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/maps/api/elevation/json"))
+            .and(matchers::query_param("locations", "-43.5,170.25"))
+            .and(matchers::query_param("key", "google-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{
+                    "elevation": 1780.5,
+                    "location": { "lat": -43.5, "lng": 170.25 },
+                    "resolution": 152.7,
+                }],
+                "status": "OK",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let elevation = gateway(&server)
+            .obtain_elevation(&parameters())
+            .await
+            .unwrap();
+        assert_eq!(1780.5, elevation);
+    }
+
+    #[tokio::test]
+    async fn test_obtain_elevation_denied() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/maps/api/elevation/json"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [],
+                "status": "REQUEST_DENIED",
+                "error_message": "The provided API key is invalid.",
+            })))
+            .mount(&server)
+            .await;
+
+        let error = gateway(&server)
+            .obtain_elevation(&parameters())
+            .await
+            .unwrap_err();
+        assert!(
+            matches!(error, Error::ResponseStatus { ref status, .. } if status == "REQUEST_DENIED"),
+            "unexpected error: {:?}",
+            error
+        );
+    }
+}
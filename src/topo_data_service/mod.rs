@@ -0,0 +1,461 @@
+//! External topographical data service.
+//! See [Port].
+//!
+//! Elevation can be obtained from several providers (see [`Provider`]), which are tried in order
+//! by [`Failover`].
+
+use std::{sync::Arc, time::Duration};
+
+use async_trait::async_trait;
+use open_topo_data::{Error, Parameters};
+use schemars::JsonSchema;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+
+use crate::{circuit_breaker::CircuitBreakerPort, rate_limit::TokenBucket};
+
+pub mod google;
+pub mod open_elevation;
+
+/// A provider of terrain elevation, specified in [`TopoDataOptions::providers`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub enum Provider {
+    /// [Open Topo Data](https://www.opentopodata.org/), hosted at
+    /// [`TopoDataOptions::base_url`], see [`Gateway`].
+    OpenTopoData,
+    /// [Open-Elevation](https://open-elevation.com/), see [`open_elevation::Gateway`].
+    OpenElevation(open_elevation::Options),
+    /// [Google Elevation API](https://developers.google.com/maps/documentation/elevation), which
+    /// requires the `GOOGLE_ELEVATION_API_KEY` secret, see [`google::Gateway`].
+    GoogleElevation,
+}
+
+impl Provider {
+    /// Name of the provider, for logging.
+    fn name(&self) -> &'static str {
+        match self {
+            Provider::OpenTopoData => "Open Topo Data",
+            Provider::OpenElevation(_) => "Open-Elevation",
+            Provider::GoogleElevation => "Google Elevation",
+        }
+    }
+}
+
+/// Options for the topographical data service, specified in [`crate::options::Options`]. API
+/// keys are loaded separately as secrets, see [`ApiKeys`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TopoDataOptions {
+    /// Base url of the [Open Topo Data](https://www.opentopodata.org/) API, e.g. of a self-hosted
+    /// server to avoid the rate limits of the public API.
+    ///
+    /// Default is the public API `https://api.opentopodata.org`.
+    #[serde(default = "default_base_url")]
+    pub base_url: url::Url,
+    /// Providers used to obtain elevation, in order of preference. If a provider fails, the next
+    /// provider is tried, see [`Failover`].
+    ///
+    /// Default is `[OpenTopoData]`.
+    #[serde(default = "default_providers")]
+    pub providers: Vec<Provider>,
+    /// Limits on the rate of requests to the Open Topo Data API, shared by all requests.
+    ///
+    /// Default matches the limits of the public API.
+    #[serde(default)]
+    pub rate_limit: TopoDataRateLimitOptions,
+}
+
+/// Options for the [`RateLimiter`] used by [`Gateway`], specified in
+/// [`TopoDataOptions::rate_limit`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TopoDataRateLimitOptions {
+    /// Number of requests permitted per second, further requests are queued until they are
+    /// permitted. `0` disables this limit.
+    ///
+    /// Default is `1`.
+    #[serde(default = "default_requests_per_second")]
+    pub requests_per_second: u32,
+    /// Number of requests permitted per day, further requests fail immediately (allowing the
+    /// next provider to be used). `0` disables this limit.
+    ///
+    /// Default is `1000`.
+    #[serde(default = "default_requests_per_day")]
+    pub requests_per_day: u32,
+}
+
+fn default_requests_per_second() -> u32 {
+    1
+}
+
+fn default_requests_per_day() -> u32 {
+    1000
+}
+
+impl Default for TopoDataRateLimitOptions {
+    fn default() -> Self {
+        Self {
+            requests_per_second: default_requests_per_second(),
+            requests_per_day: default_requests_per_day(),
+        }
+    }
+}
+
+/// Limits the rate of requests using a [`TokenBucket`] for each of the limits in
+/// [`TopoDataRateLimitOptions`]. Requests waiting for the per second limit are queued and
+/// permitted in the order that they arrived.
+pub struct RateLimiter {
+    requests_per_day: u32,
+    buckets: tokio::sync::Mutex<RateLimiterBuckets>,
+}
+
+struct RateLimiterBuckets {
+    per_second: Option<TokenBucket>,
+    per_day: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    /// Construct a new [`RateLimiter`].
+    #[must_use]
+    pub fn new(options: TopoDataRateLimitOptions) -> Self {
+        let now = tokio::time::Instant::now().into_std();
+        let bucket = |requests: u32, per: Duration| {
+            (requests > 0).then(|| TokenBucket::new(requests, requests, per, now))
+        };
+        Self {
+            requests_per_day: options.requests_per_day,
+            buckets: tokio::sync::Mutex::new(RateLimiterBuckets {
+                per_second: bucket(options.requests_per_second, Duration::from_secs(1)),
+                per_day: bucket(options.requests_per_day, Duration::from_secs(24 * 60 * 60)),
+            }),
+        }
+    }
+
+    /// Wait until a request is permitted. Returns [`Error::DailyLimitExceeded`] if the daily
+    /// limit has been exhausted.
+    pub async fn acquire(&self) -> Result<(), Error> {
+        // The lock is held while waiting so that requests are permitted in order.
+        let mut buckets = self.buckets.lock().await;
+        if let Some(per_day) = &mut buckets.per_day {
+            if !per_day.try_acquire(tokio::time::Instant::now().into_std()) {
+                return Err(Error::DailyLimitExceeded(self.requests_per_day));
+            }
+        }
+        if let Some(per_second) = &mut buckets.per_second {
+            loop {
+                let now = tokio::time::Instant::now().into_std();
+                if per_second.try_acquire(now) {
+                    break;
+                }
+                tokio::time::sleep(per_second.time_until_available(now)).await;
+            }
+        }
+        Ok(())
+    }
+}
+
+fn default_base_url() -> url::Url {
+    open_topo_data::DEFAULT_BASE_URL
+        .parse()
+        .expect("Unable to parse url")
+}
+
+fn default_providers() -> Vec<Provider> {
+    vec![Provider::OpenTopoData]
+}
+
+impl Default for TopoDataOptions {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+            providers: default_providers(),
+            rate_limit: TopoDataRateLimitOptions::default(),
+        }
+    }
+}
+
+/// API keys for the elevation providers, loaded as secrets (see [`crate::secrets::Secrets`]).
+#[derive(Clone, Default)]
+pub struct ApiKeys {
+    /// `TOPO_DATA_API_KEY`: (optional) API key for a self-hosted Open Topo Data server.
+    pub open_topo_data: Option<SecretString>,
+    /// `GOOGLE_ELEVATION_API_KEY`: API key for the Google Elevation API.
+    pub google_elevation: Option<SecretString>,
+}
+
+impl ApiKeys {
+    /// Load the API keys from `provider`, falling back to files in `secrets_dir`.
+    pub async fn initialize(
+        provider: &dyn crate::secrets::Provider,
+        secrets_dir: &std::path::Path,
+    ) -> eyre::Result<Self> {
+        Ok(Self {
+            open_topo_data: crate::secrets::optional_secret(
+                provider,
+                secrets_dir,
+                "TOPO_DATA_API_KEY",
+            )
+            .await?,
+            google_elevation: crate::secrets::optional_secret(
+                provider,
+                secrets_dir,
+                "GOOGLE_ELEVATION_API_KEY",
+            )
+            .await?,
+        })
+    }
+}
+
+/// Trait used to allow mocking the [open_topo_data] service.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait Port: Send + Sync {
+    /// Obtain a weather forecast using [open_meteo::obtain_forecast()].
+    async fn obtain_elevation(&self, paramters: &Parameters) -> Result<f32, Error>;
+}
+
+/// Concrete implementation of [Port].
+#[derive(Clone)]
+pub struct Gateway {
+    http_client: reqwest::Client,
+    base_url: String,
+    api_key: Option<SecretString>,
+    rate_limiter: Option<Arc<RateLimiter>>,
+}
+
+impl Gateway {
+    /// Construct a new [Gateway] using the public API.
+    #[must_use]
+    pub fn new(http_client: reqwest::Client) -> Self {
+        Self::with_base_url(http_client, open_topo_data::DEFAULT_BASE_URL.to_owned())
+    }
+
+    /// Construct a new [Gateway] using the API hosted at `base_url`.
+    #[must_use]
+    pub fn with_base_url(http_client: reqwest::Client, base_url: String) -> Self {
+        Self {
+            http_client,
+            base_url,
+            api_key: None,
+            rate_limiter: None,
+        }
+    }
+
+    /// Construct a new [Gateway] using the API specified in `options`, authenticating with
+    /// `api_key` if specified. Requests are limited by a [`RateLimiter`] which is shared between
+    /// clones of the gateway.
+    #[must_use]
+    pub fn from_options(
+        http_client: reqwest::Client,
+        options: &TopoDataOptions,
+        api_key: Option<SecretString>,
+    ) -> Self {
+        Self {
+            api_key,
+            rate_limiter: Some(Arc::new(RateLimiter::new(options.rate_limit))),
+            ..Self::with_base_url(http_client, options.base_url.to_string())
+        }
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn obtain_elevation(&self, parameters: &Parameters) -> Result<f32, Error> {
+        if let Some(rate_limiter) = &self.rate_limiter {
+            rate_limiter.acquire().await?;
+        }
+        open_topo_data::obtain_elevation_from(
+            &self.http_client,
+            &self.base_url,
+            self.api_key
+                .as_ref()
+                .map(|api_key| api_key.expose_secret().as_str()),
+            parameters,
+        )
+        .await
+    }
+}
+
+/// [Port] which obtains elevation from each of its providers in order, until one succeeds.
+#[derive(Clone)]
+pub struct Failover {
+    providers: Vec<(&'static str, Arc<dyn Port>)>,
+}
+
+impl Failover {
+    /// Construct a new [Failover] which tries the `providers` in order.
+    #[must_use]
+    pub fn new(providers: Vec<(&'static str, Arc<dyn Port>)>) -> Self {
+        Self { providers }
+    }
+
+    /// Construct a new [Failover] using the providers specified in `options`. Providers which
+    /// require an API key that is missing from `api_keys` are skipped.
+    #[must_use]
+    pub fn from_options(
+        http_client: &reqwest::Client,
+        options: &TopoDataOptions,
+        api_keys: &ApiKeys,
+    ) -> Self {
+        let providers = options
+            .providers
+            .iter()
+            .filter_map(|provider| {
+                let port: Arc<dyn Port> = match provider {
+                    Provider::OpenTopoData => Arc::new(Gateway::from_options(
+                        http_client.clone(),
+                        options,
+                        api_keys.open_topo_data.clone(),
+                    )),
+                    Provider::OpenElevation(open_elevation_options) => {
+                        Arc::new(open_elevation::Gateway::new(
+                            http_client.clone(),
+                            open_elevation_options.clone(),
+                        ))
+                    }
+                    Provider::GoogleElevation => match &api_keys.google_elevation {
+                        Some(api_key) => {
+                            Arc::new(google::Gateway::new(http_client.clone(), api_key.clone()))
+                        }
+                        None => {
+                            tracing::warn!(
+                                "Skipping {} elevation provider because the \
+                                GOOGLE_ELEVATION_API_KEY secret is unavailable",
+                                provider.name()
+                            );
+                            return None;
+                        }
+                    },
+                };
+                Some((provider.name(), port))
+            })
+            .collect();
+        Self::new(providers)
+    }
+}
+
+#[async_trait]
+impl Port for Failover {
+    async fn obtain_elevation(&self, parameters: &Parameters) -> Result<f32, Error> {
+        let mut last_error = None;
+        for (name, provider) in &self.providers {
+            match provider.obtain_elevation(parameters).await {
+                Ok(elevation) => return Ok(elevation),
+                Err(error) => {
+                    tracing::warn!("Error obtaining elevation from {}: {}", name, error);
+                    last_error = Some(error);
+                }
+            }
+        }
+        Err(last_error.unwrap_or(Error::NoResults))
+    }
+}
+
+#[async_trait]
+impl<'a, P: Port> Port for CircuitBreakerPort<'a, P> {
+    async fn obtain_elevation(&self, parameters: &Parameters) -> Result<f32, Error> {
+        self.request(|inner| inner.obtain_elevation(parameters))
+            .await
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secrecy::SecretString;
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use std::sync::Arc;
+
+    use super::{
+        Failover, Gateway, MockPort, Port, RateLimiter, TopoDataOptions, TopoDataRateLimitOptions,
+    };
+
+    fn parameters() -> open_topo_data::Parameters {
+        open_topo_data::Parameters {
+            latitude: -43.513832,
+            longitude: 170.33975,
+            dataset: open_topo_data::Dataset::Mapzen,
+        }
+    }
+
+    /// Test that the next provider is used when the first fails.
+    #[tokio::test]
+    async fn test_failover() {
+        let mut failing = MockPort::new();
+        failing
+            .expect_obtain_elevation()
+            .times(1)
+            .returning(|_| Err(open_topo_data::Error::NoResults));
+        let mut succeeding = MockPort::new();
+        succeeding
+            .expect_obtain_elevation()
+            .times(1)
+            .returning(|_| Ok(2216.0));
+        let mut unused = MockPort::new();
+        unused.expect_obtain_elevation().never();
+
+        let failover = Failover::new(vec![
+            ("failing", Arc::new(failing) as Arc<dyn Port>),
+            ("succeeding", Arc::new(succeeding) as Arc<dyn Port>),
+            ("unused", Arc::new(unused) as Arc<dyn Port>),
+        ]);
+        assert_eq!(
+            2216.0,
+            failover.obtain_elevation(&parameters()).await.unwrap()
+        );
+
+        let failover = Failover::new(Vec::new());
+        assert!(failover.obtain_elevation(&parameters()).await.is_err());
+    }
+
+    /// Test that the API key is sent to a self-hosted server.
+    #[tokio::test]
+    async fn test_self_hosted_api_key() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/v1/mapzen"))
+            .and(matchers::header("Authorization", "Bearer secret-key"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{
+                    "dataset": "mapzen",
+                    "elevation": 2216.0,
+                    "location": { "lat": -43.513832, "lng": 170.33975 },
+                }],
+                "status": "OK",
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let options = TopoDataOptions {
+            base_url: server.uri().parse().unwrap(),
+            ..TopoDataOptions::default()
+        };
+        let gateway = Gateway::from_options(
+            reqwest::Client::new(),
+            &options,
+            Some(SecretString::new("secret-key".to_string())),
+        );
+        let elevation = gateway.obtain_elevation(&parameters()).await.unwrap();
+        assert_eq!(2216.0, elevation);
+    }
+
+    /// Test that requests are queued to respect the per second limit, and fail once the daily
+    /// limit is exhausted.
+    #[tokio::test(start_paused = true)]
+    async fn test_rate_limiter() {
+        let limiter = RateLimiter::new(TopoDataRateLimitOptions {
+            requests_per_second: 1,
+            requests_per_day: 3,
+        });
+        let start = tokio::time::Instant::now();
+        for _ in 0..3 {
+            limiter.acquire().await.unwrap();
+        }
+        let elapsed = tokio::time::Instant::now() - start;
+        assert!(elapsed >= std::time::Duration::from_secs(2), "{elapsed:?}");
+        assert!(elapsed < std::time::Duration::from_secs(3), "{elapsed:?}");
+        assert!(matches!(
+            limiter.acquire().await,
+            Err(open_topo_data::Error::DailyLimitExceeded(3))
+        ));
+    }
+}
@@ -0,0 +1,119 @@
+//! [`Port`] implementation for the [Open-Elevation](https://open-elevation.com/) API.
+
+use async_trait::async_trait;
+use open_topo_data::{Error, Parameters};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+
+use super::Port;
+
+/// Options for [`Gateway`].
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct Options {
+    /// Base url of the Open-Elevation API.
+    ///
+    /// Default is the public API `https://api.open-elevation.com`.
+    #[serde(default = "default_base_url")]
+    pub base_url: url::Url,
+}
+
+fn default_base_url() -> url::Url {
+    "https://api.open-elevation.com"
+        .parse()
+        .expect("Unable to parse url")
+}
+
+impl Default for Options {
+    fn default() -> Self {
+        Self {
+            base_url: default_base_url(),
+        }
+    }
+}
+
+#[derive(Deserialize)]
+struct LookupResults {
+    results: Vec<LookupResult>,
+}
+
+#[derive(Deserialize)]
+struct LookupResult {
+    elevation: f32,
+}
+
+/// Obtains elevation from the Open-Elevation API. It uses a single dataset (SRTM), so
+/// [`Parameters::dataset`] is ignored.
+#[derive(Clone)]
+pub struct Gateway {
+    http_client: reqwest::Client,
+    options: Options,
+}
+
+impl Gateway {
+    /// Construct a new [`Gateway`].
+    #[must_use]
+    pub fn new(http_client: reqwest::Client, options: Options) -> Self {
+        Self {
+            http_client,
+            options,
+        }
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn obtain_elevation(&self, parameters: &Parameters) -> Result<f32, Error> {
+        let url = format!(
+            "{}/api/v1/lookup?locations={},{}",
+            self.options.base_url.as_str().trim_end_matches('/'),
+            parameters.latitude,
+            parameters.longitude,
+        );
+        let results: LookupResults = self
+            .http_client
+            .get(url)
+            .send()
+            .await?
+            .error_for_status()?
+            .json()
+            .await?;
+        Ok(results.results.get(0).ok_or(Error::NoResults)?.elevation)
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use wiremock::{matchers, Mock, MockServer, ResponseTemplate};
+
+    use super::{Gateway, Options, Port};
+
+    #[tokio::test]
+    async fn test_obtain_elevation() {
+        let server = MockServer::start().await;
+        Mock::given(matchers::method("GET"))
+            .and(matchers::path("/api/v1/lookup"))
+            .and(matchers::query_param("locations", "-43.5,170.25"))
+            .respond_with(ResponseTemplate::new(200).set_body_json(serde_json::json!({
+                "results": [{ "latitude": -43.5, "longitude": 170.25, "elevation": 1780 }],
+            })))
+            .expect(1)
+            .mount(&server)
+            .await;
+
+        let gateway = Gateway::new(
+            reqwest::Client::new(),
+            Options {
+                base_url: server.uri().parse().unwrap(),
+            },
+        );
+        let elevation = gateway
+            .obtain_elevation(&open_topo_data::Parameters {
+                latitude: -43.5,
+                longitude: 170.25,
+                dataset: open_topo_data::Dataset::Mapzen,
+            })
+            .await
+            .unwrap();
+        assert_eq!(1780.0, elevation);
+    }
+}
@@ -0,0 +1,396 @@
+//! An optional SMS transport using [Twilio](https://www.twilio.com/), for users without
+//! satellite email but with (patchy) cell coverage. Twilio forwards SMS sent to
+//! [`TwilioOptions::from_number`] to the inbound webhook served by [`twilio_routes()`], and the
+//! short format forecast is sent back as an SMS using the Twilio REST API, see [`Gateway`].
+
+use std::{collections::BTreeMap, sync::Arc};
+
+use async_trait::async_trait;
+use axum::{
+    http::{header, HeaderMap, StatusCode},
+    response::IntoResponse,
+    routing::post,
+    Form, Router,
+};
+use eyre::Context;
+use hmac::{Hmac, Mac};
+use schemars::JsonSchema;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use sha1::Sha1;
+use tokio::sync::watch;
+
+use crate::{
+    aviation_weather, flood,
+    forecast::{ForecastError, ForecastService},
+    forecast_service,
+    format::{FormatDetail, FormatForecastOptions, ShortFormatDetail},
+    options::DynamicOptions,
+    quota::Quotas,
+    request::ForecastRequest,
+    time, topo_data_service,
+};
+
+/// Header containing the signature of the webhook request, see [`validate_signature()`].
+const SIGNATURE_HEADER: &str = "X-Twilio-Signature";
+
+/// Options for the Twilio SMS transport, specified in [`crate::options::Options::twilio`]. The
+/// auth token for the account is read from the `TWILIO_AUTH_TOKEN` secret.
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+pub struct TwilioOptions {
+    /// Twilio account SID, e.g. `ACXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXXX`.
+    pub account_sid: String,
+    /// Twilio phone number (in E.164 format, e.g. `+6498765432`) that requests are sent to, and
+    /// replies are sent from.
+    pub from_number: String,
+    /// Maximum length of the forecast sent in reply. Messages longer than 160 characters are
+    /// sent (and charged) as multiple SMS segments.
+    ///
+    /// Default is `160`.
+    #[serde(default = "default_length_limit")]
+    pub length_limit: usize,
+    /// Base url of the Twilio REST API.
+    ///
+    /// Default is `https://api.twilio.com`.
+    #[serde(default = "default_api_base_url")]
+    pub api_base_url: url::Url,
+}
+
+fn default_length_limit() -> usize {
+    160
+}
+
+fn default_api_base_url() -> url::Url {
+    "https://api.twilio.com"
+        .parse()
+        .expect("Unable to parse url")
+}
+
+/// Validate the `signature` of a webhook request to `url` with the form `params`, which Twilio
+/// calculates as the base64 encoded HMAC-SHA1 (using the account's `auth_token`) of the `url`
+/// followed by each of the parameter names and values, sorted by name.
+#[must_use]
+pub fn validate_signature(
+    auth_token: &SecretString,
+    url: &str,
+    params: &BTreeMap<String, String>,
+    signature: &str,
+) -> bool {
+    let signature = match base64::decode(signature) {
+        Ok(signature) => signature,
+        Err(_) => return false,
+    };
+    let mut mac = Hmac::<Sha1>::new_from_slice(auth_token.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(url.as_bytes());
+    for (name, value) in params {
+        mac.update(name.as_bytes());
+        mac.update(value.as_bytes());
+    }
+    mac.verify_slice(&signature).is_ok()
+}
+
+/// Trait used to allow mocking the sending of SMS.
+#[cfg_attr(test, mockall::automock)]
+#[async_trait]
+pub trait Port: Send + Sync {
+    /// Send an SMS containing `body` to the phone number `to`.
+    async fn send_sms(&self, to: &str, body: &str) -> eyre::Result<()>;
+}
+
+/// Concrete implementation of [`Port`] using the Twilio REST API.
+#[derive(Clone)]
+pub struct Gateway {
+    http_client: reqwest::Client,
+    options: TwilioOptions,
+    auth_token: &'static SecretString,
+}
+
+impl Gateway {
+    /// Construct a new [`Gateway`].
+    #[must_use]
+    pub fn new(
+        http_client: reqwest::Client,
+        options: TwilioOptions,
+        auth_token: &'static SecretString,
+    ) -> Self {
+        Self {
+            http_client,
+            options,
+            auth_token,
+        }
+    }
+}
+
+#[async_trait]
+impl Port for Gateway {
+    async fn send_sms(&self, to: &str, body: &str) -> eyre::Result<()> {
+        let url = format!(
+            "{}/2010-04-01/Accounts/{}/Messages.json",
+            self.options.api_base_url.as_str().trim_end_matches('/'),
+            self.options.account_sid
+        );
+        self.http_client
+            .post(url)
+            .basic_auth(
+                &self.options.account_sid,
+                Some(self.auth_token.expose_secret()),
+            )
+            .form(&[
+                ("From", self.options.from_number.as_str()),
+                ("To", to),
+                ("Body", body),
+            ])
+            .send()
+            .await
+            .and_then(reqwest::Response::error_for_status)
+            .wrap_err("Error sending SMS using Twilio")?;
+        Ok(())
+    }
+}
+
+/// Restrict the format to the short format, within `length_limit`.
+fn transform_format(
+    mut format: FormatForecastOptions,
+    length_limit: usize,
+) -> FormatForecastOptions {
+    let length_limit = match &format.detail {
+        FormatDetail::Short(short) => short
+            .length_limit
+            .map_or(length_limit, |limit| limit.min(length_limit)),
+        FormatDetail::Long(_) => length_limit,
+    };
+    format.detail = FormatDetail::Short(ShortFormatDetail {
+        length_limit: Some(length_limit),
+    });
+    format
+}
+
+/// Obtain the forecast for the request in the SMS `body` received `from` a phone number, and send
+/// it back as an SMS using `sms`.
+pub async fn reply(
+    sms: &dyn Port,
+    service: &ForecastService<'_>,
+    quotas: &Quotas,
+    time: &dyn time::Port,
+    length_limit: usize,
+    from: &str,
+    body: &str,
+) -> eyre::Result<()> {
+    let message = if let Err(error) = quotas.check(None, time.utc_now()) {
+        tracing::warn!("Request refused: {}", error);
+        format!("{}, please try again later", error)
+    } else {
+        let (request, errors) = ForecastRequest::parse(body);
+        let errors = errors
+            .into_iter()
+            .map(|error| format!("Error parsing request: {}", error))
+            .collect();
+        match service
+            .forecast_with(&request, None, errors, |format| {
+                transform_format(format, length_limit)
+            })
+            .await
+        {
+            Ok(forecast) => forecast.message,
+            Err(ForecastError::NoPosition) => {
+                "Include a position in the request, e.g. -43.5,170.3".to_owned()
+            }
+            Err(error) => {
+                tracing::error!("Error obtaining forecast for SMS: {:?}", error);
+                "Forecast is unavailable, please try again later".to_owned()
+            }
+        }
+    };
+    sms.send_sms(from, &message).await
+}
+
+/// Services used to reply to the SMS received by the webhook, see [`twilio_routes()`].
+#[derive(Clone)]
+pub struct Twilio {
+    /// Options for the transport.
+    pub options: TwilioOptions,
+    /// Auth token of the Twilio account, used to validate the webhook requests.
+    pub auth_token: &'static SecretString,
+    /// Used to send the replies.
+    pub gateway: Gateway,
+    /// Used to obtain forecasts.
+    pub forecast_service: forecast_service::Failover,
+    /// Used to obtain the terrain elevation for forecasts.
+    pub topo_data_service: topo_data_service::Failover,
+    /// Used to obtain the reports for requests which include `METAR`.
+    pub aviation_weather: aviation_weather::Gateway,
+    /// Used to obtain the river discharge for requests which include `FLOOD`.
+    pub flood: flood::Gateway,
+    /// Profiles and default format used for forecasts.
+    pub options_rx: watch::Receiver<DynamicOptions>,
+    /// Daily quotas which the requests count against.
+    pub quotas: &'static Quotas,
+    /// Time used to obtain forecasts.
+    pub time: &'static dyn time::Port,
+}
+
+impl Twilio {
+    async fn reply(&self, from: &str, body: &str) -> eyre::Result<()> {
+        let options = self.options_rx.borrow().clone();
+        let service = ForecastService::new(
+            self.time,
+            &self.forecast_service,
+            Some(&self.topo_data_service),
+            &options,
+        )
+        .with_aviation_weather(Some(&self.aviation_weather))
+        .with_flood(Some(&self.flood));
+        reply(
+            &self.gateway,
+            &service,
+            self.quotas,
+            self.time,
+            self.options.length_limit,
+            from,
+            body,
+        )
+        .await
+    }
+}
+
+/// Routes for the Twilio inbound SMS webhook, which should be configured in Twilio as
+/// `POST /twilio/sms` (relative to `base_url`):
+///
+/// + `POST /sms` validates the signature of the request, responds immediately with an empty
+///   TwiML response, then obtains the forecast for the request in the SMS and sends it back
+///   to the sender.
+pub fn twilio_routes(twilio: Twilio, base_url: &url::Url) -> eyre::Result<Router> {
+    let webhook_url = base_url.join("twilio/sms")?.to_string();
+    let twilio = Arc::new(twilio);
+    Ok(Router::new().route(
+        "/sms",
+        post(
+            move |headers: HeaderMap, Form(params): Form<BTreeMap<String, String>>| {
+                let twilio = twilio.clone();
+                let webhook_url = webhook_url.clone();
+                async move {
+                    let signature = headers
+                        .get(SIGNATURE_HEADER)
+                        .and_then(|value| value.to_str().ok())
+                        .unwrap_or_default();
+                    if !validate_signature(twilio.auth_token, &webhook_url, &params, signature) {
+                        tracing::warn!("Invalid Twilio webhook signature");
+                        return StatusCode::FORBIDDEN.into_response();
+                    }
+                    match (params.get("From"), params.get("Body")) {
+                        (Some(from), Some(body)) => {
+                            let from = from.clone();
+                            let body = body.clone();
+                            tracing::info!("Received SMS");
+                            // Forecasts may take longer than Twilio waits for a response.
+                            tokio::spawn(async move {
+                                if let Err(error) = twilio.reply(&from, &body).await {
+                                    tracing::error!("Error replying to SMS: {:?}", error);
+                                }
+                            });
+                            (
+                                [(header::CONTENT_TYPE, "text/xml")],
+                                "<Response></Response>",
+                            )
+                                .into_response()
+                        }
+                        _ => (StatusCode::BAD_REQUEST, "Expected From and Body").into_response(),
+                    }
+                }
+            },
+        ),
+    ))
+}
+
+#[cfg(test)]
+mod test {
+    use std::collections::BTreeMap;
+
+    use once_cell::sync::Lazy;
+    use open_meteo::Forecast;
+    use secrecy::SecretString;
+    use tokio::sync::watch;
+
+    use crate::{
+        forecast::ForecastService,
+        forecast_service::{self, ObtainedForecast},
+        options::DynamicOptions,
+        quota::Quotas,
+    };
+
+    use super::{reply, validate_signature, MockPort};
+
+    static FORECAST_MT_COOK: Lazy<Forecast> = Lazy::new(|| {
+        serde_json::from_str(&std::fs::read_to_string("fixtures/forecast_mt_cook.json").unwrap())
+            .unwrap()
+    });
+
+    #[test]
+    fn test_validate_signature() {
+        let auth_token = SecretString::new("12345".to_owned());
+        let url = "https://example.com/twilio/sms";
+        let params: BTreeMap<String, String> = [
+            ("Body", "-43.5,170.3"),
+            ("From", "+64211234567"),
+            ("To", "+6498765432"),
+            ("MessageSid", "SM123"),
+        ]
+        .into_iter()
+        .map(|(name, value)| (name.to_owned(), value.to_owned()))
+        .collect();
+        let signature = "FXFFSp65Rd46ZNjb/N9gjVfImFE=";
+
+        assert!(validate_signature(&auth_token, url, &params, signature));
+        assert!(!validate_signature(
+            &auth_token,
+            "https://example.com/other",
+            &params,
+            signature
+        ));
+        assert!(!validate_signature(&auth_token, url, &params, "invalid"));
+        let mut tampered = params;
+        tampered.insert("Body".to_owned(), "0,0".to_owned());
+        assert!(!validate_signature(&auth_token, url, &tampered, signature));
+    }
+
+    #[tokio::test]
+    async fn test_reply() {
+        let mut time = crate::time::MockPort::new();
+        time.expect_utc_now()
+            .returning(|| "2022-12-03T08:00:00Z".parse().unwrap());
+        let mut forecast_service = forecast_service::MockPort::new();
+        forecast_service
+            .expect_obtain_forecast()
+            .return_once(|_| Ok(ObtainedForecast::fixture(FORECAST_MT_COOK.clone())));
+        let options = DynamicOptions::default();
+        let service = ForecastService::new(&time, &forecast_service, None, &options);
+        let quotas = Quotas::new(watch::channel(DynamicOptions::default()).1);
+
+        let mut sms = MockPort::new();
+        sms.expect_send_sms()
+            .withf(|to, body| to == "+64211234567" && body.len() <= 160 && body.contains("03T21"))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        reply(
+            &sms,
+            &service,
+            &quotas,
+            &time,
+            160,
+            "+64211234567",
+            "-43.5,170.3 ML",
+        )
+        .await
+        .unwrap();
+
+        let mut sms = MockPort::new();
+        sms.expect_send_sms()
+            .withf(|_, body| body.starts_with("Include a position"))
+            .times(1)
+            .returning(|_, _| Ok(()));
+        reply(&sms, &service, &quotas, &time, 160, "+64211234567", "FLY")
+            .await
+            .unwrap();
+    }
+}
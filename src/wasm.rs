@@ -0,0 +1,25 @@
+//! Bindings for use in a browser via WebAssembly, enabled by the `wasm-bindgen` feature. Values
+//! are returned as JSON strings. Build without the default `server` feature, e.g. `cargo build
+//! --target wasm32-unknown-unknown --no-default-features --features wasm-bindgen`.
+
+use wasm_bindgen::prelude::*;
+
+use crate::{format, request::ParsedForecastRequest};
+
+fn to_json<T: serde::Serialize>(value: &T) -> Result<String, JsValue> {
+    serde_json::to_string(value).map_err(|error| JsValue::from_str(&error.to_string()))
+}
+
+/// Parse a forecast request, see [`ParsedForecastRequest::parse()`]. Returns the parsed request
+/// and any errors as JSON, so that a request can be validated before it is sent.
+#[wasm_bindgen(js_name = parseRequest)]
+pub fn parse_request(request: &str) -> Result<String, JsValue> {
+    to_json(&ParsedForecastRequest::parse(request))
+}
+
+/// Decode a forecast received in the short format, see [`format::decode_short()`]. Returns the
+/// decoded forecast as JSON.
+#[wasm_bindgen(js_name = decodeShort)]
+pub fn decode_short(message: &str) -> Result<String, JsValue> {
+    to_json(&format::decode_short(message))
+}
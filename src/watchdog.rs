@@ -0,0 +1,312 @@
+//! A task which alerts operators (via webhooks and email) when the pipeline appears to have
+//! stalled, e.g. because the email account's authorization has expired, see [`watchdog()`].
+
+use std::{collections::HashSet, sync::Arc};
+
+use chrono::{DateTime, Utc};
+use schemars::JsonSchema;
+use serde::{Deserialize, Serialize};
+use tokio::sync::Mutex;
+
+use crate::{
+    correlation::RequestId,
+    email,
+    oauth2::AuthenticationFlow,
+    reply::{self, Reply},
+    status::ServiceStatus,
+    task::{run_retry_log_errors, TaskContext},
+    webhook::WebhookEvent,
+};
+
+/// How often the checks are performed.
+const CHECK_INTERVAL: std::time::Duration = std::time::Duration::from_secs(60);
+
+/// Options for the [`watchdog()`] task.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WatchdogOptions {
+    /// Alert when the inbox has not been successfully polled for this many minutes.
+    ///
+    /// Default is `30`.
+    #[serde(default = "default_inbox_poll_minutes")]
+    pub inbox_poll_minutes: u32,
+    /// Alert when replies have been waiting in the reply queue for this many minutes without any
+    /// reply being sent.
+    ///
+    /// Default is `30`.
+    #[serde(default = "default_reply_queue_minutes")]
+    pub reply_queue_minutes: u32,
+    /// Address that alerts are also emailed to (from the service's email account). Alerts are
+    /// always sent to webhooks which are interested in [`WebhookEvent::WatchdogAlert`].
+    ///
+    /// Default is `None`.
+    #[serde(default)]
+    pub alert_email: Option<email::Account>,
+}
+
+fn default_inbox_poll_minutes() -> u32 {
+    30
+}
+
+fn default_reply_queue_minutes() -> u32 {
+    30
+}
+
+impl Default for WatchdogOptions {
+    fn default() -> Self {
+        Self {
+            inbox_poll_minutes: default_inbox_poll_minutes(),
+            reply_queue_minutes: default_reply_queue_minutes(),
+            alert_email: None,
+        }
+    }
+}
+
+/// A check performed by the [`Watchdog`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Hash, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WatchdogCheck {
+    /// The inbox has been successfully polled recently.
+    InboxPoll,
+    /// Replies are not waiting in the reply queue without being sent.
+    ReplyQueue,
+}
+
+/// Performs the checks, and keeps track of which checks are failing so that each alert is only
+/// sent once, followed by a notification when the check recovers.
+#[derive(Debug)]
+pub struct Watchdog {
+    options: WatchdogOptions,
+    failing: HashSet<WatchdogCheck>,
+}
+
+impl Watchdog {
+    /// Construct a new [`Watchdog`] with all checks passing.
+    #[must_use]
+    pub fn new(options: WatchdogOptions) -> Self {
+        Self {
+            options,
+            failing: HashSet::new(),
+        }
+    }
+
+    /// Description of the problem if `check` is failing at `now`.
+    fn failure(
+        &self,
+        check: WatchdogCheck,
+        status: &ServiceStatus,
+        now: DateTime<Utc>,
+    ) -> Option<String> {
+        match check {
+            WatchdogCheck::InboxPoll => {
+                let minutes = (now - status.last_inbox_poll_or_started()).num_minutes();
+                (minutes >= i64::from(self.options.inbox_poll_minutes)).then(|| {
+                    format!(
+                        "The inbox has not been successfully polled for {} minutes, the email \
+                        account may need to be authorized again",
+                        minutes
+                    )
+                })
+            }
+            WatchdogCheck::ReplyQueue => {
+                let (pending, progress) = status.pending_replies()?;
+                let minutes = (now - progress).num_minutes();
+                (minutes >= i64::from(self.options.reply_queue_minutes)).then(|| {
+                    format!(
+                        "{} replies have been waiting to be sent, and no reply has been sent for \
+                        {} minutes",
+                        pending, minutes
+                    )
+                })
+            }
+        }
+    }
+
+    /// Perform the checks at `now`, returning the events for checks which have started failing
+    /// or have recovered since the previous call.
+    pub fn check(&mut self, status: &ServiceStatus, now: DateTime<Utc>) -> Vec<WebhookEvent> {
+        let mut events = Vec::new();
+        for check in [WatchdogCheck::InboxPoll, WatchdogCheck::ReplyQueue] {
+            match self.failure(check, status, now) {
+                Some(message) => {
+                    if self.failing.insert(check) {
+                        events.push(WebhookEvent::WatchdogAlert { check, message });
+                    }
+                }
+                None => {
+                    if self.failing.remove(&check) {
+                        events.push(WebhookEvent::WatchdogRecovered { check });
+                    }
+                }
+            }
+        }
+        events
+    }
+}
+
+/// Email sent to [`WatchdogOptions::alert_email`] for `event`.
+fn alert_reply(event: &WebhookEvent, to: &email::Account) -> Option<Reply> {
+    let (subject, message) = match event {
+        WebhookEvent::WatchdogAlert { check, message } => {
+            (format!("email-weather alert: {:?}", check), message.clone())
+        }
+        WebhookEvent::WatchdogRecovered { check } => (
+            format!("email-weather recovered: {:?}", check),
+            format!("The {:?} check is passing again", check),
+        ),
+        _ => return None,
+    };
+    Some(Reply::Plain(reply::Plain {
+        subject: None,
+        reply_subject: Some(subject),
+        plain_message: message,
+        html_message: None,
+        to: to.clone(),
+        in_reply_to_message_id: None,
+        attachments: Vec::new(),
+        encrypted: None,
+    }))
+}
+
+/// Perform the checks every [`CHECK_INTERVAL`], notifying webhooks and emailing alerts using
+/// `reply_port`.
+pub(crate) async fn watchdog_impl(
+    watchdog: &mut Watchdog,
+    reply_port: &dyn reply::Port,
+    context: TaskContext,
+) -> eyre::Result<()> {
+    let TaskContext {
+        status,
+        webhooks,
+        time,
+        ..
+    } = context;
+    loop {
+        time.async_sleep(CHECK_INTERVAL).await;
+        for event in watchdog.check(status, time.utc_now()) {
+            match &event {
+                WebhookEvent::WatchdogAlert { message, .. } => {
+                    tracing::error!("Watchdog alert: {}", message);
+                }
+                _ => tracing::info!("Watchdog: {:?}", event),
+            }
+            if let Some(reply) = watchdog
+                .options
+                .alert_email
+                .as_ref()
+                .and_then(|to| alert_reply(&event, to))
+            {
+                // The email account may be the cause of the alert, so failing to send is not
+                // fatal, the webhooks are still notified.
+                if let Err(error) = reply_port.send_reply(RequestId::new(), &reply).await {
+                    tracing::error!("Error emailing watchdog alert: {:?}", error);
+                }
+            }
+            webhooks.notify(event);
+        }
+    }
+}
+
+/// This function spawns a task which alerts operators when the pipeline appears to have stalled,
+/// see [`Watchdog`].
+#[tracing::instrument(skip_all)]
+pub async fn watchdog<AUTH>(
+    shutdown_rx: tokio::sync::broadcast::Receiver<()>,
+    http_client: reqwest::Client,
+    email_account: &email::Account,
+    oauth_flow: Arc<AUTH>,
+    options: WatchdogOptions,
+    context: TaskContext,
+) where
+    AUTH: AuthenticationFlow + Send + Sync,
+{
+    tracing::debug!("Starting watchdog job");
+    let watchdog = Arc::new(Mutex::new(Watchdog::new(options)));
+    run_retry_log_errors(
+        move || {
+            let http_client = http_client.clone();
+            let oauth_flow = oauth_flow.clone();
+            let watchdog = watchdog.clone();
+            async move {
+                let mut watchdog = watchdog.lock().await;
+                let reply_port = reply::Gateway::new(http_client, email_account, &*oauth_flow);
+                watchdog_impl(&mut watchdog, &reply_port, context).await
+            }
+        },
+        shutdown_rx,
+        context.backoff,
+        context.time,
+    )
+    .await;
+}
+
+#[cfg(test)]
+mod test {
+    use chrono::Duration;
+
+    use crate::{status::ServiceStatus, webhook::WebhookEvent};
+
+    use crate::time::test_now as now;
+
+    use super::{Watchdog, WatchdogCheck, WatchdogOptions};
+
+    #[test]
+    fn test_inbox_poll() {
+        let status = ServiceStatus::new(now());
+        let mut watchdog = Watchdog::new(WatchdogOptions::default());
+        assert!(watchdog.check(&status, now()).is_empty());
+
+        let events = watchdog.check(&status, now() + Duration::minutes(31));
+        assert!(
+            matches!(
+                events.as_slice(),
+                [WebhookEvent::WatchdogAlert {
+                    check: WatchdogCheck::InboxPoll,
+                    ..
+                }]
+            ),
+            "{events:?}"
+        );
+        // The alert is only sent once.
+        assert!(watchdog
+            .check(&status, now() + Duration::minutes(32))
+            .is_empty());
+
+        status.record_inbox_poll(now() + Duration::minutes(33));
+        assert_eq!(
+            vec![WebhookEvent::WatchdogRecovered {
+                check: WatchdogCheck::InboxPoll
+            }],
+            watchdog.check(&status, now() + Duration::minutes(34))
+        );
+    }
+
+    #[test]
+    fn test_reply_queue() {
+        let status = ServiceStatus::new(now());
+        let mut watchdog = Watchdog::new(WatchdogOptions {
+            reply_queue_minutes: 10,
+            ..WatchdogOptions::default()
+        });
+        status.record_reply_queued(now());
+        status.record_inbox_poll(now() + Duration::minutes(11));
+        let events = watchdog.check(&status, now() + Duration::minutes(11));
+        assert!(
+            matches!(
+                events.as_slice(),
+                [WebhookEvent::WatchdogAlert {
+                    check: WatchdogCheck::ReplyQueue,
+                    ..
+                }]
+            ),
+            "{events:?}"
+        );
+
+        status.record_reply_sent(now() + Duration::minutes(12));
+        assert_eq!(
+            vec![WebhookEvent::WatchdogRecovered {
+                check: WatchdogCheck::ReplyQueue
+            }],
+            watchdog.check(&status, now() + Duration::minutes(12))
+        );
+    }
+}
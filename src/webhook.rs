@@ -0,0 +1,252 @@
+//! Outbound webhook notifications of significant events, for integration with chat services
+//! (e.g. Slack, Matrix or ntfy), see [`Webhooks`].
+
+use std::time::Duration;
+
+use chrono::{DateTime, Utc};
+use eyre::Context;
+use hmac::{Hmac, Mac};
+use schemars::JsonSchema;
+use secrecy::{ExposeSecret, SecretString};
+use serde::{Deserialize, Serialize};
+use sha2::Sha256;
+
+use crate::{correlation::RequestId, time, watchdog::WatchdogCheck};
+
+/// Header containing the hex encoded HMAC-SHA256 signature of the request body, prefixed with
+/// `sha256=`.
+pub const SIGNATURE_HEADER: &str = "X-Email-Weather-Signature";
+
+/// Maximum time to wait for a webhook request to complete.
+const REQUEST_TIMEOUT: Duration = Duration::from_secs(10);
+
+/// Options for a webhook which is sent a `POST` request with a JSON body (see
+/// [`WebhookPayload`]) when an event occurs.
+#[derive(Debug, Clone, Serialize, Deserialize, JsonSchema)]
+pub struct WebhookOptions {
+    /// Url that the request is sent to.
+    pub url: url::Url,
+    /// If specified, the request body is signed using HMAC-SHA256 with this secret, and the
+    /// signature is provided in the `X-Email-Weather-Signature` header. This is never included
+    /// when the options are serialized (e.g. in logs).
+    ///
+    /// Default is `None`.
+    #[serde(default, skip_serializing)]
+    #[schemars(with = "Option<String>")]
+    pub secret: Option<SecretString>,
+    /// The events that this webhook is notified about.
+    ///
+    /// Default is `None` (all events).
+    #[serde(default)]
+    pub events: Option<Vec<WebhookEventKind>>,
+}
+
+/// The kind of a [`WebhookEvent`], used to select which events a webhook is notified about.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize, JsonSchema)]
+#[serde(rename_all = "snake_case")]
+pub enum WebhookEventKind {
+    /// See [`WebhookEvent::ForecastProcessed`].
+    ForecastProcessed,
+    /// See [`WebhookEvent::ReplyFailed`].
+    ReplyFailed,
+    /// See [`WebhookEvent::ReauthRequired`].
+    ReauthRequired,
+    /// See [`WebhookEvent::WatchdogAlert`].
+    WatchdogAlert,
+    /// See [`WebhookEvent::WatchdogRecovered`].
+    WatchdogRecovered,
+}
+
+/// An event which webhooks are notified about.
+#[derive(Debug, Clone, PartialEq, Serialize)]
+#[serde(tag = "event", rename_all = "snake_case")]
+pub enum WebhookEvent {
+    /// A forecast request was processed, and the reply has been queued for sending.
+    ForecastProcessed {
+        /// Id of the request that was processed.
+        request_id: RequestId,
+    },
+    /// A reply was discarded after repeatedly failing to send.
+    ReplyFailed {
+        /// Id of the request that the reply was for.
+        request_id: RequestId,
+    },
+    /// The email account needs to be authorized again, by visiting `authorization_url`.
+    ReauthRequired {
+        /// Url to visit to authorize access to the email account.
+        authorization_url: String,
+    },
+    /// A watchdog check failed, the pipeline may have stalled, see [`crate::watchdog`].
+    WatchdogAlert {
+        /// The check which failed.
+        check: WatchdogCheck,
+        /// Description of the problem.
+        message: String,
+    },
+    /// A watchdog check which previously failed is passing again.
+    WatchdogRecovered {
+        /// The check which recovered.
+        check: WatchdogCheck,
+    },
+}
+
+impl WebhookEvent {
+    /// The kind of this event.
+    #[must_use]
+    pub fn kind(&self) -> WebhookEventKind {
+        match self {
+            WebhookEvent::ForecastProcessed { .. } => WebhookEventKind::ForecastProcessed,
+            WebhookEvent::ReplyFailed { .. } => WebhookEventKind::ReplyFailed,
+            WebhookEvent::ReauthRequired { .. } => WebhookEventKind::ReauthRequired,
+            WebhookEvent::WatchdogAlert { .. } => WebhookEventKind::WatchdogAlert,
+            WebhookEvent::WatchdogRecovered { .. } => WebhookEventKind::WatchdogRecovered,
+        }
+    }
+}
+
+/// The JSON body sent to webhooks.
+#[derive(Debug, Serialize)]
+pub struct WebhookPayload<'a> {
+    /// When the event occurred.
+    pub timestamp: DateTime<Utc>,
+    /// The event that occurred.
+    #[serde(flatten)]
+    pub event: &'a WebhookEvent,
+}
+
+/// Compute the value of the [`SIGNATURE_HEADER`] for `body`.
+fn signature(secret: &SecretString, body: &[u8]) -> String {
+    let mut mac = Hmac::<Sha256>::new_from_slice(secret.expose_secret().as_bytes())
+        .expect("HMAC accepts keys of any size");
+    mac.update(body);
+    let hex: String = mac
+        .finalize()
+        .into_bytes()
+        .iter()
+        .map(|byte| format!("{:02x}", byte))
+        .collect();
+    format!("sha256={}", hex)
+}
+
+async fn send(
+    http_client: &reqwest::Client,
+    webhook: &WebhookOptions,
+    body: Vec<u8>,
+) -> eyre::Result<()> {
+    let mut request = http_client
+        .post(webhook.url.clone())
+        .timeout(REQUEST_TIMEOUT)
+        .header(reqwest::header::CONTENT_TYPE, "application/json");
+    if let Some(secret) = &webhook.secret {
+        request = request.header(SIGNATURE_HEADER, signature(secret, &body));
+    }
+    request
+        .body(body)
+        .send()
+        .await
+        .wrap_err("Error sending request")?
+        .error_for_status()
+        .wrap_err("Error response")?;
+    Ok(())
+}
+
+/// Sends notifications of events to the configured webhooks.
+pub struct Webhooks {
+    webhooks: Vec<WebhookOptions>,
+    http_client: reqwest::Client,
+    time: &'static dyn time::Port,
+}
+
+impl Webhooks {
+    /// Construct a new [`Webhooks`].
+    #[must_use]
+    pub fn new(
+        webhooks: Vec<WebhookOptions>,
+        http_client: reqwest::Client,
+        time: &'static dyn time::Port,
+    ) -> Self {
+        Self {
+            webhooks,
+            http_client,
+            time,
+        }
+    }
+
+    /// Notify the webhooks which are interested in `event`. Requests are sent in the background,
+    /// and failures are logged, so that a misbehaving webhook cannot hold up processing.
+    pub fn notify(&self, event: WebhookEvent) {
+        let kind = event.kind();
+        let webhooks: Vec<WebhookOptions> = self
+            .webhooks
+            .iter()
+            .filter(|webhook| {
+                webhook
+                    .events
+                    .as_ref()
+                    .map_or(true, |events| events.contains(&kind))
+            })
+            .cloned()
+            .collect();
+        if webhooks.is_empty() {
+            return;
+        }
+
+        let payload = WebhookPayload {
+            timestamp: self.time.utc_now(),
+            event: &event,
+        };
+        let body = match serde_json::to_vec(&payload) {
+            Ok(body) => body,
+            Err(error) => {
+                tracing::error!("Error serializing webhook payload: {:?}", error);
+                return;
+            }
+        };
+
+        for webhook in webhooks {
+            let http_client = self.http_client.clone();
+            let body = body.clone();
+            tokio::spawn(async move {
+                if let Err(error) = send(&http_client, &webhook, body).await {
+                    tracing::error!(
+                        "Error notifying webhook {} of {:?}: {:?}",
+                        webhook.url,
+                        kind,
+                        error
+                    );
+                }
+            });
+        }
+    }
+}
+
+#[cfg(test)]
+mod test {
+    use secrecy::SecretString;
+
+    use super::{signature, WebhookEvent, WebhookPayload};
+
+    #[test]
+    fn test_signature() {
+        let secret = SecretString::new("key".to_string());
+        assert_eq!(
+            "sha256=f7bc83f430538424b13298e6aa6fb143ef4d59a14946175997479dbc2d1a3cd8",
+            signature(&secret, b"The quick brown fox jumps over the lazy dog")
+        );
+    }
+
+    #[test]
+    fn test_payload() {
+        let event = WebhookEvent::ReauthRequired {
+            authorization_url: "https://example.org".to_string(),
+        };
+        let payload = WebhookPayload {
+            timestamp: "2022-12-03T08:00:00Z".parse().unwrap(),
+            event: &event,
+        };
+        assert_eq!(
+            r#"{"timestamp":"2022-12-03T08:00:00Z","event":"reauth_required","authorization_url":"https://example.org"}"#,
+            serde_json::to_string(&payload).unwrap()
+        );
+    }
+}